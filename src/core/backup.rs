@@ -0,0 +1,219 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{Instrument, Span};
+
+use crate::{ActorMessage, ActorReceiver, ActorRef, Rto};
+
+use super::{db::ProjectStore, settings::Settings};
+
+/// Requests for BackupActor
+pub enum BackupRequest {
+    /// Write a backup now, returning its path
+    Backup(Rto<PathBuf>),
+    /// Get the time of the last successful backup
+    GetLastBackup(Rto<Option<OffsetDateTime>>),
+}
+
+pub type BackupActor = ActorRef<BackupRequest>;
+
+impl ActorMessage for BackupRequest {
+    fn label(&self) -> &'static str {
+        match self {
+            BackupRequest::Backup(..) => "Backup",
+            BackupRequest::GetLastBackup(..) => "GetLastBackup",
+        }
+    }
+}
+
+pub const DEFAULT_BACKUP_KEEP: usize = 10;
+
+/// A finished backup, with the request waiting for it if it was not periodic
+type BackupDone = (anyhow::Result<PathBuf>, Option<Rto<PathBuf>>);
+
+pub async fn run_backup_actor(
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    project_folder: PathBuf,
+    mut rx: ActorReceiver<BackupRequest>,
+) -> anyhow::Result<()> {
+    let backup_dir = match &settings.backup_dir {
+        Some(dir) => project_folder.join(dir),
+        None => project_folder.join("backups"),
+    };
+    let keep = settings.backup_keep.unwrap_or(DEFAULT_BACKUP_KEEP);
+
+    let mut last_backup = None;
+    let mut last_stamp = 0;
+    let mut interval = settings.backup_interval_minutes.map(|minutes| {
+        let period = Duration::from_secs(minutes.max(1) * 60);
+        tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+    });
+
+    match &interval {
+        Some(_) => log::info!(
+            "Backing up project every {} minutes to {}",
+            settings.backup_interval_minutes.unwrap(),
+            backup_dir.to_str().unwrap()
+        ),
+        None => log::info!("Periodic backups are disabled"),
+    }
+
+    // Backups are written in their own tasks, so the actor keeps answering while they run
+    let (done_tx, mut done_rx) = unbounded_channel::<BackupDone>();
+    let start_backup = |stamp: i64, rto: Option<Rto<PathBuf>>, span: Span| {
+        let db = db.clone();
+        let backup_dir = backup_dir.clone();
+        let done_tx = done_tx.clone();
+        tokio::spawn(
+            async move {
+                let res = write_backup(&*db, &backup_dir, stamp, keep).await;
+                let _ = done_tx.send((res, rto));
+            }
+            .instrument(span),
+        );
+    };
+
+    loop {
+        tokio::select! {
+            _ = async { interval.as_mut().unwrap().tick().await }, if interval.is_some() => {
+                last_stamp = next_stamp(last_stamp);
+                start_backup(last_stamp, None, Span::current());
+            }
+            Some((res, rto)) = done_rx.recv() => {
+                match &res {
+                    Ok(path) => {
+                        log::info!("Wrote backup {}", path.to_str().unwrap());
+                        last_backup = Some(OffsetDateTime::now_utc());
+                    }
+                    Err(e) => log::error!("Failed to write backup: {}", e),
+                }
+                if let Some(rto) = rto {
+                    rto.reply(res);
+                }
+            }
+            msg = rx.recv() => match msg {
+                Some((BackupRequest::Backup(rto), span)) => {
+                    last_stamp = next_stamp(last_stamp);
+                    start_backup(last_stamp, Some(rto), span);
+                }
+                Some((BackupRequest::GetLastBackup(rto), _)) => rto.reply(Ok(last_backup)),
+                None => break,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The Unix millisecond timestamp naming the next backup,
+/// kept after `last` so backups started in the same millisecond do not overwrite each other
+fn next_stamp(last: i64) -> i64 {
+    let now = (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64;
+    now.max(last + 1)
+}
+
+/// Write a copy of the project database named after `stamp` and prune old copies
+async fn write_backup(
+    db: &dyn ProjectStore,
+    backup_dir: &Path,
+    stamp: i64,
+    keep: usize,
+) -> anyhow::Result<PathBuf> {
+    let dir = backup_dir.to_owned();
+    tokio::task::spawn_blocking(move || std::fs::create_dir_all(dir)).await??;
+
+    let path = backup_dir.join(format!("project-{}.db", stamp));
+    db.backup_to(&path).await?;
+
+    let dir = backup_dir.to_owned();
+    tokio::task::spawn_blocking(move || prune_backups(&dir, keep)).await??;
+
+    Ok(path)
+}
+
+/// Delete the oldest backups in `backup_dir` beyond the newest `keep`.
+///
+/// Backups named in Unix seconds by older versions sort before those named in milliseconds,
+/// so they are the first to go.
+fn prune_backups(backup_dir: &Path, keep: usize) -> anyhow::Result<()> {
+    let mut backups: Vec<(i64, PathBuf)> = std::fs::read_dir(backup_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_owned();
+            let timestamp = name
+                .strip_prefix("project-")?
+                .strip_suffix(".db")?
+                .parse()
+                .ok()?;
+            Some((timestamp, entry.path()))
+        })
+        .collect();
+
+    backups.sort_by_key(|(timestamp, _)| std::cmp::Reverse(*timestamp));
+    for (_, path) in backups.into_iter().skip(keep) {
+        log::debug!("Removing old backup {}", path.to_str().unwrap());
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::db::ProjectDb, send_message};
+
+    #[test]
+    fn stamps_are_unique() {
+        let first = next_stamp(0);
+        let second = next_stamp(first);
+        assert!(second > first);
+        assert_eq!(next_stamp(i64::MAX - 1), i64::MAX);
+    }
+
+    #[tokio::test]
+    async fn backups_started_together_are_kept_apart() {
+        let project_folder = std::env::temp_dir().join(format!(
+            "automarathon-backup-test-{}-{}",
+            std::process::id(),
+            next_stamp(0)
+        ));
+        // An in-memory database would be vacuumed into memory as well
+        std::fs::create_dir_all(&project_folder).unwrap();
+        let db: Arc<dyn ProjectStore> = Arc::new(
+            ProjectDb::load(&project_folder.join("project.db"), Box::new(|| {}))
+                .await
+                .unwrap(),
+        );
+        let settings = Arc::new(Settings::template());
+        let (backup_actor, rx) = BackupActor::new("backup");
+        tokio::spawn(run_backup_actor(db, settings, project_folder.clone(), rx));
+
+        let pending: Vec<_> = (0..3)
+            .map(|_| {
+                let (tx, rx) = Rto::new();
+                backup_actor.send(BackupRequest::Backup(tx));
+                rx
+            })
+            .collect();
+        let mut paths = vec![];
+        for rx in pending {
+            paths.push(rx.await.unwrap().unwrap());
+        }
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), 3);
+        assert!(paths.iter().all(|p| p.exists()));
+
+        let last = send_message!(backup_actor, BackupRequest, GetLastBackup).unwrap();
+        assert!(last.is_some());
+
+        std::fs::remove_dir_all(project_folder).unwrap();
+    }
+}
@@ -1,30 +1,307 @@
 use anyhow::anyhow;
-use std::{collections::HashMap, path::Path};
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Mutex,
+    },
+};
 
+use serde::{Deserialize, Serialize};
 use sqlx::{
-    migrate::MigrateDatabase, query, sqlite::Sqlite, types::time, QueryBuilder, SqlitePool,
+    migrate::MigrateDatabase, query, sqlite::Sqlite, types::time, QueryBuilder, SqliteConnection,
+    SqlitePool,
 };
 
 use crate::{
-    core::{event::Event, runner::Runner, stream::StreamState},
-    integrations::{therun::Run, web::WebCommand},
-    Directory,
+    core::{
+        db_cache::{CacheStats, QueryCache},
+        event::{Event, EventCompletion, Incident, Marker},
+        project::{
+            ImportMode, ImportReport, ProjectExport, TournamentExport, PROJECT_FORMAT_VERSION,
+        },
+        runner::{Runner, RunnerDependencies, RunnerSelfToken},
+        stream::{OnDeckRunner, StreamPreset, StreamState},
+        theme::Theme,
+    },
+    error::Error,
+    integrations::{obs::ObsScene, therun::Run},
 };
 
 use super::event::RunnerEventState;
 
+/// Schema changes applied to existing project databases, in order.
+///
+/// The number of applied migrations is tracked in sqlite's `user_version`
+/// pragma. New databases are created with the latest schema by `create_tables`,
+/// so any change here must also be reflected there.
+const MIGRATIONS: &[&[&str]] = &[
+    &["alter table runners add column max_stream_height integer"],
+    &["alter table runners add column archived boolean not null default false"],
+    &["alter table events add column auto_relay_handoff boolean not null default false"],
+    &["alter table runners add column monitor_type text"],
+    &[
+        "alter table events add column auto_go_live boolean not null default false",
+        "alter table events add column scheduled_host text",
+    ],
+    &[
+        "create index events_timer_end_time on events(timer_end_time)",
+        "create index events_event_start_time on events(event_start_time)",
+        "create index events_tournament on events(tournament)",
+    ],
+    &["create table stream_presets(
+            name text primary key not null collate nocase,
+            preset json not null
+        )"],
+    &[
+        "alter table streams add column active boolean not null default true",
+        "create unique index streams_active_host on streams(obs_host) where active",
+    ],
+    &["alter table streams add column rotation json"],
+    &[
+        "alter table runners add column discord_id text",
+        "create unique index runners_discord_id on runners(discord_id) where discord_id is not null",
+    ],
+    &["alter table events add column scene_collection text"],
+    &["alter table streams add column commentator_order text not null default ''"],
+    &["alter table runners_in_stream add column sync_offset_ms integer not null default 0"],
+    &["alter table streams add column manual_scene_override boolean not null default false"],
+    &[
+        "create table incidents(
+            id integer primary key not null,
+            event integer not null,
+            time integer not null,
+            timer_elapsed integer,
+            author text,
+            severity text not null default 'info',
+            text text not null,
+            foreign key(event) references events(id) on delete cascade
+        )",
+        "create index incidents_event on incidents(event)",
+    ],
+    &[
+        "alter table runners add column stream_url_fetched_at integer",
+        "alter table runners add column stream_url_expires_at integer",
+    ],
+    &["create table runner_self_tokens(
+            token text primary key not null,
+            runner integer not null,
+            expires_at integer not null,
+            used_at integer,
+            foreign key(runner) references runners(id) on delete cascade
+        )"],
+    &["alter table events add column commentary_host text"],
+    &[
+        "alter table events add column version integer not null default 0",
+        "alter table streams add column version integer not null default 0",
+    ],
+    &[
+        "alter table runners add column discord_reminder_opt_out boolean not null default false",
+        "create table discord_reminders(
+            event integer not null,
+            runner integer not null,
+            minutes integer not null,
+            start_time integer not null,
+            sent_at integer not null,
+            delivered boolean not null,
+            primary key(event, runner, minutes, start_time),
+            foreign key(event) references events(id) on delete cascade,
+            foreign key(runner) references runners(id) on delete cascade
+        )",
+    ],
+    &["alter table runners add column stream_kind text not null default 'twitch'"],
+    &["alter table events add column locale text"],
+    &[
+        "delete from runners_in_event where rowid not in
+            (select min(rowid) from runners_in_event group by event, runner)",
+        "alter table runners_in_event add column ordering integer not null default 0",
+        // Runners were listed in the order they were added
+        "update runners_in_event set ordering = (select count(*) from runners_in_event r
+            where r.event = runners_in_event.event and r.rowid < runners_in_event.rowid)",
+        "create unique index runners_in_event_runner on runners_in_event(event, runner)",
+    ],
+    &["create table layout_snapshots(
+            obs_host text not null,
+            scene text not null,
+            position integer not null,
+            layout json not null,
+            captured_at integer not null,
+            primary key(obs_host, scene)
+        )"],
+    &["create table runner_socials(
+            runner integer not null,
+            platform text not null collate nocase,
+            handle text not null,
+            primary key(runner, platform),
+            foreign key(runner) references runners(id) on delete cascade
+        )"],
+    &[
+        "create table themes(
+            name text primary key not null collate nocase,
+            tokens json not null,
+            updated_at integer not null
+        )",
+        "alter table events add column theme text",
+    ],
+    &["create table event_completions(
+            event integer primary key not null,
+            completed_at integer not null,
+            stopped_timer boolean not null,
+            stream json,
+            results json not null,
+            foreign key(event) references events(id) on delete cascade
+        )"],
+    &[
+        "create table ignored_discord_users(
+            discord_id text primary key not null,
+            name text not null
+        )",
+        "alter table streams add column ignored_voice_members text not null default ''",
+    ],
+    &[
+        "create table markers(
+            id integer primary key not null,
+            event integer not null,
+            label text not null,
+            time integer not null,
+            timer_elapsed integer,
+            author text,
+            foreign key(event) references events(id) on delete cascade
+        )",
+        "create index markers_event on markers(event)",
+    ],
+    &["create table meta(
+            key text primary key not null,
+            value integer not null
+        )"],
+    &["alter table streams add column on_deck_runners json not null default '[]'"],
+    &["alter table events add column relay_handoff_split text"],
+];
+
+/// Statements creating the indices of a new database
+const INDICES: &[&str] = &[
+    "create index events_timer_end_time on events(timer_end_time)",
+    "create index events_event_start_time on events(event_start_time)",
+    "create index events_tournament on events(tournament)",
+    "create unique index streams_active_host on streams(obs_host) where active",
+    "create unique index runners_discord_id on runners(discord_id) where discord_id is not null",
+    "create index incidents_event on incidents(event)",
+    "create index markers_event on markers(event)",
+    "create unique index runners_in_event_runner on runners_in_event(event, runner)",
+];
+
+/// Filters for listing events, all of which are optional
+#[derive(Deserialize, Default, Debug)]
+pub struct EventFilter {
+    /// Whether the event timer has been stopped
+    pub complete: Option<bool>,
+    pub tournament: Option<i64>,
+    /// Case-insensitive part of the event name, game or category
+    pub search: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// One page of a filtered listing
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of matches across all pages
+    pub total: i64,
+}
+
+/// Pattern matching values that contain `search`, for use with `escape '\'`
+fn like_pattern(search: &str) -> String {
+    format!(
+        "%{}%",
+        search
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    )
+}
+
+/// Save the social handles of a runner, whose old handles must already be deleted
+async fn insert_runner_socials(
+    conn: &mut SqliteConnection,
+    id: i64,
+    runner: &Runner,
+) -> anyhow::Result<()> {
+    let socials = runner.normalized_socials();
+    if !socials.is_empty() {
+        let mut builder =
+            QueryBuilder::new("insert or replace into runner_socials(runner, platform, handle)");
+        builder.push_values(socials.iter(), |mut b, (platform, handle)| {
+            b.push_bind(id).push_bind(platform).push_bind(handle);
+        });
+        builder.build().execute(conn).await?;
+    }
+    Ok(())
+}
+
+/// Called whenever the project changes, so clients can be sent the new state
+pub type UpdateCallback = Box<dyn Fn() + Send + Sync>;
+
+/// A row of the themes table: name, tokens and update time
+type ThemeRow = (String, sqlx::types::Json<BTreeMap<String, String>>, i64);
+
 pub struct ProjectDb {
     db: SqlitePool,
-    directory: Directory,
+    on_update: UpdateCallback,
+    /// Runners whose run was reset, with the time of the reset, until they send new run data
+    run_resets: Mutex<HashMap<i64, time::OffsetDateTime>>,
+    runners_cache: QueryCache<(), Vec<Runner>>,
+    events_cache: QueryCache<i64, Event>,
+    streams_cache: QueryCache<i64, StreamState>,
+    event_names_cache: QueryCache<(), Vec<String>>,
+    /// Number of changes made to the project, kept across restarts in the `meta` table
+    revision: AtomicI64,
+    /// Time of the last change in Unix seconds
+    revised_at: AtomicI64,
 }
 
 impl ProjectDb {
-    pub async fn load(file: &Path, directory: Directory) -> anyhow::Result<Self> {
+    /// Open or create the project database.
+    ///
+    /// `on_update` is called after every change, rather than the database
+    /// depending on the actors, so it can be opened before they run.
+    pub async fn load(file: &Path, on_update: UpdateCallback) -> anyhow::Result<Self> {
         let url = format!("sqlite://{}", file.to_str().unwrap());
         Sqlite::create_database(&url).await?;
 
         let db = SqlitePool::connect(&url).await?;
-        let proj = Self { db, directory };
+        Self::open(db, on_update).await
+    }
+
+    /// Project held in memory only, for tests.
+    ///
+    /// The pool keeps a single connection, as every connection to `:memory:`
+    /// would otherwise see its own empty database.
+    #[cfg(test)]
+    pub async fn in_memory(on_update: UpdateCallback) -> anyhow::Result<Self> {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?;
+        Self::open(db, on_update).await
+    }
+
+    async fn open(db: SqlitePool, on_update: UpdateCallback) -> anyhow::Result<Self> {
+        let proj = Self {
+            db,
+            on_update,
+            run_resets: Mutex::default(),
+            runners_cache: QueryCache::default(),
+            events_cache: QueryCache::default(),
+            streams_cache: QueryCache::default(),
+            event_names_cache: QueryCache::default(),
+            revision: AtomicI64::new(0),
+            revised_at: AtomicI64::new(time::OffsetDateTime::now_utc().unix_timestamp()),
+        };
 
         let table_exists =
             query!("SELECT name FROM sqlite_master WHERE type='table' AND name='runners'")
@@ -35,11 +312,55 @@ impl ProjectDb {
         if !table_exists {
             log::info!("Tables are not present, initalizing database...");
             proj.create_tables().await?;
+            proj.set_schema_version(MIGRATIONS.len()).await?;
+        } else {
+            proj.migrate().await?;
         }
+        proj.load_revision().await?;
 
         Ok(proj)
     }
 
+    async fn load_revision(&self) -> anyhow::Result<()> {
+        let rows: Vec<(String, i64)> = sqlx::query_as("select key, value from meta")
+            .fetch_all(&self.db)
+            .await?;
+        for (key, value) in rows {
+            match key.as_str() {
+                "revision" => self.revision.store(value, Ordering::SeqCst),
+                "revised_at" => self.revised_at.store(value, Ordering::SeqCst),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_schema_version(&self, version: usize) -> anyhow::Result<()> {
+        sqlx::query(&format!("pragma user_version = {}", version))
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Apply any migrations that have not yet been run on this database
+    async fn migrate(&self) -> anyhow::Result<()> {
+        let version: i64 = sqlx::query_scalar("pragma user_version")
+            .fetch_one(&self.db)
+            .await?;
+
+        for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            log::info!("Migrating database to version {}", idx + 1);
+            let mut tx = self.db.begin().await?;
+            for statement in migration.iter() {
+                sqlx::query(statement).execute(&mut *tx).await?;
+            }
+            tx.commit().await?;
+            self.set_schema_version(idx + 1).await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn create_tables(&self) -> anyhow::Result<()> {
         query!(
             "create table runners(
@@ -48,9 +369,17 @@ impl ProjectDb {
                         stream text, 
                         therun text,
                         cached_stream_url text,
+                        stream_url_fetched_at integer,
+                        stream_url_expires_at integer,
                         location text,
                         photo blob,
-                        volume_percent integer not null
+                        volume_percent integer not null,
+                        max_stream_height integer,
+                        archived boolean not null default false,
+                        monitor_type text,
+                        discord_id text,
+                        discord_reminder_opt_out boolean not null default false,
+                        stream_kind text not null default 'twitch'
                     );"
         )
         .execute(&self.db)
@@ -120,6 +449,15 @@ impl ProjectDb {
                     preferred_layouts json not null,
                     is_relay boolean not null, 
                     is_marathon boolean not null,
+                    auto_relay_handoff boolean not null default false,
+                    relay_handoff_split text,
+                    auto_go_live boolean not null default false,
+                    scheduled_host text,
+                    scene_collection text,
+                    commentary_host text,
+                    version integer not null default 0,
+                    locale text,
+                    theme text,
                     foreign key(tournament) references tournaments(id) on delete set null
                 );"
         )
@@ -131,6 +469,7 @@ impl ProjectDb {
                     event integer not null,
                     runner integer not null, 
                     result json,
+                    ordering integer not null default 0,
                     foreign key(event) references events(id) on delete cascade,
                     foreign key(runner) references runners(id) on delete cascade
                 );"
@@ -146,6 +485,13 @@ impl ProjectDb {
                     ignored_commentators text not null,
                     requested_layout text,
                     audible_runner text,
+                    active boolean not null default true,
+                    rotation json,
+                    commentator_order text not null default '',
+                    manual_scene_override boolean not null default false,
+                    version integer not null default 0,
+                    ignored_voice_members text not null default '',
+                    on_deck_runners json not null default '[]',
                     foreign key(event) references events(id) on delete cascade
                 );"
         )
@@ -157,6 +503,7 @@ impl ProjectDb {
                     event integer not null,
                     runner integer not null,
                     stream_order integer not null,
+                    sync_offset_ms integer not null default 0,
                     foreign key(event) references streams(event) on delete cascade,
                     foreign key(runner) references runners(id) on delete cascade
                 );"
@@ -164,186 +511,1347 @@ impl ProjectDb {
         .execute(&self.db)
         .await?;
 
-        Ok(())
-    }
+        sqlx::query(
+            "create table stream_presets(
+                    name text primary key not null collate nocase,
+                    preset json not null
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-    fn trigger_update(&self) {
-        self.directory.web_actor.send(WebCommand::SendStateUpdate);
-    }
+        sqlx::query(
+            "create table themes(
+                    name text primary key not null collate nocase,
+                    tokens json not null,
+                    updated_at integer not null
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-    pub async fn get_runners(&self) -> anyhow::Result<Vec<Runner>> {
-        Ok(sqlx::query_as("select * from runners")
-            .fetch_all(&self.db)
-            .await?)
-    }
+        sqlx::query(
+            "create table markers(
+                    id integer primary key not null,
+                    event integer not null,
+                    label text not null,
+                    time integer not null,
+                    timer_elapsed integer,
+                    author text,
+                    foreign key(event) references events(id) on delete cascade
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-    pub async fn add_runner(&self, runner: &mut Runner) -> anyhow::Result<()> {
-        log::debug!("Creating new runner {}", runner.name);
-        let mut tx = self.db.begin().await?;
-        sqlx::query("insert into runners(name, stream, therun, location, volume_percent) values(?, ?, ?, ?, ?)")
-            .bind(&runner.name)
-            .bind(&runner.stream)
-            .bind(&runner.therun)
-            .bind(&runner.location)
-            .bind(runner.volume_percent)
-            .execute(&mut *tx)
-            .await?;
+        sqlx::query(
+            "create table ignored_discord_users(
+                    discord_id text primary key not null,
+                    name text not null
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-        let last_runner_id: i64 = sqlx::query_scalar("select last_insert_rowid()")
-            .fetch_one(&mut *tx)
-            .await?;
-        runner.id = last_runner_id;
+        sqlx::query(
+            "create table event_completions(
+                    event integer primary key not null,
+                    completed_at integer not null,
+                    stopped_timer boolean not null,
+                    stream json,
+                    results json not null,
+                    foreign key(event) references events(id) on delete cascade
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-        if !runner.nicks.is_empty() {
-            let mut builder = sqlx::QueryBuilder::new("insert into nicknames(nickname, runner)");
-            builder.push_values(runner.nicks.iter(), |mut b, nick| {
-                b.push_bind(nick).push_bind(runner.id);
-            });
-            builder.build().execute(&mut *tx).await?;
-        }
-        tx.commit().await?;
-        self.trigger_update();
-        Ok(())
-    }
+        sqlx::query(
+            "create table incidents(
+                    id integer primary key not null,
+                    event integer not null,
+                    time integer not null,
+                    timer_elapsed integer,
+                    author text,
+                    severity text not null default 'info',
+                    text text not null,
+                    foreign key(event) references events(id) on delete cascade
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-    pub async fn update_runner(&self, runner: &Runner) -> anyhow::Result<()> {
-        log::debug!("Updating runner {}", runner.name);
+        sqlx::query(
+            "create table runner_self_tokens(
+                    token text primary key not null,
+                    runner integer not null,
+                    expires_at integer not null,
+                    used_at integer,
+                    foreign key(runner) references runners(id) on delete cascade
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-        let mut tx = self.db.begin().await?;
+        sqlx::query(
+            "create table discord_reminders(
+                    event integer not null,
+                    runner integer not null,
+                    minutes integer not null,
+                    start_time integer not null,
+                    sent_at integer not null,
+                    delivered boolean not null,
+                    primary key(event, runner, minutes, start_time),
+                    foreign key(event) references events(id) on delete cascade,
+                    foreign key(runner) references runners(id) on delete cascade
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
         sqlx::query(
-            "update runners set
-                    name = ?,
-                    stream = ?,
-                    therun = ?,
-                    cached_stream_url = ?,
-                    location = ?,
-                    volume_percent = ?
-                    where id = ?",
+            "create table layout_snapshots(
+                    obs_host text not null,
+                    scene text not null,
+                    position integer not null,
+                    layout json not null,
+                    captured_at integer not null,
+                    primary key(obs_host, scene)
+                );",
         )
-        .bind(&runner.name)
-        .bind(&runner.stream)
-        .bind(&runner.therun)
-        .bind(&runner.cached_stream_url)
-        .bind(&runner.location)
-        .bind(runner.volume_percent)
-        .bind(runner.id)
-        .execute(&mut *tx)
+        .execute(&self.db)
         .await?;
 
-        sqlx::query("delete from nicknames where runner = ?")
-            .bind(runner.id)
-            .execute(&mut *tx)
-            .await?;
+        sqlx::query(
+            "create table runner_socials(
+                    runner integer not null,
+                    platform text not null collate nocase,
+                    handle text not null,
+                    primary key(runner, platform),
+                    foreign key(runner) references runners(id) on delete cascade
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-        if !runner.nicks.is_empty() {
-            let mut builder = sqlx::QueryBuilder::new("insert into nicknames(nickname, runner)");
-            builder.push_values(runner.nicks.iter(), |mut b, nick| {
-                b.push_bind(nick).push_bind(runner.id);
-            });
-            builder.build().execute(&mut *tx).await?;
-        }
+        sqlx::query(
+            "create table meta(
+                    key text primary key not null,
+                    value integer not null
+                );",
+        )
+        .execute(&self.db)
+        .await?;
 
-        tx.commit().await?;
-        self.trigger_update();
+        for statement in INDICES {
+            sqlx::query(statement).execute(&self.db).await?;
+        }
 
         Ok(())
     }
 
-    pub async fn get_runner(&self, id: i64) -> anyhow::Result<Runner> {
-        let mut runner: Runner = sqlx::query_as(
-            "select * from runners 
-                        where id = ?
-                        limit 1",
-            /*"select * from runners r
-            left join nicknames n on r.name = n.runner
-            where r.name = '?' or n.nickname = '?'
-            limit 1",*/
-        )
-        .bind(id)
-        .fetch_one(&self.db)
-        .await?;
-
-        runner.nicks = sqlx::query_scalar("select nickname from nicknames where runner = ?")
-            .bind(runner.id)
-            .fetch_all(&self.db)
-            .await?;
+    fn trigger_update(&self) {
+        let revision = self.revision.fetch_add(1, Ordering::SeqCst) + 1;
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        self.revised_at.store(now, Ordering::SeqCst);
+
+        // Saved in the background as callers are not async, saves finishing
+        // out of order never move the stored revision backwards
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            let result = sqlx::query(
+                "insert into meta(key, value) values('revision', ?), ('revised_at', ?)
+                    on conflict(key) do update set value = max(value, excluded.value)",
+            )
+            .bind(revision)
+            .bind(now)
+            .execute(&db)
+            .await;
+            if let Err(e) = result {
+                log::warn!("Failed to save project revision {}: {}", revision, e);
+            }
+        });
 
-        Ok(runner)
+        (self.on_update)();
     }
 
-    pub async fn get_name_for_runner(&self, id: i64) -> anyhow::Result<String> {
-        Ok(sqlx::query_scalar("select name from runners where id = ?")
-            .bind(id)
-            .fetch_one(&self.db)
-            .await?)
+    /// Fill in the social media handles of runners read from the runners table
+    async fn load_runner_socials(&self, runners: &mut [Runner]) -> anyhow::Result<()> {
+        let socials: Vec<(i64, String, String)> =
+            sqlx::query_as("select runner, platform, handle from runner_socials")
+                .fetch_all(&self.db)
+                .await?;
+        for (runner, platform, handle) in socials {
+            if let Some(runner) = runners.iter_mut().find(|r| r.id == runner) {
+                runner.socials.insert(platform, handle);
+            }
+        }
+        Ok(())
     }
 
-    pub async fn find_runner(&self, name: &str) -> anyhow::Result<Runner> {
-        log::debug!("Searching for runner {}", name);
-        let mut runner: Runner = 
-        /*sqlx::query_as(
-            "select * from runners r
-            left join nicknames n on r.name = n.runner
-            where r.name = '?' or n.nickname = '?'
-            limit 1",
-        )*/
-        sqlx::query_as("select * from runners where name = ?")
-        .bind(name)
-        .fetch_optional(&self.db)
-        .await?
-        .ok_or(anyhow!("Failed to find runner {}", name))?;
-
-        runner.nicks = sqlx::query_scalar("select nickname from nicknames where runner = ?")
-            .bind(runner.id)
-            .fetch_all(&self.db)
-            .await?;
+    /// Write the runners of an event, removing any that are no longer in it.
+    ///
+    /// Runners follow `runner_order` if it is given, then their stored order,
+    /// then runners new to the event by ID.
+    async fn write_event_runners(
+        &self,
+        tx: &mut SqliteConnection,
+        event: &Event,
+    ) -> anyhow::Result<()> {
+        let stored: Vec<i64> = sqlx::query_scalar(
+            "select runner from runners_in_event where event = ? order by ordering, rowid",
+        )
+        .bind(event.id)
+        .fetch_all(&mut *tx)
+        .await?;
 
-        Ok(runner)
-    }
+        let mut order: Vec<i64> = vec![];
+        for runner in event.runner_order.iter().chain(stored.iter()) {
+            if event.runner_state.contains_key(runner) && !order.contains(runner) {
+                order.push(*runner);
+            }
+        }
+        let mut added: Vec<i64> = event
+            .runner_state
+            .keys()
+            .filter(|r| !order.contains(r))
+            .cloned()
+            .collect();
+        added.sort_unstable();
+        order.extend(added);
+
+        let mut builder = QueryBuilder::new("delete from runners_in_event where event = ");
+        builder.push_bind(event.id);
+        if !order.is_empty() {
+            builder.push(" and runner not in (");
+            let mut separated = builder.separated(", ");
+            for runner in &order {
+                separated.push_bind(*runner);
+            }
+            separated.push_unseparated(")");
+        }
+        builder.build().execute(&mut *tx).await?;
 
-    pub async fn delete_runner(&self, runner: i64) -> anyhow::Result<()> {
-        sqlx::query("delete from runners where id= ?")
+        // Upserted rather than reinserted, so rows keep their place
+        for (ordering, runner) in order.iter().enumerate() {
+            sqlx::query(
+                "insert into runners_in_event(event, runner, result, ordering) values(?, ?, ?, ?)
+                    on conflict(event, runner)
+                    do update set result = excluded.result, ordering = excluded.ordering",
+            )
+            .bind(event.id)
             .bind(runner)
-            .execute(&self.db)
+            .bind(event.runner_state[runner].result.clone())
+            .bind(ordering as i64)
+            .execute(&mut *tx)
             .await?;
-        self.trigger_update();
-        Ok(())
-    }
+        }
 
-    pub async fn clear_runner_run(&self, runner: i64) -> anyhow::Result<()> {
-        sqlx::query("delete from runs where runner = ?")
-            .bind(runner)
-            .execute(&self.db)
-            .await?;
-        self.trigger_update();
         Ok(())
     }
 
-    pub async fn set_runner_run_data(&self, runner: i64, run: &Run) -> anyhow::Result<()> {
-        let mut tx = self.db.begin().await?;
+    /// Insert an event without its runners, assigning its ID
+    async fn insert_event(
+        &self,
+        tx: &mut SqliteConnection,
+        event: &mut Event,
+    ) -> anyhow::Result<()> {
         sqlx::query(
-            "insert or replace into runs(
-                runner, sob, best_possible, delta, 
-                started_at, current_comparison, pb, 
-                current_split_name, current_split_index)
-                    values(?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            "insert into events(name, tournament, game, category, estimate, therun_race_id, 
+                            event_start_time, is_relay, is_marathon, auto_relay_handoff,
+                            relay_handoff_split, auto_go_live, scheduled_host, scene_collection,
+                            commentary_host, locale, theme, preferred_layouts)
+                values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
-        .bind(runner)
-        .bind(run.sob)
-        .bind(run.best_possible)
-        .bind(run.delta)
-        .bind(&run.started_at)
-        .bind(&run.current_comparison)
-        .bind(run.pb)
-        .bind(&run.current_split_name)
-        .bind(run.current_split_index)
+        .bind(&event.name)
+        .bind(event.tournament)
+        .bind(&event.game)
+        .bind(&event.category)
+        .bind(event.estimate)
+        .bind(&event.therun_race_id)
+        .bind(event.event_start_time.map(|t| t.unix_timestamp()))
+        .bind(event.is_relay)
+        .bind(event.is_marathon)
+        .bind(event.auto_relay_handoff)
+        .bind(&event.relay_handoff_split)
+        .bind(event.auto_go_live)
+        .bind(&event.scheduled_host)
+        .bind(&event.scene_collection)
+        .bind(&event.commentary_host)
+        .bind(&event.locale)
+        .bind(&event.theme)
+        .bind(serde_json::to_string(&event.preferred_layouts).unwrap())
         .execute(&mut *tx)
         .await?;
 
-        sqlx::query("delete from splits where run = ?")
-            .bind(runner)
-            .execute(&self.db)
+        let last_event_id: i64 = sqlx::query_scalar("select last_insert_rowid()")
+            .fetch_one(&mut *tx)
+            .await?;
+        event.id = last_event_id;
+        log::debug!("Event {} assigned ID {}", event.name, event.id);
+        Ok(())
+    }
+
+    /// Insert or replace a stream and its runners, returning false without
+    /// writing anything if the stored stream has a different version
+    async fn write_stream(
+        &self,
+        tx: &mut SqliteConnection,
+        state: &StreamState,
+    ) -> anyhow::Result<bool> {
+        let written = sqlx::query(
+            "insert into streams(
+                        event, obs_host, active_commentators,
+                        ignored_commentators, requested_layout,
+                        audible_runner, active, rotation, commentator_order,
+                        manual_scene_override, version, ignored_voice_members, on_deck_runners
+                    ) values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    on conflict(event) do update set
+                        obs_host = excluded.obs_host,
+                        active_commentators = excluded.active_commentators,
+                        ignored_commentators = excluded.ignored_commentators,
+                        requested_layout = excluded.requested_layout,
+                        audible_runner = excluded.audible_runner,
+                        rotation = excluded.rotation,
+                        commentator_order = excluded.commentator_order,
+                        manual_scene_override = excluded.manual_scene_override,
+                        ignored_voice_members = excluded.ignored_voice_members,
+                        version = streams.version + 1
+                        where streams.version = excluded.version",
+        )
+        .bind(state.event)
+        .bind(&state.obs_host)
+        .bind(&state.active_commentators)
+        .bind(&state.ignored_commentators)
+        .bind(&state.requested_layout)
+        .bind(state.audible_runner)
+        .bind(state.active)
+        .bind(&state.rotation)
+        .bind(&state.commentator_order)
+        .bind(state.manual_scene_override)
+        .bind(state.version)
+        .bind(&state.ignored_voice_members)
+        .bind(&state.on_deck_runners)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if written == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query("delete from runners_in_stream where event = ?")
+            .bind(state.event)
+            .execute(&mut *tx)
+            .await?;
+
+        if !state.stream_runners.is_empty() {
+            let mut builder = sqlx::QueryBuilder::new(
+                "insert into runners_in_stream(event, runner, stream_order, sync_offset_ms)",
+            );
+            builder.push_values(
+                state.stream_runners.iter().enumerate(),
+                |mut b, (_, runner)| {
+                    b.push_bind(state.event)
+                        .push_bind(runner.1)
+                        .push_bind(runner.0)
+                        .push_bind(state.get_sync_offset(*runner.1));
+                },
+            );
+            builder.build().execute(&mut *tx).await?;
+
+            // Runners who are shown are no longer on deck. The stored list is used
+            // as the list of `state` may be out of date
+            let sqlx::types::Json(on_deck): sqlx::types::Json<Vec<OnDeckRunner>> =
+                sqlx::query_scalar("select on_deck_runners from streams where event = ?")
+                    .bind(state.event)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            let remaining: Vec<_> = on_deck
+                .iter()
+                .filter(|r| !state.stream_runners.values().any(|s| *s == r.runner))
+                .collect();
+            if remaining.len() != on_deck.len() {
+                sqlx::query("update streams set on_deck_runners = ? where event = ?")
+                    .bind(sqlx::types::Json(remaining))
+                    .bind(state.event)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Everything the actors and integrations read from and write to the project.
+///
+/// Implemented by `ProjectDb`, so that code taking a `dyn ProjectStore` can be
+/// run against a database that only lives in memory.
+#[async_trait]
+pub trait ProjectStore: Send + Sync + 'static {
+    /// Run a trivial query to check that the database answers
+    async fn ping(&self) -> anyhow::Result<()>;
+
+    /// Write a consistent copy of the database to `path` while it is in use
+    async fn backup_to(&self, path: &Path) -> anyhow::Result<()>;
+
+    async fn get_tournaments(&self) -> anyhow::Result<Vec<TournamentExport>>;
+
+    /// Load an exported project in a single transaction.
+    ///
+    /// IDs are remapped to new ones. When merging, entries whose name is already in use
+    /// are reported as conflicts and the existing entry is kept.
+    async fn import_project(
+        &self,
+        project: &ProjectExport,
+        mode: ImportMode,
+    ) -> anyhow::Result<ImportReport>;
+
+    /// Number of changes made to the project, which only ever increases
+    fn get_revision(&self) -> i64;
+
+    /// Time of the last change to the project in Unix seconds
+    fn get_revised_at(&self) -> i64;
+
+    /// Hit and miss counts of the query caches, by query
+    fn get_cache_stats(&self) -> HashMap<&'static str, CacheStats>;
+
+    async fn get_runners(&self) -> anyhow::Result<Vec<Runner>>;
+
+    /// List the runners whose name or nickname contains `search`, ordered by name
+    async fn find_runners(
+        &self,
+        search: Option<&str>,
+        include_archived: bool,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> anyhow::Result<Page<Runner>>;
+
+    async fn add_runner(&self, runner: &mut Runner) -> anyhow::Result<()>;
+
+    async fn update_runner(&self, runner: &Runner) -> anyhow::Result<()>;
+
+    async fn get_runner(&self, id: i64) -> anyhow::Result<Runner>;
+
+    /// Social media handles of a runner by platform
+    async fn get_runner_socials(&self, runner: i64) -> anyhow::Result<BTreeMap<String, String>>;
+
+    async fn get_name_for_runner(&self, id: i64) -> anyhow::Result<String>;
+
+    async fn find_runner(&self, name: &str) -> anyhow::Result<Runner>;
+
+    /// Return the runner linked to a Discord user, if any
+    async fn find_runner_by_discord_id(&self, discord_id: &str) -> anyhow::Result<Option<Runner>>;
+
+    /// The runners linked to any of the given Discord users, by Discord ID.
+    ///
+    /// Runners are read through the runner cache, so this costs no more than `get_runners`.
+    async fn find_runners_by_discord_ids(
+        &self,
+        discord_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, Runner>>;
+
+    /// Link a Discord user to a runner, unlinking them from any other runner
+    async fn link_runner_discord_id(&self, runner: i64, discord_id: &str) -> anyhow::Result<()>;
+
+    /// Return every nickname with the ID of its runner
+    async fn get_nicknames(&self) -> anyhow::Result<Vec<(String, i64)>>;
+
+    async fn delete_runner(&self, runner: i64) -> anyhow::Result<()>;
+
+    /// Clear a runner's run after a reset, marking it as reset until new run data arrives
+    async fn reset_runner_run(&self, runner: i64) -> anyhow::Result<()>;
+
+    /// Runners whose run was reset, with the time of the reset
+    fn get_run_resets(&self) -> HashMap<i64, time::OffsetDateTime>;
+
+    async fn clear_runner_run(&self, runner: i64) -> anyhow::Result<()>;
+
+    async fn set_runner_run_data(&self, runner: i64, run: &Run) -> anyhow::Result<()>;
+
+    async fn get_runner_run_data(&self, runner: i64) -> anyhow::Result<Run>;
+
+    async fn add_event(&self, event: &mut Event) -> anyhow::Result<()>;
+
+    /// Create an event with runners in the given order and optionally its stream,
+    /// all in one transaction. The stream's event ID is filled in with the new event's ID.
+    async fn add_full_event(
+        &self,
+        event: &mut Event,
+        runners: &[i64],
+        stream: Option<&mut StreamState>,
+    ) -> anyhow::Result<()>;
+
+    /// List the events matching a filter, ordered by scheduled start time
+    async fn find_events(&self, filter: &EventFilter) -> anyhow::Result<Page<Event>>;
+
+    async fn get_event_ids(&self) -> anyhow::Result<Vec<i64>>;
+
+    async fn get_id_for_event(&self, name: &str) -> anyhow::Result<i64>;
+
+    async fn get_event_names(&self) -> anyhow::Result<Vec<String>>;
+
+    async fn get_event(&self, event_id: i64) -> anyhow::Result<Event>;
+
+    /// Set the commentator shown as the host of an event's commentary
+    async fn update_event_commentary_host(
+        &self,
+        event: i64,
+        host: Option<&str>,
+    ) -> anyhow::Result<()>;
+
+    async fn save_event_completion(&self, completion: &EventCompletion) -> anyhow::Result<()>;
+
+    /// The completion of an event, if it was completed and not reopened since
+    async fn get_event_completion(&self, event: i64) -> anyhow::Result<Option<EventCompletion>>;
+
+    async fn delete_event_completion(&self, event: i64) -> anyhow::Result<()>;
+
+    async fn update_timer_start_time(
+        &self,
+        event: i64,
+        start_time: Option<time::OffsetDateTime>,
+    ) -> anyhow::Result<()>;
+
+    async fn update_timer_end_time(
+        &self,
+        event: i64,
+        end_time: Option<time::OffsetDateTime>,
+    ) -> anyhow::Result<()>;
+
+    /// Save an event and its runners, failing with `Error::Conflict` if the
+    /// event was changed since `event` was loaded
+    async fn update_event(&self, event: &Event) -> anyhow::Result<()>;
+
+    /// Return the runners of an event in their event order
+    async fn get_event_runner_order(&self, event: i64) -> anyhow::Result<Vec<i64>>;
+
+    /// Set the order of an event's runners, which must list each of them once
+    async fn reorder_event_runners(&self, event: i64, order: &[i64]) -> anyhow::Result<()>;
+
+    async fn get_events_for_runner(&self, runner: i64) -> anyhow::Result<Vec<String>>;
+
+    /// Return the events and streams that reference a runner
+    /// Names of streamed events the runner is in view for
+    async fn get_stream_names_for_runner(&self, runner: i64) -> anyhow::Result<Vec<String>>;
+
+    /// Counts of the event entries, stream views and commentator references of a runner
+    async fn get_runner_dependencies(&self, runner: i64) -> anyhow::Result<RunnerDependencies>;
+
+    async fn delete_event(&self, event_id: i64) -> anyhow::Result<()>;
+
+    /// Add an incident to its event, returning the incident's ID
+    async fn add_incident(&self, incident: &Incident) -> anyhow::Result<i64>;
+
+    /// Add a marker to its event, returning the marker's ID
+    async fn add_marker(&self, marker: &Marker) -> anyhow::Result<i64>;
+
+    /// The markers of an event, oldest first
+    async fn get_markers(&self, event: i64) -> anyhow::Result<Vec<Marker>>;
+
+    async fn update_incident(&self, incident: &Incident) -> anyhow::Result<()>;
+
+    async fn delete_incident(&self, id: i64) -> anyhow::Result<()>;
+
+    async fn add_runner_self_token(&self, token: &RunnerSelfToken) -> anyhow::Result<()>;
+
+    async fn get_runner_self_token(&self, token: &str) -> anyhow::Result<Option<RunnerSelfToken>>;
+
+    /// Mark a self-service link as used, so it cannot be used again
+    async fn use_runner_self_token(&self, token: &str) -> anyhow::Result<()>;
+
+    /// Set whether a runner is sent reminders before their events
+    async fn set_runner_reminder_opt_out(&self, runner: i64, opt_out: bool) -> anyhow::Result<()>;
+
+    /// Whether a reminder was already sent to a runner for an event starting at `start_time`
+    async fn is_discord_reminder_sent(
+        &self,
+        event: i64,
+        runner: i64,
+        minutes: u64,
+        start_time: time::OffsetDateTime,
+    ) -> anyhow::Result<bool>;
+
+    /// Whether sending a reminder to a runner ever failed
+    async fn has_undelivered_discord_reminder(&self, runner: i64) -> anyhow::Result<bool>;
+
+    /// Record a reminder sent to a runner, so it is not sent again
+    async fn add_discord_reminder(
+        &self,
+        event: i64,
+        runner: i64,
+        minutes: u64,
+        start_time: time::OffsetDateTime,
+        delivered: bool,
+    ) -> anyhow::Result<()>;
+
+    async fn get_stream_count(&self) -> anyhow::Result<u32>;
+
+    async fn get_streamed_events(&self) -> anyhow::Result<Vec<i64>>;
+
+    /// Return the events whose stream is the active one on its OBS host
+    async fn get_active_streamed_events(&self) -> anyhow::Result<Vec<i64>>;
+
+    /// Return the streamed events that have the given runner in view
+    async fn get_streams_for_runner(&self, runner: i64) -> anyhow::Result<Vec<i64>>;
+
+    /// Return the event ID, event name, OBS host and active state of every stream
+    async fn get_stream_listing(&self) -> anyhow::Result<Vec<(i64, String, String, bool)>>;
+
+    async fn get_stream_runners(&self, event: i64) -> anyhow::Result<HashMap<i64, i64>>;
+
+    async fn get_stream(&self, event_id: i64) -> anyhow::Result<StreamState>;
+
+    /// Save a stream. Activation is only set when the stream is created,
+    /// afterwards it is changed with `activate_stream`.
+    ///
+    /// Fails with `Error::Conflict` if the stream was changed since `state` was loaded.
+    async fn save_stream(&self, state: &StreamState) -> anyhow::Result<()>;
+
+    /// Set the runners on deck for a stream
+    async fn set_stream_on_deck(
+        &self,
+        event_id: i64,
+        on_deck: &[OnDeckRunner],
+    ) -> anyhow::Result<()>;
+
+    /// Set the sync offset of a runner in a stream
+    async fn set_stream_sync_offset(
+        &self,
+        event_id: i64,
+        runner: i64,
+        offset_ms: u32,
+    ) -> anyhow::Result<()>;
+
+    async fn is_host_in_use(&self, obs_host: &str) -> anyhow::Result<bool>;
+
+    /// Return the event of the active stream on an OBS host
+    async fn get_event_by_obs_host(&self, obs_host: &str) -> anyhow::Result<i64>;
+
+    /// Make a stream the active one on its OBS host, deactivating the others
+    async fn activate_stream(&self, event_id: i64) -> anyhow::Result<()>;
+
+    async fn delete_stream(&self, event_id: i64) -> anyhow::Result<()>;
+
+    /// Replace the layout snapshot of an OBS host with its current scenes, in OBS order
+    async fn save_layout_snapshot(&self, obs_host: &str, scenes: &[ObsScene])
+        -> anyhow::Result<()>;
+
+    /// The last layout snapshot of an OBS host in OBS order, with the time it was captured.
+    ///
+    /// The time is None if no snapshot of the host was captured.
+    async fn get_layout_snapshot(
+        &self,
+        obs_host: &str,
+    ) -> anyhow::Result<(Vec<ObsScene>, Option<time::OffsetDateTime>)>;
+
+    async fn get_stream_presets(&self) -> anyhow::Result<Vec<StreamPreset>>;
+
+    async fn get_stream_preset(&self, name: &str) -> anyhow::Result<StreamPreset>;
+
+    /// Save a stream preset, replacing any preset with the same name
+    async fn save_stream_preset(&self, preset: &StreamPreset) -> anyhow::Result<()>;
+
+    /// Discord users that are never shown as commentators, as (Discord ID, name)
+    async fn get_ignored_discord_users(&self) -> anyhow::Result<Vec<(String, String)>>;
+
+    async fn add_ignored_discord_user(&self, discord_id: &str, name: &str) -> anyhow::Result<()>;
+
+    /// Stop ignoring a Discord user, returning whether they were ignored
+    async fn remove_ignored_discord_user(&self, discord_id: &str) -> anyhow::Result<bool>;
+
+    async fn get_themes(&self) -> anyhow::Result<Vec<Theme>>;
+
+    async fn get_theme(&self, name: &str) -> anyhow::Result<Option<Theme>>;
+
+    /// Save a theme, replacing any theme with the same name
+    async fn save_theme(&self, theme: &Theme) -> anyhow::Result<()>;
+
+    /// Delete a theme, leaving the events that used it without one
+    async fn delete_theme(&self, name: &str) -> anyhow::Result<()>;
+
+    async fn delete_stream_preset(&self, name: &str) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl ProjectStore for ProjectDb {
+    async fn ping(&self) -> anyhow::Result<()> {
+        sqlx::query("select 1").execute(&self.db).await?;
+        Ok(())
+    }
+
+    async fn backup_to(&self, path: &Path) -> anyhow::Result<()> {
+        sqlx::query("vacuum into ?")
+            .bind(path.to_str().unwrap())
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_tournaments(&self) -> anyhow::Result<Vec<TournamentExport>> {
+        let tournaments: Vec<(i64, String, String)> =
+            sqlx::query_as("select id, name, format from tournaments")
+                .fetch_all(&self.db)
+                .await?;
+        Ok(tournaments
+            .into_iter()
+            .map(|(id, name, format)| TournamentExport { id, name, format })
+            .collect())
+    }
+
+    async fn import_project(
+        &self,
+        project: &ProjectExport,
+        mode: ImportMode,
+    ) -> anyhow::Result<ImportReport> {
+        if project.version > PROJECT_FORMAT_VERSION {
+            return Err(anyhow!(
+                "Project export version {} is newer than the supported version {}",
+                project.version,
+                PROJECT_FORMAT_VERSION
+            ));
+        }
+
+        let mut report = ImportReport::default();
+        let mut tx = self.db.begin().await?;
+
+        if mode == ImportMode::Replace {
+            log::info!("Deleting the current project before import");
+            for table in [
+                "runners_in_stream",
+                "streams",
+                "runners_in_event",
+                "incidents",
+                "markers",
+                "discord_reminders",
+                "event_completions",
+                "events",
+                "nicknames",
+                "runner_socials",
+                "splits",
+                "runs",
+                "runner_self_tokens",
+                "runners",
+                "tournaments",
+            ] {
+                sqlx::query(&format!("delete from {}", table))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        let mut tournament_ids = HashMap::new();
+        for tournament in &project.tournaments {
+            let existing: Option<i64> =
+                sqlx::query_scalar("select id from tournaments where name = ?")
+                    .bind(&tournament.name)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            let id = match existing {
+                Some(id) => {
+                    report
+                        .tournaments
+                        .conflicts
+                        .push(format!("Tournament {} already exists", tournament.name));
+                    id
+                }
+                None => {
+                    sqlx::query("insert into tournaments(name, format) values(?, ?)")
+                        .bind(&tournament.name)
+                        .bind(&tournament.format)
+                        .execute(&mut *tx)
+                        .await?;
+                    report.tournaments.imported += 1;
+                    sqlx::query_scalar("select last_insert_rowid()")
+                        .fetch_one(&mut *tx)
+                        .await?
+                }
+            };
+            tournament_ids.insert(tournament.id, id);
+        }
+
+        let mut runner_ids = HashMap::new();
+        for export in &project.runners {
+            let runner = &export.runner;
+            let existing: Option<i64> = sqlx::query_scalar("select id from runners where name = ?")
+                .bind(&runner.name)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if let Some(id) = existing {
+                report
+                    .runners
+                    .conflicts
+                    .push(format!("Runner {} already exists", runner.name));
+                runner_ids.insert(runner.id, id);
+                continue;
+            }
+
+            let mut discord_id = runner.discord_id.clone();
+            if let Some(id) = &discord_id {
+                let linked: Option<String> =
+                    sqlx::query_scalar("select name from runners where discord_id = ?")
+                        .bind(id)
+                        .fetch_optional(&mut *tx)
+                        .await?;
+                if let Some(linked) = linked {
+                    report.runners.conflicts.push(format!(
+                        "Discord user of runner {} is already linked to {}",
+                        runner.name, linked
+                    ));
+                    discord_id = None;
+                }
+            }
+
+            sqlx::query(
+                "insert into runners(name, stream, therun, cached_stream_url, location, photo,
+                        volume_percent, max_stream_height, archived, monitor_type, discord_id,
+                        discord_reminder_opt_out, stream_kind)
+                    values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&runner.name)
+            .bind(&runner.stream)
+            .bind(&runner.therun)
+            .bind(&runner.cached_stream_url)
+            .bind(&runner.location)
+            .bind(export.decode_photo()?)
+            .bind(runner.volume_percent)
+            .bind(runner.max_stream_height)
+            .bind(runner.archived)
+            .bind(runner.monitor_type)
+            .bind(discord_id)
+            .bind(runner.discord_reminder_opt_out)
+            .bind(runner.stream_kind)
+            .execute(&mut *tx)
+            .await?;
+            let id: i64 = sqlx::query_scalar("select last_insert_rowid()")
+                .fetch_one(&mut *tx)
+                .await?;
+            runner_ids.insert(runner.id, id);
+            report.runners.imported += 1;
+
+            for nick in &runner.nicks {
+                let added =
+                    sqlx::query("insert or ignore into nicknames(nickname, runner) values(?, ?)")
+                        .bind(nick)
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                if added.rows_affected() == 0 {
+                    report.runners.conflicts.push(format!(
+                        "Nickname {} of {} is already in use",
+                        nick, runner.name
+                    ));
+                }
+            }
+            insert_runner_socials(&mut tx, id, runner).await?;
+        }
+
+        let mut event_ids = HashMap::new();
+        for event in &project.events {
+            let existing: Option<i64> = sqlx::query_scalar("select id from events where name = ?")
+                .bind(&event.name)
+                .fetch_optional(&mut *tx)
+                .await?;
+            if existing.is_some() {
+                report
+                    .events
+                    .conflicts
+                    .push(format!("Event {} already exists", event.name));
+                continue;
+            }
+
+            sqlx::query(
+                "insert into events(name, tournament, game, category, estimate, therun_race_id,
+                        event_start_time, timer_start_time, timer_end_time, is_relay, is_marathon,
+                        auto_relay_handoff, relay_handoff_split, auto_go_live, scheduled_host,
+                        scene_collection, commentary_host, locale, theme, preferred_layouts)
+                    values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&event.name)
+            .bind(event.tournament.and_then(|t| tournament_ids.get(&t)))
+            .bind(&event.game)
+            .bind(&event.category)
+            .bind(event.estimate)
+            .bind(&event.therun_race_id)
+            .bind(event.event_start_time.map(|t| t.unix_timestamp()))
+            .bind(event.timer_start_time.map(|t| t.unix_timestamp()))
+            .bind(event.timer_end_time.map(|t| t.unix_timestamp()))
+            .bind(event.is_relay)
+            .bind(event.is_marathon)
+            .bind(event.auto_relay_handoff)
+            .bind(&event.relay_handoff_split)
+            .bind(event.auto_go_live)
+            .bind(&event.scheduled_host)
+            .bind(&event.scene_collection)
+            .bind(&event.commentary_host)
+            .bind(&event.locale)
+            .bind(&event.theme)
+            .bind(serde_json::to_string(&event.preferred_layouts).unwrap())
+            .execute(&mut *tx)
+            .await?;
+            let id: i64 = sqlx::query_scalar("select last_insert_rowid()")
+                .fetch_one(&mut *tx)
+                .await?;
+            event_ids.insert(event.id, id);
+            report.events.imported += 1;
+
+            // Keep the exported runner order, followed by any runners missing from it
+            let mut order = event.runner_order.clone();
+            order.extend(
+                event
+                    .runner_state
+                    .keys()
+                    .filter(|r| !event.runner_order.contains(r)),
+            );
+            let mut ordering = 0;
+            for runner in order {
+                let (Some(state), Some(new_runner)) =
+                    (event.runner_state.get(&runner), runner_ids.get(&runner))
+                else {
+                    continue;
+                };
+                sqlx::query(
+                    "insert or ignore into runners_in_event(event, runner, result, ordering)
+                        values(?, ?, ?, ?)",
+                )
+                .bind(id)
+                .bind(new_runner)
+                .bind(state.result.clone())
+                .bind(ordering)
+                .execute(&mut *tx)
+                .await?;
+                ordering += 1;
+            }
+
+            for incident in &event.incidents {
+                sqlx::query(
+                    "insert into incidents(event, time, timer_elapsed, author, severity, text)
+                        values(?, ?, ?, ?, ?, ?)",
+                )
+                .bind(id)
+                .bind(incident.time)
+                .bind(incident.timer_elapsed)
+                .bind(&incident.author)
+                .bind(incident.severity)
+                .bind(&incident.text)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        for stream in &project.streams {
+            let Some(event) = event_ids.get(&stream.event) else {
+                report.streams.conflicts.push(format!(
+                    "Stream for event {} was skipped with its event",
+                    stream.event
+                ));
+                continue;
+            };
+            // Streams on a host that already has an active stream are imported inactive
+            let in_use: i64 =
+                sqlx::query_scalar("select count(*) from streams where obs_host = ? and active")
+                    .bind(&stream.obs_host)
+                    .fetch_one(&mut *tx)
+                    .await?;
+
+            sqlx::query(
+                "insert into streams(event, obs_host, active_commentators, ignored_commentators,
+                        requested_layout, audible_runner, active, rotation, commentator_order,
+                        ignored_voice_members)
+                    values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(event)
+            .bind(&stream.obs_host)
+            .bind(&stream.active_commentators)
+            .bind(&stream.ignored_commentators)
+            .bind(&stream.requested_layout)
+            .bind(stream.audible_runner.and_then(|r| runner_ids.get(&r)))
+            .bind(in_use == 0)
+            .bind(&stream.rotation)
+            .bind(&stream.commentator_order)
+            .bind(&stream.ignored_voice_members)
+            .execute(&mut *tx)
+            .await?;
+            report.streams.imported += 1;
+
+            for (slot, runner) in &stream.stream_runners {
+                if let Some(new_runner) = runner_ids.get(runner) {
+                    sqlx::query(
+                        "insert into runners_in_stream(event, runner, stream_order, sync_offset_ms)
+                            values(?, ?, ?, ?)",
+                    )
+                    .bind(event)
+                    .bind(new_runner)
+                    .bind(slot)
+                    .bind(stream.get_sync_offset(*runner))
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+        }
+
+        tx.commit().await?;
+        self.runners_cache.clear();
+        self.events_cache.clear();
+        self.streams_cache.clear();
+        self.event_names_cache.clear();
+        self.trigger_update();
+
+        Ok(report)
+    }
+
+    fn get_revision(&self) -> i64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    fn get_revised_at(&self) -> i64 {
+        self.revised_at.load(Ordering::SeqCst)
+    }
+
+    fn get_cache_stats(&self) -> HashMap<&'static str, CacheStats> {
+        HashMap::from([
+            ("get_runners", self.runners_cache.stats()),
+            ("get_event", self.events_cache.stats()),
+            ("get_stream", self.streams_cache.stats()),
+            ("get_event_names", self.event_names_cache.stats()),
+        ])
+    }
+
+    async fn get_runners(&self) -> anyhow::Result<Vec<Runner>> {
+        let generation = match self.runners_cache.get(&()) {
+            Ok(runners) => return Ok(runners),
+            Err(generation) => generation,
+        };
+
+        let mut runners: Vec<Runner> = sqlx::query_as("select * from runners")
+            .fetch_all(&self.db)
+            .await?;
+        self.load_runner_socials(&mut runners).await?;
+        self.runners_cache.insert(generation, (), runners.clone());
+        Ok(runners)
+    }
+
+    async fn find_runners(
+        &self,
+        search: Option<&str>,
+        include_archived: bool,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> anyhow::Result<Page<Runner>> {
+        fn push_filter<'a>(
+            builder: &mut QueryBuilder<'a, Sqlite>,
+            search: Option<&'a str>,
+            include_archived: bool,
+        ) {
+            builder.push(" where 1 = 1");
+            if !include_archived {
+                builder.push(" and not archived");
+            }
+            if let Some(search) = search {
+                let pattern = like_pattern(search);
+                builder
+                    .push(" and (name like ")
+                    .push_bind(pattern.clone())
+                    .push(" escape '\\' or id in ")
+                    .push("(select runner from nicknames where nickname like ")
+                    .push_bind(pattern)
+                    .push(" escape '\\'))");
+            }
+        }
+
+        let mut count = QueryBuilder::new("select count(*) from runners");
+        push_filter(&mut count, search, include_archived);
+        let total: i64 = count.build_query_scalar().fetch_one(&self.db).await?;
+
+        let mut select = QueryBuilder::new("select * from runners");
+        push_filter(&mut select, search, include_archived);
+        select
+            .push(" order by name limit ")
+            .push_bind(limit.unwrap_or(-1))
+            .push(" offset ")
+            .push_bind(offset.unwrap_or(0));
+        let mut items = select.build_query_as().fetch_all(&self.db).await?;
+        self.load_runner_socials(&mut items).await?;
+
+        Ok(Page { items, total })
+    }
+
+    async fn add_runner(&self, runner: &mut Runner) -> anyhow::Result<()> {
+        log::debug!("Creating new runner {}", runner.name);
+        let mut tx = self.db.begin().await?;
+        sqlx::query("insert into runners(name, stream, therun, location, volume_percent, max_stream_height, archived, monitor_type, discord_id, discord_reminder_opt_out, stream_kind) values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+            .bind(&runner.name)
+            .bind(&runner.stream)
+            .bind(&runner.therun)
+            .bind(&runner.location)
+            .bind(runner.volume_percent)
+            .bind(runner.max_stream_height)
+            .bind(runner.archived)
+            .bind(runner.monitor_type)
+            .bind(&runner.discord_id)
+            .bind(runner.discord_reminder_opt_out)
+            .bind(runner.stream_kind)
+            .execute(&mut *tx)
+            .await?;
+
+        let last_runner_id: i64 = sqlx::query_scalar("select last_insert_rowid()")
+            .fetch_one(&mut *tx)
+            .await?;
+        runner.id = last_runner_id;
+
+        if !runner.nicks.is_empty() {
+            let mut builder = sqlx::QueryBuilder::new("insert into nicknames(nickname, runner)");
+            builder.push_values(runner.nicks.iter(), |mut b, nick| {
+                b.push_bind(nick).push_bind(runner.id);
+            });
+            builder.build().execute(&mut *tx).await?;
+        }
+        insert_runner_socials(&mut tx, runner.id, runner).await?;
+        tx.commit().await?;
+        self.runners_cache.invalidate(&());
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn update_runner(&self, runner: &Runner) -> anyhow::Result<()> {
+        log::debug!("Updating runner {}", runner.name);
+
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query(
+            "update runners set
+                    name = ?,
+                    stream = ?,
+                    therun = ?,
+                    cached_stream_url = ?,
+                    stream_url_fetched_at = ?,
+                    stream_url_expires_at = ?,
+                    location = ?,
+                    volume_percent = ?,
+                    max_stream_height = ?,
+                    archived = ?,
+                    monitor_type = ?,
+                    discord_id = ?,
+                    discord_reminder_opt_out = ?,
+                    stream_kind = ?
+                    where id = ?",
+        )
+        .bind(&runner.name)
+        .bind(&runner.stream)
+        .bind(&runner.therun)
+        .bind(&runner.cached_stream_url)
+        .bind(runner.stream_url_fetched_at.map(|t| t.unix_timestamp()))
+        .bind(runner.stream_url_expires_at.map(|t| t.unix_timestamp()))
+        .bind(&runner.location)
+        .bind(runner.volume_percent)
+        .bind(runner.max_stream_height)
+        .bind(runner.archived)
+        .bind(runner.monitor_type)
+        .bind(&runner.discord_id)
+        .bind(runner.discord_reminder_opt_out)
+        .bind(runner.stream_kind)
+        .bind(runner.id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("delete from nicknames where runner = ?")
+            .bind(runner.id)
+            .execute(&mut *tx)
+            .await?;
+
+        if !runner.nicks.is_empty() {
+            let mut builder = sqlx::QueryBuilder::new("insert into nicknames(nickname, runner)");
+            builder.push_values(runner.nicks.iter(), |mut b, nick| {
+                b.push_bind(nick).push_bind(runner.id);
+            });
+            builder.build().execute(&mut *tx).await?;
+        }
+
+        sqlx::query("delete from runner_socials where runner = ?")
+            .bind(runner.id)
+            .execute(&mut *tx)
+            .await?;
+        insert_runner_socials(&mut tx, runner.id, runner).await?;
+
+        tx.commit().await?;
+        self.runners_cache.invalidate(&());
+        self.trigger_update();
+
+        Ok(())
+    }
+
+    async fn get_runner(&self, id: i64) -> anyhow::Result<Runner> {
+        let mut runner: Runner = sqlx::query_as(
+            "select * from runners 
+                        where id = ?
+                        limit 1",
+            /*"select * from runners r
+            left join nicknames n on r.name = n.runner
+            where r.name = '?' or n.nickname = '?'
+            limit 1",*/
+        )
+        .bind(id)
+        .fetch_one(&self.db)
+        .await?;
+
+        runner.nicks = sqlx::query_scalar("select nickname from nicknames where runner = ?")
+            .bind(runner.id)
+            .fetch_all(&self.db)
+            .await?;
+        runner.socials = self.get_runner_socials(runner.id).await?;
+
+        Ok(runner)
+    }
+
+    async fn get_runner_socials(&self, runner: i64) -> anyhow::Result<BTreeMap<String, String>> {
+        let socials: Vec<(String, String)> =
+            sqlx::query_as("select platform, handle from runner_socials where runner = ?")
+                .bind(runner)
+                .fetch_all(&self.db)
+                .await?;
+        Ok(socials.into_iter().collect())
+    }
+
+    async fn get_name_for_runner(&self, id: i64) -> anyhow::Result<String> {
+        Ok(sqlx::query_scalar("select name from runners where id = ?")
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?)
+    }
+
+    async fn find_runner(&self, name: &str) -> anyhow::Result<Runner> {
+        log::debug!("Searching for runner {}", name);
+        let mut runner: Runner = 
+        /*sqlx::query_as(
+            "select * from runners r
+            left join nicknames n on r.name = n.runner
+            where r.name = '?' or n.nickname = '?'
+            limit 1",
+        )*/
+        sqlx::query_as("select * from runners where name = ?")
+        .bind(name)
+        .fetch_optional(&self.db)
+        .await?
+        .ok_or(anyhow!("Failed to find runner {}", name))?;
+
+        runner.nicks = sqlx::query_scalar("select nickname from nicknames where runner = ?")
+            .bind(runner.id)
+            .fetch_all(&self.db)
+            .await?;
+        runner.socials = self.get_runner_socials(runner.id).await?;
+
+        Ok(runner)
+    }
+
+    async fn find_runner_by_discord_id(&self, discord_id: &str) -> anyhow::Result<Option<Runner>> {
+        Ok(sqlx::query_as("select * from runners where discord_id = ?")
+            .bind(discord_id)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    async fn find_runners_by_discord_ids(
+        &self,
+        discord_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, Runner>> {
+        Ok(self
+            .get_runners()
+            .await?
+            .into_iter()
+            .filter_map(|runner| {
+                let id = runner.discord_id.clone()?;
+                discord_ids.contains(&id).then_some((id, runner))
+            })
+            .collect())
+    }
+
+    async fn link_runner_discord_id(&self, runner: i64, discord_id: &str) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query("update runners set discord_id = null where discord_id = ?")
+            .bind(discord_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let updated = sqlx::query("update runners set discord_id = ? where id = ?")
+            .bind(discord_id)
+            .bind(runner)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            return Err(anyhow!("No runner with ID {}", runner));
+        }
+
+        tx.commit().await?;
+        self.runners_cache.invalidate(&());
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn get_nicknames(&self) -> anyhow::Result<Vec<(String, i64)>> {
+        Ok(sqlx::query_as("select nickname, runner from nicknames")
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    async fn delete_runner(&self, runner: i64) -> anyhow::Result<()> {
+        sqlx::query("delete from runners where id= ?")
+            .bind(runner)
+            .execute(&self.db)
+            .await?;
+        // Events and streams lose the runner through cascading deletes
+        self.runners_cache.invalidate(&());
+        self.events_cache.clear();
+        self.streams_cache.clear();
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn reset_runner_run(&self, runner: i64) -> anyhow::Result<()> {
+        self.run_resets
+            .lock()
+            .unwrap()
+            .insert(runner, time::OffsetDateTime::now_utc());
+        self.clear_runner_run(runner).await
+    }
+
+    fn get_run_resets(&self) -> HashMap<i64, time::OffsetDateTime> {
+        self.run_resets.lock().unwrap().clone()
+    }
+
+    async fn clear_runner_run(&self, runner: i64) -> anyhow::Result<()> {
+        sqlx::query("delete from runs where runner = ?")
+            .bind(runner)
+            .execute(&self.db)
+            .await?;
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn set_runner_run_data(&self, runner: i64, run: &Run) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query(
+            "insert or replace into runs(
+                runner, sob, best_possible, delta, 
+                started_at, current_comparison, pb, 
+                current_split_name, current_split_index)
+                    values(?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(runner)
+        .bind(run.sob)
+        .bind(run.best_possible)
+        .bind(run.delta)
+        .bind(&run.started_at)
+        .bind(&run.current_comparison)
+        .bind(run.pb)
+        .bind(&run.current_split_name)
+        .bind(run.current_split_index)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("delete from splits where run = ?")
+            .bind(runner)
+            .execute(&mut *tx)
             .await?;
 
         let mut builder =
@@ -357,12 +1865,13 @@ impl ProjectDb {
 
         builder.build().execute(&mut *tx).await?;
         tx.commit().await?;
+        self.run_resets.lock().unwrap().remove(&runner);
         self.trigger_update();
 
         Ok(())
     }
 
-    pub async fn get_runner_run_data(&self, runner: i64) -> anyhow::Result<Run> {
+    async fn get_runner_run_data(&self, runner: i64) -> anyhow::Result<Run> {
         let mut run: Run = sqlx::query_as("select * from runs where runner = ?")
             .bind(runner)
             .fetch_one(&self.db)
@@ -375,90 +1884,209 @@ impl ProjectDb {
         Ok(run)
     }
 
-    fn create_event_runners_builder(&self, event: &Event) -> QueryBuilder<Sqlite> {
-        let mut builder =
-            sqlx::QueryBuilder::new("insert into runners_in_event(event, runner, result)");
+    async fn add_event(&self, event: &mut Event) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        self.insert_event(&mut tx, event).await?;
+        self.write_event_runners(&mut tx, event).await?;
 
-        builder.push_values(event.runner_state.iter(), |mut b, result| {
-            b.push_bind(event.id)
-                .push_bind(*result.0)
-                .push_bind(result.1.result.clone());
-        });
-        builder
+        tx.commit().await?;
+        self.events_cache.invalidate(&event.id);
+        self.event_names_cache.clear();
+        self.trigger_update();
+        Ok(())
     }
 
-    pub async fn add_event(&self, event: &mut Event) -> anyhow::Result<()> {
+    async fn add_full_event(
+        &self,
+        event: &mut Event,
+        runners: &[i64],
+        stream: Option<&mut StreamState>,
+    ) -> anyhow::Result<()> {
         let mut tx = self.db.begin().await?;
-        sqlx::query(
-            "insert into events(name, tournament, game, category, estimate, therun_race_id, 
-                            event_start_time, is_relay, is_marathon, preferred_layouts) 
-                values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
-        )
-        .bind(&event.name)
-        .bind(event.tournament)
-        .bind(&event.game)
-        .bind(&event.category)
-        .bind(event.estimate)
-        .bind(&event.therun_race_id)
-        .bind(event.timer_start_time.map(|t| t.unix_timestamp()))
-        .bind(event.is_relay)
-        .bind(event.is_marathon)
-        .bind(serde_json::to_string(&event.preferred_layouts).unwrap())
-        .execute(&mut *tx)
-        .await?;
-
-        let last_event_id: i64 = sqlx::query_scalar("select last_insert_rowid()")
-            .fetch_one(&mut *tx)
-            .await?;
-        event.id = last_event_id;
-        log::debug!("Event {} assigned ID {}", event.name, event.id);
+        self.insert_event(&mut tx, event).await?;
+
+        for (ordering, runner) in runners.iter().enumerate() {
+            sqlx::query("insert into runners_in_event(event, runner, ordering) values(?, ?, ?)")
+                .bind(event.id)
+                .bind(runner)
+                .bind(ordering as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
 
-        if !event.runner_state.is_empty() {
-            let mut builder = self.create_event_runners_builder(event);
-            builder.build().execute(&mut *tx).await?;
+        if let Some(stream) = stream {
+            stream.event = event.id;
+            if !self.write_stream(&mut tx, stream).await? {
+                return Err(anyhow!("A stream already exists for event {}", event.id));
+            }
         }
 
         tx.commit().await?;
+        self.events_cache.invalidate(&event.id);
+        self.streams_cache.invalidate(&event.id);
+        self.event_names_cache.clear();
         self.trigger_update();
         Ok(())
     }
 
-    pub async fn get_event_ids(&self) -> anyhow::Result<Vec<i64>> {
+    async fn find_events(&self, filter: &EventFilter) -> anyhow::Result<Page<Event>> {
+        fn push_filter<'a>(builder: &mut QueryBuilder<'a, Sqlite>, filter: &'a EventFilter) {
+            builder.push(" where 1 = 1");
+            match filter.complete {
+                Some(true) => {
+                    builder.push(" and timer_end_time is not null");
+                }
+                Some(false) => {
+                    builder.push(" and timer_end_time is null");
+                }
+                None => {}
+            }
+            if let Some(tournament) = filter.tournament {
+                builder.push(" and tournament = ").push_bind(tournament);
+            }
+            if let Some(search) = &filter.search {
+                let pattern = like_pattern(search);
+                builder
+                    .push(" and (name like ")
+                    .push_bind(pattern.clone())
+                    .push(" escape '\\' or game like ")
+                    .push_bind(pattern.clone())
+                    .push(" escape '\\' or category like ")
+                    .push_bind(pattern)
+                    .push(" escape '\\')");
+            }
+        }
+
+        let mut count = QueryBuilder::new("select count(*) from events");
+        push_filter(&mut count, filter);
+        let total: i64 = count.build_query_scalar().fetch_one(&self.db).await?;
+
+        let mut select = QueryBuilder::new("select id from events");
+        push_filter(&mut select, filter);
+        select
+            .push(" order by event_start_time is null, event_start_time, id limit ")
+            .push_bind(filter.limit.unwrap_or(-1))
+            .push(" offset ")
+            .push_bind(filter.offset.unwrap_or(0));
+        let ids: Vec<i64> = select.build_query_scalar().fetch_all(&self.db).await?;
+
+        let mut items = vec![];
+        for id in ids {
+            items.push(self.get_event(id).await?);
+        }
+
+        Ok(Page { items, total })
+    }
+
+    async fn get_event_ids(&self) -> anyhow::Result<Vec<i64>> {
         Ok(sqlx::query_scalar("select id from events")
             .fetch_all(&self.db)
             .await?)
     }
 
-    pub async fn get_id_for_event(&self, name: &str) -> anyhow::Result<i64> {
+    async fn get_id_for_event(&self, name: &str) -> anyhow::Result<i64> {
         Ok(sqlx::query_scalar("select id from events where name = ?")
             .bind(name)
             .fetch_one(&self.db)
             .await?)
     }
 
-    pub async fn get_event_names(&self) -> anyhow::Result<Vec<String>> {
-        Ok(sqlx::query_scalar("select name from events")
+    async fn get_event_names(&self) -> anyhow::Result<Vec<String>> {
+        let generation = match self.event_names_cache.get(&()) {
+            Ok(names) => return Ok(names),
+            Err(generation) => generation,
+        };
+
+        let names: Vec<String> = sqlx::query_scalar("select name from events")
             .fetch_all(&self.db)
-            .await?)
+            .await?;
+        self.event_names_cache.insert(generation, (), names.clone());
+        Ok(names)
     }
 
-    pub async fn get_event(&self, event_id: i64) -> anyhow::Result<Event> {
+    async fn get_event(&self, event_id: i64) -> anyhow::Result<Event> {
+        let generation = match self.events_cache.get(&event_id) {
+            Ok(event) => return Ok(event),
+            Err(generation) => generation,
+        };
+
         let mut event: Event = sqlx::query_as("select * from events where id = ? limit 1")
             .bind(event_id)
             .fetch_one(&self.db)
             .await?;
 
-        let runner_state: Vec<RunnerEventState> = sqlx::query_as("select * from runners_in_event where event = ?")
-            .bind(event_id)
-            .fetch_all(&self.db)
-            .await?;
+        let runner_state: Vec<RunnerEventState> = sqlx::query_as(
+            "select * from runners_in_event where event = ? order by ordering, rowid",
+        )
+        .bind(event_id)
+        .fetch_all(&self.db)
+        .await?;
 
+        event.runner_order = runner_state.iter().map(|r| r.runner).collect();
         event.runner_state = runner_state.into_iter().map(|r| (r.runner, r)).collect();
+        event.incidents =
+            sqlx::query_as("select * from incidents where event = ? order by time, id")
+                .bind(event_id)
+                .fetch_all(&self.db)
+                .await?;
+        self.events_cache
+            .insert(generation, event_id, event.clone());
 
         Ok(event)
     }
 
-    pub async fn update_event_start_time(
+    async fn update_event_commentary_host(
+        &self,
+        event: i64,
+        host: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query("update events set commentary_host = ?, version = version + 1 where id = ?")
+            .bind(host)
+            .bind(event)
+            .execute(&self.db)
+            .await?;
+
+        self.events_cache.invalidate(&event);
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn save_event_completion(&self, completion: &EventCompletion) -> anyhow::Result<()> {
+        sqlx::query(
+            "insert into event_completions(event, completed_at, stopped_timer, stream, results)
+                values(?, ?, ?, ?, ?)",
+        )
+        .bind(completion.event)
+        .bind(completion.completed_at)
+        .bind(completion.stopped_timer)
+        .bind(completion.stream.as_ref().map(sqlx::types::Json))
+        .bind(sqlx::types::Json(&completion.results))
+        .execute(&self.db)
+        .await?;
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn get_event_completion(&self, event: i64) -> anyhow::Result<Option<EventCompletion>> {
+        Ok(sqlx::query_as(
+            "select event, completed_at, stopped_timer, stream, results
+                from event_completions where event = ?",
+        )
+        .bind(event)
+        .fetch_optional(&self.db)
+        .await?)
+    }
+
+    async fn delete_event_completion(&self, event: i64) -> anyhow::Result<()> {
+        sqlx::query("delete from event_completions where event = ?")
+            .bind(event)
+            .execute(&self.db)
+            .await?;
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn update_timer_start_time(
         &self,
         event: i64,
         start_time: Option<time::OffsetDateTime>,
@@ -466,6 +2094,7 @@ impl ProjectDb {
         sqlx::query(
             "update events set
                     timer_start_time = ?,
+                    version = version + 1
                     where id = ?",
         )
         .bind(start_time.map(|t| t.unix_timestamp()))
@@ -473,11 +2102,12 @@ impl ProjectDb {
         .execute(&self.db)
         .await?;
 
+        self.events_cache.invalidate(&event);
         self.trigger_update();
         Ok(())
     }
 
-    pub async fn update_event_end_time(
+    async fn update_timer_end_time(
         &self,
         event: i64,
         end_time: Option<time::OffsetDateTime>,
@@ -485,6 +2115,7 @@ impl ProjectDb {
         sqlx::query(
             "update events set
                     timer_end_time = ?,
+                    version = version + 1
                     where id = ?",
         )
         .bind(end_time.map(|t| t.unix_timestamp()))
@@ -492,13 +2123,14 @@ impl ProjectDb {
         .execute(&self.db)
         .await?;
 
+        self.events_cache.invalidate(&event);
         self.trigger_update();
         Ok(())
     }
 
-    pub async fn update_event(&self, event: &Event) -> anyhow::Result<()> {
+    async fn update_event(&self, event: &Event) -> anyhow::Result<()> {
         let mut tx = self.db.begin().await?;
-        sqlx::query(
+        let updated = sqlx::query(
             "update events set
                     name = ?,
                     tournament = ?,
@@ -511,8 +2143,17 @@ impl ProjectDb {
                     timer_end_time = ?,
                     is_relay = ?,
                     is_marathon = ?,
-                    preferred_layouts = ?
-                    where id = ?",
+                    auto_relay_handoff = ?,
+                    relay_handoff_split = ?,
+                    auto_go_live = ?,
+                    scheduled_host = ?,
+                    scene_collection = ?,
+                    commentary_host = ?,
+                    locale = ?,
+                    theme = ?,
+                    preferred_layouts = ?,
+                    version = version + 1
+                    where id = ? and version = ?",
         )
         .bind(&event.name)
         .bind(event.tournament)
@@ -525,67 +2166,362 @@ impl ProjectDb {
         .bind(event.timer_end_time.map(|t| t.unix_timestamp()))
         .bind(event.is_relay)
         .bind(event.is_marathon)
+        .bind(event.auto_relay_handoff)
+        .bind(&event.relay_handoff_split)
+        .bind(event.auto_go_live)
+        .bind(&event.scheduled_host)
+        .bind(&event.scene_collection)
+        .bind(&event.commentary_host)
+        .bind(&event.locale)
+        .bind(&event.theme)
         .bind(serde_json::to_string(&event.preferred_layouts).unwrap())
         .bind(event.id)
+        .bind(event.version)
         .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if updated == 0 {
+            drop(tx);
+            let current = self.get_event(event.id).await?;
+            return Err(Error::Conflict(
+                format!("Event {}", event.name),
+                serde_json::to_value(current)?,
+            )
+            .into());
+        }
+
+        self.write_event_runners(&mut tx, event).await?;
+        tx.commit().await?;
+        self.events_cache.invalidate(&event.id);
+        self.event_names_cache.clear();
+        self.trigger_update();
+
+        Ok(())
+    }
+
+    async fn get_event_runner_order(&self, event: i64) -> anyhow::Result<Vec<i64>> {
+        Ok(sqlx::query_scalar(
+            "select runner from runners_in_event where event = ? order by ordering, rowid",
+        )
+        .bind(event)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn reorder_event_runners(&self, event: i64, order: &[i64]) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        let mut current: Vec<i64> =
+            sqlx::query_scalar("select runner from runners_in_event where event = ?")
+                .bind(event)
+                .fetch_all(&mut *tx)
+                .await?;
+        let mut requested = order.to_vec();
+        current.sort_unstable();
+        requested.sort_unstable();
+        if current != requested {
+            return Err(Error::InvalidRequest(
+                "runners".to_owned(),
+                format!("the order must list each runner of event {} once", event),
+            )
+            .into());
+        }
+
+        for (ordering, runner) in order.iter().enumerate() {
+            sqlx::query("update runners_in_event set ordering = ? where event = ? and runner = ?")
+                .bind(ordering as i64)
+                .bind(event)
+                .bind(runner)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("update events set version = version + 1 where id = ?")
+            .bind(event)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.events_cache.invalidate(&event);
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn get_events_for_runner(&self, runner: i64) -> anyhow::Result<Vec<String>> {
+        Ok(sqlx::query_scalar(
+            "select e.name from events e
+                        inner join runners_in_event r on e.id = r.event
+                        where r.runner = ?",
+        )
+        .bind(runner)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn get_stream_names_for_runner(&self, runner: i64) -> anyhow::Result<Vec<String>> {
+        Ok(sqlx::query_scalar(
+            "select e.name from events e
+                        inner join runners_in_stream r on e.id = r.event
+                        where r.runner = ?",
+        )
+        .bind(runner)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn get_runner_dependencies(&self, runner: i64) -> anyhow::Result<RunnerDependencies> {
+        let (events, streams, commentators): (i64, i64, i64) = sqlx::query_as(
+            "select
+                (select count(*) from runners_in_event where runner = r.id),
+                (select count(*) from runners_in_stream where runner = r.id),
+                (select count(*) from streams s
+                    where instr(';' || s.active_commentators || ';', ';' || r.name || ';') > 0)
+                + (select count(*) from events e where e.commentary_host = r.name)
+            from runners r where r.id = ?",
+        )
+        .bind(runner)
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(RunnerDependencies {
+            events: events as usize,
+            streams: streams as usize,
+            commentators: commentators as usize,
+        })
+    }
+
+    async fn delete_event(&self, event_id: i64) -> anyhow::Result<()> {
+        sqlx::query("delete from events where id = ?")
+            .bind(event_id)
+            .execute(&self.db)
+            .await?;
+
+        // The event's stream is removed by a cascading delete
+        self.events_cache.invalidate(&event_id);
+        self.streams_cache.invalidate(&event_id);
+        self.event_names_cache.clear();
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn add_incident(&self, incident: &Incident) -> anyhow::Result<i64> {
+        let id = sqlx::query(
+            "insert into incidents(event, time, timer_elapsed, author, severity, text)
+                values(?, ?, ?, ?, ?, ?)",
+        )
+        .bind(incident.event)
+        .bind(incident.time)
+        .bind(incident.timer_elapsed)
+        .bind(&incident.author)
+        .bind(incident.severity)
+        .bind(&incident.text)
+        .execute(&self.db)
+        .await?
+        .last_insert_rowid();
+
+        self.events_cache.invalidate(&incident.event);
+        self.trigger_update();
+        Ok(id)
+    }
+
+    async fn add_marker(&self, marker: &Marker) -> anyhow::Result<i64> {
+        let id = sqlx::query(
+            "insert into markers(event, label, time, timer_elapsed, author) values(?, ?, ?, ?, ?)",
+        )
+        .bind(marker.event)
+        .bind(&marker.label)
+        .bind(marker.time)
+        .bind(marker.timer_elapsed)
+        .bind(&marker.author)
+        .execute(&self.db)
+        .await?
+        .last_insert_rowid();
+
+        self.trigger_update();
+        Ok(id)
+    }
+
+    async fn get_markers(&self, event: i64) -> anyhow::Result<Vec<Marker>> {
+        Ok(
+            sqlx::query_as("select * from markers where event = ? order by time, id")
+                .bind(event)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    async fn update_incident(&self, incident: &Incident) -> anyhow::Result<()> {
+        let result = sqlx::query(
+            "update incidents set time = ?, timer_elapsed = ?, author = ?, severity = ?, text = ?
+                where id = ? and event = ?",
+        )
+        .bind(incident.time)
+        .bind(incident.timer_elapsed)
+        .bind(&incident.author)
+        .bind(incident.severity)
+        .bind(&incident.text)
+        .bind(incident.id)
+        .bind(incident.event)
+        .execute(&self.db)
         .await?;
 
-        sqlx::query("delete from runners_in_event where event = ?")
-            .bind(event.id)
-            .execute(&mut *tx)
-            .await?;
+        if result.rows_affected() == 0 {
+            return Err(anyhow!(
+                "No incident with ID {} in event {}",
+                incident.id,
+                incident.event
+            ));
+        }
 
-        let mut builder = self.create_event_runners_builder(event);
-        builder.build().execute(&mut *tx).await?;
-        tx.commit().await?;
+        self.events_cache.invalidate(&incident.event);
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn delete_incident(&self, id: i64) -> anyhow::Result<()> {
+        let event: Option<i64> =
+            sqlx::query_scalar("delete from incidents where id = ? returning event")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?;
+
+        let Some(event) = event else {
+            return Err(anyhow!("No incident with ID {}", id));
+        };
+
+        self.events_cache.invalidate(&event);
         self.trigger_update();
+        Ok(())
+    }
 
+    async fn add_runner_self_token(&self, token: &RunnerSelfToken) -> anyhow::Result<()> {
+        sqlx::query("insert into runner_self_tokens(token, runner, expires_at) values(?, ?, ?)")
+            .bind(&token.token)
+            .bind(token.runner)
+            .bind(token.expires_at.map(|t| t.unix_timestamp()))
+            .execute(&self.db)
+            .await?;
         Ok(())
     }
 
-    pub async fn get_events_for_runner(&self, runner: i64) -> anyhow::Result<Vec<String>> {
-        Ok(sqlx::query_scalar(
-            "select e.name from events e
-                        inner join runners_in_event r on e.id = r.event
-                        where r.runner = ?",
+    async fn get_runner_self_token(&self, token: &str) -> anyhow::Result<Option<RunnerSelfToken>> {
+        Ok(
+            sqlx::query_as("select * from runner_self_tokens where token = ?")
+                .bind(token)
+                .fetch_optional(&self.db)
+                .await?,
         )
-        .bind(runner)
-        .fetch_all(&self.db)
-        .await?)
     }
 
-    pub async fn delete_event(&self, event_id: i64) -> anyhow::Result<()> {
-        sqlx::query("delete from events where id = ?")
-            .bind(event_id)
+    async fn use_runner_self_token(&self, token: &str) -> anyhow::Result<()> {
+        sqlx::query("update runner_self_tokens set used_at = ? where token = ?")
+            .bind(time::OffsetDateTime::now_utc().unix_timestamp())
+            .bind(token)
             .execute(&self.db)
             .await?;
+        Ok(())
+    }
 
+    async fn set_runner_reminder_opt_out(&self, runner: i64, opt_out: bool) -> anyhow::Result<()> {
+        sqlx::query("update runners set discord_reminder_opt_out = ? where id = ?")
+            .bind(opt_out)
+            .bind(runner)
+            .execute(&self.db)
+            .await?;
+        self.runners_cache.invalidate(&());
         self.trigger_update();
         Ok(())
     }
 
-    pub async fn get_stream_count(&self) -> anyhow::Result<u32> {
+    async fn is_discord_reminder_sent(
+        &self,
+        event: i64,
+        runner: i64,
+        minutes: u64,
+        start_time: time::OffsetDateTime,
+    ) -> anyhow::Result<bool> {
+        Ok(sqlx::query_scalar(
+            "select count(*) from discord_reminders
+                where event = ? and runner = ? and minutes = ? and start_time = ?",
+        )
+        .bind(event)
+        .bind(runner)
+        .bind(minutes as i64)
+        .bind(start_time.unix_timestamp())
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    async fn has_undelivered_discord_reminder(&self, runner: i64) -> anyhow::Result<bool> {
+        Ok(sqlx::query_scalar(
+            "select count(*) from discord_reminders where runner = ? and not delivered",
+        )
+        .bind(runner)
+        .fetch_one(&self.db)
+        .await?)
+    }
+
+    async fn add_discord_reminder(
+        &self,
+        event: i64,
+        runner: i64,
+        minutes: u64,
+        start_time: time::OffsetDateTime,
+        delivered: bool,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            "insert or ignore into discord_reminders(
+                        event, runner, minutes, start_time, sent_at, delivered
+                    ) values(?, ?, ?, ?, ?, ?)",
+        )
+        .bind(event)
+        .bind(runner)
+        .bind(minutes as i64)
+        .bind(start_time.unix_timestamp())
+        .bind(time::OffsetDateTime::now_utc().unix_timestamp())
+        .bind(delivered)
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_stream_count(&self) -> anyhow::Result<u32> {
         Ok(sqlx::query_scalar("select count(*) from streams")
             .fetch_one(&self.db)
             .await?)
     }
 
-    pub async fn get_streamed_events(&self) -> anyhow::Result<Vec<i64>> {
+    async fn get_streamed_events(&self) -> anyhow::Result<Vec<i64>> {
         Ok(sqlx::query_scalar("select event from streams")
             .fetch_all(&self.db)
             .await?)
     }
 
-    pub async fn get_streamed_event_names(&self) -> anyhow::Result<Vec<String>> {
-        Ok(sqlx::query_scalar(
-            "select e.name from events e
-                                    inner join streams s on e.id = s.event",
+    async fn get_active_streamed_events(&self) -> anyhow::Result<Vec<i64>> {
+        Ok(sqlx::query_scalar("select event from streams where active")
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    async fn get_streams_for_runner(&self, runner: i64) -> anyhow::Result<Vec<i64>> {
+        Ok(
+            sqlx::query_scalar("select distinct event from runners_in_stream where runner = ?")
+                .bind(runner)
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    async fn get_stream_listing(&self) -> anyhow::Result<Vec<(i64, String, String, bool)>> {
+        Ok(sqlx::query_as(
+            "select s.event, e.name, s.obs_host, s.active from streams s
+                                    inner join events e on e.id = s.event
+                                    order by e.name",
         )
         .fetch_all(&self.db)
         .await?)
     }
 
-    pub async fn get_stream_runners(&self, event: i64) -> anyhow::Result<HashMap<i64, i64>> {
+    async fn get_stream_runners(&self, event: i64) -> anyhow::Result<HashMap<i64, i64>> {
         let query: Vec<(i64, i64)> = sqlx::query_as(
             "select stream_order, runner from runners_in_stream where event = ? order by stream_order",
         ).bind(event).fetch_all(&self.db).await?;
@@ -593,7 +2529,12 @@ impl ProjectDb {
         Ok(query.into_iter().collect())
     }
 
-    pub async fn get_stream(&self, event_id: i64) -> anyhow::Result<StreamState> {
+    async fn get_stream(&self, event_id: i64) -> anyhow::Result<StreamState> {
+        let generation = match self.streams_cache.get(&event_id) {
+            Ok(state) => return Ok(state),
+            Err(generation) => generation,
+        };
+
         let mut state: StreamState =
             sqlx::query_as("select * from streams where event = ? limit 1")
                 .bind(event_id)
@@ -602,65 +2543,101 @@ impl ProjectDb {
 
         state.stream_runners = self.get_stream_runners(event_id).await?;
 
+        let offsets: Vec<(i64, u32)> = sqlx::query_as(
+            "select runner, sync_offset_ms from runners_in_stream where event = ? and sync_offset_ms != 0",
+        )
+        .bind(event_id)
+        .fetch_all(&self.db)
+        .await?;
+        state.sync_offsets = offsets.into_iter().collect();
+        self.streams_cache
+            .insert(generation, event_id, state.clone());
+
         Ok(state)
     }
 
-    pub async fn save_stream(&self, state: &StreamState) -> anyhow::Result<()> {
+    async fn save_stream(&self, state: &StreamState) -> anyhow::Result<()> {
         let mut tx = self.db.begin().await?;
+        if !self.write_stream(&mut tx, state).await? {
+            drop(tx);
+            let current = self.get_stream(state.event).await?;
+            return Err(Error::Conflict(
+                format!("Stream for event {}", state.event),
+                serde_json::to_value(current)?,
+            )
+            .into());
+        }
+        tx.commit().await?;
+        self.streams_cache.invalidate(&state.event);
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn set_stream_on_deck(
+        &self,
+        event_id: i64,
+        on_deck: &[OnDeckRunner],
+    ) -> anyhow::Result<()> {
         sqlx::query(
-            "insert or replace into streams(
-                        event, obs_host, active_commentators,
-                        ignored_commentators, requested_layout,
-                        audible_runner
-                    ) values(?, ?, ?, ?, ?, ?)",
+            "update streams set on_deck_runners = ?, version = version + 1 where event = ?",
         )
-        .bind(state.event)
-        .bind(&state.obs_host)
-        .bind(&state.active_commentators)
-        .bind(&state.ignored_commentators)
-        .bind(&state.requested_layout)
-        .bind(state.audible_runner)
-        .execute(&mut *tx)
+        .bind(sqlx::types::Json(on_deck))
+        .bind(event_id)
+        .execute(&self.db)
         .await?;
 
-        sqlx::query("delete from runners_in_stream where event = ?")
-            .bind(state.event)
-            .execute(&mut *tx)
-            .await?;
+        self.streams_cache.invalidate(&event_id);
+        self.trigger_update();
+        Ok(())
+    }
 
-        if !state.stream_runners.is_empty() {
-            let mut builder = sqlx::QueryBuilder::new(
-                "insert into runners_in_stream(event, runner, stream_order)",
-            );
-            builder.push_values(
-                state.stream_runners.iter().enumerate(),
-                |mut b, (_, runner)| {
-                    b.push_bind(state.event)
-                        .push_bind(runner.1)
-                        .push_bind(runner.0);
-                },
-            );
-            builder.build().execute(&mut *tx).await?;
+    async fn set_stream_sync_offset(
+        &self,
+        event_id: i64,
+        runner: i64,
+        offset_ms: u32,
+    ) -> anyhow::Result<()> {
+        let updated = sqlx::query(
+            "update runners_in_stream set sync_offset_ms = ? where event = ? and runner = ?",
+        )
+        .bind(offset_ms)
+        .bind(event_id)
+        .bind(runner)
+        .execute(&self.db)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Err(anyhow!(
+                "Runner {} is not in the stream for event {}",
+                runner,
+                event_id
+            ));
         }
-        tx.commit().await?;
+
+        sqlx::query("update streams set version = version + 1 where event = ?")
+            .bind(event_id)
+            .execute(&self.db)
+            .await?;
+
+        self.streams_cache.invalidate(&event_id);
         self.trigger_update();
         Ok(())
     }
 
-    pub async fn is_host_in_use(&self, obs_host: &str) -> anyhow::Result<bool> {
+    async fn is_host_in_use(&self, obs_host: &str) -> anyhow::Result<bool> {
         Ok(sqlx::query_scalar(
             "select count(*) from streams 
-                                    where obs_host = ?",
+                                    where obs_host = ? and active",
         )
         .bind(obs_host)
         .fetch_one(&self.db)
         .await?)
     }
 
-    pub async fn get_event_by_obs_host(&self, obs_host: &str) -> anyhow::Result<i64> {
+    async fn get_event_by_obs_host(&self, obs_host: &str) -> anyhow::Result<i64> {
         sqlx::query_scalar(
             "select event from streams 
-                                    where obs_host = ?",
+                                    where obs_host = ? and active",
         )
         .bind(obs_host)
         .fetch_optional(&self.db)
@@ -668,13 +2645,432 @@ impl ProjectDb {
         .ok_or(anyhow!("Failed to find event for host {}", obs_host))
     }
 
-    pub async fn delete_stream(&self, event_id: i64) -> anyhow::Result<()> {
+    async fn activate_stream(&self, event_id: i64) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        sqlx::query(
+            "update streams set active = false
+                where obs_host = (select obs_host from streams where event = ?)",
+        )
+        .bind(event_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let updated = sqlx::query("update streams set active = true where event = ?")
+            .bind(event_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            return Err(anyhow!("No stream exists for event {}", event_id));
+        }
+
+        tx.commit().await?;
+        // Other streams on the same host were deactivated as well
+        self.streams_cache.clear();
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn delete_stream(&self, event_id: i64) -> anyhow::Result<()> {
         sqlx::query("delete from streams where event = ?")
             .bind(event_id)
             .execute(&self.db)
             .await?;
 
+        self.streams_cache.invalidate(&event_id);
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn save_layout_snapshot(
+        &self,
+        obs_host: &str,
+        scenes: &[ObsScene],
+    ) -> anyhow::Result<()> {
+        let captured_at = time::OffsetDateTime::now_utc().unix_timestamp();
+        let mut tx = self.db.begin().await?;
+        sqlx::query("delete from layout_snapshots where obs_host = ?")
+            .bind(obs_host)
+            .execute(&mut *tx)
+            .await?;
+        for (position, scene) in scenes.iter().enumerate() {
+            sqlx::query(
+                "insert into layout_snapshots(obs_host, scene, position, layout, captured_at)
+                    values(?, ?, ?, ?, ?)",
+            )
+            .bind(obs_host)
+            .bind(&scene.name)
+            .bind(position as i64)
+            .bind(sqlx::types::Json(scene))
+            .bind(captured_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_layout_snapshot(
+        &self,
+        obs_host: &str,
+    ) -> anyhow::Result<(Vec<ObsScene>, Option<time::OffsetDateTime>)> {
+        let rows: Vec<(sqlx::types::Json<ObsScene>, i64)> = sqlx::query_as(
+            "select layout, captured_at from layout_snapshots
+                where obs_host = ? order by position",
+        )
+        .bind(obs_host)
+        .fetch_all(&self.db)
+        .await?;
+
+        let captured_at = match rows.first() {
+            Some((_, time)) => Some(time::OffsetDateTime::from_unix_timestamp(*time)?),
+            None => None,
+        };
+        Ok((rows.into_iter().map(|(l, _)| l.0).collect(), captured_at))
+    }
+
+    async fn get_stream_presets(&self) -> anyhow::Result<Vec<StreamPreset>> {
+        let presets: Vec<sqlx::types::Json<StreamPreset>> =
+            sqlx::query_scalar("select preset from stream_presets order by name")
+                .fetch_all(&self.db)
+                .await?;
+        Ok(presets.into_iter().map(|p| p.0).collect())
+    }
+
+    async fn get_stream_preset(&self, name: &str) -> anyhow::Result<StreamPreset> {
+        let preset: Option<sqlx::types::Json<StreamPreset>> =
+            sqlx::query_scalar("select preset from stream_presets where name = ?")
+                .bind(name)
+                .fetch_optional(&self.db)
+                .await?;
+        preset
+            .map(|p| p.0)
+            .ok_or_else(|| anyhow!("No stream preset named {}", name))
+    }
+
+    async fn save_stream_preset(&self, preset: &StreamPreset) -> anyhow::Result<()> {
+        sqlx::query("insert or replace into stream_presets(name, preset) values(?, ?)")
+            .bind(&preset.name)
+            .bind(sqlx::types::Json(preset))
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_ignored_discord_users(&self) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(
+            sqlx::query_as("select discord_id, name from ignored_discord_users order by name")
+                .fetch_all(&self.db)
+                .await?,
+        )
+    }
+
+    async fn add_ignored_discord_user(&self, discord_id: &str, name: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            "insert into ignored_discord_users(discord_id, name) values(?, ?)
+                on conflict(discord_id) do update set name = excluded.name",
+        )
+        .bind(discord_id)
+        .bind(name)
+        .execute(&self.db)
+        .await?;
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn remove_ignored_discord_user(&self, discord_id: &str) -> anyhow::Result<bool> {
+        let removed = sqlx::query("delete from ignored_discord_users where discord_id = ?")
+            .bind(discord_id)
+            .execute(&self.db)
+            .await?
+            .rows_affected();
+        self.trigger_update();
+        Ok(removed > 0)
+    }
+
+    async fn get_themes(&self) -> anyhow::Result<Vec<Theme>> {
+        let themes: Vec<ThemeRow> =
+            sqlx::query_as("select name, tokens, updated_at from themes order by name")
+                .fetch_all(&self.db)
+                .await?;
+        Ok(themes
+            .into_iter()
+            .map(|(name, tokens, updated_at)| Theme {
+                name,
+                tokens: tokens.0,
+                updated_at,
+            })
+            .collect())
+    }
+
+    async fn get_theme(&self, name: &str) -> anyhow::Result<Option<Theme>> {
+        let theme: Option<ThemeRow> =
+            sqlx::query_as("select name, tokens, updated_at from themes where name = ?")
+                .bind(name)
+                .fetch_optional(&self.db)
+                .await?;
+        Ok(theme.map(|(name, tokens, updated_at)| Theme {
+            name,
+            tokens: tokens.0,
+            updated_at,
+        }))
+    }
+
+    async fn save_theme(&self, theme: &Theme) -> anyhow::Result<()> {
+        theme.validate()?;
+        sqlx::query("insert or replace into themes(name, tokens, updated_at) values(?, ?, ?)")
+            .bind(&theme.name)
+            .bind(sqlx::types::Json(&theme.tokens))
+            .bind(time::OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&self.db)
+            .await?;
+        self.trigger_update();
+        Ok(())
+    }
+
+    async fn delete_theme(&self, name: &str) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+        let deleted = sqlx::query("delete from themes where name = ?")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(anyhow!("No theme named {}", name));
+        }
+        sqlx::query("update events set theme = null, version = version + 1 where theme = ?")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.events_cache.clear();
         self.trigger_update();
         Ok(())
     }
+
+    async fn delete_stream_preset(&self, name: &str) -> anyhow::Result<()> {
+        let deleted = sqlx::query("delete from stream_presets where name = ?")
+            .bind(name)
+            .execute(&self.db)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(anyhow!("No stream preset named {}", name));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, OnceLock, Weak};
+
+    use super::*;
+    use crate::core::testing::{test_event, test_runner, test_stream};
+
+    /// The copy of a conflicting record that the server holds
+    fn server_copy(e: anyhow::Error) -> serde_json::Value {
+        match e.downcast::<Error>() {
+            Ok(Error::Conflict(_, current)) => current,
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+    }
+
+    async fn db_with_event() -> (ProjectDb, i64) {
+        let db = ProjectDb::in_memory(Box::new(|| {})).await.unwrap();
+        let mut event = test_event("Race");
+        db.add_event(&mut event).await.unwrap();
+        (db, event.id)
+    }
+
+    #[tokio::test]
+    async fn second_event_write_at_same_version_conflicts() {
+        let (db, event) = db_with_event().await;
+        let mut first = db.get_event(event).await.unwrap();
+        let mut second = db.get_event(event).await.unwrap();
+        assert_eq!(first.version, second.version);
+
+        first.name = "First".to_owned();
+        db.update_event(&first).await.unwrap();
+
+        second.name = "Second".to_owned();
+        let current = server_copy(db.update_event(&second).await.unwrap_err());
+        assert_eq!(current["name"], "First");
+        assert_eq!(current["version"], first.version + 1);
+        assert_eq!(db.get_event(event).await.unwrap().name, "First");
+    }
+
+    #[tokio::test]
+    async fn second_stream_write_at_same_version_conflicts() {
+        let (db, event) = db_with_event().await;
+        db.save_stream(&test_stream(event, &[])).await.unwrap();
+        let mut first = db.get_stream(event).await.unwrap();
+        let mut second = db.get_stream(event).await.unwrap();
+        assert_eq!(first.version, second.version);
+
+        first.requested_layout = Some("2_runners".to_owned());
+        db.save_stream(&first).await.unwrap();
+
+        second.requested_layout = Some("4_runners".to_owned());
+        let current = server_copy(db.save_stream(&second).await.unwrap_err());
+        assert_eq!(current["requested_layout"], "2_runners");
+        assert_eq!(current["version"], first.version + 1);
+        assert_eq!(
+            db.get_stream(event)
+                .await
+                .unwrap()
+                .requested_layout
+                .as_deref(),
+            Some("2_runners")
+        );
+    }
+
+    /// Cached entries of each query family, recorded at every update notification
+    type CacheLog = Arc<Mutex<Vec<HashMap<&'static str, usize>>>>;
+
+    /// A project that records its cached entries whenever it notifies clients of an update
+    async fn db_recording_cache_on_update() -> (Arc<ProjectDb>, CacheLog) {
+        let project: Arc<OnceLock<Weak<ProjectDb>>> = Arc::default();
+        let seen = CacheLog::default();
+        let (weak, record) = (project.clone(), seen.clone());
+        let on_update = Box::new(move || {
+            if let Some(db) = weak.get().and_then(Weak::upgrade) {
+                let entries = db
+                    .get_cache_stats()
+                    .into_iter()
+                    .map(|(query, stats)| (query, stats.entries))
+                    .collect();
+                record.lock().unwrap().push(entries);
+            }
+        });
+        let db = Arc::new(ProjectDb::in_memory(on_update).await.unwrap());
+        project.set(Arc::downgrade(&db)).unwrap();
+        (db, seen)
+    }
+
+    /// Cached entries of a query family at the last update notification
+    fn entries_at_last_update(seen: &CacheLog, query: &str) -> usize {
+        seen.lock().unwrap().last().unwrap()[query]
+    }
+
+    #[tokio::test]
+    async fn runner_change_is_read_back() {
+        let (db, seen) = db_recording_cache_on_update().await;
+        let mut runner = test_runner(0, "first", None);
+        db.add_runner(&mut runner).await.unwrap();
+        assert_eq!(db.get_runners().await.unwrap()[0].name, "first");
+
+        runner.name = "renamed".to_owned();
+        db.update_runner(&runner).await.unwrap();
+        assert_eq!(entries_at_last_update(&seen, "get_runners"), 0);
+        assert_eq!(db.get_runners().await.unwrap()[0].name, "renamed");
+        assert_eq!(db.get_runner(runner.id).await.unwrap().name, "renamed");
+    }
+
+    #[tokio::test]
+    async fn event_change_is_read_back() {
+        let (db, seen) = db_recording_cache_on_update().await;
+        let mut event = test_event("Race");
+        db.add_event(&mut event).await.unwrap();
+        let mut event = db.get_event(event.id).await.unwrap();
+
+        event.name = "Renamed".to_owned();
+        db.update_event(&event).await.unwrap();
+        assert_eq!(entries_at_last_update(&seen, "get_event"), 0);
+        assert_eq!(db.get_event(event.id).await.unwrap().name, "Renamed");
+        assert_eq!(
+            db.get_event_names().await.unwrap(),
+            vec!["Renamed".to_owned()]
+        );
+
+        let start = time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        db.get_event(event.id).await.unwrap();
+        db.update_timer_start_time(event.id, Some(start))
+            .await
+            .unwrap();
+        assert_eq!(entries_at_last_update(&seen, "get_event"), 0);
+        assert_eq!(
+            db.get_event(event.id).await.unwrap().timer_start_time,
+            Some(start)
+        );
+    }
+
+    #[tokio::test]
+    async fn found_runners_have_their_socials() {
+        let db = ProjectDb::in_memory(Box::new(|| {})).await.unwrap();
+        let mut runner = test_runner(0, "Alice", None);
+        runner.socials = BTreeMap::from([("bluesky".to_owned(), "alice.example".to_owned())]);
+        db.add_runner(&mut runner).await.unwrap();
+
+        for page in [
+            db.find_runners(Some("ali"), false, None, None)
+                .await
+                .unwrap(),
+            db.find_runners(None, false, Some(1), None).await.unwrap(),
+        ] {
+            assert_eq!(page.items.len(), 1);
+            assert_eq!(page.items[0].socials, runner.socials);
+        }
+    }
+
+    #[tokio::test]
+    async fn runner_dependencies_count_events_streams_and_commentary() {
+        let actors = crate::core::testing::TestActors::start().await;
+        let db = &actors.db;
+        let alice = actors.add_runner("Alice").await;
+        let bob = actors.add_runner("Bob").await;
+        let race = actors.add_streamed_event("Race", &[alice, bob]).await;
+        let relay = actors.add_streamed_event("Relay", &[bob]).await;
+
+        let mut stream = test_stream(race, &[(0, alice)]);
+        stream.version = db.get_stream(race).await.unwrap().version;
+        db.save_stream(&stream).await.unwrap();
+        let mut stream = db.get_stream(relay).await.unwrap();
+        stream.active_commentators = "Alicia;Alice".to_owned();
+        db.save_stream(&stream).await.unwrap();
+        db.update_event_commentary_host(relay, Some("Alice"))
+            .await
+            .unwrap();
+
+        let alice = db.get_runner_dependencies(alice).await.unwrap();
+        assert_eq!((alice.events, alice.streams, alice.commentators), (1, 1, 2));
+        let bob = db.get_runner_dependencies(bob).await.unwrap();
+        assert_eq!((bob.events, bob.streams, bob.commentators), (2, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn stream_change_is_read_back() {
+        let (db, seen) = db_recording_cache_on_update().await;
+        let mut event = test_event("Race");
+        db.add_event(&mut event).await.unwrap();
+        let mut runner = test_runner(0, "first", None);
+        db.add_runner(&mut runner).await.unwrap();
+        db.save_stream(&test_stream(event.id, &[(0, runner.id)]))
+            .await
+            .unwrap();
+        let mut stream = db.get_stream(event.id).await.unwrap();
+
+        stream.requested_layout = Some("2_runners".to_owned());
+        db.save_stream(&stream).await.unwrap();
+        assert_eq!(entries_at_last_update(&seen, "get_stream"), 0);
+        assert_eq!(
+            db.get_stream(event.id)
+                .await
+                .unwrap()
+                .requested_layout
+                .as_deref(),
+            Some("2_runners")
+        );
+
+        db.set_stream_sync_offset(event.id, runner.id, 250)
+            .await
+            .unwrap();
+        assert_eq!(entries_at_last_update(&seen, "get_stream"), 0);
+        assert_eq!(
+            db.get_stream(event.id)
+                .await
+                .unwrap()
+                .get_sync_offset(runner.id),
+            250
+        );
+    }
 }
@@ -0,0 +1,93 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use serde::Serialize;
+
+/// Hit and miss counts of a query cache
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+struct CacheEntries<K, V> {
+    /// Incremented on every invalidation
+    generation: u64,
+    values: HashMap<K, V>,
+}
+
+/// Cached results of one family of `ProjectDb` queries.
+///
+/// A miss returns the current generation, and the queried value is only stored
+/// if no invalidation happened in the meantime. This way a read that raced
+/// with a write never caches data from before the write.
+pub struct QueryCache<K, V> {
+    entries: Mutex<CacheEntries<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K, V> Default for QueryCache<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(CacheEntries {
+                generation: 0,
+                values: HashMap::new(),
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> QueryCache<K, V> {
+    /// Return the cached value, or the generation to pass to `insert` on a miss
+    pub fn get(&self, key: &K) -> Result<V, u64> {
+        let entries = self.entries.lock().unwrap();
+        match entries.values.get(key) {
+            Some(value) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(value.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Err(entries.generation)
+            }
+        }
+    }
+
+    /// Cache a queried value, unless the cache was invalidated since `generation`
+    pub fn insert(&self, generation: u64, key: K, value: V) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation == generation {
+            entries.values.insert(key, value);
+        }
+    }
+
+    pub fn invalidate(&self, key: &K) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.generation += 1;
+        entries.values.remove(key);
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.generation += 1;
+        entries.values.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().values.len(),
+        }
+    }
+}
@@ -1,15 +1,34 @@
 use std::{collections::HashMap, sync::Arc};
 
+use ::time::format_description::well_known::{Iso8601, Rfc3339};
 use anyhow::anyhow;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sqlx::{prelude::FromRow, types::time};
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::Instrument;
 
-use crate::{send_message, ActorRef, Directory, Rto};
+use crate::{
+    error::Error,
+    integrations::{discord::DiscordCommand, obs::ObsCommand, therun::format_run_time, twitch_api},
+    record_event, send_message, send_message_with_timeout, send_nonblocking, ActorMessage,
+    ActorReceiver, ActorRef, Directory, Rto,
+};
 
-use super::{db::ProjectDb, stream::StreamRequest};
+use super::{
+    db::ProjectStore,
+    export::{self, EventExport},
+    runner::{RunnerRequest, STREAM_URL_REFRESH_TIMEOUT},
+    schedule::run_event_schedule,
+    settings::Settings,
+    stream::{
+        get_layout_names, ModifiedStreamState, StreamRequest, StreamState, StreamValidationError,
+    },
+};
 
-fn serialize_datetime<S>(x: &Option<time::OffsetDateTime>, s: S) -> Result<S::Ok, S::Error>
+pub(crate) fn serialize_datetime<S>(
+    x: &Option<time::OffsetDateTime>,
+    s: S,
+) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -20,7 +39,7 @@ where
     }
 }
 
-fn deserialize_datetime<'de, D>(d: D) -> Result<Option<time::OffsetDateTime>, D::Error>
+pub(crate) fn deserialize_datetime<'de, D>(d: D) -> Result<Option<time::OffsetDateTime>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -32,6 +51,104 @@ where
     }
 }
 
+/// Serialize an event time as an RFC 3339 string in UTC
+pub(crate) fn serialize_rfc3339<S>(
+    x: &Option<time::OffsetDateTime>,
+    s: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match x {
+        Some(x) => x
+            .to_offset(time::UtcOffset::UTC)
+            .format(&Rfc3339)
+            .map_err(serde::ser::Error::custom)
+            .and_then(|text| s.serialize_str(&text)),
+        None => s.serialize_none(),
+    }
+}
+
+/// Deserialize an event time from an RFC 3339 string or a number of Unix milliseconds
+pub(crate) fn deserialize_event_time<'de, D>(d: D) -> Result<Option<time::OffsetDateTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum EventTime {
+        Millis(i64),
+        Text(String),
+    }
+
+    match Option::<EventTime>::deserialize(d)? {
+        None => Ok(None),
+        Some(EventTime::Millis(millis)) => from_unix_millis(millis)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        Some(EventTime::Text(text)) => time::OffsetDateTime::parse(&text, &Rfc3339)
+            .map(Some)
+            .map_err(|e| serde::de::Error::custom(format!("invalid time '{}': {}", text, e))),
+    }
+}
+
+fn from_unix_millis(millis: i64) -> anyhow::Result<time::OffsetDateTime> {
+    Ok(time::OffsetDateTime::from_unix_timestamp_nanos(
+        millis as i128 * 1_000_000,
+    )?)
+}
+
+/// Parse a time typed by a user, as Unix milliseconds or an ISO 8601 date and time.
+///
+/// Times without an offset are read in `timezone`.
+pub fn parse_user_time(
+    text: &str,
+    timezone: time::UtcOffset,
+) -> anyhow::Result<time::OffsetDateTime> {
+    let text = text.trim();
+    if let Ok(millis) = text.parse::<i64>() {
+        return from_unix_millis(millis)
+            .map_err(|_| anyhow!("{} is not a valid Unix time in milliseconds", text));
+    }
+    if let Ok(time) = time::OffsetDateTime::parse(text, &Iso8601::DEFAULT) {
+        return Ok(time);
+    }
+    if let Ok(time) = time::PrimitiveDateTime::parse(text, &Iso8601::DEFAULT) {
+        return Ok(time.assume_offset(timezone));
+    }
+
+    Err(anyhow!(
+        "Could not read '{}' as a time, use Unix milliseconds or an ISO 8601 time \
+        such as 2024-05-01T18:30:00+02:00",
+        text
+    ))
+}
+
+/// Format a time for people to read, in `timezone`
+pub fn format_local_time(time: time::OffsetDateTime, timezone: time::UtcOffset) -> String {
+    let time = time.to_offset(timezone);
+    let (hours, minutes, _) = timezone.as_hms();
+    let offset = if timezone.is_utc() {
+        "UTC".to_owned()
+    } else {
+        format!(
+            "UTC{}{:02}:{:02}",
+            if timezone.is_negative() { '-' } else { '+' },
+            hours.abs(),
+            minutes.abs()
+        )
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} {}",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        offset
+    )
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum EventResult {
     SingleTime { time: f64 },
@@ -44,6 +161,116 @@ pub enum EventResult {
 pub struct RunnerEventState {
     pub runner: i64,
     pub result: Option<sqlx::types::Json<EventResult>>,
+    /// Position of the runner in the event, independent of their stream slot
+    #[serde(default)]
+    pub ordering: i64,
+}
+
+/// How serious an incident is
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum IncidentSeverity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+/// A note about something that happened during an event, such as a dropped stream
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct Incident {
+    /// Unique incident ID, assigned when the incident is added
+    #[serde(default)]
+    pub id: i64,
+    pub event: i64,
+    /// When the incident happened, in Unix milliseconds.
+    /// Defaults to the time the incident is added.
+    #[serde(default)]
+    pub time: i64,
+    /// Elapsed time of the event timer when the incident happened, in milliseconds.
+    /// Filled in from `time` when the incident is added during a running timer.
+    #[serde(default)]
+    pub timer_elapsed: Option<i64>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub severity: IncidentSeverity,
+    pub text: String,
+}
+
+/// A notable moment of an event, such as the start of a run, for editing the VOD
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct Marker {
+    /// Unique marker ID, assigned when the marker is added
+    #[serde(default)]
+    pub id: i64,
+    pub event: i64,
+    pub label: String,
+    /// When the marker was added, in Unix milliseconds
+    #[serde(default)]
+    pub time: i64,
+    /// Elapsed time of the event timer when the marker was added, in milliseconds,
+    /// or None if the timer was not running
+    #[serde(default)]
+    pub timer_elapsed: Option<i64>,
+    #[serde(default)]
+    pub author: Option<String>,
+}
+
+/// Render markers as YouTube chapters, one `0:00 Label` line each, in timer order.
+///
+/// Markers added while the timer was not running are left out. YouTube requires the
+/// first chapter to start at 0:00, so an `Intro` chapter is added if no marker does.
+pub fn format_chapters(markers: &[Marker]) -> String {
+    let mut chapters: Vec<(i64, &str)> = markers
+        .iter()
+        .filter_map(|m| Some((m.timer_elapsed? / 1000, m.label.as_str())))
+        .collect();
+    chapters.sort_by_key(|(secs, _)| *secs);
+    if chapters.first().is_none_or(|(secs, _)| *secs > 0) {
+        chapters.insert(0, (0, "Intro"));
+    }
+
+    chapters
+        .into_iter()
+        .map(|(secs, label)| {
+            let (hours, minutes, seconds) = (secs / 3600, (secs / 60) % 60, secs % 60);
+            if hours > 0 {
+                format!("{}:{:02}:{:02} {}", hours, minutes, seconds, label)
+            } else {
+                format!("{}:{:02} {}", minutes, seconds, label)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The stream to create along with a full event
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FullEventStream {
+    pub obs_host: String,
+    pub layout: Option<String>,
+}
+
+/// An event created together with its runners and stream
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FullEvent {
+    pub event: Event,
+    /// Runners in event order, the first ones fill the stream's view slots
+    #[serde(default)]
+    pub runner_ids: Vec<i64>,
+    #[serde(default)]
+    pub commentator_ids: Vec<i64>,
+    pub stream: Option<FullEventStream>,
+}
+
+/// IDs of everything created for a full event
+#[derive(Debug, Serialize)]
+pub struct CreatedEvent {
+    pub event: i64,
+    /// Streams share the ID of their event
+    pub stream: Option<i64>,
 }
 
 /// Single event (game/race/relay)
@@ -70,19 +297,22 @@ pub struct Event {
     /// The four-character therun.gg race ID
     pub therun_race_id: Option<String>,
 
-    /// The scheduled start time for this event
-    #[serde(serialize_with = "serialize_datetime")]
-    #[serde(deserialize_with = "deserialize_datetime")]
+    /// The scheduled start time for this event.
+    ///
+    /// Event times are stored as Unix seconds and sent as RFC 3339 strings in UTC,
+    /// Unix milliseconds are also accepted.
+    #[serde(serialize_with = "serialize_rfc3339")]
+    #[serde(deserialize_with = "deserialize_event_time")]
     pub event_start_time: Option<time::OffsetDateTime>,
 
     /// The start time of the event timer
-    #[serde(serialize_with = "serialize_datetime")]
-    #[serde(deserialize_with = "deserialize_datetime")]
+    #[serde(serialize_with = "serialize_rfc3339")]
+    #[serde(deserialize_with = "deserialize_event_time")]
     pub timer_start_time: Option<time::OffsetDateTime>,
 
     /// The end time of the event timer
-    #[serde(serialize_with = "serialize_datetime")]
-    #[serde(deserialize_with = "deserialize_datetime")]
+    #[serde(serialize_with = "serialize_rfc3339")]
+    #[serde(deserialize_with = "deserialize_event_time")]
     pub timer_end_time: Option<time::OffsetDateTime>,
 
     /// The default layouts to be used for this event.
@@ -94,90 +324,917 @@ pub struct Event {
     pub is_relay: bool,
     pub is_marathon: bool,
 
+    /// For relays, whether to automatically swap in the next runner
+    /// when an on-screen runner finishes their run
+    #[serde(default)]
+    pub auto_relay_handoff: bool,
+
+    /// Name of the split at which a relay runner hands off to the next,
+    /// instead of their final split
+    #[serde(default)]
+    pub relay_handoff_split: Option<String>,
+
+    /// Whether to prepare the stream and go live at `event_start_time` without an operator
+    #[serde(default)]
+    pub auto_go_live: bool,
+
+    /// The OBS host the event goes live on when `auto_go_live` is set
+    #[serde(default)]
+    pub scheduled_host: Option<String>,
+
+    /// OBS scene collection switched to when the event's stream becomes active
+    #[serde(default)]
+    pub scene_collection: Option<String>,
+
+    /// The commentator hosting the event's commentary, who is shown apart from the others
+    #[serde(default)]
+    pub commentary_host: Option<String>,
+
+    /// Locale of on-stream text, such as `fr` or `fr-CA`, English if None
+    #[serde(default)]
+    pub locale: Option<String>,
+
+    /// Name of the overlay theme of the event, if it has one
+    #[serde(default)]
+    pub theme: Option<String>,
+
+    /// Incremented on every save, updates with an older version are refused
+    #[serde(default)]
+    pub version: i64,
+
     #[sqlx(skip)]
     pub runner_state: HashMap<i64, RunnerEventState>,
+
+    /// Runners of the event, first to last.
+    /// Left empty in an update, the stored order is kept.
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub runner_order: Vec<i64>,
+
+    /// Incidents during the event, ordered by time
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub incidents: Vec<Incident>,
+}
+
+impl Event {
+    /// The estimate formatted as `h:mm:ss`
+    pub fn format_estimate(&self) -> Option<String> {
+        self.estimate
+            .map(|e| format!("{}:{:02}:{:02}", e / 3600, (e / 60) % 60, e % 60))
+    }
+
+    /// Elapsed time of the event timer at a Unix millisecond time,
+    /// if the timer was running then
+    pub fn get_timer_elapsed_at(&self, time: i64) -> Option<i64> {
+        let start = (self.timer_start_time?.unix_timestamp_nanos() / 1_000_000) as i64;
+        let end = self
+            .timer_end_time
+            .map(|t| (t.unix_timestamp_nanos() / 1_000_000) as i64);
+        if time < start || end.is_some_and(|end| time > end) {
+            None
+        } else {
+            Some(time - start)
+        }
+    }
+}
+
+/// What completing an event changed, kept so that the event can be reopened
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct EventCompletion {
+    pub event: i64,
+    /// Unix seconds
+    pub completed_at: i64,
+    /// Whether the timer was still running and was stopped by completing the event
+    pub stopped_timer: bool,
+    /// The event's stream as it was before it was deleted, if it had one
+    pub stream: Option<sqlx::types::Json<StreamState>>,
+    /// Final results, including the TheRun.gg run data of the runners at completion
+    pub results: sqlx::types::Json<EventExport>,
 }
 
 pub enum EventRequest {
     Create(Event, Rto<()>),
+    /// Create an event, its runners and its stream at once, or nothing if any part is invalid
+    CreateFull(FullEvent, Rto<CreatedEvent>),
     SetStartTime(i64, Option<time::OffsetDateTime>, Rto<()>),
     SetEndTime(i64, Option<time::OffsetDateTime>, Rto<()>),
     AddRunner(i64, i64, Rto<()>),
     RemoveRunner(i64, i64, Rto<()>),
     Update(Event, Rto<()>),
     Delete(i64, Rto<()>),
+    /// Set or clear the commentator hosting an event's commentary, by name
+    SetHost(i64, Option<String>, Rto<()>),
+    /// Add an incident to an event, returning its ID
+    AddIncident(Incident, Rto<i64>),
+    UpdateIncident(Incident, Rto<()>),
+    DeleteIncident(i64, Rto<()>),
+    /// Add a marker to an event, also marking the Twitch stream of its host if it is live,
+    /// returning the marker's ID
+    AddMarker(Marker, Rto<i64>),
+    /// Set the order of an event's runners, which must list each of them once
+    ReorderRunners(i64, Vec<i64>, Rto<()>),
+    /// Stop an event's timer, record its results and delete its stream,
+    /// showing the host's intermission scene first if set: event, intermission
+    Complete(i64, bool, Rto<()>),
+    /// Undo the completion of an event, restoring its stream
+    Reopen(i64, Rto<()>),
 }
 
 pub type EventActor = ActorRef<EventRequest>;
 
+impl ActorMessage for EventRequest {
+    fn label(&self) -> &'static str {
+        match self {
+            EventRequest::Create(..) => "Create",
+            EventRequest::CreateFull(..) => "CreateFull",
+            EventRequest::SetStartTime(..) => "SetStartTime",
+            EventRequest::SetEndTime(..) => "SetEndTime",
+            EventRequest::AddRunner(..) => "AddRunner",
+            EventRequest::RemoveRunner(..) => "RemoveRunner",
+            EventRequest::Update(..) => "Update",
+            EventRequest::Delete(..) => "Delete",
+            EventRequest::SetHost(..) => "SetHost",
+            EventRequest::AddIncident(..) => "AddIncident",
+            EventRequest::UpdateIncident(..) => "UpdateIncident",
+            EventRequest::DeleteIncident(..) => "DeleteIncident",
+            EventRequest::AddMarker(..) => "AddMarker",
+            EventRequest::ReorderRunners(..) => "ReorderRunners",
+            EventRequest::Complete(..) => "Complete",
+            EventRequest::Reopen(..) => "Reopen",
+        }
+    }
+}
+
 pub async fn run_event_actor(
-    db: Arc<ProjectDb>,
-    mut rx: UnboundedReceiver<EventRequest>,
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    mut rx: ActorReceiver<EventRequest>,
     directory: Directory,
 ) -> Result<(), anyhow::Error> {
-    while let Some(msg) = rx.recv().await {
-        match msg {
-            EventRequest::Create(mut event, rto) => {
-                log::info!("Creating event {}", event.name);
-                rto.reply(db.add_event(&mut event).await)
-            }
-            EventRequest::Update(event, rto) => rto.reply(db.update_event(&event).await),
-            EventRequest::SetStartTime(id, time, rto) => {
-                rto.reply(db.update_event_start_time(id, time).await);
-            }
-            EventRequest::SetEndTime(id, time, rto) => {
-                rto.reply(db.update_event_end_time(id, time).await);
-            }
-            EventRequest::AddRunner(id, runner, rto) => match db.get_event(id).await {
-                Ok(mut event) => {
-                    if event.runner_state.iter().any(|(r, _)| *r == runner) {
-                        rto.reply(Ok(()));
-                    } else {
-                        event.runner_state.insert(
-                            runner,
-                            RunnerEventState {
+    // Recheck the schedule whenever an event changes
+    let (schedule_tx, schedule_rx) = unbounded_channel();
+    tokio::spawn(run_event_schedule(
+        db.clone(),
+        settings.clone(),
+        directory.clone(),
+        schedule_rx,
+    ));
+
+    while let Some((msg, span)) = rx.recv().await {
+        async {
+            match msg {
+                EventRequest::Create(mut event, rto) => {
+                    log::info!("Creating event {}", event.name);
+                    rto.reply(db.add_event(&mut event).await)
+                }
+                EventRequest::CreateFull(full, rto) => {
+                    log::info!(
+                        "Creating event {} with its runners and stream",
+                        full.event.name
+                    );
+                    rto.reply(create_full_event(&*db, &settings, &directory, full).await)
+                }
+                EventRequest::Update(event, rto) => {
+                    record_event(event.id);
+                    let res = db.update_event(&event).await;
+                    if res.is_ok() && db.get_stream(event.id).await.is_ok() {
+                        // Event details may be shown in text bindings
+                        drop(send_nonblocking!(
+                            directory.obs_actor,
+                            ObsCommand,
+                            UpdateText,
+                            event.id
+                        ));
+                    }
+                    rto.reply(res)
+                }
+                EventRequest::SetStartTime(id, time, rto) => {
+                    record_event(id);
+                    rto.reply(db.update_timer_start_time(id, time).await);
+                }
+                EventRequest::SetEndTime(id, time, rto) => {
+                    record_event(id);
+                    rto.reply(db.update_timer_end_time(id, time).await);
+                }
+                EventRequest::AddRunner(id, runner, rto) => match db.get_event(id).await {
+                    Ok(mut event) => {
+                        if event.runner_state.iter().any(|(r, _)| *r == runner) {
+                            rto.reply(Ok(()));
+                        } else {
+                            event.runner_state.insert(
                                 runner,
-                                result: None,
-                            },
-                        );
-                        rto.reply(db.update_event(&event).await);
+                                RunnerEventState {
+                                    runner,
+                                    result: None,
+                                    ordering: 0,
+                                },
+                            );
+                            rto.reply(db.update_event(&event).await);
+                        }
+                    }
+                    Err(e) => rto.reply(Err(e)),
+                },
+                EventRequest::RemoveRunner(id, runner, rto) => match db.get_event(id).await {
+                    Ok(mut event) => {
+                        if event.runner_state.iter().all(|(r, _)| *r != runner) {
+                            rto.reply(Ok(()));
+                        } else {
+                            event.runner_state.remove(&runner);
+                            rto.reply(db.update_event(&event).await);
+                        }
                     }
+                    Err(e) => rto.reply(Err(e)),
+                },
+                EventRequest::SetHost(id, host, rto) => {
+                    record_event(id);
+                    rto.reply(set_commentary_host(&*db, &directory, id, host).await)
                 }
-                Err(e) => rto.reply(Err(e)),
-            },
-            EventRequest::RemoveRunner(id, runner, rto) => match db.get_event(id).await {
-                Ok(mut event) => {
-                    if event.runner_state.iter().all(|(r, _)| *r != runner) {
-                        rto.reply(Ok(()));
-                    } else {
-                        event.runner_state.remove(&id);
-                        rto.reply(db.update_event(&event).await);
+                EventRequest::AddIncident(mut incident, rto) => {
+                    record_event(incident.event);
+                    match db.get_event(incident.event).await {
+                        Ok(event) => {
+                            if incident.time == 0 {
+                                incident.time =
+                                    (time::OffsetDateTime::now_utc().unix_timestamp_nanos()
+                                        / 1_000_000) as i64;
+                            }
+                            if incident.timer_elapsed.is_none() {
+                                incident.timer_elapsed = event.get_timer_elapsed_at(incident.time);
+                            }
+                            log::info!(
+                                "Adding incident to event {}: {}",
+                                event.name,
+                                incident.text
+                            );
+                            rto.reply(db.add_incident(&incident).await)
+                        }
+                        Err(e) => rto.reply(Err(e)),
                     }
                 }
-                Err(e) => rto.reply(Err(e)),
-            },
-            EventRequest::Delete(id, rto) => match db.get_streamed_events().await {
-                Ok(ev) => {
-                    if ev.iter().any(|e| *e == id) {
-                        match send_message!(directory.stream_actor, StreamRequest, Delete, id) {
-                            Ok(_) => {
-                                log::info!("Deleting event with ID {}", id);
-                                rto.reply(db.delete_event(id).await)
+                EventRequest::UpdateIncident(incident, rto) => {
+                    record_event(incident.event);
+                    rto.reply(db.update_incident(&incident).await)
+                }
+                EventRequest::DeleteIncident(id, rto) => rto.reply(db.delete_incident(id).await),
+                EventRequest::AddMarker(marker, rto) => {
+                    record_event(marker.event);
+                    rto.reply(add_marker(&*db, &settings, &directory, marker).await)
+                }
+                EventRequest::ReorderRunners(id, order, rto) => {
+                    record_event(id);
+                    log::info!("Reordering the runners of event {}: {:?}", id, order);
+                    rto.reply(db.reorder_event_runners(id, &order).await)
+                }
+                EventRequest::Delete(id, rto) => match db.get_streamed_events().await {
+                    Ok(ev) => {
+                        if ev.contains(&id) {
+                            match send_message!(directory.stream_actor, StreamRequest, Delete, id) {
+                                Ok(_) => {
+                                    log::info!("Deleting event with ID {}", id);
+                                    rto.reply(db.delete_event(id).await)
+                                }
+                                Err(e) => rto.reply(Err(anyhow!(
+                                    "Error in deleting stream while deleting event {}: {:?}",
+                                    id,
+                                    e
+                                ))),
                             }
-                            Err(e) => rto.reply(Err(anyhow!(
-                                "Error in deleting stream while deleting event {}: {:?}",
-                                id,
-                                e
-                            ))),
+                        } else {
+                            rto.reply(db.delete_event(id).await);
                         }
-                    } else {
-                        rto.reply(db.delete_event(id).await);
                     }
+                    Err(e) => rto.reply(Err(e)),
+                },
+                EventRequest::Complete(id, intermission, rto) => {
+                    record_event(id);
+                    rto.reply(complete_event(&*db, &settings, &directory, id, intermission).await)
                 }
-                Err(e) => rto.reply(Err(e)),
-            },
+                EventRequest::Reopen(id, rto) => {
+                    record_event(id);
+                    rto.reply(reopen_event(&*db, &directory, id).await)
+                }
+            }
         }
+        .instrument(span)
+        .await;
+
+        let _ = schedule_tx.send(());
     }
 
     Ok(())
 }
+
+/// Validate and create an event with its runners and stream in one transaction
+async fn create_full_event(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+    full: FullEvent,
+) -> anyhow::Result<CreatedEvent> {
+    let FullEvent {
+        mut event,
+        runner_ids,
+        commentator_ids,
+        stream,
+    } = full;
+
+    let mut seen = vec![];
+    for runner in &runner_ids {
+        if db.get_runner(*runner).await.is_err() {
+            return Err(Error::InvalidRequest(
+                "runner_ids".to_string(),
+                format!("no runner with ID {}", runner),
+            )
+            .into());
+        }
+        if seen.contains(runner) {
+            return Err(Error::InvalidRequest(
+                "runner_ids".to_string(),
+                format!("runner {} is listed more than once", runner),
+            )
+            .into());
+        }
+        seen.push(*runner);
+    }
+
+    let mut commentators = vec![];
+    for commentator in &commentator_ids {
+        match db.get_runner(*commentator).await {
+            Ok(runner) => commentators.push(runner.name),
+            Err(_) => {
+                return Err(Error::InvalidRequest(
+                    "commentator_ids".to_string(),
+                    format!("no runner with ID {}", commentator),
+                )
+                .into())
+            }
+        }
+    }
+
+    let mut stream = match stream {
+        Some(stream) => {
+            if db.is_host_in_use(&stream.obs_host).await? {
+                return Err(Error::InvalidRequest(
+                    "stream".to_string(),
+                    format!("host '{}' is already in use", stream.obs_host),
+                )
+                .into());
+            }
+
+            let state = StreamState {
+                event: 0,
+                obs_host: stream.obs_host,
+                active_commentators: commentators.join(";"),
+                ignored_commentators: "".to_string(),
+                ignored_voice_members: "".to_string(),
+                audible_runner: runner_ids.first().copied(),
+                requested_layout: stream.layout,
+                active: true,
+                rotation: None,
+                commentator_order: commentators.join(";"),
+                manual_scene_override: false,
+                version: 0,
+                stream_runners: runner_ids
+                    .iter()
+                    .enumerate()
+                    .map(|(slot, runner)| (slot as i64, *runner))
+                    .collect(),
+                sync_offsets: HashMap::new(),
+                layout_slot_count: None,
+                on_deck_runners: sqlx::types::Json(vec![]),
+            };
+
+            let layouts = get_layout_names(settings, directory).await;
+            let violations = state.validate(db, settings, layouts.as_ref()).await?;
+            if !violations.is_empty() {
+                return Err(Error::InvalidRequest(
+                    "stream".to_string(),
+                    StreamValidationError(violations).to_string(),
+                )
+                .into());
+            }
+            Some(state)
+        }
+        None if !commentator_ids.is_empty() => {
+            return Err(Error::InvalidRequest(
+                "commentator_ids".to_string(),
+                "commentators need a stream".to_string(),
+            )
+            .into())
+        }
+        None => None,
+    };
+
+    db.add_full_event(&mut event, &runner_ids, stream.as_mut())
+        .await?;
+
+    if let Some(stream) = &stream {
+        // Fetch the runners' feeds and show the stream without holding up the reply
+        let directory = directory.clone();
+        let event = event.id;
+        let host = stream.obs_host.clone();
+        tokio::spawn(async move {
+            for runner in runner_ids {
+                let host = Some(host.clone());
+                if let Err(e) = send_message_with_timeout!(
+                    STREAM_URL_REFRESH_TIMEOUT,
+                    directory.runner_actor,
+                    RunnerRequest,
+                    RefreshStream,
+                    runner,
+                    host
+                ) {
+                    log::warn!("Failed to fetch the stream of runner {}: {}", runner, e);
+                }
+            }
+            if let Err(e) = send_message!(directory.stream_actor, StreamRequest, Reload, event) {
+                log::warn!("Failed to show new stream {}: {}", event, e);
+            }
+        });
+    }
+
+    Ok(CreatedEvent {
+        event: event.id,
+        stream: stream.map(|s| s.event),
+    })
+}
+
+/// Set the host of an event's commentary, who must be one of its stream's commentators
+async fn set_commentary_host(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    host: Option<String>,
+) -> anyhow::Result<()> {
+    let stream = db.get_stream(event).await;
+    let host = match (host, &stream) {
+        (None, _) => None,
+        (Some(host), Ok(stream)) => Some(
+            stream
+                .get_commentators()
+                .into_iter()
+                .find(|c| c.eq_ignore_ascii_case(&host))
+                .ok_or_else(|| anyhow!("{} is not a commentator of event {}", host, event))?,
+        ),
+        (Some(_), Err(_)) => {
+            return Err(anyhow!(
+                "Event {} has no stream, so it has no commentators to host",
+                event
+            ))
+        }
+    };
+
+    log::info!(
+        "Setting the commentary host of event {} to {:?}",
+        event,
+        host
+    );
+    db.update_event_commentary_host(event, host.as_deref())
+        .await?;
+
+    if stream.is_ok() {
+        let modifications = vec![ModifiedStreamState::Commentary];
+        send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            UpdateState,
+            event,
+            modifications
+        )?;
+    }
+    Ok(())
+}
+
+/// Complete an event, keeping what is needed to reopen it.
+///
+/// Runners of the deleted stream stop being polled on TheRun.gg unless they are in another stream.
+async fn complete_event(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+    event: i64,
+    intermission: bool,
+) -> anyhow::Result<()> {
+    let info = db.get_event(event).await?;
+    if db.get_event_completion(event).await?.is_some() {
+        return Err(Error::InvalidRequest(
+            "event".to_owned(),
+            format!("{} is already complete", info.name),
+        )
+        .into());
+    }
+
+    log::info!("Completing event {}", info.name);
+    let now = time::OffsetDateTime::now_utc();
+    let stopped_timer = info.timer_end_time.is_none();
+    if stopped_timer {
+        db.update_timer_end_time(event, Some(now)).await?;
+    }
+
+    // The results are read before the stream is deleted, as they include its commentators
+    let results = export::export_event(db, event).await?;
+    let stream = db.get_stream(event).await.ok();
+    db.save_event_completion(&EventCompletion {
+        event,
+        completed_at: now.unix_timestamp(),
+        stopped_timer,
+        stream: stream.clone().map(sqlx::types::Json),
+        results: sqlx::types::Json(results.clone()),
+    })
+    .await?;
+
+    if let Some(stream) = stream {
+        let scene = settings
+            .obs_hosts
+            .get(&stream.obs_host)
+            .and_then(|h| h.intermission_scene.clone());
+        if intermission && stream.active {
+            match scene {
+                Some(scene) => {
+                    let host = stream.obs_host.clone();
+                    send_message!(
+                        directory.stream_actor,
+                        StreamRequest,
+                        SwitchScene,
+                        host,
+                        scene
+                    )?
+                }
+                None => log::warn!("OBS host {} has no intermission scene", stream.obs_host),
+            }
+        }
+        send_message!(directory.stream_actor, StreamRequest, Delete, event)?;
+    }
+
+    let mut text = format!("{} is complete", results.name);
+    if let Some(winner) = results.results.first().filter(|r| r.placement == Some(1)) {
+        let value = match (winner.time, winner.score) {
+            (Some(time), _) => format_run_time(time),
+            (None, Some(score)) => score.to_string(),
+            (None, None) => String::new(),
+        };
+        text = format!("{}, won by {} ({})", text, winner.name, value);
+    }
+    directory.discord_actor.send(DiscordCommand::Notify(text));
+    if let Some(url) = &settings.event_complete_webhook_url {
+        tokio::spawn(send_completion_webhook(url.clone(), results));
+    }
+    Ok(())
+}
+
+/// Reopen a completed event, restarting its timer if completing it stopped the timer
+async fn reopen_event(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+) -> anyhow::Result<()> {
+    let info = db.get_event(event).await?;
+    let completion = db.get_event_completion(event).await?.ok_or_else(|| {
+        Error::InvalidRequest("event".to_owned(), format!("{} is not complete", info.name))
+    })?;
+
+    log::info!("Reopening event {}", info.name);
+    if let Some(stream) = completion.stream {
+        let stream = stream.0;
+        send_message!(directory.stream_actor, StreamRequest, Restore, stream)?;
+    }
+    if completion.stopped_timer {
+        db.update_timer_end_time(event, None).await?;
+    }
+    db.delete_event_completion(event).await
+}
+
+/// Add a marker to an event, stamped with the current time if it has none
+async fn add_marker(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+    mut marker: Marker,
+) -> anyhow::Result<i64> {
+    let event = db.get_event(marker.event).await?;
+    if marker.label.trim().is_empty() {
+        return Err(Error::InvalidRequest(
+            "label".to_owned(),
+            "a marker needs a label".to_owned(),
+        ))?;
+    }
+    if marker.time == 0 {
+        marker.time = (time::OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64;
+    }
+    if marker.timer_elapsed.is_none() {
+        marker.timer_elapsed = event.get_timer_elapsed_at(marker.time);
+    }
+    log::info!("Adding marker to event {}: {}", event.name, marker.label);
+    let id = db.add_marker(&marker).await?;
+
+    let channel = match db.get_stream(event.id).await {
+        Ok(stream) => settings
+            .obs_hosts
+            .get(&stream.obs_host)
+            .and_then(|h| h.twitch_channel.clone())
+            .map(|channel| (stream.obs_host, channel)),
+        Err(_) => None,
+    };
+    if let (Some((client_id, token)), Some((host, channel))) =
+        (twitch_api::helix_credentials(settings), channel)
+    {
+        tokio::spawn(create_twitch_marker(
+            directory.clone(),
+            client_id.to_owned(),
+            token.to_owned(),
+            host,
+            channel,
+            marker.label,
+        ));
+    }
+
+    Ok(id)
+}
+
+/// Mark the Twitch stream of a host, if the host is streaming
+async fn create_twitch_marker(
+    directory: Directory,
+    client_id: String,
+    token: String,
+    host: String,
+    channel: String,
+    label: String,
+) {
+    let streaming = match send_message!(directory.obs_actor, ObsCommand, GetState) {
+        Ok(hosts) => hosts.get(&host).is_some_and(|h| h.streaming && !h.dry_run),
+        Err(e) => {
+            log::warn!("Failed to check whether {} is streaming: {}", host, e);
+            false
+        }
+    };
+    if !streaming {
+        log::debug!("{} is not streaming, not creating a Twitch marker", host);
+        return;
+    }
+
+    match twitch_api::create_stream_marker(&client_id, &token, &channel, &label).await {
+        Ok(()) => log::info!("Created Twitch marker on {}: {}", channel, label),
+        Err(e) => log::warn!("Failed to create a Twitch marker on {}: {}", channel, e),
+    }
+}
+
+/// Notify a webhook of a completed event, with its results
+async fn send_completion_webhook(url: String, results: EventExport) {
+    let body = serde_json::json!({
+        "event": "event_complete",
+        "results": results,
+    });
+
+    if let Err(e) = reqwest::Client::new().post(&url).json(&body).send().await {
+        log::warn!("Failed to send event completion webhook to {}: {}", url, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::time::macros::{datetime, offset};
+    use serde_json::json;
+
+    use super::*;
+    use crate::core::testing::{test_event, TestActors};
+
+    #[test]
+    fn event_times_round_trip_as_rfc3339() {
+        let mut event = test_event("Race");
+        event.event_start_time = Some(datetime!(2024-05-01 18:00:30 UTC));
+        event.timer_start_time = Some(datetime!(2024-05-01 20:15 +02:00));
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["event_start_time"], "2024-05-01T18:00:30Z");
+        // Times are always sent in UTC
+        assert_eq!(value["timer_start_time"], "2024-05-01T18:15:00Z");
+        assert_eq!(value["timer_end_time"], serde_json::Value::Null);
+
+        let read: Event = serde_json::from_value(value).unwrap();
+        assert_eq!(read.event_start_time, event.event_start_time);
+        assert_eq!(read.timer_start_time, event.timer_start_time);
+        assert_eq!(read.timer_end_time, None);
+    }
+
+    #[test]
+    fn event_times_are_read_from_offsets_and_millis() {
+        let mut value = serde_json::to_value(test_event("Race")).unwrap();
+        value["event_start_time"] = json!("2024-05-01T20:00:00+02:00");
+        value["timer_start_time"] = json!(1_714_586_400_000i64);
+        let event: Event = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(
+            event.event_start_time,
+            Some(datetime!(2024-05-01 18:00 UTC))
+        );
+        assert_eq!(
+            event.timer_start_time,
+            Some(datetime!(2024-05-01 18:00 UTC))
+        );
+
+        value["event_start_time"] = json!("2024-05-01 18:00");
+        assert!(serde_json::from_value::<Event>(value).is_err());
+    }
+
+    #[test]
+    fn user_times_without_offset_use_timezone() {
+        let time = parse_user_time("2024-05-01T18:30:00", offset!(+02:00)).unwrap();
+        assert_eq!(time, datetime!(2024-05-01 16:30 UTC));
+
+        let time = parse_user_time("2024-05-01T18:30:00", offset!(UTC)).unwrap();
+        assert_eq!(time, datetime!(2024-05-01 18:30 UTC));
+    }
+
+    #[test]
+    fn user_times_with_offset_ignore_timezone() {
+        let time = parse_user_time("2024-05-01T18:30:00-04:00", offset!(+02:00)).unwrap();
+        assert_eq!(time, datetime!(2024-05-01 22:30 UTC));
+        let time = parse_user_time("2024-05-01T18:30:00Z", offset!(+02:00)).unwrap();
+        assert_eq!(time, datetime!(2024-05-01 18:30 UTC));
+        let time = parse_user_time(" 1714588200000 ", offset!(+02:00)).unwrap();
+        assert_eq!(time, datetime!(2024-05-01 18:30 UTC));
+    }
+
+    #[test]
+    fn user_times_across_dst_boundary_keep_the_configured_offset() {
+        // US Eastern skips from 02:00 to 03:00 on 2024-03-10. The setting is a fixed
+        // offset, so a local time in the skipped hour is still read in it
+        let time = parse_user_time("2024-03-10T02:30:00", offset!(-05:00)).unwrap();
+        assert_eq!(time, datetime!(2024-03-10 07:30 UTC));
+        // The same instant given with the summer offset
+        let time = parse_user_time("2024-03-10T03:30:00-04:00", offset!(-05:00)).unwrap();
+        assert_eq!(time, datetime!(2024-03-10 07:30 UTC));
+        // 01:30 happens twice on 2024-11-03, the configured offset picks one
+        let time = parse_user_time("2024-11-03T01:30:00", offset!(-04:00)).unwrap();
+        assert_eq!(time, datetime!(2024-11-03 05:30 UTC));
+        let time = parse_user_time("2024-11-03T01:30:00", offset!(-05:00)).unwrap();
+        assert_eq!(time, datetime!(2024-11-03 06:30 UTC));
+    }
+
+    #[test]
+    fn unreadable_user_time_is_refused() {
+        assert!(parse_user_time("tomorrow", offset!(UTC)).is_err());
+        assert!(parse_user_time("2024-13-01T18:30:00", offset!(UTC)).is_err());
+    }
+
+    #[test]
+    fn local_times_show_their_offset() {
+        let time = datetime!(2024-05-01 18:30 UTC);
+        assert_eq!(
+            format_local_time(time, offset!(UTC)),
+            "2024-05-01 18:30 UTC"
+        );
+        assert_eq!(
+            format_local_time(time, offset!(+02:00)),
+            "2024-05-01 20:30 UTC+02:00"
+        );
+        assert_eq!(
+            format_local_time(time, offset!(-05:30)),
+            "2024-05-01 13:00 UTC-05:30"
+        );
+    }
+
+    #[tokio::test]
+    async fn timer_updates_are_saved() {
+        let actors = TestActors::start().await;
+        let event = actors.add_event("Race", &[]).await;
+        let start = datetime!(2024-05-01 18:00 UTC);
+        let end = datetime!(2024-05-01 19:30 UTC);
+
+        send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            SetStartTime,
+            event,
+            Some(start)
+        )
+        .unwrap();
+        let saved = actors.db.get_event(event).await.unwrap();
+        assert_eq!(saved.timer_start_time, Some(start));
+        assert_eq!(saved.timer_end_time, None);
+
+        send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            SetEndTime,
+            event,
+            Some(end)
+        )
+        .unwrap();
+        let saved = actors.db.get_event(event).await.unwrap();
+        assert_eq!(saved.timer_end_time, Some(end));
+        assert_eq!(
+            saved.get_timer_elapsed_at(start.unix_timestamp() * 1000 + 5000),
+            Some(5000)
+        );
+
+        let cleared: Option<time::OffsetDateTime> = None;
+        send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            SetStartTime,
+            event,
+            cleared
+        )
+        .unwrap();
+        assert_eq!(
+            actors.db.get_event(event).await.unwrap().timer_start_time,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn runners_are_added_and_removed() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let second = actors.add_runner("second").await;
+        let event = actors.add_event("Race", &[first, second]).await;
+
+        let saved = actors.db.get_event(event).await.unwrap();
+        assert!(saved.runner_state.contains_key(&first));
+        assert!(saved.runner_state.contains_key(&second));
+
+        send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            RemoveRunner,
+            event,
+            first
+        )
+        .unwrap();
+        let saved = actors.db.get_event(event).await.unwrap();
+        assert!(!saved.runner_state.contains_key(&first));
+        assert!(saved.runner_state.contains_key(&second));
+    }
+
+    #[tokio::test]
+    async fn reorder_must_list_every_runner_once() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let second = actors.add_runner("second").await;
+        let event = actors.add_event("Race", &[first, second]).await;
+
+        let res = send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            ReorderRunners,
+            event,
+            vec![second]
+        );
+        assert!(matches!(
+            res.unwrap_err().downcast_ref(),
+            Some(Error::InvalidRequest(..))
+        ));
+
+        send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            ReorderRunners,
+            event,
+            vec![second, first]
+        )
+        .unwrap();
+        assert_eq!(
+            actors.db.get_event_runner_order(event).await.unwrap(),
+            vec![second, first]
+        );
+    }
+
+    #[tokio::test]
+    async fn stale_update_is_refused() {
+        let actors = TestActors::start().await;
+        let event = actors.add_event("Race", &[]).await;
+        let mut copy = actors.db.get_event(event).await.unwrap();
+
+        send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            SetStartTime,
+            event,
+            Some(datetime!(2024-05-01 18:00 UTC))
+        )
+        .unwrap();
+
+        copy.name = "Renamed".to_owned();
+        let res = send_message!(actors.directory.event_actor, EventRequest, Update, copy);
+        assert!(matches!(
+            res.unwrap_err().downcast_ref(),
+            Some(Error::Conflict(..))
+        ));
+        assert_eq!(actors.db.get_event(event).await.unwrap().name, "Race");
+    }
+
+    #[tokio::test]
+    async fn runner_of_missing_event_is_refused() {
+        let actors = TestActors::start().await;
+        let runner = actors.add_runner("first").await;
+        let res = send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            AddRunner,
+            404,
+            runner
+        );
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use crate::integrations::therun::{format_run_time, Run};
+
+use super::{
+    db::ProjectStore,
+    event::{EventResult, Incident},
+};
+
+/// The exported result of a runner in an event
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunnerResultExport {
+    pub runner: i64,
+    pub name: String,
+    /// Placement among runners with a result, starting at 1
+    pub placement: Option<usize>,
+    pub finished: bool,
+    /// Final time in milliseconds
+    pub time: Option<f64>,
+    pub score: Option<f64>,
+    /// Personal best in milliseconds that the runner held going into the run
+    pub pb: Option<f64>,
+    /// Sum of best in milliseconds, as reported by TheRun.gg during the run
+    pub sum_of_best: Option<f64>,
+}
+
+/// Results and metadata of an event
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EventExport {
+    pub id: i64,
+    pub name: String,
+    pub game: Option<String>,
+    pub category: Option<String>,
+    /// Estimate in seconds
+    pub estimate: Option<i64>,
+    pub is_relay: bool,
+    pub is_marathon: bool,
+    /// Timer start and end times in Unix milliseconds
+    pub timer_start_time: Option<i64>,
+    pub timer_end_time: Option<i64>,
+    pub commentators: Vec<String>,
+    /// Results ordered by placement, with unfinished runners last
+    pub results: Vec<RunnerResultExport>,
+    /// Incidents ordered by time
+    pub incidents: Vec<Incident>,
+}
+
+impl EventExport {
+    /// Runner results as CSV, one row per runner
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            "event,game,category,placement,runner,finished,time,score,pb,sum_of_best\n".to_string();
+
+        for result in &self.results {
+            let row = [
+                self.name.clone(),
+                self.game.clone().unwrap_or_default(),
+                self.category.clone().unwrap_or_default(),
+                result.placement.map(|p| p.to_string()).unwrap_or_default(),
+                result.name.clone(),
+                result.finished.to_string(),
+                result.time.map(format_run_time).unwrap_or_default(),
+                result.score.map(|s| s.to_string()).unwrap_or_default(),
+                result.pb.map(format_run_time).unwrap_or_default(),
+                result.sum_of_best.map(format_run_time).unwrap_or_default(),
+            ];
+
+            csv.push_str(&row.map(|f| escape_csv(&f)).join(","));
+            csv.push('\n');
+        }
+
+        csv
+    }
+}
+
+/// Quote a CSV field if it contains separators, quotes, or newlines
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// The personal best a runner held when their run started.
+///
+/// TheRun.gg replaces the run's PB once a new one is set, so the PB time of the final
+/// split, which LiveSplit keeps until the run is saved, is used when it is known.
+fn recorded_pb(run: &Run) -> Option<f64> {
+    run.splits.last().and_then(|s| s.pb_split_time).or(run.pb)
+}
+
+/// Build the results of an event.
+///
+/// Runners without a recorded result fall back to a finished TheRun.gg run,
+/// and are marked as unfinished if they have neither.
+/// Completed events keep the results they had when they were completed.
+pub async fn export_event(db: &dyn ProjectStore, event: i64) -> anyhow::Result<EventExport> {
+    if let Some(completion) = db.get_event_completion(event).await? {
+        return Ok(completion.results.0);
+    }
+    let event = db.get_event(event).await?;
+
+    let commentators = match db.get_stream(event.id).await {
+        Ok(stream) => stream
+            .get_commentators()
+            .into_iter()
+            .filter(|c| !c.is_empty())
+            .collect(),
+        Err(_) => vec![],
+    };
+
+    let mut results = vec![];
+    for runner in db.get_event_runner_order(event.id).await? {
+        let name = db.get_name_for_runner(runner).await?;
+        let run = db.get_runner_run_data(runner).await.ok();
+
+        let (mut time, score) = match event
+            .runner_state
+            .get(&runner)
+            .and_then(|s| s.result.as_ref())
+            .map(|r| &r.0)
+        {
+            Some(EventResult::SingleTime { time }) => (Some(*time), None),
+            Some(EventResult::SplitTimes { split_times }) => (split_times.last().cloned(), None),
+            Some(EventResult::SingleScore { score }) => (None, Some(*score)),
+            None => (None, None),
+        };
+
+        if time.is_none() && score.is_none() {
+            time = run
+                .as_ref()
+                .filter(|r| r.is_finished())
+                .and_then(|r| r.splits.last())
+                .and_then(|s| s.split_time);
+        }
+
+        results.push(RunnerResultExport {
+            runner,
+            name,
+            placement: None,
+            finished: time.is_some() || score.is_some(),
+            time,
+            score,
+            pb: run.as_ref().and_then(recorded_pb),
+            sum_of_best: run.as_ref().and_then(|r| r.sob),
+        });
+    }
+
+    // Times are placed before scores, with the lowest time and highest score first
+    results.sort_by(|a, b| match (a.time, b.time, a.score, b.score) {
+        (Some(a), Some(b), _, _) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (Some(_), None, _, _) => Ordering::Less,
+        (None, Some(_), _, _) => Ordering::Greater,
+        (None, None, Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+        (None, None, Some(_), None) => Ordering::Less,
+        (None, None, None, Some(_)) => Ordering::Greater,
+        (None, None, None, None) => Ordering::Equal,
+    });
+
+    for (idx, result) in results.iter_mut().enumerate() {
+        if result.finished {
+            result.placement = Some(idx + 1);
+        }
+    }
+
+    Ok(EventExport {
+        id: event.id,
+        name: event.name,
+        game: event.game,
+        category: event.category,
+        estimate: event.estimate,
+        is_relay: event.is_relay,
+        is_marathon: event.is_marathon,
+        timer_start_time: event
+            .timer_start_time
+            .map(|t| (t.unix_timestamp_nanos() / 1_000_000) as i64),
+        timer_end_time: event
+            .timer_end_time
+            .map(|t| (t.unix_timestamp_nanos() / 1_000_000) as i64),
+        commentators,
+        results,
+        incidents: event.incidents,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sqlx::types::Json;
+
+    use super::*;
+    use crate::core::{
+        db::ProjectDb,
+        event::RunnerEventState,
+        testing::{test_event, test_runner, test_stream},
+    };
+
+    /// A run through two splits, finished if both have a time
+    fn test_run(split_times: [Option<f64>; 2], pb: f64, pb_split_time: f64) -> Run {
+        serde_json::from_value(json!({
+            "pb": pb,
+            "sob": 59_000.0,
+            "bestPossible": null,
+            "delta": null,
+            "startedAt": "2024-05-01T18:00:00Z",
+            "currentComparison": "Personal Best",
+            "currentSplitName": "End",
+            "currentSplitIndex": split_times.iter().filter(|t| t.is_some()).count(),
+            "splits": [
+                { "name": "Start", "pbSplitTime": 30_000.0, "splitTime": split_times[0] },
+                { "name": "End", "pbSplitTime": pb_split_time, "splitTime": split_times[1] },
+            ],
+        }))
+        .unwrap()
+    }
+
+    /// A race between Alice, Bob, Carol and Dave, where only Bob and Carol have recorded
+    /// results and Dave has a finished run on TheRun.gg
+    async fn seeded_db() -> (ProjectDb, i64, [i64; 4]) {
+        let db = ProjectDb::in_memory(Box::new(|| {})).await.unwrap();
+        let mut runners = [0; 4];
+        for (id, name) in runners.iter_mut().zip(["Alice", "Bob", "Carol", "Dave"]) {
+            let mut runner = test_runner(0, name, None);
+            db.add_runner(&mut runner).await.unwrap();
+            *id = runner.id;
+        }
+        let [alice, bob, carol, dave] = runners;
+
+        let mut event = test_event("Race, Final");
+        event.game = Some("Game".to_owned());
+        let results = [
+            (alice, None),
+            (bob, Some(EventResult::SingleTime { time: 65_000.0 })),
+            (
+                carol,
+                Some(EventResult::SplitTimes {
+                    split_times: vec![30_000.0, 62_000.0],
+                }),
+            ),
+            (dave, None),
+        ];
+        for (ordering, (runner, result)) in results.into_iter().enumerate() {
+            let state = RunnerEventState {
+                runner,
+                result: result.map(Json),
+                ordering: ordering as i64,
+            };
+            event.runner_state.insert(runner, state);
+        }
+        db.add_event(&mut event).await.unwrap();
+
+        db.set_runner_run_data(alice, &test_run([Some(31_000.0), None], 60_000.0, 60_000.0))
+            .await
+            .unwrap();
+        // Dave set a new PB, which TheRun.gg already reports
+        db.set_runner_run_data(
+            dave,
+            &test_run([Some(29_000.0), Some(58_000.0)], 58_000.0, 61_000.0),
+        )
+        .await
+        .unwrap();
+
+        let mut stream = test_stream(event.id, &[]);
+        stream.active_commentators = "Eve;Frank".to_owned();
+        stream.ignored_commentators = "Frank".to_owned();
+        db.save_stream(&stream).await.unwrap();
+
+        (db, event.id, runners)
+    }
+
+    #[tokio::test]
+    async fn results_are_placed_by_time() {
+        let (db, event, [alice, bob, carol, dave]) = seeded_db().await;
+        let export = export_event(&db, event).await.unwrap();
+
+        assert_eq!(export.name, "Race, Final");
+        assert_eq!(export.game.as_deref(), Some("Game"));
+        assert_eq!(export.commentators, vec!["Eve"]);
+
+        let placed: Vec<(i64, Option<usize>, Option<f64>, bool)> = export
+            .results
+            .iter()
+            .map(|r| (r.runner, r.placement, r.time, r.finished))
+            .collect();
+        assert_eq!(
+            placed,
+            vec![
+                (dave, Some(1), Some(58_000.0), true),
+                (carol, Some(2), Some(62_000.0), true),
+                (bob, Some(3), Some(65_000.0), true),
+                (alice, None, None, false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pb_is_the_one_held_going_into_the_run() {
+        let (db, event, [alice, _, _, dave]) = seeded_db().await;
+        let export = export_event(&db, event).await.unwrap();
+        let result = |runner| export.results.iter().find(|r| r.runner == runner).unwrap();
+
+        assert_eq!(result(dave).pb, Some(61_000.0));
+        assert_eq!(result(dave).sum_of_best, Some(59_000.0));
+        assert_eq!(result(alice).pb, Some(60_000.0));
+    }
+
+    #[tokio::test]
+    async fn scores_follow_times() {
+        let db = ProjectDb::in_memory(Box::new(|| {})).await.unwrap();
+        let mut event = test_event("Score Attack");
+        for (ordering, (name, result)) in [
+            ("Low", EventResult::SingleScore { score: 10.0 }),
+            ("High", EventResult::SingleScore { score: 90.0 }),
+            ("Timed", EventResult::SingleTime { time: 1_000.0 }),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut runner = test_runner(0, name, None);
+            db.add_runner(&mut runner).await.unwrap();
+            let state = RunnerEventState {
+                runner: runner.id,
+                result: Some(Json(result)),
+                ordering: ordering as i64,
+            };
+            event.runner_state.insert(runner.id, state);
+        }
+        db.add_event(&mut event).await.unwrap();
+
+        let export = export_event(&db, event.id).await.unwrap();
+        let names: Vec<&str> = export.results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["Timed", "High", "Low"]);
+        assert!(export.commentators.is_empty());
+    }
+
+    #[tokio::test]
+    async fn csv_has_a_row_per_runner() {
+        let (db, event, _) = seeded_db().await;
+        let csv = export_event(&db, event).await.unwrap().to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("event,game,category,placement,runner"));
+        assert!(lines[1].starts_with("\"Race, Final\",Game,,1,Dave,true,"));
+        assert!(lines[4].starts_with("\"Race, Final\",Game,,,Alice,false,,"));
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use sqlx::types::time::OffsetDateTime;
+
+use super::{db::ProjectStore, settings::Settings};
+
+/// How long the health check waits for the database
+const DB_HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Status that actors keep up to date for the health check.
+///
+/// Reading it never waits on an actor, so the health check
+/// answers even if an actor or OBS host is stuck.
+pub struct HealthStatus {
+    started: Instant,
+    obs_hosts: Mutex<HashMap<String, ObsHostHealth>>,
+    /// None until the Discord bot is started
+    discord: Mutex<Option<DiscordStatus>>,
+    /// Users in the voice channel of each OBS host
+    discord_voice: Mutex<HashMap<String, usize>>,
+    therun: Mutex<HashMap<i64, TheRunHealth>>,
+    ws_clients: AtomicUsize,
+}
+
+#[derive(Default)]
+struct ObsHostHealth {
+    connected: bool,
+    last_success: Option<OffsetDateTime>,
+}
+
+#[derive(Default)]
+struct TheRunHealth {
+    connected: bool,
+    last_message: Option<Instant>,
+    last_message_at: Option<OffsetDateTime>,
+    reconnects: u32,
+}
+
+/// State of the Discord gateway connection
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct DiscordStatus {
+    pub connected: bool,
+    /// Time the connection was last lost in Unix millis
+    pub last_disconnect: Option<i64>,
+    /// Why the connection was last lost, if known
+    pub last_error: Option<String>,
+    /// Times the connection was lost since startup
+    pub disconnects: u32,
+}
+
+/// State of a runner's TheRun.gg websocket monitor
+#[derive(Clone, Copy, Default, Debug)]
+pub struct TheRunMonitorHealth {
+    pub connected: bool,
+    /// When TheRun.gg last sent data for the runner
+    pub last_message: Option<OffsetDateTime>,
+    /// Reconnection attempts since the runner started being polled
+    pub reconnects: u32,
+}
+
+/// Overall health, as reported by `/healthz`
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthLevel {
+    Ok,
+    Degraded,
+    Down,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthReport {
+    pub status: HealthLevel,
+    pub db: DbHealthReport,
+    pub obs_hosts: HashMap<String, ObsHostHealthReport>,
+    /// None if the Discord bot is not enabled
+    pub discord: Option<DiscordHealthReport>,
+    pub therun: TheRunHealthReport,
+    pub web: WebHealthReport,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DbHealthReport {
+    pub ok: bool,
+    pub latency_ms: f64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ObsHostHealthReport {
+    pub connected: bool,
+    /// Time of the last successful request to the host in Unix millis
+    pub last_success: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiscordHealthReport {
+    #[serde(flatten)]
+    pub status: DiscordStatus,
+    /// Users in the voice channel of each OBS host, as of the last voice update
+    pub voice_members: HashMap<String, usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TheRunHealthReport {
+    pub active_websockets: usize,
+    pub runners: HashMap<i64, TheRunRunnerHealthReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TheRunRunnerHealthReport {
+    pub connected: bool,
+    /// Seconds since TheRun.gg last sent data for the runner
+    pub last_message_age_secs: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WebHealthReport {
+    pub uptime_secs: u64,
+    pub ws_clients: usize,
+}
+
+impl Default for HealthStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthStatus {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            obs_hosts: Mutex::default(),
+            discord: Mutex::default(),
+            discord_voice: Mutex::default(),
+            therun: Mutex::default(),
+            ws_clients: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn set_obs_connected(&self, host: &str, connected: bool) {
+        let mut hosts = self.obs_hosts.lock().unwrap();
+        let health = hosts.entry(host.to_owned()).or_default();
+        health.connected = connected;
+        if connected {
+            health.last_success = Some(OffsetDateTime::now_utc());
+        }
+    }
+
+    /// Record a successful request to an OBS host
+    pub fn record_obs_success(&self, host: &str) {
+        self.set_obs_connected(host, true);
+    }
+
+    /// Set whether the Discord gateway is connected, returning whether it was before
+    pub fn set_discord_connected(&self, connected: bool) -> bool {
+        let mut discord = self.discord.lock().unwrap();
+        let status = discord.get_or_insert_with(DiscordStatus::default);
+        std::mem::replace(&mut status.connected, connected)
+    }
+
+    /// Record that the Discord gateway connection was lost
+    pub fn record_discord_disconnect(&self, error: Option<String>) {
+        let mut discord = self.discord.lock().unwrap();
+        let discord = discord.get_or_insert_with(DiscordStatus::default);
+        discord.connected = false;
+        discord.last_disconnect =
+            Some((OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64);
+        discord.last_error = error;
+        discord.disconnects += 1;
+    }
+
+    /// The state of the Discord connection, or None if the bot was not started
+    pub fn get_discord(&self) -> Option<DiscordStatus> {
+        self.discord.lock().unwrap().clone()
+    }
+
+    pub fn set_discord_voice_members(&self, host: &str, members: usize) {
+        self.discord_voice
+            .lock()
+            .unwrap()
+            .insert(host.to_owned(), members);
+    }
+
+    pub fn set_therun_connected(&self, runner: i64, connected: bool) {
+        self.therun
+            .lock()
+            .unwrap()
+            .entry(runner)
+            .or_default()
+            .connected = connected;
+    }
+
+    /// Record that TheRun.gg sent data for a runner
+    pub fn record_therun_message(&self, runner: i64) {
+        let mut therun = self.therun.lock().unwrap();
+        let health = therun.entry(runner).or_default();
+        health.last_message = Some(Instant::now());
+        health.last_message_at = Some(OffsetDateTime::now_utc());
+    }
+
+    /// Record that a runner's TheRun.gg websocket is being opened again
+    pub fn record_therun_reconnect(&self, runner: i64) {
+        self.therun
+            .lock()
+            .unwrap()
+            .entry(runner)
+            .or_default()
+            .reconnects += 1;
+    }
+
+    /// The state of a runner's TheRun.gg websocket, or None if the runner is not polled
+    pub fn get_therun_monitor(&self, runner: i64) -> Option<TheRunMonitorHealth> {
+        self.therun
+            .lock()
+            .unwrap()
+            .get(&runner)
+            .map(|h| TheRunMonitorHealth {
+                connected: h.connected,
+                last_message: h.last_message_at,
+                reconnects: h.reconnects,
+            })
+    }
+
+    /// Whether a runner's TheRun.gg websocket is connected, or None if the runner is not polled
+    pub fn get_therun_connected(&self, runner: i64) -> Option<bool> {
+        self.therun
+            .lock()
+            .unwrap()
+            .get(&runner)
+            .map(|h| h.connected)
+    }
+
+    /// Stop tracking a runner that is no longer polled
+    pub fn remove_therun(&self, runner: i64) {
+        self.therun.lock().unwrap().remove(&runner);
+    }
+
+    pub fn ws_client_connected(&self) {
+        self.ws_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn ws_client_disconnected(&self) {
+        self.ws_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Summarize the health of every subsystem.
+    ///
+    /// The project is down if the database does not answer, and degraded
+    /// if an OBS host or Discord is disconnected, or if no TheRun.gg
+    /// websocket is open while runners are polled.
+    pub async fn report(&self, db: &dyn ProjectStore, settings: &Settings) -> HealthReport {
+        let start = Instant::now();
+        let db_result = tokio::time::timeout(DB_HEALTH_TIMEOUT, db.ping()).await;
+        let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+        let db_report = match db_result {
+            Ok(Ok(())) => DbHealthReport {
+                ok: true,
+                latency_ms,
+                error: None,
+            },
+            Ok(Err(e)) => DbHealthReport {
+                ok: false,
+                latency_ms,
+                error: Some(e.to_string()),
+            },
+            Err(_) => DbHealthReport {
+                ok: false,
+                latency_ms,
+                error: Some(format!("No reply within {:?}", DB_HEALTH_TIMEOUT)),
+            },
+        };
+
+        let obs_hosts: HashMap<String, ObsHostHealthReport> = {
+            let hosts = self.obs_hosts.lock().unwrap();
+            settings
+                .obs_hosts
+                .keys()
+                .map(|name| {
+                    let health = hosts.get(name);
+                    (
+                        name.clone(),
+                        ObsHostHealthReport {
+                            connected: health.is_some_and(|h| h.connected),
+                            last_success: health
+                                .and_then(|h| h.last_success)
+                                .map(|t| (t.unix_timestamp_nanos() / 1_000_000) as i64),
+                        },
+                    )
+                })
+                .collect()
+        };
+
+        let discord = settings
+            .discord_token
+            .as_ref()
+            .map(|_| DiscordHealthReport {
+                status: self.get_discord().unwrap_or_default(),
+                voice_members: self.discord_voice.lock().unwrap().clone(),
+            });
+
+        let runners: HashMap<i64, TheRunRunnerHealthReport> = self
+            .therun
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(runner, health)| {
+                (
+                    *runner,
+                    TheRunRunnerHealthReport {
+                        connected: health.connected,
+                        last_message_age_secs: health.last_message.map(|t| t.elapsed().as_secs()),
+                    },
+                )
+            })
+            .collect();
+        let therun = TheRunHealthReport {
+            active_websockets: runners.values().filter(|r| r.connected).count(),
+            runners,
+        };
+
+        let web = WebHealthReport {
+            uptime_secs: self.started.elapsed().as_secs(),
+            ws_clients: self.ws_clients.load(Ordering::Relaxed),
+        };
+
+        let status = if !db_report.ok {
+            HealthLevel::Down
+        } else if obs_hosts.values().any(|h| !h.connected)
+            || discord.as_ref().is_some_and(|d| !d.status.connected)
+            || (!therun.runners.is_empty() && therun.active_websockets == 0)
+        {
+            HealthLevel::Degraded
+        } else {
+            HealthLevel::Ok
+        };
+
+        HealthReport {
+            status,
+            db: db_report,
+            obs_hosts,
+            discord,
+            therun,
+            web,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+/// Locale used when an event has none, and the last fallback of every other locale
+pub const DEFAULT_LOCALE: &str = "en";
+
+static TRANSLATIONS: OnceLock<HashMap<String, LocaleTable>> = OnceLock::new();
+
+/// How durations such as estimates are written
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DurationStyle {
+    /// `1:02:00`
+    Clock,
+    /// `1h02m`
+    Compact,
+}
+
+/// Translations of a single locale, read from `locales/<locale>.toml` in the project folder
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LocaleTable {
+    pub duration_style: Option<DurationStyle>,
+    /// Line shown above the commentator names, such as `Commentary:`
+    pub commentary_prefix: Option<String>,
+    /// Translated labels, available to text templates as `{label.<key>}`
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+/// The built-in English table, which translation files may override
+fn default_table() -> LocaleTable {
+    LocaleTable {
+        duration_style: Some(DurationStyle::Clock),
+        commentary_prefix: None,
+        labels: [
+            ("commentary", "Commentary"),
+            ("estimate", "Estimate"),
+            ("host", "Host"),
+            ("runners", "Runners"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect(),
+    }
+}
+
+/// Load every `<locale>.toml` in `dir`. A missing directory leaves only the built-in English.
+pub fn load_translations(dir: &Path) -> anyhow::Result<()> {
+    let mut tables = HashMap::new();
+
+    if dir.is_dir() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let table: LocaleTable = toml::from_str(&std::fs::read_to_string(&path)?)
+                .map_err(|e| anyhow!("Failed to parse {}: {}", path.display(), e))?;
+            log::info!("Loaded {} translations", name);
+            tables.insert(name.to_lowercase(), table);
+        }
+    }
+
+    let mut default = default_table();
+    if let Some(en) = tables.remove(DEFAULT_LOCALE) {
+        default.duration_style = en.duration_style.or(default.duration_style);
+        default.commentary_prefix = en.commentary_prefix;
+        default.labels.extend(en.labels);
+    }
+    tables.insert(DEFAULT_LOCALE.to_owned(), default);
+
+    if TRANSLATIONS.set(tables).is_err() {
+        log::warn!("Translations were already loaded");
+    }
+    Ok(())
+}
+
+fn translations() -> &'static HashMap<String, LocaleTable> {
+    TRANSLATIONS.get_or_init(|| HashMap::from([(DEFAULT_LOCALE.to_owned(), default_table())]))
+}
+
+/// Text formatting for a locale, falling back to its language and then to English
+pub struct Locale {
+    language: String,
+    chain: Vec<&'static LocaleTable>,
+}
+
+/// The locale for a tag such as `fr-CA`, or English if there is none
+pub fn locale(tag: Option<&str>) -> Locale {
+    let tables = translations();
+    let tag = tag
+        .map(|t| t.trim().replace('_', "-").to_lowercase())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| DEFAULT_LOCALE.to_owned());
+    let language = tag.split('-').next().unwrap_or(DEFAULT_LOCALE).to_owned();
+
+    let mut names = vec![tag.as_str(), language.as_str(), DEFAULT_LOCALE];
+    names.dedup();
+    let chain: Vec<_> = names.into_iter().filter_map(|n| tables.get(n)).collect();
+    if chain.len() == 1 && language != DEFAULT_LOCALE {
+        log::debug!(
+            "No translations for locale {}, using {}",
+            tag,
+            DEFAULT_LOCALE
+        );
+    }
+
+    Locale { language, chain }
+}
+
+impl Locale {
+    /// Every label known to the locale
+    pub fn labels(&self) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        for table in self.chain.iter().rev() {
+            labels.extend(table.labels.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        labels
+    }
+
+    pub fn commentary_prefix(&self) -> Option<&str> {
+        self.chain
+            .iter()
+            .find_map(|t| t.commentary_prefix.as_deref())
+            .filter(|p| !p.is_empty())
+    }
+
+    /// Uppercase text following the locale's rules, such as the dotted capital I of Turkish
+    pub fn uppercase(&self, text: &str) -> String {
+        match self.language.as_str() {
+            "tr" | "az" => text.replace('i', "\u{130}").to_uppercase(),
+            _ => text.to_uppercase(),
+        }
+    }
+
+    /// Format a duration in seconds, such as an estimate
+    pub fn format_duration(&self, seconds: i64) -> String {
+        let style = self
+            .chain
+            .iter()
+            .find_map(|t| t.duration_style)
+            .unwrap_or(DurationStyle::Clock);
+        let (hours, minutes, seconds) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+        match style {
+            DurationStyle::Clock => format!("{}:{:02}:{:02}", hours, minutes, seconds),
+            DurationStyle::Compact if hours > 0 => format!("{}h{:02}m", hours, minutes),
+            DurationStyle::Compact => format!("{}m", minutes),
+        }
+    }
+}
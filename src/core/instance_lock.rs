@@ -0,0 +1,179 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+/// Name of the lock file in the project folder
+const LOCK_FILE_NAME: &str = "automarathon.lock";
+
+/// Who holds the lock of a project folder, stored in the lock file
+#[derive(Serialize, Deserialize, Debug)]
+struct LockOwner {
+    pid: u32,
+    /// Unix seconds
+    started_at: i64,
+    web_port: u16,
+}
+
+impl LockOwner {
+    fn describe(&self) -> String {
+        let started = OffsetDateTime::from_unix_timestamp(self.started_at)
+            .map(|t| t.to_string())
+            .unwrap_or_else(|_| self.started_at.to_string());
+        format!(
+            "PID {}, started {}, dashboard at http://localhost:{}/",
+            self.pid, started, self.web_port
+        )
+    }
+}
+
+/// An exclusive lock on a project folder, so that only one instance uses its database.
+///
+/// The lock file is kept, as removing it would let another instance lock a new file
+/// while one still waits on the old one. When this is dropped the file is emptied,
+/// and the lock is released by closing it.
+pub struct InstanceLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl InstanceLock {
+    /// Lock a project folder, failing with a description of the other instance if it is locked.
+    ///
+    /// With `force`, a lock held by a process that no longer exists is taken over.
+    pub fn acquire(folder: &Path, web_port: u16, force: bool) -> anyhow::Result<Self> {
+        let path = folder.join(LOCK_FILE_NAME);
+        match Self::try_lock(&path, web_port)? {
+            Ok(lock) => Ok(lock),
+            Err(owner) => {
+                let description = owner
+                    .as_ref()
+                    .map(|o| o.describe())
+                    .unwrap_or_else(|| "unknown process".to_owned());
+                let stale = owner.as_ref().is_some_and(|o| !is_process_alive(o.pid));
+                if !force {
+                    return Err(anyhow!(
+                        "AutoMarathon is already running in {} ({}). Stop it first{}",
+                        folder.display(),
+                        description,
+                        if stale {
+                            ", or pass --force as that process no longer exists"
+                        } else {
+                            ""
+                        }
+                    ));
+                }
+                if !stale {
+                    return Err(anyhow!(
+                        "AutoMarathon is already running in {} ({}), --force only takes over \
+                        the lock of a process that no longer exists",
+                        folder.display(),
+                        description
+                    ));
+                }
+
+                log::warn!(
+                    "Taking over the stale project lock of {}, {} is not locked until it is \
+                    released",
+                    description,
+                    folder.display()
+                );
+                Self::take_over(&path, web_port)
+            }
+        }
+    }
+
+    /// Lock the lock file and write this process to it,
+    /// or read the owner of the lock if it is held
+    fn try_lock(path: &Path, web_port: u16) -> anyhow::Result<Result<Self, Option<LockOwner>>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open lock file {}: {}", path.display(), e))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut contents = String::new();
+                file.read_to_string(&mut contents)?;
+                return Ok(Err(serde_json::from_str(&contents).ok()));
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(anyhow!("Failed to lock {}: {}", path.display(), e));
+            }
+        }
+
+        write_owner(&mut file, web_port)?;
+        log::debug!("Locked project folder with {}", path.display());
+        Ok(Ok(Self {
+            path: path.to_owned(),
+            file,
+        }))
+    }
+
+    /// Write this process to a lock file held by a process that no longer exists,
+    /// without locking it
+    fn take_over(path: &Path, web_port: u16) -> anyhow::Result<Self> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| anyhow!("Failed to open lock file {}: {}", path.display(), e))?;
+        write_owner(&mut file, web_port)?;
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+        })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Emptied while still locked, so no other instance can be reading our details
+        if let Err(e) = self.file.set_len(0) {
+            log::warn!("Failed to empty lock file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Replace the contents of a lock file with the details of this process
+fn write_owner(file: &mut File, web_port: u16) -> anyhow::Result<()> {
+    let owner = LockOwner {
+        pid: std::process::id(),
+        started_at: OffsetDateTime::now_utc().unix_timestamp(),
+        web_port,
+    };
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(serde_json::to_string(&owner)?.as_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Whether a process with the given ID exists
+#[cfg(unix)]
+fn is_process_alive(pid: u32) -> bool {
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .output()
+        .map(|out| out.status.success())
+        .unwrap_or(true)
+}
+
+/// Whether a process with the given ID exists
+#[cfg(windows)]
+fn is_process_alive(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout).contains(&pid.to_string()))
+        .unwrap_or(true)
+}
@@ -1,6 +1,18 @@
+pub mod backup;
+pub mod db;
+pub mod db_cache;
 pub mod event;
+pub mod export;
+pub mod health;
+pub mod i18n;
+pub mod instance_lock;
+pub mod project;
 pub mod runner;
+pub mod schedule;
+pub mod settings;
 pub mod stream;
-pub mod db;
+#[cfg(test)]
+pub mod testing;
+pub mod theme;
 pub mod tournament;
-pub mod settings;
+pub mod trace;
@@ -0,0 +1,211 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use super::{db::ProjectStore, event::Event, runner::Runner, stream::StreamState};
+
+/// Version of the project export format, increased on incompatible changes
+pub const PROJECT_FORMAT_VERSION: u32 = 1;
+
+/// A tournament in a project export
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TournamentExport {
+    pub id: i64,
+    pub name: String,
+    pub format: String,
+}
+
+/// A runner in a project export, with their photo encoded as base64
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunnerExport {
+    #[serde(flatten)]
+    pub runner: Runner,
+    pub photo: Option<String>,
+}
+
+impl RunnerExport {
+    /// Decode the exported photo
+    pub fn decode_photo(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(match &self.photo {
+            Some(photo) => Some(STANDARD.decode(photo)?),
+            None => None,
+        })
+    }
+}
+
+/// A whole project, for moving it to another database.
+///
+/// IDs are those of the exporting database, and are remapped on import.
+/// Live TheRun.gg data is not included.
+#[derive(Serialize, Deserialize)]
+pub struct ProjectExport {
+    pub version: u32,
+    pub tournaments: Vec<TournamentExport>,
+    pub runners: Vec<RunnerExport>,
+    pub events: Vec<Event>,
+    pub streams: Vec<StreamState>,
+}
+
+impl std::fmt::Debug for ProjectExport {
+    // Exports are large, so only summarize them in logs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ProjectExport v{} ({} tournaments, {} runners, {} events, {} streams)",
+            self.version,
+            self.tournaments.len(),
+            self.runners.len(),
+            self.events.len(),
+            self.streams.len()
+        )
+    }
+}
+
+/// How an imported project is combined with the existing one
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Add to the existing project, keeping existing entries on name conflicts
+    #[default]
+    Merge,
+    /// Delete the existing project first
+    Replace,
+}
+
+/// The result of importing one kind of entry
+#[derive(Serialize, Default, Debug)]
+pub struct EntityImport {
+    pub imported: usize,
+    /// Entries that were not imported, and why
+    pub conflicts: Vec<String>,
+}
+
+/// The result of a project import
+#[derive(Serialize, Default, Debug)]
+pub struct ImportReport {
+    pub tournaments: EntityImport,
+    pub runners: EntityImport,
+    pub events: EntityImport,
+    pub streams: EntityImport,
+}
+
+/// Export the whole project
+pub async fn export_project(db: &dyn ProjectStore) -> anyhow::Result<ProjectExport> {
+    let mut runners = vec![];
+    for runner in db.get_runners().await? {
+        let runner = db.get_runner(runner.id).await?;
+        runners.push(RunnerExport {
+            photo: runner.photo.as_ref().map(|p| STANDARD.encode(p)),
+            runner,
+        });
+    }
+
+    let mut events = vec![];
+    for event in db.get_event_ids().await? {
+        events.push(db.get_event(event).await?);
+    }
+
+    let mut streams = vec![];
+    for event in db.get_streamed_events().await? {
+        streams.push(db.get_stream(event).await?);
+    }
+
+    Ok(ProjectExport {
+        version: PROJECT_FORMAT_VERSION,
+        tournaments: db.get_tournaments().await?,
+        runners,
+        events,
+        streams,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        core::{event::EventRequest, stream::StreamRequest, testing::TestActors},
+        integrations::web::assemble_state_update,
+        send_message, Rto,
+    };
+
+    /// The state update sent to clients, without the revision and edit versions,
+    /// which count changes to each project rather than describe it
+    async fn state_payload(actors: &TestActors) -> serde_json::Value {
+        let update = assemble_state_update(actors.db.clone(), &actors.directory)
+            .await
+            .unwrap();
+        let mut payload = serde_json::to_value(update).unwrap();
+        payload.as_object_mut().unwrap().remove("revision");
+        for list in ["events", "streams"] {
+            for entry in payload[list].as_array_mut().unwrap() {
+                entry.as_object_mut().unwrap().remove("version");
+            }
+        }
+        payload
+    }
+
+    /// A project with two runners in a relay that is on stream with a running timer
+    async fn seeded_project() -> TestActors {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let second = actors.add_runner("second").await;
+        let event = actors.add_streamed_event("Relay", &[first, second]).await;
+
+        let start = sqlx::types::time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        send_message!(
+            actors.directory.event_actor,
+            EventRequest,
+            SetStartTime,
+            event,
+            Some(start)
+        )
+        .unwrap();
+        let mut stream = actors.db.get_stream(event).await.unwrap();
+        stream.stream_runners = HashMap::from([(0, first), (1, second)]);
+        stream.active_commentators = "host;guest".to_owned();
+        send_message!(
+            actors.directory.stream_actor,
+            StreamRequest,
+            Update,
+            stream,
+            false
+        )
+        .unwrap();
+        actors
+    }
+
+    #[tokio::test]
+    async fn export_round_trips_through_a_fresh_project() {
+        let source = seeded_project().await;
+        let export = export_project(&*source.db).await.unwrap();
+        // Exports are sent as JSON, so round-trip them through it too
+        let export: ProjectExport =
+            serde_json::from_str(&serde_json::to_string(&export).unwrap()).unwrap();
+
+        let target = TestActors::start().await;
+        let report = target
+            .db
+            .import_project(&export, ImportMode::Replace)
+            .await
+            .unwrap();
+        assert_eq!(report.runners.imported, 2);
+        assert_eq!(report.events.imported, 1);
+        assert_eq!(report.streams.imported, 1);
+
+        assert_eq!(state_payload(&source).await, state_payload(&target).await);
+    }
+
+    #[tokio::test]
+    async fn newer_export_is_rejected() {
+        let source = seeded_project().await;
+        let mut export = export_project(&*source.db).await.unwrap();
+        export.version = PROJECT_FORMAT_VERSION + 1;
+
+        let target = TestActors::start().await;
+        let res = target.db.import_project(&export, ImportMode::Merge).await;
+        assert!(res.is_err());
+        assert!(target.db.get_runners().await.unwrap().is_empty());
+        assert!(target.db.get_event_ids().await.unwrap().is_empty());
+    }
+}
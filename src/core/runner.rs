@@ -1,22 +1,62 @@
 use anyhow::anyhow;
 use futures::StreamExt;
-use std::{process, sync::Arc, time};
-use tokio::{sync::broadcast, time::sleep};
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    process,
+    sync::{Arc, OnceLock},
+    time,
+};
+use tokio::{
+    sync::{broadcast, Semaphore},
+    time::sleep,
+};
+use tracing::Instrument;
 use url::Url;
 
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use sqlx::FromRow;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 
-use crate::{error::Error, integrations::therun::TheRunReturnJson, ActorRef, Rto};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{types::time::OffsetDateTime, FromRow};
 
-use super::db::ProjectDb;
+use crate::{
+    error::Error,
+    integrations::{discord::DiscordCommand, therun::TheRunReturnJson},
+    send_message, send_message_with_timeout, ActorMessage, ActorReceiver, ActorRef, Directory, Rto,
+};
+
+use super::{
+    db::ProjectStore,
+    event::{deserialize_datetime, serialize_datetime},
+    project::{ImportMode, ImportReport, ProjectExport},
+    settings::{AudioMonitorType, ObsHost, Settings},
+    stream::StreamRequest,
+};
 
 pub enum RunnerRequest {
     Create(Runner, Rto<()>),
     Update(Runner, Rto<()>),
-    RefreshStream(i64, Rto<bool>),
-    Delete(i64, Rto<()>),
+    /// Save a runner from the dashboard, keeping the stored values of omitted fields
+    Edit(RunnerUpdate, Rto<()>),
+    /// Reacquire a runner's stream URL, using the quality preferences
+    /// of the given OBS host if provided
+    RefreshStream(i64, Option<String>, Rto<bool>),
+    /// Delete a runner, refusing if they are referenced by events
+    /// or streams unless forced
+    Delete(i64, bool, Rto<()>),
+    /// Import an exported project, updating the TheRun.gg runners to poll
+    ImportProject(Box<ProjectExport>, ImportMode, Rto<ImportReport>),
+    /// Create a one-time self-service link for a runner
+    CreateSelfToken(i64, Rto<RunnerSelfToken>),
+    /// Update a runner's own details through a self-service link, using up the link
+    SelfUpdate(String, RunnerSelfUpdate, Rto<()>),
+    /// The runners joined or left a stream, so whether they are polled on TheRun.gg may change
+    StreamRunnersChanged(Vec<i64>),
+    /// Poll a runner on TheRun.gg or not, regardless of whether they are in a stream
+    SetPolling(i64, bool, Rto<()>),
+    /// List the runners polled on TheRun.gg
+    GetPollStatus(Rto<Vec<TheRunPollStatus>>),
 }
 
 /// Notifies the TheRun.gg poller of a change in runner TheRun.gg status
@@ -25,15 +65,179 @@ enum TheRunAlert {
     RemoveRunner(Runner),
 }
 
+/// A runner polled on TheRun.gg, as listed by `/therun/status`
+#[derive(Serialize, Debug)]
+pub struct TheRunPollStatus {
+    pub runner_id: i64,
+    pub therun: String,
+    /// Whether the runner is polled because of a manual override rather than being in a stream
+    pub manual: bool,
+    pub connected: bool,
+    #[serde(serialize_with = "serialize_datetime")]
+    pub last_message: Option<OffsetDateTime>,
+    pub reconnects: u32,
+}
+
+/// Decides which runners are polled on TheRun.gg.
+///
+/// Runners are polled while they are in a stream, unless polling was turned on or off by hand.
+struct TheRunPolling {
+    tx: tokio::sync::mpsc::UnboundedSender<TheRunAlert>,
+    /// Runners being polled, as they were when polling started
+    polled: HashMap<i64, Runner>,
+    /// Manual overrides of whether a runner is polled
+    overrides: HashMap<i64, bool>,
+}
+
+impl TheRunPolling {
+    fn new(tx: tokio::sync::mpsc::UnboundedSender<TheRunAlert>) -> Self {
+        Self {
+            tx,
+            polled: HashMap::new(),
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Start or stop polling a runner to match their streams, username and override
+    async fn sync(&mut self, db: &dyn ProjectStore, runner: i64) -> anyhow::Result<()> {
+        let Ok(info) = db.get_runner(runner).await else {
+            self.stop(runner);
+            return Ok(());
+        };
+
+        let username = info.get_therun_username();
+        let wanted = !username.is_empty()
+            && match self.overrides.get(&runner) {
+                Some(enabled) => *enabled,
+                None => !db.get_streams_for_runner(runner).await?.is_empty(),
+            };
+
+        match self.polled.get(&runner) {
+            Some(polled) if wanted && polled.get_therun_username() == username => {}
+            Some(_) => {
+                self.stop(runner);
+                if wanted {
+                    self.start(info);
+                }
+            }
+            None if wanted => self.start(info),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn start(&mut self, runner: Runner) {
+        log::debug!("Polling TheRun.gg for {}", runner.name);
+        let _ = self.tx.send(TheRunAlert::AddRunner(runner.clone()));
+        self.polled.insert(runner.id, runner);
+    }
+
+    fn stop(&mut self, runner: i64) {
+        if let Some(runner) = self.polled.remove(&runner) {
+            log::debug!("No longer polling TheRun.gg for {}", runner.name);
+            let _ = self.tx.send(TheRunAlert::RemoveRunner(runner));
+        }
+    }
+
+    async fn set_override(
+        &mut self,
+        db: &dyn ProjectStore,
+        runner: i64,
+        enabled: bool,
+    ) -> anyhow::Result<()> {
+        let info = db.get_runner(runner).await?;
+        if enabled && info.get_therun_username().is_empty() {
+            return Err(Error::InvalidRequest(
+                "runner_id".to_owned(),
+                format!("{} has no TheRun.gg username", info.name),
+            )
+            .into());
+        }
+
+        log::info!(
+            "{} TheRun.gg polling for {} by hand",
+            if enabled { "Enabling" } else { "Disabling" },
+            info.name
+        );
+        self.overrides.insert(runner, enabled);
+        self.sync(db, runner).await
+    }
+
+    fn status(&self, directory: &Directory) -> Vec<TheRunPollStatus> {
+        let mut status: Vec<_> = self
+            .polled
+            .values()
+            .map(|runner| {
+                let health = directory
+                    .health
+                    .get_therun_monitor(runner.id)
+                    .unwrap_or_default();
+                TheRunPollStatus {
+                    runner_id: runner.id,
+                    therun: runner.get_therun_username(),
+                    manual: self.overrides.contains_key(&runner.id),
+                    connected: health.connected,
+                    last_message: health.last_message,
+                    reconnects: health.reconnects,
+                }
+            })
+            .collect();
+        status.sort_by_key(|s| s.runner_id);
+        status
+    }
+}
+
 /// A list of TheRun.gg users who are to be polled
 type LiveRunners = Arc<tokio::sync::Mutex<Vec<String>>>;
 
+/// Delay before reconnecting a TheRun.gg websocket after the first failure
+const THERUN_MIN_BACKOFF: time::Duration = time::Duration::from_secs(30);
+/// Maximum delay between TheRun.gg websocket reconnection attempts
+const THERUN_MAX_BACKOFF: time::Duration = time::Duration::from_secs(600);
+/// Maximum number of TheRun.gg websocket connection attempts in flight at once
+const THERUN_MAX_CONCURRENT_CONNECTS: usize = 4;
+
+/// Default `stream_url_ttl_minutes`, as Twitch playlist URLs stop working after a while
+pub const DEFAULT_STREAM_URL_TTL_MINUTES: u64 = 20;
+/// Stream URLs of runners in a stream are resolved again once they expire within this margin
+const STREAM_URL_REFRESH_MARGIN: time::Duration = time::Duration::from_secs(5 * 60);
+/// Interval between checks for stream URLs that are about to expire
+const STREAM_URL_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(60);
+/// How long resolving a stream URL may take, as streamlink can be slow
+pub const STREAM_URL_REFRESH_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+/// Default `self_service_token_minutes`
+pub const DEFAULT_SELF_SERVICE_TOKEN_MINUTES: u64 = 60;
+
+/// How long a resolved stream URL is used for
+fn get_stream_url_ttl(settings: &Settings) -> time::Duration {
+    time::Duration::from_secs(
+        settings
+            .stream_url_ttl_minutes
+            .unwrap_or(DEFAULT_STREAM_URL_TTL_MINUTES)
+            * 60,
+    )
+}
+
+/// Randomize a backoff delay to between half and all of its length,
+/// so that runners that failed together do not retry together
+fn with_jitter(delay: time::Duration, runner: i64) -> time::Duration {
+    let nanos = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let seed = (nanos as u64) ^ (runner as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let fraction = (seed % 1000) as f64 / 1000.0;
+    delay.mul_f64(0.5 + fraction * 0.5)
+}
+
 /// Worker to manage TheRun.gg connections
 async fn therun_poller(
-    db: Arc<ProjectDb>,
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
     mut therun_rx: tokio::sync::mpsc::UnboundedReceiver<TheRunAlert>,
 ) -> anyhow::Result<()> {
     let live_runners = LiveRunners::default();
+    let connect_limiter = Arc::new(Semaphore::new(THERUN_MAX_CONCURRENT_CONNECTS));
     let (death_tx, _) = broadcast::channel(16);
     while let Some(alert) = therun_rx.recv().await {
         match alert {
@@ -41,9 +245,11 @@ async fn therun_poller(
                 live_runners.lock().await.push(runner.get_therun_username());
                 tokio::spawn(create_therun_websocket_monitor(
                     db.clone(),
+                    directory.clone(),
                     runner.id,
                     runner.get_therun_username(),
                     live_runners.clone(),
+                    connect_limiter.clone(),
                     death_tx.clone(),
                 ));
             }
@@ -61,60 +267,125 @@ async fn therun_poller(
 }
 
 /// Creates a player info websocket, restarting it on failure.
+///
+/// Reconnects back off exponentially, resetting once the websocket has delivered data.
 async fn create_therun_websocket_monitor(
-    db: Arc<ProjectDb>,
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
     runner: i64,
     therun: String,
     runners: LiveRunners,
+    connect_limiter: Arc<Semaphore>,
     death_monitor: broadcast::Sender<String>,
 ) -> Result<(), anyhow::Error> {
+    let mut backoff = THERUN_MIN_BACKOFF;
+    // Removals are watched for here as well, so that this monitor stops
+    // even if the runner is polled again before it would reconnect
+    let mut removals = death_monitor.subscribe();
     loop {
         let res = tokio::spawn(run_runner_websocket(
             db.clone(),
+            directory.clone(),
             runner,
             therun.clone(),
+            connect_limiter.clone(),
             death_monitor.subscribe(),
         ))
         .await;
+        directory.health.set_therun_connected(runner, false);
 
-        if runners.lock().await.contains(&therun) {
+        if !take_removal(&mut removals, &therun) && runners.lock().await.contains(&therun) {
+            if matches!(res, Ok(Ok(true))) {
+                backoff = THERUN_MIN_BACKOFF;
+            }
+
+            let delay = with_jitter(backoff, runner);
             match res {
-                Ok(_) => log::warn!(
-                    "TheRun.gg WebSocket closed for {} ({}), reattempting in 30 seconds...",
+                Ok(Ok(_)) => log::warn!(
+                    "TheRun.gg WebSocket closed for {} ({}), reattempting in {} seconds...",
                     runner,
-                    therun
+                    therun,
+                    delay.as_secs()
+                ),
+                Ok(Err(error)) => log::warn!(
+                    "TheRun.gg WebSocket closed for {} ({}, {}), reattempting in {} seconds...",
+                    runner,
+                    therun,
+                    error,
+                    delay.as_secs()
                 ),
                 Err(error) => log::warn!(
-                    "TheRun.gg WebSocket closed for {} ({}, {}), reattempting in 30 seconds...",
+                    "TheRun.gg WebSocket closed for {} ({}, {}), reattempting in {} seconds...",
                     runner,
                     therun,
-                    error.to_string()
+                    error,
+                    delay.as_secs()
                 ),
             }
-            sleep(time::Duration::from_secs(30)).await;
-        } else {
-            log::info!("TheRun.gg WebSocket closed for {} ({})", runner, therun);
-            return Ok(());
+            let removed = tokio::select! {
+                _ = sleep(delay) => false,
+                _ = wait_for_removal(&mut removals, &therun) => true,
+            };
+            if !removed {
+                backoff = (backoff * 2).min(THERUN_MAX_BACKOFF);
+                directory.health.record_therun_reconnect(runner);
+                continue;
+            }
+        }
+
+        log::info!("TheRun.gg WebSocket closed for {} ({})", runner, therun);
+        directory.health.remove_therun(runner);
+        return Ok(());
+    }
+}
+
+/// Whether a TheRun.gg user was removed from polling since the removals were last read
+fn take_removal(removals: &mut broadcast::Receiver<String>, therun: &str) -> bool {
+    let mut removed = false;
+    loop {
+        match removals.try_recv() {
+            Ok(name) => removed |= name == therun,
+            Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+            Err(_) => return removed,
         }
     }
 }
 
-/// Creates a websocket for the provided runner.
+/// Wait until a TheRun.gg user is removed from polling
+async fn wait_for_removal(removals: &mut broadcast::Receiver<String>, therun: &str) {
+    loop {
+        match removals.recv().await {
+            Ok(name) if name == therun => return,
+            Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+            _ => {}
+        }
+    }
+}
+
+/// Creates a websocket for the provided runner, returning whether any data was received.
 ///
 /// This function will occasionally completely bypass the return or panic when failing,
 /// so it is restarted by ```create_player_websocket```.
 async fn run_runner_websocket(
-    db: Arc<ProjectDb>,
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
     runner: i64,
     therun: String,
+    connect_limiter: Arc<Semaphore>,
     mut death_monitor: broadcast::Receiver<String>,
-) -> Result<(), anyhow::Error> {
-    let (mut stream, _) = tokio_tungstenite::connect_async(
-        Url::parse(&format!("wss://ws.therun.gg/?username={}", therun)).unwrap(),
-    )
-    .await?;
+) -> Result<bool, anyhow::Error> {
+    let (mut stream, _) = {
+        let _permit = connect_limiter.acquire().await?;
+        tokio_tungstenite::connect_async(
+            Url::parse(&format!("wss://ws.therun.gg/?username={}", therun)).unwrap(),
+        )
+        .await?
+    };
 
     log::info!("TheRun.gg WebSocket open for {} ({})", runner, therun);
+    directory.health.set_therun_connected(runner, true);
+
+    let mut received = false;
 
     loop {
         tokio::select! {
@@ -122,7 +393,7 @@ async fn run_runner_websocket(
                 if let Ok(kill_name) = kill_name {
                     if kill_name == therun {
                         log::debug!("Killing TheRun.gg websocket for {} ({})", runner, therun);
-                        return Ok(());
+                        return Ok(received);
                     }
                 }
             }
@@ -134,19 +405,108 @@ async fn run_runner_websocket(
                         ) {
                             Ok(stats) => {
                                 log::debug!("Received TheRun.gg data for {}", runner);
+                                received = true;
+                                directory.health.record_therun_message(runner);
+
+                                let reset = match db.get_runner_run_data(runner).await {
+                                    Ok(previous) => stats.run.reset_reason(&previous),
+                                    Err(_) => None,
+                                };
+
+                                // Keep showing the reset until the next run has progressed
+                                if reset.is_none()
+                                    && stats.run.is_at_start()
+                                    && db.get_run_resets().contains_key(&runner)
+                                {
+                                    continue;
+                                }
 
-                                match db.set_runner_run_data(runner, &stats.run).await {
-                                    Ok(_) => {}
+                                // TheRun.gg keeps reporting the splits of a reset run,
+                                // so only store the new run once it has progressed
+                                let result = match reset {
+                                    Some(reason) => {
+                                        log::debug!("Runner {} reset their run: {}", runner, reason);
+                                        if stats.run.is_at_start() {
+                                            db.reset_runner_run(runner).await
+                                        } else {
+                                            db.set_runner_run_data(runner, &stats.run).await
+                                        }
+                                    }
+                                    None => db.set_runner_run_data(runner, &stats.run).await,
+                                };
+
+                                match result {
+                                    Ok(_) => directory.stream_actor.send(StreamRequest::RunUpdated(runner)),
                                     Err(e) => log::error!("Failed to update runner {}'s run data: {}", runner, e),
                                 };
                             }
                             Err(err) => {
-                                log::warn!("Failed to parse {} endpoint: {}", runner, err.to_string());
+                                log::warn!("Failed to parse {} endpoint: {}", runner, err);
                             }
                         };
                     }
                     Some(Err(err)) => log::error!("{}", err.to_string().replace("\\\"", "\"")),
-                    None => return Ok(()),
+                    None => return Ok(received),
+                }
+            }
+        }
+    }
+}
+
+/// Resolve the stream URLs of runners in saved streams again before they expire,
+/// reloading the streams whose URLs changed
+async fn run_stream_url_refresher(db: Arc<dyn ProjectStore>, directory: Directory) {
+    let mut interval = tokio::time::interval(STREAM_URL_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let events = match db.get_streamed_events().await {
+            Ok(events) => events,
+            Err(e) => {
+                log::warn!("Failed to list streams for stream URL refreshes: {}", e);
+                continue;
+            }
+        };
+
+        for event in events {
+            let Ok(stream) = db.get_stream(event).await else {
+                continue;
+            };
+
+            let mut changed = false;
+            for runner in stream.stream_runners.values() {
+                let Ok(info) = db.get_runner(*runner).await else {
+                    continue;
+                };
+                if !info.stream_url_expires_within(STREAM_URL_REFRESH_MARGIN) {
+                    continue;
+                }
+
+                log::debug!("Stream URL of {} is about to expire, refreshing", info.name);
+                let host = Some(stream.obs_host.clone());
+                match send_message_with_timeout!(
+                    STREAM_URL_REFRESH_TIMEOUT,
+                    directory.runner_actor,
+                    RunnerRequest,
+                    RefreshStream,
+                    *runner,
+                    host
+                ) {
+                    Ok(runner_changed) => changed |= runner_changed,
+                    Err(e) => {
+                        log::warn!("Failed to refresh the stream URL of {}: {}", info.name, e)
+                    }
+                }
+            }
+
+            if changed {
+                if let Err(e) = send_message!(directory.stream_actor, StreamRequest, Reload, event)
+                {
+                    log::warn!(
+                        "Failed to reload stream {} after a URL refresh: {}",
+                        event,
+                        e
+                    );
                 }
             }
         }
@@ -154,91 +514,124 @@ async fn run_runner_websocket(
 }
 
 pub async fn run_runner_actor(
-    db: Arc<ProjectDb>,
-    mut rx: tokio::sync::mpsc::UnboundedReceiver<RunnerRequest>,
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    mut rx: ActorReceiver<RunnerRequest>,
+    directory: Directory,
 ) -> anyhow::Result<()> {
     let (therun_tx, therun_rx) = tokio::sync::mpsc::unbounded_channel::<TheRunAlert>();
-    tokio::spawn(therun_poller(db.clone(), therun_rx));
+    let mut polling = TheRunPolling::new(therun_tx);
+    tokio::spawn(therun_poller(db.clone(), directory.clone(), therun_rx));
 
-    // Add all existing runners to TheRun.gg poller.
+    tokio::spawn(run_stream_url_refresher(db.clone(), directory.clone()));
+
+    // Poll the runners who are already in a stream
     for runner in db.get_runners().await? {
-        if !runner.get_therun_username().is_empty() {
-            let _ = therun_tx.send(TheRunAlert::AddRunner(runner.clone()));
-        }
+        polling.sync(&*db, runner.id).await?;
     }
 
-    while let Some(msg) = rx.recv().await {
-        match msg {
-            RunnerRequest::Create(mut runner, rto) => {
-                log::info!("Creating runner {}", runner.name);
-                match db.add_runner(&mut runner).await {
-                    Ok(_) => {
-                        if !runner.get_therun_username().is_empty() {
-                            let _ = therun_tx.send(TheRunAlert::AddRunner(runner.clone()));
+    while let Some((msg, span)) = rx.recv().await {
+        async {
+            match msg {
+                RunnerRequest::Create(mut runner, rto) => {
+                    log::info!("Creating runner {}", runner.name);
+                    rto.reply(db.add_runner(&mut runner).await)
+                }
+                RunnerRequest::Update(runner, rto) => {
+                    rto.reply(update_runner(&*db, &mut polling, &runner).await)
+                }
+                RunnerRequest::Edit(update, rto) => match db.get_runner(update.runner.id).await {
+                    Ok(stored) => match update.merge(&stored) {
+                        Ok(runner) => rto.reply(update_runner(&*db, &mut polling, &runner).await),
+                        Err(e) => rto.reply(Err(e)),
+                    },
+                    Err(e) => rto.reply(Err(e)),
+                },
+                RunnerRequest::CreateSelfToken(runner, rto) => {
+                    rto.reply(create_self_token(&*db, &settings, runner).await)
+                }
+                RunnerRequest::SelfUpdate(token, update, rto) => {
+                    let res =
+                        self_update_runner(&*db, &directory, &mut polling, &token, update).await;
+                    rto.reply(res)
+                }
+                RunnerRequest::StreamRunnersChanged(runners) => {
+                    for runner in runners {
+                        if let Err(e) = polling.sync(&*db, runner).await {
+                            log::warn!("Failed to update TheRun.gg polling of {}: {}", runner, e);
                         }
-                        rto.reply(Ok(()));
                     }
-                    Err(e) => rto.reply(Err(e)),
                 }
-            }
-            RunnerRequest::Update(runner, rto) => {
-                let old_runner = db.get_runner(runner.id).await;
-                match old_runner {
-                    Ok(old_runner) => {
-                        // Check for changes in TheRun.gg username
-                        if old_runner.get_therun_username() != runner.get_therun_username() {
-                            if !old_runner.get_therun_username().is_empty() {
-                                let _ =
-                                    therun_tx.send(TheRunAlert::RemoveRunner(old_runner.clone()));
-                            }
-
-                            if !runner.get_therun_username().is_empty() {
-                                let _ = therun_tx.send(TheRunAlert::AddRunner(runner.clone()));
+                RunnerRequest::SetPolling(runner, enabled, rto) => {
+                    rto.reply(polling.set_override(&*db, runner, enabled).await)
+                }
+                RunnerRequest::GetPollStatus(rto) => rto.reply(Ok(polling.status(&directory))),
+                RunnerRequest::RefreshStream(runner, host, rto) => {
+                    match db.get_runner(runner).await {
+                        Ok(mut runner) => {
+                            let host = host.and_then(|h| settings.obs_hosts.get(&h));
+                            match runner
+                                .find_and_save_stream(&*db, host, get_stream_url_ttl(&settings))
+                                .await
+                            {
+                                Ok(changed) => rto.reply(Ok(changed)),
+                                Err(e) => rto.reply(Err(e)),
                             }
                         }
-
-                        match db.update_runner(&runner).await {
-                            Ok(_) => rto.reply(Ok(())),
-                            Err(e) => {
-                                log::error!("Failed to update runner {} ({}): {}", runner.name, runner.id, e);
-                                rto.reply(Err(e))
-                            },
-                        }
+                        Err(_) => rto.reply(Err(anyhow!("Runner {} not found", runner))),
                     }
-                    Err(e) => rto.reply(Err(e)),
                 }
-            }
-            RunnerRequest::RefreshStream(runner, rto) => match db.get_runner(runner).await {
-                Ok(mut runner) => match runner.find_and_save_stream(&db).await {
-                    Ok(changed) => rto.reply(Ok(changed)),
+                RunnerRequest::Delete(id, force, rto) => match db.get_runner_dependencies(id).await
+                {
                     Err(e) => rto.reply(Err(e)),
+                    Ok(deps) => {
+                        let runner_name = db
+                            .get_name_for_runner(id)
+                            .await
+                            .expect("Failed to get runner name");
+
+                        if deps.is_empty() || force {
+                            log::info!("Deleting runner {}", runner_name);
+                            if !deps.is_empty() {
+                                log::warn!("Force deleting runner {} from {}", runner_name, deps);
+                            }
+                            polling.stop(id);
+                            polling.overrides.remove(&id);
+                            rto.reply(db.delete_runner(id).await)
+                        } else {
+                            rto.reply(Err(anyhow!(
+                                "Cannot delete runner {} as they are referenced by {}. \
+                                Archive the runner instead, or force the deletion.",
+                                runner_name,
+                                deps
+                            )))
+                        }
+                    }
                 },
-                Err(_) => rto.reply(Err(anyhow!("Runner {} not found", runner))),
-            },
-            RunnerRequest::Delete(id, rto) => match db.get_events_for_runner(id).await {
-                Err(e) => rto.reply(Err(e)),
-                Ok(ev) => {
-                    let runner_name = db
-                        .get_name_for_runner(id)
-                        .await
-                        .expect("Failed to get runner name");
-
-                    log::info!("Deleting runner {}", runner_name);
-                    if ev.is_empty() {
-                        let runner = db.get_runner(id).await?;
-                        let _ = therun_tx.send(TheRunAlert::RemoveRunner(runner));
-                        rto.reply(db.delete_runner(id).await)
-                    } else {
-                        rto.reply(Err(anyhow!(
-                            "Cannot delete runner {} as they are associated with
-                             the following events: {:?}",
-                            runner_name,
-                            ev.to_vec()
-                        )))
+                RunnerRequest::ImportProject(project, mode, rto) => {
+                    match db.import_project(&project, mode).await {
+                        Ok(report) => {
+                            // Replacing deletes every runner, so stop polling all of them
+                            if mode == ImportMode::Replace {
+                                let polled: Vec<i64> = polling.polled.keys().copied().collect();
+                                for runner in polled {
+                                    polling.stop(runner);
+                                }
+                                polling.overrides.clear();
+                            }
+                            for runner in db.get_runners().await? {
+                                polling.sync(&*db, runner.id).await?;
+                            }
+                            rto.reply(Ok(report))
+                        }
+                        Err(e) => rto.reply(Err(e)),
                     }
                 }
-            },
+            }
+            Ok::<(), anyhow::Error>(())
         }
+        .instrument(span)
+        .await?;
     }
 
     Ok(())
@@ -246,6 +639,223 @@ pub async fn run_runner_actor(
 
 pub type RunnerActor = ActorRef<RunnerRequest>;
 
+impl ActorMessage for RunnerRequest {
+    fn label(&self) -> &'static str {
+        match self {
+            RunnerRequest::Create(..) => "Create",
+            RunnerRequest::Update(..) => "Update",
+            RunnerRequest::Edit(..) => "Edit",
+            RunnerRequest::RefreshStream(..) => "RefreshStream",
+            RunnerRequest::Delete(..) => "Delete",
+            RunnerRequest::ImportProject(..) => "ImportProject",
+            RunnerRequest::CreateSelfToken(..) => "CreateSelfToken",
+            RunnerRequest::SelfUpdate(..) => "SelfUpdate",
+            RunnerRequest::StreamRunnersChanged(..) => "StreamRunnersChanged",
+            RunnerRequest::SetPolling(..) => "SetPolling",
+            RunnerRequest::GetPollStatus(..) => "GetPollStatus",
+        }
+    }
+}
+
+/// A runner sent by the dashboard, which may leave out fields it does not edit
+#[derive(Debug, Clone)]
+pub struct RunnerUpdate {
+    pub runner: Runner,
+    /// The fields as sent, laid over the stored runner when saving
+    fields: Map<String, Value>,
+}
+
+impl<'de> Deserialize<'de> for RunnerUpdate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = Map::deserialize(deserializer)?;
+        let runner =
+            Runner::deserialize(Value::Object(fields.clone())).map_err(serde::de::Error::custom)?;
+        Ok(RunnerUpdate { runner, fields })
+    }
+}
+
+impl RunnerUpdate {
+    /// The runner to save, with the fields that were not sent taken from the stored runner.
+    ///
+    /// A sent null still clears a field, only a missing one is kept.
+    fn merge(self, stored: &Runner) -> anyhow::Result<Runner> {
+        let mut merged = serde_json::to_value(stored)?;
+        if let Some(merged) = merged.as_object_mut() {
+            merged.extend(self.fields);
+        }
+        let mut runner: Runner = serde_json::from_value(merged)?;
+        runner.photo = stored.photo.clone();
+        Ok(runner)
+    }
+}
+
+/// Save a runner, updating the TheRun.gg runners to poll if their username changed
+async fn update_runner(
+    db: &dyn ProjectStore,
+    polling: &mut TheRunPolling,
+    runner: &Runner,
+) -> anyhow::Result<()> {
+    db.update_runner(runner).await.inspect_err(|e| {
+        log::error!(
+            "Failed to update runner {} ({}): {}",
+            runner.name,
+            runner.id,
+            e
+        )
+    })?;
+
+    // Poll the new TheRun.gg username if it changed
+    polling.sync(db, runner.id).await
+}
+
+/// A one-time link for a runner to update their own stream details
+#[derive(Debug, FromRow, Serialize, Clone)]
+pub struct RunnerSelfToken {
+    pub token: String,
+    pub runner: i64,
+    #[serde(serialize_with = "serialize_datetime")]
+    pub expires_at: Option<OffsetDateTime>,
+    /// When the link was used, after which it no longer works
+    #[serde(serialize_with = "serialize_datetime")]
+    pub used_at: Option<OffsetDateTime>,
+}
+
+impl RunnerSelfToken {
+    /// Whether the link can still be used
+    pub fn is_pending(&self) -> bool {
+        self.used_at.is_none()
+            && self
+                .expires_at
+                .is_some_and(|expiry| expiry > OffsetDateTime::now_utc())
+    }
+}
+
+/// The details a runner may change through a self-service link
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunnerSelfUpdate {
+    pub stream: Option<String>,
+    /// Platform of `stream`, unchanged if None
+    #[serde(default)]
+    pub stream_kind: Option<StreamKind>,
+    pub therun: Option<String>,
+}
+
+async fn create_self_token(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    runner: i64,
+) -> anyhow::Result<RunnerSelfToken> {
+    let name = db.get_runner(runner).await?.name;
+    let minutes = settings
+        .self_service_token_minutes
+        .unwrap_or(DEFAULT_SELF_SERVICE_TOKEN_MINUTES);
+
+    let token = RunnerSelfToken {
+        token: URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>()),
+        runner,
+        expires_at: Some(OffsetDateTime::now_utc() + time::Duration::from_secs(minutes * 60)),
+        used_at: None,
+    };
+    db.add_runner_self_token(&token).await?;
+    log::info!(
+        "Created self-service link for {}, pending for {} minutes",
+        name,
+        minutes
+    );
+    Ok(token)
+}
+
+/// Apply a runner's update from a self-service link and use up the link
+async fn self_update_runner(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    polling: &mut TheRunPolling,
+    token: &str,
+    update: RunnerSelfUpdate,
+) -> anyhow::Result<()> {
+    let token = db
+        .get_runner_self_token(token)
+        .await?
+        .filter(|t| t.is_pending())
+        .ok_or_else(|| {
+            Error::InvalidRequest(
+                "link".to_owned(),
+                "it has expired or was already used".to_owned(),
+            )
+        })?;
+
+    let non_empty =
+        |value: Option<String>| value.map(|v| v.trim().to_owned()).filter(|v| !v.is_empty());
+
+    let mut runner = db.get_runner(token.runner).await?;
+    let stream_changed = non_empty(update.stream.clone()) != runner.stream;
+    let stream_changed =
+        stream_changed || update.stream_kind.is_some_and(|k| k != runner.stream_kind);
+    runner.stream = non_empty(update.stream);
+    runner.stream_kind = update.stream_kind.unwrap_or(runner.stream_kind);
+    runner.therun = non_empty(update.therun);
+    update_runner(db, polling, &runner).await?;
+    db.use_runner_self_token(&token.token).await?;
+
+    log::info!(
+        "{} updated their details through a self-service link, stream {:?}, TheRun.gg {:?}",
+        runner.name,
+        runner.stream,
+        runner.therun
+    );
+    directory.discord_actor.send(DiscordCommand::Notify(format!(
+        "{} updated their stream to {} and their TheRun.gg username to {}",
+        runner.name,
+        runner.stream.as_deref().unwrap_or(&runner.name),
+        runner.get_therun_username()
+    )));
+
+    if stream_changed {
+        // Resolved after replying, since this request is handled by the runner actor itself
+        let directory = directory.clone();
+        let streams = db.get_streams_for_runner(runner.id).await?;
+        tokio::spawn(async move {
+            let host = None;
+            match send_message_with_timeout!(
+                STREAM_URL_REFRESH_TIMEOUT,
+                directory.runner_actor,
+                RunnerRequest,
+                RefreshStream,
+                runner.id,
+                host
+            ) {
+                Ok(true) => {
+                    for event in streams {
+                        if let Err(e) =
+                            send_message!(directory.stream_actor, StreamRequest, Reload, event)
+                        {
+                            log::warn!("Failed to reload stream {}: {}", event, e);
+                        }
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => log::warn!("Failed to resolve the new stream of {}: {}", runner.name, e),
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Platform a runner streams on, which decides how their stream link is resolved
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum StreamKind {
+    /// Twitch channel, resolved with streamlink
+    #[default]
+    Twitch,
+    /// YouTube channel or live video, resolved with streamlink
+    Youtube,
+    /// URL played as-is without streamlink, such as an RTMP or SRT ingest
+    Direct,
+}
+
 #[derive(PartialEq, Eq, Debug, FromRow, Clone, Serialize, Deserialize)]
 pub struct Runner {
     /// Unique runner ID
@@ -257,11 +867,15 @@ pub struct Runner {
     /// Player's stream link
     /// If the link is an https:// address,
     /// it is used as-is, otherwise it is treated
-    /// as a Twitch handle.
+    /// as a Twitch or YouTube handle depending on `stream_kind`.
     ///
     /// This is assumed to be the same as the name if None
     pub stream: Option<String>,
 
+    /// Platform of `stream`
+    #[serde(default)]
+    pub stream_kind: StreamKind,
+
     /// Player's TheRun.gg username.
     ///
     /// This is assumed to be the same as the name if None
@@ -270,6 +884,18 @@ pub struct Runner {
     /// A cache of this runner's latest valid m3u8 link
     pub cached_stream_url: Option<String>,
 
+    /// When `cached_stream_url` was resolved
+    #[serde(default)]
+    #[serde(serialize_with = "serialize_datetime")]
+    #[serde(deserialize_with = "deserialize_datetime")]
+    pub stream_url_fetched_at: Option<OffsetDateTime>,
+
+    /// When `cached_stream_url` should no longer be used
+    #[serde(default)]
+    #[serde(serialize_with = "serialize_datetime")]
+    #[serde(deserialize_with = "deserialize_datetime")]
+    pub stream_url_expires_at: Option<OffsetDateTime>,
+
     /// Player's location in ISO 3166-2
     pub location: Option<String>,
 
@@ -280,47 +906,233 @@ pub struct Runner {
     /// User volume in percent
     pub volume_percent: u32,
 
+    /// Override for the OBS host's maximum stream height for this runner
+    pub max_stream_height: Option<u32>,
+
+    /// Whether this runner is hidden from listings while keeping their history
+    #[serde(default)]
+    pub archived: bool,
+
+    /// Override for the OBS host's audio monitoring of this runner's source
+    #[serde(default)]
+    pub monitor_type: Option<AudioMonitorType>,
+
+    /// ID of the runner's Discord user, used to recognize them as a commentator.
+    /// Kept as a string since snowflakes do not fit in a JavaScript number
+    #[serde(default)]
+    pub discord_id: Option<String>,
+
+    /// Whether the runner asked not to be sent reminders before their events
+    #[serde(default)]
+    pub discord_reminder_opt_out: bool,
+
+    #[sqlx(skip)]
+    pub nicks: Vec<String>,
+
+    /// Social media handles by platform, such as `twitter` or `bluesky`, without the `@`
+    #[serde(default)]
     #[sqlx(skip)]
+    pub socials: BTreeMap<String, String>,
+}
+
+/// Events and streams that reference a runner
+#[derive(Debug, Serialize)]
+pub struct RunnerDependencies {
+    /// Number of events the runner is participating in
+    pub events: usize,
+    /// Number of streams the runner is in view for
+    pub streams: usize,
+    /// Number of streams the runner commentates and events they host the commentary of
+    pub commentators: usize,
+}
+
+impl RunnerDependencies {
+    pub fn is_empty(&self) -> bool {
+        self.events == 0 && self.streams == 0 && self.commentators == 0
+    }
+}
+
+impl std::fmt::Display for RunnerDependencies {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} event(s), {} stream(s) and {} commentator reference(s)",
+            self.events, self.streams, self.commentators
+        )
+    }
+}
+
+/// Summary of a runner's live TheRun.gg run
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub current_split_name: String,
+    pub current_split_index: i64,
+    pub split_count: usize,
+    pub finished: bool,
+    /// Delta to the current comparison in milliseconds
+    pub delta: Option<f64>,
+    pub best_possible: Option<f64>,
+    pub pb: Option<f64>,
+    pub sob: Option<f64>,
+}
+
+/// Everything known about a runner, for commentators
+#[derive(Debug, Serialize)]
+pub struct RunnerInfo {
+    pub id: i64,
+    pub name: String,
     pub nicks: Vec<String>,
+    pub location: Option<String>,
+    pub socials: BTreeMap<String, String>,
+    /// Link to the runner's stream
+    pub stream: String,
+    /// Whether a stream URL has been acquired for the runner
+    pub stream_url_cached: bool,
+    pub therun: String,
+    /// The live run, if TheRun.gg has reported one
+    pub run: Option<RunSummary>,
+    /// Names of events the runner is participating in
+    pub events: Vec<String>,
+    /// Names of streamed events the runner is in view for
+    pub in_view: Vec<String>,
+}
+
+impl RunnerInfo {
+    pub async fn load(db: &dyn ProjectStore, runner: i64) -> anyhow::Result<Self> {
+        let runner = db.get_runner(runner).await?;
+        let events = db.get_events_for_runner(runner.id).await?;
+        let in_view = db.get_stream_names_for_runner(runner.id).await?;
+        let run = db
+            .get_runner_run_data(runner.id)
+            .await
+            .ok()
+            .map(|run| RunSummary {
+                finished: run.is_finished(),
+                current_split_name: run.current_split_name,
+                current_split_index: run.current_split_index,
+                split_count: run.splits.len(),
+                delta: run.delta,
+                best_possible: run.best_possible,
+                pb: run.pb,
+                sob: run.sob,
+            });
+
+        Ok(Self {
+            stream: runner.get_stream(),
+            stream_url_cached: runner.cached_stream_url.is_some(),
+            therun: runner.get_therun_username(),
+            id: runner.id,
+            name: runner.name,
+            nicks: runner.nicks,
+            location: runner.location,
+            socials: runner.socials,
+            run,
+            events,
+            in_view,
+        })
+    }
 }
 
+#[allow(dead_code)]
 #[derive(PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub struct FieldDefault {
     pub field: String,
     pub value: String,
 }
 
+/// Largest runner volume in percent, where 100 is the source's unchanged level
+pub const MAX_VOLUME_PERCENT: u32 = 100;
+
 impl Runner {
+    /// The volume multiplier sent to OBS, 1.0 being unity gain
+    pub fn get_volume_mul(&self) -> f32 {
+        self.volume_percent.min(MAX_VOLUME_PERCENT) as f32 / 100.0
+    }
+
     pub fn get_therun_username(&self) -> String {
         self.therun.clone().unwrap_or(self.name.clone())
     }
 
+    /// The social handles to save, with lowercase platforms and handles without a leading `@`
+    pub fn normalized_socials(&self) -> Vec<(String, String)> {
+        self.socials
+            .iter()
+            .filter_map(|(platform, handle)| {
+                let platform = platform.trim().to_lowercase();
+                let handle = handle.trim().trim_start_matches('@').trim();
+                (!platform.is_empty() && !handle.is_empty()).then(|| (platform, handle.to_owned()))
+            })
+            .collect()
+    }
+
+    /// The handle on the first of `platforms` the runner has one on
+    pub fn preferred_social(&self, platforms: &[String]) -> Option<&str> {
+        platforms
+            .iter()
+            .find_map(|p| self.socials.get(&p.to_lowercase()))
+            .map(|h| h.as_str())
+    }
+
+    /// Whether the cached stream URL expires within `margin`.
+    /// URLs without a known expiry are treated as expired.
+    pub fn stream_url_expires_within(&self, margin: time::Duration) -> bool {
+        self.cached_stream_url.is_some()
+            && self
+                .stream_url_expires_at
+                .is_none_or(|expiry| expiry <= OffsetDateTime::now_utc() + margin)
+    }
+
     pub fn get_stream(&self) -> String {
-        match &self.stream {
-            Some(stream) => {
-                if stream.starts_with("https://") || stream.starts_with("http://") {
-                    stream.to_string()
-                } else {
-                    format!("https://twitch.tv/{}", stream)
-                }
+        let stream = self.stream.as_deref().unwrap_or(&self.name);
+        if self.stream_kind == StreamKind::Direct
+            || stream.starts_with("https://")
+            || stream.starts_with("http://")
+        {
+            return stream.to_string();
+        }
+
+        match self.stream_kind {
+            StreamKind::Youtube => {
+                format!(
+                    "https://www.youtube.com/@{}/live",
+                    stream.trim_start_matches('@')
+                )
             }
-            None => format!("https://twitch.tv/{}", self.name),
+            _ => format!("https://twitch.tv/{}", stream),
         }
     }
 
     /// Returns a .m3u8 link corresponding to the current players' stream.
-    pub fn find_stream(&mut self) -> anyhow::Result<bool> {
+    ///
+    /// The rendition is chosen using the quality preferences of `host`,
+    /// with this runner's height cap taking precedence.
+    pub fn find_stream(
+        &mut self,
+        host: Option<&ObsHost>,
+        ttl: time::Duration,
+    ) -> anyhow::Result<bool> {
+        if self.stream_kind == StreamKind::Direct {
+            // Direct URLs are played by VLC as they are
+            let url = self
+                .stream
+                .clone()
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| anyhow!("No direct stream URL is set for {}", self.name))?;
+            log::info!("Using direct stream URL for {}", self.name);
+            return Ok(self.set_stream_url(url, ttl));
+        }
+
         let output = process::Command::new("streamlink")
             .arg("-Q")
             .arg("-j")
-            .arg(&self.get_stream())
+            .arg(self.get_stream())
             .output()
             .map_err(|e| anyhow!("Failed to acquire stream for {}: {:?}", &self.name, e))?;
 
         let json = std::str::from_utf8(output.stdout.as_slice())?;
 
-        let parsed_json: Value =
-            serde_json::from_str(json).expect("Unable to parse streamlink output");
+        let parsed_json: Value = serde_json::from_str(json)
+            .map_err(|e| anyhow!("Unable to parse streamlink output for {}: {}", self.name, e))?;
 
         if parsed_json.get("error").is_some() {
             Err(Error::FailedStreamAcq(
@@ -328,29 +1140,467 @@ impl Runner {
                 parsed_json["error"].to_string(),
             ))?
         } else {
-            let new_url = parsed_json["streams"]["best"]["url"]
-                .to_string()
-                .replace('\"', "");
-            if let Some(old_url) = &self.cached_stream_url {
-                if old_url != &new_url {
-                    self.cached_stream_url = Some(new_url);
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            } else {
-                self.cached_stream_url = Some(new_url);
-                Ok(true)
-            }
+            let streams = parsed_json["streams"]
+                .as_object()
+                .ok_or_else(|| anyhow!("No streams available for {}", self.name))?;
+
+            let max_height = self
+                .max_stream_height
+                .or(host.and_then(|h| h.max_stream_height));
+            let prefer_fps = host.and_then(|h| h.prefer_fps);
+
+            let (quality, reason) =
+                select_stream_quality(streams, self.stream_kind, max_height, prefer_fps)
+                    .ok_or_else(|| anyhow!("No usable stream rendition for {}", self.name))?;
+            log::info!(
+                "Selected {} rendition for {}: {}",
+                quality,
+                self.name,
+                reason
+            );
+
+            let new_url = streams[&quality]["url"]
+                .as_str()
+                .ok_or_else(|| anyhow!("No URL for the {} rendition of {}", quality, self.name))?
+                .to_string();
+            Ok(self.set_stream_url(new_url, ttl))
         }
     }
 
-    async fn find_and_save_stream(&mut self, db: &ProjectDb) -> anyhow::Result<bool> {
-        if self.find_stream()? {
+    /// Cache a resolved stream URL for `ttl`, returning whether it changed
+    fn set_stream_url(&mut self, new_url: String, ttl: time::Duration) -> bool {
+        let now = OffsetDateTime::now_utc();
+        self.stream_url_fetched_at = Some(now);
+        self.stream_url_expires_at = Some(now + ttl);
+        if self.cached_stream_url.as_ref() == Some(&new_url) {
+            false
+        } else {
+            self.cached_stream_url = Some(new_url);
+            true
+        }
+    }
+
+    /// Resolve the stream URL and save it with its new expiry, returning whether it changed
+    async fn find_and_save_stream(
+        &mut self,
+        db: &dyn ProjectStore,
+        host: Option<&ObsHost>,
+        ttl: time::Duration,
+    ) -> anyhow::Result<bool> {
+        let changed = self.find_stream(host, ttl)?;
+        if changed {
             println!("Updating stream url for {}", self.name);
-            db.update_runner(self).await?;
-            return Ok(true);
         }
-        Ok(false)
+        db.update_runner(self).await?;
+        Ok(changed)
+    }
+}
+
+static STREAM_QUALITY_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// Parse a streamlink quality name such as `720p60` into its height and framerate.
+///
+/// YouTube qualities may name their audio track after the video, as in `1080p60+a128k`,
+/// which is ignored. Qualities without an explicit framerate are assumed to be 30fps.
+fn parse_stream_quality(quality: &str, kind: StreamKind) -> Option<(u32, u32)> {
+    let quality = match kind {
+        StreamKind::Youtube => quality.split('+').next()?,
+        _ => quality,
+    };
+    let regex = STREAM_QUALITY_REGEX.get_or_init(|| Regex::new(r"^(\d+)p(\d+)?(_alt)?$").unwrap());
+    let caps = regex.captures(quality)?;
+    let height = caps.get(1)?.as_str().parse().ok()?;
+    let fps = caps
+        .get(2)
+        .map(|f| f.as_str().parse().ok())
+        .unwrap_or(Some(30))?;
+    Some((height, fps))
+}
+
+/// Choose a stream rendition from a streamlink quality map.
+///
+/// The tallest rendition no taller than `max_height` is used, preferring
+/// `prefer_fps` between renditions of the same height. If no rendition fits under
+/// the cap, the smallest one is used. If the map only has generic qualities,
+/// `worst` is used when a cap is set and `best` otherwise.
+///
+/// Returns the chosen quality name and the reason it was chosen.
+fn select_stream_quality(
+    streams: &Map<String, Value>,
+    kind: StreamKind,
+    max_height: Option<u32>,
+    prefer_fps: Option<u32>,
+) -> Option<(String, String)> {
+    let renditions: Vec<(&String, u32, u32)> = streams
+        .keys()
+        .filter_map(|q| parse_stream_quality(q, kind).map(|(h, f)| (q, h, f)))
+        .collect();
+
+    if renditions.is_empty() {
+        let (first, second) = if max_height.is_some() {
+            ("worst", "best")
+        } else {
+            ("best", "worst")
+        };
+
+        return [first, second]
+            .into_iter()
+            .find(|q| streams.contains_key(*q))
+            .map(|q| {
+                (
+                    q.to_string(),
+                    "no renditions with a known resolution".to_string(),
+                )
+            });
+    }
+
+    let fps_rank = |fps: u32| match prefer_fps {
+        Some(prefer) => (fps == prefer, fps <= prefer, fps),
+        None => (false, false, fps),
+    };
+
+    let capped = renditions
+        .iter()
+        .filter(|(_, h, _)| max_height.map(|max| *h <= max).unwrap_or(true))
+        .max_by_key(|(_, h, f)| (*h, fps_rank(*f)));
+
+    match capped {
+        Some((quality, _, _)) => {
+            let mut reason = match max_height {
+                Some(max) => format!("tallest rendition within the {}p cap", max),
+                None => "tallest available rendition".to_string(),
+            };
+            if let Some(fps) = prefer_fps {
+                reason.push_str(&format!(", preferring {}fps", fps));
+            }
+            Some((quality.to_string(), reason))
+        }
+        None => renditions
+            .iter()
+            .min_by_key(|(_, h, f)| (*h, std::cmp::Reverse(fps_rank(*f))))
+            .map(|(quality, _, _)| {
+                (
+                    quality.to_string(),
+                    format!(
+                        "no rendition within the {}p cap, using the smallest available",
+                        max_height.unwrap_or_default()
+                    ),
+                )
+            }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::core::testing::test_runner;
+
+    #[test]
+    fn volume_is_scaled_to_unity_gain() {
+        let mut runner = test_runner(1, "Alice", None);
+        for (percent, mul) in [(0, 0.0), (1, 0.01), (50, 0.5), (100, 1.0)] {
+            runner.volume_percent = percent;
+            assert_eq!(runner.get_volume_mul(), mul, "{}%", percent);
+        }
+    }
+
+    #[test]
+    fn volume_over_the_maximum_is_capped() {
+        let mut runner = test_runner(1, "Alice", None);
+        for percent in [MAX_VOLUME_PERCENT + 1, 250, u32::MAX] {
+            runner.volume_percent = percent;
+            assert_eq!(runner.get_volume_mul(), 1.0, "{}%", percent);
+        }
+    }
+
+    /// Streamlink quality map with the given quality names
+    fn qualities(names: &[&str]) -> Map<String, Value> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), json!({})))
+            .collect()
+    }
+
+    /// Quality names, stream kind, height cap, preferred framerate and the expected choice
+    type QualityCase<'a> = (
+        &'a [&'a str],
+        StreamKind,
+        Option<u32>,
+        Option<u32>,
+        Option<&'a str>,
+    );
+
+    #[test]
+    fn stream_quality_is_selected() {
+        let twitch = [
+            "audio_only",
+            "160p",
+            "360p",
+            "480p",
+            "720p",
+            "720p60",
+            "1080p60",
+            "worst",
+            "best",
+        ];
+        let cases: &[QualityCase] = &[
+            // Tallest rendition without a cap
+            (&twitch, StreamKind::Twitch, None, None, Some("1080p60")),
+            // Tallest rendition under the cap, higher framerate by default
+            (&twitch, StreamKind::Twitch, Some(720), None, Some("720p60")),
+            // Preferred framerate between renditions of the same height
+            (
+                &twitch,
+                StreamKind::Twitch,
+                Some(720),
+                Some(30),
+                Some("720p"),
+            ),
+            // Preferred framerate missing, the closest one below it
+            (
+                &["720p30", "720p50", "720p60"],
+                StreamKind::Twitch,
+                None,
+                Some(55),
+                Some("720p50"),
+            ),
+            // Cap between renditions
+            (&twitch, StreamKind::Twitch, Some(600), None, Some("480p")),
+            // Nothing under the cap, the smallest rendition
+            (
+                &["720p", "1080p60"],
+                StreamKind::Twitch,
+                Some(480),
+                None,
+                Some("720p"),
+            ),
+            // Alternate renditions count like the main one
+            (
+                &["480p_alt", "360p"],
+                StreamKind::Twitch,
+                None,
+                None,
+                Some("480p_alt"),
+            ),
+            // YouTube audio tracks are ignored
+            (
+                &["360p+a128k", "1080p60+a128k", "720p+a128k"],
+                StreamKind::Youtube,
+                Some(720),
+                None,
+                Some("720p+a128k"),
+            ),
+            // Only generic qualities
+            (
+                &["worst", "best"],
+                StreamKind::Twitch,
+                None,
+                None,
+                Some("best"),
+            ),
+            (
+                &["worst", "best"],
+                StreamKind::Twitch,
+                Some(480),
+                None,
+                Some("worst"),
+            ),
+            (&["best"], StreamKind::Twitch, Some(480), None, Some("best")),
+            (&["audio_only"], StreamKind::Twitch, None, None, None),
+            (&[], StreamKind::Twitch, None, None, None),
+        ];
+
+        for (names, kind, max_height, prefer_fps, expected) in cases {
+            let selected =
+                select_stream_quality(&qualities(names), *kind, *max_height, *prefer_fps);
+            assert_eq!(
+                selected.map(|(quality, _)| quality).as_deref(),
+                *expected,
+                "{:?} capped at {:?} preferring {:?}fps",
+                names,
+                max_height,
+                prefer_fps
+            );
+        }
+    }
+
+    #[test]
+    fn stream_quality_reason_names_the_cap() {
+        let streams = qualities(&["360p", "720p60"]);
+        let (_, reason) =
+            select_stream_quality(&streams, StreamKind::Twitch, Some(480), Some(30)).unwrap();
+        assert_eq!(
+            reason,
+            "tallest rendition within the 480p cap, preferring 30fps"
+        );
+
+        let (_, reason) =
+            select_stream_quality(&streams, StreamKind::Twitch, Some(240), None).unwrap();
+        assert_eq!(
+            reason,
+            "no rendition within the 240p cap, using the smallest available"
+        );
+    }
+
+    #[test]
+    fn quality_names_are_read_per_provider() {
+        let cases = [
+            ("720p60", StreamKind::Twitch, Some((720, 60))),
+            ("480p", StreamKind::Twitch, Some((480, 30))),
+            ("1080p60_alt", StreamKind::Twitch, Some((1080, 60))),
+            ("audio_only", StreamKind::Twitch, None),
+            ("best", StreamKind::Twitch, None),
+            // Only YouTube names its audio track after the video
+            ("1080p60+a128k", StreamKind::Twitch, None),
+            ("1080p60+a128k", StreamKind::Youtube, Some((1080, 60))),
+            ("360p+a128k", StreamKind::Youtube, Some((360, 30))),
+            ("720p", StreamKind::Youtube, Some((720, 30))),
+            ("audio_mp4a", StreamKind::Youtube, None),
+            ("a128k+1080p", StreamKind::Youtube, None),
+        ];
+        for (quality, kind, expected) in cases {
+            assert_eq!(
+                parse_stream_quality(quality, kind),
+                expected,
+                "{} on {:?}",
+                quality,
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn youtube_quality_map_is_read() {
+        let youtube = qualities(&[
+            "audio_mp4a",
+            "audio_opus",
+            "144p",
+            "360p",
+            "720p",
+            "720p60+a128k",
+            "1080p60+a128k",
+            "worst",
+            "best",
+        ]);
+        let select = |max_height, prefer_fps| {
+            select_stream_quality(&youtube, StreamKind::Youtube, max_height, prefer_fps)
+                .map(|(quality, _)| quality)
+        };
+        assert_eq!(select(None, None).as_deref(), Some("1080p60+a128k"));
+        assert_eq!(select(Some(720), None).as_deref(), Some("720p60+a128k"));
+        assert_eq!(select(Some(720), Some(30)).as_deref(), Some("720p"));
+        assert_eq!(select(Some(100), None).as_deref(), Some("144p"));
+
+        // Read as a Twitch map, the renditions with audio tracks are not recognized
+        let twitch = select_stream_quality(&youtube, StreamKind::Twitch, None, None);
+        assert_eq!(twitch.map(|(quality, _)| quality).as_deref(), Some("720p"));
+    }
+
+    #[test]
+    fn stream_links_follow_the_provider() {
+        let mut runner = test_runner(1, "Alice", None);
+        assert_eq!(runner.get_stream(), "https://twitch.tv/Alice");
+
+        runner.stream_kind = StreamKind::Youtube;
+        runner.stream = Some("@alice_runs".to_owned());
+        assert_eq!(
+            runner.get_stream(),
+            "https://www.youtube.com/@alice_runs/live"
+        );
+
+        runner.stream = Some("https://www.youtube.com/watch?v=abc".to_owned());
+        assert_eq!(runner.get_stream(), "https://www.youtube.com/watch?v=abc");
+
+        runner.stream_kind = StreamKind::Direct;
+        runner.stream = Some("srt://ingest.example:9000".to_owned());
+        assert_eq!(runner.get_stream(), "srt://ingest.example:9000");
+    }
+
+    #[test]
+    fn direct_streams_are_used_as_they_are() {
+        let ttl = time::Duration::from_secs(60);
+        let mut runner = test_runner(1, "Alice", None);
+        runner.stream_kind = StreamKind::Direct;
+        runner.stream = Some("rtmp://ingest.example/live/alice".to_owned());
+
+        assert!(runner.find_stream(None, ttl).unwrap());
+        assert_eq!(
+            runner.cached_stream_url.as_deref(),
+            Some("rtmp://ingest.example/live/alice")
+        );
+        assert!(runner.stream_url_expires_at.is_some());
+        // Resolving the same URL again is not a change
+        assert!(!runner.find_stream(None, ttl).unwrap());
+
+        runner.stream = None;
+        assert!(runner.find_stream(None, ttl).is_err());
+    }
+    /// A dashboard update of a stored runner with every optional field set
+    fn update_of_stored(fields: Value) -> Runner {
+        let mut stored = test_runner(1, "Alice", None);
+        stored.max_stream_height = Some(480);
+        stored.archived = true;
+        stored.monitor_type = Some(AudioMonitorType::MonitorOnly);
+        stored.discord_id = Some("123456789012345678".to_owned());
+        stored.discord_reminder_opt_out = true;
+        stored.socials = BTreeMap::from([("bluesky".to_owned(), "alice.example".to_owned())]);
+        stored.stream_url_fetched_at = Some(OffsetDateTime::UNIX_EPOCH);
+        stored.stream_url_expires_at = Some(OffsetDateTime::UNIX_EPOCH);
+
+        // Only the fields every dashboard sends
+        let mut sent = json!({
+            "id": 1,
+            "name": "Alice B",
+            "stream": null,
+            "therun": null,
+            "cached_stream_url": null,
+            "location": null,
+            "volume_percent": 100,
+            "nicks": [],
+        });
+        let sent_fields = sent.as_object_mut().unwrap();
+        sent_fields.extend(fields.as_object().unwrap().clone());
+
+        let update: RunnerUpdate = serde_json::from_value(sent).unwrap();
+        update.merge(&stored).unwrap()
+    }
+
+    #[test]
+    fn omitted_runner_fields_keep_their_stored_value() {
+        let runner = update_of_stored(json!({}));
+        assert_eq!(runner.name, "Alice B");
+        assert_eq!(runner.max_stream_height, Some(480));
+        assert!(runner.archived);
+        assert_eq!(runner.monitor_type, Some(AudioMonitorType::MonitorOnly));
+        assert_eq!(runner.discord_id.as_deref(), Some("123456789012345678"));
+        assert!(runner.discord_reminder_opt_out);
+        assert_eq!(runner.socials["bluesky"], "alice.example");
+        assert_eq!(
+            runner.stream_url_fetched_at,
+            Some(OffsetDateTime::UNIX_EPOCH)
+        );
+        assert_eq!(
+            runner.stream_url_expires_at,
+            Some(OffsetDateTime::UNIX_EPOCH)
+        );
+    }
+
+    #[test]
+    fn sent_runner_fields_replace_their_stored_value() {
+        let runner = update_of_stored(json!({"max_stream_height": 720, "archived": false}));
+        assert_eq!(runner.max_stream_height, Some(720));
+        assert!(!runner.archived);
+
+        // Null clears the override instead of keeping it
+        let runner = update_of_stored(json!({"max_stream_height": null}));
+        assert_eq!(runner.max_stream_height, None);
+        assert!(runner.archived);
+
+        let runner = update_of_stored(json!({"discord_id": null, "socials": {}}));
+        assert_eq!(runner.discord_id, None);
+        assert!(runner.socials.is_empty());
+        assert_eq!(runner.monitor_type, Some(AudioMonitorType::MonitorOnly));
     }
 }
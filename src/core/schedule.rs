@@ -0,0 +1,223 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{
+    integrations::{discord::DiscordCommand, obs::ObsCommand},
+    send_message, Directory, Rto,
+};
+
+use super::{
+    db::ProjectStore,
+    event::{Event, EventRequest},
+    settings::Settings,
+    stream::StreamRequest,
+};
+
+/// Interval between checks of the event schedule
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// How long after its start time an event may still go live, eg. after a restart
+const GO_LIVE_GRACE: Duration = Duration::from_secs(120);
+/// Default minutes before the start time that the stream is prepared
+pub const DEFAULT_LEAD_MINUTES: u64 = 5;
+
+/// A step of going live automatically
+#[derive(Hash, PartialEq, Eq, Clone, Copy)]
+enum ScheduleStep {
+    /// Create the stream and set up the runner lineup
+    Prepare,
+    /// Start streaming and the event timer
+    GoLive,
+}
+
+/// Prepare and start the streams of events with `auto_go_live` set.
+///
+/// The schedule is read from the database on every check, so edited and deleted
+/// events are picked up on the next check. A message on `wake_rx` checks immediately.
+pub async fn run_event_schedule(
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    directory: Directory,
+    mut wake_rx: UnboundedReceiver<()>,
+) {
+    // Steps already run by event, step and start time, so rescheduled events run again
+    let mut done = HashSet::new();
+    let mut interval = tokio::time::interval(SCHEDULE_CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            msg = wake_rx.recv() => {
+                if msg.is_none() {
+                    break;
+                }
+            }
+        }
+
+        if let Err(e) = check_schedule(&*db, &settings, &directory, &mut done).await {
+            log::warn!("Failed to check the event schedule: {}", e);
+        }
+    }
+}
+
+async fn check_schedule(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+    done: &mut HashSet<(i64, ScheduleStep, i64)>,
+) -> anyhow::Result<()> {
+    let lead_minutes = settings
+        .auto_go_live_lead_minutes
+        .unwrap_or(DEFAULT_LEAD_MINUTES);
+    let lead = Duration::from_secs(lead_minutes * 60);
+    let now = OffsetDateTime::now_utc();
+
+    for id in db.get_event_ids().await? {
+        let event = db.get_event(id).await?;
+        let Some(start) = event.event_start_time.filter(|_| event.auto_go_live) else {
+            continue;
+        };
+        if event.timer_start_time.is_some() {
+            continue;
+        }
+        let key = start.unix_timestamp();
+
+        if now >= start - lead && now < start && done.insert((id, ScheduleStep::Prepare, key)) {
+            match prepare_event(db, directory, &event).await {
+                Ok(host) => notify(
+                    directory,
+                    format!(
+                        "Prepared {} on {}, going live in {} minutes",
+                        event.name,
+                        host,
+                        (start - now).whole_minutes() + 1
+                    ),
+                ),
+                Err(e) => {
+                    // Never go live on a stream that failed to prepare
+                    done.insert((id, ScheduleStep::GoLive, key));
+                    notify(
+                        directory,
+                        format!(
+                            "\u{26a0} Automatic go-live for {} aborted while preparing: {}",
+                            event.name, e
+                        ),
+                    );
+                }
+            }
+        }
+
+        if now >= start
+            && now < start + GO_LIVE_GRACE
+            && done.insert((id, ScheduleStep::GoLive, key))
+        {
+            let prepared = !done.insert((id, ScheduleStep::Prepare, key));
+            match go_live(db, directory, &event, prepared).await {
+                Ok(host) => {
+                    let mut text = format!("{} is live on {}", event.name, host);
+                    if let Some(urls) = settings
+                        .obs_hosts
+                        .get(&host)
+                        .and_then(|h| h.describe_public_stream_urls())
+                    {
+                        text = format!("{}, watch at {}", text, urls);
+                    }
+                    notify(directory, text)
+                }
+                Err(e) => notify(
+                    directory,
+                    format!(
+                        "\u{26a0} Automatic go-live for {} aborted: {}",
+                        event.name, e
+                    ),
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn notify(directory: &Directory, text: String) {
+    directory.discord_actor.send(DiscordCommand::Notify(text));
+}
+
+/// Create the stream of an event on its scheduled host and show its runners,
+/// returning the host
+async fn prepare_event(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: &Event,
+) -> anyhow::Result<String> {
+    let id = event.id;
+    if db.get_stream(id).await.is_err() {
+        let host = event
+            .scheduled_host
+            .clone()
+            .ok_or_else(|| anyhow!("no 'scheduled_host' is set for the event"))?;
+        send_message!(directory.stream_actor, StreamRequest, Create, id, host)?;
+    }
+
+    let mut stream = db.get_stream(id).await?;
+    if !stream.active {
+        if db.is_host_in_use(&stream.obs_host).await? {
+            return Err(anyhow!("host {} is showing another event", stream.obs_host));
+        }
+        send_message!(directory.stream_actor, StreamRequest, Activate, id)?;
+        stream.active = true;
+    }
+
+    stream.stream_runners = db
+        .get_event_runner_order(id)
+        .await?
+        .into_iter()
+        .enumerate()
+        .map(|(slot, runner)| (slot as i64, runner))
+        .collect();
+    let host = stream.obs_host.clone();
+    send_message!(directory.stream_actor, StreamRequest, Update, stream, false)?;
+
+    check_preflight(directory, &host, id).await?;
+    Ok(host)
+}
+
+/// Start streaming and the event timer, returning the host
+async fn go_live(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: &Event,
+    prepared: bool,
+) -> anyhow::Result<String> {
+    let id = event.id;
+    let host = if prepared {
+        db.get_stream(id).await?.obs_host
+    } else {
+        prepare_event(db, directory, event).await?
+    };
+
+    check_preflight(directory, &host, id).await?;
+
+    let start_host = host.clone();
+    send_message!(directory.obs_actor, ObsCommand, StartStream, start_host)?;
+    let now = Some(OffsetDateTime::now_utc());
+    send_message!(directory.event_actor, EventRequest, SetStartTime, id, now)?;
+
+    Ok(host)
+}
+
+/// Fail if a critical pre-flight check fails for the host
+async fn check_preflight(directory: &Directory, host: &str, event: i64) -> anyhow::Result<()> {
+    let host = host.to_owned();
+    let event = Some(event);
+    let report = send_message!(directory.obs_actor, ObsCommand, PreflightCheck, host, event)?;
+    if report.passed() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "pre-flight checks failed: {}",
+            report.describe_failures()
+        ))
+    }
+}
@@ -1,16 +1,301 @@
 use std::collections::HashMap;
 
-use serde::{Serialize, Deserialize};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use time::{macros::format_description, UtcOffset};
+
+use crate::{
+    core::{
+        backup::DEFAULT_BACKUP_KEEP,
+        runner::{DEFAULT_SELF_SERVICE_TOKEN_MINUTES, DEFAULT_STREAM_URL_TTL_MINUTES},
+        schedule::DEFAULT_LEAD_MINUTES,
+        stream::{SlotOverflow, DEFAULT_ON_DECK_EXPIRY_MINUTES, DEFAULT_ROTATION_PAUSE_SECS},
+    },
+    integrations::{
+        discord_reminders::DEFAULT_REMINDER_MINUTES,
+        obs::DEFAULT_STALL_TIMEOUT_SECS,
+        tiltify::{DEFAULT_POLL_SECONDS, DEFAULT_RECENT_DONATIONS},
+        twitch_chat::DEFAULT_COMMAND_COOLDOWN_SECS,
+        web::DEFAULT_WEB_PORT,
+        web_rate_limit::{DEFAULT_RATE_LIMIT_BURST, DEFAULT_RATE_LIMIT_PER_MINUTE},
+        web_timing::DEFAULT_SLOW_REQUEST_MILLIS,
+    },
+};
+
+/// Default `stream_view_pattern`
+pub const DEFAULT_STREAM_VIEW_PATTERN: &str = r"stream_(\d+)_.*";
+/// Default `nametag_pattern`
+pub const DEFAULT_NAMETAG_PATTERN: &str = "name_{idx}";
+/// Default `delta_pattern`
+pub const DEFAULT_DELTA_PATTERN: &str = "delta_{idx}";
+/// Default `pb_pattern`
+pub const DEFAULT_PB_PATTERN: &str = "pb_{idx}";
+/// Default `best_possible_pattern`
+pub const DEFAULT_BEST_POSSIBLE_PATTERN: &str = "bpt_{idx}";
+/// Default `lower_third_format`
+pub const DEFAULT_LOWER_THIRD_FORMAT: &str = "{name} \u{2014} @{handle}";
+/// Default `lower_third_platforms`
+pub const DEFAULT_LOWER_THIRD_PLATFORMS: [&str; 2] = ["twitter", "bluesky"];
+/// Default `commentary_source_name`
+pub const DEFAULT_COMMENTARY_SOURCE_NAME: &str = "commentary";
+/// Default `commentary_host_source_name`
+pub const DEFAULT_COMMENTARY_HOST_SOURCE_NAME: &str = "commentary_host";
 
 /// Json struct for project-independent settings
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Settings {
     pub obs_hosts: HashMap<String, ObsHost>,
     pub obs_transition: Option<String>,
+    /// Layer index for created stream views by layout name
+    pub layout_view_index: Option<HashMap<String, u32>>,
+    /// Layer index for created stream views in layouts without an entry in `layout_view_index`
+    pub default_view_index: Option<u32>,
     pub keep_unused_streams: Option<bool>,
+    /// Remove runner sources that no saved stream on their host uses after each layout change,
+    /// only logging them if false
+    pub remove_orphaned_sources: Option<bool>,
     pub discord_token: Option<String>,
     pub discord_command_channel: Option<String>,
+    /// Minutes before `event_start_time` that runners linked to a Discord user are sent
+    /// a reminder by direct message, 60 and 15 if None. An empty list disables reminders
+    pub discord_reminder_minutes: Option<Vec<u64>>,
+    /// URL to POST to when the Discord bot loses or regains its connection
+    pub discord_status_webhook_url: Option<String>,
+    /// Discord role ID whose members are never shown as commentators, eg. producers
+    pub discord_ignore_role_id: Option<String>,
     pub web_port: Option<u16>,
+    /// Minutes between automatic database backups, disabled if None
+    pub backup_interval_minutes: Option<u64>,
+    /// Backup folder, relative to the project folder
+    pub backup_dir: Option<String>,
+    /// Number of backups to keep
+    pub backup_keep: Option<usize>,
+    /// URL to POST to when a replay is saved
+    pub replay_webhook_url: Option<String>,
+    /// URL to POST the results of an event to when it is completed
+    pub event_complete_webhook_url: Option<String>,
+    /// Twitch chat bot account name
+    pub twitch_bot_nick: Option<String>,
+    /// OAuth token for the Twitch chat bot, the bot is disabled if None
+    pub twitch_oauth_token: Option<String>,
+    /// Client ID of the Twitch application used for Helix API requests, eg. stream markers
+    pub twitch_client_id: Option<String>,
+    /// User access token for Helix API requests, with the `channel:manage:broadcast` scope
+    /// of the broadcaster or one of their editors
+    pub twitch_api_token: Option<String>,
+    /// Seconds before a Twitch chat command can be used again in the same channel
+    pub twitch_command_cooldown_seconds: Option<u64>,
+    /// Refuse to start streaming if a critical pre-flight check fails
+    pub enforce_preflight: Option<bool>,
+    /// Minutes before `event_start_time` that events with `auto_go_live` prepare their stream
+    pub auto_go_live_lead_minutes: Option<u64>,
+    /// Regex matching the names of stream view sources in layouts,
+    /// with one capture group for the stream slot
+    pub stream_view_pattern: Option<String>,
+    /// Name of the text source showing the runner in a stream slot, with `{idx}` as the slot
+    pub nametag_pattern: Option<String>,
+    /// Name of the text source showing the delta of the runner in a stream slot,
+    /// with `{idx}` as the slot
+    pub delta_pattern: Option<String>,
+    /// Name of the text source showing the personal best of the runner in a stream slot
+    pub pb_pattern: Option<String>,
+    /// Name of the text source showing the best possible time of the runner in a stream slot
+    pub best_possible_pattern: Option<String>,
+    /// Format of the `{runner.<idx>.lower_third}` text placeholder, with `{name}` and `{handle}`.
+    /// Runners without a handle on `lower_third_platforms` are shown by name only
+    pub lower_third_format: Option<String>,
+    /// Social platforms whose handle is shown in lower thirds, the first the runner has wins
+    pub lower_third_platforms: Option<Vec<String>>,
+    /// Name of the text source listing the commentators
+    pub commentary_source_name: Option<String>,
+    /// Name of the text source showing the commentary host, who is then left out of the commentators
+    pub commentary_host_source_name: Option<String>,
+    /// Seconds a runner source on the program feed can go without playback progress
+    /// before it is refreshed automatically
+    pub stall_timeout_seconds: Option<u64>,
+    /// Milliseconds after which a web request is logged as slow
+    pub slow_request_millis: Option<u64>,
+    /// Requests per minute each web client may make that change the project, 0 to disable
+    pub rate_limit_per_minute: Option<u32>,
+    /// Requests a web client may make at once before `rate_limit_per_minute` applies
+    pub rate_limit_burst: Option<u32>,
+    /// Minutes a runner's self-service link can be used before it expires
+    pub self_service_token_minutes: Option<u64>,
+    /// Bearer token required by the debug endpoints of the web server
+    pub admin_token: Option<String>,
+    /// Token required in the query string of the `/trigger/...` GET endpoints used by
+    /// Stream Deck buttons and hotkeys, which are disabled if None
+    pub trigger_token: Option<String>,
+    /// Transition used in Studio Mode when a layout rotation switches layouts
+    pub rotation_transition: Option<String>,
+    /// Transition used in Studio Mode when a relay runner hands off to the next,
+    /// such as a long fade
+    pub relay_handoff_transition: Option<String>,
+    /// Seconds a layout rotation is paused after the layout is changed by hand
+    pub rotation_pause_seconds: Option<u64>,
+    /// What happens to runners placed in slots that the layout of their stream does not have
+    pub slot_overflow: Option<SlotOverflow>,
+    /// Move runners into the lowest slots of their stream's layout, closing gaps between them
+    pub compact_slots: Option<bool>,
+    /// Minutes a runner stays on deck before they are taken off and their hidden source is let go
+    pub on_deck_expiry_minutes: Option<u64>,
+    /// Tiltify API access token, required to poll donations
+    pub tiltify_token: Option<String>,
+    /// ID of the Tiltify campaign to poll
+    pub tiltify_campaign_id: Option<String>,
+    /// Seconds between Tiltify polls
+    pub tiltify_poll_seconds: Option<u64>,
+    /// Number of recent donations kept for overlays
+    pub tiltify_recent_donations: Option<usize>,
+    /// Donation totals that are announced once reached
+    pub donation_milestones: Option<Vec<f64>>,
+    /// URL to POST to when a donation milestone is reached
+    pub donation_webhook_url: Option<String>,
+    /// Folder to record the messages sent to the core actors in, one file per run,
+    /// for replaying with `/debug/replay`
+    pub message_trace_dir: Option<String>,
+    /// Minutes a resolved runner stream URL is used before it is resolved again
+    pub stream_url_ttl_minutes: Option<u64>,
+    /// Origins allowed to use the web server from a browser, such as `https://dashboard.example.com`.
+    /// Any origin is allowed if empty
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Browser sources whose URLs are set together from parameters, by group name,
+    /// eg. the standings and views of a ladder league
+    #[serde(default)]
+    pub browser_source_groups: HashMap<String, BrowserSourceGroup>,
+    /// Filters of the runner sources of specific runners, by runner name. A filter replaces
+    /// the host's filter of the same name
+    pub runner_filters: Option<HashMap<String, Vec<SourceFilter>>>,
+    /// Treat every OBS host as `dry_run`, eg. for a rehearsal
+    #[serde(default)]
+    pub obs_dry_run: bool,
+    /// UTC offset that times are shown and read in, such as `+02:00`, UTC if None.
+    /// The offset is fixed, so it has to be changed when daylight saving time starts or ends
+    pub timezone: Option<String>,
+}
+
+/// OBS source naming conventions, compiled from the settings
+#[derive(Clone, Debug)]
+pub struct SourceNaming {
+    stream_view: Regex,
+    nametag: String,
+    delta: String,
+    pb: String,
+    best_possible: String,
+    pub commentary: String,
+    pub commentary_host: String,
+}
+
+impl SourceNaming {
+    /// Compile the naming settings, returning every problem found
+    pub fn from_settings(settings: &Settings) -> Result<Self, Vec<String>> {
+        let mut errors = vec![];
+
+        let pattern = settings
+            .stream_view_pattern
+            .as_deref()
+            .unwrap_or(DEFAULT_STREAM_VIEW_PATTERN);
+        let stream_view = match Regex::new(pattern) {
+            Ok(regex) if regex.captures_len() == 2 => Some(regex),
+            Ok(regex) => {
+                errors.push(format!(
+                    "'stream_view_pattern' must have exactly one capture group for the stream slot, \
+                    like the default '{}', but '{}' has {}",
+                    DEFAULT_STREAM_VIEW_PATTERN,
+                    pattern,
+                    regex.captures_len() - 1
+                ));
+                None
+            }
+            Err(e) => {
+                errors.push(format!("'stream_view_pattern' is not a valid regex: {}", e));
+                None
+            }
+        };
+
+        let mut slot_pattern = |name: &str, pattern: &Option<String>, default: &str| {
+            let pattern = pattern.clone().unwrap_or_else(|| default.to_owned());
+            if !pattern.contains("{idx}") {
+                errors.push(format!(
+                    "'{}' must contain the '{{idx}}' placeholder for the stream slot, \
+                    like the default '{}'",
+                    name, default
+                ));
+            }
+            pattern
+        };
+        let nametag = slot_pattern(
+            "nametag_pattern",
+            &settings.nametag_pattern,
+            DEFAULT_NAMETAG_PATTERN,
+        );
+        let delta = slot_pattern(
+            "delta_pattern",
+            &settings.delta_pattern,
+            DEFAULT_DELTA_PATTERN,
+        );
+        let pb = slot_pattern("pb_pattern", &settings.pb_pattern, DEFAULT_PB_PATTERN);
+        let best_possible = slot_pattern(
+            "best_possible_pattern",
+            &settings.best_possible_pattern,
+            DEFAULT_BEST_POSSIBLE_PATTERN,
+        );
+
+        let commentary = settings
+            .commentary_source_name
+            .clone()
+            .unwrap_or_else(|| DEFAULT_COMMENTARY_SOURCE_NAME.to_owned());
+        if commentary.trim().is_empty() {
+            errors.push(format!(
+                "'commentary_source_name' is empty, remove it to use the default '{}'",
+                DEFAULT_COMMENTARY_SOURCE_NAME
+            ));
+        }
+
+        let commentary_host = settings
+            .commentary_host_source_name
+            .clone()
+            .unwrap_or_else(|| DEFAULT_COMMENTARY_HOST_SOURCE_NAME.to_owned());
+        if commentary_host.trim().is_empty() {
+            errors.push(format!(
+                "'commentary_host_source_name' is empty, remove it to use the default '{}'",
+                DEFAULT_COMMENTARY_HOST_SOURCE_NAME
+            ));
+        }
+
+        match stream_view {
+            Some(stream_view) if errors.is_empty() => Ok(Self {
+                stream_view,
+                nametag,
+                delta,
+                pb,
+                best_possible,
+                commentary,
+                commentary_host,
+            }),
+            _ => Err(errors),
+        }
+    }
+
+    /// The stream slot of a stream view source, if the name is one
+    pub fn stream_view_slot(&self, source: &str) -> Option<usize> {
+        self.stream_view
+            .captures(source)
+            .and_then(|caps| caps.get(1))
+            .and_then(|slot| slot.as_str().parse().ok())
+    }
+
+    /// The name of the nametag source of a stream slot
+    pub fn nametag(&self, slot: i64) -> String {
+        self.nametag.replace("{idx}", &slot.to_string())
+    }
+
+    /// The names of the delta, personal best and best possible time sources of a stream slot
+    pub fn run_stats(&self, slot: i64) -> [String; 3] {
+        [&self.delta, &self.pb, &self.best_possible].map(|p| p.replace("{idx}", &slot.to_string()))
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -18,5 +303,983 @@ pub struct ObsHost {
     pub obs_ip: String,
     pub obs_port: u16,
     pub obs_password: Option<String>,
-    pub discord_voice_channel: Option<String>
+    pub discord_voice_channel: Option<String>,
+    /// Discord channel IDs whose commands default to the streams on this host
+    pub discord_channels: Option<Vec<String>>,
+    /// Discord role IDs whose members' commands default to the streams on this host,
+    /// used when the channel is not assigned to a host
+    pub discord_roles: Option<Vec<String>>,
+    /// The largest stream rendition height to pull for runner sources on this host
+    pub max_stream_height: Option<u32>,
+    /// The preferred framerate when choosing between renditions of the same height
+    pub prefer_fps: Option<u32>,
+    /// Text source templates by OBS input name, eg. `"{event.game} - {event.category}"`
+    /// or `"Raised: {donation.total}"`
+    pub text_bindings: Option<HashMap<String, String>>,
+    /// Scene shown by the intermission trigger
+    pub intermission_scene: Option<String>,
+    /// Twitch channel whose chat the chat bot joins for this host
+    pub twitch_channel: Option<String>,
+    /// Audio tracks (1-6) that runner sources output to, OBS defaults are kept if None
+    pub runner_audio_tracks: Option<Vec<u8>>,
+    /// Audio monitoring of runner sources, defaults to no monitoring
+    pub runner_monitor_type: Option<AudioMonitorType>,
+    /// Audio input carrying commentary, checked to be unmuted before going live
+    pub commentary_input: Option<String>,
+    /// Where viewers can watch this host, including restreams to other platforms
+    #[serde(default)]
+    pub public_stream_urls: Vec<PublicStreamUrl>,
+    /// Whether the pre-flight check fails if an output besides the main stream
+    /// is missing or not running, eg. a restream
+    #[serde(default)]
+    pub require_all_outputs: bool,
+    /// Filters added to every runner source, eg. a color correction
+    pub runner_filters: Option<Vec<SourceFilter>>,
+    /// Log the changes commands would make instead of connecting to OBS, for rehearsals
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl ObsHost {
+    /// The public stream URLs as `platform: url`, separated by spaces, or None if there are none
+    pub fn describe_public_stream_urls(&self) -> Option<String> {
+        if self.public_stream_urls.is_empty() {
+            return None;
+        }
+        Some(
+            self.public_stream_urls
+                .iter()
+                .map(|u| format!("{}: {}", u.platform, u.url))
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+}
+
+/// Browser sources set together from parameters, see `ObsCommand::SetBrowserSourceGroup`.
+///
+/// Input names and URLs can use `{param}` placeholders for the given parameters.
+/// Sources using `{runner_index}` are set once per runner slot of the host's active stream.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BrowserSourceGroup {
+    /// Prefix of every URL of the group, eg. `http://10.10.0.4:35065/`
+    pub base_url: String,
+    pub sources: Vec<BrowserSourceTemplate>,
+}
+
+/// A browser source of a group
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BrowserSourceTemplate {
+    /// Name of the OBS browser source, eg. `league_runner_{runner_index}`
+    pub input: String,
+    /// URL of the source after the group's base URL, eg. `league/{id}/runner/{runner_index}`
+    pub url: String,
+}
+
+impl BrowserSourceTemplate {
+    /// Whether the source is set once per runner slot
+    pub fn is_per_runner(&self) -> bool {
+        self.input.contains("{runner_index}") || self.url.contains("{runner_index}")
+    }
+}
+
+/// An OBS filter added to runner sources, re-created if it is removed in OBS
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SourceFilter {
+    /// Kind of the filter, eg. `color_filter_v2` or `crop_filter`
+    pub kind: String,
+    pub name: String,
+    /// Settings the filter is created with, OBS defaults are used for missing fields
+    #[serde(default)]
+    pub settings: Value,
+}
+
+/// A public URL a host's stream is watched at
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PublicStreamUrl {
+    /// Name of the platform, eg. `YouTube`
+    pub platform: String,
+    pub url: String,
+}
+
+/// How OBS monitors the audio of a source
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum AudioMonitorType {
+    None,
+    MonitorOnly,
+    MonitorAndOutput,
+}
+
+/// Problems found in the settings file
+#[derive(Default, Debug)]
+pub struct SettingsReport {
+    /// Problems that prevent AutoMarathon from starting
+    pub errors: Vec<String>,
+    /// Settings that are allowed but probably a mistake
+    pub warnings: Vec<String>,
+}
+
+impl Settings {
+    /// Parse settings.json, which may contain `//` comment lines
+    pub fn parse(text: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(&strip_comments(text))
+    }
+
+    /// Settings with every default filled in and an example OBS host, for new projects
+    pub fn template() -> Self {
+        Settings {
+            obs_hosts: HashMap::from([(
+                "main".to_owned(),
+                ObsHost {
+                    obs_ip: "localhost".to_owned(),
+                    obs_port: 4455,
+                    obs_password: None,
+                    discord_voice_channel: None,
+                    discord_channels: None,
+                    discord_roles: None,
+                    max_stream_height: None,
+                    prefer_fps: None,
+                    text_bindings: None,
+                    intermission_scene: None,
+                    twitch_channel: None,
+                    runner_audio_tracks: None,
+                    runner_monitor_type: None,
+                    commentary_input: None,
+                    public_stream_urls: vec![],
+                    require_all_outputs: false,
+                    runner_filters: None,
+                    dry_run: false,
+                },
+            )]),
+            obs_transition: None,
+            layout_view_index: None,
+            default_view_index: None,
+            keep_unused_streams: Some(true),
+            remove_orphaned_sources: Some(false),
+            discord_token: None,
+            discord_command_channel: None,
+            discord_reminder_minutes: Some(DEFAULT_REMINDER_MINUTES.to_vec()),
+            discord_status_webhook_url: None,
+            discord_ignore_role_id: None,
+            web_port: Some(DEFAULT_WEB_PORT),
+            backup_interval_minutes: None,
+            backup_dir: Some("backups".to_owned()),
+            backup_keep: Some(DEFAULT_BACKUP_KEEP),
+            replay_webhook_url: None,
+            event_complete_webhook_url: None,
+            twitch_bot_nick: None,
+            twitch_oauth_token: None,
+            twitch_client_id: None,
+            twitch_api_token: None,
+            twitch_command_cooldown_seconds: Some(DEFAULT_COMMAND_COOLDOWN_SECS),
+            enforce_preflight: Some(false),
+            auto_go_live_lead_minutes: Some(DEFAULT_LEAD_MINUTES),
+            stream_view_pattern: Some(DEFAULT_STREAM_VIEW_PATTERN.to_owned()),
+            nametag_pattern: Some(DEFAULT_NAMETAG_PATTERN.to_owned()),
+            delta_pattern: Some(DEFAULT_DELTA_PATTERN.to_owned()),
+            pb_pattern: Some(DEFAULT_PB_PATTERN.to_owned()),
+            best_possible_pattern: Some(DEFAULT_BEST_POSSIBLE_PATTERN.to_owned()),
+            lower_third_format: Some(DEFAULT_LOWER_THIRD_FORMAT.to_owned()),
+            lower_third_platforms: Some(
+                DEFAULT_LOWER_THIRD_PLATFORMS.map(|p| p.to_owned()).to_vec(),
+            ),
+            commentary_source_name: Some(DEFAULT_COMMENTARY_SOURCE_NAME.to_owned()),
+            commentary_host_source_name: Some(DEFAULT_COMMENTARY_HOST_SOURCE_NAME.to_owned()),
+            stall_timeout_seconds: Some(DEFAULT_STALL_TIMEOUT_SECS),
+            slow_request_millis: Some(DEFAULT_SLOW_REQUEST_MILLIS),
+            rate_limit_per_minute: Some(DEFAULT_RATE_LIMIT_PER_MINUTE),
+            rate_limit_burst: Some(DEFAULT_RATE_LIMIT_BURST),
+            self_service_token_minutes: Some(DEFAULT_SELF_SERVICE_TOKEN_MINUTES),
+            admin_token: None,
+            trigger_token: None,
+            rotation_transition: None,
+            relay_handoff_transition: None,
+            rotation_pause_seconds: Some(DEFAULT_ROTATION_PAUSE_SECS),
+            slot_overflow: Some(SlotOverflow::Reject),
+            compact_slots: Some(false),
+            on_deck_expiry_minutes: Some(DEFAULT_ON_DECK_EXPIRY_MINUTES),
+            tiltify_token: None,
+            tiltify_campaign_id: None,
+            tiltify_poll_seconds: Some(DEFAULT_POLL_SECONDS),
+            tiltify_recent_donations: Some(DEFAULT_RECENT_DONATIONS),
+            donation_milestones: None,
+            donation_webhook_url: None,
+            message_trace_dir: None,
+            stream_url_ttl_minutes: Some(DEFAULT_STREAM_URL_TTL_MINUTES),
+            allowed_origins: vec![],
+            browser_source_groups: HashMap::new(),
+            runner_filters: None,
+            obs_dry_run: false,
+            timezone: None,
+        }
+    }
+
+    /// The template settings as JSON with a comment explaining each field
+    pub fn template_json() -> String {
+        let template = serde_json::to_value(Self::template()).unwrap();
+        let mut out = String::new();
+        write_commented(&mut out, template.as_object().unwrap(), SETTINGS_HELP, 1);
+        out.push('\n');
+        out
+    }
+
+    /// Whether a browser request from the given origin may use the web server
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins.is_empty() || self.allowed_origins.iter().any(|o| o == origin)
+    }
+
+    /// The offset of `timezone`, UTC if it is not set or invalid
+    pub fn get_timezone(&self) -> UtcOffset {
+        self.timezone
+            .as_deref()
+            .and_then(parse_utc_offset)
+            .unwrap_or(UtcOffset::UTC)
+    }
+
+    /// Whether OBS is left untouched for a host, see `ObsHost::dry_run`
+    pub fn is_dry_run(&self, host: &str) -> bool {
+        self.obs_dry_run || self.obs_hosts.get(host).is_some_and(|h| h.dry_run)
+    }
+
+    /// The filters of a runner's source on a host: the host's filters, with the runner's own
+    /// filters replacing those of the same name
+    pub fn runner_filters(&self, host: &str, runner: &str) -> Vec<SourceFilter> {
+        let mut filters = self
+            .obs_hosts
+            .get(host)
+            .and_then(|h| h.runner_filters.clone())
+            .unwrap_or_default();
+        let own = self.runner_filters.as_ref().and_then(|f| f.get(runner));
+        for filter in own.into_iter().flatten() {
+            match filters.iter_mut().find(|f| f.name == filter.name) {
+                Some(existing) => *existing = filter.clone(),
+                None => filters.push(filter.clone()),
+            }
+        }
+        filters
+    }
+
+    /// Check the settings for problems that would otherwise only show up mid-event.
+    ///
+    /// All problems are collected rather than stopping at the first one.
+    pub fn validate(&self) -> SettingsReport {
+        let mut report = SettingsReport::default();
+
+        if self.obs_hosts.is_empty() {
+            report
+                .warnings
+                .push("No 'obs_hosts' are configured, streams cannot be created".to_owned());
+        }
+
+        if let Some(format) = &self.lower_third_format {
+            if !format.contains("{handle}") {
+                report.warnings.push(format!(
+                    "'lower_third_format' '{}' has no '{{handle}}' placeholder, \
+                    so lower thirds never show a social handle",
+                    format
+                ));
+            }
+        }
+
+        if self.obs_dry_run {
+            report
+                .warnings
+                .push("'obs_dry_run' is set, no OBS host will be changed".to_owned());
+        }
+
+        if let Some(timezone) = &self.timezone {
+            if parse_utc_offset(timezone).is_none() {
+                report.errors.push(format!(
+                    "'timezone' '{}' is not a UTC offset, use the form '+02:00' or 'UTC'",
+                    timezone
+                ));
+            }
+        }
+
+        let mut hosts: Vec<_> = self.obs_hosts.iter().collect();
+        hosts.sort_by_key(|(name, _)| name.as_str());
+
+        let mut voice_channels: HashMap<&str, &str> = HashMap::new();
+        let mut command_channels: HashMap<&str, &str> = HashMap::new();
+        let mut roles: HashMap<&str, &str> = HashMap::new();
+        for (name, host) in hosts {
+            if host.dry_run && !self.obs_dry_run {
+                report.warnings.push(format!(
+                    "OBS host '{}' is a 'dry_run' host, its OBS will not be changed",
+                    name
+                ));
+            }
+            if host.obs_ip.trim().is_empty() {
+                report
+                    .errors
+                    .push(format!("OBS host '{}' has an empty 'obs_ip'", name));
+            }
+            if host.obs_port == 0 {
+                report.errors.push(format!(
+                    "OBS host '{}' has an invalid 'obs_port' of 0",
+                    name
+                ));
+            }
+            if host.obs_password.is_none() {
+                report.warnings.push(format!(
+                    "OBS host '{}' has no 'obs_password', connecting will fail if OBS requires authentication",
+                    name
+                ));
+            }
+
+            if let Some(channel) = &host.discord_voice_channel {
+                if channel.trim().is_empty() {
+                    report.errors.push(format!(
+                        "OBS host '{}' has an empty 'discord_voice_channel'",
+                        name
+                    ));
+                } else if let Some(other) = voice_channels.insert(channel, name) {
+                    report.errors.push(format!(
+                        "OBS hosts '{}' and '{}' both use discord_voice_channel '{}'",
+                        other, name, channel
+                    ));
+                }
+                if self.discord_token.is_none() {
+                    report.warnings.push(format!(
+                        "OBS host '{}' has a 'discord_voice_channel' but no 'discord_token' is set",
+                        name
+                    ));
+                }
+            }
+
+            for channel in host.discord_channels.iter().flatten() {
+                if let Some(other) = command_channels.insert(channel, name) {
+                    report.errors.push(format!(
+                        "OBS hosts '{}' and '{}' list Discord channel '{}' in 'discord_channels'",
+                        other, name, channel
+                    ));
+                }
+            }
+            for role in host.discord_roles.iter().flatten() {
+                if let Some(other) = roles.insert(role, name) {
+                    report.warnings.push(format!(
+                        "OBS hosts '{}' and '{}' both use Discord role '{}' in 'discord_roles', \
+                        members with it get no default host",
+                        other, name, role
+                    ));
+                }
+            }
+            if (host.discord_channels.is_some() || host.discord_roles.is_some())
+                && self.discord_token.is_none()
+            {
+                report.warnings.push(format!(
+                    "OBS host '{}' has Discord channels or roles but no 'discord_token' is set",
+                    name
+                ));
+            }
+
+            if host.twitch_channel.is_some() && self.twitch_oauth_token.is_none() {
+                report.warnings.push(format!(
+                    "OBS host '{}' has a 'twitch_channel' but no 'twitch_oauth_token' is set",
+                    name
+                ));
+            }
+
+            if let Some(tracks) = &host.runner_audio_tracks {
+                if let Some(track) = tracks.iter().find(|t| !(1..=6).contains(*t)) {
+                    report.errors.push(format!(
+                        "OBS host '{}' has audio track {} in 'runner_audio_tracks', tracks must be between 1 and 6",
+                        name, track
+                    ));
+                }
+            }
+
+            for url in &host.public_stream_urls {
+                if !url.url.starts_with("https://") && !url.url.starts_with("http://") {
+                    report.errors.push(format!(
+                        "OBS host '{}' has a public stream URL '{}' that is not a web address",
+                        name, url.url
+                    ));
+                }
+            }
+
+            if host
+                .commentary_input
+                .as_ref()
+                .is_some_and(|i| i.trim().is_empty())
+            {
+                report.errors.push(format!(
+                    "OBS host '{}' has an empty 'commentary_input'",
+                    name
+                ));
+            }
+        }
+
+        if self
+            .obs_transition
+            .as_ref()
+            .is_some_and(|t| t.trim().is_empty())
+        {
+            report.errors.push(
+                "'obs_transition' is empty, remove it to use the current OBS transition".to_owned(),
+            );
+        }
+
+        if self
+            .rotation_transition
+            .as_ref()
+            .is_some_and(|t| t.trim().is_empty())
+        {
+            report.errors.push(
+                "'rotation_transition' is empty, remove it to use 'obs_transition'".to_owned(),
+            );
+        }
+
+        if self
+            .relay_handoff_transition
+            .as_ref()
+            .is_some_and(|t| t.trim().is_empty())
+        {
+            report.errors.push(
+                "'relay_handoff_transition' is empty, remove it to use 'obs_transition'".to_owned(),
+            );
+        }
+
+        if self
+            .discord_command_channel
+            .as_ref()
+            .is_some_and(|c| c.trim().is_empty())
+        {
+            report
+                .errors
+                .push("'discord_command_channel' is empty".to_owned());
+        }
+
+        if self.discord_ignore_role_id.is_some() && self.discord_token.is_none() {
+            report
+                .warnings
+                .push("'discord_ignore_role_id' is set but no 'discord_token' is set".to_owned());
+        }
+
+        if self
+            .discord_reminder_minutes
+            .as_ref()
+            .is_some_and(|m| m.contains(&0))
+        {
+            report.errors.push(
+                "'discord_reminder_minutes' contains 0, reminders must be sent before the start time"
+                    .to_owned(),
+            );
+        }
+
+        if self.allowed_origins.is_empty() {
+            report.warnings.push(
+                "No 'allowed_origins' are set, any website can use the web server".to_owned(),
+            );
+        }
+        for origin in &self.allowed_origins {
+            let valid = origin
+                .strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"))
+                .is_some_and(|host| !host.is_empty() && !host.contains('/'));
+            if !valid {
+                report.errors.push(format!(
+                    "'{}' in 'allowed_origins' is not an origin, use the form 'https://example.com'",
+                    origin
+                ));
+            }
+        }
+
+        let host_filters = self.obs_hosts.iter().filter_map(|(name, h)| {
+            Some((format!("OBS host '{}'", name), h.runner_filters.as_ref()?))
+        });
+        let own_filters = self
+            .runner_filters
+            .iter()
+            .flatten()
+            .map(|(name, f)| (format!("Runner '{}'", name), f));
+        for (owner, filters) in host_filters.chain(own_filters) {
+            for (idx, filter) in filters.iter().enumerate() {
+                if filter.kind.trim().is_empty() || filter.name.trim().is_empty() {
+                    report.errors.push(format!(
+                        "{} has a runner filter without a 'kind' or 'name'",
+                        owner
+                    ));
+                } else if filters[..idx].iter().any(|f| f.name == filter.name) {
+                    report.errors.push(format!(
+                        "{} has more than one runner filter named '{}'",
+                        owner, filter.name
+                    ));
+                }
+                if !filter.settings.is_null() && !filter.settings.is_object() {
+                    report.errors.push(format!(
+                        "{} has runner filter '{}' whose 'settings' is not an object",
+                        owner, filter.name
+                    ));
+                }
+            }
+        }
+
+        for (name, group) in &self.browser_source_groups {
+            if group.sources.is_empty() {
+                report
+                    .warnings
+                    .push(format!("Browser source group '{}' has no sources", name));
+            }
+            if group.sources.iter().any(|s| s.input.trim().is_empty()) {
+                report.errors.push(format!(
+                    "Browser source group '{}' has a source with an empty 'input'",
+                    name
+                ));
+            }
+        }
+
+        if let Some(port) = self.web_port {
+            if port == 0 {
+                report.errors.push("'web_port' cannot be 0".to_owned());
+            } else if port < 1024 && !is_root() {
+                report.errors.push(format!(
+                    "'web_port' {} is a privileged port, use a port of 1024 or above or run as root",
+                    port
+                ));
+            }
+        }
+
+        if self.twitch_client_id.is_some() != self.twitch_api_token.is_some() {
+            report.warnings.push(
+                "Only one of 'twitch_client_id' and 'twitch_api_token' is set, \
+                Twitch stream markers are not created"
+                    .to_owned(),
+            );
+        }
+
+        if self.twitch_oauth_token.is_some() && self.twitch_bot_nick.is_none() {
+            report
+                .errors
+                .push("'twitch_oauth_token' is set but 'twitch_bot_nick' is missing".to_owned());
+        }
+
+        if self
+            .trigger_token
+            .as_ref()
+            .is_some_and(|t| t.trim().is_empty())
+        {
+            report
+                .errors
+                .push("'trigger_token' is empty, remove it to disable triggers".to_owned());
+        }
+
+        if let Err(errors) = SourceNaming::from_settings(self) {
+            report.errors.extend(errors);
+        }
+
+        if self.on_deck_expiry_minutes == Some(0) {
+            report.warnings.push(
+                "'on_deck_expiry_minutes' is 0, runners are taken off deck right after they are put on it"
+                    .to_owned(),
+            );
+        }
+
+        if self.backup_keep == Some(0) {
+            report.warnings.push(
+                "'backup_keep' is 0, every backup will be deleted right after it is written"
+                    .to_owned(),
+            );
+        }
+
+        report
+    }
+}
+
+/// Parse a UTC offset such as `+02:00`, `-05:30` or `UTC`
+fn parse_utc_offset(text: &str) -> Option<UtcOffset> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("utc") || text == "Z" {
+        return Some(UtcOffset::UTC);
+    }
+    let format = format_description!("[offset_hour sign:mandatory]:[offset_minute]");
+    UtcOffset::parse(text, &format).ok()
+}
+
+/// Whether the process runs as root, and can bind privileged ports
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    true
+}
+
+/// Explanations of the settings, written above each field of the template
+const SETTINGS_HELP: &[(&str, &str)] = &[
+    (
+        "obs_hosts",
+        "OBS instances by name, each with the fields explained below",
+    ),
+    (
+        "obs_transition",
+        "Transition used in Studio Mode when the layout changes",
+    ),
+    (
+        "layout_view_index",
+        "Layer index for created stream views by layout name",
+    ),
+    (
+        "default_view_index",
+        "Layer index for created stream views in other layouts",
+    ),
+    (
+        "keep_unused_streams",
+        "Keep runner sources that are out of view instead of removing them",
+    ),
+    (
+        "remove_orphaned_sources",
+        "Remove sources of runners in no stream, only log them if false",
+    ),
+    (
+        "discord_token",
+        "Discord bot token, the bot is disabled if null",
+    ),
+    (
+        "discord_command_channel",
+        "Discord channel the bot posts notifications in",
+    ),
+    (
+        "discord_reminder_minutes",
+        "Minutes before an event starts that runners are reminded",
+    ),
+    (
+        "discord_status_webhook_url",
+        "URL to POST to when the Discord bot disconnects or reconnects",
+    ),
+    (
+        "discord_ignore_role_id",
+        "Discord role ID whose members are never shown as commentators",
+    ),
+    ("web_port", "Port of the web server and dashboard"),
+    (
+        "backup_interval_minutes",
+        "Minutes between automatic database backups, disabled if null",
+    ),
+    (
+        "backup_dir",
+        "Backup folder, relative to the project folder",
+    ),
+    ("backup_keep", "Number of backups to keep"),
+    (
+        "replay_webhook_url",
+        "URL to POST to when a replay is saved",
+    ),
+    (
+        "event_complete_webhook_url",
+        "URL to POST the results of an event to when it is completed",
+    ),
+    ("twitch_bot_nick", "Twitch chat bot account name"),
+    (
+        "twitch_oauth_token",
+        "OAuth token of the Twitch chat bot, the bot is disabled if null",
+    ),
+    (
+        "twitch_client_id",
+        "Client ID of the Twitch application for stream markers",
+    ),
+    (
+        "twitch_api_token",
+        "Twitch access token with channel:manage:broadcast for stream markers",
+    ),
+    (
+        "twitch_command_cooldown_seconds",
+        "Seconds before a chat command can be used again",
+    ),
+    (
+        "enforce_preflight",
+        "Refuse to start streaming if a critical pre-flight check fails",
+    ),
+    (
+        "auto_go_live_lead_minutes",
+        "Minutes before an event starts that auto_go_live prepares it",
+    ),
+    (
+        "stream_view_pattern",
+        "Regex of stream view source names, capturing the stream slot",
+    ),
+    (
+        "nametag_pattern",
+        "Name of the nametag text source of a slot, {idx} being the slot",
+    ),
+    (
+        "delta_pattern",
+        "Name of the text source showing a slot's delta against the PB",
+    ),
+    (
+        "pb_pattern",
+        "Name of the text source showing a slot's personal best",
+    ),
+    (
+        "best_possible_pattern",
+        "Name of the text source showing a slot's best possible time",
+    ),
+    (
+        "lower_third_format",
+        "Runner lower third text, with {name} and {handle} placeholders",
+    ),
+    (
+        "lower_third_platforms",
+        "Social platforms shown in lower thirds, in order of preference",
+    ),
+    (
+        "commentary_source_name",
+        "Name of the text source listing the commentators",
+    ),
+    (
+        "commentary_host_source_name",
+        "Name of the text source showing the commentary host",
+    ),
+    (
+        "stall_timeout_seconds",
+        "Seconds without playback progress before a runner is refreshed",
+    ),
+    (
+        "slow_request_millis",
+        "Milliseconds after which a web request is logged as slow",
+    ),
+    (
+        "rate_limit_per_minute",
+        "Changes per minute each web client may make, 0 to disable",
+    ),
+    ("rate_limit_burst", "Changes a web client may make at once"),
+    (
+        "self_service_token_minutes",
+        "Minutes a runner's self-service link can be used",
+    ),
+    (
+        "admin_token",
+        "Bearer token of the debug endpoints, which are disabled if null",
+    ),
+    (
+        "trigger_token",
+        "Token of the /trigger/ endpoints, which are disabled if null",
+    ),
+    (
+        "rotation_transition",
+        "Transition used when a layout rotation switches layouts",
+    ),
+    (
+        "relay_handoff_transition",
+        "Transition used when a relay runner hands off to the next",
+    ),
+    (
+        "rotation_pause_seconds",
+        "Seconds a rotation pauses after the layout is changed by hand",
+    ),
+    (
+        "slot_overflow",
+        "Runners in slots the layout lacks: \"reject\" the change or \"clamp\" them",
+    ),
+    (
+        "compact_slots",
+        "Move runners into the lowest slots of the layout, closing gaps",
+    ),
+    (
+        "on_deck_expiry_minutes",
+        "Minutes a runner stays on deck before their hidden source is let go",
+    ),
+    (
+        "tiltify_token",
+        "Tiltify API access token, donations are not polled if null",
+    ),
+    ("tiltify_campaign_id", "ID of the Tiltify campaign to poll"),
+    ("tiltify_poll_seconds", "Seconds between Tiltify polls"),
+    (
+        "tiltify_recent_donations",
+        "Number of recent donations kept for overlays",
+    ),
+    (
+        "donation_milestones",
+        "Donation totals that are announced once reached",
+    ),
+    (
+        "donation_webhook_url",
+        "URL to POST to when a donation milestone is reached",
+    ),
+    (
+        "message_trace_dir",
+        "Folder to record actor messages in for /debug/replay",
+    ),
+    (
+        "stream_url_ttl_minutes",
+        "Minutes a resolved runner stream URL is used",
+    ),
+    (
+        "allowed_origins",
+        "Browser origins allowed to use the web server, any if empty",
+    ),
+    (
+        "browser_source_groups",
+        "Browser sources whose URLs are set together, eg. by /league",
+    ),
+    (
+        "runner_filters",
+        "OBS filters of specific runners' sources, by runner name",
+    ),
+    (
+        "obs_dry_run",
+        "Log OBS changes instead of making them on every host",
+    ),
+    (
+        "timezone",
+        "UTC offset times are shown in, eg. +02:00, UTC if null",
+    ),
+];
+
+/// Explanations of the fields of an OBS host
+const OBS_HOST_HELP: &[(&str, &str)] = &[
+    (
+        "obs_ip",
+        "Address of the OBS websocket server, see Tools > WebSocket Server Settings",
+    ),
+    ("obs_port", "Port of the OBS websocket server"),
+    ("obs_password", "Password of the OBS websocket server"),
+    (
+        "discord_voice_channel",
+        "Discord voice channel whose members are the commentators",
+    ),
+    (
+        "discord_channels",
+        "Discord channel IDs whose commands default to this host",
+    ),
+    (
+        "discord_roles",
+        "Discord role IDs whose members' commands default to this host",
+    ),
+    (
+        "max_stream_height",
+        "Largest stream rendition height to pull for runners",
+    ),
+    (
+        "prefer_fps",
+        "Preferred framerate between renditions of the same height",
+    ),
+    (
+        "text_bindings",
+        "Text source templates by source name, eg. \"{event.game}\"",
+    ),
+    (
+        "intermission_scene",
+        "Scene shown by the intermission trigger",
+    ),
+    (
+        "twitch_channel",
+        "Twitch channel whose chat the chat bot joins",
+    ),
+    (
+        "runner_audio_tracks",
+        "Audio tracks (1-6) of runner sources, OBS defaults if null",
+    ),
+    (
+        "runner_monitor_type",
+        "Audio monitoring of runner sources: none, monitor_only or monitor_and_output",
+    ),
+    (
+        "commentary_input",
+        "Audio input carrying commentary, checked to be unmuted when going live",
+    ),
+    (
+        "public_stream_urls",
+        "Where viewers can watch, as {\"platform\": ..., \"url\": ...}",
+    ),
+    (
+        "require_all_outputs",
+        "Fail the pre-flight check if an output such as a restream is off",
+    ),
+    (
+        "runner_filters",
+        "OBS filters of runner sources, as {\"kind\", \"name\", \"settings\"}",
+    ),
+    (
+        "dry_run",
+        "Log OBS changes instead of connecting to OBS, for rehearsals",
+    ),
+];
+
+/// Write a JSON object with a `//` comment above each field that has an explanation.
+///
+/// Fields are written in the order of `help`, followed by any others.
+fn write_commented(
+    out: &mut String,
+    object: &Map<String, Value>,
+    help: &[(&str, &str)],
+    depth: usize,
+) {
+    let indent = "  ".repeat(depth);
+    let mut keys: Vec<&str> = help
+        .iter()
+        .map(|(key, _)| *key)
+        .filter(|key| object.contains_key(*key))
+        .collect();
+    keys.extend(
+        object
+            .keys()
+            .map(|key| key.as_str())
+            .filter(|key| !help.iter().any(|(k, _)| k == key)),
+    );
+
+    out.push_str("{\n");
+    for (idx, key) in keys.iter().enumerate() {
+        if let Some((_, text)) = help.iter().find(|(k, _)| k == key) {
+            out.push_str(&format!("{}// {}\n", indent, text));
+        }
+        out.push_str(&format!("{}{}: ", indent, Value::from(*key)));
+
+        match (*key, &object[*key]) {
+            ("obs_hosts", Value::Object(hosts)) => {
+                out.push_str("{\n");
+                for (host_idx, (name, host)) in hosts.iter().enumerate() {
+                    out.push_str(&format!("{}  {}: ", indent, Value::from(name.as_str())));
+                    match host {
+                        Value::Object(host) => write_commented(out, host, OBS_HOST_HELP, depth + 2),
+                        host => out.push_str(&host.to_string()),
+                    }
+                    out.push_str(if host_idx + 1 < hosts.len() {
+                        ",\n"
+                    } else {
+                        "\n"
+                    });
+                }
+                out.push_str(&format!("{}}}", indent));
+            }
+            (_, value) => {
+                let pretty = serde_json::to_string_pretty(value).unwrap();
+                out.push_str(&pretty.replace('\n', &format!("\n{}", indent)));
+            }
+        }
+        out.push_str(if idx + 1 < keys.len() { ",\n" } else { "\n" });
+    }
+    out.push_str(&"  ".repeat(depth - 1));
+    out.push('}');
+}
+
+/// Remove `//` comments outside of strings, keeping line breaks so errors point at the right line
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '/' && chars.peek() == Some(&'/') {
+            while chars.peek().is_some_and(|c| *c != '\n') {
+                chars.next();
+            }
+        } else {
+            in_string = c == '"';
+            out.push(c);
+        }
+    }
+    out
 }
@@ -1,18 +1,25 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
-use sqlx::prelude::FromRow;
-use tokio::sync::mpsc::UnboundedReceiver;
+use sqlx::{prelude::FromRow, types::Json};
 
 use crate::{
-    core::{db::ProjectDb, runner::RunnerRequest},
-    integrations::obs::ObsCommand,
-    send_message, ActorRef, Directory, Rto,
+    core::{db::ProjectStore, event::Event, runner::RunnerRequest, settings::Settings},
+    error::Error,
+    integrations::{
+        discord::DiscordCommand,
+        obs::{ObsCommand, ObsScene},
+        therun::{format_run_time, Run},
+        twitch_chat::TwitchChatCommand,
+    },
+    record_event, record_host, send_message, ActorMessage, ActorReceiver, ActorRef, Directory, Rto,
 };
+use tracing::{Instrument, Span};
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug, FromRow)]
 pub struct StreamState {
@@ -21,61 +28,476 @@ pub struct StreamState {
     /// Semicolon-separated list of commentators
     pub active_commentators: String,
     pub ignored_commentators: String,
+    /// Semicolon-separated voice channel members hidden because their Discord account or
+    /// role is ignored, set along with `active_commentators`
+    #[serde(default)]
+    pub ignored_voice_members: String,
     pub audible_runner: Option<i64>,
     pub requested_layout: Option<String>,
+    /// Whether this is the stream shown on its OBS host,
+    /// only one stream per host can be active
+    #[serde(default)]
+    pub active: bool,
+    /// Layouts cycled through automatically
+    pub rotation: Option<Json<LayoutRotation>>,
+    /// Semicolon-separated order of commentators, those not listed follow in voice channel order
+    #[serde(default)]
+    pub commentator_order: String,
+    /// Whether a scene other than a layout was switched to by hand,
+    /// which stops layouts from being applied until it is cleared
+    #[serde(default)]
+    pub manual_scene_override: bool,
+
+    /// Incremented on every save, updates with an older version are refused
+    #[serde(default)]
+    pub version: i64,
 
     #[sqlx(skip)]
     /// Map of viwe IDs to runner IDs
     pub stream_runners: HashMap<i64, i64>,
+
+    /// Delay added to the feed of each runner in milliseconds, by runner ID,
+    /// to line up feeds that arrive with different latencies
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub sync_offsets: HashMap<i64, u32>,
+
+    /// Number of slots of the layout the stream is shown with, if it is known.
+    /// Only filled in for state updates
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub layout_slot_count: Option<usize>,
+
+    /// Runners whose source is loaded but hidden, so it is buffered by the time they are
+    /// shown. Only changed with `StreamRequest::AddOnDeck` and `RemoveOnDeck`
+    #[serde(default)]
+    pub on_deck_runners: Json<Vec<OnDeckRunner>>,
+}
+
+/// A runner staged to join a stream
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct OnDeckRunner {
+    pub runner: i64,
+    /// Time the runner was put on deck in Unix millis
+    pub added_at: i64,
 }
 
+/// Default `on_deck_expiry_minutes`
+pub const DEFAULT_ON_DECK_EXPIRY_MINUTES: u64 = 15;
+/// Interval between checks for on deck runners that have expired
+const ON_DECK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Largest allowed sync offset of a runner's feed
+pub const MAX_SYNC_OFFSET_MS: u32 = 15_000;
+
 impl StreamState {
+    /// The sync offset of a runner in milliseconds, clamped to `MAX_SYNC_OFFSET_MS`
+    pub fn get_sync_offset(&self, runner: i64) -> u32 {
+        self.sync_offsets
+            .get(&runner)
+            .copied()
+            .unwrap_or(0)
+            .min(MAX_SYNC_OFFSET_MS)
+    }
+
+    /// Returns everyone in the commentary channel in the stored order,
+    /// followed by those without a position
+    pub fn get_ordered_commentators(&self) -> Vec<&str> {
+        let active: Vec<&str> = self.active_commentators.split(';').collect();
+
+        let mut commentators: Vec<&str> = self
+            .commentator_order
+            .split(';')
+            .filter(|c| !c.is_empty() && active.contains(c))
+            .collect();
+        for name in active {
+            if !commentators.contains(&name) {
+                commentators.push(name);
+            }
+        }
+
+        commentators
+    }
+
     /// Returns all active commentators
     pub fn get_commentators(&self) -> Vec<String> {
         let mut commentators = self
-            .active_commentators
-            .split(';')
+            .get_ordered_commentators()
+            .into_iter()
             .map(|s| s.to_string())
             .collect::<Vec<String>>();
 
-        let ignored_vec = self
-            .ignored_commentators
-            .split(';')
-            .map(|s| s.to_string())
-            .collect::<Vec<String>>();
+        let ignored = self.get_ignored_commentators();
+        commentators.retain(|c| !ignored.contains(&c.as_str()));
 
-        commentators.retain(|c| !&ignored_vec.contains(c));
+        commentators
+    }
+
+    /// Names hidden from the commentators, by name or as ignored voice channel members
+    pub fn get_ignored_commentators(&self) -> Vec<&str> {
+        self.ignored_commentators
+            .split(';')
+            .chain(self.ignored_voice_members.split(';'))
+            .filter(|c| !c.is_empty())
+            .collect()
+    }
 
+    /// The active commentators with the given host first, if they are one of them
+    pub fn get_commentators_with_host(&self, host: Option<&str>) -> Vec<String> {
+        let mut commentators = self.get_commentators();
+        if let Some(idx) = commentators.iter().position(|c| Some(c.as_str()) == host) {
+            let host = commentators.remove(idx);
+            commentators.insert(0, host);
+        }
         commentators
     }
+
+    /// Returns everyone in the commentary channel, matched against runners by name
+    pub async fn get_commentator_details(
+        &self,
+        db: &dyn ProjectStore,
+    ) -> anyhow::Result<Vec<Commentator>> {
+        let ignored = self.get_ignored_commentators();
+        let host = db.get_event(self.event).await?.commentary_host;
+
+        let mut commentators = vec![];
+        let mut aliases = None;
+        for name in self
+            .get_ordered_commentators()
+            .into_iter()
+            .filter(|c| !c.is_empty())
+        {
+            let runner = db.find_runner(name).await.ok();
+
+            let suggested_runner = match &runner {
+                Some(_) => None,
+                None => {
+                    if aliases.is_none() {
+                        aliases = Some(get_runner_aliases(db).await?);
+                    }
+                    let name = normalize_name(name);
+                    aliases
+                        .as_ref()
+                        .unwrap()
+                        .iter()
+                        .find(|(alias, _)| !name.is_empty() && *alias == name)
+                        .map(|(_, runner)| *runner)
+                }
+            };
+
+            commentators.push(Commentator {
+                name: name.to_owned(),
+                runner: runner.as_ref().map(|r| r.id),
+                location: runner.and_then(|r| r.location),
+                ignored: ignored.contains(&name),
+                host: host.as_deref() == Some(name),
+                suggested_runner,
+            });
+        }
+
+        Ok(commentators)
+    }
+}
+
+/// Lowercase a name and strip everything but letters and digits, for loose matching
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Normalized names, nicknames, Twitch handles and TheRun.gg usernames of all runners
+async fn get_runner_aliases(db: &dyn ProjectStore) -> anyhow::Result<Vec<(String, i64)>> {
+    let mut aliases = vec![];
+    for runner in db.get_runners().await? {
+        aliases.push((normalize_name(&runner.name), runner.id));
+        aliases.push((normalize_name(&runner.get_therun_username()), runner.id));
+        if let Some(stream) = runner.stream.as_ref().filter(|s| !s.contains('/')) {
+            aliases.push((normalize_name(stream), runner.id));
+        }
+    }
+
+    for (nick, runner) in db.get_nicknames().await? {
+        aliases.push((normalize_name(&nick), runner));
+    }
+
+    Ok(aliases)
+}
+
+/// How a stream preset picks the audible runner
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AudiblePolicy {
+    /// Keep the current audible runner while they stay in view
+    #[default]
+    Keep,
+    /// Make the runner in the lowest slot audible
+    FirstSlot,
+    /// Mute every runner
+    Muted,
+}
+
+/// How runners in slots that the layout of their stream does not have are handled
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotOverflow {
+    /// Refuse the update
+    #[default]
+    Reject,
+    /// Move the runners into free slots of the layout, refusing the update if there are none
+    Clamp,
+}
+
+/// A named stream lineup that can be applied to any stream.
+///
+/// Presets describe how runners are shown, not which runners are shown.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StreamPreset {
+    pub name: String,
+    /// Layout to request, or None to keep the stream's layout
+    pub requested_layout: Option<String>,
+    /// Number of runners in view, or None to keep the stream's runners
+    pub slot_count: Option<usize>,
+    #[serde(default)]
+    pub audible: AudiblePolicy,
+}
+
+impl StreamPreset {
+    /// Merge this preset into a stream.
+    ///
+    /// Runners in the lowest slots are kept, up to the preset's slot count.
+    pub fn apply_to(&self, stream: &mut StreamState) {
+        if let Some(layout) = &self.requested_layout {
+            stream.requested_layout = Some(layout.clone());
+        }
+
+        if let Some(count) = self.slot_count {
+            let mut slots: Vec<i64> = stream.stream_runners.keys().cloned().collect();
+            slots.sort();
+            for slot in slots.into_iter().skip(count) {
+                stream.stream_runners.remove(&slot);
+            }
+        }
+
+        stream.audible_runner = match self.audible {
+            AudiblePolicy::Keep => stream
+                .audible_runner
+                .filter(|r| stream.get_runner_slot(*r).is_some()),
+            AudiblePolicy::FirstSlot => stream
+                .stream_runners
+                .iter()
+                .min_by_key(|(slot, _)| **slot)
+                .map(|(_, runner)| *runner),
+            AudiblePolicy::Muted => None,
+        };
+    }
+}
+
+/// A commentator of a stream
+#[derive(Serialize, Clone, Debug)]
+pub struct Commentator {
+    pub name: String,
+    /// The runner with the same name, if any
+    pub runner: Option<i64>,
+    /// Location of the matching runner in ISO 3166-2
+    pub location: Option<String>,
+    /// Whether the commentator is hidden from the stream
+    pub ignored: bool,
+    /// Whether the commentator hosts the event's commentary
+    pub host: bool,
+    /// A runner whose name loosely matches that of an unmatched commentator,
+    /// shown to link them with `/link` and never applied automatically
+    pub suggested_runner: Option<i64>,
+}
+
+/// A problem found while validating a stream update
+#[derive(Serialize, Clone, Debug, thiserror::Error)]
+#[serde(tag = "type")]
+pub enum StreamViolation {
+    #[error("Slot {slot} contains unknown runner {runner}")]
+    UnknownRunner { slot: i64, runner: i64 },
+    #[error("Runner {runner} is in more than one slot")]
+    DuplicateRunner { runner: i64 },
+    #[error("Audible runner {runner} is not in view")]
+    AudibleRunnerNotInView { runner: i64 },
+    #[error("No OBS host has a layout named {layout}")]
+    UnknownLayout { layout: String },
+    #[error("No OBS host configuration found for host {host}")]
+    UnknownHost { host: String },
+    #[error("Layout rotation needs at least one layout")]
+    EmptyRotation,
+    #[error("Layout rotation dwell time must be at least {min} seconds")]
+    RotationTooShort { min: u64 },
+    #[error("Runner {runner} is in slot {slot}, which layout {layout} does not have")]
+    SlotNotInLayout {
+        slot: i64,
+        runner: i64,
+        layout: String,
+    },
+    #[error("No free slot on layout {layout} for runner {runner}")]
+    NoFreeSlot { runner: i64, layout: String },
+}
+
+/// A change made to a stream update to fit it to its layout, reported with the update
+#[derive(Serialize, Clone, Debug, thiserror::Error)]
+#[serde(tag = "type")]
+pub enum StreamWarning {
+    #[error("Runner {runner} was moved from slot {from} to slot {to} of layout {layout}")]
+    RunnerMoved {
+        runner: i64,
+        from: i64,
+        to: i64,
+        layout: String,
+    },
+}
+
+/// A stream update that failed validation
+#[derive(Debug)]
+pub struct StreamValidationError(pub Vec<StreamViolation>);
+
+impl std::fmt::Display for StreamValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.first() {
+            Some(first) if self.0.len() > 1 => {
+                write!(f, "{} (and {} more problems)", first, self.0.len() - 1)
+            }
+            Some(first) => write!(f, "{}", first),
+            None => write!(f, "Invalid stream update"),
+        }
+    }
 }
 
+impl std::error::Error for StreamValidationError {}
+
 /// Requests that can be sent to a StateActor
+// Streams are sent whole in updates, which are far more common than the other requests
+#[allow(clippy::large_enum_variant)]
 pub enum StreamRequest {
     Create(i64, String, Rto<()>),
     Reload(i64, Rto<()>),
-    Update(StreamState, Rto<()>),
+    /// Update a stream, skipping validation if `force` is set,
+    /// returning how runners were moved to fit the stream's layout
+    Update(StreamState, bool, Rto<Vec<StreamWarning>>),
     Delete(i64, Rto<()>),
+    /// Notify the stream manager that a runner's run data has changed
+    RunUpdated(i64),
+    /// Replace a relay runner with the next runner in the event,
+    /// returning the new runner if there is one
+    Handoff(i64, i64, Rto<Option<i64>>),
+    /// Apply a stream preset by name to the stream of an event,
+    /// returning how runners were moved to fit the stream's layout
+    ApplyPreset(i64, String, Rto<Vec<StreamWarning>>),
+    /// Make the stream of an event the active one on its OBS host and show it
+    Activate(i64, Rto<()>),
+    /// Set the order of the commentators of a stream by name
+    ReorderCommentators(i64, Vec<String>, Rto<()>),
+    /// Set the sync offset of a runner in a stream, in milliseconds: event, runner, offset
+    SetSyncOffset(i64, i64, u32, Rto<()>),
+    /// Show a scene on an OBS host by name: host, scene.
+    /// Scenes that are not layouts set the manual scene override of the host's stream
+    SwitchScene(String, String, Rto<()>),
+    /// Clear the manual scene override of the active stream on a host and show its layout again
+    ResumeLayout(String, Rto<()>),
+    /// Fill the empty slots of a stream with the event's runners in event order,
+    /// up to the slot count of the stream's layout
+    AutofillFromEvent(i64, Rto<()>),
+    /// Rebuild the OBS layout of an event's stream from scratch,
+    /// first removing the runner sources on its host if `purge` is set: event, purge
+    ForceResync(i64, bool, Rto<()>),
+    /// Recreate a deleted stream from a copy of it, showing it if its OBS host is free
+    Restore(StreamState, Rto<()>),
+    /// Load the source of a runner who is not shown yet, hidden: event, runner
+    AddOnDeck(i64, i64, Rto<()>),
+    /// Take a runner off deck, hiding or removing their source: event, runner
+    RemoveOnDeck(i64, i64, Rto<()>),
 }
 
 pub type StreamActor = ActorRef<StreamRequest>;
 
+impl ActorMessage for StreamRequest {
+    fn label(&self) -> &'static str {
+        match self {
+            StreamRequest::Create(..) => "Create",
+            StreamRequest::Reload(..) => "Reload",
+            StreamRequest::Update(..) => "Update",
+            StreamRequest::Delete(..) => "Delete",
+            StreamRequest::RunUpdated(..) => "RunUpdated",
+            StreamRequest::Handoff(..) => "Handoff",
+            StreamRequest::ApplyPreset(..) => "ApplyPreset",
+            StreamRequest::Activate(..) => "Activate",
+            StreamRequest::ReorderCommentators(..) => "ReorderCommentators",
+            StreamRequest::SetSyncOffset(..) => "SetSyncOffset",
+            StreamRequest::SwitchScene(..) => "SwitchScene",
+            StreamRequest::ResumeLayout(..) => "ResumeLayout",
+            StreamRequest::AutofillFromEvent(..) => "AutofillFromEvent",
+            StreamRequest::ForceResync(..) => "ForceResync",
+            StreamRequest::Restore(..) => "Restore",
+            StreamRequest::AddOnDeck(..) => "AddOnDeck",
+            StreamRequest::RemoveOnDeck(..) => "RemoveOnDeck",
+        }
+    }
+}
+
+/// Automatic cycling between layouts, for showing more runners than fit in one layout
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LayoutRotation {
+    pub layouts: Vec<String>,
+    /// Seconds each layout is shown
+    pub dwell_seconds: u64,
+    pub enabled: bool,
+}
+
+/// Default `dwell_seconds` of a layout rotation
+pub const DEFAULT_ROTATION_DWELL_SECS: u64 = 120;
+/// Shortest allowed `dwell_seconds` of a layout rotation
+const MIN_ROTATION_DWELL_SECS: u64 = 10;
+/// Default `rotation_pause_seconds`
+pub const DEFAULT_ROTATION_PAUSE_SECS: u64 = 120;
+/// Interval between checks for layout rotations that are due
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Timing of a stream's layout rotation
+struct RotationTimer {
+    last_switch: Instant,
+    /// Rotation is paused until this time after a manual layout change
+    paused_until: Option<Instant>,
+}
+
+impl RotationTimer {
+    fn new() -> Self {
+        Self {
+            last_switch: Instant::now(),
+            paused_until: None,
+        }
+    }
+}
+
 /// Elements of ProjectState that were modified during a state change.
 #[derive(PartialEq, Debug)]
 pub enum ModifiedStreamState {
     RunnerView(i64),
     Layout,
     Commentary,
+    /// The layout was changed by a layout rotation, which uses the rotation transition
+    Rotation,
+    /// A relay runner was handed off to the next, which uses the relay handoff transition
+    RelayHandoff,
 }
 
 /// Verify the ID of a streamed event.
 ///
 /// This function returns the contents of `event_id`,
-/// or attempts to get the name of the single active
-/// stream if `event_id` is `None`
+/// or attempts to get the name of the single stream, or of
+/// the single active stream if `event_id` is `None`
+/// Check that a stream exists for the given event, or pick the stream to use if none is given.
+///
+/// Without an event the streams on `host` are considered, or all streams if it has none.
+/// The only candidate stream is used, or else the only active one.
 pub async fn validate_streamed_event_id(
-    db: &ProjectDb,
+    db: &dyn ProjectStore,
     event_id: Option<i64>,
+    host: Option<&str>,
 ) -> anyhow::Result<i64> {
     if let Some(event_id) = event_id {
         match db.get_stream(event_id).await {
@@ -87,132 +509,1401 @@ pub async fn validate_streamed_event_id(
             )),
         }
     } else {
-        let count = db.get_stream_count().await?;
-        if count == 1 {
-            Ok(db.get_streamed_events().await?[0])
-        } else if count == 0 {
-            Err(anyhow!("Cannot determine event, no streams are active."))
+        let streams = db.get_stream_listing().await?;
+        let on_host: Vec<_> = streams
+            .iter()
+            .filter(|(_, _, obs_host, _)| Some(obs_host.as_str()) == host)
+            .collect();
+        let candidates = if on_host.is_empty() {
+            streams.iter().collect()
         } else {
-            Err(anyhow!(
-                "Multiple streams are active, please specify the streamed event to use."
-            ))
+            on_host
+        };
+
+        if let [(event, ..)] = candidates.as_slice() {
+            return Ok(*event);
+        }
+        if candidates.is_empty() {
+            return Err(anyhow!("Cannot determine event, no streams are active."));
+        }
+
+        let active: Vec<_> = candidates
+            .iter()
+            .copied()
+            .filter(|(.., active)| *active)
+            .collect();
+        match active.as_slice() {
+            [(event, ..)] => Ok(*event),
+            [] => Err(anyhow!(
+                "No stream is active, please specify the streamed event to use: {}.",
+                list_streams(&candidates)
+            )),
+            _ => Err(anyhow!(
+                "Multiple streams are active, please specify the streamed event to use: {}.",
+                list_streams(&active)
+            )),
         }
     }
 }
 
+/// List streams from `ProjectStore::get_stream_listing` by event name and OBS host
+fn list_streams(streams: &[&(i64, String, String, bool)]) -> String {
+    streams
+        .iter()
+        .map(|(_, name, obs_host, _)| format!("'{}' ({})", name, obs_host))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 pub async fn run_stream_manager(
-    db: Arc<ProjectDb>,
-    mut rx: UnboundedReceiver<StreamRequest>,
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    mut rx: ActorReceiver<StreamRequest>,
     directory: Directory,
 ) -> Result<(), anyhow::Error> {
     log::debug!("Started stream state manager");
-    while let Some(msg) = rx.recv().await {
-        match msg {
-            StreamRequest::Create(event, host, rto) => {
-                log::debug!("Creating stream for {} using {}", event, host);
-
-                let obs_host_data = send_message!(directory.obs_actor, ObsCommand, GetState);
-
-                if obs_host_data.is_err() {
-                    rto.reply(Err(anyhow!(
-                        "Failed to get OBS host data, cannot create stream for event {}.",
-                        event
-                    )));
-                } else if !obs_host_data.as_ref().unwrap().contains_key(&host) {
-                    rto.reply(Err(anyhow!(
-                        "Host '{}' is not a valid OBS host, cannot create stream for event {}.",
-                        host,
-                        event
-                    )));
-                } else if !obs_host_data.unwrap().get(&host).unwrap().connected {
-                    rto.reply(Err(anyhow!(
-                        "Host '{}' is not connected, cannot create stream for event {}.",
-                        host,
-                        event
-                    )));
-                } else if db
-                    .is_host_in_use(&host)
-                    .await
-                    .expect("Failed to get host usage")
-                {
-                    log::warn!(
-                        "Host '{}' is already in use, cannot create stream for event {}.",
-                        host,
-                        event
-                    );
-                    rto.reply(Err(anyhow!(
-                        "Host '{}' is already in use, cannot create stream for event {}.",
-                        host,
-                        event
-                    )));
-                } else if (db.get_stream(event).await).is_ok() {
-                    log::warn!(
-                        "Stream for event {} already exists, cannot create a new stream.",
-                        event
-                    );
-                    rto.reply(Err(anyhow!(
-                        "Stream for event {} already exists, cannot create a new stream.",
-                        event
-                    )));
-                } else {
-                    let state = StreamState {
-                        event,
-                        obs_host: host,
-                        active_commentators: "".to_string(),
-                        ignored_commentators: "".to_string(),
-                        requested_layout: None,
-                        stream_runners: HashMap::new(),
-                        audible_runner: None,
+
+    // Start time of the last run announced as finished by runner. Only the latest run is kept,
+    // as a runner's earlier runs can no longer finish.
+    let mut finished_runs: HashMap<i64, String> = HashMap::new();
+    let mut rotations: HashMap<i64, RotationTimer> = HashMap::new();
+    let rotation_pause = Duration::from_secs(
+        settings
+            .rotation_pause_seconds
+            .unwrap_or(DEFAULT_ROTATION_PAUSE_SECS),
+    );
+    let mut rotation_check = tokio::time::interval(ROTATION_CHECK_INTERVAL);
+    let on_deck_expiry = Duration::from_secs(
+        60 * settings
+            .on_deck_expiry_minutes
+            .unwrap_or(DEFAULT_ON_DECK_EXPIRY_MINUTES),
+    );
+    let mut on_deck_check = tokio::time::interval(ON_DECK_CHECK_INTERVAL);
+    // Messages taken off the channel early to look for newer stream updates
+    let mut pending: VecDeque<(StreamRequest, Span)> = VecDeque::new();
+    // Senders of updates dropped for a newer update from the same version, by event
+    let mut superseded: HashMap<i64, Vec<Rto<Vec<StreamWarning>>>> = HashMap::new();
+
+    loop {
+        let (msg, span) = match pending.pop_front() {
+            Some(msg) => msg,
+            None => tokio::select! {
+                msg = rx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                _ = rotation_check.tick() => {
+                    if let Err(e) = advance_rotations(&*db, &directory, &mut rotations).await {
+                        log::warn!("Failed to rotate layouts: {}", e);
+                    }
+                    continue;
+                }
+                _ = on_deck_check.tick() => {
+                    if let Err(e) = expire_on_deck_runners(&*db, &directory, on_deck_expiry).await {
+                        log::warn!("Failed to expire on deck runners: {}", e);
+                    }
+                    continue;
+                }
+            },
+        };
+
+        // Updates carry the whole stream, so a burst of them from the same version only needs
+        // the latest applied. The dropped ones are answered once it is.
+        if let StreamRequest::Update(stream, _, _) = &msg {
+            while let Ok(next) = rx.try_recv() {
+                pending.push_back(next);
+            }
+            if is_update_superseded(stream, &pending) {
+                log::debug!(
+                    "Skipping update of stream {}, a newer one is queued",
+                    stream.event
+                );
+                if let StreamRequest::Update(stream, _, rto) = msg {
+                    superseded.entry(stream.event).or_default().push(rto);
+                }
+                continue;
+            }
+        }
+
+        async {
+            match msg {
+                StreamRequest::Create(event, host, rto) => {
+                    record_event(event);
+                    record_host(&host);
+                    log::debug!("Creating stream for {} using {}", event, host);
+
+                    let obs_host_data = send_message!(directory.obs_actor, ObsCommand, GetState);
+
+                    if obs_host_data.is_err() {
+                        rto.reply(Err(anyhow!(
+                            "Failed to get OBS host data, cannot create stream for event {}.",
+                            event
+                        )));
+                    } else if !obs_host_data.as_ref().is_ok_and(|h| h.contains_key(&host)) {
+                        rto.reply(Err(anyhow!(
+                            "Host '{}' is not a valid OBS host, cannot create stream for event {}.",
+                            host,
+                            event
+                        )));
+                    } else if !obs_host_data.is_ok_and(|h| h[&host].connected) {
+                        rto.reply(Err(anyhow!(
+                            "Host '{}' is not connected, cannot create stream for event {}.",
+                            host,
+                            event
+                        )));
+                    } else if (db.get_stream(event).await).is_ok() {
+                        log::warn!(
+                            "Stream for event {} already exists, cannot create a new stream.",
+                            event
+                        );
+                        rto.reply(Err(anyhow!(
+                            "Stream for event {} already exists, cannot create a new stream.",
+                            event
+                        )));
+                    } else {
+                        // A host can prepare several streams, only the first one is shown
+                        let in_use = db
+                            .is_host_in_use(&host)
+                            .await
+                            .expect("Failed to get host usage");
+                        if in_use {
+                            log::info!(
+                                "Host '{}' is already in use, stream for event {} is created inactive.",
+                                host,
+                                event
+                            );
+                        }
+
+                        if !in_use {
+                            if let Err(e) = switch_scene_collection(&*db, &directory, event, &host).await {
+                                rto.reply(Err(e));
+                                return;
+                            }
+                        }
+
+                        let state = StreamState {
+                            event,
+                            obs_host: host,
+                            active_commentators: "".to_string(),
+                            ignored_commentators: "".to_string(),
+                            ignored_voice_members: "".to_string(),
+                            requested_layout: None,
+                            active: !in_use,
+                            rotation: None,
+                            commentator_order: "".to_string(),
+                            manual_scene_override: false,
+                            version: 0,
+                            stream_runners: HashMap::new(),
+                            sync_offsets: HashMap::new(),
+                            layout_slot_count: None,
+                            on_deck_runners: Json(vec![]),
+                            audible_runner: None,
+                        };
+
+                        match db.save_stream(&state).await {
+                            Ok(_) if !state.active => rto.reply(Ok(())),
+                            Ok(_) => {
+                                if let Ok(event) = db.get_event(event).await {
+                                    let mut text = format!("Now live: {}", event.name);
+                                    if let Some(urls) = settings
+                                        .obs_hosts
+                                        .get(&state.obs_host)
+                                        .and_then(|h| h.describe_public_stream_urls())
+                                    {
+                                        text = format!("{} - {}", text, urls);
+                                    }
+                                    directory.twitch_chat_actor.send(TwitchChatCommand::Announce(
+                                        Some(state.obs_host),
+                                        text,
+                                    ));
+                                }
+                                rto.reply(Ok(()))
+                            }
+                            Err(e) => {
+                                log::error!("Failed to create stream for event {}: {:?}", event, e);
+                                rto.reply(Err(e));
+                            }
+                        }
+                    }
+                }
+                StreamRequest::Update(new_stream, force, rto) => {
+                    record_event(new_stream.event);
+                    record_host(&new_stream.obs_host);
+                    let previous = db.get_stream(new_stream.event).await.ok();
+                    let updated = new_stream.clone();
+                    let res = if let Err(e) = check_stream_editable(&*db, &new_stream).await {
+                        Err(e)
+                    } else if force {
+                        log::debug!("Skipping validation for stream {}", new_stream.event);
+                        apply_stream_update(&*db, &directory, new_stream).await.map(|_| vec![])
+                    } else {
+                        validate_and_apply_stream_update(&*db, &settings, &directory, new_stream).await
                     };
 
-                    match db.save_stream(&state).await {
-                        Ok(_) => rto.reply(Ok(())),
-                        Err(e) => {
-                            log::error!("Failed to create stream for event {}: {:?}", event, e);
-                            rto.reply(Err(e));
+                    if let (Ok(_), Some(previous)) = (&res, previous) {
+                        note_stream_update(&mut rotations, &previous, &updated, rotation_pause);
+                    }
+                    for dropped in superseded.remove(&updated.event).unwrap_or_default() {
+                        dropped.reply(superseded_update_result(&*db, updated.event, &res).await);
+                    }
+                    rto.reply(res)
+                }
+                StreamRequest::ApplyPreset(event, preset, rto) => {
+                    record_event(event);
+                    let stream = db.get_stream(event).await;
+                    let preset = db.get_stream_preset(&preset).await;
+                    match (stream, preset) {
+                        (Ok(mut stream), Ok(preset)) => {
+                            record_host(&stream.obs_host);
+                            log::info!("Applying stream preset {} to event {}", preset.name, event);
+                            let original = stream.clone();
+                            preset.apply_to(&mut stream);
+                            if !original.active && !stream.determine_modified_state(&original).is_empty() {
+                                rto.reply(Err(inactive_stream_error(&stream)));
+                            } else {
+                                let updated = stream.clone();
+                                let res = validate_and_apply_stream_update(&*db, &settings, &directory, stream).await;
+                                if res.is_ok() {
+                                    note_stream_update(&mut rotations, &original, &updated, rotation_pause);
+                                }
+                                rto.reply(res)
+                            }
                         }
+                        (Err(e), _) | (_, Err(e)) => rto.reply(Err(e)),
                     }
                 }
-            }
-            StreamRequest::Update(new_stream, rto) => match db.get_stream(new_stream.event).await {
-                Ok(stream) => {
-                    let bad_runners = new_stream.trigger_refreshes(&stream, &directory).await;
-                    log::debug!("{:?}", bad_runners);
-                    let diffs = new_stream.determine_modified_state(&stream);
-                    db.save_stream(&new_stream).await?;
+                StreamRequest::Activate(event, rto) => {
+                    record_event(event);
+                    rto.reply(activate_stream(&*db, &directory, event).await)
+                }
+                StreamRequest::ForceResync(event, purge, rto) => {
+                    record_event(event);
+                    rto.reply(force_resync(&*db, &directory, event, purge).await)
+                }
+                StreamRequest::AutofillFromEvent(event, rto) => {
+                    record_event(event);
+                    let res = match autofill_stream(&*db, &directory, event).await {
+                        Ok((previous, stream)) => {
+                            let updated = stream.clone();
+                            let res = validate_and_apply_stream_update(&*db, &settings, &directory, stream).await;
+                            if res.is_ok() {
+                                note_stream_update(&mut rotations, &previous, &updated, rotation_pause);
+                            }
+                            res.map(|_| ())
+                        }
+                        Err(e) => Err(e),
+                    };
+                    rto.reply(res)
+                }
+                StreamRequest::ReorderCommentators(event, order, rto) => {
+                    record_event(event);
+                    rto.reply(reorder_commentators(&*db, &directory, event, order).await)
+                }
+                StreamRequest::SetSyncOffset(event, runner, offset, rto) => {
+                    record_event(event);
+                    rto.reply(set_sync_offset(&*db, &directory, event, runner, offset).await)
+                }
+                StreamRequest::AddOnDeck(event, runner, rto) => {
+                    record_event(event);
+                    rto.reply(add_on_deck(&*db, &directory, event, runner).await)
+                }
+                StreamRequest::RemoveOnDeck(event, runner, rto) => {
+                    record_event(event);
+                    rto.reply(remove_on_deck(&*db, &directory, event, runner).await)
+                }
+                StreamRequest::SwitchScene(host, scene, rto) => {
+                    record_host(&host);
+                    rto.reply(switch_scene(&*db, &directory, host, scene).await)
+                }
+                StreamRequest::ResumeLayout(host, rto) => {
+                    record_host(&host);
+                    let res = match db.get_event_by_obs_host(&host).await {
+                        Ok(event) => resume_layout(&*db, &directory, event).await,
+                        Err(e) => Err(e),
+                    };
+                    rto.reply(res)
+                }
+                StreamRequest::Reload(stream, rto) => {
+                    record_event(stream);
                     rto.reply(send_message!(
                         directory.obs_actor,
                         ObsCommand,
                         UpdateState,
-                        new_stream.event,
-                        diffs
-                    ));
+                        stream,
+                        Vec::<ModifiedStreamState>::new()
+                    ))
                 }
-                Err(e) => {
-                    rto.reply(Err(anyhow!(
-                        "No stream found for event '{}': {:?}.",
-                        new_stream.event,
-                        e
-                    )));
+                StreamRequest::Restore(stream, rto) => {
+                    record_event(stream.event);
+                    record_host(&stream.obs_host);
+                    rto.reply(restore_stream(&*db, &directory, stream).await)
                 }
-            },
-            StreamRequest::Reload(stream, rto) => rto.reply(send_message!(
+                StreamRequest::Delete(event, rto) => {
+                    record_event(event);
+                    let previous = db.get_stream(event).await;
+                    let res = db.delete_stream(event).await;
+                    if let (Ok(previous), Ok(_)) = (&previous, &res) {
+                        notify_stream_runners_changed(&directory, previous, None);
+                    }
+                    rto.reply(res);
+                }
+                StreamRequest::RunUpdated(runner) => {
+                    if let Err(e) = update_run_stats(&*db, &directory, runner).await {
+                        log::warn!("Failed to show run statistics of runner {}: {}", runner, e);
+                    }
+
+                    if let Ok(run) = db.get_runner_run_data(runner).await {
+                        if mark_finished(&mut finished_runs, runner, &run) {
+                            if let Err(e) =
+                                announce_finished_run(&*db, &directory, runner, &run).await
+                            {
+                                log::warn!("Failed to announce run for runner {}: {}", runner, e);
+                            }
+                        }
+
+                        if let Err(e) = check_relay_handoffs(&*db, &directory, runner, &run).await
+                        {
+                            log::warn!(
+                                "Failed to check relay handoff for runner {}: {}",
+                                runner,
+                                e
+                            );
+                        }
+                    }
+                }
+                StreamRequest::Handoff(event, runner, rto) => {
+                    record_event(event);
+                    rto.reply(relay_handoff(&*db, &directory, event, runner).await)
+                }
+            }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Show the run statistics of a runner on every active stream they are in
+async fn update_run_stats(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    runner: i64,
+) -> anyhow::Result<()> {
+    for event in db.get_streams_for_runner(runner).await? {
+        if db.get_stream(event).await?.active {
+            send_message!(
                 directory.obs_actor,
                 ObsCommand,
-                UpdateState,
-                stream,
-                Vec::<ModifiedStreamState>::new()
-            )),
-            StreamRequest::Delete(event, rto) => {
-                rto.reply(db.delete_stream(event).await);
+                UpdateRunStats,
+                event,
+                runner
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Restart the rotation timer of a stream if its rotation changed,
+/// or pause its rotation if its layout was changed by hand
+fn note_stream_update(
+    rotations: &mut HashMap<i64, RotationTimer>,
+    previous: &StreamState,
+    updated: &StreamState,
+    pause: Duration,
+) {
+    if previous.rotation != updated.rotation {
+        rotations.remove(&updated.event);
+    } else if previous.requested_layout != updated.requested_layout {
+        if let Some(timer) = rotations.get_mut(&updated.event) {
+            log::debug!(
+                "Layout of event {} changed by hand, pausing rotation for {:?}",
+                updated.event,
+                pause
+            );
+            timer.paused_until = Some(Instant::now() + pause);
+        }
+    }
+}
+
+/// Advance the layout of every active stream whose rotation is due
+async fn advance_rotations(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    rotations: &mut HashMap<i64, RotationTimer>,
+) -> anyhow::Result<()> {
+    let mut rotating = HashSet::new();
+    for event in db.get_active_streamed_events().await? {
+        let stream = db.get_stream(event).await?;
+        let Some(rotation) = stream.rotation.as_ref().filter(|r| r.enabled) else {
+            continue;
+        };
+
+        rotating.insert(event);
+        let timer = rotations.entry(event).or_insert_with(RotationTimer::new);
+        let dwell = Duration::from_secs(rotation.dwell_seconds);
+        if timer.paused_until.is_some_and(|t| t > Instant::now())
+            || timer.last_switch.elapsed() < dwell
+        {
+            continue;
+        }
+
+        timer.last_switch = Instant::now();
+        timer.paused_until = None;
+        if let Err(e) = rotate_layout(db, directory, stream).await {
+            log::warn!("Failed to rotate the layout of event {}: {}", event, e);
+        }
+    }
+
+    rotations.retain(|event, _| rotating.contains(event));
+    Ok(())
+}
+
+/// The first layout of `order` after `current` that `fits`, wrapping around to `current` itself.
+///
+/// Starts at the beginning of `order` if `current` is not in it.
+pub fn next_fitting_layout<'a>(
+    order: &'a [String],
+    current: Option<&String>,
+    fits: impl Fn(&String) -> bool,
+) -> Option<&'a String> {
+    let start = current
+        .and_then(|c| order.iter().position(|l| l == c))
+        .map(|idx| idx + 1)
+        .unwrap_or(0);
+    order
+        .iter()
+        .cycle()
+        .skip(start)
+        .take(order.len())
+        .find(|l| fits(l))
+}
+
+/// Switch a stream to the next layout of its rotation that has a runner in every slot,
+/// stopping the rotation if no layout does
+async fn rotate_layout(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    mut stream: StreamState,
+) -> anyhow::Result<()> {
+    let Some(Json(mut rotation)) = stream.rotation.clone() else {
+        return Ok(());
+    };
+
+    let mut hosts = send_message!(directory.obs_actor, ObsCommand, GetState)?;
+    let scenes = hosts
+        .remove(&stream.obs_host)
+        .map(|h| h.scenes)
+        .unwrap_or_default();
+    let fits = |layout: &String| {
+        scenes.get(layout).is_some_and(|scene| {
+            !scene.sources.is_empty()
+                && scene
+                    .sources
+                    .keys()
+                    .all(|slot| stream.stream_runners.contains_key(&(*slot as i64)))
+        })
+    };
+
+    let next =
+        next_fitting_layout(&rotation.layouts, stream.requested_layout.as_ref(), fits).cloned();
+
+    let Some(next) = next else {
+        log::info!(
+            "No rotation layout fits the runners of event {}, stopping rotation",
+            stream.event
+        );
+        rotation.enabled = false;
+        stream.rotation = Some(Json(rotation));
+        db.save_stream(&stream).await?;
+
+        let name = db.get_event(stream.event).await?.name;
+        directory.discord_actor.send(DiscordCommand::Notify(format!(
+            "Stopped layout rotation for {}, no rotation layout fits its runners",
+            name
+        )));
+        return Ok(());
+    };
+
+    if stream.requested_layout.as_ref() == Some(&next) {
+        return Ok(());
+    }
+
+    log::debug!("Rotating event {} to layout {}", stream.event, next);
+    stream.requested_layout = Some(next);
+    db.save_stream(&stream).await?;
+    send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        UpdateState,
+        stream.event,
+        vec![ModifiedStreamState::Layout, ModifiedStreamState::Rotation]
+    )
+}
+
+/// Collect the usable layouts of every connected OBS host, or None if no host is connected
+pub(crate) async fn get_layout_names(
+    settings: &Settings,
+    directory: &Directory,
+) -> Option<HashSet<String>> {
+    let mut layouts: Option<HashSet<String>> = None;
+    for host in settings.obs_hosts.keys() {
+        let host = host.clone();
+        if let Ok(scenes) = send_message!(directory.obs_actor, ObsCommand, GetSceneNames, host) {
+            layouts
+                .get_or_insert_with(HashSet::new)
+                .extend(scenes.into_iter().filter(|s| s.usable).map(|s| s.name));
+        }
+    }
+
+    layouts
+}
+
+/// Save a modified stream and apply the changes to OBS
+async fn apply_stream_update(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    new_stream: StreamState,
+) -> anyhow::Result<()> {
+    apply_stream_update_with(db, directory, new_stream, None).await
+}
+
+/// Save a modified stream and apply the changes to OBS, along with what caused them
+async fn apply_stream_update_with(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    new_stream: StreamState,
+    cause: Option<ModifiedStreamState>,
+) -> anyhow::Result<()> {
+    let stream = db
+        .get_stream(new_stream.event)
+        .await
+        .map_err(|e| anyhow!("No stream found for event '{}': {:?}.", new_stream.event, e))?;
+
+    let bad_runners = new_stream.trigger_refreshes(&stream, directory).await;
+    log::debug!("{:?}", bad_runners);
+    let mut diffs = new_stream.determine_modified_state(&stream);
+    diffs.extend(cause);
+    db.save_stream(&new_stream).await?;
+    notify_stream_runners_changed(directory, &stream, Some(&new_stream));
+    send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        UpdateState,
+        new_stream.event,
+        diffs
+    )
+}
+
+/// Tell the runner actor which runners joined or left a stream, or were in a deleted stream
+fn notify_stream_runners_changed(
+    directory: &Directory,
+    previous: &StreamState,
+    updated: Option<&StreamState>,
+) {
+    let before: HashSet<i64> = previous.stream_runners.values().copied().collect();
+    let after: HashSet<i64> = updated
+        .map(|s| s.stream_runners.values().copied().collect())
+        .unwrap_or_default();
+    let changed: Vec<i64> = before.symmetric_difference(&after).copied().collect();
+    if !changed.is_empty() {
+        directory
+            .runner_actor
+            .send(RunnerRequest::StreamRunnersChanged(changed));
+    }
+}
+
+/// Refuse an update that would change what OBS shows for a stream that is not active
+async fn check_stream_editable(
+    db: &dyn ProjectStore,
+    new_stream: &StreamState,
+) -> anyhow::Result<()> {
+    match db.get_stream(new_stream.event).await {
+        Ok(stream)
+            if !stream.active && !new_stream.determine_modified_state(&stream).is_empty() =>
+        {
+            Err(inactive_stream_error(&stream))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn inactive_stream_error(stream: &StreamState) -> anyhow::Error {
+    anyhow!(
+        "The stream for event {} is not active on host '{}', activate it before changing its layout, runners or commentary.",
+        stream.event,
+        stream.obs_host
+    )
+}
+
+/// The layout a stream with the given number of runners is shown with.
+///
+/// The requested layout and then the event's preferred layouts are used if the host has them,
+/// otherwise the smallest layout that fits every runner, or the largest one if none does.
+pub fn get_stream_layout<'a>(
+    event: &Event,
+    stream: &StreamState,
+    scenes: &'a HashMap<String, ObsScene>,
+    runner_count: usize,
+) -> Option<&'a ObsScene> {
+    if let Some(scene) = stream.requested_layout.as_ref().and_then(|l| scenes.get(l)) {
+        return Some(scene);
+    }
+
+    if let Some(scene) = event.preferred_layouts.iter().find_map(|l| scenes.get(l)) {
+        return Some(scene);
+    }
+
+    let mut layouts: Vec<&ObsScene> = scenes.values().filter(|s| !s.sources.is_empty()).collect();
+    layouts.sort_by(|a, b| (a.sources.len(), &a.name).cmp(&(b.sources.len(), &b.name)));
+    layouts
+        .iter()
+        .find(|s| s.sources.len() >= runner_count)
+        .or(layouts.last())
+        .copied()
+}
+
+/// The layout a stream with the given number of runners is shown with on its host,
+/// or None if the host is not connected
+pub(crate) async fn get_connected_stream_layout(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    stream: &StreamState,
+    runner_count: usize,
+) -> anyhow::Result<Option<ObsScene>> {
+    let event = db.get_event(stream.event).await?;
+    let hosts = send_message!(directory.obs_actor, ObsCommand, GetState)?;
+    Ok(hosts
+        .get(&stream.obs_host)
+        .filter(|h| h.connected && !h.dry_run)
+        .and_then(|h| get_stream_layout(&event, stream, &h.scenes, runner_count))
+        .cloned())
+}
+
+/// Fill a stream's empty slots from its event's runners, returning the stream before and after.
+///
+/// Runners already in view keep their slots, and runners that do not fit stay off-screen.
+async fn autofill_stream(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+) -> anyhow::Result<(StreamState, StreamState)> {
+    let previous = db.get_stream(event).await?;
+    if !previous.active {
+        return Err(inactive_stream_error(&previous));
+    }
+    let event_data = db.get_event(event).await?;
+
+    let hosts = send_message!(directory.obs_actor, ObsCommand, GetState)?;
+    let host = hosts
+        .get(&previous.obs_host)
+        .filter(|h| h.connected)
+        .ok_or_else(|| Error::ObsUnavailable(previous.obs_host.clone()))?;
+
+    let mut stream = previous.clone();
+    let waiting: Vec<i64> = db
+        .get_event_runner_order(event)
+        .await?
+        .into_iter()
+        .filter(|r| stream.get_runner_slot(*r).is_none())
+        .collect();
+
+    let runner_count = stream.stream_runners.len() + waiting.len();
+    let layout = get_stream_layout(&event_data, &stream, &host.scenes, runner_count)
+        .ok_or_else(|| anyhow!("OBS host '{}' has no layouts to fill", stream.obs_host))?;
+    let slots = layout.get_slots();
+
+    let mut waiting = waiting.into_iter();
+    for slot in &slots {
+        if stream.stream_runners.contains_key(slot) {
+            continue;
+        }
+        match waiting.next() {
+            Some(runner) => {
+                stream.stream_runners.insert(*slot, runner);
+            }
+            None => break,
+        }
+    }
+
+    log::info!(
+        "Autofilled stream {} to {} of {} slots",
+        event,
+        stream.stream_runners.len(),
+        slots.len()
+    );
+    Ok((previous, stream))
+}
+
+/// Whether an update of a stream is followed by another update of it from the same version
+/// in the queue, with no other request in between that could depend on it
+fn is_update_superseded(stream: &StreamState, pending: &VecDeque<(StreamRequest, Span)>) -> bool {
+    for (msg, _) in pending {
+        match msg {
+            StreamRequest::Update(next, _, _) if next.event == stream.event => {
+                return next.version == stream.version
             }
+            StreamRequest::Update(..) => {}
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// The result of an update that was dropped for a newer update from the same version.
+///
+/// Both were edits of the same copy, so once the newer one is saved the dropped one conflicts
+/// with it. If the newer one failed, the dropped one is refused with the same error.
+async fn superseded_update_result(
+    db: &dyn ProjectStore,
+    event: i64,
+    applied: &anyhow::Result<Vec<StreamWarning>>,
+) -> anyhow::Result<Vec<StreamWarning>> {
+    if let Err(e) = applied {
+        if let Some(StreamValidationError(violations)) = e.downcast_ref() {
+            return Err(StreamValidationError(violations.clone()).into());
+        }
+        if !matches!(e.downcast_ref(), Some(Error::Conflict(..))) {
+            return Err(anyhow!("{}", e));
+        }
+    }
+
+    let current = db.get_stream(event).await?;
+    Err(Error::Conflict(
+        format!("Stream for event {}", event),
+        serde_json::to_value(current)?,
+    )
+    .into())
+}
+
+/// Apply every part of a stream to OBS, as if all of it had changed
+async fn force_resync(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    purge: bool,
+) -> anyhow::Result<()> {
+    let stream = db.get_stream(event).await?;
+    if !stream.active {
+        return Err(anyhow!(
+            "The stream of event {} is not the one shown on {}",
+            event,
+            stream.obs_host
+        ));
+    }
+
+    if purge {
+        let removed = send_message!(directory.obs_actor, ObsCommand, PurgeRunnerSources, event)?;
+        log::info!(
+            "Removed {} runner source(s) from {} to resync event {}",
+            removed,
+            stream.obs_host,
+            event
+        );
+    }
+
+    let mut modifications = vec![ModifiedStreamState::Layout, ModifiedStreamState::Commentary];
+    modifications.extend(
+        stream
+            .stream_runners
+            .values()
+            .map(|r| ModifiedStreamState::RunnerView(*r)),
+    );
+    log::info!("Resyncing OBS for event {}", event);
+    send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        UpdateState,
+        event,
+        modifications
+    )
+}
+
+/// Store the order of a stream's commentators, updating the commentary shown in OBS
+async fn reorder_commentators(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    order: Vec<String>,
+) -> anyhow::Result<()> {
+    if let Some(name) = order.iter().find(|name| name.contains(';')) {
+        return Err(anyhow!("Commentator name '{}' cannot contain ';'", name));
+    }
+
+    let mut stream = db.get_stream(event).await?;
+    record_host(&stream.obs_host);
+    stream.commentator_order = order.join(";");
+    check_stream_editable(db, &stream).await?;
+
+    // Only the commentators change, so the rest of the stream is not revalidated
+    apply_stream_update(db, directory, stream).await
+}
+
+/// Store the sync offset of a runner and apply it to their source only
+async fn set_sync_offset(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    runner: i64,
+    offset: u32,
+) -> anyhow::Result<()> {
+    if offset > MAX_SYNC_OFFSET_MS {
+        log::warn!(
+            "Sync offset of {} ms for runner {} is clamped to {} ms",
+            offset,
+            runner,
+            MAX_SYNC_OFFSET_MS
+        );
+    }
+
+    db.set_stream_sync_offset(event, runner, offset.min(MAX_SYNC_OFFSET_MS))
+        .await?;
+    send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        ApplySyncOffset,
+        event,
+        runner
+    )
+}
+
+/// The current time in Unix millis
+fn now_millis() -> i64 {
+    (sqlx::types::time::OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+/// Put a runner on deck for a stream, so their source is loaded and buffering while hidden.
+///
+/// Putting a runner on deck again restarts their expiry.
+async fn add_on_deck(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    runner: i64,
+) -> anyhow::Result<()> {
+    let stream = db.get_stream(event).await?;
+    let name = db.get_name_for_runner(runner).await?;
+    if stream.stream_runners.values().any(|r| *r == runner) {
+        return Err(Error::InvalidRequest(
+            "runner".to_owned(),
+            format!("{} is already shown in the stream", name),
+        ))?;
+    }
+
+    // The URL is resolved now, so it is not resolved again when the runner is shown
+    send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        RefreshStream,
+        runner,
+        Some(stream.obs_host.clone())
+    )?;
+
+    let mut on_deck = stream.on_deck_runners.0;
+    on_deck.retain(|r| r.runner != runner);
+    on_deck.push(OnDeckRunner {
+        runner,
+        added_at: now_millis(),
+    });
+    db.set_stream_on_deck(event, &on_deck).await?;
+    log::info!("Put {} on deck for event {}", name, event);
+
+    send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        UpdateState,
+        event,
+        Vec::<ModifiedStreamState>::new()
+    )
+}
+
+/// Take a runner off deck, leaving their source to be hidden or removed like any unused source
+async fn remove_on_deck(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    runner: i64,
+) -> anyhow::Result<()> {
+    let stream = db.get_stream(event).await?;
+    let name = db.get_name_for_runner(runner).await?;
+    let mut on_deck = stream.on_deck_runners.0;
+    if !on_deck.iter().any(|r| r.runner == runner) {
+        return Err(Error::InvalidRequest(
+            "runner".to_owned(),
+            format!("{} is not on deck", name),
+        ))?;
+    }
+
+    on_deck.retain(|r| r.runner != runner);
+    db.set_stream_on_deck(event, &on_deck).await?;
+    log::info!("Took {} off deck for event {}", name, event);
+
+    send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        UpdateState,
+        event,
+        Vec::<ModifiedStreamState>::new()
+    )
+}
+
+/// Take runners off deck once they have been on deck for longer than `expiry`,
+/// so the hidden sources of runners who never joined are not kept forever
+async fn expire_on_deck_runners(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    expiry: Duration,
+) -> anyhow::Result<()> {
+    let cutoff = now_millis() - expiry.as_millis() as i64;
+    for event in db.get_streamed_events().await? {
+        let stream = db.get_stream(event).await?;
+        let (kept, expired): (Vec<_>, Vec<_>) = stream
+            .on_deck_runners
+            .0
+            .into_iter()
+            .partition(|r| r.added_at > cutoff);
+        if expired.is_empty() {
+            continue;
+        }
+
+        for runner in &expired {
+            let name = db
+                .get_name_for_runner(runner.runner)
+                .await
+                .unwrap_or_else(|_| runner.runner.to_string());
+            log::info!(
+                "{} was on deck for event {} for over {:?}, taking them off deck",
+                name,
+                event,
+                expiry
+            );
+        }
+        db.set_stream_on_deck(event, &kept).await?;
+        if let Err(e) = send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            UpdateState,
+            event,
+            Vec::<ModifiedStreamState>::new()
+        ) {
+            log::warn!(
+                "Failed to update OBS for event {} after expiring on deck runners: {}",
+                event,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Show a scene on an OBS host.
+///
+/// Layouts are shown through the host's active stream, so its runners are placed in them.
+/// Other scenes, such as a technical difficulties screen, are shown directly and stop
+/// layouts from being applied until `resume_layout` is called.
+async fn switch_scene(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    host: String,
+    scene: String,
+) -> anyhow::Result<()> {
+    let scenes = send_message!(directory.obs_actor, ObsCommand, GetSceneNames, host.clone())?;
+    let usable = scenes
+        .iter()
+        .find(|s| s.name == scene)
+        .ok_or_else(|| anyhow!("OBS host {} has no scene named {}", host, scene))?
+        .usable;
+
+    let Ok(event) = db.get_event_by_obs_host(&host).await else {
+        return send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            SetProgramScene,
+            host,
+            scene
+        );
+    };
+
+    let mut stream = db.get_stream(event).await?;
+    if usable {
+        stream.requested_layout = Some(scene);
+        db.save_stream(&stream).await?;
+        resume_layout(db, directory, event).await
+    } else {
+        log::info!(
+            "Showing scene {} on {}, layouts are not applied until resumed",
+            scene,
+            host
+        );
+        stream.manual_scene_override = true;
+        db.save_stream(&stream).await?;
+        send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            SetProgramScene,
+            host,
+            scene
+        )
+    }
+}
+
+/// Clear the manual scene override of a stream and apply its layout
+async fn resume_layout(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+) -> anyhow::Result<()> {
+    let mut stream = db.get_stream(event).await?;
+    if stream.manual_scene_override {
+        stream.manual_scene_override = false;
+        db.save_stream(&stream).await?;
+    }
+
+    send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        UpdateState,
+        event,
+        vec![ModifiedStreamState::Layout, ModifiedStreamState::Commentary]
+    )
+}
+
+/// Switch an OBS host to the scene collection of an event, if the event has one
+async fn switch_scene_collection(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    host: &str,
+) -> anyhow::Result<()> {
+    match db.get_event(event).await?.scene_collection {
+        Some(collection) => send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            SetSceneCollection,
+            host.to_owned(),
+            collection
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Make a stream the active one on its host and refresh everything OBS shows for it
+async fn activate_stream(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+) -> anyhow::Result<()> {
+    let stream = db.get_stream(event).await?;
+    record_host(&stream.obs_host);
+    log::info!(
+        "Activating stream for event {} on {}",
+        event,
+        stream.obs_host
+    );
+
+    // The layout is applied to the event's scene collection, so switch to it first
+    switch_scene_collection(db, directory, event, &stream.obs_host).await?;
+
+    db.activate_stream(event).await?;
+    resume_layout(db, directory, event).await
+}
+
+/// Save a stream that was deleted, activating it unless its host shows another stream
+async fn restore_stream(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    mut stream: StreamState,
+) -> anyhow::Result<()> {
+    if db.get_stream(stream.event).await.is_ok() {
+        return Err(anyhow!(
+            "Stream for event {} already exists, cannot restore it.",
+            stream.event
+        ));
+    }
+
+    log::info!(
+        "Restoring stream for event {} on {}",
+        stream.event,
+        stream.obs_host
+    );
+    stream.active = false;
+    stream.manual_scene_override = false;
+    stream.version = 0;
+    db.save_stream(&stream).await?;
+
+    let empty = StreamState {
+        stream_runners: HashMap::new(),
+        ..stream.clone()
+    };
+    notify_stream_runners_changed(directory, &empty, Some(&stream));
+
+    if db.is_host_in_use(&stream.obs_host).await? {
+        log::info!(
+            "Host '{}' is already in use, stream for event {} is restored inactive.",
+            stream.obs_host,
+            stream.event
+        );
+    } else if let Err(e) = activate_stream(db, directory, stream.event).await {
+        // The stream is kept, it can still be activated once the host is reachable
+        log::warn!(
+            "Failed to activate restored stream for event {}: {}",
+            stream.event,
+            e
+        );
+    }
+    Ok(())
+}
+
+/// Validate a stream update and fit its runners to its layout,
+/// applying it if there are no violations
+async fn validate_and_apply_stream_update(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+    mut new_stream: StreamState,
+) -> anyhow::Result<Vec<StreamWarning>> {
+    // Layouts are only needed to check a requested layout, and asking every host is slow
+    let layouts = match new_stream.requested_layout {
+        Some(_) => get_layout_names(settings, directory).await,
+        None => None,
+    };
+    let mut violations = new_stream.validate(db, settings, layouts.as_ref()).await?;
+
+    // Streams are only fit when their runners or layout change, so that a stream left in
+    // an unfit state does not block unrelated changes such as its commentators
+    let mut warnings = vec![];
+    let unchanged = db.get_stream(new_stream.event).await.is_ok_and(|s| {
+        s.stream_runners == new_stream.stream_runners
+            && s.requested_layout == new_stream.requested_layout
+    });
+    if !unchanged {
+        let runner_count = new_stream.stream_runners.len();
+        match get_connected_stream_layout(db, directory, &new_stream, runner_count).await {
+            Ok(Some(layout)) => match new_stream.fit_to_layout(
+                &layout,
+                settings.slot_overflow.unwrap_or_default(),
+                settings.compact_slots.unwrap_or(false),
+            ) {
+                Ok(moved) => warnings = moved,
+                Err(unfit) => violations.extend(unfit),
+            },
+            Ok(None) => {}
+            Err(e) => log::warn!(
+                "Failed to get the layout of stream {}, slots are not checked: {}",
+                new_stream.event,
+                e
+            ),
+        }
+    }
+
+    if violations.is_empty() {
+        for warning in &warnings {
+            log::info!("Stream {}: {}", new_stream.event, warning);
+        }
+        apply_stream_update(db, directory, new_stream).await?;
+        Ok(warnings)
+    } else {
+        Err(StreamValidationError(violations).into())
+    }
+}
+
+/// Announce a finished run in the Twitch chat of every host showing the runner
+async fn announce_finished_run(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    runner: i64,
+    run: &Run,
+) -> anyhow::Result<()> {
+    let name = db.get_name_for_runner(runner).await?;
+    let text = match run.splits.last().and_then(|s| s.split_time) {
+        Some(time) => format!("{} finished in {}!", name, format_run_time(time)),
+        None => format!("{} finished their run!", name),
+    };
+
+    for event in db.get_streams_for_runner(runner).await? {
+        let stream = db.get_stream(event).await?;
+        directory
+            .twitch_chat_actor
+            .send(TwitchChatCommand::Announce(
+                Some(stream.obs_host),
+                text.clone(),
+            ));
+    }
+
+    Ok(())
+}
+
+/// Record a run as announced if it has finished, returning whether it was not announced yet
+fn mark_finished(finished_runs: &mut HashMap<i64, String>, runner: i64, run: &Run) -> bool {
+    if !run.is_finished() || finished_runs.get(&runner) == Some(&run.started_at) {
+        return false;
+    }
+    finished_runs.insert(runner, run.started_at.clone());
+    true
+}
+
+/// Hand off any relay streams where the given runner has reached their handoff split
+async fn check_relay_handoffs(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    runner: i64,
+    run: &Run,
+) -> anyhow::Result<()> {
+    for event in db.get_streams_for_runner(runner).await? {
+        let event_data = db.get_event(event).await?;
+        if event_data.is_relay
+            && event_data.auto_relay_handoff
+            && reached_handoff(run, event_data.relay_handoff_split.as_deref())
+        {
+            relay_handoff(db, directory, event, runner).await?;
         }
     }
 
     Ok(())
 }
 
+/// Whether a run has completed the split named `split`, or its final split if the run
+/// has no such split or none is given
+fn reached_handoff(run: &Run, split: Option<&str>) -> bool {
+    let handoff = split.and_then(|name| {
+        run.splits
+            .iter()
+            .position(|s| s.name.trim().eq_ignore_ascii_case(name.trim()))
+    });
+    match handoff {
+        Some(index) => run.current_split_index > index as i64,
+        None => run.is_finished(),
+    }
+}
+
+/// Replace a relay runner with the next runner of the event that is not yet in view.
+///
+/// Runners are taken in the order they were added to the event. As the finished
+/// runner leaves view, repeated calls for the same runner have no effect.
+async fn relay_handoff(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    runner: i64,
+) -> anyhow::Result<Option<i64>> {
+    let mut stream = db.get_stream(event).await?;
+    let slot = stream
+        .get_runner_slot(runner)
+        .ok_or_else(|| anyhow!("Runner {} is not in view for event {}", runner, event))?;
+
+    let order = db.get_event_runner_order(event).await?;
+    let next = order
+        .iter()
+        .skip_while(|r| **r != runner)
+        .skip(1)
+        .find(|r| stream.get_runner_slot(**r).is_none())
+        .cloned();
+
+    let event_name = db.get_event(event).await?.name;
+    let runner_name = db.get_name_for_runner(runner).await?;
+
+    match next {
+        Some(next) => {
+            log::info!(
+                "Relay handoff in {} from {} to {}",
+                event_name,
+                runner,
+                next
+            );
+            stream.stream_runners.insert(slot, next);
+            if stream.audible_runner == Some(runner) {
+                stream.audible_runner = Some(next);
+            }
+            let cause = Some(ModifiedStreamState::RelayHandoff);
+            apply_stream_update_with(db, directory, stream, cause).await?;
+
+            let next_name = db.get_name_for_runner(next).await?;
+            directory.discord_actor.send(DiscordCommand::Notify(format!(
+                "Relay handoff in {}: {} \u{2192} {}",
+                event_name, runner_name, next_name
+            )));
+            Ok(Some(next))
+        }
+        None => {
+            log::info!(
+                "{} finished the final leg of {}, no handoff needed",
+                runner_name,
+                event_name
+            );
+            Ok(None)
+        }
+    }
+}
+
 impl StreamState {
+    /// Check this stream for invalid runners, layouts, and hosts.
+    ///
+    /// The layout is only checked if a list of known layouts is provided.
+    pub async fn validate(
+        &self,
+        db: &dyn ProjectStore,
+        settings: &Settings,
+        layouts: Option<&HashSet<String>>,
+    ) -> anyhow::Result<Vec<StreamViolation>> {
+        let mut violations = vec![];
+
+        if !settings.obs_hosts.contains_key(&self.obs_host) {
+            violations.push(StreamViolation::UnknownHost {
+                host: self.obs_host.clone(),
+            });
+        }
+
+        let mut slots: Vec<_> = self.stream_runners.iter().collect();
+        slots.sort();
+        let mut seen = HashSet::new();
+        for (slot, runner) in slots {
+            if db.get_runner(*runner).await.is_err() {
+                violations.push(StreamViolation::UnknownRunner {
+                    slot: *slot,
+                    runner: *runner,
+                });
+            } else if !seen.insert(*runner) {
+                violations.push(StreamViolation::DuplicateRunner { runner: *runner });
+            }
+        }
+
+        if let Some(runner) = self.audible_runner {
+            if self.get_runner_slot(runner).is_none() {
+                violations.push(StreamViolation::AudibleRunnerNotInView { runner });
+            }
+        }
+
+        if let (Some(layout), Some(layouts)) = (&self.requested_layout, layouts) {
+            if !layouts.contains(layout) {
+                violations.push(StreamViolation::UnknownLayout {
+                    layout: layout.clone(),
+                });
+            }
+        }
+
+        if let Some(rotation) = self.rotation.as_ref().filter(|r| r.enabled) {
+            if rotation.layouts.is_empty() {
+                violations.push(StreamViolation::EmptyRotation);
+            }
+            if rotation.dwell_seconds < MIN_ROTATION_DWELL_SECS {
+                violations.push(StreamViolation::RotationTooShort {
+                    min: MIN_ROTATION_DWELL_SECS,
+                });
+            }
+            if let Some(layouts) = layouts {
+                for layout in &rotation.layouts {
+                    if !layouts.contains(layout) {
+                        violations.push(StreamViolation::UnknownLayout {
+                            layout: layout.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(violations)
+    }
+
     pub async fn trigger_refreshes(&self, old: &StreamState, directory: &Directory) -> Vec<i64> {
         let old_runners = HashSet::<i64>::from_iter(old.stream_runners.values().cloned());
         let new_runners = HashSet::<i64>::from_iter(self.stream_runners.values().cloned());
@@ -220,11 +1911,17 @@ impl StreamState {
 
         let mut bad_runners = vec![];
         for runner in added_runners {
+            // The source of an on deck runner is already playing their resolved URL,
+            // resolving it again would restart the buffering
+            if old.on_deck_runners.iter().any(|r| r.runner == *runner) {
+                continue;
+            }
             if let Err(e) = send_message!(
                 directory.runner_actor,
                 RunnerRequest,
                 RefreshStream,
-                *runner
+                *runner,
+                Some(self.obs_host.clone())
             ) {
                 log::warn!(
                     "Failed to update runner stream for {} when entering view: {:?}",
@@ -266,11 +1963,410 @@ impl StreamState {
             .find_map(|(k, v)| if *v == runner { Some(*k) } else { None })
     }
 
-    pub fn get_first_empty_slot(&self) -> i64 {
-        let mut i = 1;
-        while self.stream_runners.contains_key(&i) {
-            i += 1;
+    /// The lowest free slot, among the given slots of a layout if they are known
+    pub fn get_first_empty_slot(&self, layout_slots: Option<&[i64]>) -> Option<i64> {
+        match layout_slots {
+            Some(slots) => slots
+                .iter()
+                .find(|slot| !self.stream_runners.contains_key(slot))
+                .copied(),
+            None => {
+                let mut i = 1;
+                while self.stream_runners.contains_key(&i) {
+                    i += 1;
+                }
+                Some(i)
+            }
+        }
+    }
+
+    /// Move runners into the slots of a layout, returning how they were moved.
+    ///
+    /// Runners in slots the layout does not have are refused or moved to its lowest free slots
+    /// depending on `overflow`, and with `compact` every runner is moved down to close gaps,
+    /// keeping their order.
+    pub fn fit_to_layout(
+        &mut self,
+        layout: &ObsScene,
+        overflow: SlotOverflow,
+        compact: bool,
+    ) -> Result<Vec<StreamWarning>, Vec<StreamViolation>> {
+        let slots = layout.get_slots();
+        let mut outside: Vec<(i64, i64)> = self
+            .stream_runners
+            .iter()
+            .filter(|(slot, _)| !slots.contains(slot))
+            .map(|(slot, runner)| (*slot, *runner))
+            .collect();
+        outside.sort();
+
+        let mut violations = vec![];
+        let mut warnings = vec![];
+        for (slot, runner) in outside {
+            match overflow {
+                SlotOverflow::Reject => violations.push(StreamViolation::SlotNotInLayout {
+                    slot,
+                    runner,
+                    layout: layout.name.clone(),
+                }),
+                SlotOverflow::Clamp => {
+                    self.stream_runners.remove(&slot);
+                    match self.get_first_empty_slot(Some(&slots)) {
+                        Some(to) => {
+                            self.stream_runners.insert(to, runner);
+                            warnings.push(StreamWarning::RunnerMoved {
+                                runner,
+                                from: slot,
+                                to,
+                                layout: layout.name.clone(),
+                            });
+                        }
+                        None => {
+                            self.stream_runners.insert(slot, runner);
+                            violations.push(StreamViolation::NoFreeSlot {
+                                runner,
+                                layout: layout.name.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        if !violations.is_empty() {
+            return Err(violations);
+        }
+
+        if compact {
+            let mut occupied: Vec<(i64, i64)> =
+                self.stream_runners.iter().map(|(s, r)| (*s, *r)).collect();
+            occupied.sort();
+            for ((from, runner), to) in occupied.into_iter().zip(slots) {
+                if from == to {
+                    continue;
+                }
+                self.stream_runners.remove(&from);
+                self.stream_runners.insert(to, runner);
+                match warnings.iter_mut().find_map(|w| match w {
+                    StreamWarning::RunnerMoved { runner: r, to, .. } if *r == runner => Some(to),
+                    _ => None,
+                }) {
+                    Some(moved_to) => *moved_to = to,
+                    None => warnings.push(StreamWarning::RunnerMoved {
+                        runner,
+                        from,
+                        to,
+                        layout: layout.name.clone(),
+                    }),
+                }
+            }
         }
-        i
+
+        Ok(warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::TestActors;
+
+    /// Change the current copy of a stream and send it to the stream actor,
+    /// as the Discord and web commands do
+    async fn update(
+        actors: &TestActors,
+        event: i64,
+        change: impl FnOnce(&mut StreamState),
+    ) -> anyhow::Result<Vec<StreamWarning>> {
+        let mut stream = actors.db.get_stream(event).await?;
+        change(&mut stream);
+        send_message!(
+            actors.directory.stream_actor,
+            StreamRequest,
+            Update,
+            stream,
+            false
+        )
+    }
+
+    fn violations(e: anyhow::Error) -> Vec<StreamViolation> {
+        e.downcast::<StreamValidationError>().unwrap().0
+    }
+
+    /// Queue updates of the runners of the current copy of a stream before the stream actor
+    /// runs, as dashboards editing it at the same time do
+    async fn queue_runner_updates(
+        actors: &TestActors,
+        event: i64,
+        updates: &[HashMap<i64, i64>],
+    ) -> Vec<anyhow::Result<Vec<StreamWarning>>> {
+        let current = actors.db.get_stream(event).await.unwrap();
+        let mut replies = vec![];
+        for runners in updates {
+            let mut stream = current.clone();
+            stream.stream_runners = runners.clone();
+            let (rto, reply) = Rto::new();
+            actors
+                .directory
+                .stream_actor
+                .send(StreamRequest::Update(stream, false, rto));
+            replies.push(reply);
+        }
+
+        let mut results = vec![];
+        for reply in replies {
+            results.push(reply.await.unwrap());
+        }
+        results
+    }
+
+    #[tokio::test]
+    async fn queued_updates_from_the_same_version_conflict() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let second = actors.add_runner("second").await;
+        let event = actors.add_streamed_event("Race", &[first, second]).await;
+
+        let updates = [HashMap::from([(0, first)]), HashMap::from([(0, second)])];
+        let results = queue_runner_updates(&actors, event, &updates).await;
+
+        let stream = actors.db.get_stream(event).await.unwrap();
+        assert_eq!(stream.stream_runners, HashMap::from([(0, second)]));
+        assert!(results[1].is_ok());
+        let err = results[0].as_ref().unwrap_err();
+        match err.downcast_ref::<Error>() {
+            Some(Error::Conflict(_, current)) => {
+                assert_eq!(current["version"], serde_json::json!(stream.version))
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_update_shares_the_validation_error_of_the_newer_one() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let event = actors.add_streamed_event("Race", &[first]).await;
+
+        let updates = [HashMap::from([(0, first)]), HashMap::from([(0, 404)])];
+        for result in queue_runner_updates(&actors, event, &updates).await {
+            assert!(matches!(
+                violations(result.unwrap_err()).as_slice(),
+                [StreamViolation::UnknownRunner {
+                    slot: 0,
+                    runner: 404
+                }]
+            ));
+        }
+        assert!(actors
+            .db
+            .get_stream(event)
+            .await
+            .unwrap()
+            .stream_runners
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn set_runners_is_saved_and_shown() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let second = actors.add_runner("second").await;
+        let event = actors.add_streamed_event("Race", &[first, second]).await;
+
+        update(&actors, event, |s| {
+            s.stream_runners = HashMap::from([(0, first), (1, second)])
+        })
+        .await
+        .unwrap();
+
+        let stream = actors.db.get_stream(event).await.unwrap();
+        assert_eq!(
+            stream.stream_runners,
+            HashMap::from([(0, first), (1, second)])
+        );
+        let updates = actors.obs_updates.lock().unwrap();
+        let (updated, modified) = updates.last().unwrap();
+        assert_eq!(*updated, event);
+        assert!(modified.contains(&ModifiedStreamState::RunnerView(first)));
+        assert!(modified.contains(&ModifiedStreamState::RunnerView(second)));
+    }
+
+    #[tokio::test]
+    async fn toggle_removes_and_restores_runner() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let second = actors.add_runner("second").await;
+        let event = actors.add_streamed_event("Race", &[first, second]).await;
+        update(&actors, event, |s| {
+            s.stream_runners = HashMap::from([(0, first), (1, second)])
+        })
+        .await
+        .unwrap();
+
+        update(&actors, event, |s| {
+            let slot = s.get_runner_slot(first).unwrap();
+            s.stream_runners.remove(&slot);
+        })
+        .await
+        .unwrap();
+        let stream = actors.db.get_stream(event).await.unwrap();
+        assert_eq!(stream.get_runner_slot(first), None);
+        assert_eq!(stream.get_runner_slot(second), Some(1));
+
+        update(&actors, event, |s| {
+            let slot = s.get_first_empty_slot(Some(&[0, 1])).unwrap();
+            s.stream_runners.insert(slot, first);
+        })
+        .await
+        .unwrap();
+        let stream = actors.db.get_stream(event).await.unwrap();
+        assert_eq!(stream.get_runner_slot(first), Some(0));
+    }
+
+    #[tokio::test]
+    async fn swap_exchanges_slots() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let second = actors.add_runner("second").await;
+        let event = actors.add_streamed_event("Race", &[first, second]).await;
+        update(&actors, event, |s| {
+            s.stream_runners = HashMap::from([(0, first), (1, second)])
+        })
+        .await
+        .unwrap();
+
+        update(&actors, event, |s| {
+            s.stream_runners.insert(0, second);
+            s.stream_runners.insert(1, first);
+        })
+        .await
+        .unwrap();
+
+        let stream = actors.db.get_stream(event).await.unwrap();
+        assert_eq!(
+            stream.stream_runners,
+            HashMap::from([(0, second), (1, first)])
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_runner_is_refused() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let event = actors.add_streamed_event("Race", &[first]).await;
+
+        let err = update(&actors, event, |s| {
+            s.stream_runners = HashMap::from([(0, first), (1, 404)])
+        })
+        .await
+        .unwrap_err();
+
+        assert!(matches!(
+            violations(err).as_slice(),
+            [StreamViolation::UnknownRunner {
+                slot: 1,
+                runner: 404
+            }]
+        ));
+        assert!(actors
+            .db
+            .get_stream(event)
+            .await
+            .unwrap()
+            .stream_runners
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn duplicate_runner_is_refused() {
+        let actors = TestActors::start().await;
+        let first = actors.add_runner("first").await;
+        let event = actors.add_streamed_event("Race", &[first]).await;
+
+        let err = update(&actors, event, |s| {
+            s.stream_runners = HashMap::from([(0, first), (1, first)])
+        })
+        .await
+        .unwrap_err();
+
+        assert!(violations(err)
+            .iter()
+            .any(|v| matches!(v, StreamViolation::DuplicateRunner { runner } if *runner == first)));
+        assert!(actors.obs_updates.lock().unwrap().is_empty());
+    }
+
+    /// A run of three splits, `completed` of which are done
+    fn relay_leg(completed: i64) -> Run {
+        let splits: Vec<serde_json::Value> = ["Forest", "Castle", "Tower"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let time = (i as i64) < completed;
+                serde_json::json!({
+                    "name": name,
+                    "pbSplitTime": null,
+                    "splitTime": time.then_some(60_000.0 * (i + 1) as f64),
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "pb": null,
+            "sob": null,
+            "bestPossible": null,
+            "delta": null,
+            "startedAt": "2024-05-01T18:00:00Z",
+            "currentComparison": "Personal Best",
+            "currentSplitName": "",
+            "currentSplitIndex": completed,
+            "splits": splits,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn handoff_happens_at_the_final_split_by_default() {
+        assert!(!reached_handoff(&relay_leg(2), None));
+        assert!(reached_handoff(&relay_leg(3), None));
+    }
+
+    #[test]
+    fn handoff_happens_at_the_configured_split() {
+        assert!(!reached_handoff(&relay_leg(0), Some("Forest")));
+        assert!(reached_handoff(&relay_leg(1), Some("Forest")));
+        assert!(!reached_handoff(&relay_leg(1), Some(" castle ")));
+        assert!(reached_handoff(&relay_leg(2), Some(" castle ")));
+        // A split the run does not have falls back to the final split
+        assert!(!reached_handoff(&relay_leg(2), Some("Moon")));
+        assert!(reached_handoff(&relay_leg(3), Some("Moon")));
+    }
+    #[test]
+    fn finished_runs_are_announced_once_and_only_the_latest_is_kept() {
+        let mut finished_runs = HashMap::new();
+        assert!(!mark_finished(&mut finished_runs, 1, &relay_leg(2)));
+        assert!(mark_finished(&mut finished_runs, 1, &relay_leg(3)));
+        assert!(!mark_finished(&mut finished_runs, 1, &relay_leg(3)));
+
+        let mut next = relay_leg(3);
+        next.started_at = "2024-05-01T19:00:00Z".to_owned();
+        assert!(mark_finished(&mut finished_runs, 1, &next));
+        assert!(mark_finished(&mut finished_runs, 2, &next));
+        assert_eq!(finished_runs.len(), 2);
+    }
+
+    #[test]
+    fn next_fitting_layout_wraps_around_and_skips_unfitting_layouts() {
+        let order: Vec<String> = ["one", "two", "three"].map(str::to_owned).into();
+        let fits = |l: &String| l != "two";
+
+        let next = |current: &str| next_fitting_layout(&order, Some(&current.to_owned()), fits);
+        assert_eq!(next("one").map(String::as_str), Some("three"));
+        assert_eq!(next("three").map(String::as_str), Some("one"));
+        assert_eq!(next("other").map(String::as_str), Some("one"));
+        assert_eq!(
+            next_fitting_layout(&order, Some(&"one".to_owned()), |l| l == "one")
+                .map(String::as_str),
+            Some("one")
+        );
+        assert_eq!(next_fitting_layout(&order, None, |_| false), None);
     }
 }
@@ -0,0 +1,304 @@
+//! Fixtures for tests that run actors against a project held in memory
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::json;
+use sqlx::types::Json;
+
+use crate::{
+    core::{
+        db::{ProjectDb, ProjectStore},
+        event::{run_event_actor, Event, EventActor, EventRequest},
+        health::HealthStatus,
+        runner::{Runner, RunnerActor, RunnerRequest},
+        settings::Settings,
+        stream::{
+            run_stream_manager, ModifiedStreamState, StreamActor, StreamRequest, StreamState,
+        },
+    },
+    integrations::{
+        discord::DiscordActor,
+        obs::{ObsActor, ObsCommand, ObsHostState, ObsScene, ObsSceneName},
+        tiltify::{TiltifyActor, TiltifyCommand},
+        twitch_chat::TwitchChatActor,
+        web::WebActor,
+    },
+    send_message, ActorReceiver, Directory, Rto,
+};
+
+use super::backup::{BackupActor, BackupRequest};
+
+/// OBS host of the settings template, which the stub OBS actor reports as connected
+pub const TEST_HOST: &str = "main";
+
+/// Updates the stub OBS actor was asked to show, by event
+pub type ObsUpdates = Arc<Mutex<Vec<(i64, Vec<ModifiedStreamState>)>>>;
+
+/// The stream and event actors running against an in-memory project,
+/// with stub OBS and runner actors
+pub struct TestActors {
+    pub db: Arc<dyn ProjectStore>,
+    pub directory: Directory,
+    pub obs_updates: ObsUpdates,
+}
+
+impl TestActors {
+    pub async fn start() -> Self {
+        let settings = Arc::new(Settings::template());
+        let db: Arc<dyn ProjectStore> =
+            Arc::new(ProjectDb::in_memory(Box::new(|| {})).await.unwrap());
+
+        let (stream_actor, stream_rx) = StreamActor::new("stream");
+        let (obs_actor, obs_rx) = ObsActor::new("obs");
+        let (runner_actor, runner_rx) = RunnerActor::new("runner");
+        let (event_actor, event_rx) = EventActor::new("event");
+        let (backup_actor, backup_rx) = BackupActor::new("backup");
+        let (tiltify_actor, tiltify_rx) = TiltifyActor::new("tiltify");
+        let directory = Directory {
+            stream_actor,
+            obs_actor,
+            runner_actor,
+            event_actor,
+            web_actor: WebActor::new("web").0,
+            backup_actor,
+            discord_actor: DiscordActor::new("discord").0,
+            twitch_chat_actor: TwitchChatActor::new("twitch_chat").0,
+            tiltify_actor,
+            health: Arc::new(HealthStatus::new()),
+        };
+
+        let obs_updates = ObsUpdates::default();
+        tokio::spawn(run_stub_obs(obs_rx, obs_updates.clone()));
+        tokio::spawn(run_stub_runner_actor(runner_rx));
+        tokio::spawn(run_stub_backup_actor(backup_rx));
+        tokio::spawn(run_stub_tiltify_actor(tiltify_rx));
+        tokio::spawn(run_stream_manager(
+            db.clone(),
+            settings.clone(),
+            stream_rx,
+            directory.clone(),
+        ));
+        tokio::spawn(run_event_actor(
+            db.clone(),
+            settings,
+            event_rx,
+            directory.clone(),
+        ));
+
+        Self {
+            db,
+            directory,
+            obs_updates,
+        }
+    }
+
+    /// Add a runner by name, returning their ID
+    pub async fn add_runner(&self, name: &str) -> i64 {
+        let mut runner = test_runner(0, name, None);
+        self.db.add_runner(&mut runner).await.unwrap();
+        runner.id
+    }
+
+    /// Create an event with the given runners through the event actor, returning its ID
+    pub async fn add_event(&self, name: &str, runners: &[i64]) -> i64 {
+        let event = test_event(name);
+        send_message!(self.directory.event_actor, EventRequest, Create, event).unwrap();
+        let id = self.db.get_id_for_event(name).await.unwrap();
+        for runner in runners {
+            send_message!(
+                self.directory.event_actor,
+                EventRequest,
+                AddRunner,
+                id,
+                *runner
+            )
+            .unwrap();
+        }
+        id
+    }
+
+    /// Create an event with the given runners and its stream on the test host,
+    /// returning the event's ID
+    pub async fn add_streamed_event(&self, name: &str, runners: &[i64]) -> i64 {
+        let event = self.add_event(name, runners).await;
+        send_message!(
+            self.directory.stream_actor,
+            StreamRequest,
+            Create,
+            event,
+            TEST_HOST.to_owned()
+        )
+        .unwrap();
+        event
+    }
+}
+
+/// A runner at full volume, with a resolved stream URL if one is given
+pub fn test_runner(id: i64, name: &str, url: Option<&str>) -> Runner {
+    serde_json::from_value(json!({
+        "id": id,
+        "name": name,
+        "stream": null,
+        "therun": null,
+        "cached_stream_url": url,
+        "location": null,
+        "volume_percent": 100,
+        "max_stream_height": null,
+        "nicks": [],
+    }))
+    .unwrap()
+}
+
+/// An active stream on the test host with runners by slot
+pub fn test_stream(event: i64, runners: &[(i64, i64)]) -> StreamState {
+    StreamState {
+        event,
+        obs_host: TEST_HOST.to_owned(),
+        active_commentators: "".to_string(),
+        ignored_commentators: "".to_string(),
+        ignored_voice_members: "".to_string(),
+        requested_layout: None,
+        active: true,
+        rotation: None,
+        commentator_order: "".to_string(),
+        manual_scene_override: false,
+        version: 0,
+        stream_runners: runners.iter().copied().collect(),
+        sync_offsets: HashMap::new(),
+        layout_slot_count: None,
+        on_deck_runners: Json(vec![]),
+        audible_runner: None,
+    }
+}
+
+/// An event without runners, times or layouts
+pub fn test_event(name: &str) -> Event {
+    serde_json::from_value(json!({
+        "id": 0,
+        "name": name,
+        "game": null,
+        "category": null,
+        "estimate": null,
+        "tournament": null,
+        "therun_race_id": null,
+        "event_start_time": null,
+        "timer_start_time": null,
+        "timer_end_time": null,
+        "preferred_layouts": [],
+        "is_relay": false,
+        "is_marathon": false,
+        "runner_state": {},
+    }))
+    .unwrap()
+}
+
+/// A layout scene with one stream view per slot
+pub fn test_layout(name: &str, slots: usize) -> ObsScene {
+    let sources: HashMap<String, serde_json::Value> = (0..slots)
+        .map(|slot| {
+            let view = json!({
+                "name": format!("Stream {}", slot),
+                "item_id": slot,
+                "index": slot,
+                "x": 0.0,
+                "y": 0.0,
+                "width": 640.0,
+                "height": 360.0,
+                "crop_left": 0,
+                "crop_right": 0,
+                "crop_top": 0,
+                "crop_bottom": 0,
+            });
+            (slot.to_string(), json!([view]))
+        })
+        .collect();
+    serde_json::from_value(json!({
+        "name": name,
+        "active": false,
+        "sources": sources,
+    }))
+    .unwrap()
+}
+
+/// The state of the test host: connected, with a two and a four runner layout
+fn test_host_state() -> ObsHostState {
+    ObsHostState {
+        connected: true,
+        dry_run: false,
+        streaming: false,
+        replay_buffer: false,
+        virtual_cam: false,
+        stream_stalls: HashMap::new(),
+        runner_audio_tracks: None,
+        scenes: [test_layout("2_runners", 2), test_layout("4_runners", 4)]
+            .into_iter()
+            .map(|scene| (scene.name.clone(), scene))
+            .collect(),
+        scene_collection: None,
+        scene_collections: vec![],
+        profile: None,
+        profiles: vec![],
+        last_connection_error: None,
+        public_stream_urls: vec![],
+        outputs: vec![],
+        runner_filters: HashMap::new(),
+    }
+}
+
+/// OBS actor that reports the test host and records updates instead of making them.
+///
+/// Other commands are dropped, failing their sender.
+async fn run_stub_obs(mut rx: ActorReceiver<ObsCommand>, updates: ObsUpdates) {
+    while let Some((msg, _)) = rx.recv().await {
+        match msg {
+            ObsCommand::GetState(rto) => rto.reply(Ok(HashMap::from([(
+                TEST_HOST.to_owned(),
+                test_host_state(),
+            )]))),
+            ObsCommand::GetSceneNames(_, rto) => rto.reply(Ok(test_host_state()
+                .scenes
+                .into_keys()
+                .map(|name| ObsSceneName {
+                    name,
+                    usable: true,
+                    from_snapshot: false,
+                })
+                .collect())),
+            ObsCommand::UpdateState(event, modified, rto) => {
+                updates.lock().unwrap().push((event, modified));
+                rto.reply(Ok(()))
+            }
+            ObsCommand::UpdateText(_, rto) => rto.reply(Ok(())),
+            ObsCommand::UpdateRunStats(_, _, rto) => rto.reply(Ok(())),
+            _ => {}
+        }
+    }
+}
+
+/// Runner actor that acknowledges stream refreshes without resolving any stream
+async fn run_stub_runner_actor(mut rx: ActorReceiver<RunnerRequest>) {
+    while let Some((msg, _)) = rx.recv().await {
+        if let RunnerRequest::RefreshStream(_, _, rto) = msg {
+            rto.reply(Ok(true));
+        }
+    }
+}
+
+/// Backup actor of a project that was never backed up
+async fn run_stub_backup_actor(mut rx: ActorReceiver<BackupRequest>) {
+    while let Some((msg, _)) = rx.recv().await {
+        if let BackupRequest::GetLastBackup(rto) = msg {
+            rto.reply(Ok(None));
+        }
+    }
+}
+
+/// Tiltify actor of a campaign that was never polled
+async fn run_stub_tiltify_actor(mut rx: ActorReceiver<TiltifyCommand>) {
+    while let Some((TiltifyCommand::GetDonations(rto), _)) = rx.recv().await {
+        rto.reply(Ok(Default::default()));
+    }
+}
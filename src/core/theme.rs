@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Longest accepted token value, in characters
+const MAX_TOKEN_VALUE_LEN: usize = 500;
+
+/// Design tokens of an overlay theme, served to overlays as CSS custom properties
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    /// Token values by name, without the leading `--`
+    pub tokens: BTreeMap<String, String>,
+    /// Time of the last save in Unix seconds, set when the theme is saved
+    #[serde(default)]
+    pub updated_at: i64,
+}
+
+impl Theme {
+    /// Check that the theme renders to CSS that cannot escape its own declarations
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.name.trim().is_empty() {
+            return Err(Error::InvalidRequest(
+                "theme".to_owned(),
+                "the name is empty".to_owned(),
+            ));
+        }
+
+        for (token, value) in &self.tokens {
+            if token.is_empty()
+                || token.starts_with('-')
+                || !token
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(Error::InvalidRequest(
+                    "theme token".to_owned(),
+                    format!(
+                        "'{}' must be letters, digits, '-' and '_', without a leading '-'",
+                        token
+                    ),
+                ));
+            }
+            if let Err(reason) = check_css_value(value) {
+                return Err(Error::InvalidRequest(
+                    "theme token".to_owned(),
+                    format!("the value of '{}' {}", token, reason),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Render the tokens as custom properties of `:root`
+    pub fn to_css(&self) -> String {
+        let mut css = String::from(":root {\n");
+        for (token, value) in &self.tokens {
+            css.push_str(&format!("  --{}: {};\n", token, value.trim()));
+        }
+        css.push_str("}\n");
+        css
+    }
+}
+
+/// Check that a value cannot end its declaration or rule, returning why it is refused
+fn check_css_value(value: &str) -> Result<(), &'static str> {
+    if value.trim().is_empty() {
+        return Err("is empty");
+    }
+    if value.chars().count() > MAX_TOKEN_VALUE_LEN {
+        return Err("is too long");
+    }
+    if value
+        .chars()
+        .any(|c| c.is_control() || matches!(c, '{' | '}' | ';' | '\\' | '<' | '>'))
+    {
+        return Err("contains a brace, semicolon, backslash, angle bracket or control character");
+    }
+    if value.contains("/*") || value.contains("*/") {
+        return Err("contains a comment");
+    }
+
+    // Unclosed strings or parentheses would swallow the declarations after this one
+    let mut quote = None;
+    let mut depth = 0;
+    for c in value.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => return Err("closes a parenthesis it did not open"),
+            (None, ')') => depth -= 1,
+            _ => {}
+        }
+    }
+    if quote.is_some() {
+        return Err("has an unclosed string");
+    }
+    if depth != 0 {
+        return Err("has an unclosed parenthesis");
+    }
+    Ok(())
+}
@@ -1,11 +1,13 @@
 use sqlx::prelude::FromRow;
 
+#[allow(dead_code)]
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum TournamentFormat {
     Bracket,
-    Ladder
+    Ladder,
 }
 
+#[allow(dead_code)]
 #[derive(FromRow, PartialEq, Eq, Debug, Clone)]
 pub struct Tournament {
     name: String,
@@ -0,0 +1,705 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::time::OffsetDateTime;
+
+use crate::{
+    core::{
+        backup::BackupRequest,
+        event::{
+            deserialize_datetime, serialize_datetime, Event, EventRequest, FullEvent, Incident,
+            Marker,
+        },
+        project::ImportMode,
+        runner::{Runner, RunnerRequest, RunnerSelfUpdate},
+        stream::{StreamRequest, StreamState},
+    },
+    integrations::{
+        obs::{LayoutSource, ObsCommand},
+        tiltify::TiltifyCommand,
+    },
+    send_message, Directory, Rto,
+};
+
+/// Keys of payload fields that are replaced before recording
+const REDACTED_KEYS: &[&str] = &["token", "password", "secret"];
+
+static RECORDER: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Start recording actor messages to a new NDJSON file in `dir`
+pub fn start_recording(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "trace-{}.ndjson",
+        OffsetDateTime::now_utc().unix_timestamp()
+    ));
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    log::info!("Recording actor messages to {}", path.display());
+    let _ = RECORDER.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Messages that can be recorded to a trace
+pub trait Traced {
+    /// The message as an externally tagged serde value, or None if it is not recorded
+    fn trace(&self) -> Option<Value> {
+        None
+    }
+}
+
+impl Traced for BackupRequest {}
+impl Traced for TiltifyCommand {}
+
+/// Whether a recorded message succeeded
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceStatus {
+    Ok,
+    Error,
+    /// The reply was not awaited
+    Sent,
+}
+
+/// A line of a trace file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TraceRecord {
+    /// Time the message was sent in Unix millis
+    pub time: i64,
+    pub actor: String,
+    pub message: String,
+    pub payload: Value,
+    pub status: TraceStatus,
+    pub error: Option<String>,
+}
+
+/// A recorded message waiting for its reply
+pub struct PendingTrace {
+    time: i64,
+    actor: &'static str,
+    payload: Value,
+}
+
+/// Start recording a message, if recording is enabled
+pub fn begin<M: Traced>(actor: &'static str, msg: &M) -> Option<PendingTrace> {
+    RECORDER.get()?;
+    let mut payload = msg.trace()?;
+    redact(&mut payload);
+    Some(PendingTrace {
+        time: (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64,
+        actor,
+        payload,
+    })
+}
+
+impl PendingTrace {
+    /// Record the message with the reply it got
+    pub fn finish<T>(self, reply: &anyhow::Result<T>) {
+        match reply {
+            Ok(_) => self.write(TraceStatus::Ok, None),
+            Err(e) => self.write(TraceStatus::Error, Some(format!("{:#}", e))),
+        }
+    }
+
+    /// Record a message whose reply is not awaited
+    pub fn sent(self) {
+        self.write(TraceStatus::Sent, None);
+    }
+
+    fn write(self, status: TraceStatus, error: Option<String>) {
+        let Some(recorder) = RECORDER.get() else {
+            return;
+        };
+
+        let message = match &self.payload {
+            Value::String(name) => name.clone(),
+            Value::Object(fields) => fields.keys().next().cloned().unwrap_or_default(),
+            _ => String::new(),
+        };
+        let record = TraceRecord {
+            time: self.time,
+            actor: self.actor.to_owned(),
+            message,
+            payload: self.payload,
+            status,
+            error,
+        };
+
+        let mut file = recorder.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", serde_json::to_string(&record).unwrap()) {
+            log::warn!("Failed to record actor message: {}", e);
+        }
+    }
+}
+
+/// Replace the values of secret fields, at any depth
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let key = key.to_lowercase();
+                if REDACTED_KEYS.iter().any(|k| key.contains(k)) {
+                    *field = Value::String("[redacted]".to_owned());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Serializable mirror of `StreamRequest`
+#[derive(Serialize, Deserialize)]
+enum StreamTrace {
+    Create(i64, String),
+    Reload(i64),
+    Update(Box<StreamState>, bool),
+    Delete(i64),
+    RunUpdated(i64),
+    Handoff(i64, i64),
+    ApplyPreset(i64, String),
+    Activate(i64),
+    ReorderCommentators(i64, Vec<String>),
+    SetSyncOffset(i64, i64, u32),
+    SwitchScene(String, String),
+    ResumeLayout(String),
+    AutofillFromEvent(i64),
+    ForceResync(i64, bool),
+    Restore(Box<StreamState>),
+    AddOnDeck(i64, i64),
+    RemoveOnDeck(i64, i64),
+}
+
+impl Traced for StreamRequest {
+    fn trace(&self) -> Option<Value> {
+        let trace = match self {
+            StreamRequest::Create(event, host, _) => StreamTrace::Create(*event, host.clone()),
+            StreamRequest::Reload(event, _) => StreamTrace::Reload(*event),
+            StreamRequest::Update(stream, force, _) => {
+                StreamTrace::Update(Box::new(stream.clone()), *force)
+            }
+            StreamRequest::Delete(event, _) => StreamTrace::Delete(*event),
+            StreamRequest::RunUpdated(runner) => StreamTrace::RunUpdated(*runner),
+            StreamRequest::Handoff(event, runner, _) => StreamTrace::Handoff(*event, *runner),
+            StreamRequest::ApplyPreset(event, preset, _) => {
+                StreamTrace::ApplyPreset(*event, preset.clone())
+            }
+            StreamRequest::Activate(event, _) => StreamTrace::Activate(*event),
+            StreamRequest::ReorderCommentators(event, order, _) => {
+                StreamTrace::ReorderCommentators(*event, order.clone())
+            }
+            StreamRequest::SetSyncOffset(event, runner, offset, _) => {
+                StreamTrace::SetSyncOffset(*event, *runner, *offset)
+            }
+            StreamRequest::SwitchScene(host, scene, _) => {
+                StreamTrace::SwitchScene(host.clone(), scene.clone())
+            }
+            StreamRequest::ResumeLayout(host, _) => StreamTrace::ResumeLayout(host.clone()),
+            StreamRequest::AutofillFromEvent(event, _) => StreamTrace::AutofillFromEvent(*event),
+            StreamRequest::ForceResync(event, purge, _) => StreamTrace::ForceResync(*event, *purge),
+            StreamRequest::Restore(stream, _) => StreamTrace::Restore(Box::new(stream.clone())),
+            StreamRequest::AddOnDeck(event, runner, _) => StreamTrace::AddOnDeck(*event, *runner),
+            StreamRequest::RemoveOnDeck(event, runner, _) => {
+                StreamTrace::RemoveOnDeck(*event, *runner)
+            }
+        };
+        serde_json::to_value(trace).ok()
+    }
+}
+
+/// Serializable mirror of `EventRequest`
+#[derive(Serialize, Deserialize)]
+enum EventTrace {
+    Create(Event),
+    CreateFull(FullEvent),
+    SetStartTime(
+        i64,
+        #[serde(serialize_with = "serialize_datetime")]
+        #[serde(deserialize_with = "deserialize_datetime")]
+        Option<OffsetDateTime>,
+    ),
+    SetEndTime(
+        i64,
+        #[serde(serialize_with = "serialize_datetime")]
+        #[serde(deserialize_with = "deserialize_datetime")]
+        Option<OffsetDateTime>,
+    ),
+    AddRunner(i64, i64),
+    RemoveRunner(i64, i64),
+    Update(Event),
+    Delete(i64),
+    SetHost(i64, Option<String>),
+    AddIncident(Incident),
+    UpdateIncident(Incident),
+    DeleteIncident(i64),
+    AddMarker(Marker),
+    ReorderRunners(i64, Vec<i64>),
+    Complete(i64, bool),
+    Reopen(i64),
+}
+
+impl Traced for EventRequest {
+    fn trace(&self) -> Option<Value> {
+        let trace = match self {
+            EventRequest::Create(event, _) => EventTrace::Create(event.clone()),
+            EventRequest::CreateFull(full, _) => EventTrace::CreateFull(full.clone()),
+            EventRequest::SetStartTime(event, time, _) => EventTrace::SetStartTime(*event, *time),
+            EventRequest::SetEndTime(event, time, _) => EventTrace::SetEndTime(*event, *time),
+            EventRequest::SetHost(event, host, _) => EventTrace::SetHost(*event, host.clone()),
+            EventRequest::AddRunner(event, runner, _) => EventTrace::AddRunner(*event, *runner),
+            EventRequest::RemoveRunner(event, runner, _) => {
+                EventTrace::RemoveRunner(*event, *runner)
+            }
+            EventRequest::Update(event, _) => EventTrace::Update(event.clone()),
+            EventRequest::Delete(event, _) => EventTrace::Delete(*event),
+            EventRequest::AddIncident(incident, _) => EventTrace::AddIncident(incident.clone()),
+            EventRequest::UpdateIncident(incident, _) => {
+                EventTrace::UpdateIncident(incident.clone())
+            }
+            EventRequest::DeleteIncident(id, _) => EventTrace::DeleteIncident(*id),
+            EventRequest::AddMarker(marker, _) => EventTrace::AddMarker(marker.clone()),
+            EventRequest::ReorderRunners(event, order, _) => {
+                EventTrace::ReorderRunners(*event, order.clone())
+            }
+            EventRequest::Complete(event, intermission, _) => {
+                EventTrace::Complete(*event, *intermission)
+            }
+            EventRequest::Reopen(event, _) => EventTrace::Reopen(*event),
+        };
+        serde_json::to_value(trace).ok()
+    }
+}
+
+/// Serializable mirror of `RunnerRequest`, which is recorded but not replayed
+#[derive(Serialize)]
+enum RunnerTrace {
+    Create(Runner),
+    Update(Runner),
+    Edit(Runner),
+    RefreshStream(i64, Option<String>),
+    Delete(i64, bool),
+    /// Exports are only recorded as a summary
+    ImportProject(String, ImportMode),
+    CreateSelfToken(i64),
+    /// The self-service token is left out, as it works like a password
+    SelfUpdate(RunnerSelfUpdate),
+    StreamRunnersChanged(Vec<i64>),
+    SetPolling(i64, bool),
+    GetPollStatus,
+}
+
+impl Traced for RunnerRequest {
+    fn trace(&self) -> Option<Value> {
+        let trace = match self {
+            RunnerRequest::Create(runner, _) => RunnerTrace::Create(runner.clone()),
+            RunnerRequest::Update(runner, _) => RunnerTrace::Update(runner.clone()),
+            RunnerRequest::Edit(update, _) => RunnerTrace::Edit(update.runner.clone()),
+            RunnerRequest::RefreshStream(runner, host, _) => {
+                RunnerTrace::RefreshStream(*runner, host.clone())
+            }
+            RunnerRequest::Delete(runner, force, _) => RunnerTrace::Delete(*runner, *force),
+            RunnerRequest::ImportProject(export, mode, _) => {
+                RunnerTrace::ImportProject(format!("{:?}", export), *mode)
+            }
+            RunnerRequest::CreateSelfToken(runner, _) => RunnerTrace::CreateSelfToken(*runner),
+            RunnerRequest::SelfUpdate(_, update, _) => RunnerTrace::SelfUpdate(update.clone()),
+            RunnerRequest::StreamRunnersChanged(runners) => {
+                RunnerTrace::StreamRunnersChanged(runners.clone())
+            }
+            RunnerRequest::SetPolling(runner, enabled, _) => {
+                RunnerTrace::SetPolling(*runner, *enabled)
+            }
+            RunnerRequest::GetPollStatus(_) => RunnerTrace::GetPollStatus,
+        };
+        serde_json::to_value(trace).ok()
+    }
+}
+
+/// Serializable mirror of `ObsCommand`, which is recorded but not replayed
+#[derive(Serialize)]
+enum ObsTrace {
+    /// Modified state is only recorded by name
+    UpdateState(i64, Vec<String>),
+    StartStream(String),
+    EndStream(String),
+    GetState,
+    GetSceneNames(String),
+    UpdateText(i64),
+    Reconnect(String),
+    SetSourceIndex(String, String, String, u32),
+    SaveReplayBuffer(String),
+    SetReplayBufferEnabled(String, bool),
+    PreflightCheck(String, Option<i64>),
+    OpenProjector(String, String, Option<u32>),
+    SetVirtualCamEnabled(String, bool),
+    SetProgramScene(String, String),
+    ApplySyncOffset(i64, i64),
+    SetSceneCollection(String, String),
+    SetProfile(String, String),
+    SetDryRun(bool),
+    PurgeRunnerSources(i64),
+    GetLayouts(String, Option<LayoutSource>),
+    UpdateRunStats(i64, i64),
+    PlanUpdate(i64),
+    CollectOrphanedSources(String, bool),
+    SetBrowserSourceGroup(String, String, HashMap<String, String>),
+    SetSourceFilterEnabled(String, String, String, bool),
+}
+
+impl Traced for ObsCommand {
+    fn trace(&self) -> Option<Value> {
+        let trace = match self {
+            ObsCommand::UpdateState(event, modified, _) => ObsTrace::UpdateState(
+                *event,
+                modified.iter().map(|m| format!("{:?}", m)).collect(),
+            ),
+            ObsCommand::StartStream(host, _) => ObsTrace::StartStream(host.clone()),
+            ObsCommand::EndStream(host, _) => ObsTrace::EndStream(host.clone()),
+            ObsCommand::GetState(_) => ObsTrace::GetState,
+            ObsCommand::GetSceneNames(host, _) => ObsTrace::GetSceneNames(host.clone()),
+            ObsCommand::UpdateText(event, _) => ObsTrace::UpdateText(*event),
+            ObsCommand::Reconnect(host, _) => ObsTrace::Reconnect(host.clone()),
+            ObsCommand::SetSourceIndex(host, scene, source, index, _) => {
+                ObsTrace::SetSourceIndex(host.clone(), scene.clone(), source.clone(), *index)
+            }
+            ObsCommand::SaveReplayBuffer(host, _) => ObsTrace::SaveReplayBuffer(host.clone()),
+            ObsCommand::SetReplayBufferEnabled(host, enabled, _) => {
+                ObsTrace::SetReplayBufferEnabled(host.clone(), *enabled)
+            }
+            ObsCommand::PreflightCheck(host, event, _) => {
+                ObsTrace::PreflightCheck(host.clone(), *event)
+            }
+            ObsCommand::OpenProjector(host, target, monitor, _) => {
+                ObsTrace::OpenProjector(host.clone(), target.clone(), *monitor)
+            }
+            ObsCommand::SetVirtualCamEnabled(host, enabled, _) => {
+                ObsTrace::SetVirtualCamEnabled(host.clone(), *enabled)
+            }
+            ObsCommand::SetProgramScene(host, scene, _) => {
+                ObsTrace::SetProgramScene(host.clone(), scene.clone())
+            }
+            ObsCommand::ApplySyncOffset(event, runner, _) => {
+                ObsTrace::ApplySyncOffset(*event, *runner)
+            }
+            ObsCommand::SetSceneCollection(host, collection, _) => {
+                ObsTrace::SetSceneCollection(host.clone(), collection.clone())
+            }
+            ObsCommand::SetProfile(host, profile, _) => {
+                ObsTrace::SetProfile(host.clone(), profile.clone())
+            }
+            ObsCommand::SetDryRun(enabled, _) => ObsTrace::SetDryRun(*enabled),
+            ObsCommand::PurgeRunnerSources(event, _) => ObsTrace::PurgeRunnerSources(*event),
+            ObsCommand::GetLayouts(host, source, _) => ObsTrace::GetLayouts(host.clone(), *source),
+            ObsCommand::UpdateRunStats(event, runner, _) => {
+                ObsTrace::UpdateRunStats(*event, *runner)
+            }
+            ObsCommand::PlanUpdate(event, _) => ObsTrace::PlanUpdate(*event),
+            ObsCommand::CollectOrphanedSources(host, apply, _) => {
+                ObsTrace::CollectOrphanedSources(host.clone(), *apply)
+            }
+            ObsCommand::SetBrowserSourceGroup(host, group, params, _) => {
+                ObsTrace::SetBrowserSourceGroup(host.clone(), group.clone(), params.clone())
+            }
+            ObsCommand::SetSourceFilterEnabled(host, source, filter, enabled, _) => {
+                ObsTrace::SetSourceFilterEnabled(
+                    host.clone(),
+                    source.clone(),
+                    filter.clone(),
+                    *enabled,
+                )
+            }
+        };
+        serde_json::to_value(trace).ok()
+    }
+}
+
+/// A recorded message that failed to replay
+#[derive(Serialize, Debug)]
+pub struct ReplayFailure {
+    /// Line of the trace file, starting at 1
+    pub line: usize,
+    pub message: String,
+    pub error: String,
+}
+
+/// Outcome of replaying a trace
+#[derive(Serialize, Default, Debug)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    /// Messages to actors that are not replayed
+    pub skipped: usize,
+    pub failures: Vec<ReplayFailure>,
+}
+
+async fn replay_stream(directory: &Directory, msg: StreamTrace) -> anyhow::Result<()> {
+    match msg {
+        StreamTrace::Create(event, host) => {
+            send_message!(directory.stream_actor, StreamRequest, Create, event, host)
+        }
+        StreamTrace::Reload(event) => {
+            send_message!(directory.stream_actor, StreamRequest, Reload, event)
+        }
+        StreamTrace::Update(stream, force) => {
+            let stream = *stream;
+            send_message!(directory.stream_actor, StreamRequest, Update, stream, force).map(|_| ())
+        }
+        StreamTrace::Delete(event) => {
+            send_message!(directory.stream_actor, StreamRequest, Delete, event)
+        }
+        StreamTrace::RunUpdated(runner) => {
+            directory
+                .stream_actor
+                .send(StreamRequest::RunUpdated(runner));
+            Ok(())
+        }
+        StreamTrace::Handoff(event, runner) => send_message!(
+            directory.stream_actor,
+            StreamRequest,
+            Handoff,
+            event,
+            runner
+        )
+        .map(|_| ()),
+        StreamTrace::ApplyPreset(event, preset) => send_message!(
+            directory.stream_actor,
+            StreamRequest,
+            ApplyPreset,
+            event,
+            preset
+        )
+        .map(|_| ()),
+        StreamTrace::Activate(event) => {
+            send_message!(directory.stream_actor, StreamRequest, Activate, event)
+        }
+        StreamTrace::ReorderCommentators(event, order) => send_message!(
+            directory.stream_actor,
+            StreamRequest,
+            ReorderCommentators,
+            event,
+            order
+        ),
+        StreamTrace::SetSyncOffset(event, runner, offset) => send_message!(
+            directory.stream_actor,
+            StreamRequest,
+            SetSyncOffset,
+            event,
+            runner,
+            offset
+        ),
+        StreamTrace::SwitchScene(host, scene) => {
+            send_message!(
+                directory.stream_actor,
+                StreamRequest,
+                SwitchScene,
+                host,
+                scene
+            )
+        }
+        StreamTrace::ResumeLayout(host) => {
+            send_message!(directory.stream_actor, StreamRequest, ResumeLayout, host)
+        }
+        StreamTrace::AutofillFromEvent(event) => {
+            send_message!(
+                directory.stream_actor,
+                StreamRequest,
+                AutofillFromEvent,
+                event
+            )
+        }
+        StreamTrace::ForceResync(event, purge) => {
+            send_message!(
+                directory.stream_actor,
+                StreamRequest,
+                ForceResync,
+                event,
+                purge
+            )
+        }
+        StreamTrace::Restore(stream) => {
+            let stream = *stream;
+            send_message!(directory.stream_actor, StreamRequest, Restore, stream)
+        }
+        StreamTrace::AddOnDeck(event, runner) => {
+            send_message!(
+                directory.stream_actor,
+                StreamRequest,
+                AddOnDeck,
+                event,
+                runner
+            )
+        }
+        StreamTrace::RemoveOnDeck(event, runner) => {
+            send_message!(
+                directory.stream_actor,
+                StreamRequest,
+                RemoveOnDeck,
+                event,
+                runner
+            )
+        }
+    }
+}
+
+async fn replay_event(directory: &Directory, msg: EventTrace) -> anyhow::Result<()> {
+    match msg {
+        EventTrace::Create(event) => {
+            send_message!(directory.event_actor, EventRequest, Create, event)
+        }
+        EventTrace::CreateFull(full) => {
+            send_message!(directory.event_actor, EventRequest, CreateFull, full).map(|_| ())
+        }
+        EventTrace::SetStartTime(event, time) => {
+            send_message!(
+                directory.event_actor,
+                EventRequest,
+                SetStartTime,
+                event,
+                time
+            )
+        }
+        EventTrace::SetEndTime(event, time) => {
+            send_message!(directory.event_actor, EventRequest, SetEndTime, event, time)
+        }
+        EventTrace::SetHost(event, host) => {
+            send_message!(directory.event_actor, EventRequest, SetHost, event, host)
+        }
+        EventTrace::AddRunner(event, runner) => {
+            send_message!(
+                directory.event_actor,
+                EventRequest,
+                AddRunner,
+                event,
+                runner
+            )
+        }
+        EventTrace::RemoveRunner(event, runner) => {
+            send_message!(
+                directory.event_actor,
+                EventRequest,
+                RemoveRunner,
+                event,
+                runner
+            )
+        }
+        EventTrace::Update(event) => {
+            send_message!(directory.event_actor, EventRequest, Update, event)
+        }
+        EventTrace::Delete(event) => {
+            send_message!(directory.event_actor, EventRequest, Delete, event)
+        }
+        EventTrace::AddIncident(incident) => {
+            send_message!(directory.event_actor, EventRequest, AddIncident, incident).map(|_| ())
+        }
+        EventTrace::UpdateIncident(incident) => {
+            send_message!(
+                directory.event_actor,
+                EventRequest,
+                UpdateIncident,
+                incident
+            )
+        }
+        EventTrace::DeleteIncident(id) => {
+            send_message!(directory.event_actor, EventRequest, DeleteIncident, id)
+        }
+        EventTrace::AddMarker(marker) => {
+            send_message!(directory.event_actor, EventRequest, AddMarker, marker).map(|_| ())
+        }
+        EventTrace::ReorderRunners(event, order) => {
+            send_message!(
+                directory.event_actor,
+                EventRequest,
+                ReorderRunners,
+                event,
+                order
+            )
+        }
+        EventTrace::Complete(event, intermission) => {
+            send_message!(
+                directory.event_actor,
+                EventRequest,
+                Complete,
+                event,
+                intermission
+            )
+        }
+        EventTrace::Reopen(event) => {
+            send_message!(directory.event_actor, EventRequest, Reopen, event)
+        }
+    }
+}
+
+/// Re-send the stream and event messages of a trace against the current database, in order.
+///
+/// With `dry_run`, the OBS actor acknowledges commands without changing OBS while replaying.
+pub async fn replay(directory: &Directory, trace: &str, dry_run: bool) -> ReplayReport {
+    let mut report = ReplayReport::default();
+
+    if dry_run {
+        if let Err(e) = send_message!(directory.obs_actor, ObsCommand, SetDryRun, true) {
+            report.failures.push(ReplayFailure {
+                line: 0,
+                message: "SetDryRun".to_owned(),
+                error: format!("{:#}", e),
+            });
+            return report;
+        }
+    }
+
+    for (idx, line) in trace.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = match serde_json::from_str::<TraceRecord>(line) {
+            Ok(record) => record,
+            Err(e) => {
+                report.failures.push(ReplayFailure {
+                    line: idx + 1,
+                    message: String::new(),
+                    error: format!("Invalid trace record: {}", e),
+                });
+                continue;
+            }
+        };
+
+        let result = match record.actor.as_str() {
+            "StreamRequest" => match serde_json::from_value(record.payload) {
+                Ok(msg) => replay_stream(directory, msg).await,
+                Err(e) => Err(e.into()),
+            },
+            "EventRequest" => match serde_json::from_value(record.payload) {
+                Ok(msg) => replay_event(directory, msg).await,
+                Err(e) => Err(e.into()),
+            },
+            _ => {
+                report.skipped += 1;
+                continue;
+            }
+        };
+
+        match result {
+            Ok(()) => report.replayed += 1,
+            Err(e) => report.failures.push(ReplayFailure {
+                line: idx + 1,
+                message: format!("{}::{}", record.actor, record.message),
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+
+    if dry_run {
+        if let Err(e) = send_message!(directory.obs_actor, ObsCommand, SetDryRun, false) {
+            log::error!("Failed to leave OBS dry run mode after a replay: {}", e);
+        }
+    }
+
+    report
+}
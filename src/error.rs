@@ -8,6 +8,19 @@ pub enum Error {
     Unknown(String),
     #[error("Unknown layout {0}")]
     UnknownLayout(String),
+    #[error("OBS host {0} is not connected")]
+    ObsUnavailable(String),
+    #[error("The replay buffer is not available on OBS host {0}, enable it in OBS under Settings > Output > Replay Buffer")]
+    ReplayBufferUnavailable(String),
+    #[error("The replay buffer is not running on OBS host {0}, start it before saving a replay")]
+    ReplayBufferNotRunning(String),
+    #[error("{0} did not reply to {1} within {2} seconds")]
+    ActorTimeout(String, String, u64),
+    #[error("Invalid {0}: {1}")]
+    InvalidRequest(String, String),
+    /// The item was changed since it was loaded, carrying its current copy
+    #[error("{0} was changed by someone else, reload it and try again")]
+    Conflict(String, serde_json::Value),
 }
 
 impl From<String> for Error {
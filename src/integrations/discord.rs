@@ -1,33 +1,67 @@
-use std::{collections::{HashMap, HashSet}, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
 
 use anyhow::anyhow;
-use poise::serenity_prelude as serenity;
+use poise::{serenity_prelude as serenity, BoxFuture};
 
-use futures::{Stream, StreamExt};
+use futures::Stream;
 use serenity::{
     http::Http,
     model::{
-        prelude::{ChannelId, GuildChannel},
+        prelude::{ChannelId, GuildChannel, GuildId},
         voice::VoiceState,
     },
 };
 use sqlx::types::time::OffsetDateTime;
+use tokio::sync::watch;
+use tracing::{Instrument, Span};
 
 use crate::{
     core::{
-        db::ProjectDb,
-        event::{Event, EventRequest, RunnerEventState},
-        runner::{Runner, RunnerRequest},
+        db::ProjectStore,
+        event::{
+            format_local_time, parse_user_time, Event, EventRequest, Incident, IncidentSeverity,
+            Marker, RunnerEventState,
+        },
+        export,
+        runner::{Runner, RunnerInfo, RunnerRequest, StreamKind, MAX_VOLUME_PERCENT},
         settings::Settings,
-        stream::{validate_streamed_event_id, StreamActor, StreamRequest},
+        stream::{
+            get_connected_stream_layout, validate_streamed_event_id, LayoutRotation, StreamRequest,
+            StreamState, StreamWarning, DEFAULT_ROTATION_DWELL_SECS,
+        },
     },
     error::Error,
-    integrations::obs::ObsCommand,
-    send_message, Directory, Rto,
+    integrations::{
+        discord_reminders::run_runner_reminders,
+        obs::{ObsCommand, ObsHostState},
+        therun::format_run_time,
+        web::WebCommand,
+    },
+    send_message, ActorMessage, ActorReceiver, ActorRef, Directory, Rto,
 };
 
+/// Requests that can be sent to the Discord bot
+pub enum DiscordCommand {
+    /// Post a message to the command channel
+    Notify(String),
+}
+
+pub type DiscordActor = ActorRef<DiscordCommand>;
+
+impl ActorMessage for DiscordCommand {
+    fn label(&self) -> &'static str {
+        match self {
+            DiscordCommand::Notify(..) => "Notify",
+        }
+    }
+}
+
 struct Data {
-    db: Arc<ProjectDb>,
+    db: Arc<dyn ProjectStore>,
     settings: Arc<Settings>,
     directory: Directory,
 }
@@ -60,64 +94,230 @@ async fn to_guild_channel(
 }
 
 /// Return the event ID corresponding to the given name, or None if None
-async fn get_event_id(name: Option<String>, db: &ProjectDb) -> anyhow::Result<Option<i64>> {
+async fn get_event_id(name: Option<String>, db: &dyn ProjectStore) -> anyhow::Result<Option<i64>> {
     match name {
         Some(name) => Ok(Some(db.get_id_for_event(&name).await?)),
         None => Ok(None),
     }
 }
 
-/// Return the stream ID for the given name, or try retrieving the single active stream
-async fn get_stream_id(name: Option<String>, db: &ProjectDb) -> anyhow::Result<i64> {
-    match get_event_id(name, db).await {
-        Ok(name) => match validate_streamed_event_id(db, name).await {
-            Ok(id) => Ok(id),
-            Err(e) => Err(e),
-        },
-        Err(e) => Err(e),
+/// The OBS host assigned to the channel a command was invoked from, or else to the invoker's roles
+async fn get_invoker_host(ctx: Context<'_>) -> Option<String> {
+    let hosts = &ctx.data().settings.obs_hosts;
+    let channel = ctx.channel_id().to_string();
+    if let Some((name, _)) = hosts
+        .iter()
+        .find(|(_, h)| h.discord_channels.iter().flatten().any(|c| *c == channel))
+    {
+        return Some(name.to_owned());
+    }
+
+    let roles: Vec<String> = ctx
+        .author_member()
+        .await?
+        .roles
+        .iter()
+        .map(|r| r.to_string())
+        .collect();
+    let mut matching = hosts
+        .iter()
+        .filter(|(_, h)| h.discord_roles.iter().flatten().any(|r| roles.contains(r)));
+    match (matching.next(), matching.next()) {
+        (Some((name, _)), None) => Some(name.to_owned()),
+        _ => None,
+    }
+}
+
+/// Return the stream ID for the given name, or try retrieving the single (active) stream,
+/// preferring the streams on the invoker's OBS host
+async fn get_stream_id(ctx: Context<'_>, name: Option<String>) -> anyhow::Result<i64> {
+    let db = &ctx.data().db;
+    let event = get_event_id(name, &**db).await?;
+    let host = get_invoker_host(ctx).await;
+    validate_streamed_event_id(&**db, event, host.as_deref()).await
+}
+
+/// Apply a change to the current copy of a stream and send it to the stream actor.
+///
+/// If the stream is saved by someone else in between, the change is applied
+/// once more to the new copy instead of overwriting theirs.
+async fn update_stream(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+    force: bool,
+    change: impl Fn(&mut StreamState),
+) -> anyhow::Result<Vec<StreamWarning>> {
+    let mut retried = false;
+    loop {
+        let mut stream = db.get_stream(event).await?;
+        change(&mut stream);
+        let res = send_message!(directory.stream_actor, StreamRequest, Update, stream, force);
+        match res {
+            Err(e) if !retried && matches!(e.downcast_ref(), Some(Error::Conflict(..))) => {
+                log::info!(
+                    "Stream for event {} changed while updating it, retrying",
+                    event
+                );
+                retried = true;
+            }
+            res => return res,
+        }
     }
 }
 
 async fn update_voice_list(
-    db: &ProjectDb,
+    db: &dyn ProjectStore,
     context: &serenity::Context,
-    actor: &StreamActor,
+    directory: &Directory,
     voice_state: &VoiceState,
     settings: &Settings,
 ) {
     if let Some(channel) = get_voice_guild_channel(voice_state, context).await {
-        if let Some(host) = settings
-            .obs_hosts
-            .iter()
-            .find(|h| {
-                h.1.discord_voice_channel
-                    .as_ref()
-                    .is_some_and(|name| name == channel.name())
-            })
-            .map(|m| m.0.to_owned())
-        {
-            if let Ok(stream) = db.get_event_by_obs_host(&host).await {
-                let users = channel.members(&context).await.unwrap();
+        if let Some(host) = get_voice_channel_host(&channel, settings) {
+            set_voice_commentators(db, context, directory, settings, &host, &channel).await;
+        }
+    }
+}
 
-                let user_list: Vec<String> =
-                    users.iter().map(|u| u.display_name().to_string()).collect();
+/// The OBS host whose commentators are in the given voice channel
+fn get_voice_channel_host(channel: &GuildChannel, settings: &Settings) -> Option<String> {
+    settings
+        .obs_hosts
+        .iter()
+        .find(|h| {
+            h.1.discord_voice_channel
+                .as_ref()
+                .is_some_and(|name| name == channel.name())
+        })
+        .map(|m| m.0.to_owned())
+}
 
-                let mut stream_data = db.get_stream(stream).await.expect("Stream not found");
-                stream_data.active_commentators = user_list.join(";");
+/// Set the commentators of the stream on a host to the members of its voice channel.
+///
+/// Members who are ignored or have the `discord_ignore_role_id` role are listed as
+/// ignored voice members, so they are never shown on stream.
+async fn set_voice_commentators(
+    db: &dyn ProjectStore,
+    context: &serenity::Context,
+    directory: &Directory,
+    settings: &Settings,
+    host: &str,
+    channel: &GuildChannel,
+) {
+    let users = match channel.members(&context).await {
+        Ok(users) => users,
+        Err(e) => {
+            log::error!(
+                "Failed to get members of voice channel {}: {}",
+                channel.name(),
+                e
+            );
+            return;
+        }
+    };
+    directory
+        .health
+        .set_discord_voice_members(host, users.len());
+
+    if let Ok(stream) = db.get_event_by_obs_host(host).await {
+        let start = Instant::now();
+        let ids: Vec<String> = users.iter().map(|u| u.user.id.to_string()).collect();
+        let runners = match db.find_runners_by_discord_ids(&ids).await {
+            Ok(runners) => runners,
+            Err(e) => {
+                log::warn!(
+                    "Failed to look up the runners of voice channel members: {}",
+                    e
+                );
+                HashMap::new()
+            }
+        };
 
-                let resp = send_message!(actor, StreamRequest, Update, stream_data);
+        // Users linked to a runner are listed by the runner's name
+        let user_list: Vec<String> = users
+            .iter()
+            .zip(&ids)
+            .map(|(user, id)| match runners.get(id) {
+                Some(runner) => runner.name.clone(),
+                None => user.display_name().to_string(),
+            })
+            .collect();
+        log::debug!(
+            "Looked up {} voice channel members of {} in {:?}",
+            users.len(),
+            host,
+            start.elapsed()
+        );
+
+        let ignored_ids: HashSet<String> = match db.get_ignored_discord_users().await {
+            Ok(users) => users.into_iter().map(|(id, _)| id).collect(),
+            Err(e) => {
+                log::warn!("Failed to get the ignored Discord users: {}", e);
+                HashSet::new()
+            }
+        };
+        let has_ignore_role = |user: &serenity::Member| {
+            settings
+                .discord_ignore_role_id
+                .as_ref()
+                .is_some_and(|role| user.roles.iter().any(|r| r.to_string() == *role))
+        };
+        let ignored_members: Vec<&str> = users
+            .iter()
+            .zip(&ids)
+            .zip(&user_list)
+            .filter(|((user, id), _)| ignored_ids.contains(*id) || has_ignore_role(user))
+            .map(|(_, name)| name.as_str())
+            .collect();
+
+        let commentators = user_list.join(";");
+        let ignored_members = ignored_members.join(";");
+
+        // Only the commentators change, so the rest of the stream is not revalidated
+        let resp = update_stream(db, directory, stream, true, |stream_data| {
+            stream_data.active_commentators = commentators.clone();
+            stream_data.ignored_voice_members = ignored_members.clone();
+        })
+        .await;
 
-                match resp {
-                    Ok(_) => {}
-                    Err(message) => {
-                        log::error!("Failed to set voice state: {}", message);
-                    }
-                }
+        match resp {
+            Ok(_) => {}
+            Err(message) => {
+                log::error!("Failed to set voice state: {}", message);
             }
         }
     }
 }
 
+/// Set the commentators of every host from the voice channels of a guild.
+///
+/// Voice state updates are only sent for changes, so this picks up
+/// users who were already in a voice channel when the bot connected.
+async fn sync_guild_voice_channels(
+    db: &dyn ProjectStore,
+    context: &serenity::Context,
+    directory: &Directory,
+    settings: &Settings,
+    guild: &serenity::Guild,
+) {
+    log::debug!("Syncing voice channels of guild {}", guild.name);
+    for channel in guild.channels.values() {
+        let serenity::Channel::Guild(channel) = channel else {
+            continue;
+        };
+        if channel.kind != serenity::ChannelType::Voice {
+            continue;
+        }
+        if let Some(host) = get_voice_channel_host(channel, settings) {
+            set_voice_commentators(db, context, directory, settings, &host, channel).await;
+        }
+    }
+
+    // Dashboards opened before the bot connected have not seen the commentators yet
+    directory.web_actor.send(WebCommand::SendStateUpdate);
+}
+
 async fn handle_voice_state_event(
     context: &serenity::Context,
     old_state: &Option<VoiceState>,
@@ -126,13 +326,13 @@ async fn handle_voice_state_event(
 ) {
     let db = &data.db;
     let settings = &data.settings;
-    let actor = &data.directory.stream_actor;
+    let directory = &data.directory;
 
     if let Some(old_state) = old_state {
-        update_voice_list(db, context, actor, old_state, settings).await;
+        update_voice_list(&**db, context, directory, old_state, settings).await;
     }
 
-    update_voice_list(db, context, actor, new_state, settings).await;
+    update_voice_list(&**db, context, directory, new_state, settings).await;
 }
 
 async fn check_channel(context: &Context<'_>) -> anyhow::Result<bool> {
@@ -168,18 +368,73 @@ async fn send_success_reply(context: &Context<'_>) -> Result<(), anyhow::Error>
     }
 }
 
+/// React to a stream update, or list the runners that were moved to fit the stream's layout
+async fn send_stream_update_reply(
+    context: &Context<'_>,
+    warnings: Vec<StreamWarning>,
+) -> Result<(), anyhow::Error> {
+    if warnings.is_empty() {
+        return send_success_reply(context).await;
+    }
+
+    let mut lines = vec![];
+    for warning in warnings {
+        match warning {
+            StreamWarning::RunnerMoved {
+                runner,
+                from,
+                to,
+                layout,
+            } => {
+                let name = context.data().db.get_name_for_runner(runner).await?;
+                lines.push(format!(
+                    "Moved {} from slot {} to slot {} to fit layout {}",
+                    name, from, to, layout
+                ));
+            }
+        }
+    }
+    context.say(lines.join("\n")).await?;
+    Ok(())
+}
+
+/// Most choices Discord shows for an autocomplete
+const AUTOCOMPLETE_LIMIT: usize = 25;
+
+/// The names containing the typed text, ignoring case, with those starting with it first
+/// and then in alphabetical order, up to `AUTOCOMPLETE_LIMIT`
+fn rank_autocomplete(names: Vec<String>, partial: &str) -> impl Stream<Item = String> {
+    let partial = partial.to_lowercase();
+    let mut matches: Vec<(bool, String, String)> = names
+        .into_iter()
+        .filter_map(|name| {
+            let lower = name.to_lowercase();
+            lower.find(&partial).map(|pos| (pos != 0, lower, name))
+        })
+        .collect();
+    matches.sort();
+    matches.dedup_by(|a, b| a.2 == b.2);
+
+    futures::stream::iter(matches.into_iter().take(AUTOCOMPLETE_LIMIT).map(|m| m.2))
+}
+
 /// Create an autocomplete stream that matches streamed events
 async fn autocomplete_streamed_event_name<'a>(
     ctx: Context<'_>,
     partial: &'a str,
 ) -> impl Stream<Item = String> + 'a {
-    let events: Vec<String> = ctx.data().db.get_streamed_event_names().await.unwrap();
+    let streams = ctx.data().db.get_stream_listing().await.unwrap();
+    let host = get_invoker_host(ctx).await;
+    let on_host = streams
+        .iter()
+        .any(|(_, _, obs_host, _)| Some(obs_host) == host.as_ref());
+    let events: Vec<String> = streams
+        .into_iter()
+        .filter(|(_, _, obs_host, _)| !on_host || Some(obs_host) == host.as_ref())
+        .map(|(_, name, ..)| name)
+        .collect();
 
-    futures::stream::iter(events)
-        .filter(move |name| {
-            futures::future::ready(name.to_lowercase().starts_with(&partial.to_lowercase()))
-        })
-        .map(|name| name.to_string())
+    rank_autocomplete(events, partial)
 }
 
 /// Create an autocomplete stream that matches events
@@ -189,11 +444,7 @@ async fn autocomplete_event_name<'a>(
 ) -> impl Stream<Item = String> + 'a {
     let runners: Vec<String> = ctx.data().db.get_event_names().await.unwrap();
 
-    futures::stream::iter(runners)
-        .filter(move |name| {
-            futures::future::ready(name.to_lowercase().starts_with(&partial.to_lowercase()))
-        })
-        .map(|name| name.to_string())
+    rank_autocomplete(runners, partial)
 }
 
 /// Create an autocomplete stream that matches runner names
@@ -208,14 +459,95 @@ async fn autocomplete_runner_name<'a>(
         .await
         .unwrap()
         .iter()
+        .filter(|p| !p.archived)
         .map(|p| p.name.clone())
         .collect();
 
-    futures::stream::iter(runners)
-        .filter(move |name| {
-            futures::future::ready(name.to_lowercase().starts_with(&partial.to_lowercase()))
-        })
-        .map(|name| name.to_string())
+    rank_autocomplete(runners, partial)
+}
+
+/// Return the value of another argument of the command being autocompleted
+fn get_autocomplete_arg(ctx: &Context<'_>, name: &str) -> Option<String> {
+    match ctx {
+        poise::Context::Application(app) => app
+            .args
+            .iter()
+            .find(|arg| arg.name == name)
+            .and_then(|arg| arg.value.as_ref())
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_owned()),
+        poise::Context::Prefix(_) => None,
+    }
+}
+
+/// Create an autocomplete stream that matches layouts on the selected event's OBS host
+async fn autocomplete_layout_name<'a>(
+    ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Stream<Item = String> + 'a {
+    let db = &ctx.data().db;
+    let event = get_autocomplete_arg(&ctx, "event");
+
+    let mut layouts = vec![];
+    if let Ok(stream_id) = get_stream_id(ctx, event).await {
+        if let Ok(stream) = db.get_stream(stream_id).await {
+            let host = stream.obs_host;
+            match send_message!(
+                ctx.data().directory.obs_actor,
+                ObsCommand,
+                GetSceneNames,
+                host
+            ) {
+                Ok(scenes) => {
+                    layouts = scenes
+                        .into_iter()
+                        .filter(|s| s.usable)
+                        .map(|s| s.name)
+                        .collect()
+                }
+                Err(e) => log::warn!("Failed to get layouts for autocomplete: {}", e),
+            }
+        }
+    }
+
+    rank_autocomplete(layouts, partial)
+}
+
+/// Create an autocomplete stream that matches the scenes of the selected OBS host
+async fn autocomplete_scene_name<'a>(
+    ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Stream<Item = String> + 'a {
+    let mut names = vec![];
+    if let Some(host) = get_autocomplete_arg(&ctx, "host") {
+        match send_message!(
+            ctx.data().directory.obs_actor,
+            ObsCommand,
+            GetSceneNames,
+            host
+        ) {
+            Ok(scenes) => names = scenes.into_iter().map(|s| s.name).collect(),
+            Err(e) => log::warn!("Failed to get scenes for autocomplete: {}", e),
+        }
+    }
+
+    rank_autocomplete(names, partial)
+}
+
+/// Create an autocomplete stream that matches stream preset names
+async fn autocomplete_preset_name<'a>(
+    ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Stream<Item = String> + 'a {
+    let presets: Vec<String> = match ctx.data().db.get_stream_presets().await {
+        Ok(presets) => presets.into_iter().map(|p| p.name).collect(),
+        Err(e) => {
+            log::warn!("Failed to get stream presets for autocomplete: {}", e);
+            vec![]
+        }
+    };
+
+    rank_autocomplete(presets, partial)
 }
 
 /// Create an autocomplete stream that matches layout names
@@ -225,9 +557,7 @@ async fn autocomplete_obs_name<'a>(
 ) -> impl Stream<Item = String> + 'a {
     let hosts: Vec<String> = ctx.data().settings.obs_hosts.keys().cloned().collect();
 
-    futures::stream::iter(hosts)
-        .filter(move |name| futures::future::ready(name.starts_with(partial)))
-        .map(|name| name.to_string())
+    rank_autocomplete(hosts, partial)
 }
 
 /// Toggle the visibility for a specific runner.
@@ -245,27 +575,57 @@ async fn toggle(
     #[autocomplete = "autocomplete_streamed_event_name"]
     event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let stream_id = get_stream_id(event.clone(), &context.data().db).await?;
-    let mut stream = context.data().db.get_stream(stream_id).await?;
+    let stream_id = get_stream_id(context, event.clone()).await?;
     let runner = context.data().db.find_runner(&runner).await?;
 
-    match stream.get_runner_slot(runner.id) {
-        Some(pos) => {
-            stream.stream_runners.remove(&pos);
+    // Runners are only placed in slots that the layout they would be shown with has
+    let stream = context.data().db.get_stream(stream_id).await?;
+    let layout = match get_connected_stream_layout(
+        &*context.data().db,
+        &context.data().directory,
+        &stream,
+        stream.stream_runners.len() + 1,
+    )
+    .await
+    {
+        Ok(layout) => layout,
+        Err(e) => {
+            log::warn!("Failed to get the layout of stream {}: {}", stream_id, e);
+            None
         }
-        None => {
-            stream.stream_runners.insert(stream.get_first_empty_slot(), runner.id);
+    };
+    let slots = layout.as_ref().map(|l| l.get_slots());
+    if let Some(layout) = &layout {
+        if stream.get_runner_slot(runner.id).is_none()
+            && stream.get_first_empty_slot(slots.as_deref()).is_none()
+        {
+            return Err(anyhow!(
+                "No free slot on layout {} for {}",
+                layout.name,
+                runner.name
+            ));
         }
     }
-       
-    send_message!(
-        &context.data().directory.stream_actor,
-        StreamRequest,
-        Update,
-        stream
-    )?;
 
-    send_success_reply(&context).await
+    let warnings = update_stream(
+        &*context.data().db,
+        &context.data().directory,
+        stream_id,
+        false,
+        |stream| match stream.get_runner_slot(runner.id) {
+            Some(pos) => {
+                stream.stream_runners.remove(&pos);
+            }
+            None => {
+                if let Some(slot) = stream.get_first_empty_slot(slots.as_deref()) {
+                    stream.stream_runners.insert(slot, runner.id);
+                }
+            }
+        },
+    )
+    .await?;
+
+    send_stream_update_reply(&context, warnings).await
 }
 
 /// Refresh the stream of an active runner, or all runners if no names are provided.
@@ -287,13 +647,15 @@ async fn refresh(
     #[autocomplete = "autocomplete_streamed_event_name"]
     event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let stream_id = get_stream_id(event.clone(), &context.data().db).await?;
+    let stream_id = get_stream_id(context, event.clone()).await?;
+    let host = Some(context.data().db.get_stream(stream_id).await?.obs_host);
     let runner = context.data().db.find_runner(&runner).await?;
     send_message!(
         &context.data().directory.runner_actor,
         RunnerRequest,
         RefreshStream,
-        runner.id
+        runner.id,
+        host
     )?;
     send_message!(
         &context.data().directory.stream_actor,
@@ -304,6 +666,39 @@ async fn refresh(
     send_success_reply(&context).await
 }
 
+/// Hand off a relay to the next runner.
+///
+/// Replaces the provided runner with the next runner of the event
+/// that is not already in view.
+/// ```
+/// /handoff javster101
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn handoff(
+    context: Context<'_>,
+    #[description = "Runner that finished their leg"]
+    #[autocomplete = "autocomplete_runner_name"]
+    runner: String,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event.clone()).await?;
+    let runner = context.data().db.find_runner(&runner).await?.id;
+    let next = send_message!(
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        Handoff,
+        stream_id,
+        runner
+    )?;
+
+    match next {
+        Some(_) => send_success_reply(&context).await,
+        None => Err(anyhow!("There is no runner left to hand off to")),
+    }
+}
+
 /// Swap two runners.
 ///
 /// This can be used to swap a runner with another in view, and to
@@ -324,39 +719,39 @@ async fn swap(
     #[autocomplete = "autocomplete_streamed_event_name"]
     event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let stream_id = get_stream_id(event.clone(), &context.data().db).await?;
-    let mut stream = context.data().db.get_stream(stream_id).await?;
+    let stream_id = get_stream_id(context, event.clone()).await?;
 
     let runner1 = context.data().db.find_runner(&runner1).await?;
     let runner2 = context.data().db.find_runner(&runner2).await?;
 
-    let pos_p1 = stream.get_runner_slot(runner1.id);
-    let pos_p2 = stream.get_runner_slot(runner2.id);
-
-    if let (Some(pos_p1), Some(pos_p2)) = (pos_p1, pos_p2) {
-        stream.stream_runners.insert(pos_p1, runner2.id);
-        stream.stream_runners.insert(pos_p2, runner1.id);
-    } else if let Some(pos_p1) = pos_p1 {
-        stream.stream_runners.insert(pos_p1, runner2.id);
-    } else if let Some(pos_p2) = pos_p2 {
-        stream.stream_runners.insert(pos_p2, runner1.id);
-    }
-
-    send_message!(
-        &context.data().directory.stream_actor,
-        StreamRequest,
-        Update,
-        stream
-    )?;
+    let warnings = update_stream(
+        &*context.data().db,
+        &context.data().directory,
+        stream_id,
+        false,
+        |stream| {
+            let pos_p1 = stream.get_runner_slot(runner1.id);
+            let pos_p2 = stream.get_runner_slot(runner2.id);
+
+            if let (Some(pos_p1), Some(pos_p2)) = (pos_p1, pos_p2) {
+                stream.stream_runners.insert(pos_p1, runner2.id);
+                stream.stream_runners.insert(pos_p2, runner1.id);
+            } else if let Some(pos_p1) = pos_p1 {
+                stream.stream_runners.insert(pos_p1, runner2.id);
+            } else if let Some(pos_p2) = pos_p2 {
+                stream.stream_runners.insert(pos_p2, runner1.id);
+            }
+        },
+    )
+    .await?;
 
-    send_success_reply(&context).await
+    send_stream_update_reply(&context, warnings).await
 }
 
 /// Enable a certain layout.
 ///
-/// If the provided layout does not support the current amount
-/// of enabled runners, it will be enabled whenever the supported amount
-/// of runners is enabled.
+/// Runners in slots the layout does not have are refused or moved into free slots,
+/// depending on the `slot_overflow` setting.
 /// ```
 /// /layout 4_runners
 /// ```
@@ -364,173 +759,991 @@ async fn swap(
 async fn layout(
     context: Context<'_>,
     #[description = "Layout to use"]
-    //  #[autocomplete = "autocomplete_layout_name"]
+    #[autocomplete = "autocomplete_layout_name"]
     layout: String,
     #[description = "Event for this command"]
     #[autocomplete = "autocomplete_streamed_event_name"]
     event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let stream_id = get_stream_id(event.clone(), &context.data().db).await?;
-    let mut stream = context.data().db.get_stream(stream_id).await?;
-    stream.requested_layout = Some(layout.clone());
-    send_message!(
-        &context.data().directory.stream_actor,
-        StreamRequest,
-        Update,
-        stream
-    )?;
+    let stream_id = get_stream_id(context, event.clone()).await?;
+    update_stream(
+        &*context.data().db,
+        &context.data().directory,
+        stream_id,
+        false,
+        |stream| stream.requested_layout = Some(layout.clone()),
+    )
+    .await?;
     send_success_reply(&context).await
 }
 
-/// Set the active runners.
+/// Apply a saved stream preset, keeping the current runners where they fit.
 ///
 /// ```
-/// /set
-/// /set javster101 p53
+/// /preset 4_way_race
 /// ```
 #[poise::command(prefix_command, slash_command)]
-async fn set(
+async fn preset(
     context: Context<'_>,
-    #[description = "Runners to enable"] runners: Option<String>,
+    #[description = "Preset to apply"]
+    #[autocomplete = "autocomplete_preset_name"]
+    name: String,
     #[description = "Event for this command"]
     #[autocomplete = "autocomplete_streamed_event_name"]
     event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let runner_ids = match runners {
-        Some(runners) => {
-            let mut res = vec![];
-            let runners: Vec<String> = runners.split(',').map(|s| s.trim().to_owned()).collect();
-            for runner in runners {
-                res.push(context.data().db.find_runner(&runner).await?.id);
-            }
-
-            res
-        }
-        None => {
-            vec![]
-        }
-    };
-    let stream_id = get_stream_id(event.clone(), &context.data().db).await?;
-    let mut stream = context.data().db.get_stream(stream_id).await?;
-    stream.stream_runners = runner_ids.iter().enumerate().map(|(i, r)| ((i as i64), *r)).collect();
-
-    send_message!(
+    let stream_id = get_stream_id(context, event).await?;
+    let warnings = send_message!(
         &context.data().directory.stream_actor,
         StreamRequest,
-        Update,
-        stream
+        ApplyPreset,
+        stream_id,
+        name.clone()
     )?;
+    send_stream_update_reply(&context, warnings).await
+}
 
-    send_success_reply(&context).await
+#[derive(Debug, poise::ChoiceParameter)]
+enum Switch {
+    #[name = "on"]
+    On,
+    #[name = "off"]
+    Off,
 }
 
-/// Set a list of commentator names to ignore.
+/// Turn automatic layout rotation on or off, optionally setting the layouts and dwell time.
 ///
-/// This command can be used to stop 'listener' users from appearing
-/// as commentators. The provided names should be the user's nicknames
-/// (those that appear in the voice channel).
 /// ```
-/// /ignore streamer_bot
+/// /rotate on
+/// /rotate on 4_way_a 4_way_b 90
+/// /rotate off
 /// ```
 #[poise::command(prefix_command, slash_command)]
-async fn ignore(
+async fn rotate(
     context: Context<'_>,
-    #[description = "Names to ignore"] ignored: Option<String>,
+    #[description = "Whether to rotate layouts"] state: Switch,
+    #[description = "Layouts to rotate through"] layouts: Option<String>,
+    #[description = "Seconds to show each layout"] dwell: Option<u64>,
     #[description = "Event for this command"]
     #[autocomplete = "autocomplete_streamed_event_name"]
     event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let stream_id = get_stream_id(event.clone(), &context.data().db).await?;
-    let mut stream = context.data().db.get_stream(stream_id).await?;
-    stream.ignored_commentators = ignored.unwrap_or_default();
-    send_message!(
-        &context.data().directory.stream_actor,
-        StreamRequest,
-        Update,
-        stream
-    )?;
+    let db = &context.data().db;
+    let stream_id = get_stream_id(context, event).await?;
+
+    update_stream(
+        &**db,
+        &context.data().directory,
+        stream_id,
+        false,
+        |stream| {
+            let mut rotation = stream
+                .rotation
+                .take()
+                .map(|r| r.0)
+                .unwrap_or(LayoutRotation {
+                    layouts: vec![],
+                    dwell_seconds: DEFAULT_ROTATION_DWELL_SECS,
+                    enabled: false,
+                });
+            if let Some(layouts) = &layouts {
+                rotation.layouts = layouts.split_whitespace().map(str::to_owned).collect();
+            }
+            if let Some(dwell) = dwell {
+                rotation.dwell_seconds = dwell;
+            }
+            rotation.enabled = matches!(state, Switch::On);
+            stream.rotation = Some(sqlx::types::Json(rotation));
+        },
+    )
+    .await?;
     send_success_reply(&context).await
 }
 
-/// Create a stream for an event.
+/// Show a prepared stream on its OBS host, replacing the stream shown there.
+///
+/// ```
+/// /activate Finals
+/// ```
 #[poise::command(prefix_command, slash_command)]
-async fn create_stream(
+async fn activate(
     context: Context<'_>,
-    #[description = "Event to stream"]
-    #[autocomplete = "autocomplete_event_name"]
+    #[description = "Event to show"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
     event: String,
-    #[description = "OBS host to use"]
-    #[autocomplete = "autocomplete_obs_name"]
-    host: String,
 ) -> Result<(), anyhow::Error> {
-    let event = context.data().db.get_id_for_event(&event).await?;
+    let stream_id = get_stream_id(context, Some(event)).await?;
     send_message!(
         &context.data().directory.stream_actor,
         StreamRequest,
-        Create,
-        event,
-        host
+        Activate,
+        stream_id
     )?;
     send_success_reply(&context).await
 }
 
-/// Stop a stream related to an event.
+/// Fill the empty slots of a stream with the event's runners.
 ///
-/// This allows the OBS host this stream is associated with to be used again.
+/// Runners already in view keep their slots.
+///
+/// ```
+/// /autofill
+/// /autofill Finals
+/// ```
 #[poise::command(prefix_command, slash_command)]
-async fn delete_stream(
+async fn autofill(
     context: Context<'_>,
-    #[description = "Stream to delete"]
+    #[description = "Stream to fill"]
     #[autocomplete = "autocomplete_streamed_event_name"]
-    event: String,
+    event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let event = context.data().db.get_id_for_event(&event).await?;
+    let stream_id = get_stream_id(context, event).await?;
     send_message!(
         &context.data().directory.stream_actor,
         StreamRequest,
-        Delete,
-        event
+        AutofillFromEvent,
+        stream_id
     )?;
     send_success_reply(&context).await
 }
 
-/// Start the timer now.
+/// Rebuild a stream's OBS layout from scratch.
 ///
-/// This may be a bit off due to input delay. For more accurate input, set a Unix timestamp with
-/// set_start_time.
+/// With `purge`, the runner sources on the host are removed and created again,
+/// which must be confirmed.
 ///
 /// ```
-/// /start_timer
+/// /resync
+/// /resync Finals purge:true confirm:true
 /// ```
 #[poise::command(prefix_command, slash_command)]
-async fn start_timer(
+async fn resync(
     context: Context<'_>,
-    #[description = "Event for this command"]
-    #[autocomplete = "autocomplete_event_name"]
-    event: String,
+    #[description = "Stream to resync"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+    #[description = "Remove and recreate the runner sources on the host"] purge: Option<bool>,
+    #[description = "Confirm removing the runner sources"] confirm: Option<bool>,
 ) -> Result<(), anyhow::Error> {
-    let time = OffsetDateTime::now_utc();
-    let event = context.data().db.get_id_for_event(&event).await?;
+    let stream_id = get_stream_id(context, event).await?;
+    let purge = purge.unwrap_or(false);
+    if purge && !confirm.unwrap_or(false) {
+        context
+            .say("Purging removes the runner sources of the host, run again with `confirm`.")
+            .await?;
+        return Ok(());
+    }
+
     send_message!(
-        &context.data().directory.event_actor,
-        EventRequest,
-        SetStartTime,
-        event,
-        Some(time)
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        ForceResync,
+        stream_id,
+        purge
     )?;
     send_success_reply(&context).await
 }
 
-/// Stop the timer now.
-///
-/// This may be a bit off due to input delay. For more accurate input, set a Unix timestamp with
-/// set_end_time.
+/// Set the active runners.
 ///
 /// ```
-/// /stop_timer
+/// /set
+/// /set javster101 p53
 /// ```
 #[poise::command(prefix_command, slash_command)]
-async fn stop_timer(
+async fn set(
+    context: Context<'_>,
+    #[description = "Runners to enable"] runners: Option<String>,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let runner_ids = match runners {
+        Some(runners) => {
+            let mut res = vec![];
+            let runners: Vec<String> = runners.split(',').map(|s| s.trim().to_owned()).collect();
+            for runner in runners {
+                res.push(context.data().db.find_runner(&runner).await?.id);
+            }
+
+            res
+        }
+        None => {
+            vec![]
+        }
+    };
+    let stream_id = get_stream_id(context, event.clone()).await?;
+    update_stream(
+        &*context.data().db,
+        &context.data().directory,
+        stream_id,
+        false,
+        |stream| {
+            stream.stream_runners = runner_ids
+                .iter()
+                .enumerate()
+                .map(|(i, r)| ((i as i64), *r))
+                .collect();
+        },
+    )
+    .await?;
+
+    send_success_reply(&context).await
+}
+
+/// Keep 'listener' users, such as bots and producers, out of the commentators.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("ignore_add", "ignore_remove", "ignore_list", "ignore_names")
+)]
+async fn ignore(_context: Context<'_>) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+/// Never show a Discord user as a commentator, on any stream.
+///
+/// ```
+/// /ignore add @streamer_bot
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "add")]
+async fn ignore_add(
+    context: Context<'_>,
+    #[description = "Discord user to ignore"] user: serenity::User,
+) -> Result<(), anyhow::Error> {
+    context
+        .data()
+        .db
+        .add_ignored_discord_user(&user.id.to_string(), &user.name)
+        .await?;
+    refresh_user_voice_channel(&context, &user).await;
+    send_success_reply(&context).await
+}
+
+/// Show an ignored Discord user as a commentator again.
+///
+/// ```
+/// /ignore remove @streamer_bot
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "remove")]
+async fn ignore_remove(
+    context: Context<'_>,
+    #[description = "Discord user to stop ignoring"] user: serenity::User,
+) -> Result<(), anyhow::Error> {
+    let removed = context
+        .data()
+        .db
+        .remove_ignored_discord_user(&user.id.to_string())
+        .await?;
+    if !removed {
+        context.say(format!("{} is not ignored", user.name)).await?;
+        return Ok(());
+    }
+    refresh_user_voice_channel(&context, &user).await;
+    send_success_reply(&context).await
+}
+
+/// List the ignored Discord users.
+///
+/// ```
+/// /ignore list
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "list")]
+async fn ignore_list(context: Context<'_>) -> Result<(), anyhow::Error> {
+    let users = context.data().db.get_ignored_discord_users().await?;
+    let mut reply = if users.is_empty() {
+        "No Discord users are ignored.".to_owned()
+    } else {
+        users
+            .iter()
+            .map(|(id, name)| format!("{} (<@{}>)", name, id))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    if let Some(role) = &context.data().settings.discord_ignore_role_id {
+        reply.push_str(&format!("\nMembers of <@&{}> are ignored as well.", role));
+    }
+    context.say(reply).await?;
+    Ok(())
+}
+
+/// Set a list of commentator names to ignore on one stream.
+///
+/// The provided names should be the user's nicknames
+/// (those that appear in the voice channel).
+/// ```
+/// /ignore names streamer_bot
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "names")]
+async fn ignore_names(
+    context: Context<'_>,
+    #[description = "Names to ignore"] ignored: Option<String>,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event.clone()).await?;
+    let ignored = ignored.unwrap_or_default();
+    update_stream(
+        &*context.data().db,
+        &context.data().directory,
+        stream_id,
+        false,
+        |stream| stream.ignored_commentators = ignored.clone(),
+    )
+    .await?;
+    send_success_reply(&context).await
+}
+
+/// Create an autocomplete stream that matches the commentators of streams
+async fn autocomplete_commentator_name<'a>(
+    ctx: Context<'_>,
+    partial: &'a str,
+) -> impl Stream<Item = String> + 'a {
+    let db = &ctx.data().db;
+    let mut commentators: Vec<String> = vec![];
+    for event in db.get_streamed_events().await.unwrap_or_default() {
+        if let Ok(stream) = db.get_stream(event).await {
+            commentators.extend(
+                stream
+                    .get_commentators()
+                    .into_iter()
+                    .filter(|c| !c.is_empty()),
+            );
+        }
+    }
+    commentators.sort();
+    commentators.dedup();
+
+    rank_autocomplete(commentators, partial)
+}
+
+/// Set the commentator hosting an event's commentary, or clear it if no name is given.
+///
+/// ```
+/// /host javster101
+/// /host
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn host(
+    context: Context<'_>,
+    #[description = "Commentator name as shown in the voice channel"]
+    #[autocomplete = "autocomplete_commentator_name"]
+    name: Option<String>,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event).await?;
+    send_message!(
+        &context.data().directory.event_actor,
+        EventRequest,
+        SetHost,
+        stream_id,
+        name
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Show or change the order of a stream's commentators.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("commentators_list", "commentators_move")
+)]
+async fn commentators(_context: Context<'_>) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+/// List the commentators of a stream with their positions.
+///
+/// ```
+/// /commentators list
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "list")]
+async fn commentators_list(
+    context: Context<'_>,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event).await?;
+    let stream = context.data().db.get_stream(stream_id).await?;
+    let commentators = stream.get_commentator_details(&*context.data().db).await?;
+
+    let reply = if commentators.is_empty() {
+        "There are no commentators.".to_owned()
+    } else {
+        commentators
+            .iter()
+            .enumerate()
+            .map(|(idx, c)| {
+                format!(
+                    "{}. {}{}",
+                    idx + 1,
+                    c.name,
+                    match (c.host, c.ignored) {
+                        (true, true) => " (host, ignored)",
+                        (true, false) => " (host)",
+                        (false, true) => " (ignored)",
+                        (false, false) => "",
+                    }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    context.say(reply).await?;
+    Ok(())
+}
+
+/// Move a commentator to a position, 1 being the first.
+///
+/// ```
+/// /commentators move javster101 1
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "move")]
+async fn commentators_move(
+    context: Context<'_>,
+    #[description = "Commentator name as shown in the voice channel"] name: String,
+    #[description = "New position, starting at 1"] position: usize,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event).await?;
+    let stream = context.data().db.get_stream(stream_id).await?;
+
+    let mut order: Vec<String> = stream
+        .get_ordered_commentators()
+        .into_iter()
+        .filter(|c| !c.is_empty())
+        .map(|c| c.to_owned())
+        .collect();
+    let current = order
+        .iter()
+        .position(|c| c.eq_ignore_ascii_case(&name))
+        .ok_or_else(|| anyhow!("{} is not a commentator of this stream", name))?;
+
+    let commentator = order.remove(current);
+    order.insert(position.clamp(1, order.len() + 1) - 1, commentator);
+
+    send_message!(
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        ReorderCommentators,
+        stream_id,
+        order
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Post the placements of an event.
+///
+/// Unfinished runners are listed after the placed runners.
+/// ```
+/// /results "Any% Race"
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn results(
+    context: Context<'_>,
+    #[description = "Event to show results for"]
+    #[autocomplete = "autocomplete_event_name"]
+    event: String,
+) -> Result<(), anyhow::Error> {
+    let event = context.data().db.get_id_for_event(&event).await?;
+    let export = export::export_event(&*context.data().db, event).await?;
+
+    let mut lines = vec![];
+    for result in export.results.iter().take(10) {
+        let value = match (result.time, result.score) {
+            (Some(time), _) => format_run_time(time),
+            (None, Some(score)) => score.to_string(),
+            (None, None) => "DNF".to_owned(),
+        };
+
+        match result.placement {
+            Some(placement) => {
+                lines.push(format!("**{}.** {} - {}", placement, result.name, value))
+            }
+            None => lines.push(format!("- {} - {}", result.name, value)),
+        }
+    }
+
+    if lines.is_empty() {
+        lines.push("No runners in this event".to_owned());
+    }
+
+    let title = match &export.game {
+        Some(game) => format!("{} ({})", export.name, game),
+        None => export.name.clone(),
+    };
+
+    context
+        .send(|m| m.embed(|e| e.title(title).description(lines.join("\n"))))
+        .await?;
+    Ok(())
+}
+
+/// Maximum length of an embed field value
+const EMBED_FIELD_LIMIT: usize = 1024;
+/// Maximum number of embeds in a message
+const EMBED_COUNT_LIMIT: usize = 10;
+
+/// Join lines into an embed field value, cutting off lines that don't fit
+fn to_field_value(lines: &[String]) -> String {
+    let mut value = String::new();
+    for (idx, line) in lines.iter().enumerate() {
+        let more = format!("\n...and {} more", lines.len() - idx);
+        if value.len() + line.len() + 1 + more.len() > EMBED_FIELD_LIMIT {
+            value.push_str(more.trim_start());
+            break;
+        }
+        value.push_str(line);
+        value.push('\n');
+    }
+
+    if value.is_empty() {
+        "None".to_owned()
+    } else {
+        value.trim_end().to_owned()
+    }
+}
+
+/// Format a split delta in milliseconds with its sign
+fn format_delta(ms: f64) -> String {
+    let sign = if ms < 0.0 { "-" } else { "+" };
+    format!("{}{}", sign, format_run_time(ms.abs()))
+}
+
+/// A streamed event as shown by /status
+struct StatusEmbed {
+    title: String,
+    fields: Vec<(&'static str, String, bool)>,
+}
+
+async fn get_status_embed(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    event: i64,
+    hosts: &HashMap<String, ObsHostState>,
+) -> anyhow::Result<StatusEmbed> {
+    let stream = db.get_stream(event).await?;
+    let event = db.get_event(event).await?;
+
+    let mut slots: Vec<_> = stream.stream_runners.iter().collect();
+    slots.sort();
+    let mut runners = vec![];
+    for (idx, (slot, runner)) in slots.into_iter().enumerate() {
+        let name = db.get_name_for_runner(*runner).await?;
+        let audible = stream
+            .audible_runner
+            .map(|r| r == *runner)
+            .unwrap_or(idx == 0);
+        let mut line = format!(
+            "{}. {}{}",
+            slot + 1,
+            name,
+            if audible { " \u{1f50a}" } else { "" }
+        );
+        if let Ok(run) = db.get_runner_run_data(*runner).await {
+            if run.is_finished() {
+                let time = run.splits.last().and_then(|s| s.split_time);
+                line.push_str(&format!(
+                    " - finished {}",
+                    time.map(format_run_time).unwrap_or_default()
+                ));
+            } else if !run.current_split_name.is_empty() {
+                line.push_str(&format!(" - {}", run.current_split_name));
+                if let Some(delta) = run.delta {
+                    line.push_str(&format!(" ({})", format_delta(delta)));
+                }
+            }
+        }
+        runners.push(line);
+    }
+
+    let timer = match (event.timer_start_time, event.timer_end_time) {
+        (Some(start), Some(end)) => format!(
+            "Stopped at {}",
+            format_run_time((end - start).whole_milliseconds() as f64)
+        ),
+        (Some(start), None) => format!(
+            "Running, {}",
+            format_run_time((OffsetDateTime::now_utc() - start).whole_milliseconds() as f64)
+        ),
+        _ => match event.event_start_time {
+            Some(start) => format!(
+                "Scheduled for {}",
+                format_local_time(start, settings.get_timezone())
+            ),
+            None => "Not started".to_owned(),
+        },
+    };
+
+    let obs = match hosts.get(&stream.obs_host) {
+        Some(host) if host.dry_run => format!("{} - Dry run, OBS is not changed", stream.obs_host),
+        Some(host) if host.connected => format!(
+            "{} - {}{}",
+            stream.obs_host,
+            if host.streaming {
+                "\u{1f534} Live"
+            } else {
+                "Offline"
+            },
+            if host.replay_buffer {
+                ", replay buffer on"
+            } else {
+                ""
+            }
+        ),
+        Some(host) => match &host.last_connection_error {
+            Some(e) => format!("{} - Disconnected: {}", stream.obs_host, e.message),
+            None => format!("{} - Disconnected", stream.obs_host),
+        },
+        None => format!("{} - Disconnected", stream.obs_host),
+    };
+
+    let commentators: Vec<_> = stream
+        .get_commentators()
+        .into_iter()
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    Ok(StatusEmbed {
+        title: match &event.game {
+            Some(game) => format!("{} ({})", event.name, game),
+            None => event.name.clone(),
+        },
+        fields: vec![
+            ("Runners", to_field_value(&runners), false),
+            (
+                "Layout",
+                stream
+                    .requested_layout
+                    .clone()
+                    .unwrap_or("Automatic".to_owned()),
+                true,
+            ),
+            ("Timer", timer, true),
+            ("OBS", obs, true),
+            ("Commentators", to_field_value(&commentators), false),
+        ],
+    })
+}
+
+/// Show what is currently live.
+///
+/// Posts one embed per streamed event, or only the stream on the given host.
+/// ```
+/// /status host1
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn status(
+    context: Context<'_>,
+    #[description = "OBS host to show"]
+    #[autocomplete = "autocomplete_obs_name"]
+    host: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let _ = context.defer().await;
+    let db = &context.data().db;
+    let hosts = send_message!(&context.data().directory.obs_actor, ObsCommand, GetState)?;
+    let updated = serenity::Timestamp::now();
+
+    let mut events = db.get_streamed_events().await?;
+    if let Some(host) = &host {
+        events = vec![db.get_event_by_obs_host(host).await?];
+    }
+    events.sort();
+
+    let mut embeds = vec![];
+    for event in &events {
+        embeds.push(get_status_embed(&**db, &context.data().settings, *event, &hosts).await?);
+    }
+
+    if embeds.is_empty() {
+        context.say("Nothing is live right now").await?;
+        return Ok(());
+    }
+
+    let hidden = embeds.len().saturating_sub(EMBED_COUNT_LIMIT);
+    embeds.truncate(EMBED_COUNT_LIMIT);
+
+    context
+        .send(|m| {
+            if hidden > 0 {
+                m.content(format!("{} more streams are not shown", hidden));
+            }
+            for embed in embeds {
+                m.embed(|e| {
+                    e.title(embed.title);
+                    for (name, value, inline) in embed.fields {
+                        e.field(name, value, inline);
+                    }
+                    e.footer(|f| f.text("OBS state as of")).timestamp(updated)
+                });
+            }
+            m
+        })
+        .await?;
+    Ok(())
+}
+
+/// Show what is known about a runner, including their live run.
+///
+/// ```
+/// /runner_info javster101
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn runner_info(
+    context: Context<'_>,
+    #[description = "Runner to show"]
+    #[autocomplete = "autocomplete_runner_name"]
+    name: String,
+) -> Result<(), anyhow::Error> {
+    let db = &context.data().db;
+    let runner = db.find_runner(&name).await?;
+    let info = RunnerInfo::load(&**db, runner.id).await?;
+
+    let mut fields = vec![];
+    if !info.nicks.is_empty() {
+        fields.push(("Nicknames", info.nicks.join(", "), true));
+    }
+    if let Some(location) = &info.location {
+        fields.push(("Location", location.clone(), true));
+    }
+    if !info.socials.is_empty() {
+        let socials: Vec<String> = info
+            .socials
+            .iter()
+            .map(|(platform, handle)| format!("{}: @{}", platform, handle))
+            .collect();
+        fields.push(("Socials", socials.join("\n"), true));
+    }
+    fields.push((
+        "Stream",
+        format!(
+            "{} ({})",
+            info.stream,
+            if info.stream_url_cached {
+                "URL acquired"
+            } else {
+                "no URL acquired yet"
+            }
+        ),
+        false,
+    ));
+
+    let run = match &info.run {
+        Some(run) => {
+            let mut lines = vec![if run.finished {
+                "Finished".to_owned()
+            } else {
+                format!(
+                    "Split {}/{}: {}",
+                    run.current_split_index + 1,
+                    run.split_count,
+                    run.current_split_name
+                )
+            }];
+            if let Some(delta) = run.delta {
+                lines.push(format!("Delta: {}", format_delta(delta)));
+            }
+            if let Some(best_possible) = run.best_possible {
+                lines.push(format!("Best possible: {}", format_run_time(best_possible)));
+            }
+            if let Some(pb) = run.pb {
+                lines.push(format!("PB: {}", format_run_time(pb)));
+            }
+            to_field_value(&lines)
+        }
+        None => "No live run".to_owned(),
+    };
+    fields.push(("TheRun.gg", format!("{}\n{}", info.therun, run), false));
+
+    if !info.events.is_empty() {
+        fields.push(("Events", to_field_value(&info.events), false));
+    }
+    if !info.in_view.is_empty() {
+        fields.push(("In view on", to_field_value(&info.in_view), false));
+    }
+
+    context
+        .send(|m| {
+            m.embed(|e| {
+                e.title(&info.name);
+                for (name, value, inline) in fields {
+                    e.field(name, value, inline);
+                }
+                e
+            })
+        })
+        .await?;
+    Ok(())
+}
+
+/// Show a scene on an OBS host.
+///
+/// Scenes that are not layouts, such as a technical difficulties screen,
+/// stop layouts from being applied to the host until `/resume_layout`.
+/// ```
+/// /scene host1 "Technical Difficulties"
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn scene(
+    context: Context<'_>,
+    #[description = "OBS host to switch"]
+    #[autocomplete = "autocomplete_obs_name"]
+    host: String,
+    #[description = "Scene to show"]
+    #[autocomplete = "autocomplete_scene_name"]
+    scene: String,
+) -> Result<(), anyhow::Error> {
+    send_message!(
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        SwitchScene,
+        host,
+        scene
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Browser source group in the settings that `/league` sets
+const LEAGUE_BROWSER_SOURCE_GROUP: &str = "league";
+
+/// Show a ladder league on an OBS host, through the `league` browser source group in the settings.
+///
+/// The league ID fills the `{id}` placeholders of the group.
+/// Uses the host of the channel or of the invoker's roles if no host is given.
+/// ```
+/// /league 42
+/// /league 42 host1
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn league(
+    context: Context<'_>,
+    #[description = "League ID"] id: String,
+    #[description = "OBS host to update"]
+    #[autocomplete = "autocomplete_obs_name"]
+    host: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let host = match host {
+        Some(host) => host,
+        None => get_invoker_host(context)
+            .await
+            .ok_or_else(|| anyhow!("No OBS host is assigned to this channel, give one"))?,
+    };
+    let group = LEAGUE_BROWSER_SOURCE_GROUP.to_owned();
+    let params = HashMap::from([("id".to_owned(), id)]);
+    send_message!(
+        &context.data().directory.obs_actor,
+        ObsCommand,
+        SetBrowserSourceGroup,
+        host,
+        group,
+        params
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Show the layout of an OBS host's stream again after `/scene`.
+#[poise::command(prefix_command, slash_command)]
+async fn resume_layout(
+    context: Context<'_>,
+    #[description = "OBS host to resume"]
+    #[autocomplete = "autocomplete_obs_name"]
+    host: String,
+) -> Result<(), anyhow::Error> {
+    send_message!(
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        ResumeLayout,
+        host
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Create a stream for an event.
+#[poise::command(prefix_command, slash_command)]
+async fn create_stream(
+    context: Context<'_>,
+    #[description = "Event to stream"]
+    #[autocomplete = "autocomplete_event_name"]
+    event: String,
+    #[description = "OBS host to use"]
+    #[autocomplete = "autocomplete_obs_name"]
+    host: String,
+) -> Result<(), anyhow::Error> {
+    let event = context.data().db.get_id_for_event(&event).await?;
+    send_message!(
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        Create,
+        event,
+        host
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Stop a stream related to an event.
+///
+/// This allows the OBS host this stream is associated with to be used again.
+#[poise::command(prefix_command, slash_command)]
+async fn delete_stream(
+    context: Context<'_>,
+    #[description = "Stream to delete"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: String,
+) -> Result<(), anyhow::Error> {
+    let event = context.data().db.get_id_for_event(&event).await?;
+    send_message!(
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        Delete,
+        event
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Start the timer now.
+///
+/// This may be a bit off due to input delay. For more accurate input, set a Unix timestamp with
+/// set_start_time.
+///
+/// ```
+/// /start_timer
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn start_timer(
+    context: Context<'_>,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_event_name"]
+    event: String,
+) -> Result<(), anyhow::Error> {
+    let time = OffsetDateTime::now_utc();
+    let event = context.data().db.get_id_for_event(&event).await?;
+    send_message!(
+        &context.data().directory.event_actor,
+        EventRequest,
+        SetStartTime,
+        event,
+        Some(time)
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Stop the timer now.
+///
+/// This may be a bit off due to input delay. For more accurate input, set a Unix timestamp with
+/// set_end_time.
+///
+/// ```
+/// /stop_timer
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn stop_timer(
     context: Context<'_>,
     #[description = "Event for this command"]
     #[autocomplete = "autocomplete_event_name"]
@@ -548,21 +1761,25 @@ async fn stop_timer(
     send_success_reply(&context).await
 }
 
-/// Set the start time for a race as a Unix millis timestamp.
+/// Set the timer start time of a race, or clear it if no time is given.
 ///
+/// The time is Unix milliseconds or an ISO 8601 time, read in the `timezone`
+/// setting if it has no offset.
 /// ```
 /// /set_start_time 1701125546192
+/// /set_start_time 2023-11-27T22:52:26+01:00
 /// ```
 #[poise::command(prefix_command, slash_command)]
 async fn set_start_time(
     context: Context<'_>,
-    #[description = "Event start time in Unix time"] time: Option<u64>,
+    #[description = "Timer start time, as Unix millis or ISO 8601"] time: Option<String>,
     #[description = "Event for this command"]
     #[autocomplete = "autocomplete_event_name"]
     event: String,
 ) -> Result<(), anyhow::Error> {
     let event = context.data().db.get_id_for_event(&event).await?;
-    let time = time.and_then(|t| OffsetDateTime::from_unix_timestamp(t.try_into().unwrap()).ok());
+    let timezone = context.data().settings.get_timezone();
+    let time = time.map(|t| parse_user_time(&t, timezone)).transpose()?;
 
     send_message!(
         &context.data().directory.event_actor,
@@ -571,24 +1788,27 @@ async fn set_start_time(
         event,
         time
     )?;
-    send_success_reply(&context).await
+    send_time_reply(&context, "Timer started", time).await
 }
 
-/// Set the end time for a race as a Unix millis timestamp.
+/// Set the timer end time of a race, or clear it if no time is given.
 ///
+/// The time is read like in `/set_start_time`.
 /// ```
-/// /set_relay_end 1701125546192
+/// /set_end_time 1701125546192
+/// /set_end_time 2023-11-27T23:40:00
 /// ```
 #[poise::command(prefix_command, slash_command)]
 async fn set_end_time(
     context: Context<'_>,
-    #[description = "Event start time in Unix time"] time: Option<u64>,
+    #[description = "Timer end time, as Unix millis or ISO 8601"] time: Option<String>,
     #[description = "Event for this command"]
     #[autocomplete = "autocomplete_event_name"]
     event: String,
 ) -> Result<(), anyhow::Error> {
     let event = context.data().db.get_id_for_event(&event).await?;
-    let time = time.and_then(|t| OffsetDateTime::from_unix_timestamp(t.try_into().unwrap()).ok());
+    let timezone = context.data().settings.get_timezone();
+    let time = time.map(|t| parse_user_time(&t, timezone)).transpose()?;
 
     send_message!(
         &context.data().directory.event_actor,
@@ -597,7 +1817,25 @@ async fn set_end_time(
         event,
         time
     )?;
-    send_success_reply(&context).await
+    send_time_reply(&context, "Timer stopped", time).await
+}
+
+/// Confirm a time that was set, shown in the `timezone` setting
+async fn send_time_reply(
+    context: &Context<'_>,
+    what: &str,
+    time: Option<OffsetDateTime>,
+) -> Result<(), anyhow::Error> {
+    let reply = match time {
+        Some(time) => format!(
+            "{} at {}",
+            what,
+            format_local_time(time, context.data().settings.get_timezone())
+        ),
+        None => format!("{} time cleared", what),
+    };
+    context.say(reply).await?;
+    Ok(())
 }
 
 /// Start the OBS stream.
@@ -618,6 +1856,73 @@ async fn start_stream(
     send_success_reply(&context).await
 }
 
+/// Save the replay buffer of an OBS host.
+///
+/// Replies with the path of the saved replay.
+/// ```
+/// /clip host1
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn clip(
+    context: Context<'_>,
+    #[description = "OBS host to save the replay of"]
+    #[autocomplete = "autocomplete_obs_name"]
+    host: String,
+) -> Result<(), anyhow::Error> {
+    let _ = context.defer().await;
+    let path = send_message!(
+        &context.data().directory.obs_actor,
+        ObsCommand,
+        SaveReplayBuffer,
+        host
+    )?;
+    context
+        .say(format!("Saved replay to `{}`", path.display()))
+        .await?;
+    Ok(())
+}
+
+/// Check that an OBS host is ready to go live.
+///
+/// Lists every check with whether it passed, without changing anything.
+/// ```
+/// /preflight host1
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn preflight(
+    context: Context<'_>,
+    #[description = "OBS host to check"]
+    #[autocomplete = "autocomplete_obs_name"]
+    host: String,
+) -> Result<(), anyhow::Error> {
+    let _ = context.defer().await;
+    let report = send_message!(
+        &context.data().directory.obs_actor,
+        ObsCommand,
+        PreflightCheck,
+        host,
+        None::<i64>
+    )?;
+
+    let mut lines = vec![];
+    for check in &report.checks {
+        let mark = match (check.passed, check.critical) {
+            (true, _) => "✅",
+            (false, true) => "❌",
+            (false, false) => "⚠️",
+        };
+        lines.push(format!("{} **{}**: {}", mark, check.name, check.detail));
+    }
+    lines.push(if report.passed() {
+        "Ready to go live".to_owned()
+    } else {
+        "Not ready to go live".to_owned()
+    });
+
+    context.say(lines.join("\n")).await?;
+    Ok(())
+}
+
 /// Stop the OBS stream.
 #[poise::command(prefix_command, slash_command)]
 async fn stop_stream(
@@ -658,9 +1963,20 @@ async fn create_event(
         timer_end_time: None,
         is_relay: false,
         is_marathon: false,
+        auto_relay_handoff: false,
+        relay_handoff_split: None,
+        auto_go_live: false,
+        scheduled_host: None,
+        scene_collection: None,
+        commentary_host: None,
+        locale: None,
+        theme: None,
+        version: 0,
         preferred_layouts: vec![],
         tournament: None,
         runner_state: HashMap::new(),
+        runner_order: vec![],
+        incidents: vec![],
     };
 
     let db = &context.data().db;
@@ -670,10 +1986,15 @@ async fn create_event(
 
     for name in runners_names_list {
         let runner = db.find_runner(&name).await?.id;
-        new_event.runner_state.insert(runner, RunnerEventState {
+        new_event.runner_state.insert(
             runner,
-            result: None,
-        });
+            RunnerEventState {
+                runner,
+                result: None,
+                ordering: 0,
+            },
+        );
+        new_event.runner_order.push(runner);
     }
 
     send_message!(
@@ -686,6 +2007,56 @@ async fn create_event(
     send_success_reply(&context).await
 }
 
+/// Complete an event: stop its timer if it is running, keep its results, and delete its stream.
+///
+/// ```
+/// /complete Finals
+/// /complete Finals true
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn complete(
+    context: Context<'_>,
+    #[description = "Event to complete"]
+    #[autocomplete = "autocomplete_event_name"]
+    event: String,
+    #[description = "Show the intermission scene of the event's OBS host"] intermission: Option<
+        bool,
+    >,
+) -> Result<(), anyhow::Error> {
+    let event = context.data().db.get_id_for_event(&event).await?;
+    let intermission = intermission.unwrap_or(false);
+    send_message!(
+        &context.data().directory.event_actor,
+        EventRequest,
+        Complete,
+        event,
+        intermission
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Undo the completion of an event, restoring its stream.
+///
+/// ```
+/// /reopen Finals
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn reopen(
+    context: Context<'_>,
+    #[description = "Event to reopen"]
+    #[autocomplete = "autocomplete_event_name"]
+    event: String,
+) -> Result<(), anyhow::Error> {
+    let event = context.data().db.get_id_for_event(&event).await?;
+    send_message!(
+        &context.data().directory.event_actor,
+        EventRequest,
+        Reopen,
+        event
+    )?;
+    send_success_reply(&context).await
+}
+
 /// Remove an event
 #[poise::command(prefix_command, slash_command)]
 async fn delete_event(
@@ -694,96 +2065,342 @@ async fn delete_event(
     #[autocomplete = "autocomplete_event_name"]
     event_name: String,
 ) -> Result<(), anyhow::Error> {
-    log::debug!("Deleting event {}", event_name);
-    let event = context.data().db.get_id_for_event(&event_name).await?;
+    log::debug!("Deleting event {}", event_name);
+    let event = context.data().db.get_id_for_event(&event_name).await?;
+
+    send_message!(
+        context.data().directory.event_actor,
+        EventRequest,
+        Delete,
+        event
+    )?;
+
+    send_success_reply(&context).await
+}
+
+/// Create a new runner.
+#[poise::command(prefix_command, slash_command)]
+async fn create_runner(
+    context: Context<'_>,
+    #[description = "Name for this runner"] name: String,
+    #[description = "TheRun.gg username"] therun: Option<String>,
+    #[description = "Twitch username/stream link"] stream: Option<String>,
+    #[description = "Nicknames for this runner"] nicknames: Option<String>,
+) -> Result<(), anyhow::Error> {
+    log::debug!("Creating runner {}", name);
+    let nicknames: Vec<_> = nicknames
+        .map(|r| r.split(',').map(|s| s.trim().to_owned()).collect())
+        .unwrap_or_default();
+
+    let runner = Runner {
+        id: -1,
+        name,
+        stream,
+        therun,
+        cached_stream_url: None,
+        stream_url_fetched_at: None,
+        stream_url_expires_at: None,
+        volume_percent: 50,
+        max_stream_height: None,
+        archived: false,
+        monitor_type: None,
+        discord_id: None,
+        discord_reminder_opt_out: false,
+        stream_kind: StreamKind::Twitch,
+        location: None,
+        photo: None,
+        nicks: nicknames,
+        socials: BTreeMap::new(),
+    };
+
+    send_message!(
+        &context.data().directory.runner_actor,
+        RunnerRequest,
+        Create,
+        runner.clone()
+    )?;
+
+    send_success_reply(&context).await
+}
+
+/// Delete a runner.
+///
+/// Runners that are part of an event or stream are only deleted
+/// if `force` is set, which also removes their event history.
+#[poise::command(prefix_command, slash_command)]
+async fn delete_runner(
+    context: Context<'_>,
+    #[description = "Name for this runner"]
+    #[autocomplete = "autocomplete_runner_name"]
+    name: String,
+    #[description = "Delete even if the runner is part of an event"] force: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    let runner = context.data().db.find_runner(&name).await?;
+    let force = force.unwrap_or(false);
+    send_message!(
+        &context.data().directory.runner_actor,
+        RunnerRequest,
+        Delete,
+        runner.id,
+        force
+    )?;
+
+    send_success_reply(&context).await
+}
+
+/// Archive a runner, hiding them from listings while keeping their history.
+///
+/// ```
+/// /archive_runner javster101
+/// /archive_runner javster101 false
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn archive_runner(
+    context: Context<'_>,
+    #[description = "Name for this runner"] name: String,
+    #[description = "Whether the runner should be archived"] archived: Option<bool>,
+) -> Result<(), anyhow::Error> {
+    let mut runner = context.data().db.find_runner(&name).await?;
+    runner.archived = archived.unwrap_or(true);
+    send_message!(
+        &context.data().directory.runner_actor,
+        RunnerRequest,
+        Update,
+        runner.clone()
+    )?;
+
+    send_success_reply(&context).await
+}
+
+/// Set the audible runner.
+#[poise::command(prefix_command, slash_command)]
+async fn set_audible_runner(
+    context: Context<'_>,
+    #[description = "Name for this runner"]
+    #[autocomplete = "autocomplete_runner_name"]
+    name: String,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event.clone()).await?;
+    let runner = context.data().db.find_runner(&name).await?;
+
+    update_stream(
+        &*context.data().db,
+        &context.data().directory,
+        stream_id,
+        false,
+        |stream| stream.audible_runner = Some(runner.id),
+    )
+    .await?;
+    send_success_reply(&context).await
+}
+
+/// Delay a runner's feed to line it up with the other runners.
+///
+/// ```
+/// /sync javster101 2500
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn sync(
+    context: Context<'_>,
+    #[description = "Runner to delay"]
+    #[autocomplete = "autocomplete_runner_name"]
+    runner: String,
+    #[description = "Delay in milliseconds, up to 15000"] ms: u32,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event).await?;
+    let runner = context.data().db.find_runner(&runner).await?;
+
+    send_message!(
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        SetSyncOffset,
+        stream_id,
+        runner.id,
+        ms
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Stage runners before they join a stream, so their feed is buffered when they are shown.
+#[poise::command(
+    prefix_command,
+    slash_command,
+    subcommands("ondeck_add", "ondeck_remove", "ondeck_list")
+)]
+async fn ondeck(_context: Context<'_>) -> Result<(), anyhow::Error> {
+    Ok(())
+}
+
+/// Load a runner's feed hidden, ready to be toggled into a slot.
+///
+/// ```
+/// /ondeck add javster101
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "add")]
+async fn ondeck_add(
+    context: Context<'_>,
+    #[description = "Runner to put on deck"]
+    #[autocomplete = "autocomplete_runner_name"]
+    runner: String,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event).await?;
+    let runner = context.data().db.find_runner(&runner).await?;
+
+    send_message!(
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        AddOnDeck,
+        stream_id,
+        runner.id
+    )?;
+    send_success_reply(&context).await
+}
+
+/// Take a runner off deck without showing them.
+///
+/// ```
+/// /ondeck remove javster101
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "remove")]
+async fn ondeck_remove(
+    context: Context<'_>,
+    #[description = "Runner to take off deck"]
+    #[autocomplete = "autocomplete_runner_name"]
+    runner: String,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let stream_id = get_stream_id(context, event).await?;
+    let runner = context.data().db.find_runner(&runner).await?;
 
     send_message!(
-        context.data().directory.event_actor,
-        EventRequest,
-        Delete,
-        event
+        &context.data().directory.stream_actor,
+        StreamRequest,
+        RemoveOnDeck,
+        stream_id,
+        runner.id
     )?;
-
     send_success_reply(&context).await
 }
 
-/// Create a new runner.
-#[poise::command(prefix_command, slash_command)]
-async fn create_runner(
+/// List the runners on deck for a stream.
+///
+/// ```
+/// /ondeck list
+/// ```
+#[poise::command(prefix_command, slash_command, rename = "list")]
+async fn ondeck_list(
     context: Context<'_>,
-    #[description = "Name for this runner"] name: String,
-    #[description = "TheRun.gg username"] therun: Option<String>,
-    #[description = "Twitch username/stream link"] stream: Option<String>,
-    #[description = "Nicknames for this runner"] nicknames: Option<String>,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    log::debug!("Creating runner {}", name);
-    let nicknames: Vec<_> = nicknames
-        .map(|r| r.split(',').map(|s| s.trim().to_owned()).collect())
-        .unwrap_or_default();
+    let stream_id = get_stream_id(context, event).await?;
+    let db = &context.data().db;
+    let stream = db.get_stream(stream_id).await?;
 
-    let runner = Runner {
-        id: -1,
-        name,
-        stream,
-        therun,
-        cached_stream_url: None,
-        volume_percent: 50,
-        location: None,
-        photo: None,
-        nicks: nicknames,
+    let mut lines = vec![];
+    for entry in stream.on_deck_runners.iter() {
+        let name = db.get_name_for_runner(entry.runner).await?;
+        lines.push(format!("{} (since <t:{}:R>)", name, entry.added_at / 1000));
+    }
+    let reply = if lines.is_empty() {
+        "No runners are on deck.".to_owned()
+    } else {
+        lines.join("\n")
     };
+    context.say(reply).await?;
+    Ok(())
+}
 
-    send_message!(
-        &context.data().directory.runner_actor,
-        RunnerRequest,
-        Create,
-        runner.clone()
-    )?;
-
-    send_success_reply(&context).await
+#[derive(Debug, poise::ChoiceParameter)]
+enum Severity {
+    #[name = "info"]
+    Info,
+    #[name = "warning"]
+    Warning,
+    #[name = "critical"]
+    Critical,
 }
 
-/// Delete a runner.
+/// Note an incident on the event timeline, stamped with the current timer time.
+///
+/// ```
+/// /incident "Stream dropped for runner 2"
+/// /incident "Timer paused for a crash" critical
+/// ```
 #[poise::command(prefix_command, slash_command)]
-async fn delete_runner(
+async fn incident(
     context: Context<'_>,
-    #[description = "Name for this runner"]
-    #[autocomplete = "autocomplete_runner_name"]
-    name: String,
+    #[description = "What happened"] text: String,
+    #[description = "How serious the incident is, info by default"] severity: Option<Severity>,
+    #[description = "Event for this command"]
+    #[autocomplete = "autocomplete_streamed_event_name"]
+    event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let runner = context.data().db.find_runner(&name).await?;
+    let event = get_stream_id(context, event).await?;
+    let incident = Incident {
+        id: 0,
+        event,
+        time: (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64,
+        timer_elapsed: None,
+        author: Some(context.author().name.clone()),
+        severity: match severity {
+            None | Some(Severity::Info) => IncidentSeverity::Info,
+            Some(Severity::Warning) => IncidentSeverity::Warning,
+            Some(Severity::Critical) => IncidentSeverity::Critical,
+        },
+        text,
+    };
+
     send_message!(
-        &context.data().directory.runner_actor,
-        RunnerRequest,
-        Delete,
-        runner.id
+        &context.data().directory.event_actor,
+        EventRequest,
+        AddIncident,
+        incident
     )?;
-
     send_success_reply(&context).await
 }
 
-/// Set the audible runner.
+/// Mark a notable moment of an event for the VOD, stamped with the current timer time.
+///
+/// The host's Twitch stream is marked as well if it is live and Twitch API access is set up.
+/// ```
+/// /mark "Any% start"
+/// ```
 #[poise::command(prefix_command, slash_command)]
-async fn set_audible_runner(
+async fn mark(
     context: Context<'_>,
-    #[description = "Name for this runner"]
-    #[autocomplete = "autocomplete_runner_name"]
-    name: String,
+    #[description = "What happens at this moment"] label: String,
     #[description = "Event for this command"]
     #[autocomplete = "autocomplete_streamed_event_name"]
     event: Option<String>,
 ) -> Result<(), anyhow::Error> {
-    let stream_id = get_stream_id(event.clone(), &context.data().db).await?;
-    let mut stream = context.data().db.get_stream(stream_id).await?;
-
-    let runner = context.data().db.find_runner(&name).await?;
-    stream.audible_runner = Some(runner.id);
+    let event = get_stream_id(context, event).await?;
+    let marker = Marker {
+        id: 0,
+        event,
+        label,
+        time: 0,
+        timer_elapsed: None,
+        author: Some(context.author().name.clone()),
+    };
 
     send_message!(
-        &context.data().directory.stream_actor,
-        StreamRequest,
-        Update,
-        stream
+        &context.data().directory.event_actor,
+        EventRequest,
+        AddMarker,
+        marker
     )?;
     send_success_reply(&context).await
 }
@@ -799,8 +2416,11 @@ async fn set_runner_volume(
 ) -> Result<(), anyhow::Error> {
     let db = &context.data().db;
 
-    if volume > 100 {
-        return Err(anyhow!("Volume must be between 0 and 100"));
+    if volume > MAX_VOLUME_PERCENT {
+        return Err(anyhow!(
+            "Volume must be between 0 and {}",
+            MAX_VOLUME_PERCENT
+        ));
     }
 
     let mut runner = db.find_runner(&name).await?;
@@ -814,63 +2434,333 @@ async fn set_runner_volume(
     send_success_reply(&context).await
 }
 
-pub async fn init_discord(
-    settings: Arc<Settings>,
-    db: Arc<ProjectDb>,
-    directory: Directory,
+/// Link a runner to a Discord user and refresh the commentators of the user's voice channel
+async fn link_runner(
+    context: &Context<'_>,
+    runner: &Runner,
+    user: &serenity::User,
+) -> anyhow::Result<()> {
+    let data = context.data();
+    data.db
+        .link_runner_discord_id(runner.id, &user.id.to_string())
+        .await?;
+
+    refresh_user_voice_channel(context, user).await;
+    Ok(())
+}
+
+/// Set the commentators again from the voice channel a user is in, if any
+async fn refresh_user_voice_channel(context: &Context<'_>, user: &serenity::User) {
+    let data = context.data();
+    let voice_state = context
+        .guild()
+        .and_then(|guild| guild.voice_states.get(&user.id).cloned());
+    if let Some(voice_state) = voice_state {
+        update_voice_list(
+            &*data.db,
+            context.serenity_context(),
+            &data.directory,
+            &voice_state,
+            &data.settings,
+        )
+        .await;
+    }
+}
+
+/// Link yourself to a runner, so you are recognized as a commentator.
+///
+/// If the runner is already linked to someone else, `confirm` must be set.
+///
+/// ```
+/// /link javster101
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn link(
+    context: Context<'_>,
+    #[description = "Runner to link to"]
+    #[autocomplete = "autocomplete_runner_name"]
+    runner: String,
+    #[description = "Replace the runner's existing link"] confirm: Option<bool>,
 ) -> Result<(), anyhow::Error> {
-    log::info!("Initializing Discord bot");
+    let runner = context.data().db.find_runner(&runner).await?;
+    let user = context.author();
+
+    if let Some(existing) = runner
+        .discord_id
+        .as_ref()
+        .filter(|id| **id != user.id.to_string())
+    {
+        if !confirm.unwrap_or(false) {
+            context
+                .say(format!(
+                    "{} is already linked to <@{}>, run again with `confirm` to replace the link.",
+                    runner.name, existing
+                ))
+                .await?;
+            return Ok(());
+        }
+    }
 
-    if let Some(channel) = &settings.discord_command_channel {
-        log::info!("Receiving Discord commands on '{}'", channel);
-    } else {
-        log::warn!("No channel specified for 'discord_command_channel' in the settings file, this bot will accept commands on any channel!");
+    link_runner(&context, &runner, user).await?;
+    send_success_reply(&context).await
+}
+
+/// Turn the reminders sent to you before your events on or off.
+///
+/// Reminders are sent by direct message to the runner you are linked to with `/link`.
+/// ```
+/// /reminders off
+/// ```
+#[poise::command(prefix_command, slash_command)]
+async fn reminders(
+    context: Context<'_>,
+    #[description = "Whether to send reminders"] state: Switch,
+) -> Result<(), anyhow::Error> {
+    let db = &context.data().db;
+    let Some(runner) = db
+        .find_runner_by_discord_id(&context.author().id.to_string())
+        .await?
+    else {
+        context
+            .say("You are not linked to a runner, use `/link` first.")
+            .await?;
+        return Ok(());
+    };
+
+    db.set_runner_reminder_opt_out(runner.id, matches!(state, Switch::Off))
+        .await?;
+    send_success_reply(&context).await
+}
+
+/// Link another Discord user to a runner.
+///
+/// ```
+/// /link_other javster101 @javster
+/// ```
+#[poise::command(
+    prefix_command,
+    slash_command,
+    required_permissions = "MANAGE_GUILD",
+    default_member_permissions = "MANAGE_GUILD"
+)]
+async fn link_other(
+    context: Context<'_>,
+    #[description = "Runner to link to"]
+    #[autocomplete = "autocomplete_runner_name"]
+    runner: String,
+    #[description = "Discord user to link"] user: serenity::User,
+) -> Result<(), anyhow::Error> {
+    let runner = context.data().db.find_runner(&runner).await?;
+    link_runner(&context, &runner, &user).await?;
+    send_success_reply(&context).await
+}
+
+/// Find the command channel in the guilds the bot is a member of
+async fn find_command_channel(http: &Http, guilds: &[GuildId], name: &str) -> Option<ChannelId> {
+    for guild in guilds {
+        match guild.channels(http).await {
+            Ok(channels) => {
+                if let Some(channel) = channels.values().find(|c| c.name == name) {
+                    return Some(channel.id);
+                }
+            }
+            Err(e) => log::warn!("Failed to get channels for guild {}: {}", guild, e),
+        }
     }
 
-    let http = Http::new(&settings.discord_token.clone().unwrap());
-    let _owners = match http.get_current_application_info().await {
-        Ok(info) => {
-            let mut owners = HashSet::new();
-            owners.insert(info.owner.id);
-            owners
+    None
+}
+
+/// Delay before restarting a Discord client that stopped, doubled while it keeps stopping
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(5);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(300);
+/// A client that ran at least this long before stopping is restarted after the minimum delay
+const RECONNECT_STABLE_AFTER: Duration = Duration::from_secs(600);
+
+/// Post notifications to the command channel, or log them if there is none.
+///
+/// The channel is looked up again on every login, so this outlives the client.
+async fn run_discord_actor(
+    http: Arc<Http>,
+    channel: watch::Receiver<Option<ChannelId>>,
+    mut rx: ActorReceiver<DiscordCommand>,
+) {
+    while let Some((msg, span)) = rx.recv().await {
+        match msg {
+            DiscordCommand::Notify(text) => {
+                span.in_scope(|| log::info!("{}", text));
+                let channel = *channel.borrow();
+                if let Some(channel) = channel {
+                    if let Err(e) = channel.say(&http, &text).await {
+                        log::warn!("Failed to send Discord notification: {}", e);
+                    }
+                }
+            }
         }
-        Err(why) => {
-            return Err(Error::Unknown(format!(
-                "Could not access app info: {:?}",
-                why
-            )))?
+    }
+}
+
+/// Tell the dashboard and the status webhook that the Discord connection was lost or restored
+fn report_discord_status(directory: &Directory, settings: &Settings, connected: bool) {
+    directory.web_actor.send(WebCommand::SendStateUpdate);
+    if let Some(url) = &settings.discord_status_webhook_url {
+        let error = directory.health.get_discord().and_then(|d| d.last_error);
+        tokio::spawn(send_status_webhook(url.clone(), connected, error));
+    }
+}
+
+/// Notify a webhook of a lost or restored Discord connection
+async fn send_status_webhook(url: String, connected: bool, error: Option<String>) {
+    let body = serde_json::json!({
+        "event": if connected { "discord_reconnected" } else { "discord_disconnected" },
+        "error": error,
+    });
+
+    if let Err(e) = reqwest::Client::new().post(&url).json(&body).send().await {
+        log::warn!("Failed to send Discord status webhook to {}: {}", url, e);
+    }
+}
+
+/// Mark the gateway as connected, reporting it if the connection had been lost
+fn set_discord_connected(data: &Data) {
+    let health = &data.directory.health;
+    if !health.set_discord_connected(true)
+        && health.get_discord().is_some_and(|d| d.disconnects > 0)
+    {
+        log::info!("Discord connection restored");
+        report_discord_status(&data.directory, &data.settings, true);
+    }
+}
+
+/// Record a lost gateway connection and report it
+fn record_discord_disconnect(directory: &Directory, settings: &Settings, error: String) {
+    log::error!("Lost connection to Discord: {}", error);
+    directory.health.record_discord_disconnect(Some(error));
+    report_discord_status(directory, settings, false);
+}
+
+/// Commands and event handling of the bot, built again for every client
+type CommandError<'a> = poise::FrameworkError<'a, Data, anyhow::Error>;
+type PrefixAction = for<'a> fn(
+    poise::PrefixContext<'a, Data, anyhow::Error>,
+) -> BoxFuture<'a, Result<(), CommandError<'a>>>;
+type SlashAction = for<'a> fn(
+    poise::ApplicationContext<'a, Data, anyhow::Error>,
+) -> BoxFuture<'a, Result<(), CommandError<'a>>>;
+
+/// The actions of every command by identifying name, which the traced actions run
+type CommandActions = HashMap<String, (Option<PrefixAction>, Option<SlashAction>)>;
+static COMMAND_ACTIONS: OnceLock<CommandActions> = OnceLock::new();
+
+/// Replace the actions of the commands and their subcommands with ones that run them in a span
+fn trace_commands(
+    commands: &mut [poise::Command<Data, anyhow::Error>],
+    actions: &mut CommandActions,
+) {
+    for command in commands {
+        actions.insert(
+            command.identifying_name.clone(),
+            (command.prefix_action, command.slash_action),
+        );
+        if command.prefix_action.is_some() {
+            command.prefix_action = Some(traced_prefix_action);
         }
-    };
+        if command.slash_action.is_some() {
+            command.slash_action = Some(traced_slash_action);
+        }
+        trace_commands(&mut command.subcommands, actions);
+    }
+}
 
-    let intents = serenity::GatewayIntents::non_privileged()
-        | serenity::GatewayIntents::GUILD_MESSAGES
-        | serenity::GatewayIntents::MESSAGE_CONTENT
-        | serenity::GatewayIntents::GUILD_VOICE_STATES;
+/// Create the span a Discord command runs in
+fn command_span(command: &poise::Command<Data, anyhow::Error>, user: &serenity::User) -> Span {
+    tracing::info_span!(
+        "command",
+        source = "discord",
+        command = %command.qualified_name,
+        user = %user.name
+    )
+}
+
+fn traced_prefix_action(
+    ctx: poise::PrefixContext<'_, Data, anyhow::Error>,
+) -> BoxFuture<'_, Result<(), CommandError<'_>>> {
+    let action = COMMAND_ACTIONS
+        .get()
+        .and_then(|a| a.get(&ctx.command.identifying_name))
+        .and_then(|(action, _)| *action)
+        .expect("Traced command has no prefix action");
+    let span = command_span(ctx.command, &ctx.msg.author);
+    Box::pin(action(ctx).instrument(span))
+}
+
+fn traced_slash_action(
+    ctx: poise::ApplicationContext<'_, Data, anyhow::Error>,
+) -> BoxFuture<'_, Result<(), CommandError<'_>>> {
+    let action = COMMAND_ACTIONS
+        .get()
+        .and_then(|a| a.get(&ctx.command.identifying_name))
+        .and_then(|(_, action)| *action)
+        .expect("Traced command has no slash action");
+    let span = command_span(ctx.command, ctx.interaction.user());
+    Box::pin(action(ctx).instrument(span))
+}
 
-    let commands = vec![
+fn framework_options() -> poise::FrameworkOptions<Data, anyhow::Error> {
+    let mut commands = vec![
         toggle(),
         set(),
         swap(),
+        handoff(),
         layout(),
+        scene(),
+        resume_layout(),
+        league(),
+        preset(),
+        activate(),
+        autofill(),
+        resync(),
+        rotate(),
         refresh(),
         ignore(),
+        commentators(),
+        host(),
         start_stream(),
         stop_stream(),
+        clip(),
+        preflight(),
         create_stream(),
+        results(),
+        status(),
+        runner_info(),
         delete_stream(),
         set_start_time(),
         set_end_time(),
         start_timer(),
         stop_timer(),
+        complete(),
+        reopen(),
         create_event(),
         delete_event(),
         create_runner(),
         delete_runner(),
+        archive_runner(),
         set_audible_runner(),
         set_runner_volume(),
+        sync(),
+        ondeck(),
+        incident(),
+        mark(),
+        link(),
+        link_other(),
+        reminders(),
     ];
+    let mut actions = HashMap::new();
+    trace_commands(&mut commands, &mut actions);
+    // Every framework has the same commands, so the actions of the first are kept
+    let _ = COMMAND_ACTIONS.set(actions);
 
-    let options = poise::FrameworkOptions::<Data, anyhow::Error> {
+    poise::FrameworkOptions::<Data, anyhow::Error> {
         commands,
         command_check: Some(|ctx| {
             Box::pin(async move {
@@ -888,34 +2778,194 @@ pub async fn init_discord(
         },
         event_handler: |ctx, event, _framework, data| {
             Box::pin(async move {
-                if let poise::event::Event::VoiceStateUpdate { old, new } = event {
-                    handle_voice_state_event(ctx, old, new, data).await;
+                match event {
+                    poise::event::Event::VoiceStateUpdate { old, new } => {
+                        handle_voice_state_event(ctx, old, new, data).await;
+                    }
+                    poise::event::Event::GuildCreate { guild, .. } => {
+                        sync_guild_voice_channels(
+                            &*data.db,
+                            ctx,
+                            &data.directory,
+                            &data.settings,
+                            guild,
+                        )
+                        .await;
+                    }
+                    poise::event::Event::Ready { .. } => set_discord_connected(data),
+                    poise::event::Event::Resume { .. } => {
+                        set_discord_connected(data);
+                        // Voice updates sent while disconnected are not replayed on resume
+                        for guild in ctx.cache.guilds() {
+                            if let Some(guild) = ctx.cache.guild(guild) {
+                                sync_guild_voice_channels(
+                                    &*data.db,
+                                    ctx,
+                                    &data.directory,
+                                    &data.settings,
+                                    &guild,
+                                )
+                                .await;
+                            }
+                        }
+                    }
+                    poise::event::Event::ShardStageUpdate { update } => {
+                        use serenity::gateway::ConnectionStage;
+                        if update.new == ConnectionStage::Connected {
+                            set_discord_connected(data);
+                        } else if update.old == ConnectionStage::Connected {
+                            record_discord_disconnect(
+                                &data.directory,
+                                &data.settings,
+                                format!("Gateway connection is {}", update.new),
+                            );
+                        }
+                    }
+                    _ => {}
                 }
 
                 Ok(())
             })
         },
         ..Default::default()
+    }
+}
+
+pub async fn init_discord(
+    settings: Arc<Settings>,
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
+    rx: ActorReceiver<DiscordCommand>,
+) -> Result<(), anyhow::Error> {
+    log::info!("Initializing Discord bot");
+
+    if let Some(channel) = &settings.discord_command_channel {
+        log::info!("Receiving Discord commands on '{}'", channel);
+    } else {
+        log::warn!("No channel specified for 'discord_command_channel' in the settings file, this bot will accept commands on any channel!");
+    }
+
+    let http = Http::new(&settings.discord_token.clone().unwrap());
+    let _owners = match http.get_current_application_info().await {
+        Ok(info) => {
+            let mut owners = HashSet::new();
+            owners.insert(info.owner.id);
+            owners
+        }
+        Err(why) => {
+            return Err(Error::Unknown(format!(
+                "Could not access app info: {:?}",
+                why
+            )))?
+        }
     };
 
-    poise::Framework::<Data, anyhow::Error>::builder()
-        .token(settings.discord_token.clone().unwrap().trim())
-        .intents(intents)
-        .options(options)
-        .setup(move |ctx, _ready, framework| {
-            Box::pin(async move {
-                log::info!("Logged in as {}", _ready.user.name);
-                poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(Data {
-                    db,
-                    settings,
-                    directory,
+    let http = Arc::new(http);
+    directory.health.set_discord_connected(false);
+
+    // Notifications and reminders keep running while the client reconnects
+    let (channel_tx, channel_rx) = watch::channel(None);
+    let channel_tx = Arc::new(channel_tx);
+    tokio::spawn(run_discord_actor(http.clone(), channel_rx, rx));
+    tokio::spawn(run_runner_reminders(
+        db.clone(),
+        settings.clone(),
+        directory.clone(),
+        http.clone(),
+    ));
+
+    let intents = serenity::GatewayIntents::non_privileged()
+        | serenity::GatewayIntents::GUILD_MESSAGES
+        | serenity::GatewayIntents::MESSAGE_CONTENT
+        | serenity::GatewayIntents::GUILD_VOICE_STATES;
+
+    let mut delay = RECONNECT_MIN_DELAY;
+    loop {
+        let started = Instant::now();
+        let (db, setup_settings, setup_directory, channel_tx) = (
+            db.clone(),
+            settings.clone(),
+            directory.clone(),
+            channel_tx.clone(),
+        );
+        let result = poise::Framework::<Data, anyhow::Error>::builder()
+            .token(settings.discord_token.clone().unwrap().trim())
+            .intents(intents)
+            .options(framework_options())
+            .setup(move |ctx, ready, framework| {
+                Box::pin(async move {
+                    let settings = setup_settings;
+                    let directory = setup_directory;
+                    log::info!("Logged in as {}", ready.user.name);
+                    poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+
+                    let channel = match &settings.discord_command_channel {
+                        Some(name) => {
+                            let guilds: Vec<GuildId> = ready.guilds.iter().map(|g| g.id).collect();
+                            find_command_channel(&ctx.http, &guilds, name).await
+                        }
+                        None => None,
+                    };
+                    channel_tx.send_replace(channel);
+
+                    // Guilds that are not cached yet are synced when they arrive in GuildCreate
+                    for guild in &ready.guilds {
+                        if let Some(guild) = ctx.cache.guild(guild.id) {
+                            sync_guild_voice_channels(&*db, ctx, &directory, &settings, &guild)
+                                .await;
+                        }
+                    }
+
+                    Ok(Data {
+                        db,
+                        settings,
+                        directory,
+                    })
                 })
             })
-        })
-        .run()
-        .await
-        .expect("Err running client");
+            .run()
+            .await;
 
-    Ok(())
+        let error = match result {
+            Ok(()) => "the client stopped".to_owned(),
+            Err(e) => e.to_string(),
+        };
+        record_discord_disconnect(&directory, &settings, error);
+
+        if started.elapsed() >= RECONNECT_STABLE_AFTER {
+            delay = RECONNECT_MIN_DELAY;
+        }
+        log::info!("Restarting the Discord client in {}s", delay.as_secs());
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Identifying names of commands and their subcommands that have an action
+    fn with_actions(commands: &[poise::Command<Data, anyhow::Error>], names: &mut Vec<String>) {
+        for command in commands {
+            if command.prefix_action.is_some() || command.slash_action.is_some() {
+                names.push(command.identifying_name.clone());
+            }
+            with_actions(&command.subcommands, names);
+        }
+    }
+
+    #[test]
+    fn every_command_action_is_kept_for_tracing() {
+        let options = framework_options();
+        let mut names = vec![];
+        with_actions(&options.commands, &mut names);
+        assert!(names.iter().any(|n| n == "ondeck_add"), "{:?}", names);
+
+        let actions = COMMAND_ACTIONS.get().unwrap();
+        for name in names {
+            let (prefix, slash) = actions.get(&name).unwrap();
+            assert!(prefix.is_some() || slash.is_some(), "{}", name);
+        }
+    }
 }
@@ -0,0 +1,178 @@
+use std::{sync::Arc, time::Duration};
+
+use poise::serenity_prelude as serenity;
+use serenity::{http::Http, model::prelude::UserId};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::{
+    core::{db::ProjectStore, event::Event, runner::Runner, settings::Settings},
+    integrations::{discord::DiscordCommand, therun::format_run_time},
+    Directory,
+};
+
+/// Interval between checks for reminders that are due
+const REMINDER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default `discord_reminder_minutes`
+pub const DEFAULT_REMINDER_MINUTES: &[u64] = &[60, 15];
+
+/// Send runners a Discord direct message before their events start.
+///
+/// Reminders are computed from the schedule on every check and recorded per start time,
+/// so rescheduled events are reminded again and restarts do not send duplicates.
+pub async fn run_runner_reminders(
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    directory: Directory,
+    http: Arc<Http>,
+) {
+    let mut offsets = settings
+        .discord_reminder_minutes
+        .clone()
+        .unwrap_or_else(|| DEFAULT_REMINDER_MINUTES.to_vec());
+    if offsets.is_empty() {
+        log::info!("Runner reminders are disabled");
+        return;
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    let mut interval = tokio::time::interval(REMINDER_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = send_due_reminders(&*db, &directory, &http, &offsets).await {
+            log::warn!("Failed to send runner reminders: {}", e);
+        }
+    }
+}
+
+async fn send_due_reminders(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    http: &Http,
+    offsets: &[u64],
+) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc();
+
+    for id in db.get_event_ids().await? {
+        let event = db.get_event(id).await?;
+        let Some(start) = event.event_start_time else {
+            continue;
+        };
+        if event.timer_start_time.is_some() || start <= now {
+            continue;
+        }
+
+        // Only the closest reminder is sent, so a late check does not send several at once
+        let Some(minutes) = offsets
+            .iter()
+            .copied()
+            .find(|m| start - Duration::from_secs(m * 60) <= now)
+        else {
+            continue;
+        };
+
+        for runner in db.get_event_runner_order(id).await? {
+            let runner = db.get_runner(runner).await?;
+            let Some(discord_id) = runner
+                .discord_id
+                .as_ref()
+                .filter(|_| !runner.discord_reminder_opt_out)
+            else {
+                continue;
+            };
+            if db
+                .is_discord_reminder_sent(id, runner.id, minutes, start)
+                .await?
+            {
+                continue;
+            }
+
+            let text = format_reminder(directory, &event, &runner, start);
+            let delivered = match send_direct_message(http, discord_id, &text).await {
+                Ok(()) => {
+                    log::info!("Reminded {} of {}", runner.name, event.name);
+                    true
+                }
+                Err(e) => {
+                    log::warn!("Failed to remind {} of {}: {}", runner.name, event.name, e);
+                    // Reported once, as a runner with closed DMs would fail every time
+                    if !db.has_undelivered_discord_reminder(runner.id).await? {
+                        directory.discord_actor.send(DiscordCommand::Notify(format!(
+                            "\u{26a0} Could not send {} a reminder for {}, they may have direct messages disabled",
+                            runner.name, event.name
+                        )));
+                    }
+                    false
+                }
+            };
+            db.add_discord_reminder(id, runner.id, minutes, start, delivered)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_direct_message(http: &Http, discord_id: &str, text: &str) -> anyhow::Result<()> {
+    let user = UserId(discord_id.parse()?);
+    let channel = user.create_dm_channel(http).await?;
+    channel.say(http, text).await?;
+    Ok(())
+}
+
+/// The reminder sent to a runner, with a checklist of what they need to have running
+fn format_reminder(
+    directory: &Directory,
+    event: &Event,
+    runner: &Runner,
+    start: OffsetDateTime,
+) -> String {
+    let start = start.unix_timestamp();
+    let mut text = format!(
+        "\u{23f0} **{}** starts <t:{}:R> at <t:{}:t>.",
+        event.name, start, start
+    );
+    match (&event.game, &event.category) {
+        (Some(game), Some(category)) => text.push_str(&format!("\n{} \u{2014} {}", game, category)),
+        (Some(game), None) => text.push_str(&format!("\n{}", game)),
+        _ => {}
+    }
+    if let Some(estimate) = event.estimate.filter(|e| *e > 0) {
+        text.push_str(&format!(
+            "\nEstimate: {}",
+            format_run_time(estimate as f64 * 1000.0)
+        ));
+    }
+
+    text.push_str("\n\nBefore your run:");
+    // A resolved stream URL that has not expired means the stream was live recently
+    if runner.cached_stream_url.is_some() && !runner.stream_url_expires_within(Duration::ZERO) {
+        text.push_str(&format!(
+            "\n\u{2705} Your stream at {} was found live",
+            runner.get_stream()
+        ));
+    } else {
+        text.push_str(&format!(
+            "\n\u{2b1c} Make sure your stream is live at {}",
+            runner.get_stream()
+        ));
+    }
+    match directory.health.get_therun_connected(runner.id) {
+        Some(true) => text.push_str(&format!(
+            "\n\u{2705} TheRun.gg is connected as {}",
+            runner.get_therun_username()
+        )),
+        Some(false) => text.push_str(&format!(
+            "\n\u{26a0} TheRun.gg is not connected, check LiveSplit's TheRun.gg component for {}",
+            runner.get_therun_username()
+        )),
+        None => text.push_str(&format!(
+            "\n\u{2b1c} Make sure LiveSplit is connected to TheRun.gg as {}",
+            runner.get_therun_username()
+        )),
+    }
+
+    text.push_str("\n\nUse `/reminders off` to stop these reminders.");
+    text
+}
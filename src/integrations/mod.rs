@@ -1,4 +1,15 @@
 pub mod discord;
+pub mod discord_reminders;
 pub mod obs;
+pub mod obs_plan;
 pub mod therun;
+pub mod tiltify;
+pub mod twitch_api;
+pub mod twitch_chat;
 pub mod web;
+pub mod web_cache;
+pub mod web_compression;
+pub mod web_ical;
+pub mod web_rate_limit;
+pub mod web_timing;
+pub mod web_trigger;
@@ -1,42 +1,80 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
     sync::{Arc, OnceLock},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::anyhow;
+use futures::future::join_all;
 use obws::{
+    common::MonitorType,
     requests::{
+        filters,
         inputs::{self, InputId, SetSettings, Volume},
         scene_items::{
             Bounds, CreateSceneItem, Position, SceneItemTransform, SetIndex, SetTransform,
         },
         scenes::SceneId,
+        sources::SourceId,
+        ui::{Location, OpenSourceProjector, OpenVideoMixProjector, VideoMixType},
         EventSubscription,
     },
-    responses::scene_items::SceneItem,
+    responses::{media_inputs::MediaState, scene_items::SceneItem, scenes::Scenes},
 };
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::UnboundedReceiver;
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::Instrument;
 
 use crate::{
     core::{
-        db::ProjectDb,
-        event::Event,
-        settings::Settings,
-        stream::{ModifiedStreamState, StreamState},
+        db::ProjectStore,
+        event::{serialize_datetime, Event},
+        i18n,
+        runner::{Runner, RunnerRequest, STREAM_URL_REFRESH_TIMEOUT},
+        settings::{
+            AudioMonitorType, PublicStreamUrl, Settings, SourceFilter, SourceNaming,
+            DEFAULT_LOWER_THIRD_FORMAT, DEFAULT_LOWER_THIRD_PLATFORMS,
+        },
+        stream::{ModifiedStreamState, StreamRequest, StreamState},
+        trace::Traced,
     },
     error::Error,
-    ActorRef, Rto,
+    integrations::{
+        discord::DiscordCommand,
+        obs_plan::{
+            plan_obs_update, runner_input_name, InputSettings, LayoutItem, ObsAction,
+            ObsUpdateState, RUNNER_INPUT_PREFIX,
+        },
+        therun::{format_delta, format_stat_time},
+        tiltify::TiltifyCommand,
+        web::WebCommand,
+    },
+    record_event, record_host, send_message, send_message_with_timeout, ActorMessage,
+    ActorReceiver, ActorRef, Directory, Rto,
 };
 
+/// OBS browser source partial settings parameters
+#[derive(Serialize)]
+struct BrowserSourceUrl<'a> {
+    url: &'a str,
+}
+
 // OBS FreeType partial settings parameters
 #[derive(Serialize)]
 struct SpecificFreetype<'a> {
     text: &'a str,
 }
 
+/// OBS FreeType text as read back from an input
+#[derive(Deserialize)]
+struct FreetypeText {
+    #[serde(default)]
+    text: String,
+}
+
 /// OBS VLC partial source parameters
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize)]
@@ -44,6 +82,33 @@ struct VLC {
     playlist: Vec<PlaylistItem>,
 }
 
+impl VLC {
+    /// Settings playing a single URL
+    fn from_url(url: &str) -> Self {
+        VLC {
+            playlist: vec![PlaylistItem {
+                hidden: false,
+                selected: false,
+                value: url.to_owned(),
+            }],
+        }
+    }
+}
+
+/// OBS VLC network caching parameter, used to delay a runner's feed
+#[derive(Serialize, Deserialize)]
+struct VlcCaching {
+    #[serde(default = "default_network_caching")]
+    network_caching: u32,
+}
+
+/// Network caching of the OBS VLC source when it is not changed, in milliseconds
+const DEFAULT_NETWORK_CACHING_MS: u32 = 400;
+
+fn default_network_caching() -> u32 {
+    DEFAULT_NETWORK_CACHING_MS
+}
+
 /// OBS PlaylistItem parameters
 #[derive(Serialize, Deserialize)]
 struct PlaylistItem {
@@ -53,9 +118,13 @@ struct PlaylistItem {
 }
 
 /// A VLC source location in OBS, derived from some existing source
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct VlcSourceBounds {
     name: String,
+    /// Scene item ID of the view
+    item_id: i64,
+    /// Position of the view in the scene's layer order, 0 being the bottom
+    index: u32,
     x: f32,
     y: f32,
     width: f32,
@@ -64,10 +133,44 @@ pub struct VlcSourceBounds {
     crop_right: u32,
     crop_top: u32,
     crop_bottom: u32,
+    /// Native size of the template source the crops were set against, if it was known
+    #[serde(default)]
+    source_width: Option<f32>,
+    #[serde(default)]
+    source_height: Option<f32>,
+}
+
+impl VlcSourceBounds {
+    /// The crops of the view, scaled from the template source to a source of the given size.
+    ///
+    /// Each axis is scaled on its own, so sources of a different aspect ratio keep the same
+    /// cropped region. An axis is left unscaled if either size on it is unknown.
+    fn scaled_crop(&self, source_width: f32, source_height: f32) -> (u32, u32, u32, u32) {
+        let scale = |template: Option<f32>, source: f32| match template {
+            Some(template) if template > 0.0 && source > 0.0 => source / template,
+            _ => 1.0,
+        };
+        let scale_x = scale(self.source_width, source_width);
+        let scale_y = scale(self.source_height, source_height);
+        log::debug!(
+            "Crop scale of stream view {}: {}x{}",
+            self.name,
+            scale_x,
+            scale_y
+        );
+
+        let apply = |crop: u32, scale: f32| (crop as f32 * scale).round() as u32;
+        (
+            apply(self.crop_left, scale_x),
+            apply(self.crop_right, scale_x),
+            apply(self.crop_top, scale_y),
+            apply(self.crop_bottom, scale_y),
+        )
+    }
 }
 
 /// A scene in OBS
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct ObsScene {
     /// Scene name
     pub name: String,
@@ -77,529 +180,3411 @@ pub struct ObsScene {
     pub sources: HashMap<usize, Vec<VlcSourceBounds>>,
 }
 
+impl ObsScene {
+    /// The runner slots of the scene, lowest first
+    pub fn get_slots(&self) -> Vec<i64> {
+        let mut slots: Vec<i64> = self.sources.keys().map(|slot| *slot as i64).collect();
+        slots.sort();
+        slots
+    }
+}
+
 /// The status of an OBS host
 #[derive(Serialize, Clone, Debug)]
 pub struct ObsHostState {
-    /// Whether the host is connected
+    /// Whether the host is connected. Always true for dry run hosts
     pub connected: bool,
+    /// Whether commands to the host are only logged, see `ObsHost::dry_run`
+    pub dry_run: bool,
     /// Whether the host is streaming
     pub streaming: bool,
+    /// Whether the host's replay buffer is running
+    pub replay_buffer: bool,
+    /// Whether the host's virtual camera is running
+    pub virtual_cam: bool,
+    /// Number of times the stream of each runner stalled on this host, by runner ID
+    pub stream_stalls: HashMap<i64, u32>,
+    /// Audio tracks (1-6) that runner sources output to, if configured
+    pub runner_audio_tracks: Option<Vec<u8>>,
     /// The scenes present in the host by name
     pub scenes: HashMap<String, ObsScene>,
+    /// The current scene collection
+    pub scene_collection: Option<String>,
+    pub scene_collections: Vec<String>,
+    /// The current profile
+    pub profile: Option<String>,
+    pub profiles: Vec<String>,
+    /// Why the last connection attempt failed, if the host is disconnected
+    pub last_connection_error: Option<ObsConnectionError>,
+    /// Where viewers can watch this host, from the settings
+    pub public_stream_urls: Vec<PublicStreamUrl>,
+    /// The outputs configured in OBS, including those added by plugins
+    pub outputs: Vec<ObsOutputState>,
+    /// Filters of the runner sources, by source name
+    pub runner_filters: HashMap<String, Vec<ObsFilterState>>,
 }
 
-/// Requests for ObsActor
-pub enum ObsCommand {
-    UpdateState(i64, Vec<ModifiedStreamState>, Rto<()>),
-    StartStream(String, Rto<()>),
-    EndStream(String, Rto<()>),
-    GetState(Rto<HashMap<String, ObsHostState>>),
+/// A filter of an OBS source
+#[derive(Serialize, Clone, Debug)]
+pub struct ObsFilterState {
+    pub name: String,
+    pub kind: String,
+    pub enabled: bool,
 }
 
-pub type ObsActor = ActorRef<ObsCommand>;
+/// An output of an OBS host, such as the main stream or a restream
+#[derive(Serialize, Clone, Debug)]
+pub struct ObsOutputState {
+    pub name: String,
+    pub kind: String,
+    /// Whether the output sends to a streaming service
+    pub service: bool,
+    pub active: bool,
+}
 
-type HostMap = HashMap<String, obws::Client>;
+/// Names of the main stream output in simple and advanced output mode
+const MAIN_STREAM_OUTPUTS: [&str; 2] = ["simple_stream", "adv_stream"];
 
-pub async fn run_obs(
-    settings: Arc<Settings>,
-    db: Arc<ProjectDb>,
-    mut rx: UnboundedReceiver<ObsCommand>,
-) -> Result<(), anyhow::Error> {
-    let mut host_map: HostMap = HostMap::new();
+impl ObsOutputState {
+    /// Whether this output streams to a service besides the main stream, eg. a restream
+    pub fn is_secondary_stream(&self) -> bool {
+        self.service && !MAIN_STREAM_OUTPUTS.contains(&self.name.as_str())
+    }
+}
 
-    loop {
-        match rx.recv().await.unwrap() {
-            ObsCommand::UpdateState(event, modifications, rto) => {
-                match db.get_stream(event).await {
-                    Ok(stream) => {
-                        if let Err(e) =
-                            connect_client_for_host(&stream.obs_host, &mut host_map, &settings)
-                                .await
-                        {
-                            rto.reply(Err(e));
-                        } else {
-                            let obs = host_map.get_mut(&stream.obs_host).unwrap();
-                            rto.reply(
-                                update_obs_state(&stream, &db, &settings, &modifications, obs)
-                                    .await,
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        rto.reply(Err(e));
-                    }
-                }
-            }
-            ObsCommand::StartStream(host, rto) => {
-                if let Err(e) = connect_client_for_host(&host, &mut host_map, &settings).await {
-                    rto.reply(Err(e));
+/// Kind of failure when connecting to an OBS host
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ObsConnectionErrorKind {
+    /// The websocket password was rejected
+    AuthFailed,
+    /// The host runs an obs-websocket version that is not supported, such as 4.x
+    UnsupportedVersion,
+    /// Nothing is listening on the configured address
+    Refused,
+    Timeout,
+    Other,
+}
+
+/// A classified error from connecting to an OBS host
+#[derive(Serialize, Clone, Debug)]
+pub struct ObsConnectionError {
+    pub kind: ObsConnectionErrorKind,
+    /// Description of the error for display
+    pub message: String,
+}
+
+impl ObsConnectionError {
+    fn from_error(e: &anyhow::Error) -> Self {
+        use obws::client::HandshakeError;
+        use obws::responses::WebSocketCloseCode;
+
+        let kind = match e.downcast_ref::<obws::Error>() {
+            Some(obws::Error::Timeout) => ObsConnectionErrorKind::Timeout,
+            Some(obws::Error::Handshake(HandshakeError::ConnectionClosed(Some(details)))) => {
+                let code = u16::from(details.code);
+                if code == WebSocketCloseCode::AuthenticationFailed as u16 {
+                    ObsConnectionErrorKind::AuthFailed
+                } else if code == WebSocketCloseCode::UnsupportedRpcVersion as u16 {
+                    ObsConnectionErrorKind::UnsupportedVersion
                 } else {
-                    let obs = host_map.get_mut(&host).unwrap();
-                   // rto.reply(obs.streaming().start().await.map_err(|e| e.into()));
-                    rto.reply(Ok(()))
+                    ObsConnectionErrorKind::Other
                 }
             }
-            ObsCommand::EndStream(host, rto) => {
-                if let Err(e) = connect_client_for_host(&host, &mut host_map, &settings).await {
-                    rto.reply(Err(e));
-                } else {
-                    let obs = host_map.get_mut(&host).unwrap();
-                    //rto.reply(obs.streaming().stop().await.map_err(|e| e.into()));
-                    rto.reply(Ok(()))
-                }
+            // obs-websocket 4.x does not use the 5.x handshake
+            Some(obws::Error::Handshake(
+                HandshakeError::NoHello | HandshakeError::DeserializeMessage(_),
+            ))
+            | Some(obws::Error::ObsStudioVersion(..))
+            | Some(obws::Error::ObsWebsocketVersion(..))
+            | Some(obws::Error::RpcVersion { .. }) => ObsConnectionErrorKind::UnsupportedVersion,
+            _ => e
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+                .map(|io| match io.kind() {
+                    std::io::ErrorKind::ConnectionRefused => ObsConnectionErrorKind::Refused,
+                    std::io::ErrorKind::TimedOut => ObsConnectionErrorKind::Timeout,
+                    _ => ObsConnectionErrorKind::Other,
+                })
+                .unwrap_or(ObsConnectionErrorKind::Other),
+        };
+
+        let message = match kind {
+            ObsConnectionErrorKind::AuthFailed => {
+                "Authentication failed, check the OBS websocket password".to_owned()
+            }
+            ObsConnectionErrorKind::UnsupportedVersion => format!(
+                "Unsupported obs-websocket version, OBS 28 or newer is required ({:#})",
+                e
+            ),
+            ObsConnectionErrorKind::Refused => {
+                "Connection refused, check that OBS is running with the websocket server enabled"
+                    .to_owned()
             }
-            ObsCommand::GetState(rto) => rto.reply(get_obs_state(&mut host_map, &settings).await),
+            ObsConnectionErrorKind::Timeout => "Timed out connecting to OBS".to_owned(),
+            ObsConnectionErrorKind::Other => format!("{:#}", e),
         };
+
+        Self { kind, message }
     }
 }
 
-async fn get_obs_state(
-    host_map: &mut HostMap,
-    settings: &Settings,
-) -> anyhow::Result<HashMap<String, ObsHostState>> {
-    let mut states = HashMap::new();
-    for host in settings.obs_hosts.keys() {
-        let connected = connect_client_for_host(host, host_map, settings).await;
-        if connected.is_ok() {
-            let obs = host_map.get_mut(host).unwrap();
-            states.insert(host.clone(), get_obs_client_info(obs).await?);
-        } else {
-            states.insert(
-                host.clone(),
-                ObsHostState {
-                    connected: false,
-                    streaming: false,
-                    scenes: HashMap::new(),
-                },
-            );
-        }
-    }
+/// The name of a scene in OBS
+#[derive(Serialize, Clone, Debug)]
+pub struct ObsSceneName {
+    /// Scene name
+    pub name: String,
+    /// Whether the scene contains stream views and can be used as a layout
+    pub usable: bool,
+    /// Whether the scene was read from the last layout snapshot because the host is offline
+    pub from_snapshot: bool,
+}
 
-    Ok(states)
+/// Where to read the layouts of a host from
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutSource {
+    /// The last layouts captured from OBS
+    Snapshot,
+    /// OBS itself, which must be connected
+    Live,
 }
 
-static STREAM_ITEM_NAME_REGEX: OnceLock<Regex> = OnceLock::new();
+/// The layout scenes of an OBS host
+#[derive(Serialize, Clone, Debug)]
+pub struct HostLayouts {
+    pub host: String,
+    /// Whether the layouts were read from the last snapshot rather than from OBS
+    pub from_snapshot: bool,
+    /// When the snapshot was captured, for snapshot layouts
+    #[serde(serialize_with = "serialize_datetime")]
+    pub captured_at: Option<OffsetDateTime>,
+    /// Scenes in OBS order
+    pub layouts: Vec<ObsScene>,
+}
 
-async fn get_obs_client_info(obs: &obws::Client) -> anyhow::Result<ObsHostState> {
-    let mut state = ObsHostState {
-        connected: true,
-        streaming: false,
-        scenes: HashMap::new(),
-    };
+/// The outcome of a single pre-flight check
+#[derive(Serialize, Clone, Debug)]
+pub struct PreflightItem {
+    /// Short name of the check
+    pub name: String,
+    pub passed: bool,
+    /// Whether a failure of this check should prevent going live
+    pub critical: bool,
+    /// What was found, or why the check failed
+    pub detail: String,
+    /// Whether the check used the last layout snapshot because the host is offline
+    pub from_snapshot: bool,
+}
 
-    state.connected = true;
-    state.streaming = obs.streaming().status().await?.active;
+/// A scene item of a runner source that no saved stream on its host uses
+#[derive(Serialize, Clone, Debug)]
+pub struct OrphanedItem {
+    pub scene: String,
+    pub item_id: i64,
+    pub source: String,
+}
 
-    let scenes = obs.scenes().list().await?.scenes;
-    let current_scene = obs.scenes().current_program_scene().await?;
-    for scene in scenes {
-        let mut out_scene = ObsScene {
-            name: scene.name.clone(),
-            active: scene.name == current_scene.id.name,
-            sources: HashMap::new(),
-        };
+/// Runner sources of a host that no saved stream on it uses
+#[derive(Serialize, Clone, Debug)]
+pub struct OrphanReport {
+    pub host: String,
+    /// Whether the orphans were removed, or only found
+    pub applied: bool,
+    /// Scene items of orphaned sources, in every scene of the host
+    pub scene_items: Vec<OrphanedItem>,
+    /// VLC inputs of orphaned sources, only collected without `keep_unused_streams`
+    pub inputs: Vec<String>,
+}
 
-        let scene_items = obs.scene_items().list(SceneId::Name(&scene.name)).await?;
+/// The result of checking an OBS host before going live
+#[derive(Serialize, Clone, Debug)]
+pub struct PreflightReport {
+    pub host: String,
+    /// The event checked, if the host has one
+    pub event: Option<i64>,
+    pub checks: Vec<PreflightItem>,
+}
 
-        let regex = STREAM_ITEM_NAME_REGEX.get_or_init(|| Regex::new(r"stream_(\d+)_.*").unwrap());
-        for item in scene_items {
-            if regex.is_match(&item.source_name) {
-                let caps = regex.captures(&item.source_name).unwrap();
-                let idx = caps.get(1).unwrap().as_str().parse::<usize>()?;
+impl PreflightReport {
+    /// Whether every critical check passed
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed || !c.critical)
+    }
 
-                let transform = obs
-                    .scene_items()
-                    .transform(SceneId::Name(&scene.name), item.id)
-                    .await?;
+    /// The failed critical checks with their details, separated by commas
+    pub fn describe_failures(&self) -> String {
+        self.checks
+            .iter()
+            .filter(|c| c.critical && !c.passed)
+            .map(|c| format!("{} ({})", c.name, c.detail))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
 
-                out_scene.sources.entry(idx).or_default().push(VlcSourceBounds {
-                    name: item.source_name.clone(),
-                    x: transform.position_x,
-                    y: transform.position_y,
-                    width: transform.bounds_width,
-                    height: transform.bounds_height,
-                    crop_left: transform.crop_left,
-                    crop_right: transform.crop_right,
-                    crop_top: transform.crop_top,
-                    crop_bottom: transform.crop_bottom,
-                });
-            }
-        }
-        state.scenes.insert(scene.name, out_scene);
+    fn check(&mut self, name: &str, critical: bool, result: Result<String, String>) {
+        self.check_with_source(name, critical, result, false);
     }
 
-    Ok(state)
+    fn check_with_source(
+        &mut self,
+        name: &str,
+        critical: bool,
+        result: Result<String, String>,
+        from_snapshot: bool,
+    ) {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        self.checks.push(PreflightItem {
+            name: name.to_owned(),
+            passed,
+            critical,
+            detail,
+            from_snapshot,
+        });
+    }
 }
 
-/// Attemt to connect to an OBS instance
-async fn connect_client_for_host(
-    host: &str,
-    host_map: &mut HostMap,
-    settings: &Settings,
-) -> anyhow::Result<()> {
-    let old_host = host_map.get(host);
-    if let Some(client) = old_host {
-        if client.general().version().await.is_ok() {
-            return Ok(());
-        } else {
-            log::debug!("Removing stale OBS client for host {}", host);
-            host_map.remove(host);
+/// Requests for ObsActor
+pub enum ObsCommand {
+    UpdateState(i64, Vec<ModifiedStreamState>, Rto<()>),
+    StartStream(String, Rto<()>),
+    EndStream(String, Rto<()>),
+    GetState(Rto<HashMap<String, ObsHostState>>),
+    GetSceneNames(String, Rto<Vec<ObsSceneName>>),
+    /// Re-render the text bindings for the stream of an event
+    UpdateText(i64, Rto<()>),
+    /// Drop the connection to a host and retry immediately
+    Reconnect(String, Rto<()>),
+    /// Move all items of a source in a scene to a layer index: host, scene, source, index
+    SetSourceIndex(String, String, String, u32, Rto<()>),
+    /// Save the replay buffer of a host, returning the path of the saved file
+    SaveReplayBuffer(String, Rto<PathBuf>),
+    /// Start or stop the replay buffer of a host
+    SetReplayBufferEnabled(String, bool, Rto<()>),
+    /// Check that a host is ready to go live without changing anything.
+    /// Uses the event streamed on the host if no event is given.
+    PreflightCheck(String, Option<i64>, Rto<PreflightReport>),
+    /// Open a fullscreen projector of "program", "preview" or a scene name on a monitor,
+    /// or in a window if no monitor is given: host, target, monitor
+    OpenProjector(String, String, Option<u32>, Rto<()>),
+    /// Start or stop the virtual camera of a host
+    SetVirtualCamEnabled(String, bool, Rto<()>),
+    /// Show a scene on a host, transitioning to it in studio mode: host, scene
+    SetProgramScene(String, String, Rto<()>),
+    /// Apply the sync offset of a runner to their source without touching the layout: event, runner
+    ApplySyncOffset(i64, i64, Rto<()>),
+    /// Switch the scene collection of a host, refusing while it is streaming: host, collection
+    SetSceneCollection(String, String, Rto<()>),
+    /// Switch the profile of a host, refusing while it is streaming: host, profile
+    SetProfile(String, String, Rto<()>),
+    /// Acknowledge commands that change OBS without running them, for replaying traces
+    SetDryRun(bool, Rto<()>),
+    /// Remove the runner sources on the host of an event's stream so they are created again,
+    /// returning how many were removed
+    PurgeRunnerSources(i64, Rto<usize>),
+    /// Read the layouts of a host, from OBS if it is connected and from the last snapshot
+    /// otherwise, unless a source is given: host, source
+    GetLayouts(String, Option<LayoutSource>, Rto<HostLayouts>),
+    /// Show the delta, personal best and best possible time of a runner in the statistic
+    /// sources of their slot, if the scene on air has them: event, runner
+    UpdateRunStats(i64, i64, Rto<()>),
+    /// Return the changes a full update of an event's stream would make, without making them
+    PlanUpdate(i64, Rto<Vec<ObsAction>>),
+    /// Find the runner sources of a host that no saved stream on it uses, removing them
+    /// if asked to: host, apply
+    CollectOrphanedSources(String, bool, Rto<OrphanReport>),
+    /// Set the URLs of a browser source group from the settings, then transition in
+    /// studio mode: host, group, parameters
+    SetBrowserSourceGroup(String, String, HashMap<String, String>, Rto<()>),
+    /// Show or hide a filter of a source: host, source, filter, enabled
+    SetSourceFilterEnabled(String, String, String, bool, Rto<()>),
+}
+
+pub type ObsActor = ActorRef<ObsCommand>;
+
+impl ActorMessage for ObsCommand {
+    fn label(&self) -> &'static str {
+        match self {
+            ObsCommand::UpdateState(..) => "UpdateState",
+            ObsCommand::StartStream(..) => "StartStream",
+            ObsCommand::EndStream(..) => "EndStream",
+            ObsCommand::GetState(..) => "GetState",
+            ObsCommand::GetSceneNames(..) => "GetSceneNames",
+            ObsCommand::UpdateText(..) => "UpdateText",
+            ObsCommand::Reconnect(..) => "Reconnect",
+            ObsCommand::SetSourceIndex(..) => "SetSourceIndex",
+            ObsCommand::SaveReplayBuffer(..) => "SaveReplayBuffer",
+            ObsCommand::SetReplayBufferEnabled(..) => "SetReplayBufferEnabled",
+            ObsCommand::PreflightCheck(..) => "PreflightCheck",
+            ObsCommand::OpenProjector(..) => "OpenProjector",
+            ObsCommand::SetVirtualCamEnabled(..) => "SetVirtualCamEnabled",
+            ObsCommand::SetProgramScene(..) => "SetProgramScene",
+            ObsCommand::ApplySyncOffset(..) => "ApplySyncOffset",
+            ObsCommand::SetSceneCollection(..) => "SetSceneCollection",
+            ObsCommand::SetProfile(..) => "SetProfile",
+            ObsCommand::SetDryRun(..) => "SetDryRun",
+            ObsCommand::PurgeRunnerSources(..) => "PurgeRunnerSources",
+            ObsCommand::GetLayouts(..) => "GetLayouts",
+            ObsCommand::UpdateRunStats(..) => "UpdateRunStats",
+            ObsCommand::PlanUpdate(..) => "PlanUpdate",
+            ObsCommand::CollectOrphanedSources(..) => "CollectOrphanedSources",
+            ObsCommand::SetBrowserSourceGroup(..) => "SetBrowserSourceGroup",
+            ObsCommand::SetSourceFilterEnabled(..) => "SetSourceFilterEnabled",
         }
     }
+}
 
-    let config = settings
-        .obs_hosts
-        .get(host)
-        .ok_or_else(|| anyhow!(format!("No OBS host configuration found for host {}", host)))?;
-
-    let obs_config = obws::client::ConnectConfig {
-        host: config.obs_ip.to_owned(),
-        port: config.obs_port.to_owned(),
-        password: config.obs_password.to_owned(),
-        event_subscriptions: Some(EventSubscription::NONE),
-        broadcast_capacity: None,
-        connect_timeout: Duration::from_secs(30),
-    };
+type HostMap = HashMap<String, Arc<obws::Client>>;
 
-    let obs = obws::Client::connect_with_config(obs_config).await?;
+/// Delay before the first reconnection attempt to a host
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(5);
+/// Maximum delay between reconnection attempts to a host
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Delay between reconnection attempts to a host that rejected the password,
+/// slow enough to not trigger the brute force protection of OBS
+const RECONNECT_AUTH_FAILED_DELAY: Duration = Duration::from_secs(60);
+/// Interval between checks that connected hosts are still reachable
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Time a connected host has to answer a health check before it is considered lost
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time a connected host has to answer the requests reading its state
+const HOST_STATE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval between checks for stalled runner streams
+const STALL_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Shortest time between two changes to the same run statistic source
+const RUN_STATS_MIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Seconds a live runner source can go without playback progress before it is stalled
+pub const DEFAULT_STALL_TIMEOUT_SECS: u64 = 20;
 
-    let obs_version = obs.general().version().await?;
-    log::info!(
-        "Connected to OBS version {}, websocket {}, running on {} ({})",
-        obs_version.obs_version.to_string(),
-        obs_version.obs_web_socket_version.to_string(),
-        obs_version.platform,
-        obs_version.platform_description
-    );
+/// Playback progress of a runner source that is on the program feed
+struct FeedProgress {
+    /// Playback position in milliseconds
+    cursor: Option<i128>,
+    last_progress: Instant,
+    /// Stalls since the source last made progress
+    attempts: u32,
+}
 
-    host_map.insert(host.to_owned(), obs);
+/// The layouts last captured from a connected host.
+///
+/// OBS events are not subscribed to, so these are served until a state read finds
+/// that the program scene or scene collection changed.
+struct CapturedLayouts {
+    scene_collection: String,
+    program_scene: String,
+    layouts: Vec<ObsScene>,
+}
 
-    Ok(())
+/// Get the client for a host, failing immediately if the host is not connected
+fn get_client<'a>(host_map: &'a HostMap, host: &str) -> anyhow::Result<&'a Arc<obws::Client>> {
+    host_map
+        .get(host)
+        .ok_or_else(|| Error::ObsUnavailable(host.to_owned()).into())
 }
 
-/// Delete all scene items for a player
-pub async fn delete_scene_items_for_player(
-    obs: &obws::Client,
-    layout: SceneId<'_>,
-    scene_items: &[SceneItem],
-    source_id_name: &str,
-) -> anyhow::Result<()> {
-    for old_item in scene_items {
-        if old_item.source_name == source_id_name
-            && obs.scene_items().enabled(layout, old_item.id).await?
-        {
-            obs.scene_items().remove(layout, old_item.id).await?;
+/// Check every connected host at once, returning the hosts that did not answer in time
+async fn find_lost_hosts(host_map: &HostMap, directory: &Directory) -> Vec<String> {
+    let checks = host_map.iter().map(|(host, obs)| async move {
+        let alive = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, obs.general().version()).await;
+        (host, matches!(alive, Ok(Ok(_))))
+    });
+
+    let mut lost = vec![];
+    for (host, alive) in join_all(checks).await {
+        if alive {
+            directory.health.record_obs_success(host);
+        } else {
+            log::warn!("Lost connection to OBS host {}", host);
+            lost.push(host.clone());
         }
     }
-    Ok(())
+    lost
 }
 
-/// Trigger the transition specified in the settings
-pub async fn do_transition(obs: &obws::Client, settings: &Settings) -> anyhow::Result<()> {
-    log::debug!("Triggering Studio Mode transition");
-    match &settings.obs_transition {
-        Some(name) => {
-            obs.transitions().set_current(name).await?;
-            obs.transitions().trigger().await?;
-        }
-        None => obs.transitions().trigger().await?,
+/// Mark a host as disconnected and wake its connection loop
+fn disconnect_host(
+    host: &str,
+    host_map: &mut HostMap,
+    connectors: &HashMap<String, UnboundedSender<()>>,
+) {
+    host_map.remove(host);
+    if let Some(wake) = connectors.get(host) {
+        let _ = wake.send(());
     }
-    Ok(())
 }
 
-/// Return the appropriate layout for the given project state
-fn get_layout<'a>(
-    event: &Event,
-    state: &StreamState,
-    obs_state: &'a ObsHostState,
-) -> Option<&'a ObsScene> {
-    if let Some(layout) = obs_state.scenes.get(&state.requested_layout.clone()?) {
-        return Some(layout);
-    }
+pub async fn run_obs(
+    settings: Arc<Settings>,
+    db: Arc<dyn ProjectStore>,
+    mut rx: ActorReceiver<ObsCommand>,
+    directory: Directory,
+) -> Result<(), anyhow::Error> {
+    let mut host_map: HostMap = HostMap::new();
+    let mut warned_placeholders = HashSet::new();
+    let mut feeds = HashMap::new();
+    // Stall counts by host and runner, kept for the lifetime of the process
+    let mut stall_counts: HashMap<String, HashMap<i64, u32>> = HashMap::new();
+    let mut captured_layouts: HashMap<String, CapturedLayouts> = HashMap::new();
+    // Settings are validated at startup, so this only fails if validation was skipped
+    let naming = SourceNaming::from_settings(&settings).map_err(|e| anyhow!(e.join(", ")))?;
 
-    for layout in &event.preferred_layouts {
-        if let Some(layout) = obs_state.scenes.get(layout) {
-            if layout.sources.len() == state.stream_runners.len() {
-                return Some(layout);
-            }
+    // Each host is connected by a background loop, which hands finished clients to this actor
+    let (connected_tx, mut connected_rx) =
+        unbounded_channel::<(String, Result<obws::Client, ObsConnectionError>)>();
+    let mut connectors = HashMap::new();
+    let mut connection_errors: HashMap<String, ObsConnectionError> = HashMap::new();
+    let mut dry_run = false;
+    for host in settings.obs_hosts.keys() {
+        if settings.is_dry_run(host) {
+            log::warn!(
+                "OBS host {} is in dry run mode, its OBS will not be changed",
+                host
+            );
+            directory.health.set_obs_connected(host, true);
+            continue;
         }
+        let (wake_tx, wake_rx) = unbounded_channel();
+        let _ = wake_tx.send(());
+        connectors.insert(host.clone(), wake_tx);
+        tokio::spawn(run_host_connector(
+            host.clone(),
+            settings.clone(),
+            wake_rx,
+            connected_tx.clone(),
+        ));
     }
 
-    obs_state
-        .scenes
-        .values()
-        .find(|l| l.sources.len() == state.stream_runners.len())
-}
-
-/// Apply project state to OBS
-pub async fn update_obs_state(
-    state: &StreamState,
-    db: &ProjectDb,
-    settings: &Settings,
-    modifications: &[ModifiedStreamState],
-    obs: &obws::Client,
-) -> anyhow::Result<()> {
-    log::debug!("Updating OBS: {:?}", modifications);
-
-    let mut vlc_inputs = obs.inputs().list(Some("vlc_source")).await?;
-
-    let obs_state = get_obs_client_info(obs).await?;
-    let scenes = obs.scenes().list().await?;
-    let event = &db.get_event(state.event).await?;
-
-    match get_layout(event, state, &obs_state) {
-        Some(layout) => {
-            let target_layout_id = SceneId::Name(&layout.name);
+    let mut health_check = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+    let mut stall_check = tokio::time::interval(STALL_CHECK_INTERVAL);
+    let mut run_stats_flush = tokio::time::interval(RUN_STATS_MIN_INTERVAL);
+    let mut run_stats = RunStatsThrottle::default();
 
-            if !scenes.scenes.iter().any(|s| s.name != layout.name) {
-                return Err(anyhow!(format!(
-                    "OBS has no scene named {}, which the current layout requires.",
-                    layout.name
-                )));
+    loop {
+        let (msg, span) = tokio::select! {
+            msg = rx.recv() => msg.unwrap(),
+            Some((host, result)) = connected_rx.recv() => {
+                match result {
+                    Ok(obs) => {
+                        directory.health.set_obs_connected(&host, true);
+                        connection_errors.remove(&host);
+                        recapture_layouts(&obs, &naming, &*db, &host, &mut captured_layouts).await;
+                        host_map.insert(host, Arc::new(obs));
+                    }
+                    Err(e) => {
+                        connection_errors.insert(host, e);
+                    }
+                }
+                continue;
+            }
+            _ = health_check.tick() => {
+                for host in find_lost_hosts(&host_map, &directory).await {
+                    directory.health.set_obs_connected(&host, false);
+                    disconnect_host(&host, &mut host_map, &connectors);
+                }
+                continue;
+            }
+            _ = stall_check.tick() => {
+                if let Err(e) = check_stalled_feeds(
+                    &host_map,
+                    &*db,
+                    &settings,
+                    &directory,
+                    &mut feeds,
+                    &mut stall_counts,
+                )
+                .await
+                {
+                    log::warn!("Failed to check for stalled streams: {}", e);
+                }
+                continue;
             }
+            _ = run_stats_flush.tick() => {
+                if !dry_run {
+                    run_stats.flush(&host_map).await;
+                }
+                continue;
+            }
+        };
 
-            let scene_items = obs.scene_items().list(target_layout_id).await?;
+        let host = command_host(&msg, &*db).await;
+        let msg = match host.filter(|h| settings.is_dry_run(h)) {
+            Some(host) => match run_dry(msg, &host, &*db, &settings, &naming).await {
+                Some(msg) => msg,
+                None => continue,
+            },
+            None => msg,
+        };
 
-            // Modify commentary text
-            if modifications.contains(&ModifiedStreamState::Commentary)
-                && scene_items.iter().any(|s| &s.source_name == "commentary")
-            {
-                log::debug!("Updating commentator list");
-                let comm_setting = SpecificFreetype {
-                    text: &state.get_commentators().join("\n"),
-                };
-                obs.inputs()
-                    .set_settings(SetSettings {
-                        input: InputId::Name("commentary"),
-                        settings: &comm_setting,
-                        overlay: Some(true),
-                    })
-                    .await?;
-            }
+        let msg = match dry_run {
+            true => match skip_in_dry_run(msg) {
+                Some(msg) => msg,
+                None => continue,
+            },
+            false => msg,
+        };
 
-            for (idx, runner) in state.stream_runners.iter() {
-                let runner = db.get_runner(*runner).await?;
-                log::debug!("Updating player {}", runner.name);
-                let stream_source_id_name = format!("streamer_{}", runner.name);
-                let stream_source_id = InputId::Name(&stream_source_id_name);
-
-                let mut just_created = false;
-                let good_url = runner.cached_stream_url.to_owned();
-
-                match &good_url {
-                    Some(url) => {
-                        if !vlc_inputs.iter().any(|i| i.id.name == stream_source_id) {
-                            // Source does not exist, create source
-                            log::debug!("Creating source for {}", runner.name);
-                            let vlc_setting = VLC {
-                                playlist: vec![PlaylistItem {
-                                    hidden: false,
-                                    selected: false,
-                                    value: url.to_owned(),
-                                }],
-                            };
-
-                            let new_input = inputs::Create {
-                                scene: target_layout_id,
-                                input: &stream_source_id_name,
-                                kind: "vlc_source",
-                                settings: Some(vlc_setting),
-                                enabled: Some(false),
-                            };
-
-                            obs.inputs().create(new_input).await?;
-                            just_created = true;
-                        } else {
-                            // Source exists, check if stream is up to date
-                            let old_setting =
-                                obs.inputs().settings::<VLC>(stream_source_id).await?;
-                            if old_setting.settings.playlist.is_empty()
-                                || old_setting.settings.playlist[0].value != *url
+        async {
+            match msg {
+                ObsCommand::UpdateState(event, modifications, rto) => {
+                    record_event(event);
+                    match db.get_stream(event).await {
+                        Ok(stream) if !stream.active => {
+                            log::debug!(
+                                "Stream for event {} is not active on {}, skipping OBS update",
+                                event,
+                                stream.obs_host
+                            );
+                            rto.reply(Ok(()));
+                        }
+                        Ok(stream) if stream.manual_scene_override => {
+                            log::debug!(
+                                "A scene was chosen by hand on {}, skipping OBS update",
+                                stream.obs_host
+                            );
+                            rto.reply(Ok(()));
+                        }
+                        Ok(stream) => match get_client(&host_map, &stream.obs_host) {
+                            Ok(obs) => {
+                                let res = update_obs_state(
+                                    &stream,
+                                    &*db,
+                                    &directory,
+                                    &settings,
+                                    &naming,
+                                    &modifications,
+                                    obs,
+                                )
+                                .await;
+                                if res.is_ok()
+                                    && modifications.contains(&ModifiedStreamState::Layout)
+                                {
+                                    // Old layouts keep the items of runners that left
+                                    let apply = settings.remove_orphaned_sources.unwrap_or(false);
+                                    let host = &stream.obs_host;
+                                    if let Err(e) =
+                                        collect_orphaned_sources(obs, &*db, &settings, host, apply)
+                                            .await
+                                    {
+                                        log::warn!(
+                                            "Failed to collect orphaned sources on {}: {}",
+                                            host,
+                                            e
+                                        );
+                                    }
+                                }
+                                rto.reply(match res {
+                                    Ok(_) => {
+                                        update_text_bindings(
+                                            &stream,
+                                            &*db,
+                                            &settings,
+                                            &directory,
+                                            obs,
+                                            &mut warned_placeholders,
+                                        )
+                                        .await
+                                    }
+                                    Err(e) => Err(e),
+                                });
+                            }
+                            Err(e) => rto.reply(Err(e)),
+                        },
+                        Err(e) => {
+                            rto.reply(Err(e));
+                        }
+                    }
+                }
+                ObsCommand::StartStream(host, rto) => match get_client(&host_map, &host) {
+                    Ok(obs) => {
+                        if settings.enforce_preflight.unwrap_or(false) {
+                            match run_preflight(&host, None, Some(obs), &*db, &settings, &naming)
+                                .await
                             {
-                                log::debug!("Applying stream change to {}", runner.name);
-                                let vlc_setting = VLC {
-                                    playlist: vec![PlaylistItem {
-                                        hidden: false,
-                                        selected: false,
-                                        value: url.to_owned(),
-                                    }],
-                                };
-                                obs.inputs()
-                                    .set_settings(SetSettings {
-                                        input: stream_source_id,
-                                        settings: &vlc_setting,
-                                        overlay: Some(true),
-                                    })
-                                    .await?;
-
-                                tokio::time::sleep(Duration::from_millis(200)).await;
-                            } else {
-                                log::debug!("Stream for {} is up to date", runner.name);
+                                Ok(report) if !report.passed() => {
+                                    rto.reply(Err(anyhow!(
+                                        "Pre-flight checks failed for OBS host {}: {}",
+                                        host,
+                                        report.describe_failures()
+                                    )));
+                                    return;
+                                }
+                                Ok(_) => {}
+                                Err(e) => {
+                                    rto.reply(Err(e));
+                                    return;
+                                }
                             }
                         }
-
-                        if state
-                            .audible_runner
-                            .as_ref()
-                            .map(|r| *r == runner.id)
-                            .unwrap_or(*idx == 0)
-                        {
-                            obs.inputs().set_muted(stream_source_id, false).await?;
-                            obs.inputs()
-                                .set_volume(
-                                    stream_source_id,
-                                    Volume::Mul(0.01 * runner.volume_percent as f32),
-                                )
-                                .await?;
-                        } else {
-                            obs.inputs().set_muted(stream_source_id, true).await?;
+                        // rto.reply(obs.streaming().start().await.map_err(|e| e.into()));
+                        rto.reply(Ok(()))
+                    }
+                    Err(e) => rto.reply(Err(e)),
+                },
+                ObsCommand::EndStream(host, rto) => match get_client(&host_map, &host) {
+                    Ok(_obs) => {
+                        //rto.reply(obs.streaming().stop().await.map_err(|e| e.into()));
+                        rto.reply(Ok(()))
+                    }
+                    Err(e) => rto.reply(Err(e)),
+                },
+                ObsCommand::GetState(rto) => rto.reply(
+                    get_obs_state(
+                        &host_map,
+                        &connection_errors,
+                        &mut captured_layouts,
+                        &settings,
+                        &naming,
+                        &*db,
+                    )
+                    .await
+                    .map(|mut states| {
+                        for (host, state) in states.iter_mut() {
+                            state.stream_stalls =
+                                stall_counts.get(host).cloned().unwrap_or_default();
+                        }
+                        states
+                    }),
+                ),
+                ObsCommand::GetSceneNames(host, rto) => {
+                    let captured = captured_layouts.get(&host);
+                    match (get_client(&host_map, &host), captured) {
+                        (Ok(_), Some(captured)) => {
+                            rto.reply(Ok(get_scene_names(&captured.layouts, false)))
                         }
+                        (Ok(obs), None) => {
+                            recapture_layouts(obs, &naming, &*db, &host, &mut captured_layouts)
+                                .await;
+                            rto.reply(match captured_layouts.get(&host) {
+                                Some(captured) => Ok(get_scene_names(&captured.layouts, false)),
+                                None => {
+                                    Err(anyhow!("Failed to read the scenes of OBS host {}", host))
+                                }
+                            })
+                        }
+                        (Err(e), _) => rto.reply(match db.get_layout_snapshot(&host).await {
+                            Ok((scenes, _)) if scenes.is_empty() => Err(e),
+                            Ok((scenes, _)) => Ok(get_scene_names(&scenes, true)),
+                            Err(snapshot_error) => Err(snapshot_error),
+                        }),
                     }
-                    None => log::warn!("No stream URL for {}, skipping...", runner.name),
                 }
-
-                // Update this player's view
-                if modifications.contains(&ModifiedStreamState::RunnerView(runner.id))
-                    || modifications.contains(&ModifiedStreamState::Layout)
-                    || just_created
-                {
-                    log::debug!("Deleting old items for {}", runner.name);
-                    // Remove old scene_items
-                    delete_scene_items_for_player(
-                        obs,
-                        target_layout_id,
-                        &scene_items,
-                        &stream_source_id_name,
+                ObsCommand::GetLayouts(host, source, rto) => {
+                    let obs = host_map.get(&host).map(|obs| obs.as_ref());
+                    rto.reply(
+                        get_host_layouts(
+                            &host,
+                            source,
+                            obs,
+                            &mut captured_layouts,
+                            &*db,
+                            &settings,
+                            &naming,
+                        )
+                        .await,
                     )
-                    .await?;
+                }
+                ObsCommand::UpdateText(event, rto) => match db.get_stream(event).await {
+                    Ok(stream) if !stream.active => rto.reply(Ok(())),
+                    Ok(stream) => match get_client(&host_map, &stream.obs_host) {
+                        Ok(obs) => rto.reply(
+                            update_text_bindings(
+                                &stream,
+                                &*db,
+                                &settings,
+                                &directory,
+                                obs,
+                                &mut warned_placeholders,
+                            )
+                            .await,
+                        ),
+                        Err(e) => rto.reply(Err(e)),
+                    },
+                    Err(e) => rto.reply(Err(e)),
+                },
+                ObsCommand::SetSourceIndex(host, scene, source, index, rto) => {
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto.reply(set_source_index(obs, &scene, &source, index).await),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::SaveReplayBuffer(host, rto) => match get_client(&host_map, &host) {
+                    Ok(obs) => match save_replay_buffer(obs, &host).await {
+                        // The file is written asynchronously, so wait for it off the actor
+                        Ok(previous) => {
+                            let webhook = settings.replay_webhook_url.clone();
+                            tokio::spawn(wait_for_saved_replay(
+                                obs.clone(),
+                                host,
+                                previous,
+                                webhook,
+                                rto,
+                            ));
+                        }
+                        Err(e) => rto.reply(Err(e)),
+                    },
+                    Err(e) => rto.reply(Err(e)),
+                },
+                ObsCommand::SetReplayBufferEnabled(host, enabled, rto) => {
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto.reply(set_replay_buffer_enabled(obs, &host, enabled).await),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::OpenProjector(host, target, monitor, rto) => {
+                    record_host(&host);
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto.reply(open_projector(obs, &host, &target, monitor).await),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::SetVirtualCamEnabled(host, enabled, rto) => {
+                    record_host(&host);
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto.reply(set_virtual_cam_enabled(obs, enabled).await),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::SetProgramScene(host, scene, rto) => {
+                    record_host(&host);
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto.reply(set_program_scene(obs, &settings, &scene).await),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::UpdateRunStats(event, runner, rto) => {
+                    record_event(event);
+                    match db.get_stream(event).await {
+                        Ok(stream) if !stream.active => rto.reply(Ok(())),
+                        Ok(stream) => match get_client(&host_map, &stream.obs_host) {
+                            Ok(obs) => rto.reply(
+                                update_run_stats(
+                                    obs,
+                                    &stream,
+                                    runner,
+                                    &*db,
+                                    &naming,
+                                    &mut run_stats,
+                                )
+                                .await,
+                            ),
+                            Err(e) => rto.reply(Err(e)),
+                        },
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::PlanUpdate(event, rto) => {
+                    record_event(event);
+                    match db.get_stream(event).await {
+                        Ok(stream) => match get_client(&host_map, &stream.obs_host) {
+                            Ok(obs) => rto.reply(
+                                plan_full_update(
+                                    &stream, &*db, &directory, &settings, &naming, obs,
+                                )
+                                .await,
+                            ),
+                            Err(e) => rto.reply(Err(e)),
+                        },
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::ApplySyncOffset(event, runner, rto) => {
+                    record_event(event);
+                    match db.get_stream(event).await {
+                        Ok(stream) if !stream.active => rto.reply(Ok(())),
+                        Ok(stream) => match get_client(&host_map, &stream.obs_host) {
+                            Ok(obs) => {
+                                let res = match db.get_name_for_runner(runner).await {
+                                    Ok(name) => {
+                                        let source = runner_input_name(&name);
+                                        apply_sync_offset(
+                                            obs,
+                                            InputId::Name(&source),
+                                            stream.get_sync_offset(runner),
+                                        )
+                                        .await
+                                    }
+                                    Err(e) => Err(e),
+                                };
+                                rto.reply(res)
+                            }
+                            Err(e) => rto.reply(Err(e)),
+                        },
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::SetSceneCollection(host, collection, rto) => {
+                    record_host(&host);
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => {
+                            let res = set_scene_collection(obs, &host, &collection).await;
+                            if matches!(res, Ok(true)) {
+                                // Sources of the old collection are gone, so their progress is stale
+                                feeds.retain(|(feed_host, _), _| *feed_host != host);
+                                recapture_layouts(obs, &naming, &*db, &host, &mut captured_layouts)
+                                    .await;
+                            }
+                            rto.reply(res.map(|_| ()))
+                        }
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::SetProfile(host, profile, rto) => {
+                    record_host(&host);
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto.reply(set_profile(obs, &host, &profile).await),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::PreflightCheck(host, event, rto) => {
+                    record_host(&host);
+                    let obs = host_map.get(&host).map(|obs| obs.as_ref());
+                    rto.reply(run_preflight(&host, event, obs, &*db, &settings, &naming).await)
+                }
+                ObsCommand::Reconnect(host, rto) => {
+                    if connectors.contains_key(&host) {
+                        log::info!("Reconnecting to OBS host {}", host);
+                        disconnect_host(&host, &mut host_map, &connectors);
+                        rto.reply(Ok(()));
+                    } else {
+                        rto.reply(Err(anyhow!(
+                            "No OBS host configuration found for host {}",
+                            host
+                        )));
+                    }
+                }
+                ObsCommand::PurgeRunnerSources(event, rto) => {
+                    record_event(event);
+                    match db.get_stream(event).await {
+                        Ok(stream) => match get_client(&host_map, &stream.obs_host) {
+                            Ok(obs) => rto.reply(purge_runner_sources(obs, &*db, &stream).await),
+                            Err(e) => rto.reply(Err(e)),
+                        },
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::CollectOrphanedSources(host, apply, rto) => {
+                    record_host(&host);
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto.reply(
+                            collect_orphaned_sources(obs, &*db, &settings, &host, apply).await,
+                        ),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::SetBrowserSourceGroup(host, group, params, rto) => {
+                    record_host(&host);
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto.reply(
+                            set_browser_source_group(obs, &*db, &settings, &host, &group, params)
+                                .await,
+                        ),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::SetSourceFilterEnabled(host, source, filter, enabled, rto) => {
+                    record_host(&host);
+                    match get_client(&host_map, &host) {
+                        Ok(obs) => rto
+                            .reply(set_source_filter_enabled(obs, &source, &filter, enabled).await),
+                        Err(e) => rto.reply(Err(e)),
+                    }
+                }
+                ObsCommand::SetDryRun(enabled, rto) => {
+                    log::info!(
+                        "{} OBS dry run mode",
+                        if enabled { "Entering" } else { "Leaving" }
+                    );
+                    dry_run = enabled;
+                    rto.reply(Ok(()));
+                }
+            };
+        }
+        .instrument(span)
+        .await;
+    }
+}
+
+/// The host a command acts on, from the stream of the event for commands given an event
+async fn command_host(msg: &ObsCommand, db: &dyn ProjectStore) -> Option<String> {
+    let event = match msg {
+        ObsCommand::UpdateState(event, _, _)
+        | ObsCommand::UpdateText(event, _)
+        | ObsCommand::ApplySyncOffset(event, _, _)
+        | ObsCommand::PurgeRunnerSources(event, _)
+        | ObsCommand::UpdateRunStats(event, _, _)
+        | ObsCommand::PlanUpdate(event, _) => *event,
+        ObsCommand::StartStream(host, _)
+        | ObsCommand::EndStream(host, _)
+        | ObsCommand::GetSceneNames(host, _)
+        | ObsCommand::Reconnect(host, _)
+        | ObsCommand::SetSourceIndex(host, _, _, _, _)
+        | ObsCommand::SaveReplayBuffer(host, _)
+        | ObsCommand::SetReplayBufferEnabled(host, _, _)
+        | ObsCommand::OpenProjector(host, _, _, _)
+        | ObsCommand::SetVirtualCamEnabled(host, _, _)
+        | ObsCommand::SetProgramScene(host, _, _)
+        | ObsCommand::SetSceneCollection(host, _, _)
+        | ObsCommand::SetProfile(host, _, _)
+        | ObsCommand::GetLayouts(host, _, _)
+        | ObsCommand::CollectOrphanedSources(host, _, _)
+        | ObsCommand::SetBrowserSourceGroup(host, _, _, _)
+        | ObsCommand::SetSourceFilterEnabled(host, _, _, _, _)
+        | ObsCommand::PreflightCheck(host, _, _) => return Some(host.clone()),
+        ObsCommand::GetState(_) | ObsCommand::SetDryRun(..) => return None,
+    };
+    db.get_stream(event).await.ok().map(|s| s.obs_host)
+}
+
+/// Log a command to a dry run host instead of running it, returning commands that only read.
+///
+/// Stream updates are planned against the host's last layout snapshot and the planned
+/// actions are logged.
+async fn run_dry(
+    msg: ObsCommand,
+    host: &str,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    naming: &SourceNaming,
+) -> Option<ObsCommand> {
+    match msg {
+        ObsCommand::UpdateState(event, modifications, rto) => {
+            rto.reply(log_dry_run_update(event, &modifications, db, settings, naming).await);
+            None
+        }
+        ObsCommand::PlanUpdate(event, rto) => {
+            let modifications = [ModifiedStreamState::Layout, ModifiedStreamState::Commentary];
+            rto.reply(plan_dry_run_update(event, &modifications, db, settings, naming).await);
+            None
+        }
+        msg => {
+            let label = msg.label();
+            let trace = msg.trace();
+            let msg = skip_in_dry_run(msg);
+            if msg.is_none() {
+                log::info!(
+                    "Dry run on {}: {}",
+                    host,
+                    trace.map(|t| t.to_string()).unwrap_or(label.to_owned())
+                );
+            }
+            msg
+        }
+    }
+}
+
+/// Plan an update of the stream of an event against the layout snapshot of its host.
+///
+/// The snapshot has no runner inputs, so every runner input is planned to be created.
+async fn plan_dry_run_update(
+    event: i64,
+    modifications: &[ModifiedStreamState],
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    naming: &SourceNaming,
+) -> anyhow::Result<Vec<ObsAction>> {
+    let state = db.get_stream(event).await?;
+    let event = db.get_event(event).await?;
+    let (layouts, _) = db.get_layout_snapshot(&state.obs_host).await?;
+    let Some(layout) = find_snapshot_layout(&event, &state, &layouts).cloned() else {
+        return Err(Error::UnknownLayout(
+            "No known layout for the current player count in the layout snapshot.".to_string(),
+        ))?;
+    };
+
+    let items = layout
+        .sources
+        .values()
+        .flatten()
+        .map(|view| LayoutItem {
+            id: view.item_id,
+            source_name: view.name.clone(),
+            enabled: false,
+        })
+        .collect();
+
+    let mut slots: Vec<_> = state.stream_runners.iter().collect();
+    slots.sort();
+    let mut runners = vec![];
+    for (idx, runner) in slots {
+        runners.push((*idx, db.get_runner(*runner).await?));
+    }
+    let mut on_deck = vec![];
+    for entry in state.on_deck_runners.iter() {
+        if !state.stream_runners.values().any(|r| *r == entry.runner) {
+            on_deck.push(db.get_runner(entry.runner).await?);
+        }
+    }
+
+    let obs_state = ObsUpdateState {
+        layout,
+        items,
+        vlc_inputs: vec![],
+        input_urls: HashMap::new(),
+        runners,
+        on_deck,
+        studio_mode: false,
+        program_scene: None,
+    };
+    Ok(plan_obs_update(
+        &state,
+        &event,
+        &obs_state,
+        settings,
+        naming,
+        modifications,
+    ))
+}
+
+/// Log the actions an update of the stream of an event would take on its dry run host
+async fn log_dry_run_update(
+    event: i64,
+    modifications: &[ModifiedStreamState],
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    naming: &SourceNaming,
+) -> anyhow::Result<()> {
+    let stream = db.get_stream(event).await?;
+    if !stream.active {
+        return Ok(());
+    }
+    let actions = plan_dry_run_update(event, modifications, db, settings, naming).await?;
+    for action in &actions {
+        log::info!(
+            "Dry run on {}: {}",
+            stream.obs_host,
+            serde_json::to_string(action)?
+        );
+    }
+    Ok(())
+}
+
+/// Acknowledge a command that changes OBS without running it, returning commands that only read
+fn skip_in_dry_run(msg: ObsCommand) -> Option<ObsCommand> {
+    match msg {
+        ObsCommand::UpdateState(_, _, rto)
+        | ObsCommand::StartStream(_, rto)
+        | ObsCommand::EndStream(_, rto)
+        | ObsCommand::UpdateText(_, rto)
+        | ObsCommand::Reconnect(_, rto)
+        | ObsCommand::SetSourceIndex(_, _, _, _, rto)
+        | ObsCommand::SetReplayBufferEnabled(_, _, rto)
+        | ObsCommand::OpenProjector(_, _, _, rto)
+        | ObsCommand::SetVirtualCamEnabled(_, _, rto)
+        | ObsCommand::SetProgramScene(_, _, rto)
+        | ObsCommand::ApplySyncOffset(_, _, rto)
+        | ObsCommand::UpdateRunStats(_, _, rto)
+        | ObsCommand::SetSceneCollection(_, _, rto)
+        | ObsCommand::SetProfile(_, _, rto)
+        | ObsCommand::SetBrowserSourceGroup(_, _, _, rto)
+        | ObsCommand::SetSourceFilterEnabled(_, _, _, _, rto) => {
+            rto.reply(Ok(()));
+            None
+        }
+        ObsCommand::SaveReplayBuffer(_, rto) => {
+            rto.reply(Err(anyhow!("OBS is in dry run mode")));
+            None
+        }
+        ObsCommand::CollectOrphanedSources(host, _, rto) => {
+            // Only report what would be removed
+            Some(ObsCommand::CollectOrphanedSources(host, false, rto))
+        }
+        ObsCommand::PurgeRunnerSources(_, rto) => {
+            rto.reply(Ok(0));
+            None
+        }
+        msg => Some(msg),
+    }
+}
+
+async fn get_obs_state(
+    host_map: &HostMap,
+    connection_errors: &HashMap<String, ObsConnectionError>,
+    captured_layouts: &mut HashMap<String, CapturedLayouts>,
+    settings: &Settings,
+    naming: &SourceNaming,
+    db: &dyn ProjectStore,
+) -> anyhow::Result<HashMap<String, ObsHostState>> {
+    let mut states = HashMap::new();
+    let mut connected = vec![];
+    for host in settings.obs_hosts.keys() {
+        if settings.is_dry_run(host) {
+            states.insert(host.clone(), get_dry_run_state(settings, db, host).await?);
+            continue;
+        }
+        match host_map.get(host) {
+            Some(obs) => connected.push((host, obs)),
+            None => {
+                let error = connection_errors.get(host).cloned();
+                states.insert(host.clone(), get_disconnected_state(settings, host, error));
+            }
+        }
+    }
+
+    // Hosts are read at once, so that one slow host does not hold up the others
+    let captured = &*captured_layouts;
+    let reads = connected.into_iter().map(|(host, obs)| async move {
+        let state = tokio::time::timeout(
+            HOST_STATE_TIMEOUT,
+            get_connected_state(obs, settings, naming, db, host, captured.get(host)),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("OBS host {} did not answer in time", host),
+            )
+            .into())
+        });
+        (host, state)
+    });
+    for (host, state) in join_all(reads).await {
+        let state = match state {
+            Ok((state, recaptured)) => {
+                if let Some(recaptured) = recaptured {
+                    captured_layouts.insert(host.clone(), recaptured);
+                }
+                state
+            }
+            Err(e) => {
+                log::warn!("Failed to read the state of OBS host {}: {:#}", host, e);
+                let error = ObsConnectionError::from_error(&e);
+                get_disconnected_state(settings, host, Some(error))
+            }
+        };
+        states.insert(host.clone(), state);
+    }
+
+    Ok(states)
+}
+
+/// Read the state of a connected host from OBS, with the layouts of `captured`
+/// unless its program scene or scene collection changed since.
+///
+/// Returns the layouts captured again, if they were.
+async fn get_connected_state(
+    obs: &obws::Client,
+    settings: &Settings,
+    naming: &SourceNaming,
+    db: &dyn ProjectStore,
+    host: &str,
+    captured: Option<&CapturedLayouts>,
+) -> anyhow::Result<(ObsHostState, Option<CapturedLayouts>)> {
+    let mut state = get_obs_client_info(obs).await?;
+    let program_scene = obs.scenes().current_program_scene().await?.id.name;
+    let recaptured = match captured {
+        Some(c)
+            if state.scene_collection.as_ref() == Some(&c.scene_collection)
+                && c.program_scene == program_scene =>
+        {
+            None
+        }
+        _ => Some(capture_layout_snapshot(obs, naming, db, host, captured).await?),
+    };
+    state.scenes = recaptured
+        .as_ref()
+        .or(captured)
+        .map(|c| c.layouts.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .map(|scene| (scene.name.clone(), scene.clone()))
+        .collect();
+    state.runner_audio_tracks = settings
+        .obs_hosts
+        .get(host)
+        .and_then(|h| h.runner_audio_tracks.clone());
+    state.public_stream_urls = get_public_stream_urls(settings, host);
+    state.runner_filters = get_runner_filters(obs).await?;
+    Ok((state, recaptured))
+}
+
+/// The state of a host that is not connected, or that failed to answer
+fn get_disconnected_state(
+    settings: &Settings,
+    host: &str,
+    last_connection_error: Option<ObsConnectionError>,
+) -> ObsHostState {
+    ObsHostState {
+        connected: false,
+        dry_run: false,
+        streaming: false,
+        replay_buffer: false,
+        virtual_cam: false,
+        stream_stalls: HashMap::new(),
+        runner_audio_tracks: settings
+            .obs_hosts
+            .get(host)
+            .and_then(|h| h.runner_audio_tracks.clone()),
+        scenes: HashMap::new(),
+        scene_collection: None,
+        scene_collections: vec![],
+        profile: None,
+        profiles: vec![],
+        last_connection_error,
+        public_stream_urls: get_public_stream_urls(settings, host),
+        outputs: vec![],
+        runner_filters: HashMap::new(),
+    }
+}
+
+/// The state of a dry run host, with the scenes of its last layout snapshot
+async fn get_dry_run_state(
+    settings: &Settings,
+    db: &dyn ProjectStore,
+    host: &str,
+) -> anyhow::Result<ObsHostState> {
+    let (scenes, _) = db.get_layout_snapshot(host).await?;
+    Ok(ObsHostState {
+        connected: true,
+        dry_run: true,
+        streaming: false,
+        replay_buffer: false,
+        virtual_cam: false,
+        stream_stalls: HashMap::new(),
+        runner_audio_tracks: settings
+            .obs_hosts
+            .get(host)
+            .and_then(|h| h.runner_audio_tracks.clone()),
+        scenes: scenes.into_iter().map(|s| (s.name.clone(), s)).collect(),
+        scene_collection: None,
+        scene_collections: vec![],
+        profile: None,
+        profiles: vec![],
+        last_connection_error: None,
+        public_stream_urls: get_public_stream_urls(settings, host),
+        outputs: vec![],
+        runner_filters: HashMap::new(),
+    })
+}
+
+/// Read the filters of the runner sources of a host
+async fn get_runner_filters(
+    obs: &obws::Client,
+) -> anyhow::Result<HashMap<String, Vec<ObsFilterState>>> {
+    let mut filters = HashMap::new();
+    for input in obs.inputs().list(Some("vlc_source")).await? {
+        let name = input.id.name;
+        if !name.starts_with(RUNNER_INPUT_PREFIX) {
+            continue;
+        }
+        let states = obs
+            .filters()
+            .list(SourceId::Name(&name))
+            .await?
+            .into_iter()
+            .map(|f| ObsFilterState {
+                name: f.name,
+                kind: f.kind,
+                enabled: f.enabled,
+            })
+            .collect();
+        filters.insert(name, states);
+    }
+    Ok(filters)
+}
+
+fn get_public_stream_urls(settings: &Settings, host: &str) -> Vec<PublicStreamUrl> {
+    settings
+        .obs_hosts
+        .get(host)
+        .map(|h| h.public_stream_urls.clone())
+        .unwrap_or_default()
+}
+
+async fn get_outputs(obs: &obws::Client) -> anyhow::Result<Vec<ObsOutputState>> {
+    Ok(obs
+        .outputs()
+        .list()
+        .await?
+        .into_iter()
+        .map(|o| ObsOutputState {
+            name: o.name,
+            kind: o.kind,
+            service: o.flags.service,
+            active: o.active,
+        })
+        .collect())
+}
+
+/// The names of the scenes of a host's layouts, in OBS order
+fn get_scene_names(layouts: &[ObsScene], from_snapshot: bool) -> Vec<ObsSceneName> {
+    layouts
+        .iter()
+        .map(|scene| ObsSceneName {
+            name: scene.name.clone(),
+            usable: !scene.sources.is_empty(),
+            from_snapshot,
+        })
+        .collect()
+}
+
+/// Read the status of an OBS client, without its scenes
+async fn get_obs_client_info(obs: &obws::Client) -> anyhow::Result<ObsHostState> {
+    let mut state = ObsHostState {
+        connected: true,
+        dry_run: false,
+        streaming: false,
+        replay_buffer: false,
+        virtual_cam: false,
+        stream_stalls: HashMap::new(),
+        runner_audio_tracks: None,
+        scenes: HashMap::new(),
+        scene_collection: None,
+        scene_collections: vec![],
+        profile: None,
+        profiles: vec![],
+        last_connection_error: None,
+        public_stream_urls: vec![],
+        outputs: vec![],
+        runner_filters: HashMap::new(),
+    };
+
+    // The status request fails if the replay buffer is disabled in the OBS output settings
+    state.replay_buffer = obs.replay_buffer().status().await.unwrap_or(false);
+    state.virtual_cam = obs.virtual_cam().status().await.unwrap_or(false);
+    state.outputs = get_outputs(obs).await.unwrap_or_default();
+
+    state.connected = true;
+    state.streaming = obs.streaming().status().await?.active;
+
+    let collections = obs.scene_collections().list().await?;
+    state.scene_collection = Some(collections.current);
+    state.scene_collections = collections.collections;
+    let profiles = obs.profiles().list().await?;
+    state.profile = Some(profiles.current);
+    state.profiles = profiles.profiles;
+
+    Ok(state)
+}
+
+/// Read the layouts of every scene of a host in OBS order, saving them as its layout snapshot
+/// if they differ from the `previous` capture
+async fn capture_layout_snapshot(
+    obs: &obws::Client,
+    naming: &SourceNaming,
+    db: &dyn ProjectStore,
+    host: &str,
+    previous: Option<&CapturedLayouts>,
+) -> anyhow::Result<CapturedLayouts> {
+    let scene_collection = obs.scene_collections().current().await?;
+    let scenes = obs.scenes().list().await?;
+    let current_scene = obs.scenes().current_program_scene().await?;
+    let mut layouts = vec![];
+    for scene in scenes.scenes {
+        let scene_items = obs.scene_items().list(SceneId::Name(&scene.name)).await?;
+        let mut layout = get_scene_layout(obs, naming, &scene.name, &scene_items).await?;
+        layout.active = scene.name == current_scene.id.name;
+        layouts.push(layout);
+    }
+
+    if previous.is_some_and(|p| p.layouts == layouts) {
+        log::debug!("Layouts of OBS host {} are unchanged", host);
+    } else if let Err(e) = db.save_layout_snapshot(host, &layouts).await {
+        log::warn!(
+            "Failed to save the layout snapshot of OBS host {}: {}",
+            host,
+            e
+        );
+    }
+    Ok(CapturedLayouts {
+        scene_collection,
+        program_scene: current_scene.id.name,
+        layouts,
+    })
+}
+
+/// Capture the layouts of a host again, keeping the previous capture if that fails
+async fn recapture_layouts(
+    obs: &obws::Client,
+    naming: &SourceNaming,
+    db: &dyn ProjectStore,
+    host: &str,
+    captured_layouts: &mut HashMap<String, CapturedLayouts>,
+) {
+    match capture_layout_snapshot(obs, naming, db, host, captured_layouts.get(host)).await {
+        Ok(captured) => {
+            captured_layouts.insert(host.to_owned(), captured);
+        }
+        Err(e) => log::warn!("Failed to capture the layouts of OBS host {}: {}", host, e),
+    }
+}
+
+/// Read the layouts of a host from OBS or from its last snapshot.
+///
+/// Without a source, the layouts last captured from a connected host are returned.
+async fn get_host_layouts(
+    host: &str,
+    source: Option<LayoutSource>,
+    obs: Option<&obws::Client>,
+    captured_layouts: &mut HashMap<String, CapturedLayouts>,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    naming: &SourceNaming,
+) -> anyhow::Result<HostLayouts> {
+    if !settings.obs_hosts.contains_key(host) {
+        return Err(Error::InvalidRequest(
+            "host".to_owned(),
+            format!("No OBS host named {}", host),
+        )
+        .into());
+    }
+
+    match (source, obs) {
+        (Some(LayoutSource::Live), None) => Err(Error::ObsUnavailable(host.to_owned()).into()),
+        (None, Some(_)) if captured_layouts.contains_key(host) => Ok(HostLayouts {
+            host: host.to_owned(),
+            from_snapshot: false,
+            captured_at: None,
+            layouts: captured_layouts[host].layouts.clone(),
+        }),
+        (Some(LayoutSource::Live) | None, Some(obs)) => {
+            let captured =
+                capture_layout_snapshot(obs, naming, db, host, captured_layouts.get(host)).await?;
+            let layouts = captured.layouts.clone();
+            captured_layouts.insert(host.to_owned(), captured);
+            Ok(HostLayouts {
+                host: host.to_owned(),
+                from_snapshot: false,
+                captured_at: None,
+                layouts,
+            })
+        }
+        (Some(LayoutSource::Snapshot), _) | (None, None) => {
+            let (layouts, captured_at) = db.get_layout_snapshot(host).await?;
+            if captured_at.is_none() {
+                return Err(anyhow!(
+                    "No layout snapshot of OBS host {} was captured yet",
+                    host
+                ));
+            }
+            Ok(HostLayouts {
+                host: host.to_owned(),
+                from_snapshot: true,
+                captured_at,
+                layouts,
+            })
+        }
+    }
+}
+
+/// Read the bounds of the stream views of a scene from its items
+async fn get_scene_layout(
+    obs: &obws::Client,
+    naming: &SourceNaming,
+    scene: &str,
+    scene_items: &[SceneItem],
+) -> anyhow::Result<ObsScene> {
+    let mut out_scene = ObsScene {
+        name: scene.to_owned(),
+        active: false,
+        sources: HashMap::new(),
+    };
+
+    for item in scene_items {
+        if let Some(idx) = naming.stream_view_slot(&item.source_name) {
+            let transform = obs
+                .scene_items()
+                .transform(SceneId::Name(scene), item.id)
+                .await?;
+
+            out_scene
+                .sources
+                .entry(idx)
+                .or_default()
+                .push(VlcSourceBounds {
+                    name: item.source_name.clone(),
+                    item_id: item.id,
+                    index: item.index,
+                    x: transform.position_x,
+                    y: transform.position_y,
+                    width: transform.bounds_width,
+                    height: transform.bounds_height,
+                    crop_left: transform.crop_left,
+                    crop_right: transform.crop_right,
+                    crop_top: transform.crop_top,
+                    crop_bottom: transform.crop_bottom,
+                    source_width: Some(transform.source_width).filter(|w| *w > 0.0),
+                    source_height: Some(transform.source_height).filter(|h| *h > 0.0),
+                });
+        }
+    }
+
+    Ok(out_scene)
+}
+
+static PLACEHOLDER_REGEX: OnceLock<Regex> = OnceLock::new();
+
+fn placeholder_regex() -> &'static Regex {
+    PLACEHOLDER_REGEX.get_or_init(|| Regex::new(r"\{([A-Za-z0-9_.]+)\}").unwrap())
+}
+
+/// Collect the values available to text binding templates for a stream
+async fn get_template_values(
+    state: &StreamState,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+) -> anyhow::Result<HashMap<String, String>> {
+    let event = db.get_event(state.event).await?;
+    let locale = i18n::locale(event.locale.as_deref());
+    let mut values = HashMap::new();
+
+    values.insert(
+        "event.estimate".to_owned(),
+        event
+            .estimate
+            .map(|e| locale.format_duration(e))
+            .unwrap_or_default(),
+    );
+    values.insert("event.name".to_owned(), event.name);
+    values.insert("event.game".to_owned(), event.game.unwrap_or_default());
+    values.insert(
+        "event.category".to_owned(),
+        event.category.unwrap_or_default(),
+    );
+    values.insert(
+        "stream.host".to_owned(),
+        event
+            .commentary_host
+            .clone()
+            .filter(|h| state.get_commentators().contains(h))
+            .unwrap_or_default(),
+    );
+    values.insert(
+        "stream.commentators".to_owned(),
+        state
+            .get_commentators()
+            .into_iter()
+            .filter(|c| !c.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    for (key, label) in locale.labels() {
+        values.insert(format!("label.{}", key), label);
+    }
+
+    for (idx, runner) in state.stream_runners.iter() {
+        let runner = db.get_runner(*runner).await?;
+        values.insert(
+            format!("runner.{}.lower_third", idx),
+            format_lower_third(&runner, settings),
+        );
+        for (platform, handle) in &runner.socials {
+            values.insert(
+                format!("runner.{}.social.{}", idx, platform),
+                handle.clone(),
+            );
+        }
+        values.insert(format!("runner.{}.name", idx), runner.name);
+        values.insert(
+            format!("runner.{}.location", idx),
+            runner.location.unwrap_or_default(),
+        );
+        values.insert(
+            format!("runner.{}.stream", idx),
+            runner.stream.unwrap_or_default(),
+        );
+    }
+
+    Ok(values)
+}
+
+/// Whether a placeholder is a runner's social handle, such as `runner.0.social.twitter`
+fn is_social_placeholder(key: &str) -> bool {
+    let mut parts = key.split('.');
+    parts.next() == Some("runner")
+        && parts.next().is_some_and(|idx| idx.parse::<i64>().is_ok())
+        && parts.next() == Some("social")
+        && parts.next().is_some()
+        && parts.next().is_none()
+}
+
+/// A runner's lower third, such as `Name — @handle`, or only their name if they have no handle
+fn format_lower_third(runner: &Runner, settings: &Settings) -> String {
+    let platforms = settings
+        .lower_third_platforms
+        .clone()
+        .unwrap_or_else(|| DEFAULT_LOWER_THIRD_PLATFORMS.map(|p| p.to_owned()).to_vec());
+    match runner.preferred_social(&platforms) {
+        Some(handle) => settings
+            .lower_third_format
+            .as_deref()
+            .unwrap_or(DEFAULT_LOWER_THIRD_FORMAT)
+            .replace("{name}", &runner.name)
+            .replace("{handle}", handle),
+        None => runner.name.clone(),
+    }
+}
+
+/// Fill the placeholders of a template, replacing unknown placeholders with nothing
+fn render_template(
+    template: &str,
+    values: &HashMap<String, String>,
+    warned: &mut HashSet<String>,
+) -> String {
+    placeholder_regex()
+        .replace_all(template, |caps: &regex::Captures| {
+            let key = &caps[1];
+            match values.get(key) {
+                Some(value) => value.clone(),
+                // Runners without a handle on a platform simply show nothing
+                None if is_social_placeholder(key) => String::new(),
+                None => {
+                    if warned.insert(key.to_owned()) {
+                        log::warn!("Unknown text binding placeholder '{{{}}}'", key);
+                    }
+                    String::new()
+                }
+            }
+        })
+        .into_owned()
+}
+
+/// Render the text bindings of a stream's host and apply any changed text to OBS
+async fn update_text_bindings(
+    state: &StreamState,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+    obs: &obws::Client,
+    warned: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    let Some(bindings) = settings
+        .obs_hosts
+        .get(&state.obs_host)
+        .and_then(|h| h.text_bindings.as_ref())
+    else {
+        return Ok(());
+    };
+
+    let mut values = get_template_values(state, db, settings).await?;
+    if let Ok(donations) = send_message!(directory.tiltify_actor, TiltifyCommand, GetDonations) {
+        values.insert(
+            "donation.total".to_owned(),
+            donations.total_formatted.unwrap_or_default(),
+        );
+    }
+
+    for (input, template) in bindings {
+        let text = render_template(template, &values, warned);
+        let current = obs
+            .inputs()
+            .settings::<FreetypeText>(InputId::Name(input))
+            .await
+            .map_err(|e| anyhow!("Failed to read text source {}: {:?}", input, e))?;
+
+        if current.settings.text != text {
+            log::debug!("Updating text source {}", input);
+            obs.inputs()
+                .set_settings(SetSettings {
+                    input: InputId::Name(input),
+                    settings: &SpecificFreetype { text: &text },
+                    overlay: Some(true),
+                })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Changes to run statistic sources, limited to one per source every `RUN_STATS_MIN_INTERVAL`
+#[derive(Default)]
+struct RunStatsThrottle {
+    /// When each source was last changed and its text, by host and source
+    sent: HashMap<(String, String), (Instant, String)>,
+    /// Text waiting for its source's interval to pass, by host and source
+    pending: HashMap<(String, String), String>,
+}
+
+impl RunStatsThrottle {
+    /// Change the text of a source now if allowed, otherwise once its interval has passed
+    async fn set(&mut self, obs: &obws::Client, host: &str, source: &str, text: String) {
+        let key = (host.to_owned(), source.to_owned());
+        match self.sent.get(&key) {
+            Some((_, sent)) if *sent == text => {
+                self.pending.remove(&key);
+            }
+            Some((at, _)) if at.elapsed() < RUN_STATS_MIN_INTERVAL => {
+                self.pending.insert(key, text);
+            }
+            _ => {
+                self.pending.remove(&key);
+                self.send(obs, key, text).await;
+            }
+        }
+    }
+
+    /// Apply the pending changes whose interval has passed
+    async fn flush(&mut self, host_map: &HostMap) {
+        let due: Vec<_> = self
+            .pending
+            .keys()
+            .filter(|key| {
+                self.sent
+                    .get(*key)
+                    .is_none_or(|(at, _)| at.elapsed() >= RUN_STATS_MIN_INTERVAL)
+            })
+            .cloned()
+            .collect();
+        for key in due {
+            let Some(text) = self.pending.remove(&key) else {
+                continue;
+            };
+            if let Some(obs) = host_map.get(&key.0) {
+                self.send(obs, key, text).await;
+            }
+        }
+    }
+
+    async fn send(&mut self, obs: &obws::Client, key: (String, String), text: String) {
+        let res = obs
+            .inputs()
+            .set_settings(SetSettings {
+                input: InputId::Name(&key.1),
+                settings: &SpecificFreetype { text: &text },
+                overlay: Some(true),
+            })
+            .await;
+        match res {
+            Ok(()) => {
+                self.sent.insert(key, (Instant::now(), text));
+            }
+            Err(e) => log::warn!("Failed to update run statistic source {}: {:?}", key.1, e),
+        }
+    }
+}
+
+/// Show the run statistics of a runner in the statistic sources of their slot
+/// that are in the scene on air
+async fn update_run_stats(
+    obs: &obws::Client,
+    stream: &StreamState,
+    runner: i64,
+    db: &dyn ProjectStore,
+    naming: &SourceNaming,
+    throttle: &mut RunStatsThrottle,
+) -> anyhow::Result<()> {
+    let Some(slot) = stream
+        .stream_runners
+        .iter()
+        .find_map(|(slot, r)| (*r == runner).then_some(*slot))
+    else {
+        return Ok(());
+    };
+
+    let scene = obs.scenes().current_program_scene().await?.id.name;
+    let items = obs.scene_items().list(SceneId::Name(&scene)).await?;
+    let sources = naming.run_stats(slot);
+    if !sources
+        .iter()
+        .any(|s| items.iter().any(|i| &i.source_name == s))
+    {
+        return Ok(());
+    }
+
+    let (delta, pb, best_possible) = match db.get_runner_run_data(runner).await {
+        Ok(run) => (run.delta, run.pb, run.best_possible),
+        Err(_) => (None, None, None),
+    };
+    let texts = [
+        format_delta(delta),
+        format_stat_time(pb),
+        format_stat_time(best_possible),
+    ];
+    for (source, text) in sources.iter().zip(texts) {
+        if items.iter().any(|i| &i.source_name == source) {
+            throttle.set(obs, &stream.obs_host, source, text).await;
+        }
+    }
+    Ok(())
+}
+
+/// Keep a host connected, retrying with exponential backoff.
+///
+/// The loop waits for a wake signal before each connection attempt, which is
+/// sent on startup, when the connection is lost, and on a forced reconnect.
+/// Failed attempts are reported to the actor as well.
+async fn run_host_connector(
+    host: String,
+    settings: Arc<Settings>,
+    mut wake_rx: UnboundedReceiver<()>,
+    connected_tx: UnboundedSender<(String, Result<obws::Client, ObsConnectionError>)>,
+) {
+    while wake_rx.recv().await.is_some() {
+        let mut delay = RECONNECT_MIN_DELAY;
+        loop {
+            match connect_client_for_host(&host, &settings).await {
+                Ok(obs) => {
+                    // Ignore wakes that were queued while connecting
+                    while wake_rx.try_recv().is_ok() {}
+                    if connected_tx.send((host.clone(), Ok(obs))).is_err() {
+                        return;
+                    }
+                    break;
+                }
+                Err(e) => {
+                    let error = ObsConnectionError::from_error(&e);
+                    let auth_failed = error.kind == ObsConnectionErrorKind::AuthFailed;
+                    if auth_failed {
+                        delay = RECONNECT_AUTH_FAILED_DELAY;
+                    }
+                    log::warn!(
+                        "Failed to connect to OBS host {}, retrying in {}s: {}",
+                        host,
+                        delay.as_secs(),
+                        error.message
+                    );
+                    if connected_tx.send((host.clone(), Err(error))).is_err() {
+                        return;
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        wake = wake_rx.recv() => if wake.is_none() { return; },
+                    }
+                    if !auth_failed {
+                        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Attemt to connect to an OBS instance
+async fn connect_client_for_host(host: &str, settings: &Settings) -> anyhow::Result<obws::Client> {
+    let config = settings
+        .obs_hosts
+        .get(host)
+        .ok_or_else(|| anyhow!(format!("No OBS host configuration found for host {}", host)))?;
+
+    let obs_config = obws::client::ConnectConfig {
+        host: config.obs_ip.to_owned(),
+        port: config.obs_port.to_owned(),
+        password: config.obs_password.to_owned(),
+        event_subscriptions: Some(EventSubscription::NONE),
+        broadcast_capacity: None,
+        connect_timeout: Duration::from_secs(30),
+    };
+
+    let obs = obws::Client::connect_with_config(obs_config).await?;
+
+    let obs_version = obs.general().version().await?;
+    log::info!(
+        "Connected to OBS host {}, version {}, websocket {}, running on {} ({})",
+        host,
+        obs_version.obs_version,
+        obs_version.obs_web_socket_version,
+        obs_version.platform,
+        obs_version.platform_description
+    );
+
+    Ok(obs)
+}
+
+/// Apply audio monitoring and track routing to a runner source if it has drifted
+async fn apply_audio_routing(
+    obs: &obws::Client,
+    input: InputId<'_>,
+    monitor: AudioMonitorType,
+    tracks: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let monitor_type = match monitor {
+        AudioMonitorType::None => MonitorType::None,
+        AudioMonitorType::MonitorOnly => MonitorType::MonitorOnly,
+        AudioMonitorType::MonitorAndOutput => MonitorType::MonitorAndOutput,
+    };
+
+    if obs.inputs().audio_monitor_type(input).await? != monitor_type {
+        log::debug!("Setting monitor type of {:?} to {:?}", input, monitor_type);
+        obs.inputs()
+            .set_audio_monitor_type(input, monitor_type)
+            .await?;
+    }
+
+    if let Some(tracks) = tracks {
+        let mut mask = [false; 6];
+        for track in tracks {
+            match track {
+                1..=6 => mask[*track as usize - 1] = true,
+                _ => log::warn!("Ignoring invalid audio track {}", track),
+            }
+        }
+
+        if obs.inputs().audio_tracks(input).await? != mask {
+            log::debug!("Setting audio tracks of {:?} to {:?}", input, tracks);
+            obs.inputs().set_audio_tracks(input, mask.map(Some)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Free recording disk space below which the pre-flight check fails, in MB
+const PREFLIGHT_MIN_DISK_SPACE_MB: f64 = 10_000.0;
+
+/// Check that a host is ready to go live, without changing anything.
+///
+/// Each check is reported separately, a check that cannot be run counts as failed.
+async fn run_preflight(
+    host: &str,
+    event: Option<i64>,
+    obs: Option<&obws::Client>,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    naming: &SourceNaming,
+) -> anyhow::Result<PreflightReport> {
+    let host_settings = settings
+        .obs_hosts
+        .get(host)
+        .ok_or_else(|| anyhow!("No OBS host configuration found for host {}", host))?;
+
+    let event = match event {
+        Some(event) => Some(event),
+        None => db.get_event_by_obs_host(host).await.ok(),
+    };
+    let mut report = PreflightReport {
+        host: host.to_owned(),
+        event,
+        checks: vec![],
+    };
+
+    report.check(
+        "OBS connected",
+        true,
+        match obs {
+            Some(_) => Ok("Connected".to_owned()),
+            None => Err(Error::ObsUnavailable(host.to_owned()).to_string()),
+        },
+    );
+
+    let stream = match event {
+        Some(event) => db.get_stream(event).await.ok(),
+        None => None,
+    };
+    let event = match event {
+        Some(event) => Some(db.get_event(event).await?),
+        None => None,
+    };
+
+    // Layout, from the last snapshot if OBS is offline
+    let layout = match (obs, &event, &stream) {
+        (_, None, _) => Err("No event is streamed on this host".to_owned()),
+        (_, Some(event), None) => Err(format!("{} has no stream", event.name)),
+        (None, Some(event), Some(stream)) => match db.get_layout_snapshot(host).await {
+            Ok((_, None)) => {
+                Err("OBS is not connected and no layout snapshot was captured".to_owned())
+            }
+            Ok((layouts, Some(_))) => match find_snapshot_layout(event, stream, &layouts) {
+                Some(layout) => Ok(format!("Using {} from the layout snapshot", layout.name)),
+                None => Err(format!(
+                    "No layout found for {} runners in the layout snapshot",
+                    stream.stream_runners.len()
+                )),
+            },
+            Err(e) => Err(e.to_string()),
+        },
+        (Some(obs), Some(event), Some(stream)) => {
+            let layout = async {
+                let scenes = obs.scenes().list().await?;
+                find_layout(obs, naming, event, stream, &scenes).await
+            }
+            .await;
+            match layout {
+                Ok(Some((layout, _))) => Ok(format!("Using {}", layout.name)),
+                Ok(None) => Err(format!(
+                    "No layout found for {} runners",
+                    stream.stream_runners.len()
+                )),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    };
+    report.check_with_source(
+        "Layout scene",
+        true,
+        layout,
+        obs.is_none() && stream.is_some(),
+    );
+
+    // Runner stream URLs
+    let runners = match &stream {
+        Some(stream) => {
+            let mut missing = vec![];
+            for runner in stream.stream_runners.values() {
+                let runner = db.get_runner(*runner).await?;
+                if runner.cached_stream_url.is_none() {
+                    missing.push(runner.name);
+                }
+            }
+            if missing.is_empty() {
+                Ok(format!(
+                    "{} runner streams resolved",
+                    stream.stream_runners.len()
+                ))
+            } else {
+                Err(format!("No stream URL for {}", missing.join(", ")))
+            }
+        }
+        None => Err("No stream to check".to_owned()),
+    };
+    report.check("Runner streams", true, runners);
+
+    // Commentary audio
+    let commentary = match (&host_settings.commentary_input, obs) {
+        (None, _) => Ok("No 'commentary_input' configured, skipped".to_owned()),
+        (Some(_), None) => Err("OBS is not connected".to_owned()),
+        (Some(input), Some(obs)) => match obs.inputs().muted(InputId::Name(input)).await {
+            Ok(false) => Ok(format!("{} is unmuted", input)),
+            Ok(true) => Err(format!("{} is muted", input)),
+            Err(e) => Err(format!("Failed to read {}: {}", input, e)),
+        },
+    };
+    report.check("Commentary audio", true, commentary);
+
+    // Stream service
+    let service = match obs {
+        None => Err("OBS is not connected".to_owned()),
+        Some(obs) => match obs
+            .config()
+            .stream_service_settings::<serde_json::Map<String, serde_json::Value>>()
+            .await
+        {
+            Ok(service) => {
+                let configured = |key: &str| {
+                    service
+                        .settings
+                        .get(key)
+                        .and_then(|v| v.as_str())
+                        .is_some_and(|v| !v.is_empty())
+                };
+                if configured("key") || configured("server") {
+                    Ok(format!("Using {}", service.r#type))
+                } else {
+                    Err("No stream key or server is configured".to_owned())
+                }
+            }
+            Err(e) => Err(e.to_string()),
+        },
+    };
+    report.check("Stream service", true, service);
+
+    // Restream outputs, which are expected for every public URL past the first
+    let expected = host_settings.public_stream_urls.len().saturating_sub(1);
+    let outputs = match obs {
+        None => Err("OBS is not connected".to_owned()),
+        Some(obs) => match (get_outputs(obs).await, obs.streaming().status().await) {
+            (Ok(outputs), Ok(streaming)) => {
+                let secondary: Vec<_> =
+                    outputs.iter().filter(|o| o.is_secondary_stream()).collect();
+                let inactive: Vec<_> = secondary
+                    .iter()
+                    .filter(|o| !o.active)
+                    .map(|o| o.name.as_str())
+                    .collect();
+                if secondary.len() < expected {
+                    Err(format!(
+                        "{} secondary outputs for {} extra public URLs",
+                        secondary.len(),
+                        expected
+                    ))
+                } else if streaming.active && !inactive.is_empty() {
+                    Err(format!("{} not running", inactive.join(", ")))
+                } else if secondary.is_empty() {
+                    Ok("No secondary outputs".to_owned())
+                } else if streaming.active {
+                    Ok(format!("{} secondary outputs running", secondary.len()))
+                } else {
+                    // Restream outputs usually start along with the stream
+                    Ok(format!(
+                        "{} secondary outputs, {} running",
+                        secondary.len(),
+                        secondary.len() - inactive.len()
+                    ))
+                }
+            }
+            (Err(e), _) => Err(e.to_string()),
+            (_, Err(e)) => Err(e.to_string()),
+        },
+    };
+    report.check("Stream outputs", host_settings.require_all_outputs, outputs);
+
+    // Recording disk space
+    let disk = match obs {
+        None => Err("OBS is not connected".to_owned()),
+        Some(obs) => match obs.general().stats().await {
+            Ok(stats) if stats.available_disk_space >= PREFLIGHT_MIN_DISK_SPACE_MB => Ok(format!(
+                "{:.1} GB free",
+                stats.available_disk_space / 1000.0
+            )),
+            Ok(stats) => Err(format!(
+                "Only {:.1} GB free",
+                stats.available_disk_space / 1000.0
+            )),
+            Err(e) => Err(e.to_string()),
+        },
+    };
+    report.check("Recording disk space", false, disk);
+
+    // Event timing
+    let timing = match &event {
+        None => Err("No event is streamed on this host".to_owned()),
+        Some(event) if event.timer_end_time.is_some() => {
+            Err("The timer has already been stopped".to_owned())
+        }
+        Some(event) if event.timer_start_time.is_some() => Ok("The timer is running".to_owned()),
+        Some(event) if event.event_start_time.is_some() => Ok("Start time is set".to_owned()),
+        Some(_) => Err("No start time is set".to_owned()),
+    };
+    report.check("Event timing", false, timing);
+
+    Ok(report)
+}
+
+/// Save the replay buffer, returning the last replay from before the save
+async fn save_replay_buffer(obs: &obws::Client, host: &str) -> anyhow::Result<Option<String>> {
+    match obs.replay_buffer().status().await {
+        Ok(true) => {}
+        Ok(false) => Err(Error::ReplayBufferNotRunning(host.to_owned()))?,
+        Err(_) => Err(Error::ReplayBufferUnavailable(host.to_owned()))?,
+    }
+
+    let previous = obs.replay_buffer().last_replay().await.ok();
+    obs.replay_buffer().save().await?;
+    Ok(previous)
+}
+
+/// Poll until the last replay of a host changes, then reply with the new file
+async fn wait_for_saved_replay(
+    obs: Arc<obws::Client>,
+    host: String,
+    previous: Option<String>,
+    webhook: Option<String>,
+    rto: Rto<PathBuf>,
+) {
+    for _ in 0..20 {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        if let Ok(last) = obs.replay_buffer().last_replay().await {
+            if Some(&last) != previous.as_ref() {
+                log::info!("Saved replay buffer for host {} to {}", host, last);
+                let path = PathBuf::from(last);
+                if let Some(url) = webhook {
+                    tokio::spawn(send_replay_webhook(url, host, path.clone()));
+                }
+                rto.reply(Ok(path));
+                return;
+            }
+        }
+    }
+
+    rto.reply(Err(anyhow!(
+        "OBS host {} did not report a saved replay in time",
+        host
+    )));
+}
+
+/// Start or stop the replay buffer
+async fn set_replay_buffer_enabled(
+    obs: &obws::Client,
+    host: &str,
+    enabled: bool,
+) -> anyhow::Result<()> {
+    let running = obs
+        .replay_buffer()
+        .status()
+        .await
+        .map_err(|_| Error::ReplayBufferUnavailable(host.to_owned()))?;
+
+    match (running, enabled) {
+        (false, true) => obs.replay_buffer().start().await?,
+        (true, false) => obs.replay_buffer().stop().await?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Check the runner sources on the program feed for playback that stopped progressing.
+///
+/// A stalled source is first reloaded with the same URL, then its URL is acquired again
+/// through the runner actor. If it is still stalled after that, Discord is warned.
+async fn check_stalled_feeds(
+    host_map: &HostMap,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+    feeds: &mut HashMap<(String, i64), FeedProgress>,
+    stall_counts: &mut HashMap<String, HashMap<i64, u32>>,
+) -> anyhow::Result<()> {
+    let timeout = Duration::from_secs(
+        settings
+            .stall_timeout_seconds
+            .unwrap_or(DEFAULT_STALL_TIMEOUT_SECS),
+    );
+    let mut live = HashSet::new();
+    let mut stalled = false;
+
+    for event in db.get_streamed_events().await? {
+        let stream = db.get_stream(event).await?;
+        if !stream.active {
+            continue;
+        }
+        let Some(obs) = host_map.get(&stream.obs_host) else {
+            continue;
+        };
+
+        for runner in stream.stream_runners.values() {
+            let name = db.get_name_for_runner(*runner).await?;
+            let source = runner_input_name(&name);
+            let input = InputId::Name(&source);
+
+            if !obs
+                .sources()
+                .active(input.into())
+                .await
+                .is_ok_and(|s| s.active)
+            {
+                continue;
+            }
+            let Ok(status) = obs.media_inputs().status(input).await else {
+                continue;
+            };
+            // Paused sources are stopped on purpose, and progress can't be measured without a cursor
+            if status.state == MediaState::Paused
+                || (status.state == MediaState::Playing && status.cursor.is_none())
+            {
+                continue;
+            }
+
+            let cursor = status.cursor.map(|c| c.whole_milliseconds());
+            let key = (stream.obs_host.clone(), *runner);
+            live.insert(key.clone());
+            let feed = feeds.entry(key).or_insert_with(|| FeedProgress {
+                cursor,
+                last_progress: Instant::now(),
+                attempts: 0,
+            });
+
+            if cursor != feed.cursor {
+                feed.cursor = cursor;
+                feed.last_progress = Instant::now();
+                feed.attempts = 0;
+                continue;
+            }
+            if feed.last_progress.elapsed() < timeout {
+                continue;
+            }
+
+            // Give each recovery attempt a full timeout to take effect
+            feed.last_progress = Instant::now();
+            feed.attempts += 1;
+            *stall_counts
+                .entry(stream.obs_host.clone())
+                .or_default()
+                .entry(*runner)
+                .or_default() += 1;
+            stalled = true;
+
+            log::warn!(
+                "Stream of {} stalled on OBS host {} ({:?}), attempt {}",
+                name,
+                stream.obs_host,
+                status.state,
+                feed.attempts
+            );
+            match feed.attempts {
+                1 => {
+                    if let Err(e) = reload_vlc_source(obs, input).await {
+                        log::warn!("Failed to reload the stream of {}: {}", name, e);
+                    }
+                }
+                2 => {
+                    tokio::spawn(reacquire_stream(
+                        directory.clone(),
+                        *runner,
+                        event,
+                        stream.obs_host.clone(),
+                    ));
+                }
+                3 => directory.discord_actor.send(DiscordCommand::Notify(format!(
+                    "The stream of {} on {} is still stalled after two automatic refreshes",
+                    name, stream.obs_host
+                ))),
+                _ => {}
+            }
+        }
+    }
+
+    feeds.retain(|key, _| live.contains(key));
+    if stalled {
+        directory.web_actor.send(WebCommand::SendStateUpdate);
+    }
+
+    Ok(())
+}
+
+/// Delay a runner's feed by raising the network caching of its VLC source.
+///
+/// VLC holds back playback until its cache is filled, so adding the offset
+/// to the default caching delays that source only. Changing the caching
+/// restarts the source, so it is only set when it differs.
+async fn apply_sync_offset(
+    obs: &obws::Client,
+    input: InputId<'_>,
+    offset_ms: u32,
+) -> anyhow::Result<()> {
+    let caching = DEFAULT_NETWORK_CACHING_MS + offset_ms;
+    let current = obs.inputs().settings::<VlcCaching>(input).await?.settings;
+    if current.network_caching != caching {
+        log::debug!("Setting network caching of {:?} to {} ms", input, caching);
+        obs.inputs()
+            .set_settings(SetSettings {
+                input,
+                settings: &VlcCaching {
+                    network_caching: caching,
+                },
+                overlay: Some(true),
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Create the filters a source is missing. Existing filters are left alone, so changes made
+/// to them in OBS are kept
+async fn ensure_filters(
+    obs: &obws::Client,
+    source: &str,
+    filters: &[SourceFilter],
+) -> anyhow::Result<()> {
+    let existing = obs.filters().list(SourceId::Name(source)).await?;
+    for filter in filters {
+        if existing.iter().any(|f| f.name == filter.name) {
+            continue;
+        }
+        log::debug!("Creating filter {} on {}", filter.name, source);
+        obs.filters()
+            .create(filters::Create {
+                source: SourceId::Name(source),
+                filter: &filter.name,
+                kind: &filter.kind,
+                settings: Some(&filter.settings).filter(|s| !s.is_null()),
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+/// Show or hide a filter of a source
+async fn set_source_filter_enabled(
+    obs: &obws::Client,
+    source: &str,
+    filter: &str,
+    enabled: bool,
+) -> anyhow::Result<()> {
+    let existing = obs
+        .filters()
+        .list(SourceId::Name(source))
+        .await
+        .map_err(|_| {
+            Error::InvalidRequest("source".to_owned(), format!("no source named {}", source))
+        })?;
+    if !existing.iter().any(|f| f.name == filter) {
+        return Err(Error::InvalidRequest(
+            "filter".to_owned(),
+            format!("{} has no filter named {}", source, filter),
+        )
+        .into());
+    }
+    obs.filters()
+        .set_enabled(filters::SetEnabled {
+            source: SourceId::Name(source),
+            filter,
+            enabled,
+        })
+        .await?;
+    Ok(())
+}
+
+/// Make a VLC source reconnect by setting its playlist again
+async fn reload_vlc_source(obs: &obws::Client, input: InputId<'_>) -> anyhow::Result<()> {
+    let settings = obs.inputs().settings::<VLC>(input).await?.settings;
+    obs.inputs()
+        .set_settings(SetSettings {
+            input,
+            settings: &settings,
+            overlay: Some(true),
+        })
+        .await?;
+    Ok(())
+}
+
+/// Acquire a new stream URL for a runner and reload the stream showing them
+async fn reacquire_stream(directory: Directory, runner: i64, event: i64, host: String) {
+    let host = Some(host);
+    let res = match send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        RefreshStream,
+        runner,
+        host
+    ) {
+        Ok(_) => send_message!(directory.stream_actor, StreamRequest, Reload, event),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = res {
+        log::warn!("Failed to reacquire the stream of runner {}: {}", runner, e);
+    }
+}
+
+/// Open a projector of the program, preview, or a scene.
+///
+/// Fails with the available monitors if `monitor` does not exist.
+async fn open_projector(
+    obs: &obws::Client,
+    host: &str,
+    target: &str,
+    monitor: Option<u32>,
+) -> anyhow::Result<()> {
+    let location = match monitor {
+        Some(monitor) => {
+            let monitors = obs.ui().list_monitors().await?;
+            if !monitors.iter().any(|m| m.index == monitor) {
+                let available: Vec<String> = monitors
+                    .iter()
+                    .map(|m| {
+                        format!(
+                            "{}: {} ({}x{})",
+                            m.index, m.name, m.size.width, m.size.height
+                        )
+                    })
+                    .collect();
+                return Err(anyhow!(
+                    "OBS host {} has no monitor {}, available monitors are {}",
+                    host,
+                    monitor,
+                    available.join(", ")
+                ));
+            }
+            Location::MonitorIndex(monitor as i32)
+        }
+        // Monitor index -1 opens a windowed projector
+        None => Location::MonitorIndex(-1),
+    };
+
+    log::info!("Opening {} projector on OBS host {}", target, host);
+    match target.to_lowercase().as_str() {
+        "program" => {
+            obs.ui()
+                .open_video_mix_projector(OpenVideoMixProjector {
+                    r#type: VideoMixType::Program,
+                    location: Some(location),
+                })
+                .await?
+        }
+        "preview" => {
+            obs.ui()
+                .open_video_mix_projector(OpenVideoMixProjector {
+                    r#type: VideoMixType::Preview,
+                    location: Some(location),
+                })
+                .await?
+        }
+        _ => {
+            let scenes = obs.scenes().list().await?.scenes;
+            if !scenes.iter().any(|s| s.name == target) {
+                return Err(anyhow!(
+                    "OBS host {} has no scene named {}, use \"program\", \"preview\" or a scene name",
+                    host,
+                    target
+                ));
+            }
+            obs.ui()
+                .open_source_projector(OpenSourceProjector {
+                    source: SourceId::Name(target),
+                    location: Some(location),
+                })
+                .await?
+        }
+    }
+
+    Ok(())
+}
+
+async fn set_virtual_cam_enabled(obs: &obws::Client, enabled: bool) -> anyhow::Result<()> {
+    match (obs.virtual_cam().status().await?, enabled) {
+        (false, true) => obs.virtual_cam().start().await?,
+        (true, false) => obs.virtual_cam().stop().await?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Show a scene, going through the preview and a transition in studio mode
+async fn set_program_scene(
+    obs: &obws::Client,
+    settings: &Settings,
+    scene: &str,
+) -> anyhow::Result<()> {
+    let scene_id = SceneId::Name(scene);
+    if obs.ui().studio_mode_enabled().await? {
+        obs.scenes().set_current_preview_scene(scene_id).await?;
+        do_transition(obs, settings.obs_transition.as_ref()).await?;
+    } else {
+        obs.scenes().set_current_program_scene(scene_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Switch the scene collection of a host if it is not already current,
+/// returning whether it was switched.
+///
+/// Switching briefly drops the program output, so it is refused while streaming.
+async fn set_scene_collection(
+    obs: &obws::Client,
+    host: &str,
+    collection: &str,
+) -> anyhow::Result<bool> {
+    let collections = obs.scene_collections().list().await?;
+    if collections.current == collection {
+        return Ok(false);
+    }
+
+    if !collections.collections.iter().any(|c| c == collection) {
+        return Err(anyhow!(
+            "OBS host {} has no scene collection named {}",
+            host,
+            collection
+        ));
+    }
+
+    if obs.streaming().status().await?.active {
+        log::warn!(
+            "Not switching OBS host {} to scene collection {} while it is live",
+            host,
+            collection
+        );
+        return Err(anyhow!(
+            "OBS host {} is live, switching to scene collection {} would drop the stream output",
+            host,
+            collection
+        ));
+    }
+
+    log::info!(
+        "Switching OBS host {} to scene collection {}",
+        host,
+        collection
+    );
+    obs.scene_collections().set_current(collection).await?;
+    Ok(true)
+}
+
+/// Switch the profile of a host if it is not already current.
+///
+/// Profiles hold the output settings, so switching is refused while streaming.
+async fn set_profile(obs: &obws::Client, host: &str, profile: &str) -> anyhow::Result<()> {
+    let profiles = obs.profiles().list().await?;
+    if profiles.current == profile {
+        return Ok(());
+    }
+
+    if !profiles.profiles.iter().any(|p| p == profile) {
+        return Err(anyhow!(
+            "OBS host {} has no profile named {}",
+            host,
+            profile
+        ));
+    }
+
+    if obs.streaming().status().await?.active {
+        log::warn!(
+            "Not switching OBS host {} to profile {} while it is live",
+            host,
+            profile
+        );
+        return Err(anyhow!(
+            "OBS host {} is live, its profile cannot be switched to {}",
+            host,
+            profile
+        ));
+    }
+
+    log::info!("Switching OBS host {} to profile {}", host, profile);
+    obs.profiles().set_current(profile).await?;
+    Ok(())
+}
+
+/// Notify a webhook of a saved replay
+async fn send_replay_webhook(url: String, host: String, path: PathBuf) {
+    let body = serde_json::json!({
+        "event": "replay_saved",
+        "host": host,
+        "path": path,
+    });
+
+    if let Err(e) = reqwest::Client::new().post(&url).json(&body).send().await {
+        log::warn!("Failed to send replay webhook to {}: {}", url, e);
+    }
+}
+
+/// Move every scene item of a source in a scene to a layer index
+async fn set_source_index(
+    obs: &obws::Client,
+    scene: &str,
+    source: &str,
+    index: u32,
+) -> anyhow::Result<()> {
+    let scene_id = SceneId::Name(scene);
+    let items: Vec<_> = obs
+        .scene_items()
+        .list(scene_id)
+        .await?
+        .into_iter()
+        .filter(|i| i.source_name == source)
+        .collect();
+
+    if items.is_empty() {
+        return Err(anyhow!(
+            "Scene {} has no items for source {}",
+            scene,
+            source
+        ));
+    }
+
+    for item in items {
+        obs.scene_items()
+            .set_index(SetIndex {
+                scene: scene_id,
+                item_id: item.id,
+                index,
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The input names of the runners, on stage or on deck, of the streams on a host.
+///
+/// The stream of `except` is left out, and with `active_only` so are inactive streams.
+async fn runner_inputs_in_use(
+    db: &dyn ProjectStore,
+    host: &str,
+    except: Option<i64>,
+    active_only: bool,
+) -> anyhow::Result<HashSet<String>> {
+    let mut inputs = HashSet::new();
+    for event in db.get_streamed_events().await? {
+        if except == Some(event) {
+            continue;
+        }
+        let stream = db.get_stream(event).await?;
+        if stream.obs_host != host || (active_only && !stream.active) {
+            continue;
+        }
+        let on_deck = stream.on_deck_runners.iter().map(|r| &r.runner);
+        for runner in stream.stream_runners.values().chain(on_deck) {
+            inputs.insert(runner_input_name(&db.get_name_for_runner(*runner).await?));
+        }
+    }
+    Ok(inputs)
+}
+
+/// Remove the runner sources on the host of a stream,
+/// keeping those of runners shown by other active streams on the host
+async fn purge_runner_sources(
+    obs: &obws::Client,
+    db: &dyn ProjectStore,
+    stream: &StreamState,
+) -> anyhow::Result<usize> {
+    let kept = runner_inputs_in_use(db, &stream.obs_host, Some(stream.event), true).await?;
+
+    let mut removed = 0;
+    for input in obs.inputs().list(Some("vlc_source")).await? {
+        let name = &input.id.name;
+        if name.starts_with(RUNNER_INPUT_PREFIX) && !kept.contains(name) {
+            log::info!("Removing source {} from {}", name, stream.obs_host);
+            obs.inputs().remove(InputId::Name(name)).await?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Set the URLs of the sources of a browser source group on a host.
+///
+/// Sources using `{runner_index}` are set for each runner slot of the host's active stream.
+async fn set_browser_source_group(
+    obs: &obws::Client,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    host: &str,
+    group: &str,
+    params: HashMap<String, String>,
+) -> anyhow::Result<()> {
+    let template = settings.browser_source_groups.get(group).ok_or_else(|| {
+        Error::InvalidRequest(
+            "group".to_owned(),
+            format!("no browser source group named {}", group),
+        )
+    })?;
+
+    let mut slots: Vec<i64> = match db.get_event_by_obs_host(host).await {
+        Ok(event) => db
+            .get_stream(event)
+            .await?
+            .stream_runners
+            .into_keys()
+            .collect(),
+        Err(_) => vec![],
+    };
+    slots.sort();
+    if slots.is_empty() && template.sources.iter().any(|s| s.is_per_runner()) {
+        log::warn!(
+            "OBS host {} has no runners on air, skipping the per-runner sources of {}",
+            host,
+            group
+        );
+    }
+
+    let mut warned = HashSet::new();
+    for source in &template.sources {
+        let indices = if source.is_per_runner() {
+            slots.iter().map(|s| Some(*s)).collect()
+        } else {
+            vec![None]
+        };
+
+        for index in indices {
+            let mut values = params.clone();
+            if let Some(index) = index {
+                values.insert("runner_index".to_owned(), index.to_string());
+            }
+            let input = render_template(&source.input, &values, &mut warned);
+            let url = format!(
+                "{}{}",
+                template.base_url,
+                render_template(&source.url, &values, &mut warned)
+            );
+
+            log::debug!("Setting browser source {} to {}", input, url);
+            obs.inputs()
+                .set_settings(SetSettings {
+                    input: InputId::Name(&input),
+                    settings: &BrowserSourceUrl { url: &url },
+                    overlay: Some(true),
+                })
+                .await
+                .map_err(|e| anyhow!("Failed to set browser source {}: {:?}", input, e))?;
+        }
+    }
+
+    if obs.ui().studio_mode_enabled().await? {
+        do_transition(obs, settings.obs_transition.as_ref()).await?;
+    }
+    Ok(())
+}
+
+/// Find the runner sources of a host that no saved stream on it uses, removing them if `apply`.
+///
+/// Scene items are collected in every scene rather than only the current layout,
+/// as switching layouts leaves the items of runners that left in the old one.
+async fn collect_orphaned_sources(
+    obs: &obws::Client,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    host: &str,
+    apply: bool,
+) -> anyhow::Result<OrphanReport> {
+    let kept = runner_inputs_in_use(db, host, None, false).await?;
+    let orphaned = |name: &str| name.starts_with(RUNNER_INPUT_PREFIX) && !kept.contains(name);
+
+    let mut scene_items = vec![];
+    for scene in obs.scenes().list().await?.scenes {
+        for item in obs.scene_items().list(SceneId::Name(&scene.name)).await? {
+            if orphaned(&item.source_name) {
+                scene_items.push(OrphanedItem {
+                    scene: scene.name.clone(),
+                    item_id: item.id,
+                    source: item.source_name,
+                });
+            }
+        }
+    }
+
+    // Every item of an orphaned input is collected above, so none is left showing it
+    let mut inputs = vec![];
+    if !settings.keep_unused_streams.unwrap_or(true) {
+        for input in obs.inputs().list(Some("vlc_source")).await? {
+            if orphaned(&input.id.name) {
+                inputs.push(input.id.name);
+            }
+        }
+    }
+
+    let verb = if apply { "Removing" } else { "Found" };
+    for item in &scene_items {
+        log::info!(
+            "{} orphaned item {} of {} in {} on {}",
+            verb,
+            item.item_id,
+            item.source,
+            item.scene,
+            host
+        );
+        if apply {
+            obs.scene_items()
+                .remove(SceneId::Name(&item.scene), item.item_id)
+                .await?;
+        }
+    }
+    for input in &inputs {
+        log::info!("{} orphaned source {} on {}", verb, input, host);
+        if apply {
+            obs.inputs().remove(InputId::Name(input)).await?;
+        }
+    }
+
+    Ok(OrphanReport {
+        host: host.to_owned(),
+        applied: apply,
+        scene_items,
+        inputs,
+    })
+}
+
+/// Trigger the given transition, or the current one if None
+pub async fn do_transition(obs: &obws::Client, transition: Option<&String>) -> anyhow::Result<()> {
+    log::debug!("Triggering Studio Mode transition");
+    match transition {
+        Some(name) => {
+            obs.transitions().set_current(name).await?;
+            obs.transitions().trigger().await?;
+        }
+        None => obs.transitions().trigger().await?,
+    }
+    Ok(())
+}
+
+/// Return the appropriate layout for the given project state with the layout's scene items.
+///
+/// Scenes are read only until a layout is found, and view transforms are only
+/// queried for that layout.
+async fn find_layout(
+    obs: &obws::Client,
+    naming: &SourceNaming,
+    event: &Event,
+    state: &StreamState,
+    scenes: &Scenes,
+) -> anyhow::Result<Option<(ObsScene, Vec<SceneItem>)>> {
+    let start = Instant::now();
+    let exists = |name: &str| scenes.scenes.iter().any(|s| s.name == name);
+    let mut scene_items: HashMap<String, Vec<SceneItem>> = HashMap::new();
+
+    let mut layout = state.requested_layout.clone().filter(|l| exists(l));
+    if layout.is_none() {
+        // Preferred layouts first, then every scene in OBS order
+        let candidates = event
+            .preferred_layouts
+            .iter()
+            .filter(|l| exists(l))
+            .chain(scenes.scenes.iter().map(|s| &s.name));
+        for name in candidates {
+            if !scene_items.contains_key(name) {
+                let items = obs.scene_items().list(SceneId::Name(name)).await?;
+                scene_items.insert(name.clone(), items);
+            }
+            let views: HashSet<usize> = scene_items[name]
+                .iter()
+                .filter_map(|item| naming.stream_view_slot(&item.source_name))
+                .collect();
+            if views.len() == state.stream_runners.len() {
+                layout = Some(name.clone());
+                break;
+            }
+        }
+    }
+
+    let Some(name) = layout else {
+        log::debug!(
+            "No layout found after reading {} scene(s) in {:?}",
+            scene_items.len(),
+            start.elapsed()
+        );
+        return Ok(None);
+    };
+
+    let items = match scene_items.remove(&name) {
+        Some(items) => items,
+        None => obs.scene_items().list(SceneId::Name(&name)).await?,
+    };
+    let mut layout = get_scene_layout(obs, naming, &name, &items).await?;
+    layout.active = scenes
+        .current_program_scene
+        .as_ref()
+        .is_some_and(|s| s.name == name);
+
+    log::debug!(
+        "Found layout {} after reading {} of {} scene(s) in {:?}",
+        name,
+        scene_items.len() + 1,
+        scenes.scenes.len(),
+        start.elapsed()
+    );
+    Ok(Some((layout, items)))
+}
+
+/// Return the layout `find_layout` would choose for a stream among the scenes of a snapshot
+fn find_snapshot_layout<'a>(
+    event: &Event,
+    state: &StreamState,
+    scenes: &'a [ObsScene],
+) -> Option<&'a ObsScene> {
+    let find = |name: &str| scenes.iter().find(|s| s.name == name);
+    if let Some(layout) = state.requested_layout.as_deref().and_then(find) {
+        return Some(layout);
+    }
+
+    event
+        .preferred_layouts
+        .iter()
+        .filter_map(|l| find(l))
+        .chain(scenes.iter())
+        .find(|s| s.sources.len() == state.stream_runners.len())
+}
+
+/// Read a runner of a stream for an OBS update.
+///
+/// An expired stream URL is resolved again through the runner actor if `refresh_urls` is set,
+/// and dropped if that fails.
+async fn get_update_runner(
+    state: &StreamState,
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    runner: i64,
+    refresh_urls: bool,
+) -> anyhow::Result<Runner> {
+    let mut runner = db.get_runner(runner).await?;
+
+    // Expired URLs may no longer play, so resolve them again before showing them
+    if refresh_urls && runner.stream_url_expires_within(Duration::ZERO) {
+        log::info!(
+            "Stream URL of {} has expired, refreshing it before use",
+            runner.name
+        );
+        let host = Some(state.obs_host.clone());
+        match send_message_with_timeout!(
+            STREAM_URL_REFRESH_TIMEOUT,
+            directory.runner_actor,
+            RunnerRequest,
+            RefreshStream,
+            runner.id,
+            host
+        ) {
+            Ok(_) => runner = db.get_runner(runner.id).await?,
+            Err(e) => {
+                log::warn!(
+                    "Failed to refresh the expired stream URL of {}: {}",
+                    runner.name,
+                    e
+                );
+                runner.cached_stream_url = None;
+            }
+        }
+    }
+    Ok(runner)
+}
+
+/// Read everything an OBS update of a stream is planned from.
+///
+/// Expired stream URLs are resolved again through the runner actor if `refresh_urls` is set,
+/// and kept as they are otherwise.
+async fn gather_update_state(
+    state: &StreamState,
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    naming: &SourceNaming,
+    event: &Event,
+    obs: &obws::Client,
+    refresh_urls: bool,
+) -> anyhow::Result<ObsUpdateState> {
+    let vlc_inputs: Vec<String> = obs
+        .inputs()
+        .list(Some("vlc_source"))
+        .await?
+        .into_iter()
+        .map(|i| i.id.name)
+        .collect();
+    let scenes = obs.scenes().list().await?;
+
+    let Some((layout, scene_items)) = find_layout(obs, naming, event, state, &scenes).await? else {
+        return Err(Error::UnknownLayout(
+            "No known layout for the current player count.".to_string(),
+        ))?;
+    };
+
+    if !scenes.scenes.iter().any(|s| s.name != layout.name) {
+        return Err(anyhow!(format!(
+            "OBS has no scene named {}, which the current layout requires.",
+            layout.name
+        )));
+    }
+
+    let mut slots: Vec<_> = state.stream_runners.iter().collect();
+    slots.sort();
+
+    let mut runners = vec![];
+    for (idx, runner) in slots {
+        runners.push((
+            *idx,
+            get_update_runner(state, db, directory, *runner, refresh_urls).await?,
+        ));
+    }
+
+    let mut on_deck = vec![];
+    for entry in state.on_deck_runners.iter() {
+        if !state.stream_runners.values().any(|r| *r == entry.runner) {
+            on_deck
+                .push(get_update_runner(state, db, directory, entry.runner, refresh_urls).await?);
+        }
+    }
+
+    let mut input_urls = HashMap::new();
+    for runner in runners.iter().map(|(_, r)| r).chain(on_deck.iter()) {
+        let input = runner_input_name(&runner.name);
+        if vlc_inputs.contains(&input) {
+            let settings = obs.inputs().settings::<VLC>(InputId::Name(&input)).await?;
+            if let Some(item) = settings.settings.playlist.into_iter().next() {
+                input_urls.insert(input, item.value);
+            }
+        }
+    }
+
+    // Only the items of runner inputs are ever removed, so only those need their state
+    let layout_id = SceneId::Name(&layout.name);
+    let mut items = vec![];
+    for item in scene_items {
+        let is_runner_input = vlc_inputs.contains(&item.source_name)
+            || runners
+                .iter()
+                .map(|(_, r)| r)
+                .chain(on_deck.iter())
+                .any(|r| runner_input_name(&r.name) == item.source_name);
+        let enabled = is_runner_input && obs.scene_items().enabled(layout_id, item.id).await?;
+        items.push(LayoutItem {
+            id: item.id,
+            source_name: item.source_name,
+            enabled,
+        });
+    }
 
-                    let name_field = &format!("name_{}", idx);
-                    if scene_items.iter().any(|s| &s.source_name == name_field) {
-                        log::debug!("Updating name field for to {}", runner.name);
-                        // Update name field
-                        let name_setting = SpecificFreetype {
-                            text: &runner.name.to_uppercase(),
-                        };
+    Ok(ObsUpdateState {
+        layout,
+        items,
+        vlc_inputs,
+        input_urls,
+        runners,
+        on_deck,
+        studio_mode: obs.ui().studio_mode_enabled().await?,
+        program_scene: scenes.current_program_scene.map(|s| s.name),
+    })
+}
 
+/// Apply planned changes to OBS, in order
+async fn execute_obs_actions(obs: &obws::Client, actions: &[ObsAction]) -> anyhow::Result<()> {
+    for action in actions {
+        match action {
+            ObsAction::CreateInput { scene, input, url } => {
+                log::debug!("Creating source {}", input);
+                obs.inputs()
+                    .create(inputs::Create {
+                        scene: SceneId::Name(scene),
+                        input,
+                        kind: "vlc_source",
+                        settings: Some(VLC::from_url(url)),
+                        enabled: Some(false),
+                    })
+                    .await?;
+            }
+            ObsAction::SetInputSettings { input, settings } => {
+                let input = InputId::Name(input);
+                match settings {
+                    InputSettings::Text { text } => {
+                        log::debug!("Setting text of {:?}", input);
                         obs.inputs()
                             .set_settings(SetSettings {
-                                input: InputId::Name(name_field),
-                                settings: &name_setting,
+                                input,
+                                settings: &SpecificFreetype { text },
                                 overlay: Some(true),
                             })
-                            .await?;
-                    } else {
-                        log::debug!("{} has no nametag, skipping", runner.name);
+                            .await?
                     }
-
-                    if good_url.is_some() {
-                        log::debug!("Creating new stream views for {}", runner.name);
-                        // Get the user-defined list of stream views in the layout
-                        let stream_views = layout.sources.get(&(*idx as usize)).cloned().unwrap_or_default();
-
-                        // Create a VLC source scene item for each identified stream view
-                        for view in stream_views {
-                            let new_item = obs
-                                .scene_items()
-                                .create(CreateSceneItem {
-                                    scene: target_layout_id,
-                                    source: stream_source_id.into(),
-                                    enabled: Some(true),
-                                })
-                                .await?;
-
-                            tokio::time::sleep(Duration::from_millis(200)).await;
-
-                            obs.scene_items()
-                                .set_index(SetIndex {
-                                    scene: target_layout_id,
-                                    item_id: new_item,
-                                    index: 0,
-                                })
-                                .await?;
-
-                            let new_transform = SetTransform {
-                                scene: target_layout_id,
-                                item_id: new_item,
-                                transform: SceneItemTransform {
-                                    position: Some(Position {
-                                        x: Some(view.x),
-                                        y: Some(view.y),
-                                    }),
-                                    rotation: None,
-                                    scale: None,
-                                    alignment: None, // TODO
-                                    bounds: Some(Bounds {
-                                        r#type: Some(obws::common::BoundsType::Stretch),
-                                        alignment: None, // TODO 2
-                                        width: Some(view.width),
-                                        height: Some(view.height),
-                                    }),
-                                    crop: Some(obws::requests::scene_items::Crop {
-                                        left: Some(view.crop_left),
-                                        right: Some(view.crop_right),
-                                        top: Some(view.crop_top),
-                                        bottom: Some(view.crop_bottom),
-                                    }),
-                                },
-                            };
-                            obs.scene_items().set_transform(new_transform).await.map_err(|e| anyhow!(format!(
-                            "Failed to set stream bounds: \n{:?}. \n\nIs the stream view {} transform set correctly (eg. with a bounding box enabled)?", e, view.name)))?;
-                        }
-                    } else {
-                        log::warn!(
-                            "Not creating stream views for {} due to missing stream URL",
-                            runner.name
-                        );
+                    InputSettings::Playlist { url } => {
+                        log::debug!("Applying stream change to {:?}", input);
+                        obs.inputs()
+                            .set_settings(SetSettings {
+                                input,
+                                settings: &VLC::from_url(url),
+                                overlay: Some(true),
+                            })
+                            .await?
                     }
                 }
-
-                // Remove this input from the list of inputs
-                vlc_inputs.retain_mut(|i| i.id.name != stream_source_id);
-                tokio::time::sleep(Duration::from_millis(100)).await;
             }
+            ObsAction::SetAudioRouting {
+                input,
+                monitor,
+                tracks,
+            } => {
+                apply_audio_routing(obs, InputId::Name(input), *monitor, tracks.as_deref()).await?
+            }
+            ObsAction::SetSyncOffset { input, offset_ms } => {
+                apply_sync_offset(obs, InputId::Name(input), *offset_ms).await?
+            }
+            ObsAction::EnsureFilters { input, filters } => {
+                ensure_filters(obs, input, filters).await?
+            }
+            ObsAction::SetMuted { input, muted } => {
+                obs.inputs().set_muted(InputId::Name(input), *muted).await?
+            }
+            ObsAction::SetVolume { input, volume } => {
+                obs.inputs()
+                    .set_volume(InputId::Name(input), Volume::Mul(*volume))
+                    .await?
+            }
+            ObsAction::RemoveItem {
+                scene,
+                item_id,
+                source,
+            } => {
+                log::debug!("Deleting old item {} of {}", item_id, source);
+                obs.scene_items()
+                    .remove(SceneId::Name(scene), *item_id)
+                    .await?
+            }
+            ObsAction::CreateSceneItem {
+                scene,
+                input,
+                view,
+                index,
+            } => {
+                log::debug!("Creating stream view {} for {}", view.name, input);
+                let scene = SceneId::Name(scene);
+                let index = match index {
+                    Some(index) => *index,
+                    None => obs
+                        .scene_items()
+                        .index(scene, view.item_id)
+                        .await
+                        .unwrap_or(view.index),
+                };
 
-            // Delete any input that was not visited
-            for input in vlc_inputs {
-                if settings.keep_unused_streams.unwrap_or(true) {
-                    log::debug!("Deleting old items for unused input {}", input.id.name);
-                    delete_scene_items_for_player(
-                        obs,
-                        target_layout_id,
-                        &scene_items,
-                        &input.id.name,
-                    )
+                let new_item = obs
+                    .scene_items()
+                    .create(CreateSceneItem {
+                        scene,
+                        source: InputId::Name(input).into(),
+                        enabled: Some(true),
+                    })
                     .await?;
-                } else {
-                    log::debug!("Deleting stale VLC input {}", input.id.name);
-                    obs.inputs().remove(InputId::Name(&input.id.name)).await?;
-                }
-            }
 
-            tokio::time::sleep(Duration::from_millis(200)).await;
-            if obs.ui().studio_mode_enabled().await? {
-                obs.scenes()
-                    .set_current_preview_scene(target_layout_id)
+                tokio::time::sleep(Duration::from_millis(200)).await;
+
+                obs.scene_items()
+                    .set_index(SetIndex {
+                        scene,
+                        item_id: new_item,
+                        index,
+                    })
                     .await?;
-                do_transition(obs, settings).await?;
+
+                // The runner's source is only sized once it has loaded a frame
+                let (crop_left, crop_right, crop_top, crop_bottom) =
+                    match obs.scene_items().transform(scene, new_item).await {
+                        Ok(transform) => {
+                            view.scaled_crop(transform.source_width, transform.source_height)
+                        }
+                        Err(_) => view.scaled_crop(0.0, 0.0),
+                    };
+
+                let new_transform = SetTransform {
+                    scene,
+                    item_id: new_item,
+                    transform: SceneItemTransform {
+                        position: Some(Position {
+                            x: Some(view.x),
+                            y: Some(view.y),
+                        }),
+                        rotation: None,
+                        scale: None,
+                        alignment: None, // TODO
+                        bounds: Some(Bounds {
+                            r#type: Some(obws::common::BoundsType::Stretch),
+                            alignment: None, // TODO 2
+                            width: Some(view.width),
+                            height: Some(view.height),
+                        }),
+                        crop: Some(obws::requests::scene_items::Crop {
+                            left: Some(crop_left),
+                            right: Some(crop_right),
+                            top: Some(crop_top),
+                            bottom: Some(crop_bottom),
+                        }),
+                    },
+                };
+                obs.scene_items()
+                    .set_transform(new_transform)
+                    .await
+                    .map_err(|e| {
+                        anyhow!(
+                            "Failed to set stream bounds: \n{:?}. \n\nIs the stream view {} \
+                             transform set correctly (eg. with a bounding box enabled)?",
+                            e,
+                            view.name
+                        )
+                    })?;
+            }
+            ObsAction::RemoveInput { input } => {
+                log::debug!("Deleting stale VLC input {}", input);
+                obs.inputs().remove(InputId::Name(input)).await?;
+            }
+            ObsAction::Wait { millis } => tokio::time::sleep(Duration::from_millis(*millis)).await,
+            ObsAction::SetPreviewScene { scene } => {
                 obs.scenes()
-                    .set_current_preview_scene(target_layout_id)
-                    .await?;
-            } else if modifications.contains(&ModifiedStreamState::Layout)
-                && scenes.current_program_scene.unwrap().name != layout.name
-            {
-                log::debug!("Activating new layout: {}", layout.name);
+                    .set_current_preview_scene(SceneId::Name(scene))
+                    .await?
+            }
+            ObsAction::Transition { transition } => do_transition(obs, transition.as_ref()).await?,
+            ObsAction::SwitchScene { scene } => {
+                log::debug!("Activating new layout: {}", scene);
                 obs.scenes()
-                    .set_current_program_scene(target_layout_id)
+                    .set_current_program_scene(SceneId::Name(scene))
                     .await?;
             }
+        }
+    }
+    Ok(())
+}
+
+/// Apply project state to OBS
+pub async fn update_obs_state(
+    state: &StreamState,
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    settings: &Settings,
+    naming: &SourceNaming,
+    modifications: &[ModifiedStreamState],
+    obs: &obws::Client,
+) -> anyhow::Result<()> {
+    log::debug!("Updating OBS: {:?}", modifications);
+    let start = Instant::now();
+
+    let event = db.get_event(state.event).await?;
+    let obs_state = gather_update_state(state, db, directory, naming, &event, obs, true).await?;
+    let actions = plan_obs_update(state, &event, &obs_state, settings, naming, modifications);
+    execute_obs_actions(obs, &actions).await?;
+
+    log::debug!(
+        "OBS update of {} action(s) complete in {:?}",
+        actions.len(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// Return the changes a full OBS update of a stream would make, without making them.
+///
+/// Expired stream URLs are not refreshed, so the plan shows the URLs currently cached.
+async fn plan_full_update(
+    state: &StreamState,
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    settings: &Settings,
+    naming: &SourceNaming,
+    obs: &obws::Client,
+) -> anyhow::Result<Vec<ObsAction>> {
+    let event = db.get_event(state.event).await?;
+    let obs_state = gather_update_state(state, db, directory, naming, &event, obs, false).await?;
+    let modifications = [ModifiedStreamState::Layout, ModifiedStreamState::Commentary];
+    Ok(plan_obs_update(
+        state,
+        &event,
+        &obs_state,
+        settings,
+        naming,
+        &modifications,
+    ))
+}
 
-            log::debug!("OBS update complete");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            Ok(())
+    /// A view cropping 10% off each side of a 1920x1080 template source, if its size is known
+    fn template_view(source_width: Option<f32>, source_height: Option<f32>) -> VlcSourceBounds {
+        VlcSourceBounds {
+            name: "Stream 0".to_owned(),
+            item_id: 1,
+            index: 0,
+            x: 0.0,
+            y: 0.0,
+            width: 640.0,
+            height: 360.0,
+            crop_left: 192,
+            crop_right: 192,
+            crop_top: 108,
+            crop_bottom: 108,
+            source_width,
+            source_height,
         }
-        _ => Err(Error::UnknownLayout(
-            "No known layout for the current player count.".to_string(),
-        ))?,
+    }
+
+    #[test]
+    fn crops_are_scaled_to_the_source_size() {
+        let view = template_view(Some(1920.0), Some(1080.0));
+        let cases = [
+            // Same size as the template
+            ((1920.0, 1080.0), (192, 192, 108, 108)),
+            // Smaller and larger sources of the same aspect ratio
+            ((1280.0, 720.0), (128, 128, 72, 72)),
+            ((3840.0, 2160.0), (384, 384, 216, 216)),
+            // Each axis is scaled on its own
+            ((1440.0, 1080.0), (144, 144, 108, 108)),
+            ((1920.0, 540.0), (192, 192, 54, 54)),
+            // Fractional crops are rounded
+            ((854.0, 480.0), (85, 85, 48, 48)),
+        ];
+        for ((width, height), expected) in cases {
+            assert_eq!(
+                view.scaled_crop(width, height),
+                expected,
+                "{}x{}",
+                width,
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn crops_are_unscaled_on_axes_of_unknown_size() {
+        // Snapshots taken before source sizes were recorded
+        let view = template_view(None, None);
+        assert_eq!(view.scaled_crop(1280.0, 720.0), (192, 192, 108, 108));
+
+        // Sources that have not loaded a frame yet report no size
+        let view = template_view(Some(1920.0), Some(1080.0));
+        assert_eq!(view.scaled_crop(0.0, 0.0), (192, 192, 108, 108));
+        assert_eq!(view.scaled_crop(1280.0, 0.0), (128, 128, 108, 108));
+
+        let view = template_view(Some(1920.0), None);
+        assert_eq!(view.scaled_crop(1280.0, 720.0), (128, 128, 108, 108));
+        let view = template_view(Some(0.0), Some(1080.0));
+        assert_eq!(view.scaled_crop(1280.0, 720.0), (192, 192, 72, 72));
     }
 }
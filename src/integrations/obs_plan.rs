@@ -0,0 +1,667 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::core::{
+    event::Event,
+    i18n,
+    runner::Runner,
+    settings::{AudioMonitorType, Settings, SourceFilter, SourceNaming},
+    stream::{ModifiedStreamState, StreamState},
+};
+
+use super::obs::{ObsScene, VlcSourceBounds};
+
+/// Pause after changing the playlist of a runner source, so VLC picks it up
+const PLAYLIST_CHANGE_WAIT_MILLIS: u64 = 200;
+/// Pause after updating each runner
+const RUNNER_WAIT_MILLIS: u64 = 100;
+/// Pause before changing scenes, so the new views are in place
+const SCENE_CHANGE_WAIT_MILLIS: u64 = 200;
+
+/// Everything read from OBS and the project that an OBS update is planned from
+#[derive(Debug)]
+pub struct ObsUpdateState {
+    /// The layout the stream is shown in
+    pub layout: ObsScene,
+    /// Items of the layout's scene
+    pub items: Vec<LayoutItem>,
+    /// Names of the runner (VLC) inputs that exist on the host
+    pub vlc_inputs: Vec<String>,
+    /// Current playlist URL of each runner input, by input name
+    pub input_urls: HashMap<String, String>,
+    /// Runners of the stream by slot, in slot order
+    pub runners: Vec<(i64, Runner)>,
+    /// Runners on deck for the stream who are not in a slot
+    pub on_deck: Vec<Runner>,
+    pub studio_mode: bool,
+    pub program_scene: Option<String>,
+}
+
+/// An item of a layout's scene
+#[derive(Debug)]
+pub struct LayoutItem {
+    pub id: i64,
+    pub source_name: String,
+    /// Whether the item is shown. Only read for items of runner inputs, false otherwise
+    pub enabled: bool,
+}
+
+/// Settings written to an input
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum InputSettings {
+    /// Text of a FreeType text source
+    Text { text: String },
+    /// Single playlist entry of a VLC source
+    Playlist { url: String },
+}
+
+/// A single change to OBS, planned by `plan_obs_update`
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ObsAction {
+    /// Create a disabled VLC input for a runner in a scene
+    CreateInput {
+        scene: String,
+        input: String,
+        url: String,
+    },
+    SetInputSettings {
+        input: String,
+        settings: InputSettings,
+    },
+    /// Set the audio monitoring and tracks of an input, leaving the tracks alone if None
+    SetAudioRouting {
+        input: String,
+        monitor: AudioMonitorType,
+        tracks: Option<Vec<u8>>,
+    },
+    /// Delay a runner input by raising its network caching
+    SetSyncOffset {
+        input: String,
+        offset_ms: u32,
+    },
+    /// Create the filters an input is missing, leaving existing filters alone
+    EnsureFilters {
+        input: String,
+        filters: Vec<SourceFilter>,
+    },
+    SetMuted {
+        input: String,
+        muted: bool,
+    },
+    /// Set the volume of an input as a multiplier, 1.0 being unity gain
+    SetVolume {
+        input: String,
+        volume: f32,
+    },
+    /// Remove a scene item if it is enabled
+    RemoveItem {
+        scene: String,
+        item_id: i64,
+        source: String,
+    },
+    /// Show an input in a stream view. The item is placed at `index`, or at the view's
+    /// current index if None
+    CreateSceneItem {
+        scene: String,
+        input: String,
+        view: VlcSourceBounds,
+        index: Option<u32>,
+    },
+    RemoveInput {
+        input: String,
+    },
+    Wait {
+        millis: u64,
+    },
+    SetPreviewScene {
+        scene: String,
+    },
+    /// Trigger a studio mode transition, the current one if None
+    Transition {
+        transition: Option<String>,
+    },
+    SwitchScene {
+        scene: String,
+    },
+}
+
+/// Start of the names of the VLC inputs of runners
+pub const RUNNER_INPUT_PREFIX: &str = "streamer_";
+
+/// Name of the VLC input of the runner with the given name
+pub fn runner_input_name(runner: &str) -> String {
+    format!("{}{}", RUNNER_INPUT_PREFIX, runner)
+}
+
+/// Plan the changes that show a stream in OBS, without touching OBS.
+///
+/// Commentary and runner views are only rewritten if they are in `modifications`,
+/// or if a runner's input is created by the plan.
+pub fn plan_obs_update(
+    state: &StreamState,
+    event: &Event,
+    obs_state: &ObsUpdateState,
+    settings: &Settings,
+    naming: &SourceNaming,
+    modifications: &[ModifiedStreamState],
+) -> Vec<ObsAction> {
+    let mut actions = vec![];
+    let layout = &obs_state.layout;
+    let scene = &layout.name;
+    let locale = i18n::locale(event.locale.as_deref());
+    let has_source = |name: &str| obs_state.items.iter().any(|i| i.source_name == name);
+    let remove_items = |actions: &mut Vec<ObsAction>, input: &str| {
+        for item in &obs_state.items {
+            if item.source_name == input && item.enabled {
+                actions.push(ObsAction::RemoveItem {
+                    scene: scene.clone(),
+                    item_id: item.id,
+                    source: input.to_owned(),
+                });
+            }
+        }
+    };
+
+    if modifications.contains(&ModifiedStreamState::Commentary) {
+        let has_host_source = has_source(&naming.commentary_host);
+        let host = event.commentary_host.as_deref();
+        let mut commentators = state.get_commentators_with_host(host);
+        if has_host_source {
+            // The host is shown in their own source rather than in the list
+            commentators.retain(|c| Some(c.as_str()) != host);
+        }
+
+        if has_source(&naming.commentary) {
+            let mut text = commentators.join("\n");
+            if let Some(prefix) = locale.commentary_prefix().filter(|_| !text.is_empty()) {
+                text = format!("{}\n{}", prefix, text);
+            }
+            actions.push(ObsAction::SetInputSettings {
+                input: naming.commentary.clone(),
+                settings: InputSettings::Text { text },
+            });
+        }
+
+        if has_host_source {
+            let text = host
+                .filter(|h| state.get_commentators().iter().any(|c| c == h))
+                .unwrap_or_default()
+                .to_owned();
+            actions.push(ObsAction::SetInputSettings {
+                input: naming.commentary_host.clone(),
+                settings: InputSettings::Text { text },
+            });
+        }
+    }
+
+    let host = settings.obs_hosts.get(&state.obs_host);
+    // Use the configured layer for this layout, otherwise the template view's layer
+    let configured_index = settings
+        .layout_view_index
+        .as_ref()
+        .and_then(|m| m.get(scene))
+        .or(settings.default_view_index.as_ref())
+        .copied();
+
+    let mut unused_inputs = obs_state.vlc_inputs.clone();
+    for (idx, runner) in &obs_state.runners {
+        let input = runner_input_name(&runner.name);
+        let mut just_created = false;
+
+        match &runner.cached_stream_url {
+            Some(url) => {
+                if !obs_state.vlc_inputs.contains(&input) {
+                    actions.push(ObsAction::CreateInput {
+                        scene: scene.clone(),
+                        input: input.clone(),
+                        url: url.clone(),
+                    });
+                    just_created = true;
+                } else if obs_state.input_urls.get(&input) != Some(url) {
+                    actions.push(ObsAction::SetInputSettings {
+                        input: input.clone(),
+                        settings: InputSettings::Playlist { url: url.clone() },
+                    });
+                    actions.push(ObsAction::Wait {
+                        millis: PLAYLIST_CHANGE_WAIT_MILLIS,
+                    });
+                }
+
+                actions.push(ObsAction::SetAudioRouting {
+                    input: input.clone(),
+                    monitor: runner
+                        .monitor_type
+                        .or(host.and_then(|h| h.runner_monitor_type))
+                        .unwrap_or(AudioMonitorType::None),
+                    tracks: host.and_then(|h| h.runner_audio_tracks.clone()),
+                });
+                actions.push(ObsAction::SetSyncOffset {
+                    input: input.clone(),
+                    offset_ms: state.get_sync_offset(runner.id),
+                });
+                let filters = settings.runner_filters(&state.obs_host, &runner.name);
+                if !filters.is_empty() {
+                    actions.push(ObsAction::EnsureFilters {
+                        input: input.clone(),
+                        filters,
+                    });
+                }
+
+                let audible = state
+                    .audible_runner
+                    .map(|r| r == runner.id)
+                    .unwrap_or(*idx == 0);
+                actions.push(ObsAction::SetMuted {
+                    input: input.clone(),
+                    muted: !audible,
+                });
+                if audible {
+                    actions.push(ObsAction::SetVolume {
+                        input: input.clone(),
+                        volume: runner.get_volume_mul(),
+                    });
+                }
+            }
+            None => log::warn!("No stream URL for {}, skipping...", runner.name),
+        }
+
+        if modifications.contains(&ModifiedStreamState::RunnerView(runner.id))
+            || modifications.contains(&ModifiedStreamState::Layout)
+            || just_created
+        {
+            remove_items(&mut actions, &input);
+
+            let nametag = naming.nametag(*idx);
+            if has_source(&nametag) {
+                actions.push(ObsAction::SetInputSettings {
+                    input: nametag,
+                    settings: InputSettings::Text {
+                        text: locale.uppercase(&runner.name),
+                    },
+                });
+            } else {
+                log::debug!("{} has no nametag, skipping", runner.name);
+            }
+
+            if runner.cached_stream_url.is_some() {
+                // Create a scene item for each of the runner's stream views in the layout
+                for view in layout.sources.get(&(*idx as usize)).into_iter().flatten() {
+                    actions.push(ObsAction::CreateSceneItem {
+                        scene: scene.clone(),
+                        input: input.clone(),
+                        view: view.clone(),
+                        index: configured_index,
+                    });
+                }
+            } else {
+                log::warn!(
+                    "Not creating stream views for {} due to missing stream URL",
+                    runner.name
+                );
+            }
+        }
+
+        unused_inputs.retain(|i| i != &input);
+        actions.push(ObsAction::Wait {
+            millis: RUNNER_WAIT_MILLIS,
+        });
+    }
+
+    // Runners on deck get their input ahead of time, hidden and muted,
+    // so it is already buffered when they are put in a slot
+    for runner in &obs_state.on_deck {
+        let input = runner_input_name(&runner.name);
+        let Some(url) = &runner.cached_stream_url else {
+            log::warn!(
+                "No stream URL for on deck runner {}, skipping...",
+                runner.name
+            );
+            continue;
+        };
+
+        if !obs_state.vlc_inputs.contains(&input) {
+            actions.push(ObsAction::CreateInput {
+                scene: scene.clone(),
+                input: input.clone(),
+                url: url.clone(),
+            });
+        } else if obs_state.input_urls.get(&input) != Some(url) {
+            actions.push(ObsAction::SetInputSettings {
+                input: input.clone(),
+                settings: InputSettings::Playlist { url: url.clone() },
+            });
+        }
+        remove_items(&mut actions, &input);
+        actions.push(ObsAction::SetMuted {
+            input: input.clone(),
+            muted: true,
+        });
+        let filters = settings.runner_filters(&state.obs_host, &runner.name);
+        if !filters.is_empty() {
+            actions.push(ObsAction::EnsureFilters {
+                input: input.clone(),
+                filters,
+            });
+        }
+
+        unused_inputs.retain(|i| i != &input);
+    }
+
+    // Hide or delete the inputs of runners who are not in the stream
+    for input in unused_inputs {
+        if settings.keep_unused_streams.unwrap_or(true) {
+            remove_items(&mut actions, &input);
+        } else {
+            actions.push(ObsAction::RemoveInput { input });
+        }
+    }
+
+    actions.push(ObsAction::Wait {
+        millis: SCENE_CHANGE_WAIT_MILLIS,
+    });
+    if obs_state.studio_mode {
+        let transition = if modifications.contains(&ModifiedStreamState::RelayHandoff) {
+            settings
+                .relay_handoff_transition
+                .as_ref()
+                .or(settings.obs_transition.as_ref())
+        } else if modifications.contains(&ModifiedStreamState::Rotation) {
+            settings
+                .rotation_transition
+                .as_ref()
+                .or(settings.obs_transition.as_ref())
+        } else {
+            settings.obs_transition.as_ref()
+        };
+        actions.push(ObsAction::SetPreviewScene {
+            scene: scene.clone(),
+        });
+        actions.push(ObsAction::Transition {
+            transition: transition.cloned(),
+        });
+        actions.push(ObsAction::SetPreviewScene {
+            scene: scene.clone(),
+        });
+    } else if modifications.contains(&ModifiedStreamState::Layout)
+        && obs_state.program_scene.as_ref() != Some(scene)
+    {
+        actions.push(ObsAction::SwitchScene {
+            scene: scene.clone(),
+        });
+    }
+
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::{test_event, test_layout, test_runner, test_stream};
+
+    const FIRST_URL: &str = "https://example.com/first.m3u8";
+    const SECOND_URL: &str = "https://example.com/second.m3u8";
+
+    fn obs_state(runners: Vec<(i64, Runner)>, vlc_inputs: &[&str]) -> ObsUpdateState {
+        ObsUpdateState {
+            layout: test_layout("2_runners", 2),
+            items: vec![],
+            vlc_inputs: vlc_inputs.iter().map(|i| i.to_string()).collect(),
+            input_urls: HashMap::new(),
+            runners,
+            on_deck: vec![],
+            studio_mode: false,
+            program_scene: Some("intermission".to_owned()),
+        }
+    }
+
+    fn plan(
+        stream: &StreamState,
+        obs_state: &ObsUpdateState,
+        settings: &Settings,
+        modifications: &[ModifiedStreamState],
+    ) -> Vec<ObsAction> {
+        let naming = SourceNaming::from_settings(settings).unwrap();
+        plan_obs_update(
+            stream,
+            &test_event("Race"),
+            obs_state,
+            settings,
+            &naming,
+            modifications,
+        )
+    }
+
+    fn muted(actions: &[ObsAction], input: &str) -> Option<bool> {
+        actions.iter().find_map(|a| match a {
+            ObsAction::SetMuted { input: i, muted } if i == input => Some(*muted),
+            _ => None,
+        })
+    }
+
+    fn scene_items_of(actions: &[ObsAction], input: &str) -> usize {
+        actions
+            .iter()
+            .filter(|a| matches!(a, ObsAction::CreateSceneItem { input: i, .. } if i == input))
+            .count()
+    }
+
+    #[test]
+    fn layout_change_recreates_views_and_switches_scene() {
+        let settings = Settings::template();
+        let first = test_runner(1, "first", Some(FIRST_URL));
+        let second = test_runner(2, "second", Some(SECOND_URL));
+        let stream = test_stream(1, &[(0, 1), (1, 2)]);
+        let mut state = obs_state(
+            vec![(0, first), (1, second)],
+            &["streamer_first", "streamer_second"],
+        );
+        state.input_urls = HashMap::from([
+            ("streamer_first".to_owned(), FIRST_URL.to_owned()),
+            ("streamer_second".to_owned(), SECOND_URL.to_owned()),
+        ]);
+        state.items = vec![LayoutItem {
+            id: 7,
+            source_name: "streamer_first".to_owned(),
+            enabled: true,
+        }];
+
+        let actions = plan(&stream, &state, &settings, &[ModifiedStreamState::Layout]);
+
+        assert!(actions.iter().any(|a| matches!(
+            a,
+            ObsAction::RemoveItem { item_id: 7, source, .. } if source == "streamer_first"
+        )));
+        assert_eq!(scene_items_of(&actions, "streamer_first"), 1);
+        assert_eq!(scene_items_of(&actions, "streamer_second"), 1);
+        assert!(!actions
+            .iter()
+            .any(|a| matches!(a, ObsAction::CreateInput { .. })));
+        assert!(matches!(
+            actions.last(),
+            Some(ObsAction::SwitchScene { scene }) if scene == "2_runners"
+        ));
+    }
+
+    #[test]
+    fn unchanged_views_are_left_alone() {
+        let settings = Settings::template();
+        let first = test_runner(1, "first", Some(FIRST_URL));
+        let stream = test_stream(1, &[(0, 1)]);
+        let mut state = obs_state(vec![(0, first)], &["streamer_first"]);
+        state.input_urls = HashMap::from([("streamer_first".to_owned(), FIRST_URL.to_owned())]);
+        state.program_scene = Some("2_runners".to_owned());
+
+        let actions = plan(&stream, &state, &settings, &[ModifiedStreamState::Layout]);
+        assert_eq!(scene_items_of(&actions, "streamer_first"), 1);
+        assert!(!actions
+            .iter()
+            .any(|a| matches!(a, ObsAction::SwitchScene { .. })));
+
+        let actions = plan(&stream, &state, &settings, &[]);
+        assert_eq!(scene_items_of(&actions, "streamer_first"), 0);
+    }
+
+    #[test]
+    fn studio_mode_transitions_instead_of_switching() {
+        let settings = Settings::template();
+        let first = test_runner(1, "first", Some(FIRST_URL));
+        let stream = test_stream(1, &[(0, 1)]);
+        let mut state = obs_state(vec![(0, first)], &[]);
+        state.studio_mode = true;
+
+        let actions = plan(&stream, &state, &settings, &[ModifiedStreamState::Layout]);
+        assert!(actions
+            .iter()
+            .any(|a| matches!(a, ObsAction::Transition { .. })));
+        assert!(!actions
+            .iter()
+            .any(|a| matches!(a, ObsAction::SwitchScene { .. })));
+    }
+
+    #[test]
+    fn relay_handoff_uses_its_transition() {
+        let settings = Settings {
+            obs_transition: Some("Cut".to_owned()),
+            relay_handoff_transition: Some("Long Fade".to_owned()),
+            ..Settings::template()
+        };
+        let first = test_runner(1, "first", Some(FIRST_URL));
+        let stream = test_stream(1, &[(0, 1)]);
+        let mut state = obs_state(vec![(0, first)], &[]);
+        state.studio_mode = true;
+        let transition = |modifications: &[ModifiedStreamState]| {
+            plan(&stream, &state, &settings, modifications)
+                .into_iter()
+                .find_map(|a| match a {
+                    ObsAction::Transition { transition } => Some(transition),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let handoff = [
+            ModifiedStreamState::RunnerView(1),
+            ModifiedStreamState::RelayHandoff,
+        ];
+        assert_eq!(transition(&handoff).as_deref(), Some("Long Fade"));
+        assert_eq!(
+            transition(&[ModifiedStreamState::RunnerView(1)]).as_deref(),
+            Some("Cut")
+        );
+    }
+
+    #[test]
+    fn audible_runner_switch_mutes_the_others() {
+        let settings = Settings::template();
+        let runners = vec![
+            (0, test_runner(1, "first", Some(FIRST_URL))),
+            (1, test_runner(2, "second", Some(SECOND_URL))),
+        ];
+        let state = obs_state(runners, &["streamer_first", "streamer_second"]);
+
+        // The first slot is audible unless another runner is chosen
+        let mut stream = test_stream(1, &[(0, 1), (1, 2)]);
+        let actions = plan(&stream, &state, &settings, &[]);
+        assert_eq!(muted(&actions, "streamer_first"), Some(false));
+        assert_eq!(muted(&actions, "streamer_second"), Some(true));
+
+        stream.audible_runner = Some(2);
+        let actions = plan(&stream, &state, &settings, &[]);
+        assert_eq!(muted(&actions, "streamer_first"), Some(true));
+        assert_eq!(muted(&actions, "streamer_second"), Some(false));
+        assert!(actions.iter().any(|a| matches!(
+            a,
+            ObsAction::SetVolume { input, .. } if input == "streamer_second"
+        )));
+        assert!(!actions.iter().any(|a| matches!(
+            a,
+            ObsAction::SetVolume { input, .. } if input == "streamer_first"
+        )));
+    }
+
+    #[test]
+    fn runner_without_url_gets_no_input() {
+        let settings = Settings::template();
+        let naming = SourceNaming::from_settings(&settings).unwrap();
+        let stream = test_stream(1, &[(0, 1)]);
+        let mut state = obs_state(vec![(0, test_runner(1, "first", None))], &[]);
+        state.items = vec![LayoutItem {
+            id: 3,
+            source_name: naming.nametag(0),
+            enabled: false,
+        }];
+
+        let actions = plan(
+            &stream,
+            &state,
+            &settings,
+            &[ModifiedStreamState::RunnerView(1)],
+        );
+
+        assert!(!actions.iter().any(|a| matches!(
+            a,
+            ObsAction::CreateInput { .. }
+                | ObsAction::CreateSceneItem { .. }
+                | ObsAction::SetMuted { .. }
+        )));
+        // The nametag is still written, so the slot is labelled while the stream is found
+        assert!(actions.iter().any(|a| matches!(
+            a,
+            ObsAction::SetInputSettings { settings: InputSettings::Text { text }, .. } if text == "FIRST"
+        )));
+    }
+
+    #[test]
+    fn new_input_is_created_and_shown() {
+        let settings = Settings::template();
+        let stream = test_stream(1, &[(0, 1)]);
+        let state = obs_state(vec![(0, test_runner(1, "first", Some(FIRST_URL)))], &[]);
+
+        let actions = plan(&stream, &state, &settings, &[]);
+        assert!(actions.iter().any(|a| matches!(
+            a,
+            ObsAction::CreateInput { input, url, .. } if input == "streamer_first" && url == FIRST_URL
+        )));
+        assert_eq!(scene_items_of(&actions, "streamer_first"), 1);
+    }
+
+    #[test]
+    fn stale_inputs_are_hidden_or_removed() {
+        let mut settings = Settings::template();
+        let stream = test_stream(1, &[(0, 1)]);
+        let mut state = obs_state(
+            vec![(0, test_runner(1, "first", Some(FIRST_URL)))],
+            &["streamer_first", "streamer_old", "streamer_next"],
+        );
+        state.items = vec![LayoutItem {
+            id: 9,
+            source_name: "streamer_old".to_owned(),
+            enabled: true,
+        }];
+        state.on_deck = vec![test_runner(3, "next", Some(SECOND_URL))];
+
+        let actions = plan(&stream, &state, &settings, &[]);
+        assert!(actions.iter().any(|a| matches!(
+            a,
+            ObsAction::RemoveItem { item_id: 9, source, .. } if source == "streamer_old"
+        )));
+        assert!(!actions
+            .iter()
+            .any(|a| matches!(a, ObsAction::RemoveInput { .. })));
+        assert_eq!(muted(&actions, "streamer_next"), Some(true));
+
+        settings.keep_unused_streams = Some(false);
+        let actions = plan(&stream, &state, &settings, &[]);
+        let removed: Vec<_> = actions
+            .iter()
+            .filter_map(|a| match a {
+                ObsAction::RemoveInput { input } => Some(input.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(removed, vec!["streamer_old"]);
+    }
+}
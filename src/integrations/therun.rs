@@ -1,7 +1,6 @@
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
-
 /// TheRun websocket return type
 #[derive(Serialize, Deserialize)]
 pub struct TheRunReturnJson {
@@ -36,3 +35,74 @@ pub struct Split {
     pub pb_split_time: Option<f64>,
     pub split_time: Option<f64>,
 }
+
+impl Run {
+    /// Whether the final split of this run has been completed
+    pub fn is_finished(&self) -> bool {
+        !self.splits.is_empty() && self.current_split_index >= self.splits.len() as i64
+    }
+
+    /// Whether this run has no completed splits
+    pub fn is_at_start(&self) -> bool {
+        self.current_split_index <= 0 && self.splits.iter().all(|s| s.split_time.is_none())
+    }
+
+    /// Whether this update means the runner reset `previous`, returning why if so.
+    ///
+    /// Finished runs are kept, as their results are still relevant after the runner resets.
+    pub fn reset_reason(&self, previous: &Run) -> Option<String> {
+        if previous.is_finished() {
+            return None;
+        }
+
+        if !previous.started_at.is_empty() && previous.started_at != self.started_at {
+            Some(format!(
+                "start time changed from {} to {}",
+                previous.started_at, self.started_at
+            ))
+        } else if previous.current_split_index > 0 && self.is_at_start() {
+            Some(format!(
+                "split index dropped from {} to {} with cleared split times",
+                previous.current_split_index, self.current_split_index
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Format a run time in milliseconds as `h:mm:ss`, or `m:ss` for times under an hour
+pub fn format_run_time(ms: f64) -> String {
+    let secs = (ms / 1000.0) as i64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Text shown in place of a run statistic that is not known yet
+pub const MISSING_STAT: &str = "\u{2014}";
+
+/// Format a run statistic in milliseconds as `h:mm:ss`, or a dash if it is missing
+pub fn format_stat_time(ms: Option<f64>) -> String {
+    match ms {
+        Some(ms) => {
+            let secs = (ms / 1000.0) as i64;
+            format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+        }
+        None => MISSING_STAT.to_owned(),
+    }
+}
+
+/// Format a delta in milliseconds as `+m:ss` or `-m:ss`, or a dash if it is missing
+pub fn format_delta(ms: Option<f64>) -> String {
+    match ms {
+        Some(ms) => {
+            let secs = (ms.abs() / 1000.0) as i64;
+            let sign = if ms < 0.0 { '-' } else { '+' };
+            format!("{}{}:{:02}", sign, secs / 60, secs % 60)
+        }
+        None => MISSING_STAT.to_owned(),
+    }
+}
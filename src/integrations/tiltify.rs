@@ -0,0 +1,308 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{db::ProjectStore, settings::Settings},
+    integrations::{discord::DiscordCommand, obs::ObsCommand, web::WebCommand},
+    send_nonblocking, ActorMessage, ActorReceiver, ActorRef, Directory, Rto,
+};
+
+/// Requests for TiltifyActor
+pub enum TiltifyCommand {
+    /// Get the last known donation total and recent donations
+    GetDonations(Rto<DonationState>),
+}
+
+pub type TiltifyActor = ActorRef<TiltifyCommand>;
+
+impl ActorMessage for TiltifyCommand {
+    fn label(&self) -> &'static str {
+        match self {
+            TiltifyCommand::GetDonations(..) => "GetDonations",
+        }
+    }
+}
+
+const TILTIFY_API_URL: &str = "https://v5api.tiltify.com/api/public";
+pub const DEFAULT_POLL_SECONDS: u64 = 30;
+pub const DEFAULT_RECENT_DONATIONS: usize = 10;
+
+/// A single donation to the campaign
+#[derive(Serialize, Clone, Debug)]
+pub struct Donation {
+    pub id: String,
+    pub donor_name: String,
+    pub amount: f64,
+    pub currency: String,
+    pub comment: Option<String>,
+    /// Completion time as reported by Tiltify, in RFC 3339
+    pub completed_at: Option<String>,
+}
+
+/// The last known state of the donation campaign
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct DonationState {
+    /// Amount raised, None until the campaign was polled successfully
+    pub total: Option<f64>,
+    pub currency: Option<String>,
+    /// Amount raised formatted for display, such as `1,234.56 USD`
+    pub total_formatted: Option<String>,
+    /// Most recent donations, newest first
+    pub recent: Vec<Donation>,
+}
+
+#[derive(Deserialize)]
+struct TiltifyResponse<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct TiltifyAmount {
+    currency: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct TiltifyCampaign {
+    amount_raised: TiltifyAmount,
+}
+
+#[derive(Deserialize)]
+struct TiltifyDonation {
+    id: String,
+    donor_name: Option<String>,
+    donor_comment: Option<String>,
+    amount: TiltifyAmount,
+    completed_at: Option<String>,
+}
+
+/// Format an amount with thousands separators and its currency
+pub fn format_amount(value: f64, currency: &str) -> String {
+    let cents = format!("{:.2}", value);
+    let (whole, fraction) = cents.split_once('.').unwrap_or((&cents, "00"));
+    let (sign, digits) = match whole.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", whole),
+    };
+
+    let mut grouped = String::new();
+    for (idx, digit) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    format!("{}{}.{} {}", sign, grouped, fraction, currency)
+}
+
+/// Poll a Tiltify campaign for its total and recent donations.
+///
+/// Polling only runs if `tiltify_token` and `tiltify_campaign_id` are set,
+/// otherwise requests are answered with an empty state.
+pub async fn run_tiltify_actor(
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    mut rx: ActorReceiver<TiltifyCommand>,
+    directory: Directory,
+) -> anyhow::Result<()> {
+    let campaign = match (&settings.tiltify_token, &settings.tiltify_campaign_id) {
+        (Some(token), Some(campaign)) => {
+            Some((token.trim().to_owned(), campaign.trim().to_owned()))
+        }
+        _ => None,
+    };
+
+    let mut interval = campaign.as_ref().map(|_| {
+        tokio::time::interval(Duration::from_secs(
+            settings
+                .tiltify_poll_seconds
+                .unwrap_or(DEFAULT_POLL_SECONDS)
+                .max(5),
+        ))
+    });
+
+    match &campaign {
+        Some((_, id)) => log::info!("Polling Tiltify campaign {}", id),
+        None => log::info!("Tiltify polling is disabled"),
+    }
+
+    let recent_count = settings
+        .tiltify_recent_donations
+        .unwrap_or(DEFAULT_RECENT_DONATIONS);
+    let client = reqwest::Client::new();
+    let mut state = DonationState::default();
+    let mut failing = false;
+
+    loop {
+        tokio::select! {
+            _ = async { interval.as_mut().unwrap().tick().await }, if interval.is_some() => {
+                let (token, id) = campaign.as_ref().unwrap();
+                match poll_campaign(&client, token, id, recent_count).await {
+                    Ok(new_state) => {
+                        if failing {
+                            log::info!("Tiltify polling recovered");
+                            failing = false;
+                        }
+
+                        if let Err(e) = update_donations(&*db, &settings, &directory, &mut state, new_state).await {
+                            log::warn!("Failed to update donations: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        // Keep the last known total and only log when polling starts failing
+                        if !failing {
+                            log::warn!("Failed to poll Tiltify, keeping the last known total: {}", e);
+                            failing = true;
+                        }
+                    }
+                }
+            }
+            msg = rx.recv() => match msg {
+                Some((TiltifyCommand::GetDonations(rto), _)) => rto.reply(Ok(state.clone())),
+                None => break,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn poll_campaign(
+    client: &reqwest::Client,
+    token: &str,
+    campaign: &str,
+    recent_count: usize,
+) -> anyhow::Result<DonationState> {
+    let details: TiltifyResponse<TiltifyCampaign> = client
+        .get(format!("{}/campaigns/{}", TILTIFY_API_URL, campaign))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let donations: TiltifyResponse<Vec<TiltifyDonation>> = client
+        .get(format!(
+            "{}/campaigns/{}/donations",
+            TILTIFY_API_URL, campaign
+        ))
+        .query(&[("limit", recent_count)])
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let raised = details.data.amount_raised;
+    let total: f64 = raised
+        .value
+        .parse()
+        .map_err(|_| anyhow!("Invalid campaign total '{}'", raised.value))?;
+
+    let recent = donations
+        .data
+        .into_iter()
+        .take(recent_count)
+        .map(|d| Donation {
+            id: d.id,
+            donor_name: d.donor_name.unwrap_or_else(|| "Anonymous".to_owned()),
+            amount: d.amount.value.parse().unwrap_or_default(),
+            currency: d.amount.currency,
+            comment: d.donor_comment.filter(|c| !c.is_empty()),
+            completed_at: d.completed_at,
+        })
+        .collect();
+
+    Ok(DonationState {
+        total: Some(total),
+        total_formatted: Some(format_amount(total, &raised.currency)),
+        currency: Some(raised.currency),
+        recent,
+    })
+}
+
+/// Store a new donation state, announcing any milestones it passed
+/// and refreshing the state update and OBS text bindings if it changed
+async fn update_donations(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+    state: &mut DonationState,
+    new_state: DonationState,
+) -> anyhow::Result<()> {
+    let previous_total = state.total;
+    let total_changed = previous_total != new_state.total;
+    let recent_changed =
+        state.recent.first().map(|d| &d.id) != new_state.recent.first().map(|d| &d.id);
+    *state = new_state;
+
+    if !total_changed && !recent_changed {
+        return Ok(());
+    }
+
+    // Milestones that were already reached when polling started are not announced
+    if let (Some(previous), Some(total)) = (previous_total, state.total) {
+        let currency = state.currency.clone().unwrap_or_default();
+        for milestone in settings.donation_milestones.iter().flatten() {
+            if previous < *milestone && total >= *milestone {
+                announce_milestone(settings, directory, *milestone, total, &currency);
+            }
+        }
+    }
+
+    directory.web_actor.send(WebCommand::SendStateUpdate);
+    if total_changed {
+        for event in db.get_active_streamed_events().await? {
+            drop(send_nonblocking!(
+                directory.obs_actor,
+                ObsCommand,
+                UpdateText,
+                event
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn announce_milestone(
+    settings: &Settings,
+    directory: &Directory,
+    milestone: f64,
+    total: f64,
+    currency: &str,
+) {
+    log::info!("Donation milestone of {} reached", milestone);
+    directory.discord_actor.send(DiscordCommand::Notify(format!(
+        "\u{1f389} Donation milestone reached: {} (total {})",
+        format_amount(milestone, currency),
+        format_amount(total, currency)
+    )));
+
+    if let Some(url) = &settings.donation_webhook_url {
+        tokio::spawn(send_milestone_webhook(
+            url.clone(),
+            milestone,
+            total,
+            currency.to_owned(),
+        ));
+    }
+}
+
+async fn send_milestone_webhook(url: String, milestone: f64, total: f64, currency: String) {
+    let body = serde_json::json!({
+        "event": "donation_milestone",
+        "milestone": milestone,
+        "total": total,
+        "currency": currency,
+    });
+
+    if let Err(e) = reqwest::Client::new().post(&url).json(&body).send().await {
+        log::warn!("Failed to send donation webhook to {}: {}", url, e);
+    }
+}
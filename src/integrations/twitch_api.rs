@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::settings::Settings;
+
+const HELIX_API_URL: &str = "https://api.twitch.tv/helix";
+/// Longest description Twitch accepts for a stream marker
+const MAX_MARKER_DESCRIPTION_CHARS: usize = 140;
+
+#[derive(Deserialize)]
+struct HelixResponse<T> {
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct HelixUser {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct CreateMarker<'a> {
+    user_id: &'a str,
+    description: String,
+}
+
+/// Client ID and user access token for the Helix API, None if either is not set
+pub fn helix_credentials(settings: &Settings) -> Option<(&str, &str)> {
+    let client_id = settings.twitch_client_id.as_deref()?;
+    let token = settings.twitch_api_token.as_deref()?;
+    Some((client_id, token.trim_start_matches("oauth:")))
+}
+
+/// Create a marker in the live stream of a Twitch channel, by channel name.
+///
+/// Twitch refuses markers for channels that are offline.
+pub async fn create_stream_marker(
+    client_id: &str,
+    token: &str,
+    channel: &str,
+    description: &str,
+) -> anyhow::Result<()> {
+    let client = reqwest::Client::new();
+    let users: HelixResponse<HelixUser> = client
+        .get(format!("{}/users", HELIX_API_URL))
+        .query(&[("login", channel)])
+        .header("Client-Id", client_id)
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let Some(user) = users.data.first() else {
+        return Err(anyhow::anyhow!("No Twitch channel named {}", channel));
+    };
+
+    client
+        .post(format!("{}/streams/markers", HELIX_API_URL))
+        .header("Client-Id", client_id)
+        .bearer_auth(token)
+        .json(&CreateMarker {
+            user_id: &user.id,
+            description: description
+                .chars()
+                .take(MAX_MARKER_DESCRIPTION_CHARS)
+                .collect(),
+        })
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use twitch_irc::{
+    login::StaticLoginCredentials, message::ServerMessage, ClientConfig, SecureTCPTransport,
+    TwitchIRCClient,
+};
+
+use crate::{
+    core::{db::ProjectStore, settings::Settings, stream::StreamState},
+    integrations::therun::format_run_time,
+    ActorMessage, ActorReceiver, ActorRef,
+};
+
+/// Requests for the Twitch chat bot
+pub enum TwitchChatCommand {
+    /// Post a message in the chat of an OBS host, or of every host if None
+    Announce(Option<String>, String),
+}
+
+pub type TwitchChatActor = ActorRef<TwitchChatCommand>;
+
+impl ActorMessage for TwitchChatCommand {
+    fn label(&self) -> &'static str {
+        match self {
+            TwitchChatCommand::Announce(..) => "Announce",
+        }
+    }
+}
+
+type ChatClient = TwitchIRCClient<SecureTCPTransport, StaticLoginCredentials>;
+
+/// Default `twitch_command_cooldown_seconds`
+pub const DEFAULT_COMMAND_COOLDOWN_SECS: u64 = 30;
+
+/// Chat commands answered by the bot
+const CHAT_COMMANDS: &[&str] = &["!runners", "!game", "!commentators", "!estimate", "!pb"];
+
+/// Run the Twitch chat bot.
+///
+/// The bot joins the `twitch_channel` of every OBS host and answers chat commands
+/// using the stream running on that host. The IRC client reconnects by itself
+/// if the connection to Twitch drops.
+pub async fn run_twitch_chat(
+    settings: Arc<Settings>,
+    db: Arc<dyn ProjectStore>,
+    mut rx: ActorReceiver<TwitchChatCommand>,
+) -> anyhow::Result<()> {
+    let nick = settings
+        .twitch_bot_nick
+        .clone()
+        .ok_or_else(|| anyhow!("No 'twitch_bot_nick' specified in the settings file"))?;
+    let token = settings.twitch_oauth_token.clone().unwrap();
+    let token = token.trim().trim_start_matches("oauth:").to_owned();
+
+    let config = ClientConfig::new_simple(StaticLoginCredentials::new(
+        nick.to_lowercase(),
+        Some(token),
+    ));
+    let (mut incoming, client) = ChatClient::new(config);
+
+    // Channel name to OBS host
+    let channels: HashMap<String, String> = settings
+        .obs_hosts
+        .iter()
+        .filter_map(|(host, config)| {
+            config
+                .twitch_channel
+                .as_ref()
+                .map(|c| (c.to_lowercase(), host.clone()))
+        })
+        .collect();
+
+    if channels.is_empty() {
+        log::warn!(
+            "No OBS host has a 'twitch_channel', the Twitch chat bot will not join any chat"
+        );
+    }
+
+    for channel in channels.keys() {
+        log::info!("Joining Twitch chat for {}", channel);
+        client.join(channel.clone())?;
+    }
+
+    let cooldown = Duration::from_secs(
+        settings
+            .twitch_command_cooldown_seconds
+            .unwrap_or(DEFAULT_COMMAND_COOLDOWN_SECS),
+    );
+    let mut last_used: HashMap<(String, String), Instant> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            msg = incoming.recv() => match msg {
+                Some(ServerMessage::Privmsg(msg)) => {
+                    let Some(host) = channels.get(&msg.channel_login) else {
+                        continue;
+                    };
+
+                    let mut args = msg.message_text.trim().splitn(2, ' ');
+                    let command = args.next().unwrap_or_default().to_lowercase();
+                    let arg = args.next().unwrap_or_default().trim();
+
+                    if !CHAT_COMMANDS.contains(&command.as_str()) {
+                        continue;
+                    }
+
+                    let key = (msg.channel_login.clone(), command.clone());
+                    if last_used.get(&key).is_some_and(|t| t.elapsed() < cooldown) {
+                        log::debug!("{} is on cooldown in {}", command, msg.channel_login);
+                        continue;
+                    }
+                    last_used.insert(key, Instant::now());
+
+                    let reply = match get_command_reply(&*db, host, &command, arg).await {
+                        Ok(reply) => reply,
+                        Err(e) => {
+                            log::warn!("Failed to answer Twitch command {}: {}", command, e);
+                            continue;
+                        }
+                    };
+
+                    if let Err(e) = client.say(msg.channel_login.clone(), reply).await {
+                        log::warn!("Failed to send Twitch message to {}: {}", msg.channel_login, e);
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            },
+            cmd = rx.recv() => match cmd {
+                Some((TwitchChatCommand::Announce(host, text), _)) => {
+                    for (channel, channel_host) in &channels {
+                        if host.as_ref().is_none_or(|h| h == channel_host) {
+                            if let Err(e) = client.say(channel.clone(), text.clone()).await {
+                                log::warn!("Failed to send Twitch message to {}: {}", channel, e);
+                            }
+                        }
+                    }
+                }
+                None => break,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the stream running on an OBS host
+async fn get_host_stream(db: &dyn ProjectStore, host: &str) -> anyhow::Result<Option<StreamState>> {
+    for event in db.get_streamed_events().await? {
+        let stream = db.get_stream(event).await?;
+        if stream.obs_host == host && stream.active {
+            return Ok(Some(stream));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Build the reply to a chat command
+async fn get_command_reply(
+    db: &dyn ProjectStore,
+    host: &str,
+    command: &str,
+    arg: &str,
+) -> anyhow::Result<String> {
+    if command == "!pb" {
+        if arg.is_empty() {
+            return Ok("Usage: !pb <runner>".to_owned());
+        }
+
+        let Ok(runner) = db.find_runner(arg).await else {
+            return Ok(format!("No runner named {}", arg));
+        };
+
+        return Ok(
+            match db
+                .get_runner_run_data(runner.id)
+                .await
+                .ok()
+                .and_then(|r| r.pb)
+            {
+                Some(pb) => format!("{}'s PB is {}", runner.name, format_run_time(pb)),
+                None => format!("No PB found for {}", runner.name),
+            },
+        );
+    }
+
+    let Some(stream) = get_host_stream(db, host).await? else {
+        return Ok("Nothing is live right now".to_owned());
+    };
+    let event = db.get_event(stream.event).await?;
+
+    Ok(match command {
+        "!runners" => {
+            let mut slots: Vec<_> = stream.stream_runners.iter().collect();
+            slots.sort();
+            let mut names = vec![];
+            for (_, runner) in slots {
+                names.push(db.get_name_for_runner(*runner).await?);
+            }
+
+            if names.is_empty() {
+                "No runners are on screen".to_owned()
+            } else {
+                format!("Runners: {}", names.join(", "))
+            }
+        }
+        "!game" => match (&event.game, &event.category) {
+            (Some(game), Some(category)) => format!("{} - {}", game, category),
+            (Some(game), None) => game.to_owned(),
+            _ => format!("No game set for {}", event.name),
+        },
+        "!commentators" => {
+            let commentators: Vec<_> = stream
+                .get_commentators()
+                .into_iter()
+                .filter(|c| !c.is_empty())
+                .collect();
+
+            if commentators.is_empty() {
+                "No commentators right now".to_owned()
+            } else {
+                format!("Commentators: {}", commentators.join(", "))
+            }
+        }
+        "!estimate" => match event.format_estimate() {
+            Some(estimate) => format!("The estimate for {} is {}", event.name, estimate),
+            None => format!("No estimate set for {}", event.name),
+        },
+        _ => unreachable!(),
+    })
+}
@@ -1,35 +1,361 @@
-use crate::core::settings::Settings;
-use crate::core::{runner::RunnerRequest, stream::StreamRequest};
+use crate::core::backup::BackupRequest;
+use crate::core::export;
+use crate::core::health::{DiscordStatus, HealthLevel};
+use crate::core::i18n;
+use crate::core::project::{self, ImportMode, ProjectExport};
+use crate::core::settings::{AudioMonitorType, PublicStreamUrl, Settings};
+use crate::core::theme::Theme;
+use crate::core::trace;
+use crate::core::{
+    runner::{RunnerInfo, RunnerRequest, RunnerSelfUpdate, RunnerUpdate},
+    stream::{
+        get_stream_layout, StreamPreset, StreamRequest, StreamValidationError, StreamWarning,
+    },
+};
+use crate::error::Error;
 use crate::Rto;
-use std::{collections::HashMap, convert::Infallible, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
 
-use futures::{SinkExt, StreamExt};
+use futures::{stream::SplitStream, SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{broadcast::Receiver, mpsc::UnboundedReceiver};
-use warp::{http::Method, reply::WithStatus, Filter};
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::broadcast::{
+    error::{RecvError, TryRecvError},
+    Receiver,
+};
+use tracing::Instrument;
+use warp::{
+    http::Method,
+    hyper::{
+        server::conn::AddrStream,
+        service::{make_service_fn, service_fn},
+        Body, Request,
+    },
+    reply::WithStatus,
+    Filter, Reply,
+};
 
 use crate::{
     core::{
-        db::ProjectDb,
-        event::{Event, EventRequest},
+        db::{EventFilter, ProjectStore},
+        event::{format_chapters, Event, EventRequest, FullEvent, Incident, Marker},
         runner::Runner,
-        stream::StreamState,
+        stream::{Commentator, StreamState},
     },
-    send_message, ActorRef, Directory,
+    send_message, send_message_with_timeout, ActorMessage, ActorReceiver, ActorRef, Directory,
 };
 
 use super::{
-    obs::{ObsCommand, ObsHostState},
-    therun::Run,
+    obs::{LayoutSource, ObsCommand, ObsHostState},
+    therun::{Run, Split},
+    tiltify::{DonationState, TiltifyCommand},
+    web_cache::{content_etag, etag_matches, not_modified, revision_etag, with_cache_headers},
+    web_compression::{accepts_gzip, gzip_response},
+    web_ical::{export_schedule, ScheduleFilter},
+    web_rate_limit::{
+        limit_request, RateLimiter, DEFAULT_RATE_LIMIT_BURST, DEFAULT_RATE_LIMIT_PER_MINUTE,
+    },
+    web_timing::{timed_request, RequestTimings, DEFAULT_SLOW_REQUEST_MILLIS},
+    web_trigger::{check_trigger_token, run_trigger, ClientAddr, TriggerQuery, TriggerRefusal},
 };
 
+/// Default `web_port`
+pub const DEFAULT_WEB_PORT: u16 = 28010;
+
 #[derive(Serialize, Clone, Debug)]
-struct StateUpdate {
+pub(crate) struct StateUpdate {
     streams: Vec<StreamState>,
+    /// Event of the active stream on each OBS host
+    active_streams: HashMap<String, i64>,
     events: Vec<Event>,
     runners: HashMap<i64, Runner>,
     active_runs: HashMap<i64, Run>,
+    /// Runners whose run was reset, with the reset time in Unix millis,
+    /// until they start a new run
+    run_resets: HashMap<i64, i64>,
     hosts: HashMap<String, ObsHostState>,
+    /// Commentators of each stream by event ID
+    commentators: HashMap<i64, Vec<Commentator>>,
+    /// Time of the last successful database backup in Unix millis
+    last_backup: Option<i64>,
+    donations: DonationState,
+    /// State of the Discord connection, None if the bot is disabled
+    discord: Option<DiscordStatus>,
+    /// Project revision this state was read at, matching the ETag of REST snapshots
+    revision: i64,
+}
+
+/// Public view of an event, for overlays
+#[derive(Serialize, Clone, Debug)]
+struct PublicEvent {
+    id: i64,
+    name: String,
+    game: Option<String>,
+    category: Option<String>,
+    estimate: Option<i64>,
+    /// The estimate formatted for the event's locale
+    estimate_text: Option<String>,
+    /// Locale of on-stream text, English if None
+    locale: Option<String>,
+    /// Overlay theme, served as CSS by `/overlay/theme.css`
+    theme: Option<String>,
+    is_relay: bool,
+    is_marathon: bool,
+    /// Start and end times in Unix millis
+    event_start_time: Option<i64>,
+    timer_start_time: Option<i64>,
+    timer_end_time: Option<i64>,
+    runners: Vec<i64>,
+}
+
+/// Public view of a runner, for overlays
+#[derive(Serialize, Clone, Debug)]
+struct PublicRunner {
+    id: i64,
+    name: String,
+    location: Option<String>,
+    /// Social media handles by platform, without the `@`
+    socials: BTreeMap<String, String>,
+}
+
+/// Public view of a stream, for overlays
+#[derive(Serialize, Clone, Debug)]
+struct PublicStream {
+    event: i64,
+    /// Map of view IDs to runner IDs
+    stream_runners: HashMap<i64, i64>,
+    audible_runner: Option<i64>,
+    commentators: Vec<String>,
+    /// Whether the stream is the one shown on its OBS host
+    active: bool,
+    /// Where viewers can watch the stream's OBS host
+    public_stream_urls: Vec<PublicStreamUrl>,
+    /// The commentator hosting the commentary, if they are commentating
+    commentary_host: Option<String>,
+}
+
+/// Summary of a runner's active run, for overlays
+#[derive(Serialize, Clone, Debug)]
+struct PublicRun {
+    current_split_name: String,
+    current_split_index: i64,
+    split_count: usize,
+    delta: Option<f64>,
+    best_possible: Option<f64>,
+    pb: Option<f64>,
+    sob: Option<f64>,
+}
+
+/// A reduced StateUpdate that is safe to expose to unauthenticated overlays
+#[derive(Serialize, Clone, Debug)]
+struct PublicState {
+    events: Vec<PublicEvent>,
+    streams: Vec<PublicStream>,
+    runners: HashMap<i64, PublicRunner>,
+    active_runs: HashMap<i64, PublicRun>,
+    /// Runners whose run was reset, with the reset time in Unix millis
+    run_resets: HashMap<i64, i64>,
+    donations: DonationState,
+}
+
+/// State of a single event, for the read-only commentator view of that event
+#[derive(Serialize, Clone, Debug)]
+struct EventState {
+    event: PublicEvent,
+    stream: Option<PublicStream>,
+    /// Runners of the event in event order, then those only in its stream
+    runners: Vec<PublicRunner>,
+    /// Active runs of the event's runners by runner ID
+    active_runs: BTreeMap<i64, EventRun>,
+    /// Runners whose run was reset, with the reset time in Unix millis
+    run_resets: BTreeMap<i64, i64>,
+    /// Commentators of the event's stream in order
+    commentators: Vec<Commentator>,
+}
+
+/// A runner's active run with its splits, for the commentator view
+#[derive(Serialize, Clone, Debug)]
+struct EventRun {
+    current_split_name: String,
+    current_split_index: i64,
+    delta: Option<f64>,
+    best_possible: Option<f64>,
+    pb: Option<f64>,
+    sob: Option<f64>,
+    splits: Vec<Split>,
+}
+
+fn to_unix_millis(time: Option<OffsetDateTime>) -> Option<i64> {
+    time.map(|t| (t.unix_timestamp_nanos() / 1_000_000) as i64)
+}
+
+impl PublicState {
+    /// Project a full state update, optionally restricted to a single event
+    fn from_update(update: &StateUpdate, event: Option<i64>) -> Self {
+        let events: Vec<PublicEvent> = update
+            .events
+            .iter()
+            .filter(|e| event.map(|id| id == e.id).unwrap_or(true))
+            .map(|e| PublicEvent {
+                id: e.id,
+                name: e.name.clone(),
+                game: e.game.clone(),
+                category: e.category.clone(),
+                estimate: e.estimate,
+                estimate_text: e
+                    .estimate
+                    .map(|s| i18n::locale(e.locale.as_deref()).format_duration(s)),
+                locale: e.locale.clone(),
+                theme: e.theme.clone(),
+                is_relay: e.is_relay,
+                is_marathon: e.is_marathon,
+                event_start_time: to_unix_millis(e.event_start_time),
+                timer_start_time: to_unix_millis(e.timer_start_time),
+                timer_end_time: to_unix_millis(e.timer_end_time),
+                runners: e.runner_state.keys().cloned().collect(),
+            })
+            .collect();
+
+        let streams: Vec<PublicStream> = update
+            .streams
+            .iter()
+            .filter(|s| event.map(|id| id == s.event).unwrap_or(true))
+            .map(|s| PublicStream {
+                event: s.event,
+                stream_runners: s.stream_runners.clone(),
+                audible_runner: s.audible_runner,
+                commentators: s.get_commentators(),
+                active: s.active,
+                public_stream_urls: update
+                    .hosts
+                    .get(&s.obs_host)
+                    .map(|h| h.public_stream_urls.clone())
+                    .unwrap_or_default(),
+                commentary_host: update
+                    .events
+                    .iter()
+                    .find(|e| e.id == s.event)
+                    .and_then(|e| e.commentary_host.clone())
+                    .filter(|h| s.get_commentators().contains(h)),
+            })
+            .collect();
+
+        let visible_runner = |id: &i64| {
+            event.is_none()
+                || events.iter().any(|e| e.runners.contains(id))
+                || streams
+                    .iter()
+                    .any(|s| s.stream_runners.values().any(|r| r == id))
+        };
+
+        let runners = update
+            .runners
+            .iter()
+            .filter(|(id, _)| visible_runner(id))
+            .map(|(id, r)| {
+                (
+                    *id,
+                    PublicRunner {
+                        id: r.id,
+                        name: r.name.clone(),
+                        location: r.location.clone(),
+                        socials: r.socials.clone(),
+                    },
+                )
+            })
+            .collect();
+
+        let active_runs = update
+            .active_runs
+            .iter()
+            .filter(|(id, _)| visible_runner(id))
+            .map(|(id, run)| {
+                (
+                    *id,
+                    PublicRun {
+                        current_split_name: run.current_split_name.clone(),
+                        current_split_index: run.current_split_index,
+                        split_count: run.splits.len(),
+                        delta: run.delta,
+                        best_possible: run.best_possible,
+                        pb: run.pb,
+                        sob: run.sob,
+                    },
+                )
+            })
+            .collect();
+
+        let run_resets = update
+            .run_resets
+            .iter()
+            .filter(|(id, _)| visible_runner(id))
+            .map(|(id, time)| (*id, *time))
+            .collect();
+
+        Self {
+            donations: update.donations.clone(),
+            events,
+            streams,
+            runners,
+            active_runs,
+            run_resets,
+        }
+    }
+}
+
+impl EventState {
+    /// Project a full state update onto a single event, None if the event does not exist.
+    ///
+    /// Built on the event-restricted PublicState, so nothing of other events is included.
+    fn from_update(update: &StateUpdate, event: i64) -> Option<Self> {
+        let public = PublicState::from_update(update, Some(event));
+        let details = update.events.iter().find(|e| e.id == event)?;
+
+        let position = |id: &i64| {
+            details
+                .runner_order
+                .iter()
+                .position(|r| r == id)
+                .unwrap_or(usize::MAX)
+        };
+        let mut runners: Vec<PublicRunner> = public.runners.into_values().collect();
+        runners.sort_by_key(|r| (position(&r.id), r.id));
+
+        let active_runs = update
+            .active_runs
+            .iter()
+            .filter(|(id, _)| runners.iter().any(|r| r.id == **id))
+            .map(|(id, run)| {
+                (
+                    *id,
+                    EventRun {
+                        current_split_name: run.current_split_name.clone(),
+                        current_split_index: run.current_split_index,
+                        delta: run.delta,
+                        best_possible: run.best_possible,
+                        pb: run.pb,
+                        sob: run.sob,
+                        splits: run.splits.clone(),
+                    },
+                )
+            })
+            .collect();
+
+        Some(Self {
+            event: public.events.into_iter().next()?,
+            stream: public.streams.into_iter().next(),
+            runners,
+            active_runs,
+            run_resets: public.run_resets.into_iter().collect(),
+            commentators: update.commentators.get(&event).cloned().unwrap_or_default(),
+        })
+    }
 }
 
 /// A Json struct to store an event/runner ID
@@ -38,11 +364,120 @@ struct Id {
     id: i64,
 }
 
+/// A Json struct to delete an event/runner by ID
+#[derive(Serialize, Deserialize, Debug)]
+struct DeleteId {
+    id: i64,
+    /// Delete even if the entry is referenced elsewhere
+    #[serde(default)]
+    force: bool,
+}
+
 /// A Json struct to store values for a new stream
 #[derive(Serialize, Deserialize, Debug)]
 struct NewStream {
     event: i64,
     host: String,
+    /// Fill the stream's slots with the event's runners once it is created
+    #[serde(default)]
+    autofill: bool,
+}
+
+/// A Json struct to set or clear the host of an event's commentary
+#[derive(Serialize, Deserialize, Debug)]
+struct CommentaryHost {
+    event: i64,
+    host: Option<String>,
+}
+
+/// A Json struct to complete an event
+#[derive(Serialize, Deserialize, Debug)]
+struct CompleteEvent {
+    event: i64,
+    /// Whether to show the intermission scene of the event's OBS host
+    #[serde(default)]
+    intermission: bool,
+}
+
+/// A Json struct to reopen a completed event
+#[derive(Serialize, Deserialize, Debug)]
+struct ReopenEvent {
+    event: i64,
+}
+
+/// A Json struct to set the order of a stream's commentators
+#[derive(Serialize, Deserialize, Debug)]
+struct CommentatorOrder {
+    event: i64,
+    /// Commentator names, first to last
+    commentators: Vec<String>,
+}
+
+/// A Json struct to rebuild the OBS layout of a stream
+#[derive(Serialize, Deserialize, Debug)]
+struct StreamResync {
+    event: i64,
+    /// Remove and recreate the runner sources on the stream's host
+    #[serde(default)]
+    purge: bool,
+}
+
+/// A Json struct to set the order of an event's runners
+#[derive(Serialize, Deserialize, Debug)]
+struct RunnerOrder {
+    event: i64,
+    /// Runner IDs, first to last
+    runners: Vec<i64>,
+}
+
+/// A Json struct to set the sync offset of a runner in a stream
+#[derive(Serialize, Deserialize, Debug)]
+struct SyncOffset {
+    event: i64,
+    runner: i64,
+    /// Delay of the runner's feed, clamped to 0-15000
+    offset_ms: u32,
+}
+
+/// A Json struct naming a runner to put on or take off deck for a stream
+#[derive(Serialize, Deserialize, Debug)]
+struct OnDeckRunner {
+    event: i64,
+    runner: i64,
+}
+
+/// A Json struct naming a stream preset
+#[derive(Serialize, Deserialize, Debug)]
+struct PresetName {
+    name: String,
+}
+
+/// A Json struct naming an overlay theme
+#[derive(Serialize, Deserialize, Debug)]
+struct ThemeName {
+    name: String,
+}
+
+/// A Json struct of a Discord user that is never shown as a commentator
+#[derive(Serialize, Deserialize, Debug)]
+struct IgnoredDiscordUser {
+    discord_id: String,
+    /// Name to recognize the user by in lists
+    #[serde(default)]
+    name: String,
+}
+
+/// Query of the theme stylesheet of an event
+#[derive(Deserialize, Debug)]
+struct ThemeCssQuery {
+    event: i64,
+}
+
+/// A Json struct to apply a stream preset to the stream of an event
+#[derive(Serialize, Deserialize, Debug)]
+struct ApplyPreset {
+    event: i64,
+    preset: String,
 }
 
 /// A Json struct to set the streaming state of an OBS host
@@ -52,15 +487,181 @@ struct SetStreamingState {
     streaming: bool,
 }
 
+/// Query arguments for requests that can skip validation
+#[derive(Serialize, Deserialize, Debug)]
+struct ForceArg {
+    #[serde(default)]
+    force: bool,
+}
+
+/// A Json struct to move a source to a layer of an OBS scene
+#[derive(Serialize, Deserialize, Debug)]
+struct SetSourceIndex {
+    host: String,
+    scene: String,
+    source: String,
+    index: u32,
+}
+
+/// A Json struct to control the replay buffer of an OBS host.
+///
+/// Saves a replay if `enabled` is not set.
+#[derive(Serialize, Deserialize, Debug)]
+struct ReplayRequest {
+    host: String,
+    enabled: Option<bool>,
+}
+
+/// A Json struct to open a projector on an OBS host.
+///
+/// `target` is "program", "preview" or a scene name.
+/// The projector is windowed if no monitor is given.
+#[derive(Serialize, Deserialize, Debug)]
+struct ProjectorRequest {
+    host: String,
+    target: String,
+    monitor: Option<u32>,
+}
+
+/// A Json struct to start or stop the virtual camera of an OBS host
+#[derive(Serialize, Deserialize, Debug)]
+struct VirtualCamRequest {
+    host: String,
+    enabled: bool,
+}
+
+/// A Json struct to show a scene on an OBS host
+#[derive(Serialize, Deserialize, Debug)]
+struct SceneRequest {
+    host: String,
+    scene: String,
+}
+
+/// A Json struct to switch the scene collection or profile of an OBS host
+#[derive(Serialize, Deserialize, Debug)]
+struct HostConfigRequest {
+    host: String,
+    name: String,
+}
+
+/// Query arguments naming a runner by ID
+#[derive(Serialize, Deserialize, Debug)]
+struct RunnerId {
+    id: i64,
+}
+
+/// Json struct naming the runner to create a self-service link for
+#[derive(Serialize, Deserialize, Debug)]
+struct SelfTokenRequest {
+    runner_id: i64,
+}
+
+/// Json struct to poll a runner on TheRun.gg or not, regardless of their streams
+#[derive(Serialize, Deserialize, Debug)]
+struct TheRunPollRequest {
+    runner_id: i64,
+    enabled: bool,
+}
+
+/// A self-service link for a runner
+#[derive(Serialize, Debug)]
+struct SelfServiceLink {
+    /// Path of the link on this server
+    url: String,
+    /// Expiry time in Unix millis
+    expires_at: Option<i64>,
+}
+
+/// What a runner sees when opening their self-service link
+#[derive(Serialize, Debug)]
+struct SelfServiceView {
+    name: String,
+    stream: Option<String>,
+    therun: Option<String>,
+    /// Whether the link can still be used
+    pending: bool,
+    /// Expiry time in Unix millis
+    expires_at: Option<i64>,
+}
+
+/// The audio settings sent to OBS for a runner
+#[derive(Serialize, Debug)]
+struct RunnerAudio {
+    runner: i64,
+    volume_percent: u32,
+    /// Volume multiplier sent to OBS
+    volume_mul: f32,
+    /// The multiplier in dB, None for silence
+    volume_db: Option<f32>,
+    monitor_type: Option<AudioMonitorType>,
+}
+
+/// A Json struct naming an OBS host
+#[derive(Serialize, Deserialize, Debug)]
+struct HostName {
+    host: String,
+}
+
+/// Query of the layouts of an OBS host
+#[derive(Serialize, Deserialize, Debug)]
+struct HostLayoutsQuery {
+    host: String,
+    /// Read from OBS or the last snapshot, defaults to the layouts last read from OBS
+    /// when the host is connected
+    source: Option<LayoutSource>,
+}
+
+/// Query of the orphaned runner sources of an OBS host
+#[derive(Serialize, Deserialize, Debug)]
+struct OrphanQuery {
+    host: String,
+    /// Remove the orphans rather than only reporting them
+    #[serde(default)]
+    apply: bool,
+}
+
+/// A Json struct to set the browser sources of a group on an OBS host
+#[derive(Serialize, Deserialize, Debug)]
+struct BrowserSourceGroupArgs {
+    host: String,
+    group: String,
+    /// Values of the `{param}` placeholders of the group
+    #[serde(default)]
+    params: HashMap<String, String>,
+}
+
+/// A Json struct to show or hide a filter of a source on an OBS host
+#[derive(Serialize, Deserialize, Debug)]
+struct SourceFilterArgs {
+    host: String,
+    source: String,
+    filter: String,
+    enabled: bool,
+}
+
+/// Query of the OBS changes a full update of an event's stream would make
+#[derive(Serialize, Deserialize, Debug)]
+struct StreamPlanQuery {
+    event: i64,
+}
+
 pub enum WebCommand {
     SendStateUpdate,
 }
 
 pub type WebActor = ActorRef<WebCommand>;
 
+impl ActorMessage for WebCommand {
+    fn label(&self) -> &'static str {
+        match self {
+            WebCommand::SendStateUpdate => "SendStateUpdate",
+        }
+    }
+}
+
 async fn get_event_by_args(
     args: HashMap<String, String>,
-    db: &ProjectDb,
+    db: &dyn ProjectStore,
 ) -> Result<Event, WithStatus<String>> {
     if let Some(event) = args.get("id") {
         match event.parse::<i64>() {
@@ -98,16 +699,31 @@ async fn get_event_by_args(
     }
 }
 
+/// The status code for an error, distinguishing actors that did not reply in time
+fn error_status(e: &anyhow::Error) -> warp::http::StatusCode {
+    match e.downcast_ref::<Error>() {
+        Some(Error::ActorTimeout(..)) => warp::http::StatusCode::GATEWAY_TIMEOUT,
+        Some(Error::InvalidRequest(..)) => warp::http::StatusCode::BAD_REQUEST,
+        Some(Error::Conflict(..)) => warp::http::StatusCode::CONFLICT,
+        _ => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// The body of an error reply, which for a conflicting edit is the current copy of the item
+fn error_body(e: &anyhow::Error) -> String {
+    match e.downcast_ref::<Error>() {
+        Some(Error::Conflict(_, current)) => current.to_string(),
+        _ => e.to_string(),
+    }
+}
+
 fn to_http_none_or_error(result: anyhow::Result<()>) -> Result<impl warp::Reply, Infallible> {
     match result {
         Ok(_) => Ok(warp::reply::with_status(
             "Success".to_string(),
             warp::http::StatusCode::OK,
         )),
-        Err(e) => Ok(warp::reply::with_status(
-            e.to_string(),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        )),
+        Err(e) => Ok(warp::reply::with_status(error_body(&e), error_status(&e))),
     }
 }
 
@@ -117,10 +733,7 @@ fn to_http_output<T: Serialize>(result: anyhow::Result<T>) -> Result<impl warp::
             serde_json::to_string::<T>(&data).unwrap(),
             warp::http::StatusCode::OK,
         )),
-        Err(e) => Ok(warp::reply::with_status(
-            e.to_string(),
-            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-        )),
+        Err(e) => Ok(warp::reply::with_status(error_body(&e), error_status(&e))),
     }
 }
 
@@ -133,16 +746,76 @@ async fn create_event(event: Event, directory: Directory) -> Result<impl warp::R
     ))
 }
 
+async fn set_commentary_host(
+    args: CommentaryHost,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.event_actor,
+        EventRequest,
+        SetHost,
+        args.event,
+        args.host
+    ))
+}
+
+async fn complete_event(
+    args: CompleteEvent,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.event_actor,
+        EventRequest,
+        Complete,
+        args.event,
+        args.intermission
+    ))
+}
+
+async fn reopen_event(
+    args: ReopenEvent,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.event_actor,
+        EventRequest,
+        Reopen,
+        args.event
+    ))
+}
+
+async fn create_full_event(
+    full: FullEvent,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(send_message!(
+        directory.event_actor,
+        EventRequest,
+        CreateFull,
+        full
+    ))
+}
+
 async fn get_event(
     args: HashMap<String, String>,
-    db: Arc<ProjectDb>,
-) -> Result<impl warp::Reply, Infallible> {
-    match get_event_by_args(args, &db).await {
-        Ok(event) => Ok(warp::reply::with_status(
-            serde_json::to_string::<Event>(&event).unwrap(),
-            warp::http::StatusCode::OK,
+    if_none_match: Option<String>,
+    db: Arc<dyn ProjectStore>,
+) -> Result<warp::reply::Response, Infallible> {
+    let etag = revision_etag(db.get_revision());
+    if etag_matches(if_none_match.as_deref(), &etag) {
+        return Ok(not_modified(&etag));
+    }
+
+    match get_event_by_args(args, &*db).await {
+        Ok(event) => Ok(with_cache_headers(
+            warp::reply::with_status(
+                serde_json::to_string::<Event>(&event).unwrap(),
+                warp::http::StatusCode::OK,
+            ),
+            &etag,
+            Some(db.get_revised_at()),
         )),
-        Err(reply) => Ok(reply),
+        Err(reply) => Ok(reply.into_response()),
     }
 }
 
@@ -167,128 +840,1135 @@ async fn delete_event(
     ))
 }
 
-async fn create_runner(
-    runner: Runner,
-    directory: Directory,
+async fn get_incidents(
+    args: HashMap<String, String>,
+    db: Arc<dyn ProjectStore>,
 ) -> Result<impl warp::Reply, Infallible> {
-    to_http_none_or_error(send_message!(
-        directory.runner_actor,
-        RunnerRequest,
-        Create,
-        runner
-    ))
+    match get_event_by_args(args, &*db).await {
+        Ok(event) => Ok(warp::reply::with_status(
+            serde_json::to_string(&event.incidents).unwrap(),
+            warp::http::StatusCode::OK,
+        )),
+        Err(reply) => Ok(reply),
+    }
 }
 
-async fn update_runner(
-    runner: Runner,
+async fn add_incident(
+    incident: Incident,
     directory: Directory,
 ) -> Result<impl warp::Reply, Infallible> {
-    to_http_none_or_error(send_message!(
-        directory.runner_actor,
-        RunnerRequest,
-        Update,
-        runner
+    to_http_output(send_message!(
+        directory.event_actor,
+        EventRequest,
+        AddIncident,
+        incident
     ))
 }
 
-async fn delete_runner(
-    runner_name: Id,
+async fn update_incident(
+    incident: Incident,
     directory: Directory,
 ) -> Result<impl warp::Reply, Infallible> {
     to_http_none_or_error(send_message!(
-        directory.runner_actor,
-        RunnerRequest,
-        Delete,
-        runner_name.id
+        directory.event_actor,
+        EventRequest,
+        UpdateIncident,
+        incident
     ))
 }
 
-async fn create_stream(
-    stream: NewStream,
-    directory: Directory,
-) -> Result<impl warp::Reply, Infallible> {
+async fn delete_incident(id: Id, directory: Directory) -> Result<impl warp::Reply, Infallible> {
     to_http_none_or_error(send_message!(
-        directory.stream_actor,
-        StreamRequest,
-        Create,
+        directory.event_actor,
+        EventRequest,
+        DeleteIncident,
+        id.id
+    ))
+}
+
+async fn add_marker(marker: Marker, directory: Directory) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(send_message!(
+        directory.event_actor,
+        EventRequest,
+        AddMarker,
+        marker
+    ))
+}
+
+/// List the markers of an event as JSON, or as YouTube chapters with
+/// `format=youtube_chapters`
+async fn get_markers(
+    args: HashMap<String, String>,
+    db: Arc<dyn ProjectStore>,
+) -> Result<warp::reply::Response, Infallible> {
+    let event = match get_event_by_args(args.clone(), &*db).await {
+        Ok(event) => event,
+        Err(reply) => return Ok(reply.into_response()),
+    };
+    let markers = match db.get_markers(event.id).await {
+        Ok(markers) => markers,
+        Err(e) => {
+            return Ok(warp::reply::with_status(error_body(&e), error_status(&e)).into_response())
+        }
+    };
+
+    match args.get("format").map(|f| f.as_str()) {
+        None | Some("json") => Ok(warp::reply::json(&markers).into_response()),
+        Some("youtube_chapters") => Ok(warp::reply::with_header(
+            format_chapters(&markers),
+            "Content-Type",
+            "text/plain; charset=utf-8",
+        )
+        .into_response()),
+        Some(format) => Ok(warp::reply::with_status(
+            format!("Unknown format '{}', use json or youtube_chapters", format),
+            warp::http::StatusCode::BAD_REQUEST,
+        )
+        .into_response()),
+    }
+}
+
+async fn create_runner(
+    runner: Runner,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        Create,
+        runner
+    ))
+}
+
+async fn update_runner(
+    runner: RunnerUpdate,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        Edit,
+        runner
+    ))
+}
+
+async fn delete_runner(
+    runner: DeleteId,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        Delete,
+        runner.id,
+        runner.force
+    ))
+}
+
+async fn get_runners(
+    args: HashMap<String, String>,
+    if_none_match: Option<String>,
+    db: Arc<dyn ProjectStore>,
+) -> Result<warp::reply::Response, Infallible> {
+    // Read before the runners, so the tag is never newer than the list
+    let etag = revision_etag(db.get_revision());
+    if etag_matches(if_none_match.as_deref(), &etag) {
+        return Ok(not_modified(&etag));
+    }
+    let last_modified = Some(db.get_revised_at());
+
+    let include_archived = args.get("include_archived").is_some_and(|a| a == "true");
+    let search = args.get("search").map(|s| s.as_str());
+    let limit = args.get("limit").and_then(|l| l.parse().ok());
+    let offset = args.get("offset").and_then(|o| o.parse().ok());
+
+    // Without search or paging, keep returning a plain list
+    if search.is_none() && limit.is_none() && offset.is_none() {
+        return to_http_output(db.get_runners().await.map(|runners| {
+            runners
+                .into_iter()
+                .filter(|r| include_archived || !r.archived)
+                .collect::<Vec<_>>()
+        }))
+        .map(|r| with_cache_headers(r, &etag, last_modified));
+    }
+
+    to_http_output(
+        db.find_runners(search, include_archived, limit, offset)
+            .await,
+    )
+    .map(|r| with_cache_headers(r, &etag, last_modified))
+}
+
+async fn get_events(
+    filter: EventFilter,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(db.find_events(&filter).await)
+}
+
+async fn get_runner_info(
+    args: RunnerId,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(RunnerInfo::load(&*db, args.id).await)
+}
+
+async fn create_self_token(
+    request: SelfTokenRequest,
+    authorization: Option<String>,
+    settings: Arc<Settings>,
+    directory: Directory,
+) -> Result<warp::reply::Response, Infallible> {
+    if let Err(reply) = check_admin_token(authorization, &settings) {
+        return Ok(reply.into_response());
+    }
+
+    let runner = request.runner_id;
+    let link = send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        CreateSelfToken,
+        runner
+    )
+    .map(|token| SelfServiceLink {
+        url: format!("/self/{}", token.token),
+        expires_at: to_unix_millis(token.expires_at),
+    });
+    Ok(to_http_output(link).into_response())
+}
+
+async fn set_therun_polling(
+    args: TheRunPollRequest,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        SetPolling,
+        args.runner_id,
+        args.enabled
+    ))
+}
+
+async fn get_therun_status(directory: Directory) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        GetPollStatus
+    ))
+}
+
+async fn get_self_service(
+    token: String,
+    db: Arc<dyn ProjectStore>,
+) -> Result<warp::reply::Response, Infallible> {
+    let token = match db.get_runner_self_token(&token).await {
+        Ok(Some(token)) => token,
+        Ok(None) => {
+            return Ok(warp::reply::with_status(
+                "Unknown self-service link".to_string(),
+                warp::http::StatusCode::NOT_FOUND,
+            )
+            .into_response())
+        }
+        Err(e) => return Ok(to_http_output::<()>(Err(e)).into_response()),
+    };
+
+    let view = db
+        .get_runner(token.runner)
+        .await
+        .map(|runner| SelfServiceView {
+            name: runner.name,
+            stream: runner.stream,
+            therun: runner.therun,
+            pending: token.is_pending(),
+            expires_at: to_unix_millis(token.expires_at),
+        });
+    Ok(to_http_output(view).into_response())
+}
+
+async fn self_update_runner(
+    token: String,
+    update: RunnerSelfUpdate,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.runner_actor,
+        RunnerRequest,
+        SelfUpdate,
+        token,
+        update
+    ))
+}
+
+async fn get_runner_audio(
+    args: RunnerId,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(db.get_runner(args.id).await.map(|runner| {
+        let volume_mul = runner.get_volume_mul();
+        RunnerAudio {
+            runner: runner.id,
+            volume_percent: runner.volume_percent,
+            volume_mul,
+            volume_db: (volume_mul > 0.0).then(|| 20.0 * volume_mul.log10()),
+            monitor_type: runner.monitor_type,
+        }
+    }))
+}
+
+async fn create_stream(
+    stream: NewStream,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    let event = stream.event;
+    let result = send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        Create,
         stream.event,
         stream.host
+    );
+    let result = match result {
+        Ok(_) if stream.autofill => send_message!(
+            directory.stream_actor,
+            StreamRequest,
+            AutofillFromEvent,
+            event
+        ),
+        result => result,
+    };
+    to_http_none_or_error(result)
+}
+
+async fn update_stream(
+    stream: StreamState,
+    args: ForceArg,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    let force = args.force;
+    to_stream_update_output(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        Update,
+        stream,
+        force
+    ))
+}
+
+/// Reply to a stream update with the runners moved to fit the stream's layout,
+/// listing the violations if validation failed
+fn to_stream_update_output(
+    result: anyhow::Result<Vec<StreamWarning>>,
+) -> Result<impl warp::Reply, Infallible> {
+    match result {
+        Ok(warnings) => Ok(warp::reply::with_status(
+            serde_json::json!({ "warnings": warnings }).to_string(),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => match e.downcast_ref::<StreamValidationError>() {
+            Some(violations) => Ok(warp::reply::with_status(
+                serde_json::to_string(&violations.0).unwrap(),
+                warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+            )),
+            None => Ok(warp::reply::with_status(error_body(&e), error_status(&e))),
+        },
+    }
+}
+
+async fn delete_stream(event: Id, directory: Directory) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        Delete,
+        event.id
+    ))
+}
+
+async fn activate_stream(event: Id, directory: Directory) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        Activate,
+        event.id
+    ))
+}
+
+async fn resync_stream(
+    resync: StreamResync,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        ForceResync,
+        resync.event,
+        resync.purge
+    ))
+}
+
+async fn get_stream_plan(
+    query: StreamPlanQuery,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        PlanUpdate,
+        query.event
+    ))
+}
+
+async fn reorder_commentators(
+    order: CommentatorOrder,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        ReorderCommentators,
+        order.event,
+        order.commentators
+    ))
+}
+
+async fn reorder_event_runners(
+    order: RunnerOrder,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.event_actor,
+        EventRequest,
+        ReorderRunners,
+        order.event,
+        order.runners
+    ))
+}
+
+async fn set_sync_offset(
+    offset: SyncOffset,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        SetSyncOffset,
+        offset.event,
+        offset.runner,
+        offset.offset_ms
+    ))
+}
+
+async fn add_on_deck(
+    on_deck: OnDeckRunner,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        AddOnDeck,
+        on_deck.event,
+        on_deck.runner
+    ))
+}
+
+async fn remove_on_deck(
+    on_deck: OnDeckRunner,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        RemoveOnDeck,
+        on_deck.event,
+        on_deck.runner
+    ))
+}
+
+async fn get_stream_presets(db: Arc<dyn ProjectStore>) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(db.get_stream_presets().await)
+}
+
+async fn save_stream_preset(
+    preset: StreamPreset,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(db.save_stream_preset(&preset).await)
+}
+
+async fn delete_stream_preset(
+    preset: PresetName,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(db.delete_stream_preset(&preset.name).await)
+}
+
+async fn get_themes(db: Arc<dyn ProjectStore>) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(db.get_themes().await)
+}
+
+async fn save_theme(
+    theme: Theme,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(db.save_theme(&theme).await)
+}
+
+async fn get_ignored_discord_users(
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(db.get_ignored_discord_users().await.map(|users| {
+        users
+            .into_iter()
+            .map(|(discord_id, name)| IgnoredDiscordUser { discord_id, name })
+            .collect::<Vec<_>>()
+    }))
+}
+
+/// Ignore a Discord user, which applies when their voice channel next changes
+async fn add_ignored_discord_user(
+    user: IgnoredDiscordUser,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(
+        db.add_ignored_discord_user(&user.discord_id, &user.name)
+            .await,
+    )
+}
+
+async fn remove_ignored_discord_user(
+    user: IgnoredDiscordUser,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(
+        match db.remove_ignored_discord_user(&user.discord_id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::InvalidRequest(
+                "discord_id".to_owned(),
+                format!("Discord user {} is not ignored", user.discord_id),
+            )
+            .into()),
+            Err(e) => Err(e),
+        },
+    )
+}
+
+async fn delete_theme(
+    theme: ThemeName,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(db.delete_theme(&theme.name).await)
+}
+
+/// Serve the theme of an event as CSS custom properties, for overlays to import.
+/// Events without a theme, or with a theme that no longer exists, get an empty `:root`.
+async fn get_theme_css(
+    query: ThemeCssQuery,
+    if_none_match: Option<String>,
+    db: Arc<dyn ProjectStore>,
+) -> Result<warp::reply::Response, Infallible> {
+    let event = match db.get_event(query.event).await {
+        Ok(event) => event,
+        Err(_) => {
+            return Ok(warp::reply::with_status(
+                "Failed to find event by ID".to_string(),
+                warp::http::StatusCode::NOT_FOUND,
+            )
+            .into_response())
+        }
+    };
+
+    let theme = match &event.theme {
+        Some(name) => match db.get_theme(name).await {
+            Ok(theme) => theme,
+            Err(e) => {
+                return Ok(warp::reply::with_status(
+                    e.to_string(),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                )
+                .into_response())
+            }
+        },
+        None => None,
+    };
+
+    let etag = match &theme {
+        Some(theme) => format!("\"{}-{}\"", theme.name, theme.updated_at),
+        None => "\"none\"".to_owned(),
+    };
+    if etag_matches(if_none_match.as_deref(), &etag) {
+        return Ok(not_modified(&etag));
+    }
+
+    let css = theme
+        .map(|t| t.to_css())
+        .unwrap_or_else(|| ":root {\n}\n".to_owned());
+    let reply = warp::reply::with_header(css, "Content-Type", "text/css; charset=utf-8");
+    let reply = warp::reply::with_header(reply, "Cache-Control", "no-cache");
+    Ok(warp::reply::with_header(reply, "ETag", etag).into_response())
+}
+
+async fn apply_stream_preset(
+    args: ApplyPreset,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_stream_update_output(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        ApplyPreset,
+        args.event,
+        args.preset.clone()
+    ))
+}
+
+/// Summarize the health of every subsystem, with a status code
+/// of 200, 207 or 503 for ok, degraded or down
+async fn get_health(
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    let report = directory.health.report(&*db, &settings).await;
+    let status = match report.status {
+        HealthLevel::Ok => warp::http::StatusCode::OK,
+        HealthLevel::Degraded => warp::http::StatusCode::MULTI_STATUS,
+        HealthLevel::Down => warp::http::StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    Ok(warp::reply::with_status(
+        serde_json::to_string(&report).unwrap(),
+        status,
+    ))
+}
+
+/// Check the admin token of a debug endpoint, returning the error reply if it is not accepted
+fn check_admin_token(
+    authorization: Option<String>,
+    settings: &Settings,
+) -> Result<(), warp::reply::WithStatus<String>> {
+    let Some(token) = &settings.admin_token else {
+        return Err(warp::reply::with_status(
+            "No 'admin_token' is set, debug endpoints are disabled".to_string(),
+            warp::http::StatusCode::FORBIDDEN,
+        ));
+    };
+
+    if authorization
+        .as_deref()
+        .and_then(|a| a.strip_prefix("Bearer "))
+        != Some(token.as_str())
+    {
+        return Err(warp::reply::with_status(
+            "Invalid admin token".to_string(),
+            warp::http::StatusCode::UNAUTHORIZED,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Run a `/trigger/...` endpoint, replying with a short text outcome.
+///
+/// Triggers are plain GET requests so they can be fired by Stream Deck buttons and hotkeys,
+/// which cannot send headers. The query string token is their only guard.
+async fn trigger(
+    action: String,
+    query: TriggerQuery,
+    addr: Option<ClientAddr>,
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    let addr = addr.map_or("unknown address".to_owned(), |a| a.0.to_string());
+    if let Err(refusal) = check_trigger_token(&query, &settings) {
+        log::warn!("Refused trigger {} from {}", action, addr);
+        return Ok(match refusal {
+            TriggerRefusal::Disabled => warp::reply::with_status(
+                "No 'trigger_token' is set, triggers are disabled".to_string(),
+                warp::http::StatusCode::FORBIDDEN,
+            ),
+            TriggerRefusal::InvalidToken => warp::reply::with_status(
+                "Invalid trigger token".to_string(),
+                warp::http::StatusCode::UNAUTHORIZED,
+            ),
+        });
+    }
+
+    match run_trigger(&action, &query, &*db, &settings, &directory).await {
+        Ok(outcome) => {
+            log::info!("Trigger {} from {}: {}", action, addr, outcome);
+            Ok(warp::reply::with_status(
+                outcome,
+                warp::http::StatusCode::OK,
+            ))
+        }
+        Err(e) => {
+            log::warn!("Trigger {} from {} failed: {}", action, addr, e);
+            Ok(warp::reply::with_status(error_body(&e), error_status(&e)))
+        }
+    }
+}
+
+async fn get_request_timings(
+    authorization: Option<String>,
+    settings: Arc<Settings>,
+    timings: Arc<RequestTimings>,
+) -> Result<impl warp::Reply, Infallible> {
+    if let Err(reply) = check_admin_token(authorization, &settings) {
+        return Ok(reply);
+    }
+
+    Ok(warp::reply::with_status(
+        serde_json::to_string(&timings.get_slowest()).unwrap(),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+async fn get_actor_stats(
+    authorization: Option<String>,
+    settings: Arc<Settings>,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    if let Err(reply) = check_admin_token(authorization, &settings) {
+        return Ok(reply);
+    }
+
+    Ok(warp::reply::with_status(
+        serde_json::to_string(&directory.actor_stats()).unwrap(),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+/// Replay a recorded message trace, keeping OBS untouched unless `dry_run=false` is given
+async fn replay_trace(
+    args: HashMap<String, String>,
+    authorization: Option<String>,
+    body: warp::hyper::body::Bytes,
+    settings: Arc<Settings>,
+    directory: Directory,
+) -> Result<warp::reply::Response, Infallible> {
+    if let Err(reply) = check_admin_token(authorization, &settings) {
+        return Ok(reply.into_response());
+    }
+
+    let dry_run = args.get("dry_run").map(|d| d != "false").unwrap_or(true);
+    let trace = String::from_utf8_lossy(&body);
+    log::info!("Replaying message trace, dry run: {}", dry_run);
+    Ok(warp::reply::json(&trace::replay(&directory, &trace, dry_run).await).into_response())
+}
+
+async fn get_cache_stats(
+    authorization: Option<String>,
+    settings: Arc<Settings>,
+    db: Arc<dyn ProjectStore>,
+) -> Result<impl warp::Reply, Infallible> {
+    if let Err(reply) = check_admin_token(authorization, &settings) {
+        return Ok(reply);
+    }
+
+    Ok(warp::reply::with_status(
+        serde_json::to_string(&db.get_cache_stats()).unwrap(),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+async fn get_recent_donations(directory: Directory) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(send_message!(
+        directory.tiltify_actor,
+        TiltifyCommand,
+        GetDonations
+    ))
+}
+
+async fn get_hosts(
+    if_none_match: Option<String>,
+    directory: Directory,
+) -> Result<warp::reply::Response, Infallible> {
+    let hosts = match send_message!(directory.obs_actor, ObsCommand, GetState) {
+        Ok(hosts) => hosts,
+        Err(e) => {
+            return Ok(warp::reply::with_status(error_body(&e), error_status(&e)).into_response())
+        }
+    };
+
+    // OBS state is not part of the project revision, so it is tagged by content,
+    // sorted so the same state always serializes the same way
+    let body = serde_json::to_string(&hosts.into_iter().collect::<BTreeMap<_, _>>()).unwrap();
+    let etag = content_etag(&body);
+    if etag_matches(if_none_match.as_deref(), &etag) {
+        return Ok(not_modified(&etag));
+    }
+    Ok(with_cache_headers(
+        warp::reply::with_status(body, warp::http::StatusCode::OK),
+        &etag,
+        None,
+    ))
+}
+
+async fn get_host_scenes(
+    args: HashMap<String, String>,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    match args.get("host") {
+        Some(host) => match send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            GetSceneNames,
+            host.to_owned()
+        ) {
+            Ok(scenes) => Ok(warp::reply::with_status(
+                serde_json::to_string(&scenes).unwrap(),
+                warp::http::StatusCode::OK,
+            )),
+            Err(e) => Ok(warp::reply::with_status(error_body(&e), error_status(&e))),
+        },
+        None => Ok(warp::reply::with_status(
+            "Missing 'host' field".to_string(),
+            warp::http::StatusCode::BAD_REQUEST,
+        )),
+    }
+}
+
+async fn set_streaming_state(
+    streaming: SetStreamingState,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    if streaming.streaming {
+        to_http_none_or_error(send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            StartStream,
+            streaming.host
+        ))
+    } else {
+        to_http_none_or_error(send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            EndStream,
+            streaming.host
+        ))
+    }
+}
+
+async fn set_source_index(
+    args: SetSourceIndex,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        SetSourceIndex,
+        args.host,
+        args.scene,
+        args.source,
+        args.index
+    ))
+}
+
+async fn open_projector(
+    args: ProjectorRequest,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        OpenProjector,
+        args.host,
+        args.target,
+        args.monitor
+    ))
+}
+
+async fn set_virtual_cam(
+    args: VirtualCamRequest,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        SetVirtualCamEnabled,
+        args.host,
+        args.enabled
+    ))
+}
+
+async fn switch_scene(
+    args: SceneRequest,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        SwitchScene,
+        args.host,
+        args.scene
+    ))
+}
+
+async fn resume_layout(
+    args: HostName,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        ResumeLayout,
+        args.host
+    ))
+}
+
+async fn set_scene_collection(
+    args: HostConfigRequest,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        SetSceneCollection,
+        args.host,
+        args.name
+    ))
+}
+
+async fn set_profile(
+    args: HostConfigRequest,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        SetProfile,
+        args.host,
+        args.name
+    ))
+}
+
+async fn replay_buffer(
+    args: ReplayRequest,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    let host = args.host;
+    let res = match args.enabled {
+        Some(enabled) => send_message!(
+            directory.obs_actor,
+            ObsCommand,
+            SetReplayBufferEnabled,
+            host,
+            enabled
+        )
+        .map(|_| "Success".to_string()),
+        None => send_message!(directory.obs_actor, ObsCommand, SaveReplayBuffer, host)
+            .map(|path| serde_json::to_string(&path).unwrap()),
+    };
+
+    match res {
+        Ok(body) => Ok(warp::reply::with_status(body, warp::http::StatusCode::OK)),
+        Err(e) => Ok(warp::reply::with_status(error_body(&e), error_status(&e))),
+    }
+}
+
+async fn reconnect_host(
+    host: HostName,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_none_or_error(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        Reconnect,
+        host.host
+    ))
+}
+
+async fn get_host_layouts(
+    query: HostLayoutsQuery,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        GetLayouts,
+        query.host,
+        query.source
     ))
 }
 
-async fn update_stream(
-    stream: StreamState,
+async fn collect_orphaned_sources(
+    query: OrphanQuery,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        CollectOrphanedSources,
+        query.host,
+        query.apply
+    ))
+}
+
+async fn set_browser_source_group(
+    args: BrowserSourceGroupArgs,
     directory: Directory,
 ) -> Result<impl warp::Reply, Infallible> {
     to_http_none_or_error(send_message!(
-        directory.stream_actor,
-        StreamRequest,
-        Update,
-        stream
+        directory.obs_actor,
+        ObsCommand,
+        SetBrowserSourceGroup,
+        args.host,
+        args.group,
+        args.params
     ))
 }
 
-async fn delete_stream(event: Id, directory: Directory) -> Result<impl warp::Reply, Infallible> {
+async fn set_source_filter_enabled(
+    args: SourceFilterArgs,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
     to_http_none_or_error(send_message!(
-        directory.stream_actor,
-        StreamRequest,
-        Delete,
-        event.id
+        directory.obs_actor,
+        ObsCommand,
+        SetSourceFilterEnabled,
+        args.host,
+        args.source,
+        args.filter,
+        args.enabled
     ))
 }
 
-async fn get_hosts(directory: Directory) -> Result<impl warp::Reply, Infallible> {
-    to_http_output(send_message!(directory.obs_actor, ObsCommand, GetState))
+async fn preflight_check(
+    host: HostName,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(send_message!(
+        directory.obs_actor,
+        ObsCommand,
+        PreflightCheck,
+        host.host,
+        None::<i64>
+    ))
 }
 
-async fn set_streaming_state(
-    streaming: SetStreamingState,
+async fn create_backup(directory: Directory) -> Result<impl warp::Reply, Infallible> {
+    // Copying a large database can take longer than the default timeout
+    to_http_output(send_message_with_timeout!(
+        Duration::from_secs(120),
+        directory.backup_actor,
+        BackupRequest,
+        Backup
+    ))
+}
+
+async fn export_project(db: Arc<dyn ProjectStore>) -> Result<impl warp::Reply, Infallible> {
+    to_http_output(project::export_project(&*db).await)
+}
+
+#[derive(Deserialize)]
+struct ImportArgs {
+    #[serde(default)]
+    mode: ImportMode,
+}
+
+async fn import_project(
+    args: ImportArgs,
+    project: ProjectExport,
     directory: Directory,
 ) -> Result<impl warp::Reply, Infallible> {
-    if streaming.streaming {
-        to_http_none_or_error(send_message!(
-            directory.obs_actor,
-            ObsCommand,
-            StartStream,
-            streaming.host
-        ))
-    } else {
-        to_http_none_or_error(send_message!(
-            directory.obs_actor,
-            ObsCommand,
-            EndStream,
-            streaming.host
-        ))
+    let project = Box::new(project);
+    let mode = args.mode;
+    // Large projects can take longer than the default timeout to insert
+    to_http_output(send_message_with_timeout!(
+        Duration::from_secs(120),
+        directory.runner_actor,
+        RunnerRequest,
+        ImportProject,
+        project,
+        mode
+    ))
+}
+
+async fn export_event(
+    args: HashMap<String, String>,
+    db: Arc<dyn ProjectStore>,
+) -> Result<warp::reply::Response, Infallible> {
+    let event = match get_event_by_args(args.clone(), &*db).await {
+        Ok(event) => event,
+        Err(reply) => return Ok(reply.into_response()),
+    };
+
+    let export = match export::export_event(&*db, event.id).await {
+        Ok(export) => export,
+        Err(e) => {
+            return Ok(warp::reply::with_status(error_body(&e), error_status(&e)).into_response())
+        }
+    };
+
+    match args.get("format").map(|f| f.as_str()) {
+        None | Some("json") => Ok(warp::reply::with_status(
+            serde_json::to_string(&export).unwrap(),
+            warp::http::StatusCode::OK,
+        )
+        .into_response()),
+        Some("csv") => Ok(
+            warp::reply::with_header(export.to_csv(), "Content-Type", "text/csv").into_response(),
+        ),
+        Some(format) => Ok(warp::reply::with_status(
+            format!("Unknown export format '{}'", format),
+            warp::http::StatusCode::BAD_REQUEST,
+        )
+        .into_response()),
+    }
+}
+
+async fn get_schedule_ics(
+    filter: ScheduleFilter,
+    db: Arc<dyn ProjectStore>,
+    settings: Arc<Settings>,
+) -> Result<warp::reply::Response, Infallible> {
+    match export_schedule(&*db, &settings, &filter).await {
+        Ok(calendar) => {
+            Ok(
+                warp::reply::with_header(calendar, "Content-Type", "text/calendar; charset=utf-8")
+                    .into_response(),
+            )
+        }
+        Err(e) => Ok(warp::reply::with_status(e.to_string(), error_status(&e)).into_response()),
     }
 }
 
 async fn commentary_endpoint(
     args: HashMap<String, String>,
-    db: Arc<ProjectDb>,
+    db: Arc<dyn ProjectStore>,
 ) -> Result<impl warp::Reply, Infallible> {
-    match get_event_by_args(args, &db).await {
+    // `format=names` returns the visible commentator names only
+    let names_only = args.get("format").is_some_and(|f| f == "names");
+    match get_event_by_args(args, &*db).await {
         Ok(event) => match db.get_stream(event.id).await {
-            Ok(stream) => Ok(warp::reply::with_status(
+            Ok(stream) if names_only => Ok(warp::reply::with_status(
                 serde_json::to_string::<Vec<String>>(&stream.get_commentators()).unwrap(),
                 warp::http::StatusCode::OK,
             )),
-            Err(e) => Ok(warp::reply::with_status(
-                e.to_string(),
-                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
-            )),
+            Ok(stream) => match stream.get_commentator_details(&*db).await {
+                Ok(commentators) => Ok(warp::reply::with_status(
+                    serde_json::to_string(&commentators).unwrap(),
+                    warp::http::StatusCode::OK,
+                )),
+                Err(e) => Ok(warp::reply::with_status(error_body(&e), error_status(&e))),
+            },
+            Err(e) => Ok(warp::reply::with_status(error_body(&e), error_status(&e))),
         },
         Err(reply) => Ok(reply),
     }
 }
 
 async fn run_dashboard_websocket(
-    db: Arc<ProjectDb>,
+    db: Arc<dyn ProjectStore>,
     directory: Directory,
     socket: warp::ws::WebSocket,
     mut state_rx: Receiver<StateUpdate>,
 ) {
     log::info!("New dashboard websocket connection opened");
-    let (mut tx, _) = socket.split();
+    let (mut tx, mut incoming) = socket.split();
+    directory.health.ws_client_connected();
 
     tokio::spawn(async move {});
 
@@ -296,7 +1976,7 @@ async fn run_dashboard_websocket(
         Ok(update) => {
             if let Err(e) = tx
                 .send(warp::ws::Message::text(
-                    &serde_json::to_string(&update).unwrap(),
+                    serde_json::to_string(&update).unwrap(),
                 ))
                 .await
             {
@@ -308,7 +1988,7 @@ async fn run_dashboard_websocket(
         }
     }
 
-    while let Ok(update) = state_rx.recv().await {
+    while let Some(update) = next_state_update(&mut incoming, &mut state_rx).await {
         if let Ok(update) = serde_json::to_string(&update) {
             if let Err(e) = tx.send(warp::ws::Message::text(update)).await {
                 log::error!("Failed to send state update: {}", e);
@@ -319,12 +1999,181 @@ async fn run_dashboard_websocket(
             break;
         }
     }
+
+    directory.health.ws_client_disconnected();
+}
+
+/// Wait for the next state update, skipping to the newest one if the client fell behind.
+///
+/// Every update carries the full state, so dropping older ones loses nothing.
+async fn recv_latest(rx: &mut Receiver<StateUpdate>) -> Option<StateUpdate> {
+    let mut latest = loop {
+        match rx.recv().await {
+            Ok(update) => break update,
+            Err(RecvError::Lagged(skipped)) => {
+                log::warn!(
+                    "Websocket client fell behind, skipping {} state updates",
+                    skipped
+                )
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    };
+
+    loop {
+        match rx.try_recv() {
+            Ok(update) => latest = update,
+            Err(TryRecvError::Lagged(_)) => {}
+            Err(_) => return Some(latest),
+        }
+    }
+}
+
+/// Wait for the next state update while reading what the client sends.
+///
+/// Reading lets pings be answered and a closed connection be noticed without waiting for
+/// the next update. Returns None once the client or the state updates are gone.
+async fn next_state_update(
+    incoming: &mut SplitStream<warp::ws::WebSocket>,
+    state_rx: &mut Receiver<StateUpdate>,
+) -> Option<StateUpdate> {
+    loop {
+        tokio::select! {
+            update = recv_latest(state_rx) => return update,
+            message = incoming.next() => match message {
+                Some(Ok(message)) if !message.is_close() => {}
+                _ => return None,
+            },
+        }
+    }
+}
+
+/// Parse the optional `event` argument of the public endpoints
+fn get_public_event_arg(args: &HashMap<String, String>) -> Result<Option<i64>, WithStatus<String>> {
+    match args.get("event") {
+        Some(event) => event.parse::<i64>().map(Some).map_err(|_| {
+            warp::reply::with_status(
+                "Failed to parse event ID".to_string(),
+                warp::http::StatusCode::BAD_REQUEST,
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+async fn get_public_state(
+    args: HashMap<String, String>,
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
+) -> Result<impl warp::Reply, Infallible> {
+    match get_public_event_arg(&args) {
+        Ok(event) => match assemble_state_update(db, &directory).await {
+            Ok(update) => Ok(warp::reply::with_status(
+                serde_json::to_string(&PublicState::from_update(&update, event)).unwrap(),
+                warp::http::StatusCode::OK,
+            )),
+            Err(e) => Ok(warp::reply::with_status(error_body(&e), error_status(&e))),
+        },
+        Err(reply) => Ok(reply),
+    }
+}
+
+async fn run_public_websocket(
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
+    socket: warp::ws::WebSocket,
+    mut state_rx: Receiver<StateUpdate>,
+    event: Option<i64>,
+) {
+    log::info!("New public websocket connection opened");
+    let (mut tx, mut incoming) = socket.split();
+    directory.health.ws_client_connected();
+
+    match assemble_state_update(db, &directory).await {
+        Ok(update) => {
+            let update = PublicState::from_update(&update, event);
+            if let Err(e) = tx
+                .send(warp::ws::Message::text(
+                    serde_json::to_string(&update).unwrap(),
+                ))
+                .await
+            {
+                log::error!("Failed to send initial public state: {}", e);
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to assemble initial public state: {}", e);
+        }
+    }
+
+    while let Some(update) = next_state_update(&mut incoming, &mut state_rx).await {
+        let update = PublicState::from_update(&update, event);
+        if let Ok(update) = serde_json::to_string(&update) {
+            if let Err(e) = tx.send(warp::ws::Message::text(update)).await {
+                log::error!("Failed to send public state update: {}", e);
+                break;
+            }
+        } else {
+            log::error!("Failed to serialize public state update");
+            break;
+        }
+    }
+
+    directory.health.ws_client_disconnected();
+}
+
+/// Send the state of a single event, only when it changed since it was last sent
+async fn run_event_websocket(
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
+    socket: warp::ws::WebSocket,
+    mut state_rx: Receiver<StateUpdate>,
+    event: i64,
+) {
+    log::info!("New websocket connection opened for event {}", event);
+    let (mut tx, mut incoming) = socket.split();
+    directory.health.ws_client_connected();
+
+    let mut update = match assemble_state_update(db, &directory).await {
+        Ok(update) => Some(update),
+        Err(e) => {
+            log::error!("Failed to assemble initial event state: {}", e);
+            None
+        }
+    };
+
+    // Compared as JSON values, which ignore the iteration order of maps
+    let mut last_sent: Option<serde_json::Value> = None;
+    loop {
+        if let Some(update) = &update {
+            let Ok(state) = serde_json::to_value(EventState::from_update(update, event)) else {
+                log::error!("Failed to serialize event state update");
+                break;
+            };
+            if last_sent.as_ref() != Some(&state) {
+                if let Err(e) = tx.send(warp::ws::Message::text(state.to_string())).await {
+                    log::error!("Failed to send event state update: {}", e);
+                    break;
+                }
+                last_sent = Some(state);
+            }
+        }
+
+        match next_state_update(&mut incoming, &mut state_rx).await {
+            Some(next) => update = Some(next),
+            None => break,
+        }
+    }
+
+    directory.health.ws_client_disconnected();
 }
 
-async fn assemble_state_update(
-    db: Arc<ProjectDb>,
+pub(crate) async fn assemble_state_update(
+    db: Arc<dyn ProjectStore>,
     directory: &Directory,
 ) -> anyhow::Result<StateUpdate> {
+    // Read first, so changes made while assembling are never missed by the revision
+    let revision = db.get_revision();
     let event_names = db.get_event_ids().await?;
     let mut events = vec![];
     for event in event_names {
@@ -345,77 +2194,306 @@ async fn assemble_state_update(
         }
     }
 
+    let run_resets = db
+        .get_run_resets()
+        .into_iter()
+        .map(|(runner, time)| (runner, to_unix_millis(Some(time)).unwrap()))
+        .collect();
+
     let stream_names = db.get_streamed_events().await?;
     let mut streams = vec![];
+    let mut commentators = HashMap::new();
     for stream in stream_names {
-        streams.push(db.get_stream(stream).await?);
+        let stream = db.get_stream(stream).await?;
+        commentators.insert(stream.event, stream.get_commentator_details(&*db).await?);
+        streams.push(stream);
     }
 
+    let active_streams = streams
+        .iter()
+        .filter(|s| s.active)
+        .map(|s| (s.obs_host.clone(), s.event))
+        .collect();
+
     let hosts = send_message!(directory.obs_actor, ObsCommand, GetState)?;
+    for stream in &mut streams {
+        let event = events.iter().find(|e| e.id == stream.event);
+        let scenes = hosts.get(&stream.obs_host).map(|h| &h.scenes);
+        if let (Some(event), Some(scenes)) = (event, scenes) {
+            stream.layout_slot_count =
+                get_stream_layout(event, stream, scenes, stream.stream_runners.len())
+                    .map(|l| l.sources.len());
+        }
+    }
+    let last_backup = send_message!(directory.backup_actor, BackupRequest, GetLastBackup)?;
+    let donations = send_message!(directory.tiltify_actor, TiltifyCommand, GetDonations)?;
 
     Ok(StateUpdate {
+        active_streams,
         events,
         runners,
         streams,
         active_runs: runs,
+        run_resets,
         hosts,
+        commentators,
+        last_backup: to_unix_millis(last_backup),
+        donations,
+        discord: directory.health.get_discord(),
+        revision,
     })
 }
 
+/// Refuse websocket upgrades from browser origins that are not in `allowed_origins`.
+///
+/// CORS does not apply to websockets, so the Origin header is checked here.
+/// Clients outside a browser send no Origin and are let through.
+fn reject_origin(origin: Option<String>, settings: &Settings) -> Option<Box<dyn warp::Reply>> {
+    match origin {
+        Some(origin) if !settings.is_origin_allowed(&origin) => {
+            log::warn!("Refused websocket connection from origin {}", origin);
+            Some(Box::new(warp::reply::with_status(
+                format!("Origin {} is not allowed", origin),
+                warp::http::StatusCode::FORBIDDEN,
+            )))
+        }
+        _ => None,
+    }
+}
+
+fn upgrade_dashboard_socket(
+    ws: warp::ws::Ws,
+    origin: Option<String>,
+    settings: Arc<Settings>,
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
+    state_rx: Receiver<StateUpdate>,
+) -> Box<dyn warp::Reply> {
+    if let Some(reply) = reject_origin(origin, &settings) {
+        return reply;
+    }
+    Box::new(ws.on_upgrade(move |socket| run_dashboard_websocket(db, directory, socket, state_rx)))
+}
+
+fn upgrade_public_socket(
+    ws: warp::ws::Ws,
+    args: HashMap<String, String>,
+    origin: Option<String>,
+    settings: Arc<Settings>,
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
+    state_rx: Receiver<StateUpdate>,
+) -> Box<dyn warp::Reply> {
+    if let Some(reply) = reject_origin(origin, &settings) {
+        return reply;
+    }
+    let event = args.get("event").and_then(|e| e.parse::<i64>().ok());
+    Box::new(
+        ws.on_upgrade(move |socket| run_public_websocket(db, directory, socket, state_rx, event)),
+    )
+}
+
+fn upgrade_event_socket(
+    event: i64,
+    ws: warp::ws::Ws,
+    origin: Option<String>,
+    settings: Arc<Settings>,
+    db: Arc<dyn ProjectStore>,
+    directory: Directory,
+    state_rx: Receiver<StateUpdate>,
+) -> Box<dyn warp::Reply> {
+    if let Some(reply) = reject_origin(origin, &settings) {
+        return reply;
+    }
+    Box::new(
+        ws.on_upgrade(move |socket| run_event_websocket(db, directory, socket, state_rx, event)),
+    )
+}
+
+/// Files of web/ at any depth under /static, with unknown paths falling back to the dashboard
+fn static_files() -> impl Filter<Extract = (warp::fs::File,), Error = warp::Rejection> + Clone {
+    warp::path("static").and(warp::get()).and(
+        warp::fs::dir("web")
+            .or(warp::fs::file("web/dashboard.html"))
+            .unify(),
+    )
+}
+
+/// Browser origins, headers and methods allowed to call the web server
+fn cors_policy(settings: &Settings) -> warp::cors::Builder {
+    let cors = if settings.allowed_origins.is_empty() {
+        warp::cors().allow_any_origin()
+    } else {
+        warp::cors().allow_origins(settings.allowed_origins.iter().map(|o| o.as_str()))
+    };
+    cors.allow_headers(vec![
+        "User-Agent",
+        "Sec-Fetch-Mode",
+        "Referer",
+        "Origin",
+        "Content-Type",
+        "Access-Control-Allow-Origin",
+        "Access-Control-Request-Method",
+        "Access-Control-Request-Headers",
+        "Access-Control-Allow-Headers",
+        "Authorization",
+    ])
+    .allow_methods(&[Method::GET, Method::POST, Method::PUT, Method::DELETE])
+}
+
 pub async fn run_http_server(
-    db: Arc<ProjectDb>,
+    db: Arc<dyn ProjectStore>,
     directory: Directory,
     settings: Arc<Settings>,
-    mut rx: UnboundedReceiver<WebCommand>,
+    mut rx: ActorReceiver<WebCommand>,
 ) -> Result<(), anyhow::Error> {
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_headers(vec![
-            "User-Agent",
-            "Sec-Fetch-Mode",
-            "Referer",
-            "Origin",
-            "Content-Type",
-            "Access-Control-Allow-Origin",
-            "Access-Control-Request-Method",
-            "Access-Control-Request-Headers",
-            "Access-Control-Allow-Headers",
-        ])
-        .allow_methods(&[Method::GET, Method::POST, Method::PUT, Method::DELETE]);
+    let cors = cors_policy(&settings);
 
     let (update_tx, _) = tokio::sync::broadcast::channel::<StateUpdate>(256);
 
     let reader_tx = update_tx.clone();
+    let public_tx = update_tx.clone();
+    let event_tx = update_tx.clone();
     let socket = warp::path("ws")
         .and(warp::path::end())
         .and(warp::ws())
+        .and(warp::header::optional::<String>("origin"))
+        .and(with_settings(settings.clone()))
+        .and(with_db(db.clone()))
+        .and(with_directory(directory.clone()))
+        .and(warp::any().map(move || update_tx.subscribe()))
+        .map(upgrade_dashboard_socket);
+
+    let public_socket = warp::path!("ws" / "public")
+        .and(warp::ws())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("origin"))
+        .and(with_settings(settings.clone()))
+        .and(with_db(db.clone()))
+        .and(with_directory(directory.clone()))
+        .and(warp::any().map(move || public_tx.subscribe()))
+        .map(upgrade_public_socket);
+
+    let event_socket = warp::path!("ws" / "event" / i64)
+        .and(warp::ws())
+        .and(warp::header::optional::<String>("origin"))
+        .and(with_settings(settings.clone()))
+        .and(with_db(db.clone()))
+        .and(with_directory(directory.clone()))
+        .and(warp::any().map(move || event_tx.subscribe()))
+        .map(upgrade_event_socket);
+
+    let public_state = warp::path!("public" / "state")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_db(db.clone()))
+        .and(with_directory(directory.clone()))
+        .and_then(get_public_state);
+
+    let commentary_endpoint = warp::path("commentators")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_db(db.clone()))
+        .and_then(commentary_endpoint);
+
+    let get_ignored_discord_users = warp::path!("commentators" / "ignored")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_ignored_discord_users);
+
+    let add_ignored_discord_user = warp::path!("commentators" / "ignored")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_db(db.clone()))
+        .and_then(add_ignored_discord_user);
+
+    let remove_ignored_discord_user = warp::path!("commentators" / "ignored")
+        .and(warp::delete())
+        .and(warp::body::json())
+        .and(with_db(db.clone()))
+        .and_then(remove_ignored_discord_user);
+
+    let reorder_commentators = warp::path!("event" / "commentators" / "reorder")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(reorder_commentators);
+
+    let complete_event = warp::path!("event" / "complete")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(complete_event);
+
+    let reopen_event = warp::path!("event" / "reopen")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(reopen_event);
+
+    let reorder_event_runners = warp::path!("event" / "runners" / "reorder")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(reorder_event_runners);
+
+    let create_runner = warp::path("runner")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(create_runner);
+
+    let get_runners = warp::path("runners")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-none-match"))
         .and(with_db(db.clone()))
+        .and_then(get_runners);
+
+    let get_runner_audio = warp::path!("runner" / "audio")
+        .and(warp::get())
+        .and(warp::query::<RunnerId>())
+        .and(with_db(db.clone()))
+        .and_then(get_runner_audio);
+
+    let get_runner_info = warp::path!("runner" / "info")
+        .and(warp::get())
+        .and(warp::query::<RunnerId>())
+        .and(with_db(db.clone()))
+        .and_then(get_runner_info);
+
+    let create_self_token = warp::path!("runner" / "self" / "token")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_settings(settings.clone()))
+        .and(with_directory(directory.clone()))
+        .and_then(create_self_token);
+
+    let set_therun_polling = warp::path!("therun" / "poll")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_therun_polling);
+
+    let get_therun_status = warp::path!("therun" / "status")
+        .and(warp::get())
         .and(with_directory(directory.clone()))
-        .and(warp::any().map(move || update_tx.subscribe()))
-        .map(
-            |ws: warp::ws::Ws,
-             db: Arc<ProjectDb>,
-             directory: Directory,
-             state_rx: Receiver<StateUpdate>| {
-                ws.on_upgrade(move |socket| {
-                    run_dashboard_websocket(db, directory, socket, state_rx)
-                })
-            },
-        );
+        .and_then(get_therun_status);
 
-    let commentary_endpoint = warp::path("commentators")
-        .and(warp::path::end())
+    let get_self_service = warp::path!("self" / String)
         .and(warp::get())
-        .and(warp::query::<HashMap<String, String>>())
         .and(with_db(db.clone()))
-        .and_then(commentary_endpoint);
+        .and_then(get_self_service);
 
-    let create_runner = warp::path("runner")
-        .and(warp::path::end())
+    let self_update_runner = warp::path!("self" / String)
         .and(warp::post())
         .and(warp::body::json())
         .and(with_directory(directory.clone()))
-        .and_then(create_runner);
+        .and_then(self_update_runner);
 
     let update_runner = warp::path("runner")
         .and(warp::path::end())
@@ -431,6 +2509,13 @@ pub async fn run_http_server(
         .and(with_directory(directory.clone()))
         .and_then(delete_runner);
 
+    let get_events = warp::path("events")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<EventFilter>())
+        .and(with_db(db.clone()))
+        .and_then(get_events);
+
     let create_event = warp::path("event")
         .and(warp::path::end())
         .and(warp::post())
@@ -438,10 +2523,30 @@ pub async fn run_http_server(
         .and(with_directory(directory.clone()))
         .and_then(create_event);
 
+    let create_full_event = warp::path!("event" / "full")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(create_full_event);
+
+    let export_event = warp::path!("event" / "export")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_db(db.clone()))
+        .and_then(export_event);
+
+    let schedule_ics = warp::path!("schedule.ics")
+        .and(warp::get())
+        .and(warp::query::<ScheduleFilter>())
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and_then(get_schedule_ics);
+
     let read_event = warp::path("event")
         .and(warp::path::end())
         .and(warp::get())
         .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("if-none-match"))
         .and(with_db(db.clone()))
         .and(warp::path::end())
         .and_then(get_event);
@@ -453,6 +2558,12 @@ pub async fn run_http_server(
         .and(with_directory(directory.clone()))
         .and_then(update_event);
 
+    let set_commentary_host = warp::path!("event" / "host")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_commentary_host);
+
     let delete_event = warp::path("event")
         .and(warp::path::end())
         .and(warp::delete())
@@ -460,6 +2571,42 @@ pub async fn run_http_server(
         .and(with_directory(directory.clone()))
         .and_then(delete_event);
 
+    let get_incidents = warp::path!("event" / "incident")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_db(db.clone()))
+        .and_then(get_incidents);
+
+    let add_incident = warp::path!("event" / "incident")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(add_incident);
+
+    let update_incident = warp::path!("event" / "incident")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(update_incident);
+
+    let delete_incident = warp::path!("event" / "incident")
+        .and(warp::delete())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(delete_incident);
+
+    let add_marker = warp::path!("event" / "marker")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(add_marker);
+
+    let get_markers = warp::path!("event" / "markers")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_db(db.clone()))
+        .and_then(get_markers);
+
     let create_stream = warp::path("stream")
         .and(warp::path::end())
         .and(warp::post())
@@ -471,6 +2618,7 @@ pub async fn run_http_server(
         .and(warp::path::end())
         .and(warp::put())
         .and(warp::body::json())
+        .and(warp::query::<ForceArg>())
         .and(with_directory(directory.clone()))
         .and_then(update_stream);
 
@@ -481,12 +2629,102 @@ pub async fn run_http_server(
         .and(with_directory(directory.clone()))
         .and_then(delete_stream);
 
+    let activate_stream = warp::path!("stream" / "activate")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(activate_stream);
+
+    let resync_stream = warp::path!("stream" / "resync")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(resync_stream);
+
+    let get_stream_plan = warp::path!("stream" / "plan")
+        .and(warp::get())
+        .and(warp::query::<StreamPlanQuery>())
+        .and(with_directory(directory.clone()))
+        .and_then(get_stream_plan);
+
+    let set_sync_offset = warp::path!("stream" / "sync")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_sync_offset);
+
+    let add_on_deck = warp::path!("stream" / "on_deck")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(add_on_deck);
+
+    let remove_on_deck = warp::path!("stream" / "on_deck")
+        .and(warp::delete())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(remove_on_deck);
+
+    let get_stream_presets = warp::path!("stream" / "preset")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_stream_presets);
+
+    let save_stream_preset = warp::path!("stream" / "preset")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_db(db.clone()))
+        .and_then(save_stream_preset);
+
+    let delete_stream_preset = warp::path!("stream" / "preset")
+        .and(warp::delete())
+        .and(warp::body::json())
+        .and(with_db(db.clone()))
+        .and_then(delete_stream_preset);
+
+    let get_themes = warp::path!("theme")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(get_themes);
+
+    let save_theme = warp::path!("theme")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_db(db.clone()))
+        .and_then(save_theme);
+
+    let delete_theme = warp::path!("theme")
+        .and(warp::delete())
+        .and(warp::body::json())
+        .and(with_db(db.clone()))
+        .and_then(delete_theme);
+
+    let get_theme_css = warp::path!("overlay" / "theme.css")
+        .and(warp::get())
+        .and(warp::query::<ThemeCssQuery>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(with_db(db.clone()))
+        .and_then(get_theme_css);
+
+    let apply_stream_preset = warp::path!("stream" / "preset" / "apply")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(apply_stream_preset);
+
     let get_hosts = warp::path("hosts")
         .and(warp::path::end())
         .and(warp::get())
+        .and(warp::header::optional::<String>("if-none-match"))
         .and(with_directory(directory.clone()))
         .and_then(get_hosts);
 
+    let get_host_scenes = warp::path!("hosts" / "scenes")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_directory(directory.clone()))
+        .and_then(get_host_scenes);
+
     let set_streaming_state = warp::path("hosts")
         .and(warp::path::end())
         .and(warp::put())
@@ -494,37 +2732,322 @@ pub async fn run_http_server(
         .and(with_directory(directory.clone()))
         .and_then(set_streaming_state);
 
-    let dashboard = warp::path("static")
+    let set_source_index = warp::path!("hosts" / "source_index")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_source_index);
+
+    let replay_buffer = warp::path!("hosts" / "replay")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(replay_buffer);
+
+    let open_projector = warp::path!("hosts" / "projector")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(open_projector);
+
+    let set_virtual_cam = warp::path!("hosts" / "virtualcam")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_virtual_cam);
+
+    let switch_scene = warp::path!("hosts" / "scene")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(switch_scene);
+
+    let resume_layout = warp::path!("hosts" / "scene" / "resume")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(resume_layout);
+
+    let set_scene_collection = warp::path!("hosts" / "scene_collection")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_scene_collection);
+
+    let set_profile = warp::path!("hosts" / "profile")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_profile);
+
+    let reconnect_host = warp::path!("hosts" / "reconnect")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(reconnect_host);
+
+    let get_host_layouts = warp::path!("hosts" / "layouts")
+        .and(warp::get())
+        .and(warp::query::<HostLayoutsQuery>())
+        .and(with_directory(directory.clone()))
+        .and_then(get_host_layouts);
+
+    let collect_orphaned_sources = warp::path!("hosts" / "gc")
+        .and(warp::post())
+        .and(warp::query::<OrphanQuery>())
+        .and(with_directory(directory.clone()))
+        .and_then(collect_orphaned_sources);
+
+    let set_browser_source_group = warp::path!("hosts" / "browser-group")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_browser_source_group);
+
+    let set_source_filter_enabled = warp::path!("hosts" / "filter")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(set_source_filter_enabled);
+
+    let preflight_check = warp::path!("hosts" / "preflight")
+        .and(warp::get())
+        .and(warp::query::<HostName>())
+        .and(with_directory(directory.clone()))
+        .and_then(preflight_check);
+
+    let create_backup = warp::path!("admin" / "backup")
+        .and(warp::post())
+        .and(with_directory(directory.clone()))
+        .and_then(create_backup);
+
+    let export_project = warp::path!("admin" / "export")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(export_project);
+
+    let import_project = warp::path!("admin" / "import")
+        .and(warp::post())
+        .and(warp::query::<ImportArgs>())
+        .and(warp::body::json())
+        .and(with_directory(directory.clone()))
+        .and_then(import_project);
+
+    let timings = Arc::new(RequestTimings::new(Duration::from_millis(
+        settings
+            .slow_request_millis
+            .unwrap_or(DEFAULT_SLOW_REQUEST_MILLIS),
+    )));
+    let limiter = RateLimiter::new(
+        settings
+            .rate_limit_per_minute
+            .unwrap_or(DEFAULT_RATE_LIMIT_PER_MINUTE),
+        settings
+            .rate_limit_burst
+            .unwrap_or(DEFAULT_RATE_LIMIT_BURST),
+    )
+    .map(Arc::new);
+
+    let trigger = warp::path!("trigger" / String)
+        .and(warp::get())
+        .and(warp::query::<TriggerQuery>())
+        .and(warp::ext::optional::<ClientAddr>())
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(with_directory(directory.clone()))
+        .and_then(trigger);
+
+    let request_timings = warp::path!("debug" / "timings")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_settings(settings.clone()))
+        .and(with_timings(timings.clone()))
+        .and_then(get_request_timings);
+
+    let actor_stats = warp::path!("debug" / "actors")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_settings(settings.clone()))
+        .and(with_directory(directory.clone()))
+        .and_then(get_actor_stats);
+
+    let cache_stats = warp::path!("debug" / "cache")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_settings(settings.clone()))
+        .and(with_db(db.clone()))
+        .and_then(get_cache_stats);
+
+    let replay = warp::path!("debug" / "replay")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::bytes())
+        .and(with_settings(settings.clone()))
+        .and(with_directory(directory.clone()))
+        .and_then(replay_trace);
+
+    let recent_donations = warp::path!("donations" / "recent")
+        .and(warp::get())
+        .and(with_directory(directory.clone()))
+        .and_then(get_recent_donations);
+
+    let health = warp::path("healthz")
+        .and(warp::path::end())
         .and(warp::get())
-        .and(warp::fs::dir("web/static/timer.html"));
+        .and(with_db(db.clone()))
+        .and(with_settings(settings.clone()))
+        .and(with_directory(directory.clone()))
+        .and_then(get_health);
+
+    let dashboard = static_files();
+
+    let routes = read_event
+        .or(export_event)
+        .or(schedule_ics)
+        .or(commentary_endpoint)
+        .or(get_ignored_discord_users)
+        .or(add_ignored_discord_user)
+        .or(remove_ignored_discord_user)
+        .or(reorder_commentators)
+        .or(reorder_event_runners)
+        .or(complete_event)
+        .or(reopen_event)
+        .or(dashboard)
+        .or(socket)
+        .or(public_socket)
+        .or(event_socket)
+        .or(public_state)
+        .or(get_runners)
+        .or(get_events)
+        .or(get_runner_audio)
+        .or(get_runner_info)
+        .or(create_runner)
+        .or(update_runner)
+        .or(create_self_token)
+        .or(set_therun_polling)
+        .or(get_therun_status)
+        .or(get_self_service)
+        .or(self_update_runner)
+        .or(delete_runner)
+        .or(create_event)
+        .or(create_full_event)
+        .or(update_event)
+        .or(set_commentary_host)
+        .or(delete_event)
+        .or(get_incidents)
+        .or(add_incident)
+        .or(update_incident)
+        .or(delete_incident)
+        .or(add_marker)
+        .or(get_markers)
+        .or(create_stream)
+        .or(update_stream)
+        .or(delete_stream)
+        .or(activate_stream)
+        .or(resync_stream)
+        .or(get_stream_plan)
+        .or(set_sync_offset)
+        .or(add_on_deck)
+        .or(remove_on_deck)
+        .or(get_stream_presets)
+        .or(save_stream_preset)
+        .or(delete_stream_preset)
+        .or(apply_stream_preset)
+        .or(get_themes)
+        .or(save_theme)
+        .or(delete_theme)
+        .or(get_theme_css)
+        .or(get_hosts)
+        .or(get_host_scenes)
+        .or(set_streaming_state)
+        .or(set_source_index)
+        .or(replay_buffer)
+        .or(open_projector)
+        .or(set_virtual_cam)
+        .or(switch_scene)
+        .or(resume_layout)
+        .or(set_scene_collection)
+        .or(set_profile)
+        .or(reconnect_host)
+        .or(get_host_layouts)
+        .or(collect_orphaned_sources)
+        .or(set_browser_source_group)
+        .or(set_source_filter_enabled)
+        .or(preflight_check)
+        .or(create_backup)
+        .or(export_project)
+        .or(import_project)
+        .or(request_timings)
+        .or(cache_stats)
+        .or(actor_stats)
+        .or(replay)
+        .or(health)
+        .or(recent_donations)
+        .or(trigger)
+        .with(cors)
+        .with(warp::trace(|info| {
+            tracing::info_span!(
+                "request",
+                source = "web",
+                method = %info.method(),
+                path = %info.path()
+            )
+        }));
+
+    let service = warp::service(routes);
+    let addr = SocketAddr::from(([0, 0, 0, 0], settings.web_port.unwrap_or(DEFAULT_WEB_PORT)));
+    let limit_settings = settings.clone();
 
     tokio::spawn(async move {
-        warp::serve(
-            read_event
-                .or(commentary_endpoint)
-                .or(dashboard)
-                .or(socket)
-                .or(create_runner)
-                .or(update_runner)
-                .or(delete_runner)
-                .or(create_event)
-                .or(update_event)
-                .or(delete_event)
-                .or(create_stream)
-                .or(update_stream)
-                .or(delete_stream)
-                .or(get_hosts)
-                .or(set_streaming_state)
-                .with(cors),
-        )
-        .run(([0, 0, 0, 0], settings.web_port.unwrap_or(28010)))
-        .await;
+        // Every request goes through timed_request to log its latency,
+        // requests that change the project are rate limited first.
+        // Large text responses are gzipped for clients that accept it.
+        // The client address is added to each request for handlers that log it
+        let make_service = make_service_fn(move |conn: &AddrStream| {
+            let service = service.clone();
+            let timings = timings.clone();
+            let limiter = limiter.clone();
+            let settings = limit_settings.clone();
+            let addr = conn.remote_addr().ip();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+                    request.extensions_mut().insert(ClientAddr(addr));
+                    let service = service.clone();
+                    let timings = timings.clone();
+                    let limiter = limiter.clone();
+                    let settings = settings.clone();
+                    async move {
+                        if let Some(response) =
+                            limit_request(limiter.as_deref(), &settings, addr, &request)
+                        {
+                            return Ok(response);
+                        }
+                        let gzip = accepts_gzip(&request);
+                        let response = timed_request(service, timings, request).await?;
+                        Ok::<_, Infallible>(if gzip {
+                            gzip_response(response).await
+                        } else {
+                            response
+                        })
+                    }
+                }))
+            }
+        });
+
+        if let Err(e) = warp::hyper::Server::bind(&addr).serve(make_service).await {
+            log::error!("Web server failed: {}", e);
+        }
     });
 
     loop {
-        match rx.recv().await.unwrap() {
+        let (msg, span) = rx.recv().await.unwrap();
+        match msg {
             WebCommand::SendStateUpdate => {
-                match assemble_state_update(db.clone(), &directory).await {
+                match assemble_state_update(db.clone(), &directory)
+                    .instrument(span)
+                    .await
+                {
                     Ok(update) => {
                         let _ = reader_tx.send(update);
                     }
@@ -538,8 +3061,8 @@ pub async fn run_http_server(
 }
 
 fn with_db(
-    db: Arc<ProjectDb>,
-) -> impl Filter<Extract = (Arc<ProjectDb>,), Error = Infallible> + Clone {
+    db: Arc<dyn ProjectStore>,
+) -> impl Filter<Extract = (Arc<dyn ProjectStore>,), Error = Infallible> + Clone {
     warp::any().map(move || db.clone())
 }
 
@@ -548,3 +3071,232 @@ fn with_directory(
 ) -> impl Filter<Extract = (Directory,), Error = Infallible> + Clone {
     warp::any().map(move || directory.clone())
 }
+
+fn with_settings(
+    settings: Arc<Settings>,
+) -> impl Filter<Extract = (Arc<Settings>,), Error = Infallible> + Clone {
+    warp::any().map(move || settings.clone())
+}
+
+fn with_timings(
+    timings: Arc<RequestTimings>,
+) -> impl Filter<Extract = (Arc<RequestTimings>,), Error = Infallible> + Clone {
+    warp::any().map(move || timings.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::{http::StatusCode, Reply};
+
+    use super::*;
+
+    const ALLOWED: &str = "https://dashboard.example";
+    const OTHER: &str = "https://elsewhere.example";
+
+    fn settings() -> Settings {
+        Settings {
+            allowed_origins: vec![ALLOWED.to_owned()],
+            ..Settings::template()
+        }
+    }
+
+    async fn preflight(
+        settings: &Settings,
+        origin: &str,
+        method: &str,
+    ) -> warp::http::Response<warp::hyper::body::Bytes> {
+        let route = warp::any().map(warp::reply).with(cors_policy(settings));
+        warp::test::request()
+            .method("OPTIONS")
+            .path("/runner")
+            .header("origin", origin)
+            .header("access-control-request-method", method)
+            .reply(&route)
+            .await
+    }
+
+    #[tokio::test]
+    async fn allowed_origin_passes_preflight() {
+        let settings = settings();
+        for method in ["PUT", "DELETE"] {
+            let res = preflight(&settings, ALLOWED, method).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", method);
+            assert_eq!(res.headers()["access-control-allow-origin"], ALLOWED);
+            let methods = res.headers()["access-control-allow-methods"]
+                .to_str()
+                .unwrap();
+            assert!(methods.contains(method), "{} not in {}", method, methods);
+        }
+    }
+
+    #[tokio::test]
+    async fn disallowed_origin_fails_cors() {
+        let settings = settings();
+        for method in ["PUT", "DELETE"] {
+            let res = preflight(&settings, OTHER, method).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", method);
+            assert!(!res.headers().contains_key("access-control-allow-origin"));
+        }
+
+        let route = warp::any().map(warp::reply).with(cors_policy(&settings));
+        let res = warp::test::request()
+            .method("DELETE")
+            .path("/runner")
+            .header("origin", OTHER)
+            .reply(&route)
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn rate_limited_requests_allow_allowed_origins() {
+        let settings = settings();
+        let limiter = RateLimiter::new(1, 1).unwrap();
+        let addr = std::net::Ipv4Addr::LOCALHOST.into();
+        let request = |origin: &str| {
+            Request::builder()
+                .method(Method::PUT)
+                .uri("/runner")
+                .header("origin", origin)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert!(limit_request(Some(&limiter), &settings, addr, &request(ALLOWED)).is_none());
+        let res = limit_request(Some(&limiter), &settings, addr, &request(ALLOWED)).unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["access-control-allow-origin"], ALLOWED);
+        assert_eq!(
+            res.headers()["access-control-expose-headers"],
+            "retry-after"
+        );
+
+        let res = limit_request(Some(&limiter), &settings, addr, &request(OTHER)).unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(!res.headers().contains_key("access-control-allow-origin"));
+    }
+
+    #[tokio::test]
+    async fn any_origin_passes_without_allowed_origins() {
+        let settings = Settings::template();
+        let res = preflight(&settings, OTHER, "PUT").await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn websocket_origin_check() {
+        let settings = settings();
+        let refused = reject_origin(Some(OTHER.to_owned()), &settings).unwrap();
+        assert_eq!(refused.into_response().status(), StatusCode::FORBIDDEN);
+        assert!(reject_origin(Some(ALLOWED.to_owned()), &settings).is_none());
+        // Clients outside a browser send no Origin
+        assert!(reject_origin(None, &settings).is_none());
+        assert!(reject_origin(Some(OTHER.to_owned()), &Settings::template()).is_none());
+    }
+
+    #[tokio::test]
+    async fn every_web_file_is_served() {
+        let route = static_files();
+        let mut files = vec![];
+        let mut dirs = vec![std::path::PathBuf::from("web")];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        assert!(!files.is_empty());
+
+        for file in files {
+            let relative = file
+                .strip_prefix("web")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .replace('\\', "/");
+            let res = warp::test::request()
+                .path(&format!("/static/{}", relative))
+                .reply(&route)
+                .await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", relative);
+            assert_eq!(
+                res.body().as_ref(),
+                std::fs::read(&file).unwrap(),
+                "{}",
+                relative
+            );
+            if relative.ends_with(".html") {
+                assert!(res.headers()["content-type"]
+                    .to_str()
+                    .unwrap()
+                    .starts_with("text/html"));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_static_path_serves_the_dashboard() {
+        let route = static_files();
+        let dashboard = std::fs::read("web/dashboard.html").unwrap();
+        for path in ["/static/runners/12", "/static/missing.js"] {
+            let res = warp::test::request().path(path).reply(&route).await;
+            assert_eq!(res.status(), StatusCode::OK, "{}", path);
+            assert_eq!(res.body().as_ref(), dashboard, "{}", path);
+        }
+
+        assert!(!warp::test::request().path("/runners").matches(&route).await);
+        assert!(
+            !warp::test::request()
+                .method("POST")
+                .path("/static/timer.html")
+                .matches(&route)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn public_websocket_skips_missed_updates_and_ends_on_close() {
+        let actors = crate::core::testing::TestActors::start().await;
+        let update = assemble_state_update(actors.db.clone(), &actors.directory)
+            .await
+            .unwrap();
+        // Room for a single update, so a burst makes the client lag
+        let (state_tx, _) = tokio::sync::broadcast::channel::<StateUpdate>(1);
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+        let done_tx = Arc::new(std::sync::Mutex::new(Some(done_tx)));
+
+        let (db, directory, subscriber) = (
+            actors.db.clone(),
+            actors.directory.clone(),
+            state_tx.clone(),
+        );
+        let route = warp::ws().map(move |ws: warp::ws::Ws| {
+            let (db, directory, state_rx) = (db.clone(), directory.clone(), subscriber.subscribe());
+            let done_tx = done_tx.clone();
+            ws.on_upgrade(move |socket| async move {
+                run_public_websocket(db, directory, socket, state_rx, None).await;
+                if let Some(done_tx) = done_tx.lock().unwrap().take() {
+                    let _ = done_tx.send(());
+                }
+            })
+        });
+
+        let mut client = warp::test::ws().handshake(route).await.unwrap();
+        assert!(client.recv().await.unwrap().is_text());
+
+        for _ in 0..3 {
+            state_tx.send(update.clone()).unwrap();
+        }
+        assert!(client.recv().await.unwrap().is_text());
+
+        client.send(warp::ws::Message::close()).await;
+        tokio::time::timeout(Duration::from_secs(5), done_rx)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
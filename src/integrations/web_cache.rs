@@ -0,0 +1,74 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use sqlx::types::time::OffsetDateTime;
+use time::macros::format_description;
+use warp::{
+    http::{header, HeaderValue, StatusCode},
+    reply::Response,
+    Reply,
+};
+
+/// ETag of project state read at a revision of the project
+pub fn revision_etag(revision: i64) -> String {
+    format!("\"r{}\"", revision)
+}
+
+/// ETag of state that is not stored in the project, derived from its content
+pub fn content_etag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"c{:x}\"", hasher.finish())
+}
+
+/// Whether an `If-None-Match` header matches an ETag.
+///
+/// Tags are compared weakly, as gzipped replies carry the weak form of their ETag.
+pub fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match.is_some_and(|tags| {
+        tags.split(',')
+            .map(|t| t.trim())
+            .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+    })
+}
+
+/// Reply to a client that already has the current state
+pub fn not_modified(etag: &str) -> Response {
+    let reply = warp::reply::with_status(warp::reply(), StatusCode::NOT_MODIFIED);
+    warp::reply::with_header(reply, header::ETAG, etag).into_response()
+}
+
+/// Add the headers clients use to revalidate a state snapshot, to a successful reply.
+///
+/// `last_modified` is in Unix seconds.
+pub fn with_cache_headers(reply: impl Reply, etag: &str, last_modified: Option<i64>) -> Response {
+    let mut response = reply.into_response();
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, etag);
+    }
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    if let Some(date) = last_modified.and_then(http_date) {
+        if let Ok(date) = HeaderValue::from_str(&date) {
+            headers.insert(header::LAST_MODIFIED, date);
+        }
+    }
+    response
+}
+
+/// Format Unix seconds as an HTTP date, like `Sun, 06 Nov 1994 08:49:37 GMT`
+fn http_date(seconds: i64) -> Option<String> {
+    OffsetDateTime::from_unix_timestamp(seconds)
+        .ok()?
+        .format(format_description!(
+            "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+        ))
+        .ok()
+}
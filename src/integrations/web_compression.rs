@@ -0,0 +1,116 @@
+use std::io::Write;
+
+use flate2::{write::GzEncoder, Compression};
+use warp::hyper::{
+    self,
+    body::HttpBody,
+    header::{self, HeaderValue},
+    Body, Request, Response, StatusCode,
+};
+
+/// Responses smaller than this are sent as they are, as gzip would barely shrink them
+const GZIP_MIN_BYTES: u64 = 1024;
+
+/// Paths that are never compressed, as they are upgraded to websockets
+const UNCOMPRESSED_PATHS: &[&str] = &["/ws"];
+
+/// Whether the response to a request may be gzipped
+pub fn accepts_gzip(request: &Request<Body>) -> bool {
+    if UNCOMPRESSED_PATHS
+        .iter()
+        .any(|p| request.uri().path().starts_with(p))
+        || request.headers().contains_key(header::UPGRADE)
+    {
+        return false;
+    }
+
+    request
+        .headers()
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut params = encoding.split(';').map(|p| p.trim());
+            // A quality of 0 means the client refuses the encoding
+            params
+                .next()
+                .is_some_and(|e| e.eq_ignore_ascii_case("gzip"))
+                && params
+                    .find_map(|p| p.strip_prefix("q="))
+                    .is_none_or(|q| q.parse::<f32>().is_ok_and(|q| q > 0.0))
+        })
+}
+
+/// Whether a content type is text that gzip shrinks well
+fn is_compressible(content_type: &str) -> bool {
+    let content_type = content_type.split(';').next().unwrap_or_default().trim();
+    content_type.starts_with("text/")
+        || content_type.ends_with("json")
+        || content_type.ends_with("javascript")
+        || content_type.ends_with("xml")
+}
+
+/// Gzip a response if it is text large enough to be worth it.
+///
+/// Only bodies of known size are compressed, streamed bodies such as
+/// files are sent as they are.
+pub async fn gzip_response(response: Response<Body>) -> Response<Body> {
+    let headers = response.headers();
+    let compressible = response.status() == StatusCode::OK
+        && !headers.contains_key(header::CONTENT_ENCODING)
+        && headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .is_some_and(is_compressible)
+        && response
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|len| len >= GZIP_MIN_BYTES);
+    if !compressible {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::error!("Failed to read response body to compress: {}", e);
+            parts.status = StatusCode::INTERNAL_SERVER_ERROR;
+            parts.headers.remove(header::CONTENT_LENGTH);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let mut encoder = GzEncoder::new(Vec::with_capacity(bytes.len() / 4), Compression::fast());
+    let compressed = match encoder.write_all(&bytes).and_then(|_| encoder.finish()) {
+        Ok(compressed) => compressed,
+        Err(e) => {
+            log::error!("Failed to compress response: {}", e);
+            return Response::from_parts(parts, Body::from(bytes));
+        }
+    };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    // The compressed body is a different representation, so its ETag can only match weakly
+    if let Some(etag) = parts
+        .headers
+        .get(header::ETAG)
+        .and_then(|e| e.to_str().ok())
+    {
+        if !etag.starts_with("W/") {
+            if let Ok(weak) = HeaderValue::from_str(&format!("W/{}", etag)) {
+                parts.headers.insert(header::ETAG, weak);
+            }
+        }
+    }
+
+    Response::from_parts(parts, Body::from(compressed))
+}
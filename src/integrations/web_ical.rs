@@ -0,0 +1,311 @@
+use serde::Deserialize;
+use sqlx::types::time::{OffsetDateTime, UtcOffset};
+
+use crate::core::{db::ProjectStore, event::Event, settings::Settings};
+
+/// Product identifier of the exported calendar
+const PRODID: &str = "-//BrickBench//AutoMarathon//EN";
+
+/// Longest line allowed by RFC 5545 in octets, excluding the line break
+const MAX_LINE_OCTETS: usize = 75;
+
+/// Filters for `/schedule.ics`, all of which are optional
+#[derive(Deserialize, Default, Debug)]
+pub struct ScheduleFilter {
+    pub tournament: Option<i64>,
+    /// OBS host the event is streamed or scheduled on
+    pub host: Option<String>,
+}
+
+/// Escape a value of a TEXT property
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Append a content line, folding it so no line is longer than 75 octets
+fn push_line(out: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            // Continuation lines start with a space, which counts towards their length
+            out.push_str("\r\n ");
+            octets = 1;
+        }
+        out.push(c);
+        octets += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+/// Format a time as a UTC DATE-TIME value
+fn format_utc(time: OffsetDateTime) -> String {
+    let time = time.to_offset(UtcOffset::UTC);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// Format a time as a local DATE-TIME value in a fixed offset
+fn format_local(time: OffsetDateTime, offset: UtcOffset) -> String {
+    let time = time.to_offset(offset);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// The TZID and UTC-OFFSET value of a fixed offset, eg. `UTC+02:00` and `+0200`
+fn describe_offset(offset: UtcOffset) -> (String, String) {
+    let (hours, minutes, _) = offset.as_hms();
+    let sign = if offset.is_negative() { '-' } else { '+' };
+    (
+        format!("UTC{}{:02}:{:02}", sign, hours.abs(), minutes.abs()),
+        format!("{}{:02}{:02}", sign, hours.abs(), minutes.abs()),
+    )
+}
+
+/// Write a time zone component for the `timezone` setting, which is a fixed offset
+fn write_timezone(out: &mut String, offset: UtcOffset) {
+    let (tzid, value) = describe_offset(offset);
+    push_line(out, "BEGIN:VTIMEZONE");
+    push_line(out, &format!("TZID:{}", tzid));
+    push_line(out, "BEGIN:STANDARD");
+    push_line(out, "DTSTART:19700101T000000");
+    push_line(out, &format!("TZOFFSETFROM:{}", value));
+    push_line(out, &format!("TZOFFSETTO:{}", value));
+    push_line(out, "END:STANDARD");
+    push_line(out, "END:VTIMEZONE");
+}
+
+/// The OBS host an event is shown on, preferring its stream over its scheduled host
+async fn get_event_host(db: &dyn ProjectStore, event: &Event) -> Option<String> {
+    match db.get_stream(event.id).await {
+        Ok(stream) => Some(stream.obs_host),
+        Err(_) => event.scheduled_host.clone(),
+    }
+}
+
+async fn write_event(
+    out: &mut String,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    event: &Event,
+    start: OffsetDateTime,
+    host: Option<&str>,
+    stamp: &str,
+) -> anyhow::Result<()> {
+    let mut runners = vec![];
+    for runner in db.get_event_runner_order(event.id).await? {
+        runners.push(db.get_name_for_runner(runner).await?);
+    }
+
+    let mut summary = match (&event.game, &event.category) {
+        (Some(game), Some(category)) => format!("{} \u{2014} {}", game, category),
+        (Some(game), None) => game.clone(),
+        _ => event.name.clone(),
+    };
+    if !runners.is_empty() {
+        summary.push_str(&format!(" ({})", runners.join(", ")));
+    }
+
+    let mut description = vec![];
+    if let Ok(stream) = db.get_stream(event.id).await {
+        let commentators: Vec<String> = stream
+            .get_commentators()
+            .into_iter()
+            .filter(|c| !c.is_empty())
+            .collect();
+        if !commentators.is_empty() {
+            description.push(format!("Commentators: {}", commentators.join(", ")));
+        }
+    }
+    if let Some(channel) = host
+        .and_then(|h| settings.obs_hosts.get(h))
+        .and_then(|h| h.twitch_channel.as_ref())
+    {
+        description.push(format!("Watch: https://twitch.tv/{}", channel));
+    }
+
+    push_line(out, "BEGIN:VEVENT");
+    // Derived from the event ID, so calendars update the event instead of duplicating it
+    push_line(out, &format!("UID:event-{}@automarathon", event.id));
+    push_line(out, &format!("DTSTAMP:{}", stamp));
+    let offset = settings.get_timezone();
+    if offset.is_utc() {
+        push_line(out, &format!("DTSTART:{}", format_utc(start)));
+    } else {
+        let (tzid, _) = describe_offset(offset);
+        push_line(
+            out,
+            &format!("DTSTART;TZID={}:{}", tzid, format_local(start, offset)),
+        );
+    }
+    if let Some(estimate) = event.estimate.filter(|e| *e > 0) {
+        push_line(out, &format!("DURATION:PT{}S", estimate));
+    }
+    push_line(out, &format!("SUMMARY:{}", escape_text(&summary)));
+    if !description.is_empty() {
+        push_line(
+            out,
+            &format!("DESCRIPTION:{}", escape_text(&description.join("\n"))),
+        );
+    }
+    push_line(out, "END:VEVENT");
+
+    Ok(())
+}
+
+/// Write the events with a start time as an iCalendar file
+pub async fn export_schedule(
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    filter: &ScheduleFilter,
+) -> anyhow::Result<String> {
+    let stamp = format_utc(OffsetDateTime::now_utc());
+
+    let mut out = String::new();
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, &format!("PRODID:{}", PRODID));
+    push_line(&mut out, "CALSCALE:GREGORIAN");
+    if !settings.get_timezone().is_utc() {
+        write_timezone(&mut out, settings.get_timezone());
+    }
+
+    for id in db.get_event_ids().await? {
+        let event = db.get_event(id).await?;
+        let Some(start) = event.event_start_time else {
+            continue;
+        };
+        if filter.tournament.is_some() && event.tournament != filter.tournament {
+            continue;
+        }
+
+        let host = get_event_host(db, &event).await;
+        if filter.host.is_some() && host != filter.host {
+            continue;
+        }
+
+        write_event(
+            &mut out,
+            db,
+            settings,
+            &event,
+            start,
+            host.as_deref(),
+            &stamp,
+        )
+        .await?;
+    }
+
+    push_line(&mut out, "END:VCALENDAR");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::time::macros::{datetime, offset};
+
+    use super::*;
+
+    /// Lines of an output with their line breaks checked and removed
+    fn lines(out: &str) -> Vec<&str> {
+        let lines: Vec<&str> = out.split_terminator("\r\n").collect();
+        assert!(out.ends_with("\r\n"));
+        assert!(lines.iter().all(|l| !l.contains('\n')));
+        lines
+    }
+
+    /// Undo folding, as a calendar reading the output does
+    fn unfold(out: &str) -> String {
+        out.replace("\r\n ", "")
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(escape_text("Game \u{2014} Any%"), "Game \u{2014} Any%");
+        assert_eq!(escape_text("Race (Alice, Bob)"), "Race (Alice\\, Bob)");
+        assert_eq!(escape_text("a;b"), "a\\;b");
+        assert_eq!(escape_text("C:\\runs"), "C:\\\\runs");
+        assert_eq!(
+            escape_text("first\r\nsecond\nthird"),
+            "first\\nsecond\\nthird"
+        );
+        // Backslashes are escaped once, before the escapes they introduce
+        assert_eq!(escape_text("\\,"), "\\\\\\,");
+    }
+
+    #[test]
+    fn short_lines_are_not_folded() {
+        let mut out = String::new();
+        let line = format!("SUMMARY:{}", "a".repeat(MAX_LINE_OCTETS - "SUMMARY:".len()));
+        push_line(&mut out, &line);
+        assert_eq!(lines(&out), vec![line.as_str()]);
+    }
+
+    #[test]
+    fn long_lines_are_folded_at_75_octets() {
+        let mut out = String::new();
+        let line = format!("DESCRIPTION:{}", "x".repeat(200));
+        push_line(&mut out, &line);
+
+        let lines = lines(&out);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), MAX_LINE_OCTETS);
+        assert!(lines[1..]
+            .iter()
+            .all(|l| l.starts_with(' ') && l.len() <= MAX_LINE_OCTETS));
+        assert_eq!(unfold(&out), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn folding_does_not_split_characters() {
+        let mut out = String::new();
+        // Three octets each, so 74 octets of ASCII leave no room for the next one
+        let line = format!("SUMMARY:{}{}", "a".repeat(66), "\u{2014}".repeat(30));
+        push_line(&mut out, &line);
+
+        for line in lines(&out) {
+            assert!(line.len() <= MAX_LINE_OCTETS, "{} octets", line.len());
+        }
+        assert_eq!(lines(&out)[0].len(), 74);
+        assert_eq!(unfold(&out), format!("{}\r\n", line));
+    }
+
+    #[test]
+    fn times_are_formatted_in_utc_or_the_offset() {
+        let time = datetime!(2024-05-01 18:30:05 +02:00);
+        assert_eq!(format_utc(time), "20240501T163005Z");
+        assert_eq!(format_local(time, offset!(-05:00)), "20240501T113005");
+        assert_eq!(
+            describe_offset(offset!(-05:30)),
+            ("UTC-05:30".to_owned(), "-0530".to_owned())
+        );
+        assert_eq!(
+            describe_offset(offset!(+02:00)),
+            ("UTC+02:00".to_owned(), "+0200".to_owned())
+        );
+    }
+}
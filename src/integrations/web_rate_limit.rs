@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use warp::hyper::{header, Body, Method, Request, Response, StatusCode};
+
+use crate::core::settings::Settings;
+
+/// Default `rate_limit_per_minute`
+pub const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 120;
+
+/// Default `rate_limit_burst`
+pub const DEFAULT_RATE_LIMIT_BURST: u32 = 20;
+
+/// Number of clients above which full buckets are forgotten
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// Requests a client may still make, refilled over time
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket rate limiter for requests that change the project
+pub struct RateLimiter {
+    /// Tokens added per second
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter, or None if `per_minute` is 0
+    pub fn new(per_minute: u32, burst: u32) -> Option<Self> {
+        if per_minute == 0 {
+            return None;
+        }
+        Some(Self {
+            rate: per_minute as f64 / 60.0,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::default(),
+        })
+    }
+
+    /// Take a token for a client, returning how long to wait if there is none
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst
+            });
+        }
+
+        let bucket = buckets.entry(client.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated).as_secs_f64() * self.rate)
+            .min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+/// Whether a request can change the project and is rate limited
+fn is_mutating(method: &Method) -> bool {
+    matches!(*method, Method::POST | Method::PUT | Method::DELETE)
+}
+
+/// Rate limit a request by client address and credentials,
+/// returning the 429 response to send if the client is over its limit.
+///
+/// The response is sent without going through the CORS filter of the routes,
+/// so it allows the origins of `allowed_origins` itself.
+pub fn limit_request(
+    limiter: Option<&RateLimiter>,
+    settings: &Settings,
+    addr: IpAddr,
+    request: &Request<Body>,
+) -> Option<Response<Body>> {
+    let limiter = limiter?;
+    // Triggers change the project through GET requests
+    if !is_mutating(request.method()) && !request.uri().path().starts_with("/trigger/") {
+        return None;
+    }
+
+    // Clients behind the same address are told apart by their credentials, if any
+    let client = match request.headers().get(header::AUTHORIZATION) {
+        Some(token) => format!("{} {}", addr, String::from_utf8_lossy(token.as_bytes())),
+        None => addr.to_string(),
+    };
+
+    let retry_after = limiter.check(&client).err()?;
+    let seconds = retry_after.as_secs() + 1;
+    log::warn!(
+        "Rate limited {} {} from {}, retry in {}s",
+        request.method(),
+        request.uri().path(),
+        addr,
+        seconds
+    );
+
+    let mut response = Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, seconds.to_string())
+        .header(header::VARY, header::ORIGIN.as_str());
+    if let Some(origin) = request.headers().get(header::ORIGIN) {
+        if origin
+            .to_str()
+            .is_ok_and(|origin| settings.is_origin_allowed(origin))
+        {
+            response = response
+                .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+                .header(
+                    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                    header::RETRY_AFTER.as_str(),
+                );
+        }
+    }
+    response
+        .body(Body::from(format!(
+            "Too many requests, retry in {} seconds",
+            seconds
+        )))
+        .ok()
+}
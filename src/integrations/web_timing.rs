@@ -0,0 +1,155 @@
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use sqlx::types::time::OffsetDateTime;
+use warp::hyper::{service::Service, Body, Request, Response};
+
+/// Default `slow_request_millis`
+pub const DEFAULT_SLOW_REQUEST_MILLIS: u64 = 1000;
+
+/// Number of slow requests kept for `/debug/timings`
+const SLOWEST_REQUEST_COUNT: usize = 20;
+
+/// How long a slow request is kept for `/debug/timings`
+const SLOW_REQUEST_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Paths that are not API calls, which are never reported as slow
+const UNTIMED_PATHS: &[&str] = &["/ws", "/static"];
+
+tokio::task_local! {
+    static REQUEST_TIMING: RequestTiming;
+}
+
+/// Time spent waiting on an actor while handling a request
+#[derive(Serialize, Clone, Debug)]
+pub struct ActorCallTiming {
+    pub call: String,
+    pub millis: f64,
+}
+
+/// Actor calls made while handling a single request
+#[derive(Clone, Default)]
+struct RequestTiming {
+    calls: Arc<Mutex<Vec<ActorCallTiming>>>,
+}
+
+/// A request that took longer than `slow_request_millis`
+#[derive(Serialize, Clone, Debug)]
+pub struct SlowRequest {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub millis: f64,
+    /// Completion time in Unix millis
+    pub time: i64,
+    /// Actor calls made by the request, slowest first
+    pub calls: Vec<ActorCallTiming>,
+    /// Time not spent waiting on actors, such as database queries
+    pub other_millis: f64,
+}
+
+/// The slowest recent requests handled by the web server
+pub struct RequestTimings {
+    slow_threshold: Duration,
+    slowest: Mutex<Vec<(Instant, SlowRequest)>>,
+}
+
+impl RequestTimings {
+    pub fn new(slow_threshold: Duration) -> Self {
+        Self {
+            slow_threshold,
+            slowest: Mutex::default(),
+        }
+    }
+
+    /// The slowest requests of the last 15 minutes, slowest first
+    pub fn get_slowest(&self) -> Vec<SlowRequest> {
+        let mut slowest = self.slowest.lock().unwrap();
+        slowest.retain(|(at, _)| at.elapsed() < SLOW_REQUEST_WINDOW);
+        slowest.iter().map(|(_, r)| r.clone()).collect()
+    }
+
+    fn add_slow_request(&self, request: SlowRequest) {
+        let mut slowest = self.slowest.lock().unwrap();
+        slowest.retain(|(at, _)| at.elapsed() < SLOW_REQUEST_WINDOW);
+        slowest.push((Instant::now(), request));
+        slowest.sort_by(|a, b| b.1.millis.total_cmp(&a.1.millis));
+        slowest.truncate(SLOWEST_REQUEST_COUNT);
+    }
+}
+
+/// Record how long an actor call took, if it was made while handling a web request
+pub fn record_actor_call(call: &str, elapsed: Duration) {
+    let _ = REQUEST_TIMING.try_with(|timing| {
+        timing.calls.lock().unwrap().push(ActorCallTiming {
+            call: call.to_owned(),
+            millis: elapsed.as_secs_f64() * 1000.0,
+        })
+    });
+}
+
+/// Handle a request with `service`, logging its latency and
+/// keeping track of it if it is slow
+pub async fn timed_request<S>(
+    mut service: S,
+    timings: Arc<RequestTimings>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    let method = request.method().to_string();
+    let path = request.uri().path().to_owned();
+    let timing = RequestTiming::default();
+    let start = Instant::now();
+
+    let response = REQUEST_TIMING
+        .scope(timing.clone(), service.call(request))
+        .await?;
+
+    let elapsed = start.elapsed();
+    let status = response.status().as_u16();
+    log::debug!("{} {} {} in {:?}", method, path, status, elapsed);
+
+    if elapsed < timings.slow_threshold || UNTIMED_PATHS.iter().any(|p| path.starts_with(p)) {
+        return Ok(response);
+    }
+
+    let mut calls = timing.calls.lock().unwrap().clone();
+    calls.sort_by(|a, b| b.millis.total_cmp(&a.millis));
+    let millis = elapsed.as_secs_f64() * 1000.0;
+    let other_millis = (millis - calls.iter().map(|c| c.millis).sum::<f64>()).max(0.0);
+
+    match calls.first() {
+        Some(slowest) => log::warn!(
+            "Slow request {} {} took {:?}, mostly waiting on {} for {:.0}ms",
+            method,
+            path,
+            elapsed,
+            slowest.call,
+            slowest.millis
+        ),
+        None => log::warn!(
+            "Slow request {} {} took {:?} without waiting on any actor",
+            method,
+            path,
+            elapsed
+        ),
+    }
+
+    timings.add_slow_request(SlowRequest {
+        method,
+        path,
+        status,
+        millis,
+        time: (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64,
+        calls,
+        other_millis,
+    });
+
+    Ok(response)
+}
@@ -0,0 +1,249 @@
+use std::net::IpAddr;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use sqlx::types::time::OffsetDateTime;
+
+use crate::{
+    core::{
+        db::ProjectStore,
+        event::EventRequest,
+        settings::Settings,
+        stream::{next_fitting_layout, StreamRequest, StreamState},
+    },
+    error::Error,
+    integrations::obs::{LayoutSource, ObsCommand},
+    send_message, Directory, Rto,
+};
+
+/// Address of the client that sent a request, added to the request by the web server
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddr(pub IpAddr);
+
+/// Query of a `/trigger/...` endpoint
+#[derive(Deserialize, Debug)]
+pub struct TriggerQuery {
+    pub token: Option<String>,
+    pub host: Option<String>,
+    pub event: Option<i64>,
+}
+
+/// Why a trigger was refused before running
+pub enum TriggerRefusal {
+    /// No 'trigger_token' is set
+    Disabled,
+    InvalidToken,
+}
+
+/// Check the trigger token of a request
+pub fn check_trigger_token(
+    query: &TriggerQuery,
+    settings: &Settings,
+) -> Result<(), TriggerRefusal> {
+    match &settings.trigger_token {
+        None => Err(TriggerRefusal::Disabled),
+        Some(token) if query.token.as_ref() != Some(token) => Err(TriggerRefusal::InvalidToken),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Run a trigger by name, returning a short description of what happened
+pub async fn run_trigger(
+    action: &str,
+    query: &TriggerQuery,
+    db: &dyn ProjectStore,
+    settings: &Settings,
+    directory: &Directory,
+) -> anyhow::Result<String> {
+    match action {
+        "next-layout" => next_layout(db, directory, &get_active_stream(query, db).await?).await,
+        "swap-audible" => swap_audible(db, directory, get_active_stream(query, db).await?).await,
+        "start-timer" => start_timer(db, directory, get_event(query)?).await,
+        "stop-timer" => stop_timer(db, directory, get_event(query)?).await,
+        "intermission" => intermission(settings, directory, get_host(query)?).await,
+        _ => Err(Error::InvalidRequest(
+            "trigger".to_owned(),
+            format!("Unknown trigger {}", action),
+        )
+        .into()),
+    }
+}
+
+fn get_host(query: &TriggerQuery) -> anyhow::Result<String> {
+    query
+        .host
+        .clone()
+        .ok_or_else(|| Error::InvalidRequest("host".to_owned(), "Missing 'host'".to_owned()).into())
+}
+
+fn get_event(query: &TriggerQuery) -> anyhow::Result<i64> {
+    query.event.ok_or_else(|| {
+        Error::InvalidRequest("event".to_owned(), "Missing 'event'".to_owned()).into()
+    })
+}
+
+/// The active stream on the host of a trigger
+async fn get_active_stream(
+    query: &TriggerQuery,
+    db: &dyn ProjectStore,
+) -> anyhow::Result<StreamState> {
+    let host = get_host(query)?;
+    let event = db.get_event_by_obs_host(&host).await?;
+    db.get_stream(event).await
+}
+
+/// Show the next layout that has a runner in every slot, following the stream's
+/// layout rotation if it has one and the host's scene order otherwise
+async fn next_layout(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    stream: &StreamState,
+) -> anyhow::Result<String> {
+    let host = stream.obs_host.clone();
+    let source: Option<LayoutSource> = None;
+    let layouts = send_message!(directory.obs_actor, ObsCommand, GetLayouts, host, source)?.layouts;
+    let fits = |name: &String| {
+        layouts.iter().any(|scene| {
+            &scene.name == name
+                && !scene.sources.is_empty()
+                && scene
+                    .sources
+                    .keys()
+                    .all(|slot| stream.stream_runners.contains_key(&(*slot as i64)))
+        })
+    };
+
+    let order: Vec<String> = match &stream.rotation {
+        Some(rotation) if !rotation.layouts.is_empty() => rotation.layouts.clone(),
+        _ => layouts.iter().map(|scene| scene.name.clone()).collect(),
+    };
+    let next = next_fitting_layout(&order, stream.requested_layout.as_ref(), fits).cloned();
+
+    match next {
+        Some(next) if stream.requested_layout.as_ref() == Some(&next) => {
+            Ok(format!("{} is the only layout that fits", next))
+        }
+        Some(next) => {
+            let mut stream = db.get_stream(stream.event).await?;
+            stream.requested_layout = Some(next.clone());
+            send_message!(directory.stream_actor, StreamRequest, Update, stream, false)?;
+            Ok(format!("Showing {}", next))
+        }
+        None => Err(anyhow!("No layout fits the runners of the stream")),
+    }
+}
+
+/// Make the runner in the next slot audible
+async fn swap_audible(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    mut stream: StreamState,
+) -> anyhow::Result<String> {
+    let mut slots: Vec<(i64, i64)> = stream
+        .stream_runners
+        .iter()
+        .map(|(s, r)| (*s, *r))
+        .collect();
+    slots.sort();
+    let runners: Vec<i64> = slots.into_iter().map(|(_, runner)| runner).collect();
+
+    let current = stream
+        .audible_runner
+        .and_then(|a| runners.iter().position(|r| *r == a));
+    let next = match current {
+        Some(idx) => runners[(idx + 1) % runners.len()],
+        None => *runners
+            .first()
+            .ok_or_else(|| anyhow!("The stream has no runners in view"))?,
+    };
+    if stream.audible_runner == Some(next) {
+        return Ok("Only one runner is in view".to_owned());
+    }
+
+    stream.audible_runner = Some(next);
+    send_message!(directory.stream_actor, StreamRequest, Update, stream, false)?;
+    Ok(format!(
+        "{} is audible",
+        db.get_name_for_runner(next).await?
+    ))
+}
+
+/// Start the timer of an event, doing nothing if it is already running
+async fn start_timer(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+) -> anyhow::Result<String> {
+    let event = db.get_event(event).await?;
+    match (event.timer_start_time, event.timer_end_time) {
+        (Some(_), None) => Ok("Timer is already running".to_owned()),
+        (Some(_), Some(_)) => Err(Error::InvalidRequest(
+            "event".to_owned(),
+            format!("The timer of {} was already stopped", event.name),
+        )
+        .into()),
+        (None, _) => {
+            let now = Some(OffsetDateTime::now_utc());
+            send_message!(
+                directory.event_actor,
+                EventRequest,
+                SetStartTime,
+                event.id,
+                now
+            )?;
+            Ok("Timer started".to_owned())
+        }
+    }
+}
+
+/// Stop the timer of an event, doing nothing if it is already stopped
+async fn stop_timer(
+    db: &dyn ProjectStore,
+    directory: &Directory,
+    event: i64,
+) -> anyhow::Result<String> {
+    let event = db.get_event(event).await?;
+    match (event.timer_start_time, event.timer_end_time) {
+        (_, Some(_)) => Ok("Timer is already stopped".to_owned()),
+        (None, None) => Err(Error::InvalidRequest(
+            "event".to_owned(),
+            format!("The timer of {} was not started", event.name),
+        )
+        .into()),
+        (Some(_), None) => {
+            let now = Some(OffsetDateTime::now_utc());
+            send_message!(
+                directory.event_actor,
+                EventRequest,
+                SetEndTime,
+                event.id,
+                now
+            )?;
+            Ok("Timer stopped".to_owned())
+        }
+    }
+}
+
+/// Show the intermission scene of a host
+async fn intermission(
+    settings: &Settings,
+    directory: &Directory,
+    host: String,
+) -> anyhow::Result<String> {
+    let scene = settings
+        .obs_hosts
+        .get(&host)
+        .ok_or_else(|| Error::InvalidRequest("host".to_owned(), format!("No OBS host {}", host)))?
+        .intermission_scene
+        .clone()
+        .ok_or_else(|| anyhow!("OBS host {} has no 'intermission_scene'", host))?;
+    let outcome = format!("Showing {}", scene);
+    send_message!(
+        directory.stream_actor,
+        StreamRequest,
+        SwitchScene,
+        host,
+        scene
+    )?;
+    Ok(outcome)
+}
@@ -1,26 +1,50 @@
+// The warp route chain in the web server nests deeper than the default limit
+#![recursion_limit = "512"]
+
 use core::{
+    backup::{run_backup_actor, BackupActor},
     event::{run_event_actor, EventActor},
+    health::HealthStatus,
+    instance_lock::InstanceLock,
     runner::{run_runner_actor, RunnerActor},
 };
-use std::{env::consts, fs::read_to_string, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    env::consts,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use anyhow::anyhow;
 use clap::Parser;
+use serde::Serialize;
 
-use integrations::web::{run_http_server, WebActor};
+use integrations::web::{run_http_server, WebActor, WebCommand, DEFAULT_WEB_PORT};
 use tokio::{
     sync::{
-        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        mpsc::{self, error::TryRecvError, UnboundedReceiver, UnboundedSender},
         oneshot,
     },
     task::JoinSet,
 };
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
 
 use crate::{
-    core::db::ProjectDb,
+    core::db::{ProjectDb, ProjectStore},
     core::settings::Settings,
     core::stream::{run_stream_manager, StreamActor},
-    integrations::obs::{run_obs, ObsActor},
+    integrations::{
+        discord::DiscordActor,
+        obs::{run_obs, ObsActor},
+        tiltify::{run_tiltify_actor, TiltifyActor},
+        twitch_chat::{run_twitch_chat, TwitchChatActor},
+    },
 };
 
 mod core;
@@ -29,9 +53,24 @@ mod integrations;
 
 const AUTOMARATHON_VER: &str = "0.1";
 
+/// How long send_message! waits for a reply before failing
+pub const MESSAGE_TIMEOUT: Duration = Duration::from_secs(20);
+
 #[macro_export]
 macro_rules! send_message {
     ($actor: expr, $type: ident, $msg: ident, $($vals: expr),*) => {
+        $crate::send_message_with_timeout!($crate::MESSAGE_TIMEOUT, $actor, $type, $msg, $($vals),*)
+    };
+    ($actor: expr, $type: ident, $msg: ident) => {
+        $crate::send_message_with_timeout!($crate::MESSAGE_TIMEOUT, $actor, $type, $msg)
+    };
+}
+
+/// Send a message and wait for the reply, failing with `Error::ActorTimeout`
+/// if there is none within `$timeout`
+#[macro_export]
+macro_rules! send_message_with_timeout {
+    ($timeout: expr, $actor: expr, $type: ident, $msg: ident, $($vals: expr),*) => {
         {
             let (tx, rx) = Rto::new();
             if log::log_enabled!(log::Level::Debug) {
@@ -42,14 +81,18 @@ macro_rules! send_message {
                 log::debug!("{}", log_str);
             }
 
-            $actor.send($type::$msg($($vals),*, tx));
-            match rx.await {
-                Ok(val) => val,
-                Err(e) => Err(e.into())
+            let message = $type::$msg($($vals),*, tx);
+            let trace = $crate::core::trace::begin(stringify!($type), &message);
+            $crate::message_span(stringify!($type), stringify!($msg))
+                .in_scope(|| $actor.send(message));
+            let reply = $crate::await_reply!($timeout, rx, $actor, $type, $msg);
+            if let Some(trace) = trace {
+                trace.finish(&reply);
             }
+            reply
         }
     };
-    ($actor: expr, $type: ident, $msg: ident) => {
+    ($timeout: expr, $actor: expr, $type: ident, $msg: ident) => {
         {
             let (tx, rx) = Rto::new();
             if log::log_enabled!(log::Level::Debug) {
@@ -57,21 +100,63 @@ macro_rules! send_message {
                         stringify!($type), stringify!($msg), stringify!($actor));
             }
 
-            $actor.send($type::$msg(tx));
-            match rx.await {
-                Ok(val) => val,
-                Err(e) => Err(e.into())
+            let message = $type::$msg(tx);
+            let trace = $crate::core::trace::begin(stringify!($type), &message);
+            $crate::message_span(stringify!($type), stringify!($msg))
+                .in_scope(|| $actor.send(message));
+            let reply = $crate::await_reply!($timeout, rx, $actor, $type, $msg);
+            if let Some(trace) = trace {
+                trace.finish(&reply);
             }
+            reply
         }
     };
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! await_reply {
+    ($timeout: expr, $rx: expr, $actor: expr, $type: ident, $msg: ident) => {{
+        let timeout: std::time::Duration = $timeout;
+        let start = std::time::Instant::now();
+        let reply = tokio::time::timeout(timeout, $rx).await;
+        $crate::integrations::web_timing::record_actor_call(
+            concat!(stringify!($type), "::", stringify!($msg)),
+            start.elapsed(),
+        );
+        match reply {
+            Ok(Ok(val)) => val,
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => {
+                log::error!(
+                    "No reply to {}::{} from {} within {:?}",
+                    stringify!($type),
+                    stringify!($msg),
+                    stringify!($actor),
+                    timeout
+                );
+                Err($crate::error::Error::ActorTimeout(
+                    stringify!($actor).trim_start_matches('&').to_owned(),
+                    concat!(stringify!($type), "::", stringify!($msg)).to_owned(),
+                    timeout.as_secs(),
+                )
+                .into())
+            }
+        }
+    }};
+}
+
 #[macro_export]
 macro_rules! send_nonblocking {
     ($actor: expr, $type: ident, $msg: ident, $($vals: expr),*) => {
         {
             let (tx, rx) = Rto::new();
-            $actor.send($type::$msg($($vals),*, tx));
+            let message = $type::$msg($($vals),*, tx);
+            if let Some(trace) = $crate::core::trace::begin(stringify!($type), &message) {
+                trace.sent();
+            }
+            $crate::message_span(stringify!($type), stringify!($msg))
+                .in_scope(|| $actor.send(message));
             rx
         }
     };
@@ -85,24 +170,215 @@ pub struct Directory {
     pub runner_actor: RunnerActor,
     pub event_actor: EventActor,
     pub web_actor: WebActor,
+    pub backup_actor: BackupActor,
+    pub discord_actor: DiscordActor,
+    pub twitch_chat_actor: TwitchChatActor,
+    pub tiltify_actor: TiltifyActor,
+    /// Cached status of every actor, for the health check
+    pub health: Arc<HealthStatus>,
+}
+
+impl Directory {
+    /// Queue statistics of every actor
+    pub fn actor_stats(&self) -> Vec<ActorStatsReport> {
+        vec![
+            self.stream_actor.stats().report(),
+            self.obs_actor.stats().report(),
+            self.runner_actor.stats().report(),
+            self.event_actor.stats().report(),
+            self.web_actor.stats().report(),
+            self.backup_actor.stats().report(),
+            self.discord_actor.stats().report(),
+            self.twitch_chat_actor.stats().report(),
+            self.tiltify_actor.stats().report(),
+        ]
+    }
+}
+
+/// Create the span an actor handles a message in.
+///
+/// Handlers record the `event_id` and `host` fields once known,
+/// so all logs caused by a message can be filtered by them.
+pub fn message_span(actor: &'static str, msg: &'static str) -> Span {
+    tracing::info_span!(
+        "message",
+        actor,
+        msg,
+        event_id = tracing::field::Empty,
+        host = tracing::field::Empty
+    )
+}
+
+/// Record the event a message concerns on the current message span
+pub fn record_event(event: i64) {
+    Span::current().record("event_id", event);
+}
+
+/// Record the OBS host a message concerns on the current message span
+pub fn record_host(host: &str) {
+    Span::current().record("host", host);
+}
+
+/// Queue depth of an actor at which a backpressure warning is logged
+const ACTOR_QUEUE_WARN_DEPTH: usize = 100;
+
+/// Messages that can be sent to an actor
+pub trait ActorMessage {
+    /// Name of the message variant, used to label queue statistics
+    fn label(&self) -> &'static str;
+}
+
+/// Message counters of an actor's queue
+pub struct ActorStats {
+    name: &'static str,
+    sent: AtomicU64,
+    handled: AtomicU64,
+    /// Messages waiting in the queue by label
+    pending: Mutex<HashMap<&'static str, usize>>,
+    /// Whether the queue has crossed the warning depth and not yet drained
+    backed_up: AtomicBool,
+}
+
+/// Queue statistics of an actor, as reported by `/debug/actors`
+#[derive(Serialize, Debug)]
+pub struct ActorStatsReport {
+    pub actor: &'static str,
+    pub sent: u64,
+    pub handled: u64,
+    pub depth: u64,
+    /// Messages waiting in the queue by label
+    pub pending: HashMap<&'static str, usize>,
+}
+
+impl ActorStats {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            sent: AtomicU64::new(0),
+            handled: AtomicU64::new(0),
+            pending: Mutex::default(),
+            backed_up: AtomicBool::new(false),
+        }
+    }
+
+    /// Messages waiting in the queue, approximated by sent - handled
+    fn depth(&self) -> u64 {
+        let handled = self.handled.load(Ordering::Relaxed);
+        self.sent.load(Ordering::Relaxed).saturating_sub(handled)
+    }
+
+    fn record_sent(&self, label: &'static str) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        let most_pending = {
+            let mut pending = self.pending.lock().unwrap();
+            *pending.entry(label).or_default() += 1;
+            pending
+                .iter()
+                .max_by_key(|(_, count)| **count)
+                .map(|(l, c)| (*l, *c))
+        };
+
+        let depth = self.depth();
+        if depth >= ACTOR_QUEUE_WARN_DEPTH as u64 && !self.backed_up.swap(true, Ordering::Relaxed) {
+            if let Some((label, count)) = most_pending {
+                log::warn!(
+                    "Actor {} is backed up with {} queued messages, mostly {} ({})",
+                    self.name,
+                    depth,
+                    label,
+                    count
+                );
+            }
+        }
+    }
+
+    fn record_handled(&self, label: &'static str) {
+        self.handled.fetch_add(1, Ordering::Relaxed);
+        if let Some(count) = self.pending.lock().unwrap().get_mut(label) {
+            *count = count.saturating_sub(1);
+        }
+
+        // Warn again only once the queue has mostly drained
+        if self.depth() < (ACTOR_QUEUE_WARN_DEPTH / 2) as u64
+            && self.backed_up.swap(false, Ordering::Relaxed)
+        {
+            log::info!("Actor {} has caught up with its queue", self.name);
+        }
+    }
+
+    pub fn report(&self) -> ActorStatsReport {
+        let mut pending = self.pending.lock().unwrap().clone();
+        pending.retain(|_, count| *count > 0);
+        ActorStatsReport {
+            actor: self.name,
+            sent: self.sent.load(Ordering::Relaxed),
+            handled: self.handled.load(Ordering::Relaxed),
+            depth: self.depth(),
+            pending,
+        }
+    }
+}
+
+/// Receiver for an actor's messages, each paired with the span of its sender
+pub struct ActorReceiver<T> {
+    rx: UnboundedReceiver<(T, Span)>,
+    stats: Arc<ActorStats>,
+}
+
+impl<T: ActorMessage> ActorReceiver<T> {
+    /// Receive the next message, or None if every ActorRef was dropped
+    pub async fn recv(&mut self) -> Option<(T, Span)> {
+        let msg = self.rx.recv().await;
+        if let Some((msg, _)) = &msg {
+            self.stats.record_handled(msg.label());
+        }
+        msg
+    }
+
+    /// Receive the next message if one is queued
+    pub fn try_recv(&mut self) -> Result<(T, Span), TryRecvError> {
+        let msg = self.rx.try_recv();
+        if let Ok((msg, _)) = &msg {
+            self.stats.record_handled(msg.label());
+        }
+        msg
+    }
 }
 
 /// Actor reference
 pub struct ActorRef<T> {
-    tx: UnboundedSender<T>,
+    tx: UnboundedSender<(T, Span)>,
+    stats: Arc<ActorStats>,
 }
 
-impl<T> ActorRef<T> {
-    /// Send a message to the provided actor
+impl<T: ActorMessage> ActorRef<T> {
+    /// Send a message to the provided actor, to be handled in the current span
     pub fn send(&self, msg: T) {
-        let _ = self.tx.send(msg);
+        let label = msg.label();
+        if self.tx.send((msg, Span::current())).is_ok() {
+            self.stats.record_sent(label);
+        }
     }
+}
 
-    /// Spawn an actor, returning an ActorRef and the corresponding receiver
-    pub fn new() -> (Self, UnboundedReceiver<T>) {
+impl<T> ActorRef<T> {
+    /// Spawn an actor named `name`, returning an ActorRef and the corresponding receiver
+    pub fn new(name: &'static str) -> (Self, ActorReceiver<T>) {
         let (tx, rx) = mpsc::unbounded_channel();
+        let stats = Arc::new(ActorStats::new(name));
+
+        (
+            Self {
+                tx,
+                stats: stats.clone(),
+            },
+            ActorReceiver { rx, stats },
+        )
+    }
 
-        (Self { tx }, rx)
+    /// Queue statistics of this actor
+    pub fn stats(&self) -> &ActorStats {
+        &self.stats
     }
 }
 
@@ -110,6 +386,7 @@ impl<T> Clone for ActorRef<T> {
     fn clone(&self) -> Self {
         Self {
             tx: self.tx.clone(),
+            stats: self.stats.clone(),
         }
     }
 }
@@ -138,23 +415,70 @@ impl<T> Rto<T> {
 #[command(about = "An automation tool for speedrunning events.", long_about = None)]
 struct Args {
     /// The folder containing the project files.
-    #[clap(default_value = "/var/home/javst/Documents/AutoMarathonTest/")]
+    /// A folder without settings.json is set up as a new project
+    #[clap(default_value = ".")]
     project_folder: PathBuf,
+
+    /// Write logs as JSON lines
+    #[clap(long)]
+    json_logs: bool,
+
+    /// Take over the project folder's lock if the instance holding it no longer exists
+    #[clap(long)]
+    force: bool,
+}
+
+/// Exit status after setting up a new project folder, telling it apart from failures
+/// and from the status 2 of command line errors
+const NEW_PROJECT_EXIT_CODE: i32 = 3;
+
+/// Fail with a readable error if files cannot be created in the project folder
+fn check_writable(folder: &Path) -> anyhow::Result<()> {
+    let probe = folder.join(".automarathon-write-check");
+    std::fs::write(&probe, b"")
+        .and_then(|_| std::fs::remove_file(&probe))
+        .map_err(|e| anyhow!("Project folder {} is not writable: {}", folder.display(), e))
+}
+
+/// Write a template settings.json and an empty database to a new project folder
+async fn init_project(folder: &Path) -> anyhow::Result<()> {
+    let settings_path = folder.join("settings.json");
+    std::fs::write(&settings_path, Settings::template_json())?;
+    ProjectDb::load(&folder.join("project.db"), Box::new(|| {})).await?;
+
+    println!();
+    println!("Created a new project in {}", folder.display());
+    println!();
+    println!("Next steps:");
+    println!("  1. In OBS, enable the websocket server under Tools > WebSocket Server Settings");
+    println!(
+        "  2. Edit {} and set the address and password of each OBS host in 'obs_hosts'",
+        settings_path.display()
+    );
+    println!("  3. Fill in the integrations you use, such as 'discord_token'");
+    println!("  4. Start AutoMarathon again with the same project folder");
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    env_logger::builder()
-        .filter(Some("tracing::span"), log::LevelFilter::Warn)
-        .filter(Some("serenity"), log::LevelFilter::Warn)
-        .filter(Some("hyper"), log::LevelFilter::Warn)
-        .filter(Some("h2"), log::LevelFilter::Warn)
-        .filter(Some("rustls"), log::LevelFilter::Warn)
-        .filter(Some("sqlx"), log::LevelFilter::Info)
-        .filter_level(log::LevelFilter::Debug)
-        .init();
+    // RUST_LOG overrides the default filter
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(
+            "debug,tracing::span=warn,serenity=warn,hyper=warn,h2=warn,rustls=warn,sqlx=info",
+        )
+    });
+
+    if args.json_logs {
+        tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(filter)
+            .init();
+    } else {
+        tracing_subscriber::fmt().with_env_filter(filter).init();
+    }
 
     log::info!(
         "Launching AutoMarathon {} on {}",
@@ -168,13 +492,61 @@ async fn main() -> anyhow::Result<()> {
             args.project_folder.to_str().unwrap()
         ));
     }
+    check_writable(&args.project_folder)?;
+
+    if !args.project_folder.join("settings.json").exists() {
+        log::info!(
+            "No settings.json in {}, setting up a new project",
+            args.project_folder.display()
+        );
+        init_project(&args.project_folder).await?;
+        std::process::exit(NEW_PROJECT_EXIT_CODE);
+    }
+
+    // Load settings
+    let settings: Arc<Settings> = Arc::new(
+        Settings::parse(
+            &read_to_string(args.project_folder.join("settings.json")).map_err(|_| {
+                anyhow!(format!(
+                    "Failed to load settings.json file, could not read from {}/settings.json",
+                    args.project_folder.to_str().unwrap()
+                ))
+            })?,
+        )
+        .map_err(|e| anyhow!(format!("Error while loading settings.json: {}", e)))?,
+    );
+
+    let report = settings.validate();
+    for warning in &report.warnings {
+        log::warn!("settings.json: {}", warning);
+    }
+    if !report.errors.is_empty() {
+        for error in &report.errors {
+            log::error!("settings.json: {}", error);
+        }
+        return Err(anyhow!(
+            "settings.json has {} error(s), fix them before starting",
+            report.errors.len()
+        ));
+    }
+
+    // Two instances on one database interleave OBS updates and fail on sqlite locks
+    let _lock = InstanceLock::acquire(
+        &args.project_folder,
+        settings.web_port.unwrap_or(DEFAULT_WEB_PORT),
+        args.force,
+    )?;
 
     // Set up messaging channels
-    let (state_actor, state_rx) = StreamActor::new();
-    let (obs_actor, obs_rx) = ObsActor::new();
-    let (runner_actor, runner_rx) = RunnerActor::new();
-    let (event_actor, event_rx) = EventActor::new();
-    let (web_actor, web_rx) = WebActor::new();
+    let (state_actor, state_rx) = StreamActor::new("stream");
+    let (obs_actor, obs_rx) = ObsActor::new("obs");
+    let (runner_actor, runner_rx) = RunnerActor::new("runner");
+    let (event_actor, event_rx) = EventActor::new("event");
+    let (web_actor, web_rx) = WebActor::new("web");
+    let (backup_actor, backup_rx) = BackupActor::new("backup");
+    let (discord_actor, discord_rx) = DiscordActor::new("discord");
+    let (twitch_chat_actor, twitch_chat_rx) = TwitchChatActor::new("twitch_chat");
+    let (tiltify_actor, tiltify_rx) = TiltifyActor::new("tiltify");
 
     let directory = Directory {
         stream_actor: state_actor.clone(),
@@ -182,33 +554,73 @@ async fn main() -> anyhow::Result<()> {
         runner_actor: runner_actor.clone(),
         event_actor: event_actor.clone(),
         web_actor: web_actor.clone(),
+        backup_actor: backup_actor.clone(),
+        discord_actor: discord_actor.clone(),
+        twitch_chat_actor: twitch_chat_actor.clone(),
+        tiltify_actor: tiltify_actor.clone(),
+        health: Arc::new(HealthStatus::new()),
     };
 
-    let db = Arc::new(
-        ProjectDb::load(&args.project_folder.join("project.db"), directory.clone()).await?,
-    );
-
-    // Load settings
-    let settings: Arc<Settings> = Arc::new(
-        serde_json::from_str::<Settings>(
-            &read_to_string(args.project_folder.join("settings.json")).map_err(|_| {
-                anyhow!(format!(
-                    "Failed to load settings.json file, could not read from {}/settings.json",
-                    args.project_folder.to_str().unwrap()
-                ))
-            })?,
+    let update_actor = web_actor.clone();
+    let db: Arc<dyn ProjectStore> = Arc::new(
+        ProjectDb::load(
+            &args.project_folder.join("project.db"),
+            Box::new(move || update_actor.send(WebCommand::SendStateUpdate)),
         )
-        .map_err(|e| anyhow!(format!("Error while loading settings.json: {:?}", e)))?,
+        .await?,
     );
 
+    core::i18n::load_translations(&args.project_folder.join("locales"))?;
+
+    if let Some(dir) = &settings.message_trace_dir {
+        core::trace::start_recording(&args.project_folder.join(dir))?;
+    }
+
     let mut tasks = JoinSet::<Result<(), anyhow::Error>>::new();
 
     // Spawn core tasks
-    tasks.spawn(run_obs(settings.clone(), db.clone(), obs_rx));
-    tasks.spawn(run_stream_manager(db.clone(), state_rx, directory.clone()));
-    tasks.spawn(run_event_actor(db.clone(), event_rx, directory.clone()));
-    tasks.spawn(run_http_server(db.clone(), directory.clone(), settings.clone(), web_rx));
-    tasks.spawn(run_runner_actor(db.clone(), runner_rx));
+    tasks.spawn(run_obs(
+        settings.clone(),
+        db.clone(),
+        obs_rx,
+        directory.clone(),
+    ));
+    tasks.spawn(run_stream_manager(
+        db.clone(),
+        settings.clone(),
+        state_rx,
+        directory.clone(),
+    ));
+    tasks.spawn(run_event_actor(
+        db.clone(),
+        settings.clone(),
+        event_rx,
+        directory.clone(),
+    ));
+    tasks.spawn(run_http_server(
+        db.clone(),
+        directory.clone(),
+        settings.clone(),
+        web_rx,
+    ));
+    tasks.spawn(run_runner_actor(
+        db.clone(),
+        settings.clone(),
+        runner_rx,
+        directory.clone(),
+    ));
+    tasks.spawn(run_backup_actor(
+        db.clone(),
+        settings.clone(),
+        args.project_folder.clone(),
+        backup_rx,
+    ));
+    tasks.spawn(run_tiltify_actor(
+        db.clone(),
+        settings.clone(),
+        tiltify_rx,
+        directory.clone(),
+    ));
 
     // Spawn integrations
     if settings.discord_token.is_some() {
@@ -216,17 +628,118 @@ async fn main() -> anyhow::Result<()> {
             settings.clone(),
             db.clone(),
             directory.clone(),
+            discord_rx,
+        ));
+    } else {
+        // Discard Discord notifications
+        drop(discord_rx);
+    }
+
+    if settings.twitch_oauth_token.is_some() {
+        tasks.spawn(run_twitch_chat(
+            settings.clone(),
+            db.clone(),
+            twitch_chat_rx,
         ));
+    } else {
+        // Discard Twitch chat announcements
+        drop(twitch_chat_rx);
     }
 
     log::info!("AutoMarathon initialized");
 
     loop {
-        match tasks.join_next().await {
-            Some(Ok(Ok(()))) => log::info!("Service Done"),
-            Some(Ok(Err(err))) => log::error!("Service error: {}", err.to_string()),
-            Some(Err(err)) => log::error!("Failed to join tasks: {}", err.to_string()),
-            None => break Ok(()),
+        tokio::select! {
+            task = tasks.join_next() => match task {
+                Some(Ok(Ok(()))) => log::info!("Service Done"),
+                Some(Ok(Err(err))) => log::error!("Service error: {}", err),
+                Some(Err(err)) => log::error!("Failed to join tasks: {}", err),
+                None => break Ok(()),
+            },
+            _ = tokio::signal::ctrl_c() => {
+                // Dropping the tasks and the lock releases the project folder
+                log::info!("Shutting down");
+                tasks.shutdown().await;
+                break Ok(());
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::trace::Traced, error::Error};
+
+    enum StallRequest {
+        /// Replied to right away
+        Ping(Rto<()>),
+        /// Held without a reply for as long as the actor runs
+        Stall(Rto<()>),
+        /// Dropped without a reply
+        Drop(Rto<()>),
+    }
+
+    impl ActorMessage for StallRequest {
+        fn label(&self) -> &'static str {
+            match self {
+                StallRequest::Ping(_) => "Ping",
+                StallRequest::Stall(_) => "Stall",
+                StallRequest::Drop(_) => "Drop",
+            }
+        }
+    }
+
+    impl Traced for StallRequest {}
+
+    type StallActor = ActorRef<StallRequest>;
+
+    fn start_stall_actor() -> StallActor {
+        let (actor, mut rx) = StallActor::new("stall");
+        tokio::spawn(async move {
+            let mut stalled = vec![];
+            while let Some((msg, _)) = rx.recv().await {
+                match msg {
+                    StallRequest::Ping(rto) => rto.reply(Ok(())),
+                    StallRequest::Stall(rto) => stalled.push(rto),
+                    StallRequest::Drop(rto) => drop(rto),
+                }
+            }
+        });
+        actor
+    }
+
+    const TEST_TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[tokio::test]
+    async fn stalled_actor_times_out() {
+        let stall_actor = start_stall_actor();
+        let start = std::time::Instant::now();
+        let err =
+            send_message_with_timeout!(TEST_TIMEOUT, stall_actor, StallRequest, Stall).unwrap_err();
+
+        assert!(start.elapsed() >= TEST_TIMEOUT);
+        match err.downcast::<Error>() {
+            Ok(Error::ActorTimeout(actor, msg, _)) => {
+                assert_eq!(actor, "stall_actor");
+                assert_eq!(msg, "StallRequest::Stall");
+            }
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+
+        // The actor keeps handling messages after a caller gave up on it
+        send_message_with_timeout!(TEST_TIMEOUT, stall_actor, StallRequest, Ping).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_reply_fails_without_waiting() {
+        let stall_actor = start_stall_actor();
+        let err =
+            send_message_with_timeout!(Duration::from_secs(60), stall_actor, StallRequest, Drop)
+                .unwrap_err();
+        assert!(!matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ActorTimeout(..))
+        ));
+    }
+}